@@ -0,0 +1,204 @@
+//! Generate include/split_reads.h from the `extern "C"` functions and opaque handle types of
+//! src/lib.rs, so the header can't drift from the definitions. Each function is declared with the
+//! summary of its doc comment (everything before its "# Safety" section).
+
+use std::{env, fs, path::Path};
+
+/// Start of the header, before the declarations
+const PROLOGUE: &str = "\
+/*
+ * C API of split-reads: build and read split-indexes, and read chunks of FASTQ or SAM/BAM/CRAM
+ * files record by record. Link against libsplit_reads_ffi (built by `cargo build --release` as
+ * target/release/libsplit_reads_ffi.so and libsplit_reads_ffi.a).
+ *
+ * Generated from split-reads-ffi/src/lib.rs by split-reads-ffi/build.rs; don't edit it by hand.
+ *
+ * Indexes and readers are opaque handles, released by the matching sr_*_free. Functions returning
+ * a handle return NULL on failure, and functions returning int return 0 (or, for
+ * sr_reader_next_record, 1 for a record and 0 at the end of the chunk) on success and -1 on
+ * failure. After a failure, sr_last_error describes it. Handles must not be used from two threads
+ * at once.
+ */
+#ifndef SPLIT_READS_H
+#define SPLIT_READS_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern \"C\" {
+#endif
+";
+
+/// End of the header, after the declarations
+const EPILOGUE: &str = "\
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* SPLIT_READS_H */
+";
+
+/// Width the declarations are wrapped to
+const MAX_LINE_WIDTH: usize = 100;
+
+/// Translate a Rust FFI type to C, e.g. "*const c_char" to "const char *".
+fn c_type(rust_type: &str) -> String {
+    if let Some(pointee) = rust_type.strip_prefix("*const ") {
+        return format!("const {}", pointer_to(&c_type(pointee)));
+    }
+    if let Some(pointee) = rust_type.strip_prefix("*mut ") {
+        return pointer_to(&c_type(pointee));
+    }
+    match rust_type {
+        "c_char" => "char",
+        "c_int" => "int",
+        "usize" => "size_t",
+        "u8" => "uint8_t",
+        "()" => "void",
+        handle => handle,
+    }
+    .to_string()
+}
+
+/// Make a C pointer type to pointee
+fn pointer_to(pointee: &str) -> String {
+    match pointee.ends_with('*') {
+        true => format!("{pointee}*"),
+        false => format!("{pointee} *"),
+    }
+}
+
+/// Join a C type and a name, without a space after a pointer's "*"
+fn declare(c_type: &str, name: &str) -> String {
+    match c_type.ends_with('*') {
+        true => format!("{c_type}{name}"),
+        false => format!("{c_type} {name}"),
+    }
+}
+
+/// Format doc comment lines as a C comment, without trailing blank lines.
+fn c_comment(doc: &[String]) -> String {
+    let end = doc
+        .iter()
+        .rposition(|line| !line.is_empty())
+        .map_or(0, |last| last + 1);
+    match &doc[..end] {
+        [] => String::new(),
+        [line] if line.len() + 6 <= MAX_LINE_WIDTH => format!("/* {line} */\n"),
+        lines => {
+            let body: String = lines
+                .iter()
+                .map(|line| format!(" * {line}").trim_end().to_string() + "\n")
+                .collect();
+            format!("/*\n{body} */\n")
+        }
+    }
+}
+
+/// Translate the signature of an extern "C" function, e.g. "sr_index_read(path: *const c_char)
+/// -> *mut SrIndex", to a C declaration, wrapped to one parameter per line if too long.
+fn c_declaration(signature: &str) -> String {
+    let (name, rest) = signature.split_once('(').expect("function has parameters");
+    let (params, ret) = rest.rsplit_once(')').expect("parameters end");
+    let ret = ret.trim().strip_prefix("->").map_or("()", str::trim);
+    let params: Vec<String> = params
+        .split(',')
+        .map(str::trim)
+        .filter(|param| !param.is_empty())
+        .map(|param| {
+            let (param_name, param_type) = param.split_once(':').expect("parameter has a type");
+            declare(&c_type(param_type.trim()), param_name.trim())
+        })
+        .collect();
+    let function = declare(&c_type(ret), name.trim());
+    let params = match params.is_empty() {
+        true => "void".to_string(),
+        false => params.join(", "),
+    };
+    let declaration = format!("{function}({params});\n");
+    if declaration.len() <= MAX_LINE_WIDTH + 1 {
+        return declaration;
+    }
+    let indent = " ".repeat(function.len() + 1);
+    format!(
+        "{function}({});\n",
+        params.replace(", ", &format!(",\n{indent}"))
+    )
+}
+
+/// Generate the header from the source of the C API.
+fn generate_header(source: &str) -> String {
+    let mut header = PROLOGUE.to_string();
+    let mut doc: Vec<String> = Vec::new();
+    let mut in_safety = false;
+    let mut signature: Option<String> = None;
+    for line in source.lines() {
+        if line.starts_with("#[cfg(test)]") {
+            break;
+        }
+        if let Some(partial) = signature.as_mut() {
+            // continue a signature spanning lines until its body opens
+            partial.push_str(line.trim());
+            partial.push(' ');
+            if line.ends_with('{') {
+                let complete = partial.trim_end().trim_end_matches('{').replace(", )", ")");
+                header.push('\n');
+                header.push_str(&c_comment(&doc));
+                header.push_str(&c_declaration(&complete.replace("( ", "(")));
+                signature = None;
+                doc.clear();
+            }
+            continue;
+        }
+        if let Some(text) = line.strip_prefix("///") {
+            let text = text.strip_prefix(' ').unwrap_or(text);
+            in_safety |= text.starts_with("# Safety");
+            if !in_safety {
+                doc.push(text.to_string());
+            }
+            continue;
+        }
+        in_safety = false;
+        if line.starts_with("#[") {
+            continue;
+        }
+        if let Some(rest) = line
+            .strip_prefix("pub unsafe extern \"C\" fn ")
+            .or_else(|| line.strip_prefix("pub extern \"C\" fn "))
+        {
+            if !rest.ends_with('{') {
+                // keep the doc comment until the rest of the signature is read
+                signature = Some(format!("{rest} "));
+                continue;
+            }
+            header.push('\n');
+            header.push_str(&c_comment(&doc));
+            header.push_str(&c_declaration(rest.trim_end_matches('{')));
+        } else if let Some(rest) = line.strip_prefix("pub struct ") {
+            // an opaque handle
+            let name = rest.split(['(', ' ', '{', ';']).next().unwrap_or(rest);
+            header.push('\n');
+            header.push_str(&c_comment(&doc));
+            header.push_str(&format!("typedef struct {name} {name};\n"));
+        }
+        doc.clear();
+    }
+    header.push('\n');
+    header.push_str(EPILOGUE);
+    header
+}
+
+fn main() {
+    println!("cargo:rerun-if-changed=src/lib.rs");
+    let crate_dir = env::var("CARGO_MANIFEST_DIR").expect("cargo sets CARGO_MANIFEST_DIR");
+    let crate_dir = Path::new(&crate_dir);
+    let source = fs::read_to_string(crate_dir.join("src/lib.rs")).expect("reading src/lib.rs");
+    let header = generate_header(&source);
+    let path = crate_dir.join("include/split_reads.h");
+    // only write a changed header, so that C builds depending on it aren't redone
+    if fs::read_to_string(&path).ok().as_deref() != Some(header.as_str()) {
+        fs::create_dir_all(crate_dir.join("include")).expect("creating include");
+        fs::write(&path, header).expect("writing include/split_reads.h");
+    }
+}
@@ -0,0 +1,111 @@
+/*
+ * C API of split-reads: build and read split-indexes, and read chunks of FASTQ or SAM/BAM/CRAM
+ * files record by record. Link against libsplit_reads_ffi (built by `cargo build --release` as
+ * target/release/libsplit_reads_ffi.so and libsplit_reads_ffi.a).
+ *
+ * Generated from split-reads-ffi/src/lib.rs by split-reads-ffi/build.rs; don't edit it by hand.
+ *
+ * Indexes and readers are opaque handles, released by the matching sr_*_free. Functions returning
+ * a handle return NULL on failure, and functions returning int return 0 (or, for
+ * sr_reader_next_record, 1 for a record and 0 at the end of the chunk) on success and -1 on
+ * failure. After a failure, sr_last_error describes it. Handles must not be used from two threads
+ * at once.
+ */
+#ifndef SPLIT_READS_H
+#define SPLIT_READS_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/*
+ * Get a description of the last failure on this thread, or NULL if nothing has failed. The
+ * string is valid until the next failure on this thread.
+ */
+const char *sr_last_error(void);
+
+/* Opaque handle to a split-index */
+typedef struct SrIndex SrIndex;
+
+/*
+ * Build the split-index of the reads file at input, into num_bins bins, using threads threads
+ * (0 for all available CPUs). reference_fasta may be NULL unless input is CRAM.
+ */
+SrIndex *sr_index_build(const char *input,
+                        const char *reference_fasta,
+                        size_t num_bins,
+                        size_t threads);
+
+/* Read a split-index from a path or URL. */
+SrIndex *sr_index_read(const char *path);
+
+/* Write a split-index to a path or URL. */
+int sr_index_write(const SrIndex *index, const char *path);
+
+/* Get the number of query groups in the indexed file, or 0 if index is NULL. */
+size_t sr_index_num_queries(const SrIndex *index);
+
+/* Get the number of reads in the indexed file, or 0 if index is NULL. */
+size_t sr_index_num_reads(const SrIndex *index);
+
+/* Get the 0-based query range [*start, *stop) of chunk chunk_index of num_chunks chunks. */
+int sr_index_chunk_range(const SrIndex *index,
+                         size_t chunk_index,
+                         size_t num_chunks,
+                         size_t *start,
+                         size_t *stop);
+
+/* Free an index handle. NULL is ignored. */
+void sr_index_free(SrIndex *index);
+
+/* Opaque handle to a reader of a reads file */
+typedef struct SrReader SrReader;
+
+/*
+ * Open the reads file at input for reading chunks, using threads threads (0 for all available
+ * CPUs). reference_fasta may be NULL unless input is CRAM.
+ */
+SrReader *sr_reader_open(const char *input, const char *reference_fasta, size_t threads);
+
+/*
+ * Fast-forward the reader to the query groups [start, stop) of the file indexed by index, so
+ * sr_reader_next_record reads them. index must be the index of the reader's file.
+ */
+int sr_reader_fast_forward(SrReader *reader, const SrIndex *index, size_t start, size_t stop);
+
+/*
+ * Read the next record of the chunk: return 1 if there is one, 0 at the end of the chunk (or
+ * before any fast-forward), and -1 on failure.
+ */
+int sr_reader_next_record(SrReader *reader);
+
+/*
+ * Get the query name of the current record, not NUL-terminated, with its length in *len. Valid
+ * until the next call to sr_reader_next_record.
+ */
+const uint8_t *sr_reader_record_name(const SrReader *reader, size_t *len);
+
+/*
+ * Get the bases of the current record as ASCII, with their number in *len. Valid until the next
+ * call to sr_reader_next_record.
+ */
+const uint8_t *sr_reader_record_seq(const SrReader *reader, size_t *len);
+
+/*
+ * Get the base qualities of the current record as Phred scores (without the FASTQ ASCII
+ * offset), with their number in *len, or NULL if it has none. Valid until the next call to
+ * sr_reader_next_record.
+ */
+const uint8_t *sr_reader_record_qual(const SrReader *reader, size_t *len);
+
+/* Free a reader handle. NULL is ignored. */
+void sr_reader_free(SrReader *reader);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* SPLIT_READS_H */
@@ -0,0 +1,544 @@
+//! C API of split-reads, for pipeline runners and tools not written in Rust. The declarations are
+//! in `include/split_reads.h`, generated from this file by build.rs.
+//!
+//! Indexes and readers are opaque handles, created by `sr_*_open`/`sr_index_build`/
+//! `sr_index_read` and released by the matching `sr_*_free`. Functions returning a handle return
+//! NULL on failure, and functions returning int return 0 (or, for sr_reader_next_record, 1 for a
+//! record and 0 at the end of the chunk) on success and -1 on failure. After a failure,
+//! sr_last_error describes it.
+
+use anyhow::{Result, anyhow};
+use rust_htslib::bam::Record as BamRecord;
+use split_reads::{
+    chunkable::{
+        ChunkCursor, ChunkableRecord, ChunkableRecordReader, FastForwardIndex, FastForwardInfo,
+    },
+    fastq::{FastqReader, FastqRecord},
+    maybe_compressed_io::MaybeCompressedReader,
+    sam_text::SamReader,
+    split_index::SplitIndex,
+    splitter::Splitter,
+    util::{RecordType, get_fastq_reader, get_sam_reader},
+    validation::{ValidatingReader, ValidationLevel},
+};
+use std::{
+    cell::RefCell,
+    ffi::{CStr, CString, c_char, c_int},
+    num::NonZero,
+    panic::{AssertUnwindSafe, catch_unwind},
+    path::PathBuf,
+    ptr,
+};
+
+thread_local! {
+    /// Description of the last failure on this thread, for sr_last_error
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+/// Run f, converting an error or panic into failure_value and recording it for sr_last_error,
+/// because neither may cross into C.
+fn guard<T, F>(failure_value: T, f: F) -> T
+where
+    F: FnOnce() -> Result<T>,
+{
+    let err = match catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(value)) => return value,
+        Ok(Err(err)) => format!("{err:#}"),
+        Err(panic) => match panic.downcast_ref::<&str>() {
+            Some(message) => format!("panic: {message}"),
+            None => match panic.downcast_ref::<String>() {
+                Some(message) => format!("panic: {message}"),
+                None => "panic".to_string(),
+            },
+        },
+    };
+    let err = CString::new(err.replace('\0', " ")).unwrap_or_default();
+    LAST_ERROR.with(|last_error| *last_error.borrow_mut() = Some(err));
+    failure_value
+}
+
+/// Convert a C string argument to a path.
+///
+/// # Safety
+/// path must be NULL or a valid NUL-terminated string.
+unsafe fn to_path(path: *const c_char, name: &str) -> Result<PathBuf> {
+    if path.is_null() {
+        return Err(anyhow!("{name} is NULL"));
+    }
+    // SAFETY: path is a valid NUL-terminated string, by the caller's contract
+    let path = unsafe { CStr::from_ptr(path) };
+    Ok(PathBuf::from(path.to_str()?))
+}
+
+/// Convert a C handle argument to a mutable reference.
+///
+/// # Safety
+/// handle must be NULL or a valid handle, not used elsewhere for the lifetime 'a.
+unsafe fn to_mut<'a, T>(handle: *mut T, name: &str) -> Result<&'a mut T> {
+    // SAFETY: handle is NULL or valid and unaliased, by the caller's contract
+    unsafe { handle.as_mut() }.ok_or_else(|| anyhow!("{name} is NULL"))
+}
+
+/// Convert a C handle argument to a shared reference.
+///
+/// # Safety
+/// handle must be NULL or a valid handle, not mutated elsewhere for the lifetime 'a.
+unsafe fn to_ref<'a, T>(handle: *const T, name: &str) -> Result<&'a T> {
+    // SAFETY: handle is NULL or valid and not mutated, by the caller's contract
+    unsafe { handle.as_ref() }.ok_or_else(|| anyhow!("{name} is NULL"))
+}
+
+/// Get a description of the last failure on this thread, or NULL if nothing has failed. The
+/// string is valid until the next failure on this thread.
+#[unsafe(no_mangle)]
+pub extern "C" fn sr_last_error() -> *const c_char {
+    LAST_ERROR.with(|last_error| {
+        last_error
+            .borrow()
+            .as_ref()
+            .map_or(ptr::null(), |err| err.as_ptr())
+    })
+}
+
+/// Opaque handle to a split-index
+pub struct SrIndex(SplitIndex);
+
+/// Build the split-index of the reads file at input, into num_bins bins, using threads threads
+/// (0 for all available CPUs). reference_fasta may be NULL unless input is CRAM.
+///
+/// # Safety
+/// input and reference_fasta must be NULL or valid NUL-terminated strings.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn sr_index_build(
+    input: *const c_char,
+    reference_fasta: *const c_char,
+    num_bins: usize,
+    threads: usize,
+) -> *mut SrIndex {
+    guard(ptr::null_mut(), || {
+        // SAFETY: input is NULL or a valid NUL-terminated string, by the caller's contract
+        let mut splitter = Splitter::open(unsafe { to_path(input, "input") }?)?;
+        if !reference_fasta.is_null() {
+            // SAFETY: reference_fasta is a valid NUL-terminated string, by the caller's contract
+            splitter.reference_fasta(Some(unsafe {
+                to_path(reference_fasta, "reference_fasta")
+            }?));
+        }
+        if let Some(threads) = NonZero::new(threads) {
+            splitter.threads(threads);
+        }
+        let num_bins =
+            NonZero::new(num_bins).ok_or_else(|| anyhow!("num_bins must be positive"))?;
+        let split_index = splitter.index(num_bins)?.split_index()?.clone();
+        Ok(Box::into_raw(Box::new(SrIndex(split_index))))
+    })
+}
+
+/// Read a split-index from a path or URL.
+///
+/// # Safety
+/// path must be NULL or a valid NUL-terminated string.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn sr_index_read(path: *const c_char) -> *mut SrIndex {
+    guard(ptr::null_mut(), || {
+        // SAFETY: path is NULL or a valid NUL-terminated string, by the caller's contract
+        let split_index = SplitIndex::read(unsafe { to_path(path, "path") }?)?;
+        Ok(Box::into_raw(Box::new(SrIndex(split_index))))
+    })
+}
+
+/// Write a split-index to a path or URL.
+///
+/// # Safety
+/// index must be NULL or a valid index handle, and path NULL or a valid NUL-terminated string.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn sr_index_write(index: *const SrIndex, path: *const c_char) -> c_int {
+    guard(-1, || {
+        // SAFETY: index and path are NULL or valid, by the caller's contract
+        let (index, path) = unsafe { (to_ref(index, "index")?, to_path(path, "path")?) };
+        index.0.clone().write(path)?;
+        Ok(0)
+    })
+}
+
+/// Get the number of query groups in the indexed file, or 0 if index is NULL.
+///
+/// # Safety
+/// index must be NULL or a valid index handle.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn sr_index_num_queries(index: *const SrIndex) -> usize {
+    // SAFETY: index is NULL or valid, by the caller's contract
+    guard(0, || Ok(unsafe { to_ref(index, "index") }?.0.num_queries()))
+}
+
+/// Get the number of reads in the indexed file, or 0 if index is NULL.
+///
+/// # Safety
+/// index must be NULL or a valid index handle.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn sr_index_num_reads(index: *const SrIndex) -> usize {
+    // SAFETY: index is NULL or valid, by the caller's contract
+    guard(0, || Ok(unsafe { to_ref(index, "index") }?.0.num_reads()))
+}
+
+/// Get the 0-based query range [*start, *stop) of chunk chunk_index of num_chunks chunks.
+///
+/// # Safety
+/// index must be NULL or a valid index handle, and start and stop NULL or valid pointers.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn sr_index_chunk_range(
+    index: *const SrIndex,
+    chunk_index: usize,
+    num_chunks: usize,
+    start: *mut usize,
+    stop: *mut usize,
+) -> c_int {
+    guard(-1, || {
+        // SAFETY: index, start and stop are NULL or valid, by the caller's contract
+        let (index, start, stop) = unsafe {
+            (
+                to_ref(index, "index")?,
+                to_mut(start, "start")?,
+                to_mut(stop, "stop")?,
+            )
+        };
+        let num_chunks =
+            NonZero::new(num_chunks).ok_or_else(|| anyhow!("num_chunks must be positive"))?;
+        if chunk_index >= num_chunks.get() {
+            return Err(anyhow!(
+                "Chunk index {chunk_index} must be less than the number of chunks {num_chunks}."
+            ));
+        }
+        *start = index.0.get_chunk_query_start(chunk_index, num_chunks)?;
+        *stop = index.0.get_chunk_query_start(chunk_index + 1, num_chunks)?;
+        Ok(0)
+    })
+}
+
+/// Free an index handle. NULL is ignored.
+///
+/// # Safety
+/// index must be NULL or a valid index handle, and is invalid afterwards.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn sr_index_free(index: *mut SrIndex) {
+    if !index.is_null() {
+        // SAFETY: index came from Box::into_raw and is freed only once, by the caller's contract
+        drop(unsafe { Box::from_raw(index) });
+    }
+}
+
+/// Reader of a reads file and its position in the current chunk, with copies of the current
+/// record's fields so C can borrow them.
+struct RecordSource<R: ChunkableRecord, Reader> {
+    reader: Reader,
+    record: R,
+    cursor: Option<ChunkCursor<R>>,
+    seq: Vec<u8>,
+    qual: Option<Vec<u8>>,
+}
+
+impl<R, Reader> RecordSource<R, Reader>
+where
+    R: ChunkableRecord,
+    Reader: ChunkableRecordReader<R>,
+{
+    fn new(reader: Reader) -> Self {
+        RecordSource {
+            reader,
+            record: R::new(),
+            cursor: None,
+            seq: Vec::new(),
+            qual: None,
+        }
+    }
+
+    fn fast_forward(&mut self, split_index: &SplitIndex, start: usize, stop: usize) -> Result<()> {
+        self.cursor = self
+            .reader
+            .fast_forward_to_queries(split_index.clone(), start, stop)?
+            .map(FastForwardInfo::into_cursor);
+        Ok(())
+    }
+
+    fn next_record(&mut self) -> Result<bool> {
+        let Some(cursor) = self.cursor.as_mut() else {
+            return Ok(false);
+        };
+        if !cursor.next_record(&mut self.reader, &mut self.record)? {
+            self.cursor = None;
+            return Ok(false);
+        }
+        self.seq.clear();
+        self.seq.extend_from_slice(&self.record.seq());
+        self.qual = self.record.qual().map(|qual| qual.into_owned());
+        Ok(true)
+    }
+
+    fn fields(&self) -> (&[u8], &[u8], Option<&[u8]>) {
+        (self.record.qname(), &self.seq, self.qual.as_deref())
+    }
+}
+
+/// Opaque handle to a reader of a reads file
+pub struct SrReader(Source);
+
+/// Record source of a reader handle, by record type
+enum Source {
+    Bam(RecordSource<BamRecord, ValidatingReader<SamReader>>),
+    Fastq(RecordSource<FastqRecord, ValidatingReader<FastqReader<MaybeCompressedReader>>>),
+}
+
+impl SrReader {
+    fn fields(&self) -> (&[u8], &[u8], Option<&[u8]>) {
+        match &self.0 {
+            Source::Bam(source) => source.fields(),
+            Source::Fastq(source) => source.fields(),
+        }
+    }
+}
+
+/// Open the reads file at input for reading chunks, using threads threads (0 for all available
+/// CPUs). reference_fasta may be NULL unless input is CRAM.
+///
+/// # Safety
+/// input and reference_fasta must be NULL or valid NUL-terminated strings.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn sr_reader_open(
+    input: *const c_char,
+    reference_fasta: *const c_char,
+    threads: usize,
+) -> *mut SrReader {
+    guard(ptr::null_mut(), || {
+        // SAFETY: input is NULL or a valid NUL-terminated string, by the caller's contract
+        let input = unsafe { to_path(input, "input") }?;
+        let reference_fasta = if reference_fasta.is_null() {
+            None
+        } else {
+            // SAFETY: reference_fasta is a valid NUL-terminated string, by the caller's contract
+            Some(unsafe { to_path(reference_fasta, "reference_fasta") }?)
+        };
+        let threads =
+            NonZero::new(threads).unwrap_or_else(split_reads::thread_budget::available_cpus);
+        let record_type = RecordType::detect(&input)?
+            .ok_or_else(|| anyhow!("Cannot tell the type of {input:?}"))?;
+        let reader = if record_type.is_sam_family() {
+            Source::Bam(RecordSource::new(ValidatingReader::new(
+                get_sam_reader(&input, reference_fasta, threads)?,
+                ValidationLevel::Strict,
+            )))
+        } else {
+            Source::Fastq(RecordSource::new(ValidatingReader::new(
+                get_fastq_reader(&input, threads)?,
+                ValidationLevel::Strict,
+            )))
+        };
+        Ok(Box::into_raw(Box::new(SrReader(reader))))
+    })
+}
+
+/// Fast-forward the reader to the query groups [start, stop) of the file indexed by index, so
+/// sr_reader_next_record reads them. index must be the index of the reader's file.
+///
+/// # Safety
+/// reader and index must be NULL or valid handles.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn sr_reader_fast_forward(
+    reader: *mut SrReader,
+    index: *const SrIndex,
+    start: usize,
+    stop: usize,
+) -> c_int {
+    guard(-1, || {
+        // SAFETY: reader and index are NULL or valid, by the caller's contract
+        let (reader, index) = unsafe { (to_mut(reader, "reader")?, to_ref(index, "index")?) };
+        let split_index = &index.0;
+        match &mut reader.0 {
+            Source::Bam(source) => {
+                source.reader.skip_ranges(split_index.skipped_ranges());
+                source.fast_forward(split_index, start, stop)?
+            }
+            Source::Fastq(source) => {
+                source.reader.skip_ranges(split_index.skipped_ranges());
+                source.fast_forward(split_index, start, stop)?
+            }
+        }
+        Ok(0)
+    })
+}
+
+/// Read the next record of the chunk: return 1 if there is one, 0 at the end of the chunk (or
+/// before any fast-forward), and -1 on failure.
+///
+/// # Safety
+/// reader must be NULL or a valid reader handle.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn sr_reader_next_record(reader: *mut SrReader) -> c_int {
+    guard(-1, || {
+        // SAFETY: reader is NULL or valid, by the caller's contract
+        let has_record = match &mut unsafe { to_mut(reader, "reader") }?.0 {
+            Source::Bam(source) => source.next_record()?,
+            Source::Fastq(source) => source.next_record()?,
+        };
+        Ok(c_int::from(has_record))
+    })
+}
+
+/// Point *data at one field of the current record and set *len to its length, or set them to
+/// NULL and 0 if the field is missing.
+///
+/// # Safety
+/// reader must be NULL or a valid reader handle, and len NULL or a valid pointer.
+unsafe fn record_field(
+    reader: *const SrReader,
+    len: *mut usize,
+    field: fn(&SrReader) -> Option<&[u8]>,
+) -> *const u8 {
+    guard(ptr::null(), || {
+        // SAFETY: reader and len are NULL or valid, by the caller's contract
+        let (reader, len) = unsafe { (to_ref(reader, "reader")?, to_mut(len, "len")?) };
+        let data = field(reader).unwrap_or_default();
+        *len = data.len();
+        Ok(if data.is_empty() {
+            ptr::null()
+        } else {
+            data.as_ptr()
+        })
+    })
+}
+
+/// Get the query name of the current record, not NUL-terminated, with its length in *len. Valid
+/// until the next call to sr_reader_next_record.
+///
+/// # Safety
+/// reader must be NULL or a valid reader handle, and len NULL or a valid pointer.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn sr_reader_record_name(
+    reader: *const SrReader,
+    len: *mut usize,
+) -> *const u8 {
+    // SAFETY: the caller's contract is the same
+    unsafe { record_field(reader, len, |reader| Some(reader.fields().0)) }
+}
+
+/// Get the bases of the current record as ASCII, with their number in *len. Valid until the next
+/// call to sr_reader_next_record.
+///
+/// # Safety
+/// reader must be NULL or a valid reader handle, and len NULL or a valid pointer.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn sr_reader_record_seq(
+    reader: *const SrReader,
+    len: *mut usize,
+) -> *const u8 {
+    // SAFETY: the caller's contract is the same
+    unsafe { record_field(reader, len, |reader| Some(reader.fields().1)) }
+}
+
+/// Get the base qualities of the current record as Phred scores (without the FASTQ ASCII
+/// offset), with their number in *len, or NULL if it has none. Valid until the next call to
+/// sr_reader_next_record.
+///
+/// # Safety
+/// reader must be NULL or a valid reader handle, and len NULL or a valid pointer.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn sr_reader_record_qual(
+    reader: *const SrReader,
+    len: *mut usize,
+) -> *const u8 {
+    // SAFETY: the caller's contract is the same
+    unsafe { record_field(reader, len, |reader| reader.fields().2) }
+}
+
+/// Free a reader handle. NULL is ignored.
+///
+/// # Safety
+/// reader must be NULL or a valid reader handle, and is invalid afterwards.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn sr_reader_free(reader: *mut SrReader) {
+    if !reader.is_null() {
+        // SAFETY: reader came from Box::into_raw and is freed only once, by the caller's contract
+        drop(unsafe { Box::from_raw(reader) });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        sr_index_build, sr_index_chunk_range, sr_index_free, sr_index_num_queries,
+        sr_index_num_reads, sr_index_read, sr_index_write, sr_last_error, sr_reader_fast_forward,
+        sr_reader_free, sr_reader_next_record, sr_reader_open, sr_reader_record_name,
+        sr_reader_record_qual, sr_reader_record_seq,
+    };
+    use anyhow::Result;
+    use std::{
+        ffi::{CStr, CString},
+        fs, ptr, slice,
+    };
+    use tempfile::TempDir;
+
+    /// Test building, writing and reading an index, and reading chunks record by record, through
+    /// the C API, and that failures are reported.
+    #[test]
+    fn test_ffi() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let fastq = temp_dir.path().join("reads.fastq");
+        let text: String = (0..60)
+            .map(|index| format!("@read{:02}\nACGT\n+\nI#5I\n", index / 2))
+            .collect();
+        fs::write(&fastq, &text)?;
+        let fastq = CString::new(fastq.to_str().unwrap())?;
+        let index_path = CString::new(temp_dir.path().join("reads.si").to_str().unwrap())?;
+        // SAFETY: every pointer passed is a valid string, handle, or NULL, and handles are freed
+        // once
+        unsafe {
+            let built = sr_index_build(fastq.as_ptr(), ptr::null(), 5, 1);
+            assert!(!built.is_null());
+            assert_eq!(sr_index_num_queries(built), 30);
+            assert_eq!(sr_index_num_reads(built), 60);
+            assert_eq!(sr_index_write(built, index_path.as_ptr()), 0);
+            sr_index_free(built);
+
+            let index = sr_index_read(index_path.as_ptr());
+            assert!(!index.is_null());
+            let reader = sr_reader_open(fastq.as_ptr(), ptr::null(), 1);
+            assert!(!reader.is_null());
+            let mut names = Vec::new();
+            for chunk_index in 0..3 {
+                let (mut start, mut stop) = (0, 0);
+                assert_eq!(
+                    sr_index_chunk_range(index, chunk_index, 3, &mut start, &mut stop),
+                    0
+                );
+                assert_eq!(sr_reader_fast_forward(reader, index, start, stop), 0);
+                while sr_reader_next_record(reader) == 1 {
+                    let mut len = 0;
+                    let name = sr_reader_record_name(reader, &mut len);
+                    names.push(String::from_utf8(
+                        slice::from_raw_parts(name, len).to_vec(),
+                    )?);
+                    let seq = sr_reader_record_seq(reader, &mut len);
+                    assert_eq!(slice::from_raw_parts(seq, len), b"ACGT");
+                    let qual = sr_reader_record_qual(reader, &mut len);
+                    assert_eq!(slice::from_raw_parts(qual, len), [40, 2, 20, 40]);
+                }
+            }
+            let expected: Vec<String> = (0..60)
+                .map(|index| format!("read{:02}", index / 2))
+                .collect();
+            assert_eq!(names, expected);
+            assert_eq!(sr_reader_next_record(reader), 0);
+
+            let (mut start, mut stop) = (0, 0);
+            assert_eq!(sr_index_chunk_range(index, 3, 3, &mut start, &mut stop), -1);
+            let err = CStr::from_ptr(sr_last_error()).to_str()?;
+            assert!(err.contains("Chunk index 3"), "{err}");
+            assert!(sr_index_read(c"missing.si".as_ptr()).is_null());
+            assert_eq!(sr_reader_fast_forward(ptr::null_mut(), index, 0, 1), -1);
+            let err = CStr::from_ptr(sr_last_error()).to_str()?;
+            assert_eq!(err, "reader is NULL");
+            sr_reader_free(reader);
+            sr_index_free(index);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,271 @@
+use crate::{
+    chunkable::{ChunkableRecord, ChunkableRecordWriter},
+    path_type::PathType,
+    util::configure_remote_output,
+};
+use anyhow::{Result, anyhow};
+use log::warn;
+use std::{
+    fs,
+    ops::{Deref, DerefMut},
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+/// Whether outputs are written under a temporary name and renamed once complete
+static ATOMIC_OUTPUT: AtomicBool = AtomicBool::new(true);
+
+/// Whether existing non-empty outputs may be overwritten
+static OVERWRITE: AtomicBool = AtomicBool::new(false);
+
+/// Turn atomic output on or off for the whole process. It is on by default; turn it off for
+/// filesystems where renaming is unsupported or slow (e.g. some FUSE mounts of object stores).
+pub fn set_atomic_output(enabled: bool) {
+    ATOMIC_OUTPUT.store(enabled, Ordering::Relaxed);
+}
+
+/// Allow or refuse overwriting existing non-empty outputs for the whole process. They are
+/// refused by default, so that re-running a pipeline step can't silently destroy earlier results.
+pub fn set_overwrite(enabled: bool) {
+    OVERWRITE.store(enabled, Ordering::Relaxed);
+}
+
+/// Check that output to file_path may be written: it must not exist as a non-empty regular file
+/// unless overwrite is true.
+fn check_overwrite(file_path: &Path, overwrite: bool) -> Result<()> {
+    match fs::metadata(file_path) {
+        Ok(metadata) if metadata.is_file() && metadata.len() > 0 && !overwrite => Err(anyhow!(
+            "Output {file_path:?} already exists. Use --force to overwrite it."
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Check that output to path may be written, as AtomicWriter::create would, so that long jobs
+/// that only open their outputs at the end can fail before doing any work.
+pub fn check_output<P>(path: P) -> Result<()>
+where
+    P: AsRef<Path>,
+{
+    match PathType::from_path(path.as_ref())? {
+        PathType::FilePath(file_path) => {
+            check_overwrite(&file_path, OVERWRITE.load(Ordering::Relaxed))
+        }
+        PathType::UrlPath(url) => configure_remote_output(&url),
+        PathType::Pipe => Ok(()),
+    }
+}
+
+/// Get the temporary path that output to path is written to: "<path>.tmp.<pid>"
+pub fn temp_path<P>(path: P) -> PathBuf
+where
+    P: AsRef<Path>,
+{
+    let mut temp_path = path.as_ref().as_os_str().to_owned();
+    temp_path.push(format!(".tmp.{}", std::process::id()));
+    PathBuf::from(temp_path)
+}
+
+/// Writer for an output file that is written under a temporary name next to it and renamed onto
+/// its path by commit, so downstream steps never pick up a half-written file. If it is dropped
+/// without being committed (e.g. after an error), the temporary file is removed instead, leaving
+/// any earlier output untouched. Stdout, URLs, and existing special files (e.g. FIFOs) can't be
+/// renamed, so they are written directly, as is everything when atomic output is turned off.
+pub struct AtomicWriter<W> {
+    inner: Option<W>,
+    /// Path being written
+    path: PathBuf,
+    /// Path to rename the output to once it is complete, if it is written under a temporary name
+    final_path: Option<PathBuf>,
+}
+
+impl<W> AtomicWriter<W> {
+    /// Open a writer for output to path with open, which is passed the path to write to. Fails if
+    /// path is an existing non-empty file, unless overwriting was allowed with set_overwrite.
+    pub fn create<P, F>(path: P, open: F) -> Result<Self>
+    where
+        P: AsRef<Path>,
+        F: FnOnce(&Path) -> Result<W>,
+    {
+        Self::open(path.as_ref(), OVERWRITE.load(Ordering::Relaxed), open)
+    }
+
+    /// Open a writer that replaces path, which may exist, e.g. to rewrite a file in place.
+    pub fn replace<P, F>(path: P, open: F) -> Result<Self>
+    where
+        P: AsRef<Path>,
+        F: FnOnce(&Path) -> Result<W>,
+    {
+        Self::open(path.as_ref(), true, open)
+    }
+
+    /// Open a writer for output to path, checking whether it may be overwritten.
+    fn open<F>(path: &Path, overwrite: bool, open: F) -> Result<Self>
+    where
+        F: FnOnce(&Path) -> Result<W>,
+    {
+        let path_type = PathType::from_path(path)?;
+        if let PathType::FilePath(ref file_path) = path_type {
+            check_overwrite(file_path, overwrite)?;
+        }
+        let is_renamable = path_type.is_regular_file();
+        if is_renamable && ATOMIC_OUTPUT.load(Ordering::Relaxed) {
+            let temp_path = temp_path(path);
+            let inner = open(&temp_path)?;
+            Ok(AtomicWriter {
+                inner: Some(inner),
+                path: temp_path,
+                final_path: Some(path.to_path_buf()),
+            })
+        } else {
+            Self::direct(path, open)
+        }
+    }
+
+    /// Open a writer that writes directly to path, e.g. for scratch files that are never read by
+    /// other steps.
+    pub fn direct<P, F>(path: P, open: F) -> Result<Self>
+    where
+        P: AsRef<Path>,
+        F: FnOnce(&Path) -> Result<W>,
+    {
+        Ok(AtomicWriter {
+            inner: Some(open(path.as_ref())?),
+            path: path.as_ref().to_path_buf(),
+            final_path: None,
+        })
+    }
+
+    /// Get the path being written: the temporary path until the output is committed.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Close the writer, then rename the output onto its path.
+    pub fn commit(self) -> Result<()> {
+        self.commit_with(|_| Ok(()))
+    }
+
+    /// Close the writer with close (e.g. to report errors that dropping it would ignore), then
+    /// rename the output onto its path. If close fails, the output is removed.
+    pub fn commit_with<F>(mut self, close: F) -> Result<()>
+    where
+        F: FnOnce(W) -> Result<()>,
+    {
+        if let Some(inner) = self.inner.take() {
+            close(inner)?;
+        }
+        if let Some(final_path) = self.final_path.take() {
+            fs::rename(&self.path, &final_path)
+                .map_err(|err| anyhow!("Renaming {:?} to {final_path:?}: {err}", self.path))?;
+        }
+        Ok(())
+    }
+}
+
+impl<W> Deref for AtomicWriter<W> {
+    type Target = W;
+
+    fn deref(&self) -> &W {
+        self.inner
+            .as_ref()
+            .expect("writer is only taken when committing")
+    }
+}
+
+impl<W> DerefMut for AtomicWriter<W> {
+    fn deref_mut(&mut self) -> &mut W {
+        self.inner
+            .as_mut()
+            .expect("writer is only taken when committing")
+    }
+}
+
+/// Remove the temporary output of a writer that was never committed.
+impl<W> Drop for AtomicWriter<W> {
+    fn drop(&mut self) {
+        // close the writer before removing its file
+        drop(self.inner.take());
+        if self.final_path.is_some()
+            && let Err(err) = fs::remove_file(&self.path)
+        {
+            warn!("Removing incomplete output {:?}: {err}", self.path);
+        }
+    }
+}
+
+/// Implement ChunkableRecordWriter for AtomicWriter, so it can be used anywhere records are
+/// written.
+impl<R, W> ChunkableRecordWriter<R> for AtomicWriter<W>
+where
+    R: ChunkableRecord,
+    W: ChunkableRecordWriter<R>,
+{
+    fn write(&mut self, record: &R) -> Result<()> {
+        self.deref_mut().write(record)
+    }
+
+    fn is_full(&mut self) -> bool {
+        self.deref_mut().is_full()
+    }
+
+    fn tell(&mut self) -> Result<Option<u64>> {
+        self.deref_mut().tell()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AtomicWriter, check_output, temp_path};
+    use anyhow::{Result, anyhow};
+    use std::{fs, fs::File, io::Write};
+    use tempfile::TempDir;
+
+    /// Test that output only appears at its path once committed, that uncommitted output is
+    /// removed without touching earlier output, and that non-empty output isn't overwritten.
+    #[test]
+    fn test_atomic_writer() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let path = temp_dir.path().join("chunk.fastq");
+        let mut writer = AtomicWriter::create(&path, |path| Ok(File::create(path)?))?;
+        assert_eq!(writer.path(), temp_path(&path));
+        writer.write_all(b"@r1\nACGT\n+\nIIII\n")?;
+        assert!(!path.exists());
+        writer.commit()?;
+        assert_eq!(fs::read_to_string(&path)?, "@r1\nACGT\n+\nIIII\n");
+        assert!(!temp_path(&path).exists());
+
+        assert!(AtomicWriter::create(&path, |path| Ok(File::create(path)?)).is_err());
+        let writer = AtomicWriter::replace(&path, |path| Ok(File::create(path)?))?;
+        drop(writer);
+
+        // empty outputs (e.g. touched by a workflow engine) may be overwritten
+        fs::write(&path, "")?;
+        let mut writer = AtomicWriter::create(&path, |path| Ok(File::create(path)?))?;
+        writer.write_all(b"@r2\n")?;
+        drop(writer);
+        assert!(!temp_path(&path).exists());
+        assert_eq!(fs::read_to_string(&path)?, "");
+
+        let writer = AtomicWriter::create(&path, |path| Ok(File::create(path)?))?;
+        assert!(writer.commit_with(|_| Err(anyhow!("closing"))).is_err());
+        assert!(!temp_path(&path).exists());
+
+        let direct = temp_dir.path().join("bucket.fastq");
+        let writer = AtomicWriter::direct(&direct, |path| Ok(File::create(path)?))?;
+        assert!(direct.exists());
+        drop(writer);
+        assert!(direct.exists());
+        Ok(())
+    }
+
+    /// Test that cloud URL outputs are rejected up front without the cloud-output feature.
+    #[test]
+    fn test_check_url_output() {
+        assert_eq!(
+            check_output("gs://bucket/chunk.bam").is_ok(),
+            cfg!(feature = "cloud-output")
+        );
+        assert!(check_output("-").is_ok());
+    }
+}
@@ -0,0 +1,230 @@
+use crate::{
+    chunkable::{ChunkableRecordReader, OffsetKind},
+    error_category::{Categorize, ErrorCategory},
+    path_type::PathType,
+    split_index::{SplitIndex, SplitRecord},
+    util::get_bam_reader,
+};
+use anyhow::{Result, anyhow};
+use log::info;
+use rust_htslib::{
+    bam::{Read, Record as BamRecord},
+    htslib,
+};
+use std::{ffi::CString, fs, num::NonZero, path::Path};
+
+/// BAI or CSI index of a coordinate-sorted BAM, loaded by htslib
+pub struct BamIndex {
+    inner: *mut htslib::hts_idx_t,
+}
+
+impl BamIndex {
+    /// Load the index of the BAM at path: "<path>.csi" or "<path>.bai" (or with ".bai" replacing
+    /// ".bam"), as samtools finds them.
+    pub fn load<P>(path: P) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+        let c_path = CString::new(
+            path.to_str()
+                .ok_or_else(|| anyhow!("Path {path:?} is not valid UTF-8"))?,
+        )?;
+        // SAFETY: c_path is a valid NUL-terminated string
+        let inner = unsafe { htslib::hts_idx_load(c_path.as_ptr(), htslib::HTS_FMT_BAI as i32) };
+        if inner.is_null() {
+            return Err(anyhow!(
+                "No BAI or CSI index found for {path:?}. Index it with samtools index."
+            ))
+            .categorize(ErrorCategory::BadIndex);
+        }
+        Ok(BamIndex { inner })
+    }
+
+    /// Get the number of reads placed on the reference sequence tid, mapped or not
+    pub fn num_reads(&self, tid: u32) -> u64 {
+        let (mut mapped, mut unmapped) = (0u64, 0u64);
+        // SAFETY: inner is a valid index, and mapped and unmapped are valid for writes
+        let result =
+            unsafe { htslib::hts_idx_get_stat(self.inner, tid as i32, &mut mapped, &mut unmapped) };
+        if result < 0 { 0 } else { mapped + unmapped }
+    }
+
+    /// Get the number of unplaced reads, which follow the placed reads at the end of the BAM
+    pub fn num_unplaced_reads(&self) -> u64 {
+        // SAFETY: inner is a valid index
+        unsafe { htslib::hts_idx_get_n_no_coor(self.inner) }
+    }
+
+    /// Get the virtual offset of the first record that may overlap positions beg..end (0-based)
+    /// of the reference sequence tid, or None if the index has no records there.
+    pub fn first_offset(&self, tid: u32, beg: u64, end: u64) -> Option<u64> {
+        // SAFETY: inner is a valid index
+        let itr = unsafe { htslib::sam_itr_queryi(self.inner, tid as i32, beg as i64, end as i64) };
+        // SAFETY: itr is NULL or a valid iterator, whose off array holds n_off chunks sorted by
+        // their start
+        let offset = unsafe { itr.as_ref() }
+            .filter(|itr| itr.n_off > 0 && !itr.off.is_null())
+            .map(|itr| unsafe { (*itr.off).u });
+        // SAFETY: itr is NULL or a valid iterator, and is not used after this
+        unsafe { htslib::hts_itr_destroy(itr) };
+        offset
+    }
+
+    /// Get the virtual offset of the first unplaced read, or None if there are none.
+    pub fn unplaced_offset(&self) -> Option<u64> {
+        if self.num_unplaced_reads() == 0 {
+            return None;
+        }
+        // SAFETY: inner is a valid index
+        let itr = unsafe { htslib::sam_itr_queryi(self.inner, htslib::HTS_IDX_NOCOOR, 0, 0) };
+        // SAFETY: itr is NULL or a valid iterator, whose curr_off is where reading starts
+        let offset = unsafe { itr.as_ref() }.map(|itr| itr.curr_off);
+        // SAFETY: itr is NULL or a valid iterator, and is not used after this
+        unsafe { htslib::hts_itr_destroy(itr) };
+        offset
+    }
+}
+
+/// impl Drop for BamIndex, freeing the htslib index
+impl Drop for BamIndex {
+    fn drop(&mut self) {
+        // SAFETY: inner is a valid index, and is not used after this
+        unsafe { htslib::hts_idx_destroy(self.inner) };
+    }
+}
+
+/// Spread num_reads over bins in proportion to their spans, returning the cumulative count at the
+/// end of each bin. The last bin always ends with every read counted.
+pub(crate) fn spread_reads(num_reads: u64, spans: &[u64]) -> Vec<u64> {
+    let total_span: u128 = spans.iter().map(|&span| span as u128).sum();
+    let mut cumulative_span: u128 = 0;
+    spans
+        .iter()
+        .map(|&span| {
+            cumulative_span += span as u128;
+            (num_reads as u128 * cumulative_span / total_span.max(1)) as u64
+        })
+        .enumerate()
+        .map(|(index, count)| {
+            if index + 1 == spans.len() {
+                num_reads
+            } else {
+                count
+            }
+        })
+        .collect()
+}
+
+/// Build an approximate SplitIndex (see SplitIndex::is_approximate) of a local coordinate-sorted
+/// BAM from its BAI or CSI index, without reading its records. Bins start at the first record
+/// that may overlap each of about num_bins windows spaced evenly over the reference sequences,
+/// plus one bin for the unplaced reads at the end. Each reference's read count (from the index)
+/// is spread over its bins by their share of its span of the file, so the counts are estimates,
+/// and every read is counted as its own query group.
+pub fn build_approximate_index<P>(path: P, num_bins: NonZero<usize>) -> Result<SplitIndex>
+where
+    P: AsRef<Path>,
+{
+    let PathType::FilePath(path) = PathType::from_path(path.as_ref())? else {
+        return Err(anyhow!(
+            "Can only index a local BAM from its BAI/CSI index."
+        ));
+    };
+    let bam_index = BamIndex::load(&path)?;
+    let mut reader = get_bam_reader(&path, None::<&Path>, NonZero::<usize>::MIN)?;
+    let first_record_offset = ChunkableRecordReader::<BamRecord>::tell(&mut reader)?;
+    let header = reader.header();
+    let reference_lengths: Vec<u64> = (0..header.target_count())
+        .map(|tid| header.target_len(tid).unwrap_or_default())
+        .collect();
+    let window = reference_lengths
+        .iter()
+        .sum::<u64>()
+        .div_ceil(num_bins.get() as u64)
+        .max(1);
+
+    // the bins of each reference (and the unplaced reads), with their starting offsets
+    let mut groups: Vec<(u64, Vec<u64>)> = Vec::new();
+    let mut last_offset: Option<u64> = None;
+    for (tid, &length) in (0u32..).zip(reference_lengths.iter()) {
+        let num_reads = bam_index.num_reads(tid);
+        if num_reads == 0 {
+            continue;
+        }
+        let mut offsets = Vec::new();
+        for beg in (0..length.max(1)).step_by(window as usize) {
+            if let Some(offset) = bam_index.first_offset(tid, beg, beg + 1)
+                && last_offset.is_none_or(|last_offset| offset > last_offset)
+            {
+                offsets.push(offset);
+                last_offset = Some(offset);
+            }
+        }
+        if offsets.is_empty() {
+            return Err(anyhow!(
+                "Index of {path:?} counts {num_reads} reads on reference {tid} but has no offsets \
+                for them. Corrupted or stale index."
+            ))
+            .categorize(ErrorCategory::BadIndex);
+        }
+        groups.push((num_reads, offsets));
+    }
+    if let Some(offset) = bam_index.unplaced_offset()
+        && last_offset.is_none_or(|last_offset| offset > last_offset)
+    {
+        groups.push((bam_index.num_unplaced_reads(), vec![offset]));
+    }
+    // the first bin starts at the first record, whatever the index says
+    match groups.first_mut() {
+        Some((_, offsets)) => offsets[0] = first_record_offset,
+        None => return Err(anyhow!("Index of {path:?} has no reads.")),
+    }
+
+    let end_offset = fs::metadata(&path)?.len() << 16;
+    let mut split_records = Vec::new();
+    let mut num_previous_reads = 0u64;
+    for (index, (num_reads, offsets)) in groups.iter().enumerate() {
+        let group_end = groups
+            .get(index + 1)
+            .map_or(end_offset, |(_, next_offsets)| next_offsets[0]);
+        let spans: Vec<u64> = offsets
+            .iter()
+            .zip(offsets[1..].iter().chain([&group_end]))
+            .map(|(start, stop)| stop.saturating_sub(*start))
+            .collect();
+        for (&offset, cumulative_reads) in offsets.iter().zip(spread_reads(*num_reads, &spans)) {
+            let num_reads = usize::try_from(num_previous_reads + cumulative_reads)?;
+            split_records.push(SplitRecord {
+                offset,
+                uncompressed_offset: None,
+                qname_range: None,
+                max_read_length: None,
+                first_record_hash: None,
+                num_queries: num_reads,
+                num_reads,
+            });
+        }
+        num_previous_reads += num_reads;
+    }
+    info!(
+        "Estimated {num_previous_reads} reads in {} bins from the index of {path:?}.",
+        split_records.len()
+    );
+    SplitIndex::from_approximate_bins(split_records, OffsetKind::Virtual)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::spread_reads;
+
+    /// Test spreading reads over bins by their spans, always counting every read by the last bin.
+    #[test]
+    fn test_spread_reads() {
+        assert_eq!(spread_reads(100, &[1, 1, 2]), vec![25, 50, 100]);
+        assert_eq!(spread_reads(10, &[3, 3, 3]), vec![3, 6, 10]);
+        assert_eq!(spread_reads(7, &[0, 0]), vec![0, 7]);
+        assert_eq!(spread_reads(5, &[4]), vec![5]);
+        assert_eq!(spread_reads(0, &[2, 2]), vec![0, 0]);
+    }
+}
@@ -0,0 +1,85 @@
+use crate::path_type::PathType;
+use anyhow::Result;
+use std::{fs, num::NonZero, path::Path};
+
+/// Default target spacing of index bins in bytes for adaptive bin sizing.
+pub const DEFAULT_BIN_SPACING: NonZero<u64> = NonZero::new(8 << 20).unwrap();
+
+/// Bins smaller than this (about one BGZF block) cannot make seeking any faster, and only waste
+/// memory.
+pub const MIN_USEFUL_BIN_SPACING: u64 = 64 << 10;
+
+/// Bins larger than this make every chunk extraction read a lot of data before reaching the start
+/// of the chunk.
+pub const MAX_USEFUL_BIN_SPACING: u64 = 64 << 20;
+
+/// Get the size of a local reads file. Pipes and URLs have no known size, so return None for them.
+pub fn file_size<P>(path: P) -> Result<Option<u64>>
+where
+    P: AsRef<Path>,
+{
+    match PathType::from_path(path)? {
+        PathType::Pipe | PathType::UrlPath(_) => Ok(None),
+        PathType::FilePath(file_path) => Ok(Some(fs::metadata(file_path)?.len())),
+    }
+}
+
+/// Get the number of bins that gives roughly the requested spacing in bytes for a file of the
+/// requested size. Always at least one bin.
+pub fn adaptive_num_bins(file_size: u64, bin_spacing: NonZero<u64>) -> NonZero<usize> {
+    let num_bins = file_size.div_ceil(bin_spacing.get());
+    NonZero::new(usize::try_from(num_bins).unwrap_or(usize::MAX)).unwrap_or(NonZero::<usize>::MIN)
+}
+
+/// Check whether the requested number of bins is sensible for a file of the requested size. If
+/// not, return a warning message with a suggested value.
+pub fn check_num_bins(num_bins: NonZero<usize>, file_size: u64) -> Option<String> {
+    let bin_spacing = file_size / num_bins.get() as u64;
+    let suggested = adaptive_num_bins(file_size, DEFAULT_BIN_SPACING);
+    if bin_spacing > MAX_USEFUL_BIN_SPACING {
+        Some(format!(
+            "{num_bins} bins for a {file_size} byte file gives {bin_spacing} bytes per bin, so \
+            extracting a chunk may read up to that much before reaching the chunk start. Consider \
+            --num-bins {suggested} or --bin-spacing {DEFAULT_BIN_SPACING}."
+        ))
+    } else if bin_spacing < MIN_USEFUL_BIN_SPACING && num_bins > suggested {
+        Some(format!(
+            "{num_bins} bins for a {file_size} byte file gives only {bin_spacing} bytes per bin, \
+            which wastes memory without speeding up extraction. Consider --num-bins {suggested} or \
+            --bin-spacing {DEFAULT_BIN_SPACING}."
+        ))
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DEFAULT_BIN_SPACING, adaptive_num_bins, check_num_bins};
+    use std::num::NonZero;
+
+    /// Test that adaptive bin counts follow the requested spacing, with at least one bin.
+    #[test]
+    fn test_adaptive_num_bins() {
+        let spacing = DEFAULT_BIN_SPACING;
+        assert_eq!(adaptive_num_bins(0, spacing).get(), 1);
+        assert_eq!(adaptive_num_bins(1, spacing).get(), 1);
+        assert_eq!(adaptive_num_bins(spacing.get(), spacing).get(), 1);
+        assert_eq!(adaptive_num_bins(spacing.get() + 1, spacing).get(), 2);
+        assert_eq!(adaptive_num_bins(100 << 30, spacing).get(), 12800);
+    }
+
+    /// Test that extreme bin counts produce warnings, and sensible ones do not.
+    #[test]
+    fn test_check_num_bins() {
+        let num_bins = |n: usize| NonZero::new(n).unwrap();
+        // 100 GiB file
+        assert!(check_num_bins(num_bins(10), 100 << 30).is_some());
+        assert!(check_num_bins(num_bins(10000), 100 << 30).is_none());
+        // 10 MiB file
+        assert!(check_num_bins(num_bins(10000), 10 << 20).is_some());
+        assert!(check_num_bins(num_bins(2), 10 << 20).is_none());
+        // tiny files are fine with one bin
+        assert!(check_num_bins(num_bins(1), 1000).is_none());
+    }
+}
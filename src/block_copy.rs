@@ -0,0 +1,238 @@
+use crate::{
+    chunkable::OffsetKind,
+    maybe_compressed_io::BGZF_EOF,
+    resync::{BGZF_EXTRA_SUBFIELD, BGZF_HEADER_START},
+};
+use anyhow::{Result, anyhow};
+use noodles_bgzf::io::{
+    Reader as NoodlesBgzfReader,
+    writer::{Builder as NoodlesBgzfWriterBuilder, CompressionLevel},
+};
+use std::{
+    fs::File,
+    io::{self, BufRead, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+/// Size of a BGZF block header, up to and including the BSIZE field
+const BGZF_HEADER_SIZE: usize = 18;
+
+/// Copies the data between two virtual offsets of a local BGZF file to another BGZF stream without
+/// decoding the records in between. Blocks that lie entirely inside the range are copied
+/// byte-for-byte; only the partial blocks at either end are decompressed and compressed again.
+/// Because BGZF blocks are independent gzip members, the output is a valid BGZF stream whose
+/// decompressed data is exactly the data of the range.
+pub struct BgzfBlockCopier {
+    path: PathBuf,
+    file: File,
+    /// Level used to compress the partial blocks at the ends of each range
+    compression_level: CompressionLevel,
+}
+
+impl BgzfBlockCopier {
+    /// Open the BGZF file at path. Partial blocks are compressed at the requested level, or the
+    /// default level if None.
+    pub fn open<P>(path: P, compression: Option<u32>) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let file = File::open(path.as_ref())
+            .map_err(|err| anyhow!("Opening {:?}: {err}", path.as_ref()))?;
+        let compression_level = match compression {
+            Some(level) => u8::try_from(level)
+                .ok()
+                .and_then(|level| CompressionLevel::try_from(level).ok())
+                .ok_or_else(|| anyhow!("Invalid BGZF compression level {level}."))?,
+            None => CompressionLevel::default(),
+        };
+        Ok(BgzfBlockCopier {
+            path: path.as_ref().to_path_buf(),
+            file,
+            compression_level,
+        })
+    }
+
+    /// Get the virtual offset of the end of the data: the start of the end-of-file block, or the
+    /// end of the file if it has none.
+    pub fn end_offset(&mut self) -> Result<u64> {
+        let len = self.file.metadata()?.len();
+        let mut data_end = len;
+        if let Some(eof_start) = len.checked_sub(BGZF_EOF.len() as u64) {
+            let mut tail = [0u8; BGZF_EOF.len()];
+            self.file.seek(SeekFrom::Start(eof_start))?;
+            self.file.read_exact(&mut tail)?;
+            if tail == BGZF_EOF {
+                data_end = eof_start;
+            }
+        }
+        OffsetKind::virtual_offset(data_end, 0)
+    }
+
+    /// Copy the data from virtual offset start up to (not including) virtual offset end, writing
+    /// BGZF blocks to output. Returns the number of bytes written. Does not write an end-of-file
+    /// block, so that several ranges can be copied into one output.
+    pub fn copy_range<W>(&mut self, start: u64, end: u64, output: &mut W) -> Result<u64>
+    where
+        W: Write,
+    {
+        if end < start {
+            return Err(anyhow!(
+                "Cannot copy BGZF range backwards (from {start} to {end})."
+            ));
+        }
+        let (start_block, start_in_block) = OffsetKind::split_virtual_offset(start);
+        let (end_block, end_in_block) = OffsetKind::split_virtual_offset(end);
+        let (start_in_block, end_in_block) = (start_in_block as usize, end_in_block as usize);
+        if start_block == end_block {
+            if start_in_block == end_in_block {
+                return Ok(0);
+            }
+            let data = self.read_block_data(start_block)?;
+            return self.compress_to(data_slice(&data, start_in_block, end_in_block)?, output);
+        }
+        let mut num_written = 0u64;
+        // copy the first block whole if the range starts at its beginning
+        let mut raw_start = start_block;
+        if start_in_block > 0 {
+            let (data, block_size) = self.read_block(start_block)?;
+            let data = decompress(&data)?;
+            num_written +=
+                self.compress_to(data_slice(&data, start_in_block, data.len())?, output)?;
+            raw_start += block_size;
+        }
+        self.file.seek(SeekFrom::Start(raw_start))?;
+        num_written += io::copy(
+            &mut (&mut self.file).take(end_block.saturating_sub(raw_start)),
+            output,
+        )?;
+        if end_in_block > 0 {
+            let data = self.read_block_data(end_block)?;
+            num_written += self.compress_to(data_slice(&data, 0, end_in_block)?, output)?;
+        }
+        Ok(num_written)
+    }
+
+    /// Read the raw bytes of the BGZF block at compressed_offset, and return them with the block
+    /// size.
+    fn read_block(&mut self, compressed_offset: u64) -> Result<(Vec<u8>, u64)> {
+        let mut header = [0u8; BGZF_HEADER_SIZE];
+        self.file.seek(SeekFrom::Start(compressed_offset))?;
+        self.file.read_exact(&mut header)?;
+        if header[..4] != BGZF_HEADER_START || header[12..16] != BGZF_EXTRA_SUBFIELD {
+            return Err(anyhow!(
+                "{:?} has no BGZF block at offset {compressed_offset}",
+                self.path
+            ));
+        }
+        let block_size = u16::from_le_bytes([header[16], header[17]]) as usize + 1;
+        let mut block = vec![0u8; block_size];
+        block[..BGZF_HEADER_SIZE].copy_from_slice(&header);
+        self.file.read_exact(&mut block[BGZF_HEADER_SIZE..])?;
+        Ok((block, block_size as u64))
+    }
+
+    /// Read and decompress the BGZF block at compressed_offset.
+    fn read_block_data(&mut self, compressed_offset: u64) -> Result<Vec<u8>> {
+        let (block, _) = self.read_block(compressed_offset)?;
+        decompress(&block)
+    }
+
+    /// Compress data into BGZF blocks written to output, and return the number of bytes written.
+    fn compress_to<W>(&self, data: &[u8], output: &mut W) -> Result<u64>
+    where
+        W: Write,
+    {
+        if data.is_empty() {
+            return Ok(0);
+        }
+        let mut writer = NoodlesBgzfWriterBuilder::default()
+            .set_compression_level(self.compression_level)
+            .build_from_writer(Vec::new());
+        writer.write_all(data)?;
+        let mut blocks = writer.finish()?;
+        // the writer always ends with an end-of-file block, which would end the output early
+        blocks.truncate(blocks.len() - BGZF_EOF.len());
+        output.write_all(&blocks)?;
+        Ok(blocks.len() as u64)
+    }
+}
+
+/// Decompress BGZF data, e.g. a single raw block. Goes through the reader's block buffer rather
+/// than read_to_end: noodles decodes straight into large enough read buffers, and then keeps
+/// reading blocks over the data it just decoded.
+fn decompress(blocks: &[u8]) -> Result<Vec<u8>> {
+    let mut reader = NoodlesBgzfReader::new(blocks);
+    let mut data = Vec::new();
+    loop {
+        let block_data = reader.fill_buf()?;
+        if block_data.is_empty() {
+            return Ok(data);
+        }
+        let num_read = block_data.len();
+        data.extend_from_slice(block_data);
+        reader.consume(num_read);
+    }
+}
+
+/// Get data[start..end], checking that the offsets lie within the block.
+fn data_slice(data: &[u8], start: usize, end: usize) -> Result<&[u8]> {
+    data.get(start..end).ok_or_else(|| {
+        anyhow!(
+            "Offsets {start}..{end} lie outside a BGZF block of {} bytes.",
+            data.len()
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BgzfBlockCopier, decompress};
+    use crate::maybe_compressed_io::BGZF_EOF;
+    use anyhow::Result;
+    use noodles_bgzf::io::Writer as NoodlesBgzfWriter;
+    use std::{fs::File, io::Write};
+    use tempfile::TempDir;
+
+    /// Test that ranges starting and ending inside blocks, within one block, and on block
+    /// boundaries all decompress to the data between their offsets.
+    #[test]
+    fn test_copy_range() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let path = temp_dir.path().join("lines.gz");
+        let mut writer = NoodlesBgzfWriter::new(File::create(&path)?);
+        let mut data = Vec::new();
+        let mut offsets = Vec::new();
+        for line in 0..20000 {
+            offsets.push(u64::from(writer.virtual_position()));
+            let line = format!("line {line}\n");
+            writer.write_all(line.as_bytes())?;
+            data.extend(line.as_bytes());
+        }
+        writer.finish()?;
+        let mut copier = BgzfBlockCopier::open(&path, Some(1))?;
+        let data_end = copier.end_offset()?;
+        assert_eq!(data_end >> 16, std::fs::metadata(&path)?.len() - 28);
+        let byte_offset = |line: usize| {
+            data.split_inclusive(|&c| c == b'\n')
+                .take(line)
+                .map(<[u8]>::len)
+                .sum::<usize>()
+        };
+        for (start, stop) in [(0, 20000), (3, 5), (17, 15000), (9000, 20000), (7, 7)] {
+            let end = offsets.get(stop).copied().unwrap_or(data_end);
+            let mut copied = Vec::new();
+            copier.copy_range(offsets[start], end, &mut copied)?;
+            assert!(!copied.ends_with(&BGZF_EOF));
+            assert_eq!(
+                decompress(&copied)?,
+                &data[byte_offset(start)..byte_offset(stop)]
+            );
+        }
+        assert!(
+            copier
+                .copy_range(offsets[5], offsets[3], &mut Vec::new())
+                .is_err()
+        );
+        Ok(())
+    }
+}
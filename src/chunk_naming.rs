@@ -0,0 +1,280 @@
+use crate::file_fingerprint::FileFingerprint;
+use anyhow::{Result, anyhow};
+use std::{
+    fs::{self, OpenOptions},
+    io::{ErrorKind, Write},
+    num::NonZero,
+    path::{Path, PathBuf},
+    thread,
+    time::Duration,
+};
+
+/// Name of the registry directory, in a shared output directory, holding one file per namespace
+/// with the identity of the input it was split from.
+pub const REGISTRY_DIR_NAME: &str = ".split-reads-namespaces";
+
+/// How many times to read a namespace file that another process has created but not yet written,
+/// and how long to wait between reads
+const CLAIM_READ_TRIES: usize = 50;
+const CLAIM_READ_INTERVAL: Duration = Duration::from_millis(20);
+
+/// Check that a namespace is usable as the prefix of a file name.
+pub fn validate_namespace(namespace: &str) -> Result<()> {
+    if namespace.is_empty() {
+        Err(anyhow!("Namespace cannot be empty."))
+    } else if namespace.starts_with('.') {
+        Err(anyhow!("Namespace {namespace:?} cannot start with '.'."))
+    } else if let Some(bad) = namespace
+        .chars()
+        .find(|c| c.is_whitespace() || c.is_control() || matches!(c, '/' | '\\'))
+    {
+        Err(anyhow!(
+            "Namespace {namespace:?} cannot contain {bad:?}, because it is part of file names."
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+/// Get the default namespace for an input path: its file name up to the first '.', e.g. "sample1"
+/// for "/data/sample1.unmapped.bam".
+pub fn default_namespace<P>(input: P) -> Option<String>
+where
+    P: AsRef<Path>,
+{
+    let file_name = input.as_ref().file_name()?.to_str()?;
+    let namespace = file_name.split('.').next()?;
+    (!namespace.is_empty()).then(|| namespace.to_string())
+}
+
+/// Structured names for the chunks split from one input, so that chunks from different inputs
+/// cannot share names in a shared output directory.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ChunkNamer {
+    namespace: String,
+}
+
+impl ChunkNamer {
+    /// Create a new ChunkNamer, checking that the namespace is usable in file names.
+    pub fn new(namespace: &str) -> Result<Self> {
+        validate_namespace(namespace)?;
+        Ok(ChunkNamer {
+            namespace: namespace.to_string(),
+        })
+    }
+
+    /// Get the namespace of the chunks
+    pub fn namespace(&self) -> &str {
+        &self.namespace
+    }
+
+    /// Get the file name for a chunk, e.g. "sample1.chunk-003-of-250.bam". The chunk index is
+    /// zero-padded so names sort in chunk order.
+    pub fn chunk_file_name(
+        &self,
+        chunk_index: usize,
+        num_chunks: NonZero<usize>,
+        extension: &str,
+    ) -> String {
+        let width = (num_chunks.get() - 1).max(1).ilog10() as usize + 1;
+        format!(
+            "{}.chunk-{chunk_index:0width$}-of-{num_chunks}.{extension}",
+            self.namespace
+        )
+    }
+
+    /// Get the file name for an arbitrary range of query groups, e.g. "sample1.queries-100-200.bam"
+    pub fn query_range_file_name(
+        &self,
+        query_start: usize,
+        query_stop: usize,
+        extension: &str,
+    ) -> String {
+        format!(
+            "{}.queries-{query_start}-{query_stop}.{extension}",
+            self.namespace
+        )
+    }
+
+    /// Register this namespace as belonging to the input in the output directory's registry. Error
+    /// if the namespace was already registered for a different input, because chunks from the two
+    /// inputs would then be mixed. The namespace's file is created only if it doesn't exist, so of
+    /// two processes registering it at once, exactly one claims it and the other checks its claim.
+    pub fn register<P1, P2>(&self, output_dir: P1, input: P2) -> Result<()>
+    where
+        P1: AsRef<Path>,
+        P2: AsRef<Path>,
+    {
+        let identity = input_identity(input.as_ref())?;
+        let registry_dir: PathBuf = output_dir.as_ref().join(REGISTRY_DIR_NAME);
+        fs::create_dir_all(&registry_dir)
+            .map_err(|err| anyhow!("Creating {registry_dir:?}: {err}"))?;
+        let claim_path = registry_dir.join(&self.namespace);
+        match OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&claim_path)
+        {
+            Ok(mut claim) => {
+                claim.write_all(format!("{identity}\n").as_bytes())?;
+                Ok(())
+            }
+            Err(err) if err.kind() == ErrorKind::AlreadyExists => {
+                let registered = read_claim(&claim_path)?;
+                if registered == identity {
+                    Ok(())
+                } else {
+                    Err(anyhow!(
+                        "Namespace {} in {:?} is already used for a different input ({}), so \
+                        chunks would collide. Use a different --namespace.",
+                        self.namespace,
+                        output_dir.as_ref(),
+                        registered
+                    ))
+                }
+            }
+            Err(err) => Err(anyhow!("Creating {claim_path:?}: {err}")),
+        }
+    }
+}
+
+/// Read the input identity from a namespace file, waiting for it to be complete (ending in a
+/// newline) if another process has only just created it.
+fn read_claim(claim_path: &Path) -> Result<String> {
+    for _ in 0..CLAIM_READ_TRIES {
+        let claim = fs::read_to_string(claim_path)
+            .map_err(|err| anyhow!("Reading {claim_path:?}: {err}"))?;
+        if let Some(identity) = claim.strip_suffix('\n') {
+            return Ok(identity.to_string());
+        }
+        thread::sleep(CLAIM_READ_INTERVAL);
+    }
+    Err(anyhow!(
+        "Namespace file {claim_path:?} is incomplete. If no other split is registering the \
+        namespace, delete it and retry."
+    ))
+}
+
+/// Identify an input independently of where it was localized: by fingerprint for local files,
+/// or by path for pipes and URLs.
+fn input_identity(input: &Path) -> Result<String> {
+    Ok(match FileFingerprint::from_path(input)? {
+        Some(FileFingerprint {
+            size,
+            checksum: Some(checksum),
+        }) => format!("size={size},xxh3={checksum:016x}"),
+        Some(FileFingerprint {
+            size,
+            checksum: None,
+        }) => format!("size={size}"),
+        None => input.to_string_lossy().to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ChunkNamer, REGISTRY_DIR_NAME, default_namespace, validate_namespace};
+    use anyhow::Result;
+    use std::{fs, num::NonZero, sync::Barrier, thread};
+    use tempfile::TempDir;
+
+    /// Test namespace validation, defaults, and chunk file names.
+    #[test]
+    fn test_chunk_names() -> Result<()> {
+        assert!(validate_namespace("sample_1-A").is_ok());
+        for bad in ["", ".hidden", "a/b", "a b"] {
+            assert!(
+                validate_namespace(bad).is_err(),
+                "{bad:?} should be invalid"
+            );
+        }
+        assert_eq!(
+            default_namespace("/data/sample1.unmapped.bam"),
+            Some("sample1".to_string())
+        );
+
+        let namer = ChunkNamer::new("sample1")?;
+        let num_chunks = |n: usize| NonZero::new(n).unwrap();
+        assert_eq!(
+            namer.chunk_file_name(3, num_chunks(250), "bam"),
+            "sample1.chunk-003-of-250.bam"
+        );
+        assert_eq!(
+            namer.chunk_file_name(0, num_chunks(1), "fastq"),
+            "sample1.chunk-0-of-1.fastq"
+        );
+        assert_eq!(
+            namer.chunk_file_name(9, num_chunks(10), "sam"),
+            "sample1.chunk-9-of-10.sam"
+        );
+        assert_eq!(
+            namer.query_range_file_name(100, 200, "cram"),
+            "sample1.queries-100-200.cram"
+        );
+        Ok(())
+    }
+
+    /// Test that a namespace can be reused for the same input, but not for a different input.
+    #[test]
+    fn test_register_collision() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let input_1 = temp_dir.path().join("input_1.fastq");
+        let input_2 = temp_dir.path().join("input_2.fastq");
+        fs::write(&input_1, b"@read1\nACGT\n+\nIIII\n")?;
+        fs::write(&input_2, b"@read2\nACGT\n+\nIIII\n")?;
+        let output_dir = temp_dir.path().join("chunks");
+
+        let namer = ChunkNamer::new("sample")?;
+        namer.register(&output_dir, &input_1)?;
+        namer.register(&output_dir, &input_1)?;
+        assert!(namer.register(&output_dir, &input_2).is_err());
+        ChunkNamer::new("other")?.register(&output_dir, &input_2)?;
+
+        // a namespace file another process has created but not yet written is waited for, then
+        // an incomplete one is an error
+        let claim = output_dir.join(REGISTRY_DIR_NAME).join("partial");
+        fs::write(&claim, b"size=")?;
+        assert!(
+            ChunkNamer::new("partial")?
+                .register(&output_dir, &input_1)
+                .is_err()
+        );
+        Ok(())
+    }
+
+    /// Test that when several processes register one namespace for different inputs at once,
+    /// exactly one of them succeeds.
+    #[test]
+    fn test_register_race() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let output_dir = temp_dir.path().join("chunks");
+        let inputs = (0..8)
+            .map(|index| {
+                let input = temp_dir.path().join(format!("input_{index}.fastq"));
+                fs::write(&input, format!("@read{index}\nACGT\n+\nIIII\n"))?;
+                Ok(input)
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let barrier = Barrier::new(inputs.len());
+        let num_registered = thread::scope(|scope| {
+            let handles: Vec<_> = inputs
+                .iter()
+                .map(|input| {
+                    let (barrier, output_dir) = (&barrier, &output_dir);
+                    scope.spawn(move || {
+                        barrier.wait();
+                        ChunkNamer::new("sample")
+                            .and_then(|namer| namer.register(output_dir, input))
+                            .is_ok()
+                    })
+                })
+                .collect();
+            handles
+                .into_iter()
+                .filter_map(|handle| handle.join().unwrap().then_some(()))
+                .count()
+        });
+        assert_eq!(num_registered, 1);
+        Ok(())
+    }
+}
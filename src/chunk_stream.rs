@@ -0,0 +1,127 @@
+use anyhow::{Result, anyhow};
+use std::{
+    io::{self, PipeReader, Read},
+    os::fd::AsRawFd,
+    path::{Path, PathBuf},
+    thread::{self, JoinHandle},
+};
+
+/// Already-encoded output bytes (e.g. a BAM or compressed FASTQ chunk), streamed from a producer
+/// thread that writes them to a path. Every writer in this crate writes to a path (htslib opens
+/// its own file), so the producer is given the path of the write end of a pipe, and whatever it
+/// writes there can be read from the ChunkStream. This lets anything that forwards chunks (to a
+/// socket, another process, or an upload) consume one producer instead of running its own record
+/// loop.
+///
+/// If the producer fails, reading returns its error in place of end-of-file, so a consumer never
+/// mistakes truncated output for a complete chunk.
+pub struct ChunkStream<T> {
+    reader: Option<PipeReader>,
+    producer: Option<JoinHandle<Result<T>>>,
+    /// Result of the producer, once it has been joined
+    result: Option<Result<T>>,
+}
+
+impl<T> ChunkStream<T>
+where
+    T: Send + 'static,
+{
+    /// Start the producer on its own thread, writing to the path it is passed.
+    pub fn spawn<F>(produce: F) -> Result<Self>
+    where
+        F: FnOnce(&Path) -> Result<T> + Send + 'static,
+    {
+        let (reader, writer) = io::pipe()?;
+        let producer = thread::Builder::new()
+            .name("chunk-stream".to_string())
+            .spawn(move || {
+                let path = PathBuf::from(format!("/dev/fd/{}", writer.as_raw_fd()));
+                let result = produce(&path);
+                // the reader sees end-of-file once the last write end is closed
+                drop(writer);
+                result
+            })?;
+        Ok(ChunkStream {
+            reader: Some(reader),
+            producer: Some(producer),
+            result: None,
+        })
+    }
+
+    /// Wait for the producer to finish, and keep its result.
+    fn join(&mut self) {
+        if let Some(producer) = self.producer.take() {
+            self.result = Some(
+                producer
+                    .join()
+                    .unwrap_or_else(|_| Err(anyhow!("Chunk stream producer panicked."))),
+            );
+        }
+    }
+
+    /// Stop reading, wait for the producer, and return its result. If the stream was not read to
+    /// the end, the producer fails writing to the closed pipe.
+    pub fn finish(mut self) -> Result<T> {
+        self.reader = None;
+        self.join();
+        self.result
+            .take()
+            .unwrap_or_else(|| Err(anyhow!("Chunk stream result was already taken.")))
+    }
+}
+
+impl<T> Read for ChunkStream<T>
+where
+    T: Send + 'static,
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let Some(ref mut reader) = self.reader else {
+            return Ok(0);
+        };
+        let num_read = reader.read(buf)?;
+        if num_read == 0 && !buf.is_empty() {
+            self.join();
+            if let Some(Err(err)) = &self.result {
+                return Err(io::Error::other(format!("Producing chunk stream: {err}")));
+            }
+        }
+        Ok(num_read)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ChunkStream;
+    use anyhow::{Result, anyhow};
+    use std::{
+        fs::File,
+        io::{Read, Write},
+    };
+
+    /// Test that bytes written to the producer's path are streamed, and that producer errors are
+    /// reported instead of end-of-file.
+    #[test]
+    fn test_chunk_stream() -> Result<()> {
+        let mut stream = ChunkStream::spawn(|path| {
+            let mut file = File::create(path)?;
+            for line in 0..1000 {
+                writeln!(file, "line {line}")?;
+            }
+            Ok(1000)
+        })?;
+        let mut streamed = String::new();
+        stream.read_to_string(&mut streamed)?;
+        assert_eq!(streamed.lines().count(), 1000);
+        assert!(streamed.ends_with("line 999\n"));
+        assert_eq!(stream.finish()?, 1000);
+
+        let mut failing = ChunkStream::spawn(|path| -> Result<()> {
+            File::create(path)?.write_all(b"partial")?;
+            Err(anyhow!("bad record"))
+        })?;
+        let err = failing.read_to_end(&mut Vec::new()).unwrap_err();
+        assert!(err.to_string().contains("bad record"), "{err}");
+        assert!(failing.finish().is_err());
+        Ok(())
+    }
+}
@@ -1,32 +1,415 @@
 use anyhow::{Result, anyhow};
 use log::info;
 use rust_htslib::bam::{
-    Read as BamRead, Reader as BamReader, Record as BamRecord, Writer as BamWriter,
+    Format as BamFormat, Read as BamRead, Reader as BamReader, Record as BamRecord,
+    Writer as BamWriter, record::Aux,
 };
 use seq_io::fastq::{
     OwnedRecord as OwnedSeqIoFastqRecord, Position, Reader as SeqIoFastqReader,
     Record as SeqIoFastqRecord,
 };
-use std::io::{BufRead, Read, Seek, SeekFrom, Write};
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::fmt;
+use std::io::{BufRead, BufReader, Cursor, Read, Seek, SeekFrom, Write};
 use std::num::NonZero;
+use std::str::FromStr;
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::thread;
+use xxhash_rust::xxh3::Xxh3;
 
+use crate::error_category::{Categorize, ErrorCategory};
 use crate::fastq::{FastqReader, FastqRecord, FastqWriter};
-use crate::maybe_compressed_io::MaybeCompressedWriter;
+use crate::maybe_compressed_io::{MaybeCompressedReader, MaybeCompressedWriter};
+use crate::memory_budget::MemoryBudget;
+use crate::resync::SkippedRange;
+use crate::sam_writer::SamWriter;
+use crate::translate_options::TranslateOptions;
+
+/// Offset added to Phred scores to make printable FASTQ quality characters.
+pub const PHRED_OFFSET: u8 = 33;
+
+/// Byte htslib uses to fill the qualities of records that have none ("*" in SAM).
+pub const MISSING_QUALITY_SENTINEL: u8 = 0xFF;
+
+/// Default Phred score used in place of missing qualities. Matches `samtools fastq -v`.
+pub const DEFAULT_MISSING_QUALITY: u8 = 1;
+
+/// Longest query name a BAM record can hold (the SAM spec limits QNAME to 254 characters).
+pub const MAX_BAM_QNAME_LENGTH: usize = 254;
+
+/// Longest sequence a BAM record can hold (l_qseq is a signed 32-bit integer).
+pub const MAX_BAM_SEQ_LENGTH: usize = i32::MAX as usize;
+
+/// How to handle records with missing base qualities when translating between record types.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MissingQualityPolicy {
+    /// Fill every base with this constant Phred score.
+    Fill(u8),
+    /// Error on the first record that has missing qualities.
+    Strict,
+}
+
+impl MissingQualityPolicy {
+    /// Get replacement Phred scores for a record of length `len` that has no qualities, or error
+    /// if the policy is strict.
+    fn fill(&self, qname: &[u8], len: usize) -> Result<Vec<u8>> {
+        match self {
+            Self::Fill(quality) => Ok(vec![*quality; len]),
+            Self::Strict => Err(anyhow!(
+                "Record {} has missing qualities.",
+                String::from_utf8_lossy(qname)
+            )),
+        }
+    }
+}
+
+impl Default for MissingQualityPolicy {
+    fn default() -> Self {
+        Self::Fill(DEFAULT_MISSING_QUALITY)
+    }
+}
+
+/// Mate information of a paired read, as stored in SAM/BAM/CRAM.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MateInfo {
+    /// Reference index of the mate, or -1 if the mate is unmapped
+    pub tid: i32,
+    /// 0-based position of the mate, or -1 if the mate is unmapped
+    pub pos: i64,
+    /// Observed template length (TLEN)
+    pub insert_size: i64,
+}
+
+impl MateInfo {
+    /// Mate information of a read whose mate is unmapped
+    pub const UNMAPPED: MateInfo = MateInfo {
+        tid: -1,
+        pos: -1,
+        insert_size: 0,
+    };
+}
+
+/// Return true if a FASTQ comment field looks like a SAM optional field ("TG:T:value").
+fn is_sam_tag(field: &[u8]) -> bool {
+    field.len() >= 5
+        && field[0].is_ascii_alphabetic()
+        && field[1].is_ascii_alphanumeric()
+        && field[2] == b':'
+        && b"AifZHB".contains(&field[3])
+        && field[4] == b':'
+}
+
+/// Get the SAM optional fields in a FASTQ comment, such as those stamped by ReadGroupStamp.
+fn comment_tags(comment: &[u8]) -> Vec<Vec<u8>> {
+    comment
+        .split(u8::is_ascii_whitespace)
+        .filter(|field| is_sam_tag(field))
+        .map(<[u8]>::to_vec)
+        .collect()
+}
+
+/// Get the value of the SAM optional field tag in a FASTQ comment, if it has one.
+fn comment_tag_value<'a>(comment: &'a [u8], tag: &[u8; 2]) -> Option<&'a [u8]> {
+    comment
+        .split(u8::is_ascii_whitespace)
+        .find(|field| is_sam_tag(field) && field.starts_with(tag))
+        .map(|field| &field[5..])
+}
+
+/// Split a SAM optional field ("TG:T:value") into its tag, type code, and value.
+fn split_sam_tag(field: &[u8]) -> Result<(&[u8], u8, &[u8])> {
+    if is_sam_tag(field) {
+        Ok((&field[..2], field[3], &field[5..]))
+    } else {
+        Err(anyhow!(
+            "{:?} is not a SAM optional field (\"TG:T:value\").",
+            String::from_utf8_lossy(field)
+        ))
+    }
+}
+
+/// Add the SAM optional field `tag` to a FASTQ header line (the query name of length qname_len,
+/// then optionally whitespace and a comment) as a tab-separated comment field, replacing any
+/// field with the same tag. The separator after the query name is kept.
+fn push_comment_tag(header: &mut Vec<u8>, qname_len: usize, tag: &[u8]) {
+    let comment = header.split_off(qname_len);
+    header.push(comment.first().copied().unwrap_or(b'\t'));
+    for field in comment
+        .get(1..)
+        .unwrap_or_default()
+        .split(|&c| c == b'\t')
+        .filter(|field| !field.is_empty() && !field.starts_with(&tag[..3]))
+    {
+        header.extend_from_slice(field);
+        header.push(b'\t');
+    }
+    header.extend_from_slice(tag);
+}
+
+/// Format a BAM aux array as SAM text ("<subtype>,<value>,...")
+fn format_aux_array<T: ToString>(subtype: char, values: impl Iterator<Item = T>) -> String {
+    values.fold(subtype.to_string(), |text, value| {
+        format!("{text},{}", value.to_string())
+    })
+}
+
+/// Format a BAM aux field as SAM text ("TG:T:value")
+fn format_aux(tag: &[u8], aux: Aux) -> Vec<u8> {
+    let (type_code, value) = match aux {
+        Aux::Char(value) => ('A', (value as char).to_string()),
+        Aux::I8(value) => ('i', value.to_string()),
+        Aux::U8(value) => ('i', value.to_string()),
+        Aux::I16(value) => ('i', value.to_string()),
+        Aux::U16(value) => ('i', value.to_string()),
+        Aux::I32(value) => ('i', value.to_string()),
+        Aux::U32(value) => ('i', value.to_string()),
+        Aux::Float(value) => ('f', value.to_string()),
+        Aux::Double(value) => ('f', value.to_string()),
+        Aux::String(value) => ('Z', value.to_string()),
+        Aux::HexByteArray(value) => ('H', value.to_string()),
+        Aux::ArrayI8(values) => ('B', format_aux_array('c', values.iter())),
+        Aux::ArrayU8(values) => ('B', format_aux_array('C', values.iter())),
+        Aux::ArrayI16(values) => ('B', format_aux_array('s', values.iter())),
+        Aux::ArrayU16(values) => ('B', format_aux_array('S', values.iter())),
+        Aux::ArrayI32(values) => ('B', format_aux_array('i', values.iter())),
+        Aux::ArrayU32(values) => ('B', format_aux_array('I', values.iter())),
+        Aux::ArrayFloat(values) => ('B', format_aux_array('f', values.iter())),
+    };
+    [tag, format!(":{type_code}:{value}").as_bytes()].concat()
+}
 
 /// A trait with required functions for records that can be extracte as part of a chunk
 pub trait ChunkableRecord {
+    /// Query name. For FASTQ this is the header up to the first whitespace, without the leading
+    /// '@' or any comment, so reads whose headers differ only in their comments share it.
     fn qname(&self) -> &[u8];
-    fn seq(&self) -> &[u8];
-    fn qual(&self) -> &[u8];
+    /// Query name shared by every read of the query, which reads are grouped by. For most records
+    /// this is the query name itself.
+    fn group_qname(&self) -> &[u8] {
+        self.qname()
+    }
+    /// Sequence as ASCII bases.
+    fn seq(&self) -> Cow<'_, [u8]>;
+    /// Base qualities as Phred scores (without ASCII offset), or None if the record has none.
+    fn qual(&self) -> Option<Cow<'_, [u8]>>;
     fn new() -> Self;
-    fn set_fields(&mut self, qname: &[u8], seq: &[u8], qual: &[u8]);
+    /// Set fields, with `seq` as ASCII bases and `qual` as Phred scores (without ASCII offset).
+    /// Error if the record type cannot hold the fields.
+    fn set_fields(&mut self, qname: &[u8], seq: &[u8], qual: &[u8]) -> Result<()>;
 
-    fn translate<CR: ChunkableRecord>(&mut self, chunkable_record: &CR) {
-        self.set_fields(
-            chunkable_record.qname(),
-            chunkable_record.seq(),
-            chunkable_record.qual(),
-        );
+    /// Replace the query name, keeping every other field (e.g. FASTQ comments).
+    fn set_qname(&mut self, qname: &[u8]) -> Result<()>;
+
+    /// Replace the sequence (as ASCII bases) and qualities (as Phred scores), keeping the name and
+    /// every other field.
+    fn set_seq_qual(&mut self, seq: &[u8], qual: &[u8]) -> Result<()> {
+        let qname = self.qname().to_vec();
+        self.set_fields(&qname, seq, qual)
+    }
+
+    /// Number of bases in the sequence.
+    fn seq_len(&self) -> usize {
+        self.seq().len()
+    }
+
+    /// Read group ID of the record, for record types that have one.
+    fn read_group(&self) -> Option<&[u8]> {
+        None
+    }
+
+    /// SAM flags of the record, for record types that have them.
+    fn flags(&self) -> Option<u16> {
+        None
+    }
+
+    /// Mate information, for paired records of types that have it.
+    fn mate_info(&self) -> Option<MateInfo> {
+        None
+    }
+
+    /// Optional fields as SAM text ("TG:T:value"), for record types that have them.
+    fn tags(&self) -> Option<Vec<Vec<u8>>> {
+        None
+    }
+
+    /// Value of the optional field tag as SAM text, for record types that have it.
+    fn tag_value(&self, tag: &[u8; 2]) -> Option<Cow<'_, [u8]>> {
+        self.tags()?
+            .into_iter()
+            .find(|field| field.starts_with(tag) && field.get(2) == Some(&b':'))
+            .map(|field| Cow::Owned(field[5..].to_vec()))
+    }
+
+    /// Free-text comment, for record types that have one (e.g. FASTQ header text after the name).
+    fn comment(&self) -> Option<&[u8]> {
+        None
+    }
+
+    /// Set a free-text comment, for record types that can hold one. Otherwise do nothing.
+    fn set_comment(&mut self, _comment: &[u8]) {}
+
+    /// Add an optional field given as SAM text ("TG:T:value"), replacing any field with the same
+    /// tag, for record types that can hold one. Otherwise do nothing.
+    fn push_tag(&mut self, _tag: &[u8]) -> Result<()> {
+        Ok(())
+    }
+
+    /// Set SAM flags, for record types that can hold them. Otherwise do nothing.
+    fn set_flags(&mut self, _flags: u16) {}
+
+    /// Set mate information, for record types that can hold it. Otherwise do nothing.
+    fn set_mate_info(&mut self, _mate_info: MateInfo) {}
+
+    /// Copy fields from a record of another type, according to the translation `options`.
+    fn translate<CR: ChunkableRecord>(
+        &mut self,
+        chunkable_record: &CR,
+        options: &TranslateOptions,
+    ) -> Result<()> {
+        // records without flags (i.e. FASTQ) are unaligned, and may be marked as pairs
+        let (qname, flags) = match chunkable_record.flags() {
+            Some(flags) => (chunkable_record.qname(), flags),
+            None => options.unaligned_flags(chunkable_record.qname()),
+        };
+        let qname = options
+            .name_rewrite
+            .rewrite(qname, chunkable_record.comment(), Some(flags));
+        let qname = qname.as_ref();
+        let seq = chunkable_record.seq();
+        if let Some(qual) = chunkable_record.qual() {
+            self.set_fields(qname, &seq, &qual)?;
+        } else {
+            let qual = options.missing_quality.fill(qname, seq.len())?;
+            self.set_fields(qname, &seq, &qual)?;
+        }
+        self.set_flags(flags);
+        self.set_mate_info(chunkable_record.mate_info().unwrap_or(MateInfo::UNMAPPED));
+        if !options.read_group_stamp.is_empty() {
+            self.set_comment(
+                &options
+                    .read_group_stamp
+                    .comment(chunkable_record.read_group()),
+            );
+        }
+        if !options.copy_tags.is_empty() {
+            for tag in chunkable_record.tags().unwrap_or_default() {
+                if options.is_copied(&tag) {
+                    self.push_tag(&tag)?;
+                }
+            }
+        }
+        for tag in &options.tags {
+            self.push_tag(tag)?;
+        }
+        Ok(())
+    }
+}
+
+/// Kind of file offset that a ChunkableRecordReader reports from tell() and expects in seek(),
+/// and that a SplitIndex stores. Offsets of one kind are meaningless to a reader of the other, so
+/// seeking checks that they match instead of silently landing in the wrong place.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum OffsetKind {
+    /// BGZF virtual offset: the compressed offset of a block in the upper 48 bits, and the offset
+    /// into its uncompressed data in the lower 16. htslib's virtual offsets (BAM, and SAM read
+    /// through htslib) and noodles' VirtualPosition (BGZF FASTQ) share this encoding, so offsets
+    /// from either backend can be used by the other.
+    Virtual,
+    /// Plain byte offset into an uncompressed stream (e.g. uncompressed FASTQ)
+    Byte,
+}
+
+impl OffsetKind {
+    /// Largest compressed offset a virtual offset can hold
+    const MAX_COMPRESSED_OFFSET: u64 = (1 << 48) - 1;
+
+    /// Get the code identifying this kind in a serialized SplitIndex.
+    pub fn code(self) -> u8 {
+        match self {
+            OffsetKind::Virtual => 1,
+            OffsetKind::Byte => 2,
+        }
+    }
+
+    /// Get the kind identified by a code in a serialized SplitIndex.
+    pub fn from_code(code: u8) -> Result<Self> {
+        match code {
+            1 => Ok(OffsetKind::Virtual),
+            2 => Ok(OffsetKind::Byte),
+            _ => Err(anyhow!("Unknown offset kind {code}. Index corrupted.")),
+        }
+    }
+
+    /// Make a virtual offset from the compressed offset of a BGZF block and an offset into its
+    /// uncompressed data.
+    pub fn virtual_offset(compressed_offset: u64, uncompressed_offset: u16) -> Result<u64> {
+        if compressed_offset > Self::MAX_COMPRESSED_OFFSET {
+            return Err(anyhow!(
+                "Compressed offset {compressed_offset} is too large for a virtual offset."
+            ));
+        }
+        Ok((compressed_offset << 16) | u64::from(uncompressed_offset))
+    }
+
+    /// Split a virtual offset into the compressed offset of its BGZF block and the offset into
+    /// the block's uncompressed data.
+    pub fn split_virtual_offset(virtual_offset: u64) -> (u64, u16) {
+        (virtual_offset >> 16, (virtual_offset & 0xFFFF) as u16)
+    }
+
+    /// Check that a reader with offsets of this kind can seek to offsets of index_kind, taken from
+    /// an index. Indices written before offset kinds were recorded have none, and are trusted.
+    pub fn check_seekable(self, index_kind: Option<OffsetKind>) -> Result<()> {
+        match index_kind {
+            Some(index_kind) if index_kind != self => Err(anyhow!(
+                "Index holds {index_kind} offsets, but the reader seeks by {self} offsets. Was \
+                the index built for a differently compressed copy of the reads?"
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl fmt::Display for OffsetKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OffsetKind::Virtual => write!(f, "BGZF virtual"),
+            OffsetKind::Byte => write!(f, "uncompressed byte"),
+        }
+    }
+}
+
+/// Seekable stream that knows the kind of offsets its positions are.
+pub trait OffsetKindSource {
+    fn offset_kind(&self) -> OffsetKind;
+}
+
+/// Compressed and remote readers seek by virtual offset, uncompressed ones by byte.
+impl OffsetKindSource for MaybeCompressedReader {
+    fn offset_kind(&self) -> OffsetKind {
+        match self {
+            MaybeCompressedReader::Compressed(_) | MaybeCompressedReader::Remote(_) => {
+                OffsetKind::Virtual
+            }
+            MaybeCompressedReader::Gzip(_) | MaybeCompressedReader::Uncompressed(_) => {
+                OffsetKind::Byte
+            }
+            #[cfg(feature = "zstd")]
+            MaybeCompressedReader::Zstd(_) => OffsetKind::Byte,
+        }
+    }
+}
+
+impl<T> OffsetKindSource for Cursor<T> {
+    fn offset_kind(&self) -> OffsetKind {
+        OffsetKind::Byte
+    }
+}
+
+impl<R> OffsetKindSource for BufReader<R> {
+    fn offset_kind(&self) -> OffsetKind {
+        OffsetKind::Byte
     }
 }
 
@@ -36,6 +419,8 @@ pub trait ChunkableRecord {
 pub struct SplitRange {
     /// File offset at the first read in this bin
     pub offset: u64,
+    /// Kind of the offset, or None if the index predates recording it
+    pub offset_kind: Option<OffsetKind>,
     /// Cumulative number of queries in the entire reads file at the end of the previous bin
     pub num_previous_queries: usize,
     /// Cumulative number of queries in the entire reads file at the end of this bin.
@@ -44,6 +429,146 @@ pub struct SplitRange {
     pub num_previous_reads: usize,
     /// Cumulative number of reads in the entire reads file at the end of this bin.
     pub num_end_reads: usize,
+    /// How records were grouped into query groups when the bin was indexed
+    pub group_by: GroupBy,
+    /// Hash of the first read in this bin (see first_record_hash), if the index recorded it
+    pub first_record_hash: Option<u32>,
+}
+
+impl SplitRange {
+    /// Check that record, read right after seeking to this bin, is the first read the index
+    /// recorded for the bin, so that an index that doesn't match the reads file errors instead
+    /// of silently producing a shifted chunk.
+    pub fn verify_first_record<R>(&self, record: &R) -> Result<()>
+    where
+        R: ChunkableRecord,
+    {
+        match self.first_record_hash {
+            Some(hash) if hash != first_record_hash(record) => Err(anyhow!(
+                "Split index does not match file: the read at offset {} is {:?}, not the first \
+                read of the indexed bin. Re-index the reads file.",
+                self.offset,
+                String::from_utf8_lossy(record.qname())
+            ))
+            .categorize(ErrorCategory::BadIndex),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Short hash of a record's query name and sequence. Indices store it for the first read of each
+/// bin, to check that seeking to the bin lands on the same read.
+pub fn first_record_hash<R>(record: &R) -> u32
+where
+    R: ChunkableRecord,
+{
+    let mut hasher = Xxh3::new();
+    hasher.update(record.qname());
+    hasher.update(b"\0");
+    hasher.update(&record.seq());
+    hasher.digest() as u32
+}
+
+/// Key that records are grouped into query groups by. Consecutive records with the same key form
+/// a query group, which chunks never split. Grouping by something coarser than the query name
+/// keeps e.g. every read of a UMI group or cell barcode in one chunk, as long as the reads file is
+/// sorted or grouped by it.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum GroupBy {
+    /// The query name
+    #[default]
+    QueryName,
+    /// The query name up to the first occurrence of a delimiter, e.g. a UMI or barcode prefix
+    /// before ':'
+    QnamePrefix(u8),
+    /// The value of an optional field, e.g. MI or CB. Reads without it are grouped by query name.
+    Tag([u8; 2]),
+}
+
+impl GroupBy {
+    /// Get the key that record is grouped by
+    pub fn key<'a, R>(self, record: &'a R) -> Cow<'a, [u8]>
+    where
+        R: ChunkableRecord,
+    {
+        match self {
+            GroupBy::QueryName => Cow::Borrowed(record.group_qname()),
+            GroupBy::QnamePrefix(delimiter) => {
+                let qname = record.group_qname();
+                let end = qname
+                    .iter()
+                    .position(|&byte| byte == delimiter)
+                    .unwrap_or(qname.len());
+                Cow::Borrowed(&qname[..end])
+            }
+            GroupBy::Tag(tag) => record
+                .tag_value(&tag)
+                .unwrap_or(Cow::Borrowed(record.group_qname())),
+        }
+    }
+
+    /// Serialize by appending a code and its parameter to bytes
+    pub fn serialize(self, bytes: &mut Vec<u8>) {
+        match self {
+            GroupBy::QueryName => bytes.push(0),
+            GroupBy::QnamePrefix(delimiter) => bytes.extend([1, delimiter]),
+            GroupBy::Tag(tag) => bytes.extend([2, tag[0], tag[1]]),
+        }
+    }
+
+    /// Deserialize from bytes written by serialize
+    pub fn deserialize(bytes: &[u8]) -> Result<Self> {
+        match bytes {
+            [0] => Ok(GroupBy::QueryName),
+            [1, delimiter] => Ok(GroupBy::QnamePrefix(*delimiter)),
+            [2, first, second] => Ok(GroupBy::Tag([*first, *second])),
+            _ => Err(anyhow!(
+                "Unknown query grouping {bytes:?}. Index corrupted."
+            )),
+        }
+    }
+}
+
+/// Describe the grouping as given on the command line: "qname", "qname-prefix:<delimiter>", or
+/// "tag:<tag>".
+impl fmt::Display for GroupBy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GroupBy::QueryName => write!(f, "qname"),
+            GroupBy::QnamePrefix(delimiter) => write!(f, "qname-prefix:{}", *delimiter as char),
+            GroupBy::Tag(tag) => write!(f, "tag:{}", String::from_utf8_lossy(tag)),
+        }
+    }
+}
+
+/// Parse the grouping from its command line form.
+impl FromStr for GroupBy {
+    type Err = anyhow::Error;
+
+    fn from_str(group_by: &str) -> Result<Self> {
+        let invalid = || {
+            anyhow!(
+                "Invalid grouping {group_by:?}: expected \"qname\", \"qname-prefix:<delimiter>\" \
+                (one character), or \"tag:<tag>\" (e.g. \"tag:CB\")."
+            )
+        };
+        match group_by.split_once(':') {
+            None if group_by == "qname" => Ok(GroupBy::QueryName),
+            Some(("qname-prefix", delimiter)) => match delimiter.as_bytes() {
+                [delimiter] => Ok(GroupBy::QnamePrefix(*delimiter)),
+                _ => Err(invalid()),
+            },
+            Some(("tag", tag)) => match tag.as_bytes() {
+                [first, second]
+                    if first.is_ascii_alphabetic() && second.is_ascii_alphanumeric() =>
+                {
+                    Ok(GroupBy::Tag([*first, *second]))
+                }
+                _ => Err(invalid()),
+            },
+            _ => Err(invalid()),
+        }
+    }
 }
 
 /// A trait that allows fast-forwarding a chunkable reader. Given a chunk index and number of
@@ -56,98 +581,417 @@ pub trait FastForwardIndex {
         num_chunks: NonZero<usize>,
     ) -> Result<usize>;
     fn get_record_for_num_queries(&self, num_queries: usize) -> Option<SplitRange>;
+    fn get_record_for_num_reads(&self, num_reads: usize) -> Option<SplitRange>;
+
+    /// Return true if the index's counts are only estimates, so chunks must start and end at bin
+    /// boundaries (see get_approximate_bin) rather than after counted query groups.
+    fn is_approximate(&self) -> bool {
+        false
+    }
+
+    /// For approximate indices, get the bin that a chunk starting at the query group with 0-based
+    /// index num_queries starts at, or None if it starts at the end of the file.
+    fn get_approximate_bin(&self, _num_queries: usize) -> Option<SplitRange> {
+        None
+    }
+}
+
+/// Location of a single record in a reads file, found via a FastForwardIndex
+#[derive(Clone, Debug, PartialEq)]
+pub struct Location {
+    /// Query name of the record
+    pub qname: Vec<u8>,
+    /// 0-based index of the record's query group in the reads file
+    pub query_index: usize,
+    /// 0-based index of the record in the reads file
+    pub read_index: usize,
+    /// File offset of the record, as returned by ChunkableRecordReader::tell
+    pub offset: u64,
+}
+
+/// Outcome of writing a chunk or range of query groups, so that empty chunks are handled the same
+/// way by every caller.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChunkResult {
+    /// The chunk had no query groups, so nothing was written
+    Empty,
+    /// The chunk was written, with this many reads and query groups
+    Written { reads: usize, queries: usize },
+}
+
+impl ChunkResult {
+    /// Return true if nothing was written
+    pub fn is_empty(&self) -> bool {
+        matches!(self, ChunkResult::Empty)
+    }
+
+    /// Get the number of reads written
+    pub fn num_reads(&self) -> usize {
+        match self {
+            ChunkResult::Empty => 0,
+            ChunkResult::Written { reads, .. } => *reads,
+        }
+    }
+
+    /// Get the number of query groups written
+    pub fn num_queries(&self) -> usize {
+        match self {
+            ChunkResult::Empty => 0,
+            ChunkResult::Written { queries, .. } => *queries,
+        }
+    }
 }
 
 /// Struct holding information needed to fast-forward a reader to a chunk and write it out
 #[derive(Debug)]
 pub struct FastForwardInfo<'a, R: ChunkableRecord, Reader: ChunkableRecordReader<R>> {
     num_queries: usize,
-    stop_num_queries: usize,
     num_reads: usize,
-    hard_stop_num_reads: usize,
+    end: ChunkEnd,
     record: R,
     reader: &'a mut Reader,
+    group_by: GroupBy,
+    max_inflight_records: usize,
+    transform: Option<&'a mut (dyn RecordTransform<R> + Send)>,
 }
 
+/// Where a chunk ends
+#[derive(Clone, Copy, Debug)]
+enum ChunkEnd {
+    /// After the query group that completes stop_num_queries, reading no further than the end of
+    /// its bin at hard_stop_num_reads
+    Queries {
+        stop_num_queries: usize,
+        hard_stop_num_reads: usize,
+    },
+    /// Before the record at this offset, or at the end of the file if None. For approximate
+    /// indices, whose counts can't say where query groups end.
+    Offset(Option<u64>),
+}
+
+/// Number of record batches buffered between the reading and writing threads of a chunk
+const PIPELINE_DEPTH: usize = 4;
+
+/// Number of records in each batch handed from the reading thread to the writing thread
+const PIPELINE_BATCH_SIZE: usize = 256;
+
+/// Default bound on records read but not yet written by a chunk pipeline: the buffered batches,
+/// plus the batch being filled and the batch being written.
+pub const DEFAULT_MAX_INFLIGHT_RECORDS: usize = (PIPELINE_DEPTH + 2) * PIPELINE_BATCH_SIZE;
+
+/// Batch of records read for a chunk: the records, and how many of them are filled. Records are
+/// reused once the writing thread sends the batch back.
+type RecordBatch<R> = (Vec<R>, usize);
+
 impl<'a, R, Reader> FastForwardInfo<'a, R, Reader>
 where
-    R: ChunkableRecord + 'a,
-    Reader: ChunkableRecordReader<R>,
+    R: ChunkableRecord + Send + 'a,
+    Reader: ChunkableRecordReader<R> + Send,
 {
+    /// Bound the records read but not yet written, so a slow writer stalls the reading thread
+    /// instead of letting it buffer ever more records. Small bounds shrink the batches and the
+    /// number buffered, trading throughput for memory.
+    pub fn max_inflight_records(&mut self, max_inflight_records: NonZero<usize>) -> &mut Self {
+        self.max_inflight_records = max_inflight_records.get();
+        self
+    }
+
+    /// Transform the records written with transform, e.g. to filter or trim them. Query groups
+    /// are still counted (and chunks still end) as if every read were written unchanged.
+    pub fn transform(
+        &mut self,
+        transform: Option<&'a mut (dyn RecordTransform<R> + Send)>,
+    ) -> &mut Self {
+        self.transform = transform;
+        self
+    }
+
+    /// Records per batch and number of batches buffered in the channel, keeping the batches in
+    /// flight (buffered, being filled, and being written) within max_inflight_records, and the
+    /// memory budget.
+    fn pipeline_shape(&self) -> (usize, usize) {
+        let max_inflight_records =
+            MemoryBudget::current().inflight_records(self.max_inflight_records);
+        let batch_size =
+            (max_inflight_records / (PIPELINE_DEPTH + 2)).clamp(1, PIPELINE_BATCH_SIZE);
+        let depth = (max_inflight_records / batch_size)
+            .saturating_sub(2)
+            .min(PIPELINE_DEPTH);
+        (batch_size, depth)
+    }
+
     /// Write a chunk to the writer, reading and writing the same record type
-    pub fn write_chunk<Writer>(&mut self, writer: &mut Writer) -> Result<()>
+    pub fn write_chunk<Writer>(&mut self, writer: &mut Writer) -> Result<ChunkResult>
     where
         Writer: ChunkableRecordWriter<R>,
     {
-        let mut last_query_name = self.record.qname().to_owned();
-        while self.num_queries < self.stop_num_queries {
-            // have the 1st record of a new query here
-            writer.write(&self.record)?;
-            self.reader
-                .read_no_missing(&mut self.record, &mut self.num_reads)?;
-            while self.record.qname() == last_query_name {
-                writer.write(&self.record)?;
-                self.reader
-                    .read_no_missing(&mut self.record, &mut self.num_reads)?;
-            }
-            self.num_queries += 1;
-            last_query_name = self.record.qname().to_owned();
-        }
-        // write the last query, being careful to check we don't read past the end of the bin/file
-        writer.write(&self.record)?;
-        while self.num_reads < self.hard_stop_num_reads {
-            self.reader
-                .read_no_missing(&mut self.record, &mut self.num_reads)?;
-            if self.record.qname() != last_query_name {
-                break;
-            }
-            writer.write(&self.record)?;
-        }
-        Ok(())
+        self.pipeline_chunk(writer, |writer, record| writer.write(record))
     }
 
     /// Write a chunk to the writer, translating to a different record type
     pub fn translate_and_write_chunk<WriteRecord, Writer>(
         &mut self,
         writer: &mut Writer,
-    ) -> Result<()>
+        options: &TranslateOptions,
+    ) -> Result<ChunkResult>
     where
         Writer: ChunkableRecordWriter<WriteRecord>,
         WriteRecord: ChunkableRecord,
     {
-        let mut last_query_name = self.record.qname().to_owned();
         let mut write_record = WriteRecord::new();
-        while self.num_queries < self.stop_num_queries {
+        self.pipeline_chunk(writer, |writer, record| {
+            write_record.translate(record, options)?;
+            writer.write(&write_record)
+        })
+    }
+
+    /// Read the chunk's records on a separate thread, handing them to this thread in batches
+    /// through a bounded channel, and write them here with write_record. Decompressing the input
+    /// and compressing the output then overlap instead of taking turns. The writer is checked
+    /// for being full at the start of each query group; if it is, that query group is the last.
+    fn pipeline_chunk<WriteRecord, Writer, F>(
+        &mut self,
+        writer: &mut Writer,
+        mut write_record: F,
+    ) -> Result<ChunkResult>
+    where
+        Writer: ChunkableRecordWriter<WriteRecord>,
+        WriteRecord: ChunkableRecord,
+        F: FnMut(&mut Writer, &R) -> Result<()>,
+    {
+        let (batch_size, depth) = self.pipeline_shape();
+        let (batch_sender, batch_receiver) = mpsc::sync_channel::<Result<RecordBatch<R>>>(depth);
+        let (recycle_sender, recycle_receiver) = mpsc::channel::<Vec<R>>();
+        let mut transform = self.transform.take();
+        let group_by = self.group_by;
+        thread::scope(|scope| {
+            scope.spawn(|| {
+                let mut batches = BatchSender {
+                    sender: batch_sender,
+                    recycled: recycle_receiver,
+                    batch: Vec::with_capacity(batch_size),
+                    batch_size,
+                    filled: 0,
+                };
+                if let Err(err) = self.read_chunk(&mut batches) {
+                    // if the writing thread stopped early, nobody is left to report to
+                    let _ = batches.sender.send(Err(err));
+                }
+            });
+
+            let mut last_query_name = LastQueryName::default();
+            let mut last_query = false;
+            let (mut reads, mut queries) = (0usize, 0usize);
+            'batches: for batch in batch_receiver {
+                let (mut records, filled) = batch?;
+                for record in &mut records[..filled] {
+                    let key = group_by.key(record);
+                    if !last_query_name.matches(&key) {
+                        if last_query {
+                            break 'batches;
+                        }
+                        last_query = writer.is_full();
+                        queries += 1;
+                        last_query_name.set(&key);
+                    }
+                    if let Some(ref mut transform) = transform
+                        && transform.transform(record)? == TransformResult::Drop
+                    {
+                        continue;
+                    }
+                    write_record(writer, record)?;
+                    reads += 1;
+                }
+                let _ = recycle_sender.send(records);
+            }
+            // dropping the receiver stops the reading thread if it is still going
+            Ok(ChunkResult::Written { reads, queries })
+        })
+    }
+
+    /// Read the records of the chunk (on the reading thread), sending them in batches: whole
+    /// query groups until stop_num_queries, then the query group after, being careful not to
+    /// read past the end of the bin/file. Chunks ending at an offset are read up to it instead.
+    fn read_chunk(&mut self, batches: &mut BatchSender<R>) -> Result<()> {
+        let (stop_num_queries, hard_stop_num_reads) = match self.end {
+            ChunkEnd::Queries {
+                stop_num_queries,
+                hard_stop_num_reads,
+            } => (stop_num_queries, hard_stop_num_reads),
+            ChunkEnd::Offset(stop_offset) => {
+                loop {
+                    batches.push(&mut self.record)?;
+                    if !self.reader.read_before_offset(
+                        &mut self.record,
+                        &mut self.num_reads,
+                        stop_offset,
+                    )? {
+                        break;
+                    }
+                }
+                return batches.flush();
+            }
+        };
+        let group_by = self.group_by;
+        let mut last_query_name = LastQueryName::default();
+        last_query_name.set(&group_by.key(&self.record));
+        while self.num_queries < stop_num_queries {
             // have the 1st record of a new query here
-            write_record.translate(&self.record);
-            writer.write(&write_record)?;
+            batches.push(&mut self.record)?;
             self.reader
                 .read_no_missing(&mut self.record, &mut self.num_reads)?;
-            while self.record.qname() == last_query_name {
-                write_record.translate(&self.record);
-                writer.write(&write_record)?;
+            while last_query_name.matches(&group_by.key(&self.record)) {
+                batches.push(&mut self.record)?;
                 self.reader
                     .read_no_missing(&mut self.record, &mut self.num_reads)?;
             }
             self.num_queries += 1;
-            last_query_name = self.record.qname().to_owned();
+            last_query_name.set(&group_by.key(&self.record));
         }
-        // write the last query, being careful to check we don't read past the end of the bin/file
-        write_record.translate(&self.record);
-        writer.write(&write_record)?;
-        while self.num_reads < self.hard_stop_num_reads {
+        // the last query
+        batches.push(&mut self.record)?;
+        while self.num_reads < hard_stop_num_reads {
             self.reader
                 .read_no_missing(&mut self.record, &mut self.num_reads)?;
-            if self.record.qname() != last_query_name {
+            if !last_query_name.matches(&group_by.key(&self.record)) {
                 break;
             }
-            write_record.translate(&self.record);
-            writer.write(&write_record)?;
+            batches.push(&mut self.record)?;
         }
+        batches.flush()
+    }
+}
+
+/// Sending side of the batches of a chunk pipeline
+struct BatchSender<R> {
+    sender: SyncSender<Result<RecordBatch<R>>>,
+    /// Batches the writing thread has finished with, to reuse their records
+    recycled: Receiver<Vec<R>>,
+    batch: Vec<R>,
+    batch_size: usize,
+    filled: usize,
+}
+
+impl<R> BatchSender<R>
+where
+    R: ChunkableRecord,
+{
+    /// Add a record to the current batch, swapping it with a spare record, and send the batch
+    /// once it is full.
+    fn push(&mut self, record: &mut R) -> Result<()> {
+        if self.filled == self.batch.len() {
+            self.batch.push(R::new());
+        }
+        std::mem::swap(&mut self.batch[self.filled], record);
+        self.filled += 1;
+        if self.filled == self.batch_size {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Send the current batch, if it has any records, and start the next one.
+    fn flush(&mut self) -> Result<()> {
+        if self.filled == 0 {
+            return Ok(());
+        }
+        let next = self
+            .recycled
+            .try_recv()
+            .unwrap_or_else(|_| Vec::with_capacity(self.batch_size));
+        let batch = std::mem::replace(&mut self.batch, next);
+        self.sender
+            .send(Ok((batch, self.filled)))
+            .map_err(|_| anyhow!("Chunk writer stopped."))?;
+        self.filled = 0;
         Ok(())
     }
 }
 
+impl<'a, R, Reader> FastForwardInfo<'a, R, Reader>
+where
+    R: ChunkableRecord,
+    Reader: ChunkableRecordReader<R>,
+{
+    /// Release the reader, keeping the position in the chunk, to pull the chunk's records one at
+    /// a time with ChunkCursor::next_record instead of writing them.
+    pub fn into_cursor(self) -> ChunkCursor<R> {
+        let mut last_query_name = LastQueryName::default();
+        last_query_name.set(&self.group_by.key(&self.record));
+        ChunkCursor {
+            group_by: self.group_by,
+            num_queries: self.num_queries,
+            num_reads: self.num_reads,
+            end: self.end,
+            record: self.record,
+            last_query_name,
+            done: false,
+        }
+    }
+}
+
+/// Position within a chunk, for pulling its records one at a time from the reader it was
+/// fast-forwarded with. Yields the same records as FastForwardInfo::write_chunk.
+#[derive(Debug)]
+pub struct ChunkCursor<R: ChunkableRecord> {
+    group_by: GroupBy,
+    num_queries: usize,
+    num_reads: usize,
+    end: ChunkEnd,
+    /// next record of the chunk, already read
+    record: R,
+    last_query_name: LastQueryName,
+    done: bool,
+}
+
+impl<R> ChunkCursor<R>
+where
+    R: ChunkableRecord,
+{
+    /// Swap the next record of the chunk into record and return true, or return false once the
+    /// chunk is finished. reader must be the one the cursor was fast-forwarded with.
+    pub fn next_record<Reader>(&mut self, reader: &mut Reader, record: &mut R) -> Result<bool>
+    where
+        Reader: ChunkableRecordReader<R>,
+    {
+        if self.done {
+            return Ok(false);
+        }
+        std::mem::swap(record, &mut self.record);
+        let (stop_num_queries, hard_stop_num_reads) = match self.end {
+            ChunkEnd::Queries {
+                stop_num_queries,
+                hard_stop_num_reads,
+            } => (stop_num_queries, hard_stop_num_reads),
+            ChunkEnd::Offset(stop_offset) => {
+                self.done = !reader.read_before_offset(
+                    &mut self.record,
+                    &mut self.num_reads,
+                    stop_offset,
+                )?;
+                return Ok(true);
+            }
+        };
+        let last_query = self.num_queries >= stop_num_queries;
+        if last_query && self.num_reads >= hard_stop_num_reads {
+            // the last query group ends at the end of its bin (or the file!)
+            self.done = true;
+            return Ok(true);
+        }
+        reader.read_no_missing(&mut self.record, &mut self.num_reads)?;
+        let key = self.group_by.key(&self.record);
+        if !self.last_query_name.matches(&key) {
+            if last_query {
+                self.done = true;
+            } else {
+                self.num_queries += 1;
+                self.last_query_name.set(&key);
+            }
+        }
+        Ok(true)
+    }
+}
+
 /// Public trait for a reader that can fast-forward to a desired chunk then read only the records
 /// from that chunk. Directly tied to the type of ChunkableRecord.
 pub trait ChunkableRecordReader<R>
@@ -157,15 +1001,183 @@ where
 {
     fn tell(&mut self) -> Result<u64>;
     fn seek(&mut self, offset: u64) -> Result<()>;
+
+    /// Kind of offsets reported by tell() and expected by seek()
+    fn offset_kind(&self) -> OffsetKind;
+
+    /// Seek to the start of an index bin, checking that its offset is of the kind this reader
+    /// seeks by.
+    fn seek_to_range(&mut self, split_range: &SplitRange) -> Result<()> {
+        self.offset_kind().check_seekable(split_range.offset_kind)?;
+        self.seek(split_range.offset)
+    }
+
+    /// Offset of the next record in the uncompressed stream, for seeking in a decompressed copy of
+    /// the file. None if the reader does not track it.
+    fn tell_uncompressed(&mut self) -> Result<Option<u64>> {
+        Ok(None)
+    }
     // Read into existing record, returning potentially missing record, or Result with anyhow error
     fn read_into(&mut self, record: &mut R) -> Option<Result<()>>;
 
+    /// Take the ranges skipped after corrupt records while reading. Empty unless the reader skips
+    /// errors.
+    fn take_skipped_ranges(&mut self) -> Vec<SkippedRange> {
+        Vec::new()
+    }
+
     /// Read into record that should not be missing, and handle any errors.
     fn read_no_missing(&mut self, record: &mut R, num_reads: &mut usize) -> Result<()> {
         *num_reads += 1;
-        self.read_into(record)
-            .unwrap_or_else(|| Err(anyhow!("file truncated.")))
-            .map_err(|err| anyhow!("Unable to read at record {num_reads}: {err:?}"))
+        match self.read_into(record) {
+            Some(result) => {
+                result.map_err(|err| anyhow!("Unable to read at record {num_reads}: {err:?}"))
+            }
+            None => Err(anyhow!(
+                "Unable to read at record {num_reads}: file truncated."
+            ))
+            .categorize(ErrorCategory::TruncatedInput),
+        }
+    }
+
+    /// Read the next record of a chunk that ends before the record at stop_offset (or at the end
+    /// of the file if None) into record, counting it in num_reads. Return false at the end of the
+    /// chunk.
+    fn read_before_offset(
+        &mut self,
+        record: &mut R,
+        num_reads: &mut usize,
+        stop_offset: Option<u64>,
+    ) -> Result<bool> {
+        if let Some(stop_offset) = stop_offset
+            && self.tell()? >= stop_offset
+        {
+            return Ok(false);
+        }
+        match self.read_into(record) {
+            Some(result) => {
+                *num_reads += 1;
+                result.map_err(|err| anyhow!("Unable to read at record {num_reads}: {err:?}"))?;
+                Ok(true)
+            }
+            None if stop_offset.is_none() => Ok(false),
+            None => Err(anyhow!(
+                "Unable to read at record {}: file truncated.",
+                *num_reads + 1
+            ))
+            .categorize(ErrorCategory::TruncatedInput),
+        }
+    }
+
+    /// Find the first record of the query group with the requested 0-based index.
+    fn locate_query<SI>(&mut self, split_index: SI, query_index: usize) -> Result<Location>
+    where
+        SI: FastForwardIndex,
+    {
+        if split_index.is_approximate() {
+            return Err(anyhow!(
+                "Cannot locate a query by its index with an approximate index, whose counts are \
+                estimates."
+            ));
+        }
+        let split_range = split_index
+            .get_record_for_num_queries(query_index + 1)
+            .ok_or_else(|| anyhow!("Query index {query_index} is past the end of the index."))?;
+        self.scan_to_location(&split_range, |_, scan_query_index, _| {
+            scan_query_index == query_index
+        })
+    }
+
+    /// Find the record with the requested 0-based index.
+    fn locate_read<SI>(&mut self, split_index: SI, read_index: usize) -> Result<Location>
+    where
+        SI: FastForwardIndex,
+    {
+        if split_index.is_approximate() {
+            return Err(anyhow!(
+                "Cannot locate a read by its index with an approximate index, whose counts are \
+                estimates."
+            ));
+        }
+        let split_range = split_index
+            .get_record_for_num_reads(read_index + 1)
+            .ok_or_else(|| anyhow!("Read index {read_index} is past the end of the index."))?;
+        self.scan_to_location(&split_range, |_, _, scan_read_index| {
+            scan_read_index == read_index
+        })
+    }
+
+    /// Find the first record with the requested query name in the bin described by split_range.
+    fn locate_qname(&mut self, split_range: &SplitRange, qname: &[u8]) -> Result<Location> {
+        self.scan_to_location(split_range, |record, _, _| record.qname() == qname)
+    }
+
+    /// Seek to the start of the bin, then scan records until `found(record, query_index,
+    /// read_index)` is true for the current record, and return its Location. Errors if no record
+    /// in the bin is found.
+    fn scan_to_location<F>(&mut self, split_range: &SplitRange, found: F) -> Result<Location>
+    where
+        F: Fn(&R, usize, usize) -> bool,
+    {
+        self.seek_to_range(split_range)?;
+        let mut record = R::new();
+        let mut num_reads: usize = split_range.num_previous_reads;
+        let mut num_queries: usize = split_range.num_previous_queries;
+        // bins never split query groups, so the first record always starts a new query group
+        let mut last_query_name = LastQueryName::default();
+        while num_reads < split_range.num_end_reads {
+            let offset = self.tell()?;
+            self.read_no_missing(&mut record, &mut num_reads)?;
+            if num_reads == split_range.num_previous_reads + 1 {
+                split_range.verify_first_record(&record)?;
+            }
+            let key = split_range.group_by.key(&record);
+            if !last_query_name.matches(&key) {
+                num_queries += 1;
+                last_query_name.set(&key);
+            }
+            if found(&record, num_queries - 1, num_reads - 1) {
+                return Ok(Location {
+                    qname: record.qname().to_owned(),
+                    query_index: num_queries - 1,
+                    read_index: num_reads - 1,
+                    offset,
+                });
+            }
+        }
+        Err(anyhow!("Requested record not found in index bin."))
+    }
+
+    /// Find the 0-based indices of the query groups, in the bin described by split_range, that
+    /// hold a read longer than long_read_length.
+    fn find_long_queries(
+        &mut self,
+        split_range: &SplitRange,
+        long_read_length: usize,
+    ) -> Result<Vec<usize>> {
+        self.seek_to_range(split_range)?;
+        let mut record = R::new();
+        let mut num_reads: usize = split_range.num_previous_reads;
+        let mut num_queries: usize = split_range.num_previous_queries;
+        let mut last_query_name = LastQueryName::default();
+        let mut long_queries: Vec<usize> = Vec::new();
+        while num_reads < split_range.num_end_reads {
+            self.read_no_missing(&mut record, &mut num_reads)?;
+            if num_reads == split_range.num_previous_reads + 1 {
+                split_range.verify_first_record(&record)?;
+            }
+            let key = split_range.group_by.key(&record);
+            if !last_query_name.matches(&key) {
+                num_queries += 1;
+                last_query_name.set(&key);
+            }
+            if record.seq_len() > long_read_length
+                && long_queries.last() != Some(&(num_queries - 1))
+            {
+                long_queries.push(num_queries - 1);
+            }
+        }
+        Ok(long_queries)
     }
 
     /// Fast forward the reader to the beginning of the chunk that needs to be read
@@ -180,15 +1192,88 @@ where
         SI: FastForwardIndex,
     {
         // Number of completed queries that should have been read before this chunk starts
-        let mut start_num_queries: usize =
+        let start_num_queries: usize =
             split_index.get_chunk_query_start(chunk_index, num_chunks)?;
         // Number of completed queries that should have been read by the end of this chunk
         let stop_num_queries: usize =
             split_index.get_chunk_query_start(chunk_index + 1, num_chunks)?;
+        self.fast_forward_to_queries(split_index, start_num_queries, stop_num_queries)
+    }
+
+    /// Write the query groups from the 0-based query index start_num_queries up to (but not
+    /// including) stop_num_queries, reading and writing the same record type.
+    fn write_queries<SI, Writer>(
+        &mut self,
+        split_index: SI,
+        start_num_queries: usize,
+        stop_num_queries: usize,
+        writer: &mut Writer,
+    ) -> Result<ChunkResult>
+    where
+        Self: Send,
+        R: Send,
+        SI: FastForwardIndex,
+        Writer: ChunkableRecordWriter<R>,
+    {
+        match self.fast_forward_to_queries(split_index, start_num_queries, stop_num_queries)? {
+            Some(mut fast_forward_info) => fast_forward_info.write_chunk(writer),
+            None => Ok(ChunkResult::Empty),
+        }
+    }
+
+    /// Write the query groups from the 0-based query index start_num_queries up to (but not
+    /// including) stop_num_queries, translating to a different record type.
+    fn translate_and_write_queries<SI, WriteRecord, Writer>(
+        &mut self,
+        split_index: SI,
+        start_num_queries: usize,
+        stop_num_queries: usize,
+        writer: &mut Writer,
+        options: &TranslateOptions,
+    ) -> Result<ChunkResult>
+    where
+        Self: Send,
+        R: Send,
+        SI: FastForwardIndex,
+        WriteRecord: ChunkableRecord,
+        Writer: ChunkableRecordWriter<WriteRecord>,
+    {
+        match self.fast_forward_to_queries(split_index, start_num_queries, stop_num_queries)? {
+            Some(mut fast_forward_info) => {
+                fast_forward_info.translate_and_write_chunk(writer, options)
+            }
+            None => Ok(ChunkResult::Empty),
+        }
+    }
+
+    /// Fast forward the reader to the beginning of an arbitrary range of query groups, from the
+    /// 0-based query index start_num_queries up to (but not including) stop_num_queries.
+    /// This may involve reading the first record of that range, in which case return it.
+    fn fast_forward_to_queries<'a, SI>(
+        &'a mut self,
+        split_index: SI,
+        mut start_num_queries: usize,
+        stop_num_queries: usize,
+    ) -> Result<Option<FastForwardInfo<'a, R, Self>>>
+    where
+        SI: FastForwardIndex,
+    {
         if start_num_queries >= stop_num_queries {
             // This will be an empty chunk
             return Ok(None);
         }
+        if split_index.is_approximate() {
+            let stop_offset = split_index
+                .get_approximate_bin(stop_num_queries)
+                .map(|split_range| split_range.offset);
+            return match split_index.get_approximate_bin(start_num_queries) {
+                Some(split_range) if Some(split_range.offset) != stop_offset => {
+                    self.fast_forward_to_offsets(split_range, stop_offset)
+                }
+                // both ends fall at the same bin boundary
+                _ => Ok(None),
+            };
+        }
         // Get the SplitRange for the bin containing the requested start_num_queries
         let split_range = split_index
             .get_record_for_num_queries(start_num_queries)
@@ -198,7 +1283,7 @@ where
 
         // seek to the file offset
         info!("Seeking to {}", split_range.offset);
-        self.seek(split_range.offset)?;
+        self.seek_to_range(&split_range)?;
         // if necessary, read until we reach the requested number of queries
         let mut num_reads: usize = split_range.num_previous_reads;
         let mut record = R::new();
@@ -207,20 +1292,23 @@ where
             // The only way to know this is to *start* the query group AFTER start_num_queries
             let mut num_queries: usize = split_range.num_previous_queries;
             self.read_no_missing(&mut record, &mut num_reads)?;
-            let mut last_query_name = record.qname().to_owned();
+            split_range.verify_first_record(&record)?;
+            let mut last_query_name = LastQueryName::default();
+            last_query_name.set(&split_range.group_by.key(&record));
             num_queries += 1;
             while num_queries <= start_num_queries {
                 self.read_no_missing(&mut record, &mut num_reads)?;
-                let query_name = record.qname();
-                if query_name != last_query_name {
+                let key = split_range.group_by.key(&record);
+                if !last_query_name.matches(&key) {
                     num_queries += 1;
-                    last_query_name = query_name.to_owned();
+                    last_query_name.set(&key);
                 }
             }
             start_num_queries = num_queries;
         } else {
             // Always read the first record for algorithm simplicity
             self.read_no_missing(&mut record, &mut num_reads)?;
+            split_range.verify_first_record(&record)?;
             start_num_queries += 1; // this will be the start of a new query, because it's a new bin.
         };
         // We know that bins never split query groups, so set a limit on reads to avoid reading past
@@ -232,11 +1320,42 @@ where
 
         Ok(Some(FastForwardInfo {
             num_queries: start_num_queries,
-            stop_num_queries,
             num_reads,
-            hard_stop_num_reads,
+            end: ChunkEnd::Queries {
+                stop_num_queries,
+                hard_stop_num_reads,
+            },
+            record,
+            reader: self,
+            group_by: split_range.group_by,
+            max_inflight_records: DEFAULT_MAX_INFLIGHT_RECORDS,
+            transform: None,
+        }))
+    }
+
+    /// Fast forward the reader to the start of the bin described by split_range, to read every
+    /// record from there up to the record at stop_offset (or the end of the file if None). Used
+    /// for approximate indices, whose counts can't locate query groups.
+    fn fast_forward_to_offsets<'a>(
+        &'a mut self,
+        split_range: SplitRange,
+        stop_offset: Option<u64>,
+    ) -> Result<Option<FastForwardInfo<'a, R, Self>>> {
+        info!("Seeking to {}", split_range.offset);
+        self.seek_to_range(&split_range)?;
+        let mut num_reads: usize = split_range.num_previous_reads;
+        let mut record = R::new();
+        self.read_no_missing(&mut record, &mut num_reads)?;
+        split_range.verify_first_record(&record)?;
+        Ok(Some(FastForwardInfo {
+            num_queries: split_range.num_previous_queries + 1,
+            num_reads,
+            end: ChunkEnd::Offset(stop_offset),
             record,
             reader: self,
+            group_by: split_range.group_by,
+            max_inflight_records: DEFAULT_MAX_INFLIGHT_RECORDS,
+            transform: None,
         }))
     }
 }
@@ -247,6 +1366,108 @@ where
     R: ChunkableRecord,
 {
     fn write(&mut self, record: &R) -> Result<()>;
+
+    /// Return true if the writer wants no more query groups. Chunk writing checks this between
+    /// query groups, so output always ends on a whole query group.
+    fn is_full(&mut self) -> bool {
+        false
+    }
+
+    /// Position of the next record in the uncompressed output, counted from the first record, or
+    /// None if the writer does not track it. For BGZF output, convert positions to virtual
+    /// offsets with SplitIndex::resolve_output_offsets once the output is finished.
+    fn tell(&mut self) -> Result<Option<u64>> {
+        Ok(None)
+    }
+}
+
+/// Implement ChunkableRecordWriter for mutable references, so a writer can be lent to a wrapper
+/// that is consumed (e.g. by SplitIndex::build) and still be finished afterwards.
+impl<R, W> ChunkableRecordWriter<R> for &mut W
+where
+    R: ChunkableRecord,
+    W: ChunkableRecordWriter<R> + ?Sized,
+{
+    fn write(&mut self, record: &R) -> Result<()> {
+        (**self).write(record)
+    }
+
+    fn is_full(&mut self) -> bool {
+        (**self).is_full()
+    }
+
+    fn tell(&mut self) -> Result<Option<u64>> {
+        (**self).tell()
+    }
+}
+
+/// Outcome of transforming a record on its way from reader to writer
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TransformResult {
+    /// The record is written unchanged
+    Keep,
+    /// The record is not written
+    Drop,
+    /// The record was changed in place, and is written
+    Modified,
+}
+
+/// Public trait for a transform applied to every record of a chunk between reading and writing
+/// it, e.g. filtering, trimming, or renaming. Records are transformed after their query group is
+/// found, so transforms may change query names without moving chunk boundaries.
+pub trait RecordTransform<R>: fmt::Debug
+where
+    R: ChunkableRecord,
+{
+    /// Transform record in place, returning whether it is kept unchanged, dropped, or modified.
+    fn transform(&mut self, record: &mut R) -> Result<TransformResult>;
+}
+
+/// Convert FASTQ quality characters to Phred scores. FASTQ has no missing-quality sentinel, but
+/// an empty or "*" quality line for a non-empty sequence is treated as missing.
+fn fastq_qual_to_phred<'a>(seq: &[u8], qual: &'a [u8]) -> Option<Cow<'a, [u8]>> {
+    if qual.len() != seq.len() && (qual.is_empty() || qual == b"*") {
+        None
+    } else {
+        Some(Cow::Owned(
+            qual.iter()
+                .map(|q| q.saturating_sub(PHRED_OFFSET))
+                .collect(),
+        ))
+    }
+}
+
+/// Convert Phred scores to FASTQ quality characters, reusing the allocation of `fastq_qual`.
+fn set_fastq_qual(fastq_qual: &mut Vec<u8>, qual: &[u8]) {
+    fastq_qual.clear();
+    fastq_qual.extend(qual.iter().map(|q| q.saturating_add(PHRED_OFFSET)));
+}
+
+/// Replace the contents of `field` with `value`, reusing its allocation.
+fn set_field(field: &mut Vec<u8>, value: &[u8]) {
+    field.clear();
+    field.extend_from_slice(value);
+}
+
+/// Name of the query group of the last record seen while scanning, kept in a reusable buffer so
+/// that starting a new query group doesn't allocate. Matches no name until one is set.
+#[derive(Debug, Default)]
+pub(crate) struct LastQueryName {
+    name: Vec<u8>,
+    is_set: bool,
+}
+
+impl LastQueryName {
+    /// Return true if qname is the last query name.
+    pub(crate) fn matches(&self, qname: &[u8]) -> bool {
+        self.is_set && self.name == qname
+    }
+
+    /// Make qname the last query name.
+    pub(crate) fn set(&mut self, qname: &[u8]) {
+        set_field(&mut self.name, qname);
+        self.is_set = true;
+    }
 }
 
 /// Implement ChunkableRecord trait for BAM/SAM/CRAM records.
@@ -255,20 +1476,142 @@ impl ChunkableRecord for BamRecord {
         self.qname()
     }
 
-    fn qual(&self) -> &[u8] {
-        self.qual()
+    fn qual(&self) -> Option<Cow<'_, [u8]>> {
+        let qual = self.qual();
+        if qual.first() == Some(&MISSING_QUALITY_SENTINEL) {
+            None
+        } else {
+            Some(Cow::Borrowed(qual))
+        }
     }
 
-    fn seq(&self) -> &[u8] {
-        self.seq().encoded
+    fn seq(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(self.seq().as_bytes())
     }
 
     fn new() -> BamRecord {
         BamRecord::new()
     }
 
-    fn set_fields(&mut self, qname: &[u8], seq: &[u8], qual: &[u8]) {
-        self.set(qname, None, seq, qual)
+    fn set_qname(&mut self, qname: &[u8]) -> Result<()> {
+        if qname.len() > MAX_BAM_QNAME_LENGTH {
+            return Err(anyhow!(
+                "Query name {} is {} characters, but BAM allows at most {MAX_BAM_QNAME_LENGTH}.",
+                String::from_utf8_lossy(qname),
+                qname.len()
+            ));
+        }
+        BamRecord::set_qname(self, qname);
+        Ok(())
+    }
+
+    /// Keeps the CIGAR, which is only still valid if the length of the sequence is unchanged.
+    fn set_seq_qual(&mut self, seq: &[u8], qual: &[u8]) -> Result<()> {
+        if seq.len() != qual.len() {
+            return Err(anyhow!(
+                "Sequence and qualities of {} have different lengths.",
+                String::from_utf8_lossy(self.qname())
+            ));
+        }
+        let qname = self.qname().to_vec();
+        let cigar = self.cigar().take();
+        self.set(&qname, Some(&cigar), seq, qual);
+        Ok(())
+    }
+
+    fn seq_len(&self) -> usize {
+        // without decoding the sequence
+        BamRecord::seq_len(self)
+    }
+
+    fn read_group(&self) -> Option<&[u8]> {
+        match self.aux(b"RG") {
+            Ok(Aux::String(read_group)) => Some(read_group.as_bytes()),
+            _ => None,
+        }
+    }
+
+    fn flags(&self) -> Option<u16> {
+        Some(self.flags())
+    }
+
+    fn mate_info(&self) -> Option<MateInfo> {
+        self.is_paired().then(|| MateInfo {
+            tid: self.mtid(),
+            pos: self.mpos(),
+            insert_size: self.insert_size(),
+        })
+    }
+
+    fn tags(&self) -> Option<Vec<Vec<u8>>> {
+        Some(
+            self.aux_iter()
+                .filter_map(|aux| aux.ok())
+                .map(|(tag, aux)| format_aux(tag, aux))
+                .collect(),
+        )
+    }
+
+    fn tag_value(&self, tag: &[u8; 2]) -> Option<Cow<'_, [u8]>> {
+        match self.aux(tag).ok()? {
+            Aux::String(value) => Some(Cow::Borrowed(value.as_bytes())),
+            aux => Some(Cow::Owned(format_aux(tag, aux)[5..].to_vec())),
+        }
+    }
+
+    fn set_flags(&mut self, flags: u16) {
+        self.set_flags(flags);
+    }
+
+    fn set_mate_info(&mut self, mate_info: MateInfo) {
+        self.set_mtid(mate_info.tid);
+        self.set_mpos(mate_info.pos);
+        self.set_insert_size(mate_info.insert_size);
+    }
+
+    fn push_tag(&mut self, tag: &[u8]) -> Result<()> {
+        let (tag, type_code, value) = split_sam_tag(tag)?;
+        let value = std::str::from_utf8(value)?;
+        // the field may not be there yet
+        let _ = self.remove_aux(tag);
+        match type_code {
+            b'A' if value.len() == 1 => self.push_aux(tag, Aux::Char(value.as_bytes()[0]))?,
+            b'i' => self.push_aux(tag, Aux::I32(value.parse()?))?,
+            b'f' => self.push_aux(tag, Aux::Float(value.parse()?))?,
+            b'Z' => self.push_aux(tag, Aux::String(value))?,
+            b'H' => self.push_aux(tag, Aux::HexByteArray(value))?,
+            _ => {
+                return Err(anyhow!(
+                    "Cannot add optional field of type {}, only A, i, f, Z, and H.",
+                    type_code as char
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    fn set_fields(&mut self, qname: &[u8], seq: &[u8], qual: &[u8]) -> Result<()> {
+        if qname.len() > MAX_BAM_QNAME_LENGTH {
+            Err(anyhow!(
+                "Query name {} is {} characters, but BAM allows at most {MAX_BAM_QNAME_LENGTH}.",
+                String::from_utf8_lossy(qname),
+                qname.len()
+            ))
+        } else if seq.len() > MAX_BAM_SEQ_LENGTH {
+            Err(anyhow!(
+                "Sequence of {} is {} bases, but BAM allows at most {MAX_BAM_SEQ_LENGTH}.",
+                String::from_utf8_lossy(qname),
+                seq.len()
+            ))
+        } else if seq.len() != qual.len() {
+            Err(anyhow!(
+                "Sequence and qualities of {} have different lengths.",
+                String::from_utf8_lossy(qname)
+            ))
+        } else {
+            self.set(qname, None, seq, qual);
+            Ok(())
+        }
     }
 }
 
@@ -281,9 +1624,15 @@ impl ChunkableRecordReader<BamRecord> for BamReader {
         Ok(<BamReader as BamRead>::seek(self, offset as i64)?)
     }
 
+    /// htslib reports virtual offsets, even for uncompressed SAM
+    fn offset_kind(&self) -> OffsetKind {
+        OffsetKind::Virtual
+    }
+
     fn read_into(&mut self, record: &mut BamRecord) -> Option<Result<()>> {
         match self.read(record) {
-            Some(Err(err)) => Some(Err(anyhow!("{err}"))),
+            // keep the htslib error type, so callers can tell invalid records from truncation
+            Some(Err(err)) => Some(Err(err.into())),
             Some(Ok(())) => Some(Ok(())),
             None => None,
         }
@@ -297,6 +1646,104 @@ impl ChunkableRecordWriter<BamRecord> for BamWriter {
     }
 }
 
+/// BAM/SAM/CRAM writer that counts the uncompressed bytes of the records it writes, because
+/// rust_htslib writers cannot report their position. Only BAM output is counted: SAM and CRAM
+/// record sizes can't be known without encoding them again.
+pub struct CountingBamWriter<W = SamWriter> {
+    writer: W,
+    /// Uncompressed bytes of records written so far, or None if not counting
+    position: Option<u64>,
+}
+
+impl<W> CountingBamWriter<W> {
+    /// Wrap a writer that writes in the given format.
+    pub fn new(writer: W, format: BamFormat) -> Self {
+        let position = (format == BamFormat::Bam).then_some(0);
+        CountingBamWriter { writer, position }
+    }
+
+    /// Wrap a writer in any format, counting records as uncompressed BAM, e.g. to measure output
+    /// against a byte quota.
+    pub fn counting_as_bam(writer: W) -> Self {
+        CountingBamWriter {
+            writer,
+            position: Some(0),
+        }
+    }
+}
+
+/// Implement ChunkableRecordWriter trait for CountingBamWriter.
+impl<W> ChunkableRecordWriter<BamRecord> for CountingBamWriter<W>
+where
+    W: ChunkableRecordWriter<BamRecord>,
+{
+    fn write(&mut self, record: &BamRecord) -> Result<()> {
+        self.writer.write(record)?;
+        if let Some(ref mut position) = self.position {
+            // block_size, fixed-length fields, then variable-length data without qname padding
+            let inner = record.inner();
+            *position += 4 + 32 + inner.l_data as u64 - inner.core.l_extranul as u64;
+        }
+        Ok(())
+    }
+
+    fn tell(&mut self) -> Result<Option<u64>> {
+        Ok(self.position)
+    }
+}
+
+/// Writer that translates records of any type to the record type of the wrapped writer, e.g. to
+/// write FASTQ while reading BAM. Query names must survive translation unchanged (so e.g. "/1" and
+/// "/2" suffixes are an error), so that query groups in the output are the same as in the input.
+pub struct TranslatingWriter<T, W> {
+    writer: W,
+    /// Reused record holding each translated record
+    record: T,
+    options: TranslateOptions,
+}
+
+impl<T, W> TranslatingWriter<T, W>
+where
+    T: ChunkableRecord,
+{
+    /// Wrap a writer, translating records according to `options`.
+    pub fn new(writer: W, options: TranslateOptions) -> Self {
+        TranslatingWriter {
+            writer,
+            record: T::new(),
+            options,
+        }
+    }
+}
+
+/// Implement ChunkableRecordWriter for TranslatingWriter, for every record type that can be
+/// translated.
+impl<R, T, W> ChunkableRecordWriter<R> for TranslatingWriter<T, W>
+where
+    R: ChunkableRecord,
+    T: ChunkableRecord,
+    W: ChunkableRecordWriter<T>,
+{
+    fn write(&mut self, record: &R) -> Result<()> {
+        self.record.translate(record, &self.options)?;
+        if self.record.group_qname() != record.group_qname() {
+            return Err(anyhow!(
+                "Translating query {} renames it, which would change query groups.",
+                String::from_utf8_lossy(record.qname())
+            ));
+        }
+        self.writer.write(&self.record)
+    }
+
+    fn is_full(&mut self) -> bool {
+        self.writer.is_full()
+    }
+
+    fn tell(&mut self) -> Result<Option<u64>> {
+        self.writer.tell()
+    }
+}
+
 /// Implement ChunkableRecord trait for seq_io FASTQ records.
 impl ChunkableRecord for OwnedSeqIoFastqRecord {
     fn new() -> OwnedSeqIoFastqRecord {
@@ -309,17 +1756,57 @@ impl ChunkableRecord for OwnedSeqIoFastqRecord {
     fn qname(&self) -> &[u8] {
         self.id_bytes()
     }
-    fn qual(&self) -> &[u8] {
-        &self.qual
+    fn qual(&self) -> Option<Cow<'_, [u8]>> {
+        fastq_qual_to_phred(&self.seq, &self.qual)
+    }
+    fn seq(&self) -> Cow<'_, [u8]> {
+        Cow::Borrowed(&self.seq)
+    }
+
+    fn set_fields(&mut self, qname: &[u8], seq: &[u8], qual: &[u8]) -> Result<()> {
+        set_field(&mut self.head, qname);
+        set_field(&mut self.seq, seq);
+        set_fastq_qual(&mut self.qual, qual);
+        Ok(())
+    }
+
+    fn set_qname(&mut self, qname: &[u8]) -> Result<()> {
+        self.head
+            .splice(..self.id_bytes().len(), qname.iter().copied());
+        Ok(())
+    }
+
+    fn set_seq_qual(&mut self, seq: &[u8], qual: &[u8]) -> Result<()> {
+        set_field(&mut self.seq, seq);
+        set_fastq_qual(&mut self.qual, qual);
+        Ok(())
+    }
+
+    fn tags(&self) -> Option<Vec<Vec<u8>>> {
+        self.desc_bytes().map(comment_tags)
+    }
+
+    fn comment(&self) -> Option<&[u8]> {
+        self.desc_bytes()
+    }
+
+    fn tag_value(&self, tag: &[u8; 2]) -> Option<Cow<'_, [u8]>> {
+        comment_tag_value(self.desc_bytes()?, tag).map(Cow::Borrowed)
     }
-    fn seq(&self) -> &[u8] {
-        &self.seq
+
+    fn set_comment(&mut self, comment: &[u8]) {
+        self.head.truncate(self.id_bytes().len());
+        if !comment.is_empty() {
+            self.head.push(b'\t');
+            self.head.extend_from_slice(comment);
+        }
     }
 
-    fn set_fields(&mut self, qname: &[u8], seq: &[u8], qual: &[u8]) {
-        self.head = qname.to_vec();
-        self.seq = seq.to_vec();
-        self.qual = qual.to_vec();
+    fn push_tag(&mut self, tag: &[u8]) -> Result<()> {
+        split_sam_tag(tag)?;
+        let qname_len = self.id_bytes().len();
+        push_comment_tag(&mut self.head, qname_len, tag);
+        Ok(())
     }
 }
 
@@ -337,12 +1824,19 @@ where
         Ok(self.seek(&pos)?)
     }
 
+    /// seq_io counts bytes read from the (decompressed) stream
+    fn offset_kind(&self) -> OffsetKind {
+        OffsetKind::Byte
+    }
+
     fn read_into(&mut self, record: &mut OwnedSeqIoFastqRecord) -> Option<Result<()>> {
         match self.next() {
             None => None,
             Some(Err(err)) => Some(Err(anyhow!("{err}"))),
             Some(Ok(ref_record)) => {
-                ref_record.to_owned_record().clone_into(record);
+                set_field(&mut record.head, ref_record.head());
+                set_field(&mut record.seq, ref_record.seq());
+                set_field(&mut record.qual, ref_record.qual());
                 Some(Ok(()))
             }
         }
@@ -363,32 +1857,79 @@ impl ChunkableRecord for FastqRecord {
     }
 
     fn qname(&self) -> &[u8] {
-        &self.name
+        FastqRecord::qname(self)
+    }
+
+    fn group_qname(&self) -> &[u8] {
+        FastqRecord::mate_qname(self)
+    }
+
+    fn qual(&self) -> Option<Cow<'_, [u8]>> {
+        fastq_qual_to_phred(&self.sequence, &self.qualities)
+    }
+
+    fn seq(&self) -> Cow<'_, [u8]> {
+        Cow::Borrowed(&self.sequence)
+    }
+
+    fn set_fields(&mut self, qname: &[u8], seq: &[u8], qual: &[u8]) -> Result<()> {
+        set_field(&mut self.name, qname);
+        set_field(&mut self.sequence, seq);
+        set_field(&mut self.separator, b"+");
+        set_fastq_qual(&mut self.qualities, qual);
+        Ok(())
+    }
+
+    fn set_qname(&mut self, qname: &[u8]) -> Result<()> {
+        let qname_len = FastqRecord::qname(self).len();
+        self.name.splice(..qname_len, qname.iter().copied());
+        Ok(())
+    }
+
+    fn set_seq_qual(&mut self, seq: &[u8], qual: &[u8]) -> Result<()> {
+        set_field(&mut self.sequence, seq);
+        set_fastq_qual(&mut self.qualities, qual);
+        Ok(())
+    }
+
+    fn tags(&self) -> Option<Vec<Vec<u8>>> {
+        FastqRecord::comment(self).map(comment_tags)
+    }
+
+    fn comment(&self) -> Option<&[u8]> {
+        FastqRecord::comment(self)
     }
 
-    fn qual(&self) -> &[u8] {
-        &self.qualities
+    fn tag_value(&self, tag: &[u8; 2]) -> Option<Cow<'_, [u8]>> {
+        comment_tag_value(FastqRecord::comment(self)?, tag).map(Cow::Borrowed)
     }
 
-    fn seq(&self) -> &[u8] {
-        &self.sequence
+    fn set_comment(&mut self, comment: &[u8]) {
+        FastqRecord::set_comment(self, comment)
     }
 
-    fn set_fields(&mut self, qname: &[u8], seq: &[u8], qual: &[u8]) {
-        self.name = qname.to_vec();
-        self.sequence = seq.to_vec();
-        self.qualities = qual.to_vec();
+    fn push_tag(&mut self, tag: &[u8]) -> Result<()> {
+        split_sam_tag(tag)?;
+        let qname_len = FastqRecord::qname(self).len();
+        push_comment_tag(&mut self.name, qname_len, tag);
+        Ok(())
     }
 }
 
 /// Implement ChunkableRecordReader trait for custom FASTQ readers.
-impl<R: BufRead + Seek> ChunkableRecordReader<FastqRecord> for FastqReader<R> {
+impl<R: BufRead + Seek + OffsetKindSource> ChunkableRecordReader<FastqRecord> for FastqReader<R> {
     fn tell(&mut self) -> Result<u64> {
         Ok(self.stream_position()?)
         // let offset = self.stream_position()?;
         // info!("offset: {offset}");
         // Ok(offset)
     }
+    fn tell_uncompressed(&mut self) -> Result<Option<u64>> {
+        Ok(self.uncompressed_position())
+    }
+    fn offset_kind(&self) -> OffsetKind {
+        self.get_ref().offset_kind()
+    }
     fn seek(&mut self, offset: u64) -> Result<()> {
         if let Err(err) = <FastqReader<R> as Seek>::seek(self, SeekFrom::Start(offset)) {
             Err(anyhow!("{err}"))
@@ -398,14 +1939,7 @@ impl<R: BufRead + Seek> ChunkableRecordReader<FastqRecord> for FastqReader<R> {
     }
 
     fn read_into(&mut self, record: &mut FastqRecord) -> Option<Result<()>> {
-        match self.next() {
-            None => None,
-            Some(Err(err)) => Some(Err(anyhow!("{err}"))),
-            Some(Ok(fastq_record)) => {
-                *record = fastq_record;
-                Some(Ok(()))
-            }
-        }
+        self.read_record_into(record)
     }
 }
 
@@ -414,4 +1948,615 @@ impl<W: Write> ChunkableRecordWriter<FastqRecord> for FastqWriter<W> {
     fn write(&mut self, record: &FastqRecord) -> Result<()> {
         FastqWriter::<W>::write(self, record)
     }
+
+    fn tell(&mut self) -> Result<Option<u64>> {
+        Ok(Some(self.position()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        ChunkResult, ChunkableRecord, ChunkableRecordReader, ChunkableRecordWriter, LastQueryName,
+        MAX_BAM_QNAME_LENGTH, MISSING_QUALITY_SENTINEL, MateInfo, MissingQualityPolicy, OffsetKind,
+    };
+    use crate::{
+        fastq::{FastqReader, FastqRecord, FastqWriter},
+        split_index::SplitIndex,
+        translate_options::{PairEnd, ReadGroupStamp, TranslateOptions},
+    };
+    use anyhow::Result;
+    use rust_htslib::bam::{
+        Header, HeaderView, Record as BamRecord, header::HeaderRecord, record::Aux,
+    };
+    use std::{
+        io::Cursor,
+        num::NonZero,
+        sync::{
+            Arc,
+            atomic::{AtomicUsize, Ordering},
+        },
+    };
+
+    /// Make TranslateOptions with the requested missing-quality policy.
+    fn options(missing_quality: MissingQualityPolicy) -> TranslateOptions {
+        TranslateOptions::new()
+            .missing_quality(missing_quality)
+            .to_owned()
+    }
+
+    /// Make a BAM record with the given sequence and Phred qualities.
+    fn bam_record(seq: &[u8], qual: &[u8]) -> BamRecord {
+        let mut record = BamRecord::new();
+        record.set(b"read1", None, seq, qual);
+        record
+    }
+
+    /// Test that qualities survive translation from BAM to FASTQ and back.
+    #[test]
+    fn test_translate_qualities_round_trip() -> Result<()> {
+        let bam = bam_record(b"ACGT", &[0, 10, 20, 40]);
+        let mut fastq = FastqRecord::new();
+        fastq.translate(&bam, &options(MissingQualityPolicy::Strict))?;
+        assert_eq!(fastq.sequence, b"ACGT");
+        assert_eq!(fastq.qualities, b"!+5I");
+
+        let mut round_trip = BamRecord::new();
+        round_trip.translate(&fastq, &options(MissingQualityPolicy::Strict))?;
+        assert_eq!(round_trip.seq().as_bytes(), b"ACGT");
+        assert_eq!(round_trip.qual(), &[0, 10, 20, 40]);
+        Ok(())
+    }
+
+    /// Test that missing BAM qualities are filled with the constant quality, or error if strict.
+    #[test]
+    fn test_translate_missing_bam_qualities() -> Result<()> {
+        let bam = bam_record(b"ACGT", &[MISSING_QUALITY_SENTINEL; 4]);
+        assert!(ChunkableRecord::qual(&bam).is_none());
+        let mut fastq = FastqRecord::new();
+        fastq.translate(&bam, &options(MissingQualityPolicy::Fill(30)))?;
+        assert_eq!(fastq.qualities, b"????");
+        assert!(
+            fastq
+                .translate(&bam, &options(MissingQualityPolicy::Strict))
+                .is_err()
+        );
+        Ok(())
+    }
+
+    /// Test that missing FASTQ qualities ("*" or empty) are filled, or error if strict.
+    #[test]
+    fn test_translate_missing_fastq_qualities() -> Result<()> {
+        for missing in [b"*".to_vec(), Vec::new()] {
+            let mut fastq = FastqRecord::new();
+            fastq.set_fields(b"read1", b"ACGT", &[0; 4])?;
+            fastq.qualities = missing;
+            let mut bam = BamRecord::new();
+            bam.translate(&fastq, &options(MissingQualityPolicy::Fill(2)))?;
+            assert_eq!(bam.qual(), &[2, 2, 2, 2]);
+            assert!(
+                bam.translate(&fastq, &options(MissingQualityPolicy::Strict))
+                    .is_err()
+            );
+        }
+        // A single-base read with quality "*" (Phred 9) is not missing.
+        let mut fastq = FastqRecord::new();
+        fastq.set_fields(b"read1", b"A", &[9])?;
+        assert_eq!(fastq.qualities, b"*");
+        let mut bam = BamRecord::new();
+        bam.translate(&fastq, &options(MissingQualityPolicy::Strict))?;
+        assert_eq!(bam.qual(), &[9]);
+        Ok(())
+    }
+
+    /// Test the optional flag, mate, and tag accessors, and that translation carries flags and mate
+    /// information between records that hold them.
+    #[test]
+    fn test_optional_fields() -> Result<()> {
+        let mut bam = bam_record(b"ACGT", &[30; 4]);
+        bam.set_flags(0x1 | 0x4 | 0x8 | 0x40);
+        bam.set_mtid(-1);
+        bam.set_mpos(-1);
+        bam.push_aux(b"RG", Aux::String("A"))?;
+        bam.push_aux(b"NM", Aux::U8(3))?;
+        bam.push_aux(b"ZB", Aux::ArrayI16((&[1i16, -2][..]).into()))?;
+        assert_eq!(ChunkableRecord::flags(&bam), Some(0x4d));
+        assert_eq!(
+            bam.mate_info(),
+            Some(MateInfo {
+                tid: -1,
+                pos: -1,
+                insert_size: 0
+            })
+        );
+        assert_eq!(
+            bam.tags(),
+            Some(vec![
+                b"RG:Z:A".to_vec(),
+                b"NM:i:3".to_vec(),
+                b"ZB:B:s,1,-2".to_vec()
+            ])
+        );
+
+        let mut copy = BamRecord::new();
+        copy.translate(&bam, &options(MissingQualityPolicy::Strict))?;
+        assert_eq!(copy.flags(), 0x4d);
+        assert_eq!(copy.mate_info(), bam.mate_info());
+
+        let mut fastq = FastqRecord::new();
+        fastq.translate(&bam, &options(MissingQualityPolicy::Strict))?;
+        assert_eq!(ChunkableRecord::flags(&fastq), None);
+        assert_eq!(fastq.mate_info(), None);
+        assert_eq!(fastq.tags(), None);
+        ChunkableRecord::set_comment(&mut fastq, b"RG:Z:A\tSM:Z:sample 1:N:0:ACGT");
+        assert_eq!(
+            fastq.tags(),
+            Some(vec![b"RG:Z:A".to_vec(), b"SM:Z:sample".to_vec()])
+        );
+        Ok(())
+    }
+
+    /// Test that selected tags survive a round trip from BAM through FASTQ comments and back.
+    #[test]
+    fn test_copy_tags() -> Result<()> {
+        let mut bam = bam_record(b"ACGT", &[30; 4]);
+        bam.push_aux(b"RG", Aux::String("A"))?;
+        bam.push_aux(b"RX", Aux::String("ACGT-TTGA"))?;
+        bam.push_aux(b"NM", Aux::U8(3))?;
+        let mut options = options(MissingQualityPolicy::Strict);
+        options.copy_tags(vec![*b"RX", *b"RG"]);
+        assert!(options.is_copied(b"RX:Z:A"));
+        assert!(!options.is_copied(b"RXZ:A"));
+
+        let mut fastq = FastqRecord::new();
+        fastq.translate(&bam, &options)?;
+        assert_eq!(fastq.comment(), Some(b"RG:Z:A\tRX:Z:ACGT-TTGA".as_slice()));
+        let mut back = BamRecord::new();
+        back.translate(&fastq, &options)?;
+        assert_eq!(back.aux(b"RG")?, Aux::String("A"));
+        assert_eq!(back.aux(b"RX")?, Aux::String("ACGT-TTGA"));
+        assert!(back.aux(b"NM").is_err());
+
+        options.copy_tags(Vec::new());
+        fastq.translate(&bam, &options)?;
+        assert_eq!(fastq.comment(), None);
+        Ok(())
+    }
+
+    /// Test that FASTQ reads translate to unaligned BAM records with pair flags from "/1" "/2"
+    /// suffixes, or from the pair end of the file, and are otherwise unpaired.
+    #[test]
+    fn test_translate_pair_flags() -> Result<()> {
+        assert_eq!(
+            PairEnd::from_path("dir/sample_S1_L001_R1_001.fastq.gz"),
+            Some(PairEnd::First)
+        );
+        assert_eq!(PairEnd::from_path("sample.r2.fq"), Some(PairEnd::Second));
+        assert_eq!(PairEnd::from_path("sample_2.fastq"), Some(PairEnd::Second));
+        assert_eq!(PairEnd::from_path("sample_R1_R2.fastq"), None);
+        assert_eq!(PairEnd::from_path("sample_1_trimmed.fastq"), None);
+        assert_eq!(PairEnd::from_path("sample.fastq"), None);
+
+        let mut fastq = FastqRecord::new();
+        let mut bam = BamRecord::new();
+        let mut options = options(MissingQualityPolicy::Strict);
+        for (qname, pair_end, expected_qname, expected_flags) in [
+            (b"read1/1".as_slice(), None, b"read1".as_slice(), 0x4d),
+            (b"read1/2", None, b"read1", 0x8d),
+            (b"read1/2", Some(PairEnd::First), b"read1", 0x8d),
+            (b"read1", Some(PairEnd::First), b"read1", 0x4d),
+            (b"read1", Some(PairEnd::Second), b"read1", 0x8d),
+            (b"read1", None, b"read1", 0x4),
+            (b"/1", None, b"/1", 0x4),
+        ] {
+            fastq.set_fields(qname, b"ACGT", &[30; 4])?;
+            options.pair_end(pair_end);
+            bam.translate(&fastq, &options)?;
+            assert_eq!(bam.qname(), expected_qname);
+            assert_eq!(bam.flags(), expected_flags);
+            assert!(bam.is_unmapped());
+            assert_eq!(
+                (bam.tid(), bam.pos(), bam.mtid(), bam.mpos()),
+                (-1, -1, -1, -1)
+            );
+        }
+        Ok(())
+    }
+
+    /// Test that ultralong reads translate from BAM to FASTQ.
+    #[test]
+    fn test_translate_long_read() -> Result<()> {
+        let seq = b"ACGT".repeat(1 << 19);
+        let bam = bam_record(&seq, &vec![30; seq.len()]);
+        let mut fastq = FastqRecord::new();
+        fastq.translate(&bam, &options(MissingQualityPolicy::Strict))?;
+        assert_eq!(fastq.sequence, seq);
+        assert_eq!(fastq.qualities, vec![b'?'; seq.len()]);
+        Ok(())
+    }
+
+    /// Test that query names too long for BAM are an error rather than a panic.
+    #[test]
+    fn test_translate_long_qname() -> Result<()> {
+        let mut fastq = FastqRecord::new();
+        fastq.set_fields(&[b'N'; MAX_BAM_QNAME_LENGTH], b"ACGT", &[30; 4])?;
+        let mut bam = BamRecord::new();
+        bam.translate(&fastq, &options(MissingQualityPolicy::Strict))?;
+        assert_eq!(bam.qname().len(), MAX_BAM_QNAME_LENGTH);
+
+        fastq.set_fields(&[b'N'; MAX_BAM_QNAME_LENGTH + 1], b"ACGT", &[30; 4])?;
+        assert!(
+            bam.translate(&fastq, &options(MissingQualityPolicy::Strict))
+                .is_err()
+        );
+        Ok(())
+    }
+
+    /// Test that read group and sample are stamped into FASTQ comments when requested.
+    #[test]
+    fn test_translate_read_group_stamp() -> Result<()> {
+        let mut header = Header::new();
+        header.push_record(
+            HeaderRecord::new(b"RG")
+                .push_tag(b"ID", "rg1")
+                .push_tag(b"SM", "sample1"),
+        );
+        let header_view = HeaderView::from_header(&header);
+        let mut bam = bam_record(b"ACGT", &[30; 4]);
+        bam.push_aux(b"RG", Aux::String("rg1"))?;
+
+        let mut fastq = FastqRecord::new();
+        fastq.translate(&bam, &TranslateOptions::new())?;
+        assert_eq!(fastq.name, b"read1");
+
+        let mut stamp = ReadGroupStamp::new();
+        stamp.read_group(true);
+        let mut options = TranslateOptions::new();
+        options.read_group_stamp(stamp.clone());
+        fastq.translate(&bam, &options)?;
+        assert_eq!(fastq.name, b"read1\tRG:Z:rg1");
+
+        stamp.samples_from_header(&header_view);
+        options.read_group_stamp(stamp);
+        fastq.translate(&bam, &options)?;
+        assert_eq!(fastq.name, b"read1\tRG:Z:rg1\tSM:Z:sample1");
+        assert_eq!(ChunkableRecord::qname(&fastq), b"read1");
+
+        // reads without a read group get no comment
+        let bam = bam_record(b"ACGT", &[30; 4]);
+        fastq.translate(&bam, &options)?;
+        assert_eq!(fastq.name, b"read1");
+        Ok(())
+    }
+
+    /// Writer that keeps records in memory
+    struct MemoryWriter(Vec<BamRecord>);
+
+    impl ChunkableRecordWriter<BamRecord> for MemoryWriter {
+        fn write(&mut self, record: &BamRecord) -> Result<()> {
+            self.0.push(record.clone());
+            Ok(())
+        }
+    }
+
+    /// Test that the last query name matches nothing until set, and reuses its buffer for each new
+    /// query name.
+    #[test]
+    fn test_last_query_name() {
+        let mut last_query_name = LastQueryName::default();
+        assert!(!last_query_name.matches(b""));
+        last_query_name.set(b"a-long-first-query-name");
+        let buffer = last_query_name.name.as_ptr();
+        assert!(last_query_name.matches(b"a-long-first-query-name"));
+        for qname in [b"read1".as_slice(), b"read2", b"read2/1"] {
+            last_query_name.set(qname);
+            assert!(last_query_name.matches(qname));
+            assert!(!last_query_name.matches(b"read"));
+        }
+        assert_eq!(last_query_name.name.as_ptr(), buffer);
+    }
+
+    /// Test that writing a range of queries reports the reads and queries written, or Empty.
+    #[test]
+    fn test_chunk_result() -> Result<()> {
+        // 100 paired queries
+        let fastq: Vec<u8> = (0..200)
+            .flat_map(|index| format!("@read{:03}\nACGT\n+\nIIII\n", index / 2).into_bytes())
+            .collect();
+        let split_index = SplitIndex::build::<FastqRecord, _, FastqWriter<Vec<u8>>>(
+            FastqReader::new(Cursor::new(fastq.clone())),
+            None,
+            NonZero::new(10).unwrap(),
+            u64::MAX,
+        )?;
+        let mut reader = FastqReader::new(Cursor::new(fastq));
+        let mut bytes: Vec<u8> = Vec::new();
+        let chunk_result = reader.write_queries(
+            split_index.clone(),
+            15,
+            40,
+            &mut FastqWriter::new(&mut bytes),
+        )?;
+        assert_eq!(
+            chunk_result,
+            ChunkResult::Written {
+                reads: 50,
+                queries: 25
+            }
+        );
+        assert_eq!(bytes.iter().filter(|&&byte| byte == b'@').count(), 50);
+
+        let mut translated = MemoryWriter(Vec::new());
+        let chunk_result = reader.translate_and_write_queries(
+            split_index.clone(),
+            0,
+            100,
+            &mut translated,
+            &options(MissingQualityPolicy::Strict),
+        )?;
+        assert_eq!(chunk_result.num_reads(), 200);
+        assert_eq!(chunk_result.num_queries(), 100);
+        assert_eq!(translated.0.len(), 200);
+
+        let chunk_result =
+            reader.write_queries(split_index, 30, 30, &mut FastqWriter::new(&mut bytes))?;
+        assert!(chunk_result.is_empty());
+        assert_eq!(chunk_result.num_reads(), 0);
+        Ok(())
+    }
+
+    /// Writer that keeps FASTQ query names in memory, and is full once it has max_reads reads
+    struct LimitedWriter {
+        names: Vec<Vec<u8>>,
+        max_reads: usize,
+    }
+
+    impl ChunkableRecordWriter<FastqRecord> for LimitedWriter {
+        fn write(&mut self, record: &FastqRecord) -> Result<()> {
+            self.names.push(record.name.clone());
+            Ok(())
+        }
+
+        fn is_full(&mut self) -> bool {
+            self.names.len() >= self.max_reads
+        }
+    }
+
+    /// Test that chunks spanning many pipeline batches are written in order, and that a full
+    /// writer stops them at the end of the query group it started.
+    #[test]
+    fn test_pipeline_chunk() -> Result<()> {
+        // 1000 queries of 3 reads each
+        let fastq: Vec<u8> = (0..3000)
+            .flat_map(|index| format!("@read{:04}\nACGT\n+\nIIII\n", index / 3).into_bytes())
+            .collect();
+        let split_index = SplitIndex::build::<FastqRecord, _, FastqWriter<Vec<u8>>>(
+            FastqReader::new(Cursor::new(fastq.clone())),
+            None,
+            NonZero::new(7).unwrap(),
+            u64::MAX,
+        )?;
+        let mut reader = FastqReader::new(Cursor::new(fastq));
+        let mut writer = LimitedWriter {
+            names: Vec::new(),
+            max_reads: usize::MAX,
+        };
+        let chunk_result = reader.write_queries(split_index.clone(), 100, 900, &mut writer)?;
+        assert_eq!(chunk_result.num_queries(), 800);
+        let expected: Vec<Vec<u8>> = (300..2700)
+            .map(|index| format!("read{:04}", index / 3).into_bytes())
+            .collect();
+        assert_eq!(writer.names, expected);
+
+        let mut writer = LimitedWriter {
+            names: Vec::new(),
+            max_reads: 700,
+        };
+        let chunk_result = reader.write_queries(split_index, 0, 1000, &mut writer)?;
+        assert_eq!(
+            chunk_result,
+            ChunkResult::Written {
+                reads: 705,
+                queries: 235
+            }
+        );
+        assert_eq!(writer.names.last().unwrap(), b"read0234");
+        Ok(())
+    }
+
+    /// Test that fast-forwarding checks the first read of the bin it seeks to, erroring if the
+    /// reads file has the same layout but not the indexed reads, and that the check passes on the
+    /// indexed file.
+    #[test]
+    fn test_first_record_hash() -> Result<()> {
+        // 100 queries of 2 reads each, and the same reads with query names shifted by one
+        let fastq = |shift: usize| -> Vec<u8> {
+            (0..200)
+                .flat_map(|index| {
+                    format!("@read{:04}\nACGT\n+\nIIII\n", index / 2 + shift).into_bytes()
+                })
+                .collect()
+        };
+        let split_index = SplitIndex::build::<FastqRecord, _, FastqWriter<Vec<u8>>>(
+            FastqReader::new(Cursor::new(fastq(0))),
+            None,
+            NonZero::new(5).unwrap(),
+            u64::MAX,
+        )?;
+        assert!(split_index.has_first_record_hashes());
+        let mut writer = LimitedWriter {
+            names: Vec::new(),
+            max_reads: usize::MAX,
+        };
+        let mut reader = FastqReader::new(Cursor::new(fastq(0)));
+        let chunk_result = reader.write_queries(split_index.clone(), 40, 60, &mut writer)?;
+        assert_eq!(chunk_result.num_queries(), 20);
+
+        let mut shifted = FastqReader::new(Cursor::new(fastq(1)));
+        let err = shifted
+            .write_queries(split_index.clone(), 40, 60, &mut writer)
+            .unwrap_err();
+        assert!(
+            err.to_string().contains("index does not match file"),
+            "{err}"
+        );
+
+        let mut unchecked = split_index;
+        unchecked.clear_first_record_hashes();
+        let chunk_result = shifted.write_queries(unchecked, 40, 60, &mut writer)?;
+        assert_eq!(chunk_result.num_queries(), 20);
+        Ok(())
+    }
+
+    /// Test that locating a query group, as copying whole BGZF blocks does to find the ends of a
+    /// chunk, checks the first read of the bin it seeks to.
+    #[test]
+    fn test_locate_first_record_hash() -> Result<()> {
+        let fastq = |shift: usize| -> Vec<u8> {
+            (0..200)
+                .flat_map(|index| {
+                    format!("@read{:04}\nACGT\n+\nIIII\n", index / 2 + shift).into_bytes()
+                })
+                .collect()
+        };
+        let split_index = SplitIndex::build::<FastqRecord, _, FastqWriter<Vec<u8>>>(
+            FastqReader::new(Cursor::new(fastq(0))),
+            None,
+            NonZero::new(5).unwrap(),
+            u64::MAX,
+        )?;
+        let mut reader = FastqReader::new(Cursor::new(fastq(0)));
+        let location = reader.locate_query(split_index.clone(), 45)?;
+        assert_eq!(location.qname, b"read0045");
+
+        let mut shifted = FastqReader::new(Cursor::new(fastq(1)));
+        let err = shifted.locate_query(split_index, 45).unwrap_err();
+        assert!(
+            err.to_string().contains("index does not match file"),
+            "{err}"
+        );
+        Ok(())
+    }
+
+    /// Reader that counts the records it has read, so a test can watch the reading thread
+    struct CountingReader {
+        inner: FastqReader<Cursor<Vec<u8>>>,
+        num_read: Arc<AtomicUsize>,
+    }
+
+    impl ChunkableRecordReader<FastqRecord> for CountingReader {
+        fn tell(&mut self) -> Result<u64> {
+            self.inner.tell()
+        }
+
+        fn seek(&mut self, offset: u64) -> Result<()> {
+            self.inner.seek(offset)
+        }
+
+        fn offset_kind(&self) -> OffsetKind {
+            self.inner.offset_kind()
+        }
+
+        fn read_into(&mut self, record: &mut FastqRecord) -> Option<Result<()>> {
+            let result = self.inner.read_into(record);
+            if let Some(Ok(())) = result {
+                self.num_read.fetch_add(1, Ordering::SeqCst);
+            }
+            result
+        }
+    }
+
+    /// Slow writer that checks how far the reading thread has run ahead of it
+    struct SlowWriter {
+        num_read: Arc<AtomicUsize>,
+        num_written: usize,
+        max_ahead: usize,
+    }
+
+    impl ChunkableRecordWriter<FastqRecord> for SlowWriter {
+        fn write(&mut self, _record: &FastqRecord) -> Result<()> {
+            std::thread::sleep(std::time::Duration::from_micros(50));
+            self.num_written += 1;
+            let ahead = self.num_read.load(Ordering::SeqCst) - self.num_written;
+            self.max_ahead = self.max_ahead.max(ahead);
+            Ok(())
+        }
+    }
+
+    /// Test that max_inflight_records bounds how far reading runs ahead of a slow writer.
+    #[test]
+    fn test_max_inflight_records() -> Result<()> {
+        let fastq: Vec<u8> = (0..2000)
+            .flat_map(|index| format!("@read{:04}\nACGT\n+\nIIII\n", index / 2).into_bytes())
+            .collect();
+        let split_index = SplitIndex::build::<FastqRecord, _, FastqWriter<Vec<u8>>>(
+            FastqReader::new(Cursor::new(fastq.clone())),
+            None,
+            NonZero::new(10).unwrap(),
+            u64::MAX,
+        )?;
+        for max_inflight_records in [1, 10, 100] {
+            let num_read = Arc::new(AtomicUsize::new(0));
+            let mut reader = CountingReader {
+                inner: FastqReader::new(Cursor::new(fastq.clone())),
+                num_read: num_read.clone(),
+            };
+            let mut writer = SlowWriter {
+                num_read,
+                num_written: 0,
+                max_ahead: 0,
+            };
+            let chunk_result = reader
+                .fast_forward_to_queries(split_index.clone(), 0, 1000)?
+                .unwrap()
+                .max_inflight_records(NonZero::new(max_inflight_records).unwrap())
+                .write_chunk(&mut writer)?;
+            assert_eq!(chunk_result.num_reads(), 2000);
+            // the fast-forwarded record waiting to be sent may be one beyond the bound
+            assert!(
+                writer.max_ahead <= max_inflight_records + 1,
+                "{} records in flight, more than {max_inflight_records}",
+                writer.max_ahead
+            );
+        }
+        Ok(())
+    }
+
+    /// Test that a ChunkCursor pulls the same records that writing the chunk writes.
+    #[test]
+    fn test_chunk_cursor() -> Result<()> {
+        // 500 queries of 1 to 3 reads each
+        let fastq: Vec<u8> = (0..500)
+            .flat_map(|query| {
+                (0..1 + query % 3)
+                    .flat_map(move |_| format!("@read{query:03}\nACGT\n+\nIIII\n").into_bytes())
+            })
+            .collect();
+        let split_index = SplitIndex::build::<FastqRecord, _, FastqWriter<Vec<u8>>>(
+            FastqReader::new(Cursor::new(fastq.clone())),
+            None,
+            NonZero::new(9).unwrap(),
+            u64::MAX,
+        )?;
+        let mut reader = FastqReader::new(Cursor::new(fastq));
+        for (start, stop) in [(0, 500), (0, 1), (37, 212), (499, 500)] {
+            let mut writer = LimitedWriter {
+                names: Vec::new(),
+                max_reads: usize::MAX,
+            };
+            reader.write_queries(split_index.clone(), start, stop, &mut writer)?;
+            let mut cursor = reader
+                .fast_forward_to_queries(split_index.clone(), start, stop)?
+                .unwrap()
+                .into_cursor();
+            let mut record = FastqRecord::new();
+            let mut names = Vec::new();
+            while cursor.next_record(&mut reader, &mut record)? {
+                names.push(record.name.clone());
+            }
+            assert_eq!(names, writer.names, "queries {start}..{stop}");
+            assert!(!cursor.next_record(&mut reader, &mut record)?);
+        }
+        Ok(())
+    }
 }
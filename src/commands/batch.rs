@@ -0,0 +1,312 @@
+use crate::{Args, Subcommand, commands::command::Command};
+use anyhow::{Result, anyhow};
+use clap::{CommandFactory, Parser};
+use log::info;
+use split_reads::thread_budget::Threads;
+use std::{fs, iter::once, path::PathBuf};
+
+/// Run the operations listed in a batch file in order, in a single process. The batch file is a
+/// small subset of YAML: an optional thread budget and seed shared by the operations, and a list
+/// of split-reads command lines, e.g.
+///
+///     threads: 8
+///     operations:
+///       - index -i sample1.bam
+///       - get-chunk -i sample1.bam -c 0 -n 2 -o sample1.0.bam
+///       - get-chunk -i sample1.bam -c 1 -n 2 -o sample1.1.bam
+///
+/// Command lines are split on whitespace, except within single or double quotes. Lines starting
+/// with "#" are comments. Operations that take --threads get the whole budget unless they set it;
+/// the budget may be "auto" to divide the available CPUs between each operation's thread pools.
+/// Operations that make random choices use the seed ("seed: 7") unless they set their own.
+/// Stops at the first operation that fails.
+#[derive(Parser, Debug)]
+#[command(version, verbatim_doc_comment)]
+pub(crate) struct Batch {
+    /// Batch file listing the operations to run.
+    batch_file: PathBuf,
+
+    /// Number of threads shared by the operations, or "auto". Overrides "threads" in the batch
+    /// file. Without either, each operation uses its own default, dividing the available CPUs
+    /// between its thread pools.
+    #[clap(long, short = 't', required = false, default_value = None)]
+    threads: Option<Threads>,
+
+    /// Seed for operations that make random choices, unless they set their own. Overrides "seed"
+    /// in the batch file.
+    #[clap(long, short = 's', required = false, default_value = None)]
+    seed: Option<u64>,
+}
+
+/// Operations parsed from a batch file.
+#[derive(Debug, PartialEq)]
+struct BatchOperations {
+    /// Thread budget from the batch file, if set
+    threads: Option<Threads>,
+    /// Seed from the batch file, if set
+    seed: Option<u64>,
+    /// 1-based line number and command line arguments (without the program name) of each
+    /// operation
+    operations: Vec<(usize, Vec<String>)>,
+}
+
+/// Split a command line into arguments on whitespace, keeping single- or double-quoted text
+/// together. An unquoted "#" at the start of an argument starts a comment.
+fn split_command_line(line: &str) -> Result<Vec<String>> {
+    let mut arguments = Vec::new();
+    let mut argument: Option<String> = None;
+    let mut quote: Option<char> = None;
+    for character in line.chars() {
+        match (quote, character) {
+            (Some(open), character) if character == open => quote = None,
+            (Some(_), character) => argument.get_or_insert_default().push(character),
+            (None, '\'' | '"') => {
+                quote = Some(character);
+                argument.get_or_insert_default();
+            }
+            (None, '#') if argument.is_none() => break,
+            (None, character) if character.is_whitespace() => {
+                arguments.extend(argument.take());
+            }
+            (None, character) => argument.get_or_insert_default().push(character),
+        }
+    }
+    if let Some(open) = quote {
+        return Err(anyhow!("Unterminated {open} quote in: {line}"));
+    }
+    arguments.extend(argument);
+    Ok(arguments)
+}
+
+impl BatchOperations {
+    /// Parse the text of a batch file.
+    fn parse(text: &str) -> Result<Self> {
+        let mut threads = None;
+        let mut seed = None;
+        let mut operations = Vec::new();
+        let mut in_operations = false;
+        for (line_index, line) in text.lines().enumerate() {
+            let line_number = line_index + 1;
+            let trimmed = line.trim();
+            if trimmed.is_empty() || trimmed.starts_with('#') {
+                continue;
+            }
+            if let Some(item) = trimmed.strip_prefix("- ").filter(|_| in_operations) {
+                // a whole item may be one quoted YAML string
+                let item = item.trim();
+                let arguments = match split_command_line(item)?.as_slice() {
+                    [single] if item.starts_with(['\'', '"']) => split_command_line(single)?,
+                    arguments => arguments.to_vec(),
+                };
+                if arguments.is_empty() {
+                    return Err(anyhow!("Empty operation on line {line_number}."));
+                }
+                operations.push((line_number, arguments));
+                continue;
+            }
+            let Some((key, value)) = trimmed.split_once(':') else {
+                return Err(anyhow!("Cannot parse line {line_number}: {line}"));
+            };
+            let value = split_command_line(value)?;
+            match (key.trim(), value.as_slice()) {
+                ("threads", [value]) => {
+                    threads = Some(value.parse().map_err(|_| {
+                        anyhow!(
+                            "threads on line {line_number} must be a positive integer or \"auto\"."
+                        )
+                    })?);
+                    in_operations = false;
+                }
+                ("seed", [value]) => {
+                    seed = Some(value.parse().map_err(|_| {
+                        anyhow!("seed on line {line_number} must be a non-negative integer.")
+                    })?);
+                    in_operations = false;
+                }
+                ("operations", []) => in_operations = true,
+                _ => return Err(anyhow!("Cannot parse line {line_number}: {line}")),
+            }
+        }
+        Ok(BatchOperations {
+            threads,
+            seed,
+            operations,
+        })
+    }
+}
+
+impl Batch {
+    /// Parse the arguments of one operation into a subcommand, giving it the thread budget (if
+    /// any) if it takes --threads and doesn't set it.
+    fn parse_operation(
+        line_number: usize,
+        arguments: &[String],
+        threads: Option<Threads>,
+    ) -> Result<Subcommand> {
+        let name = arguments[0].as_str();
+        if name == "batch" {
+            return Err(anyhow!(
+                "Operation on line {line_number} is a nested batch, which is not allowed."
+            ));
+        }
+        let takes_threads = Args::command()
+            .find_subcommand(name)
+            .is_some_and(|subcommand| {
+                subcommand
+                    .get_arguments()
+                    .any(|argument| argument.get_id() == "threads")
+            });
+        let sets_threads = arguments
+            .iter()
+            .any(|argument| argument.starts_with("--threads") || argument.starts_with("-t"));
+        let budget = threads
+            .filter(|_| takes_threads && !sets_threads)
+            .map(|threads| ["--threads".to_string(), threads.to_string()])
+            .into_iter()
+            .flatten();
+        let args = Args::try_parse_from(
+            once("split-reads".to_string())
+                .chain(arguments.iter().cloned())
+                .chain(budget),
+        )
+        .map_err(|err| anyhow!("Invalid operation on line {line_number}: {err}"))?;
+        Ok(args.subcommand)
+    }
+}
+
+/// Implement the Command trait for `Batch` struct.
+impl Command for Batch {
+    /// Parse every operation first, so that mistakes are found before anything runs, then run
+    /// them in order.
+    fn execute(&self) -> Result<()> {
+        let batch_operations = BatchOperations::parse(&fs::read_to_string(&self.batch_file)?)?;
+        let threads = self.threads.or(batch_operations.threads);
+        let seed = self.seed.or(batch_operations.seed);
+        let subcommands = batch_operations
+            .operations
+            .iter()
+            .map(|(line_number, arguments)| {
+                let mut subcommand = Self::parse_operation(*line_number, arguments, threads)?;
+                if let Some(seed) = seed {
+                    subcommand.default_seed(seed);
+                }
+                Ok((*line_number, subcommand))
+            })
+            .collect::<Result<Vec<(usize, Subcommand)>>>()?;
+        let num_operations = subcommands.len();
+        for (operation_index, (line_number, subcommand)) in subcommands.into_iter().enumerate() {
+            info!(
+                "Running operation {} of {num_operations} (line {line_number}).",
+                operation_index + 1
+            );
+            subcommand.execute().map_err(|err| {
+                anyhow!("Operation on line {line_number} of the batch file failed: {err}")
+            })?;
+        }
+        match threads {
+            Some(threads) => info!("Ran {num_operations} operation(s) with {threads} thread(s)."),
+            None => info!("Ran {num_operations} operation(s)."),
+        }
+        Ok(())
+    }
+
+    fn default_seed(&mut self, seed: u64) {
+        self.seed.get_or_insert(seed);
+    }
+}
+
+#[cfg(all(test, feature = "synthetic"))]
+mod tests {
+    use super::{Batch, BatchOperations, split_command_line};
+    use crate::{
+        commands::command::Command,
+        test_utils::random_bam::{QueryType, RandomBam},
+    };
+    use anyhow::Result;
+    use clap::Parser;
+    use rust_htslib::bam::{Read as BamRead, Reader as BamReader};
+    use split_reads::thread_budget::Threads;
+    use std::{fs, num::NonZero};
+    use tempfile::TempDir;
+
+    /// Test parsing batch files: quoting, comments, and errors.
+    #[test]
+    fn test_parse_batch_file() -> Result<()> {
+        assert_eq!(
+            split_command_line(r#"get-chunk -i "my reads.bam" -o 'a b'.bam # comment"#)?,
+            ["get-chunk", "-i", "my reads.bam", "-o", "a b.bam"]
+        );
+        assert!(split_command_line("index -i 'unterminated").is_err());
+
+        let batch_operations = BatchOperations::parse(
+            "# per-sample workflow\nthreads: 4\noperations:\n  - index -i a.bam\n\n  - \"tell -I a.bam.si\"\n",
+        )?;
+        assert_eq!(
+            batch_operations,
+            BatchOperations {
+                threads: Some(Threads::Count(NonZero::new(4).unwrap())),
+                seed: None,
+                operations: vec![
+                    (4, vec!["index".into(), "-i".into(), "a.bam".into()]),
+                    (6, vec!["tell".into(), "-I".into(), "a.bam.si".into()]),
+                ],
+            }
+        );
+        assert_eq!(
+            BatchOperations::parse("threads: auto\n")?.threads,
+            Some(Threads::Auto)
+        );
+        assert_eq!(BatchOperations::parse("seed: 7\n")?.seed, Some(7));
+        assert!(BatchOperations::parse("threads: 0\n").is_err());
+        assert!(BatchOperations::parse("seed: -1\n").is_err());
+        assert!(BatchOperations::parse("- index -i a.bam\n").is_err());
+        Ok(())
+    }
+
+    /// Test that a batch indexes and chunks a BAM in order, and rejects invalid operations before
+    /// running any.
+    #[test]
+    fn test_batch() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let temp_path = temp_dir.path();
+        let (random_bam, num_reads) = QueryType::Paired.random_bam(&temp_path, 1000)?;
+        let chunk = |chunk_index: usize| temp_path.join(format!("chunk{chunk_index}.bam"));
+        let batch_file = temp_path.join("ops.yaml");
+        fs::write(
+            &batch_file,
+            format!(
+                "threads: 2\noperations:\n  - index -i {0:?}\n  - get-chunk -i {0:?} -c 0 -n 2 -o {1:?}\n  - get-chunk -i {0:?} -c 1 -n 2 -o {2:?}\n",
+                random_bam,
+                chunk(0),
+                chunk(1)
+            ),
+        )?;
+        Batch::try_parse_from(["batch", batch_file.to_str().unwrap()])?.execute()?;
+        let num_chunk_reads = (0..2)
+            .map(|chunk_index| Ok(BamReader::from_path(chunk(chunk_index))?.records().count()))
+            .collect::<Result<Vec<usize>>>()?;
+        assert_eq!(num_chunk_reads.iter().sum::<usize>(), num_reads);
+
+        let bad_file = temp_path.join("bad.yaml");
+        let never_written = temp_path.join("never.bam");
+        fs::write(
+            &bad_file,
+            format!(
+                "operations:\n  - get-chunk -i {random_bam:?} -c 0 -n 1 -o {never_written:?}\n  - get-chunk --no-such-flag\n"
+            ),
+        )?;
+        assert!(
+            Batch::try_parse_from(["batch", bad_file.to_str().unwrap()])?
+                .execute()
+                .is_err()
+        );
+        assert!(!never_written.exists());
+        fs::write(&bad_file, "operations:\n  - batch ops.yaml\n")?;
+        assert!(
+            Batch::try_parse_from(["batch", bad_file.to_str().unwrap()])?
+                .execute()
+                .is_err()
+        );
+        Ok(())
+    }
+}
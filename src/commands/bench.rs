@@ -0,0 +1,396 @@
+use crate::commands::{command::Command, get_chunk::GetChunk, index::Index};
+use anyhow::{Result, anyhow};
+use clap::Parser;
+use log::info;
+use split_reads::{
+    rng::RandomFeature,
+    synthetic::{DEFAULT_READ_LENGTH, QueryType, bam_to_fastq},
+};
+use std::{
+    env, fs, io,
+    num::NonZero,
+    path::{Path, PathBuf},
+    process,
+    time::Instant,
+};
+
+/// Input format to benchmark
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BenchFormat {
+    Bam,
+    Fastq,
+}
+
+impl BenchFormat {
+    /// Extension of synthetic input files in this format
+    fn extension(self) -> &'static str {
+        match self {
+            BenchFormat::Bam => "bam",
+            BenchFormat::Fastq => "fastq.gz",
+        }
+    }
+}
+
+/// Step of the pipeline that was timed
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum BenchStep {
+    Index,
+    GetChunk,
+}
+
+impl BenchStep {
+    fn label(self) -> &'static str {
+        match self {
+            BenchStep::Index => "index",
+            BenchStep::GetChunk => "get-chunk",
+        }
+    }
+}
+
+/// Throughput of one timed step
+#[derive(Clone, Copy, Debug)]
+struct BenchResult {
+    step: BenchStep,
+    format: BenchFormat,
+    threads: NonZero<usize>,
+    num_bins: NonZero<usize>,
+    seconds: f64,
+    num_reads: usize,
+    /// Size of the input file
+    num_bytes: u64,
+}
+
+impl BenchResult {
+    /// Header of the table printed by bench
+    const TSV_HEADER: &str = "step\tformat\tthreads\tnum_bins\tseconds\treads_per_sec\tmb_per_sec";
+
+    fn reads_per_sec(&self) -> f64 {
+        self.num_reads as f64 / self.seconds
+    }
+
+    fn mb_per_sec(&self) -> f64 {
+        self.num_bytes as f64 / 1e6 / self.seconds
+    }
+
+    fn to_tsv(self) -> String {
+        format!(
+            "{}\t{:?}\t{}\t{}\t{:.3}\t{:.0}\t{:.1}",
+            self.step.label(),
+            self.format,
+            self.threads,
+            self.num_bins,
+            self.seconds,
+            self.reads_per_sec(),
+            self.mb_per_sec()
+        )
+        .to_lowercase()
+    }
+}
+
+/// Measure indexing and chunk extraction throughput on this machine, to choose --threads and
+/// --num-bins. Generates a synthetic BAM (and the same reads as BGZF-compressed FASTQ), then for
+/// each thread count and number of bins, times indexing it and extracting every chunk. Prints a
+/// table of seconds, reads per second, and MB of input per second for each step.
+#[derive(Parser, Debug)]
+#[command(version, verbatim_doc_comment)]
+pub(crate) struct Bench {
+    /// Number of query groups in the synthetic input.
+    #[clap(long, short = 'q', required = false, default_value_t = 100000)]
+    num_queries: usize,
+
+    /// Kind of query groups in the synthetic input.
+    #[clap(long, required = false, default_value = "paired", value_enum)]
+    query_type: QueryType,
+
+    /// Length of every synthetic read.
+    #[clap(long, required = false, default_value_t = DEFAULT_READ_LENGTH)]
+    read_length: usize,
+
+    /// Input formats to benchmark.
+    #[clap(
+        long,
+        short = 'f',
+        required = false,
+        default_value = "bam,fastq",
+        value_enum,
+        value_delimiter = ','
+    )]
+    formats: Vec<BenchFormat>,
+
+    /// Thread counts to benchmark.
+    #[clap(
+        long,
+        short = 't',
+        required = false,
+        default_value = "1,2,4",
+        value_delimiter = ','
+    )]
+    threads: Vec<NonZero<usize>>,
+
+    /// Numbers of index bins to benchmark.
+    #[clap(
+        long,
+        short = 'b',
+        required = false,
+        default_value = "1000,10000",
+        value_delimiter = ','
+    )]
+    num_bins: Vec<NonZero<usize>>,
+
+    /// Number of chunks to extract, one at a time, after each indexing run.
+    #[clap(long, short = 'n', required = false, default_value_t = NonZero::new(10usize).unwrap())]
+    num_chunks: NonZero<usize>,
+
+    /// Directory to create the work directory for the synthetic inputs, indices and chunks in.
+    /// Defaults to the system temporary directory. Only the work directory is removed afterwards,
+    /// never anything else in this directory.
+    #[clap(long, short = 'd', required = false, default_value = None)]
+    work_dir: Option<PathBuf>,
+
+    /// Keep the work directory afterwards, instead of removing it.
+    #[clap(long, required = false, default_value_t = false)]
+    keep: bool,
+
+    /// Seed for generating the synthetic input. Defaults to the global --seed, or 0.
+    #[clap(long, short = 's', required = false, default_value = None)]
+    seed: Option<u64>,
+}
+
+impl Bench {
+    /// Get the work directory to use: a directory of this process in --work-dir
+    fn work_dir(&self) -> PathBuf {
+        self.work_dir
+            .clone()
+            .unwrap_or_else(env::temp_dir)
+            .join(format!("split-reads-bench-{}", process::id()))
+    }
+
+    /// Generate the synthetic input in each format. Returns the path, number of reads, and size
+    /// of each input.
+    fn generate_inputs(&self, work_dir: &Path) -> Result<Vec<(BenchFormat, PathBuf, usize, u64)>> {
+        let bam = work_dir.join(self.query_type.file_name(self.num_queries));
+        info!("Generating {:?}", bam);
+        let num_reads = self.query_type.write_bam(
+            &bam,
+            self.num_queries,
+            self.read_length,
+            RandomFeature::TestData.seed(self.seed),
+        )?;
+        self.formats
+            .iter()
+            .map(|&format| {
+                let input = bam.with_extension(format.extension());
+                if format == BenchFormat::Fastq {
+                    bam_to_fastq(&bam, &input)?;
+                }
+                Ok((
+                    format,
+                    input.clone(),
+                    num_reads,
+                    fs::metadata(&input)?.len(),
+                ))
+            })
+            .collect()
+    }
+
+    /// Time one step, and return its duration in seconds.
+    fn time<F>(step: F) -> Result<f64>
+    where
+        F: FnOnce() -> Result<()>,
+    {
+        let start = Instant::now();
+        step()?;
+        Ok(start.elapsed().as_secs_f64())
+    }
+
+    /// Time indexing the input, then extracting every chunk, with each combination of thread
+    /// count and number of bins.
+    fn run(&self, work_dir: &Path) -> Result<Vec<BenchResult>> {
+        if self.num_queries == 0 {
+            return Err(anyhow!(
+                "Cannot benchmark an empty input (--num-queries 0)."
+            ));
+        }
+        let mut results = Vec::new();
+        for (format, input, num_reads, num_bytes) in self.generate_inputs(work_dir)? {
+            let input_arg = input.to_string_lossy().to_string();
+            let index_arg = format!("{input_arg}.si");
+            let chunk_args: Vec<String> = (0..self.num_chunks.get())
+                .map(|chunk_index| {
+                    work_dir
+                        .join(format!("chunk{chunk_index}.{}", format.extension()))
+                        .to_string_lossy()
+                        .to_string()
+                })
+                .collect();
+            for &threads in &self.threads {
+                for &num_bins in &self.num_bins {
+                    let (threads_arg, num_bins_arg) = (threads.to_string(), num_bins.to_string());
+                    // outputs of the previous run are replaced, as existing outputs are refused
+                    for output in chunk_args.iter().chain([&index_arg]) {
+                        remove_if_exists(output)?;
+                    }
+                    let index = Index::try_parse_from([
+                        "index",
+                        "-i",
+                        &input_arg,
+                        "-I",
+                        &index_arg,
+                        "-n",
+                        &num_bins_arg,
+                        "-t",
+                        &threads_arg,
+                    ])?;
+                    let mut result = BenchResult {
+                        step: BenchStep::Index,
+                        format,
+                        threads,
+                        num_bins,
+                        seconds: Self::time(|| index.execute())?,
+                        num_reads,
+                        num_bytes,
+                    };
+                    results.push(result);
+                    let num_chunks_arg = self.num_chunks.to_string();
+                    let get_chunks = chunk_args
+                        .iter()
+                        .enumerate()
+                        .map(|(chunk_index, chunk_arg)| {
+                            GetChunk::try_parse_from([
+                                "get-chunk",
+                                "-i",
+                                &input_arg,
+                                "-I",
+                                &index_arg,
+                                "-c",
+                                &chunk_index.to_string(),
+                                "-n",
+                                &num_chunks_arg,
+                                "-o",
+                                chunk_arg,
+                                "-t",
+                                &threads_arg,
+                            ])
+                        })
+                        .collect::<Result<Vec<GetChunk>, _>>()?;
+                    result = BenchResult {
+                        step: BenchStep::GetChunk,
+                        seconds: Self::time(|| {
+                            get_chunks
+                                .iter()
+                                .try_for_each(|get_chunk| get_chunk.execute())
+                        })?,
+                        ..result
+                    };
+                    results.push(result);
+                }
+            }
+        }
+        Ok(results)
+    }
+}
+
+/// Remove the file at path, if there is one.
+fn remove_if_exists(path: &str) -> Result<()> {
+    match fs::remove_file(path) {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => {
+            Err(anyhow!("Removing {path:?}: {err}"))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Implement the Command trait for `Bench` struct.
+impl Command for Bench {
+    /// Execute the bench command to print a table of throughputs.
+    fn execute(&self) -> Result<()> {
+        let work_dir = self.work_dir();
+        if let Some(parent) = work_dir.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        // a new directory, so that removing it can't remove anything bench didn't write
+        fs::create_dir(&work_dir)
+            .map_err(|err| anyhow!("Creating work directory {work_dir:?}: {err}"))?;
+        let results = self.run(&work_dir);
+        if self.keep {
+            info!("Keeping work directory {:?}", work_dir);
+        } else {
+            fs::remove_dir_all(&work_dir)?;
+        }
+        println!("{}", BenchResult::TSV_HEADER);
+        for result in results? {
+            println!("{}", result.to_tsv());
+        }
+        Ok(())
+    }
+
+    fn default_seed(&mut self, seed: u64) {
+        self.seed.get_or_insert(seed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Bench, BenchFormat, BenchStep};
+    use anyhow::Result;
+    use clap::Parser;
+    use tempfile::TempDir;
+
+    /// Test that every format, thread count, and number of bins gets an index and a get-chunk
+    /// result covering every read, and that the work directory bench creates is removed unless
+    /// kept, leaving the rest of --work-dir alone.
+    #[test]
+    fn test_bench() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let work_dir = temp_dir.path().join("work");
+        let bench = Bench::try_parse_from([
+            "bench",
+            "--num-queries",
+            "200",
+            "--threads",
+            "1,2",
+            "--num-bins",
+            "20",
+            "--num-chunks",
+            "3",
+            "--seed",
+            "7",
+        ])?;
+        std::fs::create_dir_all(&work_dir)?;
+        let results = bench.run(&work_dir)?;
+        assert_eq!(results.len(), 2 * 2 * 2);
+        for (result, format) in results
+            .iter()
+            .zip([BenchFormat::Bam; 4].iter().chain(&[BenchFormat::Fastq; 4]))
+        {
+            assert_eq!(result.format, *format);
+            assert_eq!(result.num_reads, 400);
+            assert!(result.seconds > 0.0);
+            assert!(result.to_tsv().starts_with(result.step.label()));
+        }
+        assert_eq!(results[1].step, BenchStep::GetChunk);
+
+        let bench = Bench::try_parse_from([
+            "bench",
+            "--num-queries",
+            "10",
+            "--formats",
+            "fastq",
+            "--threads",
+            "1",
+            "--num-bins",
+            "5",
+            "--num-chunks",
+            "2",
+            "--work-dir",
+            work_dir.to_str().unwrap(),
+        ])?;
+        let unrelated = work_dir.join("unrelated.txt");
+        std::fs::write(&unrelated, "not bench's")?;
+        crate::commands::command::Command::execute(&bench)?;
+        assert!(unrelated.exists());
+        assert!(!bench.work_dir().exists());
+        assert!(bench.work_dir().starts_with(&work_dir));
+        Ok(())
+    }
+}
@@ -0,0 +1,272 @@
+use crate::commands::{command::Command, get_chunk::GetChunk};
+use anyhow::{Result, anyhow};
+use clap::Parser;
+use log::info;
+use split_reads::{
+    file_fingerprint::FileFingerprint,
+    maybe_compressed_io::{BGZF_EOF, open_file},
+    memory_budget::MemoryBudget,
+    path_type::PathType,
+    resync::{bgzf_blocks, is_bgzf},
+    split_index::{SPLIT_INDEX_EXTENSION, SplitIndex},
+    util::RecordType,
+};
+use std::{
+    fs::{File, create_dir_all},
+    io::{self, BufWriter, Read, Write},
+    path::PathBuf,
+};
+
+/// Concatenate BGZF-compressed FASTQs at the block level, without decompressing or compressing
+/// again. With --write-index, also write the split-index of the output, combined from the
+/// inputs' indices without reading any reads, to cheaply build one chunkable dataset from many
+/// deliveries.
+#[derive(Parser, Debug)]
+#[command(version, verbatim_doc_comment)]
+pub(crate) struct Cat {
+    /// BGZF-compressed FASTQs to concatenate, in order.
+    #[clap(required = true, num_args = 1..)]
+    inputs: Vec<PathBuf>,
+
+    /// Output path for the concatenated FASTQ. Use "-" for stdout (without --write-index).
+    #[clap(long, short = 'o', required = true)]
+    output: PathBuf,
+
+    /// Write the split-index of the output (the output path with ".si" appended), combined from
+    /// the index of each input. Query groups must not continue from one input to the next.
+    #[clap(long, required = false, default_value_t = false)]
+    write_index: bool,
+
+    /// Split-index of each input, in the same order as the inputs. Defaults to each input path
+    /// with ".si" appended.
+    #[clap(long, short = 'I', required = false, num_args = 1.., requires = "write_index")]
+    index: Vec<PathBuf>,
+}
+
+/// BGZF input to concatenate.
+struct CatInput {
+    path: PathBuf,
+    /// Number of bytes to copy: the whole file, except empty blocks (EOF markers) at its end
+    num_copied_bytes: u64,
+    /// Number of uncompressed bytes in the file
+    uncompressed_size: u64,
+}
+
+impl CatInput {
+    /// Check that path is a local BGZF FASTQ, and measure it.
+    fn new(path: &PathBuf) -> Result<Self> {
+        if !matches!(PathType::from_path(path)?, PathType::FilePath(_))
+            || RecordType::detect(path)? != Some(RecordType::Fastq)
+            || !is_bgzf(path)?
+        {
+            return Err(anyhow!(
+                "Can only concatenate local BGZF-compressed FASTQs, but got {path:?}."
+            ));
+        }
+        let blocks = bgzf_blocks(path)?;
+        let num_data_blocks = blocks
+            .iter()
+            .rposition(|(_, uncompressed_size)| *uncompressed_size > 0)
+            .map_or(0, |index| index + 1);
+        let num_copied_bytes = match blocks.get(num_data_blocks) {
+            Some((compressed_offset, _)) => *compressed_offset,
+            None => path.metadata()?.len(),
+        };
+        Ok(CatInput {
+            path: path.clone(),
+            num_copied_bytes,
+            uncompressed_size: blocks.iter().map(|(_, size)| size).sum(),
+        })
+    }
+}
+
+impl Cat {
+    /// Get the index of each input, checking that it matches the input.
+    fn load_indices(&self) -> Result<Vec<SplitIndex>> {
+        if !self.index.is_empty() && self.index.len() != self.inputs.len() {
+            return Err(anyhow!(
+                "Got {} indices for {} inputs.",
+                self.index.len(),
+                self.inputs.len()
+            ));
+        }
+        self.inputs
+            .iter()
+            .enumerate()
+            .map(|(input_index, input)| {
+                GetChunk::load_split_index(self.index.get(input_index), input, false)
+            })
+            .collect()
+    }
+
+    /// Open the output for writing, truncating any existing file.
+    fn open_output(&self) -> Result<Box<dyn Write>> {
+        Ok(match PathType::from_path(&self.output)? {
+            PathType::Pipe if !self.write_index => Box::new(open_file("-", true)?),
+            PathType::FilePath(file_path) => {
+                if let Some(parent_dir) = file_path.parent() {
+                    create_dir_all(parent_dir)?;
+                }
+                Box::new(
+                    File::create(&file_path)
+                        .map_err(|err| anyhow!("Creating {file_path:?}: {err}"))?,
+                )
+            }
+            _ => {
+                return Err(anyhow!(
+                    "cat writes to a local file, or to stdout without --write-index."
+                ));
+            }
+        })
+    }
+}
+
+/// Implement the Command trait for `Cat` struct.
+impl Command for Cat {
+    /// Concatenate the inputs, then combine their indices if requested. Inputs and indices are
+    /// all checked before anything is written.
+    fn execute(&self) -> Result<()> {
+        let inputs = self
+            .inputs
+            .iter()
+            .map(CatInput::new)
+            .collect::<Result<Vec<CatInput>>>()?;
+        let split_indices = if self.write_index {
+            self.load_indices()?
+        } else {
+            Vec::new()
+        };
+        let mut writer =
+            BufWriter::with_capacity(MemoryBudget::current().io_buffer(), self.open_output()?);
+        let (mut compressed_start, mut uncompressed_start) = (0u64, 0u64);
+        let mut parts = Vec::with_capacity(split_indices.len());
+        let mut split_indices = split_indices.into_iter();
+        for input in &inputs {
+            let file = File::open(&input.path)
+                .map_err(|err| anyhow!("Opening {:?}: {err}", input.path))?;
+            io::copy(&mut file.take(input.num_copied_bytes), &mut writer)?;
+            if let Some(split_index) = split_indices.next() {
+                parts.push((split_index, compressed_start, uncompressed_start));
+            }
+            compressed_start += input.num_copied_bytes;
+            uncompressed_start += input.uncompressed_size;
+        }
+        writer.write_all(&BGZF_EOF)?;
+        writer.flush()?;
+        drop(writer);
+        info!(
+            "Concatenated {} inputs ({uncompressed_start} uncompressed bytes).",
+            inputs.len()
+        );
+
+        if self.write_index {
+            let mut split_index = SplitIndex::concatenate(parts)?;
+            split_index.set_fingerprint(FileFingerprint::from_path(&self.output)?);
+            let index_path = PathType::from_path(&self.output)?
+                .default_index(SPLIT_INDEX_EXTENSION)?
+                .ok_or_else(|| anyhow!("Cannot form index path for {:?}.", self.output))?;
+            let (num_bins, num_queries) = (split_index.len(), split_index.num_queries());
+            split_index.write(&index_path)?;
+            info!("Wrote index {index_path:?} with {num_bins} bins and {num_queries} queries.");
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Cat;
+    use crate::commands::{command::Command, get_chunk::GetChunk, index::Index};
+    use anyhow::Result;
+    use clap::Parser;
+    use noodles_bgzf::io::Writer as NoodlesBgzfWriter;
+    use split_reads::{maybe_compressed_io::read_decompressed, split_index::SplitIndex};
+    use std::{
+        fs::{self, File},
+        io::Write,
+        path::{Path, PathBuf},
+    };
+    use tempfile::TempDir;
+
+    /// Write a BGZF FASTQ of num_reads reads, several reads per block.
+    fn write_fastq(path: &Path, prefix: &str, num_reads: usize) -> Result<PathBuf> {
+        let mut writer = NoodlesBgzfWriter::new(File::create(path)?);
+        for read in 0..num_reads {
+            writer.write_all(format!("@{prefix}{read}\nACGTACGTAC\n+\nIIIIIIIIII\n").as_bytes())?;
+            if read % 25 == 24 {
+                writer.flush()?;
+            }
+        }
+        writer.finish()?;
+        Ok(path.to_path_buf())
+    }
+
+    /// Test that the concatenation decompresses to the concatenated inputs, and that its combined
+    /// index chunks it the same as indexing it from scratch would.
+    #[test]
+    fn test_cat() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let temp_path = temp_dir.path();
+        let inputs = [
+            write_fastq(&temp_path.join("a.fastq.gz"), "a", 130)?,
+            write_fastq(&temp_path.join("b.fastq.gz"), "b", 70)?,
+        ];
+        for input in &inputs {
+            Index::try_parse_from(["index", "-i", input.to_str().unwrap(), "-t", "1"])?
+                .index_reads()?;
+        }
+        let output = temp_path.join("all.fastq.gz");
+        Cat::try_parse_from([
+            "cat",
+            inputs[0].to_str().unwrap(),
+            inputs[1].to_str().unwrap(),
+            "-o",
+            output.to_str().unwrap(),
+            "--write-index",
+        ])?
+        .execute()?;
+        let expected = inputs
+            .iter()
+            .map(|input| read_decompressed(input.to_str().unwrap()))
+            .collect::<Result<Vec<Vec<u8>>>>()?
+            .concat();
+        assert_eq!(read_decompressed(output.to_str().unwrap())?, expected);
+
+        let split_index = SplitIndex::read(output.with_added_extension("si"))?;
+        assert_eq!(split_index.num_reads(), 200);
+        assert_eq!(split_index.num_queries(), 200);
+        let mut chunks = Vec::new();
+        for chunk_index in 0..4 {
+            let chunk = temp_path.join(format!("chunk{chunk_index}.fastq"));
+            GetChunk::try_parse_from([
+                "get-chunk",
+                "-i",
+                output.to_str().unwrap(),
+                "-o",
+                chunk.to_str().unwrap(),
+                "-t",
+                "1",
+                "-c",
+                &chunk_index.to_string(),
+                "-n",
+                "4",
+            ])?
+            .execute()?;
+            chunks.extend(fs::read(&chunk)?);
+        }
+        assert_eq!(chunks, expected);
+
+        assert!(
+            Cat::try_parse_from([
+                "cat",
+                inputs[0].to_str().unwrap(),
+                "-o",
+                "-",
+                "--write-index",
+            ])?
+            .execute()
+            .is_err()
+        );
+        Ok(())
+    }
+}
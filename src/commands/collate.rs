@@ -0,0 +1,334 @@
+use crate::commands::command::Command;
+use anyhow::{Result, anyhow};
+use clap::{Parser, value_parser};
+use log::info;
+use rust_htslib::bam::{Format, Header, HeaderView, Read};
+use split_reads::{
+    chunkable::{ChunkableRecord, ChunkableRecordReader, ChunkableRecordWriter},
+    fastq::FastqWriter,
+    maybe_compressed_io::MaybeCompressedWriter,
+    output_spec::OutputSpec,
+    qname_hash::{QnameHasher, Xxh3QnameHasher},
+    rng::RandomFeature,
+    sam_writer::SamWriter,
+    sam_writer_spec::SamWriterSpec,
+    thread_budget::{READ_WRITE_POOLS, Threads},
+    util::{RecordType, get_bam_reader, get_fastq_reader, get_fastq_writer},
+};
+use std::{
+    collections::HashMap,
+    env, fs,
+    num::NonZero,
+    path::{Path, PathBuf},
+    process,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Group the reads of a FASTQ or SAM/BAM/CRAM by query name (e.g. a coordinate-sorted BAM), like
+/// `samtools collate`, so the output is ready for `split-reads index`. Reads are spilled to
+/// temporary files in buckets by a hash of their query name, then each bucket is grouped in
+/// memory, so memory use is about the input size divided by the number of buckets.
+#[derive(Parser, Debug)]
+#[command(version, verbatim_doc_comment)]
+pub(crate) struct Collate {
+    /// Input FASTQ or SAM/BAM/CRAM to collate.
+    #[clap(long, short = 'i', required = true)]
+    input: PathBuf,
+
+    /// Reference FASTA (required for CRAMs)
+    #[clap(long, short = 'R', required = false, default_value = None)]
+    ref_fasta: Option<PathBuf>,
+
+    /// Output path for collated reads, in the same record type as the input. Use "-" (or omit)
+    /// for stdout.
+    #[clap(long, short = 'o', required = false, default_value = "-")]
+    output: PathBuf,
+
+    /// Number of temporary bucket files. Increase to use less memory for large inputs.
+    #[clap(long, short = 'b', required = false, default_value_t = NonZero::new(64usize).unwrap())]
+    buckets: NonZero<usize>,
+
+    /// Directory for temporary bucket files. Defaults to the system temporary directory.
+    #[clap(long, short = 'T', required = false, default_value = None)]
+    tmp_dir: Option<PathBuf>,
+
+    /// Compression level for output compressed formats. Default to 0 for writing to stdout .
+    #[clap(long, short = 'C', required = false, value_parser = value_parser!(u32).range(..=9))]
+    compression: Option<u32>,
+
+    /// Number of threads to use for reading or writing. Defaults to "auto", which divides the CPUs
+    /// available to this process between reading and writing.
+    #[clap(long, short = 't', required = false)]
+    threads: Option<Threads>,
+
+    /// Seed salting the hash that assigns query groups to buckets, which determines the order of
+    /// query groups in the output. Defaults to the global --seed. Without either, the hash is
+    /// unsalted, as it was before seeds were added, so the output order doesn't change.
+    #[clap(long, short = 's', required = false, default_value = None)]
+    seed: Option<u64>,
+}
+
+/// Temporary directory for bucket files, removed with everything in it when dropped.
+struct BucketDir {
+    path: PathBuf,
+}
+
+impl BucketDir {
+    /// Create a new uniquely named directory inside parent.
+    fn create<P>(parent: P) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH)?.as_nanos();
+        let path = parent
+            .as_ref()
+            .join(format!("split-reads-collate-{}-{nanos}", process::id()));
+        fs::create_dir(&path)
+            .map_err(|err| anyhow!("Creating temporary directory {path:?}: {err}"))?;
+        Ok(BucketDir { path })
+    }
+
+    /// Get the path of the bucket file with this index
+    fn bucket_path(&self, bucket: usize, extension: &str) -> PathBuf {
+        self.path.join(format!("bucket-{bucket:05}.{extension}"))
+    }
+}
+
+impl Drop for BucketDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+/// Group records by query name through bucket files: spill every record to the bucket chosen by
+/// hashing its query name, then read back one bucket at a time and write its query groups in the
+/// order their first reads appeared. Return the number of reads and query groups written.
+fn collate_records<R, Reader, BucketWriter, BucketReader, Writer>(
+    reader: &mut Reader,
+    hasher: Xxh3QnameHasher,
+    bucket_paths: &[PathBuf],
+    open_bucket_writer: impl Fn(&Path) -> Result<BucketWriter>,
+    open_bucket_reader: impl Fn(&Path) -> Result<BucketReader>,
+    writer: &mut Writer,
+) -> Result<(usize, usize)>
+where
+    R: ChunkableRecord + Clone,
+    Reader: ChunkableRecordReader<R>,
+    BucketWriter: ChunkableRecordWriter<R>,
+    BucketReader: ChunkableRecordReader<R>,
+    Writer: ChunkableRecordWriter<R>,
+{
+    let num_buckets = NonZero::new(bucket_paths.len())
+        .ok_or_else(|| anyhow!("Collating needs at least one bucket."))?;
+    let mut bucket_writers = bucket_paths
+        .iter()
+        .map(|path| open_bucket_writer(path))
+        .collect::<Result<Vec<BucketWriter>>>()?;
+    let mut record = R::new();
+    let mut num_reads = 0usize;
+    while let Some(result) = reader.read_into(&mut record) {
+        result?;
+        bucket_writers[hasher.bucket(record.group_qname(), num_buckets)].write(&record)?;
+        num_reads += 1;
+    }
+    // close bucket files before reading them back
+    drop(bucket_writers);
+    info!("Spilled {num_reads} reads to {num_buckets} buckets.");
+
+    let mut num_queries = 0usize;
+    for path in bucket_paths {
+        let mut groups: Vec<Vec<R>> = Vec::new();
+        let mut group_indices: HashMap<Vec<u8>, usize> = HashMap::new();
+        let mut bucket_reader = open_bucket_reader(path)?;
+        while let Some(result) = bucket_reader.read_into(&mut record) {
+            result?;
+            let group_index = *group_indices
+                .entry(record.group_qname().to_vec())
+                .or_insert_with(|| {
+                    groups.push(Vec::new());
+                    groups.len() - 1
+                });
+            groups[group_index].push(record.clone());
+        }
+        num_queries += groups.len();
+        for group_record in groups.iter().flatten() {
+            writer.write(group_record)?;
+        }
+        drop(bucket_reader);
+        fs::remove_file(path).map_err(|err| anyhow!("Removing bucket {path:?}: {err}"))?;
+    }
+    Ok((num_reads, num_queries))
+}
+
+impl Collate {
+    /// Get the number of threads for each of the reading and writing thread pools.
+    fn threads(&self) -> NonZero<usize> {
+        Threads::per_pool(self.threads, READ_WRITE_POOLS)
+    }
+
+    /// Open the reader, bucket files, and writer for the input record type, then collate.
+    fn collate(&self) -> Result<()> {
+        let input_record_type = RecordType::detect(&self.input)?.ok_or_else(|| {
+            anyhow!("Input type must be FASTQ or SAM/BAM/CRAM. Cannot read from stdin.")
+        })?;
+        if let Some(output_record_type) =
+            OutputSpec::from_path(&self.output)?.map(|output_spec| output_spec.record_type)
+            && output_record_type.is_sam_family() != input_record_type.is_sam_family()
+        {
+            return Err(anyhow!(
+                "Output type ({output_record_type}) must match input type ({input_record_type})."
+            ));
+        }
+        let bucket_dir = BucketDir::create(self.tmp_dir.clone().unwrap_or_else(env::temp_dir))?;
+        let extension = if input_record_type.is_sam_family() {
+            "bam"
+        } else {
+            "fastq"
+        };
+        let bucket_paths: Vec<PathBuf> = (0..self.buckets.get())
+            .map(|bucket| bucket_dir.bucket_path(bucket, extension))
+            .collect();
+        let hasher = self.seed.map_or_else(Xxh3QnameHasher::default, |seed| {
+            Xxh3QnameHasher::new(RandomFeature::CollateBuckets.seed(Some(seed)))
+        });
+        // bucket files are read back once, so favor speed over size, and are scratch files, so
+        // they are written directly rather than atomically
+        let one_thread = NonZero::new(1usize).unwrap();
+        let (num_reads, num_queries) = if input_record_type.is_sam_family() {
+            let mut reader =
+                get_bam_reader(self.input.clone(), self.ref_fasta.clone(), self.threads())?;
+            let header: HeaderView = reader.header().clone();
+            let mut writer = SamWriterSpec::new(self.output.clone())
+                .header_from_reader(&reader)
+                .format_from_path_or_default(input_record_type.into())?
+                .threads(self.threads())
+                .reference_fasta(self.ref_fasta.clone())
+                .compression(self.compression)
+                .get_bam_writer()?;
+            let counts = collate_records(
+                &mut reader,
+                hasher,
+                &bucket_paths,
+                |path| {
+                    SamWriter::new(
+                        path.to_str()
+                            .ok_or_else(|| anyhow!("Bucket path {path:?} is not valid UTF-8."))?,
+                        Format::Bam,
+                        &Header::from_template(&header),
+                        None::<PathBuf>,
+                        &["nthreads=1".to_string(), "level=1".to_string()],
+                    )
+                },
+                |path| get_bam_reader(path, None::<PathBuf>, one_thread),
+                &mut writer,
+            )?;
+            writer.finish()?;
+            counts
+        } else {
+            let mut reader = get_fastq_reader(self.input.clone(), self.threads())?;
+            let mut writer =
+                get_fastq_writer(self.output.clone(), self.compression, self.threads())?;
+            let counts = collate_records(
+                &mut reader,
+                hasher,
+                &bucket_paths,
+                |path| {
+                    let inner = MaybeCompressedWriter::new(path, false, one_thread)?;
+                    Ok(FastqWriter::new(inner))
+                },
+                |path| get_fastq_reader(path, one_thread),
+                &mut writer,
+            )?;
+            writer.finish()?;
+            counts
+        };
+        info!("Collated {num_reads} reads into {num_queries} query groups.");
+        Ok(())
+    }
+}
+
+/// Implement the Command trait for `Collate` struct.
+impl Command for Collate {
+    /// Execute the collate command to group reads by query name.
+    fn execute(&self) -> Result<()> {
+        Threads::warn_if_oversubscribed(self.threads, READ_WRITE_POOLS);
+        info!("Using {} thread(s)", self.threads());
+        self.collate()
+    }
+
+    fn default_seed(&mut self, seed: u64) {
+        self.seed.get_or_insert(seed);
+    }
+}
+
+#[cfg(all(test, feature = "synthetic"))]
+mod tests {
+    use super::Collate;
+    use crate::{
+        commands::{command::Command, index::Index},
+        test_utils::random_bam::{QueryType, RandomBam},
+    };
+    use anyhow::Result;
+    use clap::Parser;
+    use rand::{SeedableRng, rngs::StdRng, seq::SliceRandom};
+    use rstest::rstest;
+    use rust_htslib::bam::{Format, Header, Read, Reader, Record, Writer};
+    use std::{collections::HashMap, fs};
+    use tempfile::TempDir;
+
+    /// Test that collating shuffled reads regroups every query group whole, keeps every read, and
+    /// cleans up its bucket files.
+    #[rstest(query_type => [QueryType::Paired, QueryType::Grouped])]
+    fn test_collate(query_type: QueryType) -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let temp_path = temp_dir.path();
+        let (random_bam, num_reads) = query_type.random_bam(&temp_path, 500)?;
+        let mut reader = Reader::from_path(&random_bam)?;
+        let header = Header::from_template(reader.header());
+        let mut records: Vec<Record> = reader.records().collect::<Result<_, _>>()?;
+        records.shuffle(&mut StdRng::seed_from_u64(7));
+        let shuffled = temp_path.join("shuffled.bam");
+        let mut writer = Writer::from_path(&shuffled, &header, Format::Bam)?;
+        for record in &records {
+            writer.write(record)?;
+        }
+        drop(writer);
+
+        let tmp_dir = temp_path.join("tmp");
+        fs::create_dir(&tmp_dir)?;
+        let collated = temp_path.join("collated.bam");
+        Collate::try_parse_from([
+            "collate",
+            "-i",
+            shuffled.to_str().unwrap(),
+            "-o",
+            collated.to_str().unwrap(),
+            "-b",
+            "4",
+            "-T",
+            tmp_dir.to_str().unwrap(),
+        ])?
+        .execute()?;
+        assert_eq!(fs::read_dir(&tmp_dir)?.count(), 0);
+
+        let qnames: Vec<Vec<u8>> = Reader::from_path(&collated)?
+            .records()
+            .map(|record| record.map(|record| record.qname().to_vec()))
+            .collect::<Result<_, _>>()?;
+        assert_eq!(qnames.len(), num_reads);
+        // every query group is contiguous
+        let mut group_starts: HashMap<&[u8], usize> = HashMap::new();
+        for (index, qname) in qnames.iter().enumerate() {
+            let start = *group_starts.entry(qname).or_insert(index);
+            assert!(qnames[start..index].iter().all(|other| other == qname));
+        }
+        Index::try_parse_from([
+            "index",
+            "-i",
+            collated.to_str().unwrap(),
+            "--assert-query-grouped",
+        ])?
+        .index_reads()?;
+        Ok(())
+    }
+}
@@ -5,4 +5,8 @@ use enum_dispatch::enum_dispatch;
 pub trait Command {
     #[allow(clippy::missing_errors_doc)]
     fn execute(&self) -> Result<()>;
+
+    /// Give the command the seed of the global --seed option, unless it sets its own. Commands
+    /// that make no random choices ignore it.
+    fn default_seed(&mut self, _seed: u64) {}
 }
@@ -1,21 +1,50 @@
-use crate::commands::command::Command;
+use crate::commands::{command::Command, transform_args::TransformArgs};
 use anyhow::{Result, anyhow};
-use clap::{Parser, builder::PossibleValuesParser, value_parser};
+use clap::{Parser, value_parser};
 use log::{info, warn};
+use rust_htslib::bam::{Header, HeaderView, Read as BamRead};
 use split_reads::{
-    chunkable::ChunkableRecordReader,
+    atomic_output::AtomicWriter,
+    block_copy::BgzfBlockCopier,
+    chunk_naming::{ChunkNamer, default_namespace},
+    chunk_stream::ChunkStream,
+    chunkable::{
+        ChunkResult, ChunkableRecord, ChunkableRecordReader, CountingBamWriter,
+        DEFAULT_MAX_INFLIGHT_RECORDS, DEFAULT_MISSING_QUALITY, FastForwardIndex,
+        MissingQualityPolicy, OffsetKind, TranslatingWriter,
+    },
+    fastq::FastqRecord,
+    heartbeat::{Heartbeat, HeartbeatReader, HeartbeatWriter},
+    manifest::{ChecksummedOutput, ChunkChecksum, ManifestEntry},
+    maybe_compressed_io::{BGZF_EOF, Codec, MaybeCompressedWriter, strip_bgzf_eof},
+    output_quota::ByteQuotaWriter,
+    output_spec::OutputSpec,
     path_type::PathType,
-    sam_writer_spec::SamWriterSpec,
-    split_index::{SPLIT_INDEX_EXTENSION, SplitIndex},
-    util::{RecordType, get_bam_reader, get_fastq_reader, get_fastq_writer},
+    progress::{Progress, ProgressReader, ProgressUnit},
+    record_tags::{TaggingReader, chunk_tag},
+    regions::{RegionSet, write_region_reads},
+    resync::is_bgzf,
+    sam_writer_spec::{CramVersion, OutputFormat, SamWriterSpec},
+    split_index::{ChunkBoundaries, SPLIT_INDEX_EXTENSION, SplitIndex},
+    thread_budget::{IoCost, ThreadSplit, Threads},
+    transform::{Rename, Transform, TransformPipeline},
+    translate_options::{PairEnd, ReadGroupStamp, TranslateOptions, parse_tag_name},
+    util::{
+        RecordType, get_bam_reader, get_fastq_reader, get_fastq_writer_for, get_indexed_bam_reader,
+        get_sam_reader,
+    },
+    validation::{ValidatingReader, ValidationLevel},
 };
 use std::{
+    fs::File,
+    io::{self, Write},
     num::NonZero,
     path::{Path, PathBuf},
+    time::Duration,
 };
 
 /// Rapidly extract a chunk from a SAM, BAM, or CRAM that has a split-index (".si") file.
-#[derive(Parser, Debug)]
+#[derive(Parser, Clone, Debug)]
 #[command(version, verbatim_doc_comment)]
 pub(crate) struct GetChunk {
     /// Input SAM/BAM/CRAM to extract from. Cannot read from stdin, because it is not seekable.
@@ -35,187 +64,1202 @@ pub(crate) struct GetChunk {
     #[clap(long, short = 'o', required = false, default_value = "-")]
     output: PathBuf,
 
-    /// Compression level for output compressed formats. Default to 0 for writing to stdout .
+    /// Compression level for output compressed formats. Default to 0 for writing to stdout. Setting
+    /// a level turns off copying whole BGZF blocks of same-format output.
     #[clap(long, short = 'C', required = false, value_parser = value_parser!(u32).range(..=9))]
     compression: Option<u32>,
 
+    /// CRAM version for CRAM output (2.1, 3.0, or 3.1). Defaults to htslib's default, 3.0. 3.1
+    /// compresses better but needs a recent reader.
+    #[clap(long, required = false, default_value = None)]
+    cram_version: Option<CramVersion>,
+
+    /// htslib format option for SAM/BAM/CRAM output, as "key=value" like samtools
+    /// --output-fmt-option, e.g. "lossy_names=1" or "embed_ref=1". May be repeated.
+    #[clap(long, required = false)]
+    output_fmt_option: Vec<String>,
+
     /// Index of chunk to take (0, 1, ..., num_chunks - 1)
-    #[clap(long, short = 'c', required = true)]
-    chunk_index: usize,
-
-    /// Number of chunks in total input file.
-    #[clap(long, short = 'n', required = true)]
-    num_chunks: NonZero<usize>,
-
-    /// Output format type. When specifying file output file names, the extension (.sam, .bam, .cram, or .fastq)
-    /// determines format, so this setting will only have an effect when writing to stdout. If left unspecified,
-    /// use the same format as input.
-    #[clap(long, short = 'O', required = false, default_value = None, value_parser = PossibleValuesParser::new(["sam", "bam", "cram", "fastq"]))]
-    output_format: Option<String>,
-
-    /// Number of threads to use for reading or writing BAM
-    #[clap(long, short = 't', default_value_t = NonZero::new(num_cpus::get()).unwrap_or(NonZero::new(1usize).unwrap()))]
-    threads: NonZero<usize>,
+    #[clap(
+        long,
+        short = 'c',
+        required_unless_present = "query_start",
+        conflicts_with = "query_start"
+    )]
+    chunk_index: Option<usize>,
+
+    /// Number of chunks in total input file. Optional with a BED --region-set, which has one chunk
+    /// per region.
+    #[clap(
+        long,
+        short = 'n',
+        required_unless_present_any = ["query_start", "region_set"],
+        conflicts_with = "query_start"
+    )]
+    num_chunks: Option<NonZero<usize>>,
+
+    /// Instead of a chunk, extract query groups starting at this 0-based query index. Requires
+    /// --query-count.
+    #[clap(long, required = false, default_value = None, requires = "query_count")]
+    query_start: Option<usize>,
+
+    /// Number of query groups to extract starting at --query-start. Ranges past the end of the
+    /// input are truncated.
+    #[clap(long, required = false, default_value = None, requires = "query_start")]
+    query_count: Option<usize>,
+
+    /// Instead of chunks of query groups, make each chunk the reads of a coordinate-sorted BAM/CRAM
+    /// that start in genomic regions: one chunk per region of a BED file, or (for BAM only) "auto"
+    /// to split the references into --num-chunks regions of about equal read counts using the
+    /// BAI/CSI index.
+    /// Reads are fetched through the BAI/CSI/CRAI index, so no split-index is needed. Unplaced
+    /// reads are in no chunk, and mates on different regions land in different chunks (see
+    /// --keep-mates).
+    #[clap(
+        long,
+        required = false,
+        default_value = None,
+        conflicts_with_all = [
+            "query_start", "dry_run", "uncompressed_mirror", "max_output_bytes", "block_align",
+            "tag_chunk"
+        ]
+    )]
+    region_set: Option<RegionSet>,
+
+    /// With --region-set, keep mate pairs together: each pair is in the chunk of its leftmost mate,
+    /// which also fetches the other mate wherever it starts, so paired coordinate-sorted reads can
+    /// be chunked without collating them. Mates are found by the position their mate gives.
+    #[clap(
+        long,
+        required = false,
+        default_value_t = false,
+        requires = "region_set"
+    )]
+    keep_mates: bool,
+
+    /// Output format type. Output paths with a recognized extension (.sam, .bam, .cram, .fastq,
+    /// .fq, optionally followed by .gz for FASTQ) determine the format, so this setting is for
+    /// stdout, named pipes and other extensionless outputs, and is an error if it disagrees with
+    /// the extension. If left unspecified, use the same format as input.
+    #[clap(long, short = 'O', required = false, value_enum)]
+    output_format: Option<OutputFormat>,
+
+    /// Total number of threads for reading and writing, divided between them by how much work each
+    /// does: e.g. compressing BAM output at a high level gets most of them, and writing plain SAM
+    /// or FASTQ just one. Use "auto" or leave unspecified to use all CPUs available to this
+    /// process.
+    #[clap(long, short = 't', required = false)]
+    threads: Option<Threads>,
+
+    /// Number of threads for reading (decompressing) the input, overriding the share of --threads
+    /// it would get. The writer gets the rest, unless --writer-threads is also given.
+    #[clap(long, required = false)]
+    reader_threads: Option<NonZero<usize>>,
+
+    /// Number of threads for writing (compressing) the output, overriding the share of --threads
+    /// it would get. The reader gets the rest, unless --reader-threads is also given.
+    #[clap(long, required = false)]
+    writer_threads: Option<NonZero<usize>>,
+
+    /// Phred score to emit for every base of records with missing qualities ("*" in SAM) when
+    /// translating between FASTQ and SAM/BAM/CRAM.
+    #[clap(long, required = false, default_value_t = DEFAULT_MISSING_QUALITY, value_parser = value_parser!(u8).range(..=93))]
+    missing_quality: u8,
+
+    /// Error on records with missing qualities instead of filling them with --missing-quality.
+    #[clap(long, required = false, default_value_t = false)]
+    strict: bool,
+
+    /// When translating SAM/BAM/CRAM to FASTQ, stamp each read's read group ("RG:Z:<id>") into
+    /// its FASTQ comment.
+    #[clap(long, required = false, default_value_t = false)]
+    stamp_read_group: bool,
+
+    /// When translating SAM/BAM/CRAM to FASTQ, stamp the sample of each read's read group
+    /// ("SM:Z:<sample>", from the @RG header lines) into its FASTQ comment.
+    #[clap(long, required = false, default_value_t = false)]
+    stamp_sample: bool,
+
+    /// When translating, carry these optional fields over, comma-separated (e.g. "RG,BC,RX,OX"):
+    /// SAM/BAM/CRAM tags are written into FASTQ comments as "TG:T:value", and such comments are
+    /// parsed back into tags.
+    #[clap(long, required = false, value_delimiter = ',', value_parser = parse_tag_name)]
+    copy_tags: Vec<[u8; 2]>,
+
+    /// Input is a decompressed copy of the indexed FASTQ (e.g. reads.fastq for an index built on
+    /// reads.fastq.gz), or a SAM copy of a BAM/CRAM indexed with --sam-mirror. Seek using the
+    /// uncompressed offsets stored in the index. The copy cannot be checked against the index
+    /// fingerprint.
+    #[clap(long, required = false, default_value_t = false)]
+    uncompressed_mirror: bool,
+
+    /// Instead of --output, write the chunk to this directory with a structured name, e.g.
+    /// "sample1.chunk-003-of-250.bam". The directory records which input each namespace came
+    /// from, and it is an error to reuse a namespace for a different input.
+    #[clap(long, required = false, default_value = None, conflicts_with = "output")]
+    output_dir: Option<PathBuf>,
+
+    /// Namespace (e.g. sample ID) prefixing chunk names in --output-dir. Defaults to the input
+    /// file name up to the first '.'.
+    #[clap(long, required = false, default_value = None, requires = "output_dir")]
+    namespace: Option<String>,
+
+    /// Validation of SAM/BAM/CRAM records: "strict" errors on the first malformed record,
+    /// "lenient" warns and skips records that htslib rejects as invalid but are complete. Use the
+    /// same validation level the index was built with.
+    #[clap(long, required = false, default_value = "strict")]
+    validation: ValidationLevel,
+
+    /// Append a JSON line describing the written chunk (path, query and read counts, byte size,
+    /// MD5, and xxh3) to this manifest file. Parallel get-chunk calls may share one manifest. Size
+    /// and checksums are null when writing to stdout or a URL. Check copies of the chunk against
+    /// it with verify-chunk.
+    #[clap(long, required = false, default_value = None)]
+    manifest: Option<PathBuf>,
+
+    /// Stop at the first query group boundary after the records written reach this many bytes,
+    /// counted uncompressed (as BAM for SAM, BAM, or CRAM output), e.g. to fit fixed-size upload
+    /// units. The output may overshoot by one query group, and compressed output is smaller. Logs
+    /// the query index to resume from with --query-start.
+    #[clap(long, required = false, default_value = None)]
+    max_output_bytes: Option<NonZero<u64>>,
+
+    /// Leave off the empty end-of-file block of compressed FASTQ output, so that chunks (which
+    /// never share BGZF blocks) can later be concatenated byte-wise, followed by a single
+    /// end-of-file block, into a valid combined file without compressing again. Requires a local
+    /// output file.
+    #[clap(long, required = false, default_value_t = false)]
+    block_align: bool,
+
+    /// Build a BAI index of the output (the output path with ".bai" appended) while writing it,
+    /// rather than indexing it in a second pass. Requires coordinate-sorted SAM/BAM/CRAM input
+    /// and BAM output to a local file.
+    #[clap(long, required = false, default_value_t = false)]
+    write_bai: bool,
+
+    /// Write the MD5 of the output next to it (the output path with ".md5" appended), in the
+    /// format of md5sum, so that copies can be checked with verify-chunk or `md5sum -c`. Requires
+    /// output to a local file.
+    #[clap(long, required = false, default_value_t = false)]
+    write_md5: bool,
+
+    /// Rewrite this small JSON file with the status, read and query counts written, and a
+    /// timestamp at least every --heartbeat-interval seconds while extracting, so external
+    /// watchdogs can detect a stalled job.
+    #[clap(long, required = false, default_value = None)]
+    heartbeat: Option<PathBuf>,
+
+    /// Time in seconds between heartbeats
+    #[clap(long, required = false, default_value_t = 30, requires = "heartbeat")]
+    heartbeat_interval: u64,
+
+    /// Show a progress line on stderr with the reads extracted out of the chunk's approximate
+    /// total, reads per second, and an estimated time remaining.
+    #[clap(long, required = false, default_value_t = false)]
+    progress: bool,
+
+    /// Tag every extracted record with the chunk index, as a "ch:i:<chunk_index>" aux field in
+    /// SAM/BAM/CRAM output or comment field in FASTQ output, so reads can still be traced back to
+    /// the chunk that produced them after merging and downstream processing.
+    #[clap(
+        long,
+        required = false,
+        default_value_t = false,
+        conflicts_with = "query_start"
+    )]
+    tag_chunk: bool,
+
+    /// Warn if the chunk holds a read longer than this. Defaults to 10 times the median over index
+    /// bins of their longest read. Requires an index built with --max-read-lengths.
+    #[clap(long, required = false, default_value = None)]
+    long_read_length: Option<NonZero<usize>>,
+
+    /// Most records read from the input but not yet written to the output. When the output is a
+    /// slow consumer (e.g. a pipe into a stalling aligner), reading pauses at this bound, so memory
+    /// stays flat regardless of consumer speed. Smaller bounds cost some throughput.
+    #[clap(
+        long,
+        required = false,
+        default_value_t = NonZero::new(DEFAULT_MAX_INFLIGHT_RECORDS).unwrap()
+    )]
+    max_inflight_records: NonZero<usize>,
+
+    /// Print the chunk's boundaries as a JSON object (its query range, the offset extraction seeks
+    /// to, and estimated reads and bytes, from the index alone) instead of extracting it. Neither
+    /// the reads nor the output are opened, so chunking parameters can be checked cheaply.
+    #[clap(long, required = false, default_value_t = false)]
+    dry_run: bool,
+
+    /// Filters and transforms of the reads written
+    #[clap(flatten)]
+    transform_args: Box<TransformArgs>,
+
+    /// When streaming the chunk (see stream_chunk), the path the writer writes to in place of
+    /// stdout. Everything else still treats the output as stdout.
+    #[clap(skip)]
+    stream_to: Option<PathBuf>,
 }
 
 impl GetChunk {
-    /// Load the SplitIndex for the original reads file
-    fn load_split_index<P1, P2>(index: Option<P1>, input: P2) -> Result<SplitIndex>
+    /// Divide the threads between reading input_record_type input and writing output_spec at
+    /// compression, by the cost of each.
+    fn thread_split(
+        &self,
+        input_record_type: RecordType,
+        output_spec: OutputSpec,
+        compression: Option<u32>,
+    ) -> ThreadSplit {
+        // only BGZF is decompressed by worker threads; files that can't be opened before reading
+        // (URLs and stdin) are judged by their extension
+        let input_compressed = match PathType::from_path(&self.input) {
+            Ok(PathType::FilePath(_)) => is_bgzf(&self.input).unwrap_or(false),
+            _ => OutputSpec::path_compression(&self.input) == Some(true),
+        };
+        let threads = ThreadSplit::new(
+            self.threads,
+            IoCost::reading(input_record_type, input_compressed),
+            IoCost::writing(
+                output_spec.record_type,
+                output_spec.is_compressed(compression),
+                compression,
+            ),
+            self.reader_threads,
+            self.writer_threads,
+        );
+        threads.warn_if_oversubscribed();
+        info!(
+            "Using {} thread(s) for reading and {} for writing",
+            threads.reader, threads.writer
+        );
+        threads
+    }
+
+    /// Load the SplitIndex for the original reads file, and check that it matches the file. If
+    /// the input is a decompressed copy of the indexed file (or a SAM copy of an indexed BAM/CRAM),
+    /// switch to uncompressed offsets instead.
+    pub(crate) fn load_split_index<P1, P2>(
+        index: Option<P1>,
+        input: P2,
+        uncompressed_mirror: bool,
+    ) -> Result<SplitIndex>
     where
         P1: AsRef<Path>,
         P2: AsRef<Path>,
     {
-        if let Some(path_buf) = index {
-            SplitIndex::read(path_buf)
+        let split_index = if let Some(path_buf) = index {
+            SplitIndex::read(path_buf)?
         } else {
-            let default = PathType::from_path(input)?
+            let default = PathType::from_path(input.as_ref())?
                 .default_index(SPLIT_INDEX_EXTENSION)?
                 .ok_or_else(|| {
                     anyhow!("When reading from stdin, must explicitly specify index path.")
                 })?;
-            SplitIndex::read(default)
+            SplitIndex::read(default)?
+        };
+        if uncompressed_mirror {
+            // A SAM mirror's header may differ from the indexed file's, so find where its records
+            // start instead of assuming they start at the same offset.
+            let records_start = if RecordType::detect(input.as_ref())? == Some(RecordType::Sam) {
+                let mut reader =
+                    get_sam_reader(input.as_ref(), None::<PathBuf>, NonZero::new(1).unwrap())?;
+                if reader.offset_kind() == OffsetKind::Byte {
+                    Some(reader.tell()?)
+                } else {
+                    None
+                }
+            } else {
+                None
+            };
+            split_index.into_uncompressed(records_start)
+        } else {
+            split_index.verify_fingerprint(input)?;
+            Ok(split_index)
+        }
+    }
+
+    /// If more chunks were requested than the index has bins, several chunks share each bin, so
+    /// reaching a chunk means scanning past the reads of earlier chunks in its bin. Return a
+    /// warning reporting the expected scan overhead, or None if the index is dense enough.
+    pub(crate) fn sparse_bins_warning(
+        split_index: &SplitIndex,
+        chunk_index: usize,
+        num_chunks: NonZero<usize>,
+    ) -> Result<Option<String>> {
+        if num_chunks.get() <= split_index.len() {
+            return Ok(None);
+        }
+        let start_num_queries = split_index.get_chunk_query_start(chunk_index, num_chunks)?;
+        let scan_reads = split_index
+            .estimate_scan_reads(start_num_queries)
+            .unwrap_or_default();
+        Ok(Some(format!(
+            "Requested {num_chunks} chunks, but the index only has {} bins, so about {:.1} chunks \
+            share each bin. Chunk {chunk_index} must scan past ~{scan_reads} reads before it \
+            starts (chunks average ~{} reads). Rebuild the index with \
+            `split-reads index --num-bins {num_chunks}` or more to avoid this overhead.",
+            split_index.len(),
+            num_chunks.get() as f64 / split_index.len().max(1) as f64,
+            split_index.num_reads() / num_chunks.get(),
+        )))
+    }
+
+    /// If the index records read lengths, and a bin overlapping the query groups
+    /// start_num_queries..stop_num_queries holds a read longer than long_read_length (or the
+    /// index's outlier threshold), return a warning that the chunk holds an ultra-long read.
+    pub(crate) fn long_read_warning(
+        split_index: &SplitIndex,
+        start_num_queries: usize,
+        stop_num_queries: usize,
+        long_read_length: Option<NonZero<usize>>,
+    ) -> Option<String> {
+        let threshold = long_read_length
+            .map(NonZero::get)
+            .or_else(|| split_index.long_read_threshold())?;
+        let max_read_length = split_index.max_read_length(start_num_queries, stop_num_queries)?;
+        (max_read_length > threshold).then(|| {
+            format!(
+                "Queries {start_num_queries}..{stop_num_queries} include a read of up to \
+                {max_read_length} bases, longer than {threshold}, which may dominate downstream \
+                memory. Plan chunks that isolate it with `split-reads tell --suggest-chunks \
+                --isolate-long-reads`."
+            )
+        })
+    }
+
+    /// Resolve the output: from the output path extensions if recognized, otherwise the
+    /// output_format option if specified, otherwise the input record type. Chunks in --output-dir
+    /// are named for the resolved type, so only use the latter two.
+    fn get_output_spec(&self, input_record_type: RecordType) -> Result<OutputSpec> {
+        let output = if self.output_dir.is_some() {
+            Path::new("-")
+        } else {
+            &self.output
+        };
+        OutputSpec::resolve(output, self.output_format, input_record_type)
+    }
+
+    /// Get the namespace for chunk names in --output-dir: --namespace, or the input file name up to
+    /// the first '.'.
+    fn chunk_namespace(&self) -> Result<String> {
+        match self.namespace {
+            Some(ref namespace) => Ok(namespace.clone()),
+            None => default_namespace(&self.input).ok_or_else(|| {
+                anyhow!("Cannot get a namespace from the input path, specify --namespace.")
+            }),
+        }
+    }
+
+    /// Get the output path: either --output, or a structured chunk name in --output-dir, after
+    /// checking that the namespace does not collide with chunks from a different input.
+    fn output_path(&self, output_spec: OutputSpec) -> Result<PathBuf> {
+        let Some(ref output_dir) = self.output_dir else {
+            return Ok(self.output.clone());
+        };
+        let namespace = self.chunk_namespace()?;
+        let namer = ChunkNamer::new(&namespace)?;
+        namer.register(output_dir, &self.input)?;
+        let extension = output_spec.extension(self.compression);
+        let file_name = match (
+            self.query_start,
+            self.query_count,
+            self.chunk_index,
+            self.num_chunks,
+        ) {
+            (Some(query_start), Some(query_count), _, _) => {
+                namer.query_range_file_name(query_start, query_start + query_count, &extension)
+            }
+            (_, _, Some(chunk_index), Some(num_chunks)) => {
+                namer.chunk_file_name(chunk_index, num_chunks, &extension)
+            }
+            _ => Err(anyhow!("Must specify a chunk or query range."))?,
+        };
+        Ok(output_dir.join(file_name))
+    }
+
+    /// Get the options for translating records to a different record type. Read-group metadata
+    /// can only be stamped when reading SAM/BAM/CRAM, so needs the input header.
+    fn translate_options(&self, header: Option<&HeaderView>) -> TranslateOptions {
+        let missing_quality = if self.strict {
+            MissingQualityPolicy::Strict
+        } else {
+            MissingQualityPolicy::Fill(self.missing_quality)
+        };
+        let mut read_group_stamp = ReadGroupStamp::new();
+        read_group_stamp.read_group(self.stamp_read_group);
+        if self.stamp_sample
+            && let Some(header) = header
+        {
+            read_group_stamp.samples_from_header(header);
+        }
+        // FASTQ records have no flags, so mark pairs by the R1/R2 naming of the input file
+        let pair_end = PairEnd::from_path(&self.input);
+        if header.is_none()
+            && let Some(pair_end) = pair_end
+        {
+            info!("Input file name marks reads without a /1 or /2 suffix as {pair_end:?} of pair.");
+        }
+        TranslateOptions::new()
+            .missing_quality(missing_quality)
+            .read_group_stamp(read_group_stamp)
+            .pair_end(pair_end)
+            .tags(self.record_tags())
+            .name_rewrite(self.transform_args.name_rewrite())
+            .copy_tags(self.copy_tags.clone())
+            .to_owned()
+    }
+
+    /// Get the optional fields to add to every extracted record: the chunk tag, if requested.
+    fn record_tags(&self) -> Vec<Vec<u8>> {
+        match self.chunk_index {
+            Some(chunk_index) if self.tag_chunk => vec![chunk_tag(chunk_index)],
+            _ => Vec::new(),
+        }
+    }
+
+    /// Get the progress of extracting the query groups start_num_queries..stop_num_queries, if
+    /// requested, counting the reads from the start of the first query group's bin.
+    fn progress(
+        &self,
+        split_index: &SplitIndex,
+        start_num_queries: usize,
+        stop_num_queries: usize,
+    ) -> Option<Progress> {
+        if !self.progress {
+            return None;
+        }
+        let total_reads = split_index
+            .get_record_for_num_queries(start_num_queries)
+            .zip(split_index.estimate_num_reads_before(stop_num_queries))
+            .map(|(split_range, stop_num_reads)| {
+                stop_num_reads.saturating_sub(split_range.num_previous_reads) as u64
+            });
+        Some(Progress::new(
+            "Extracting",
+            ProgressUnit::Reads,
+            total_reads,
+        ))
+    }
+
+    /// Get the range of query groups to extract: the 0-based index of the first query and one past
+    /// the last query. Either from the requested query range, or from the chunk arithmetic.
+    fn query_range(&self, split_index: &SplitIndex) -> Result<(usize, usize)> {
+        match (
+            self.query_start,
+            self.query_count,
+            self.chunk_index,
+            self.num_chunks,
+        ) {
+            (Some(query_start), Some(query_count), _, _) => {
+                let num_queries = split_index.num_queries();
+                let query_stop = query_start.saturating_add(query_count);
+                if query_stop > num_queries {
+                    warn!(
+                        "Requested queries {query_start}..{query_stop}, but input only has \
+                        {num_queries} queries. Truncating."
+                    );
+                }
+                Ok((query_start.min(num_queries), query_stop.min(num_queries)))
+            }
+            (_, _, Some(chunk_index), Some(num_chunks)) => Ok((
+                split_index.get_chunk_query_start(chunk_index, num_chunks)?,
+                split_index.get_chunk_query_start(chunk_index + 1, num_chunks)?,
+            )),
+            _ => Err(anyhow!(
+                "Must specify either --chunk-index and --num-chunks, or --query-start and \
+                --query-count."
+            )),
         }
     }
 
-    /// Determine the record type to use for output.
-    ///
-    /// Uses the output path extension if available, falls back to the output_format
-    /// option if specified, otherwise uses the input record type.
-    fn get_output_record_type(&self, input_record_type: &RecordType) -> Result<RecordType> {
-        if let Some(record_type) = RecordType::from_path(self.input.clone()) {
-            Ok(record_type)
-        } else if let Some(ref type_string) = self.output_format {
-            RecordType::from_extension(Some(type_string))
-                .ok_or_else(|| anyhow!("Should be unreachable."))
+    /// Describe the requested chunk or query range for log messages.
+    fn range_description(&self) -> String {
+        match (self.query_start, self.query_count, self.chunk_index) {
+            (Some(query_start), Some(query_count), _) => {
+                format!("Query range {query_start}..{}", query_start + query_count)
+            }
+            (_, _, Some(chunk_index)) => format!("Chunk {chunk_index}"),
+            _ => "Requested range".to_string(),
+        }
+    }
+
+    /// Return true if the chunk can be copied as whole BGZF blocks instead of decoding and encoding
+    /// every record: the input is a local BGZF BAM or FASTQ, the output is the same format, also
+    /// BGZF, and nothing asks for records to be changed, checked, or counted as they are written.
+    fn can_copy_blocks(
+        &self,
+        input_record_type: RecordType,
+        output_record_type: RecordType,
+        output: &Path,
+        split_index: &SplitIndex,
+        query_range: (usize, usize),
+    ) -> Result<bool> {
+        let same_bgzf_format = match (input_record_type, output_record_type) {
+            (RecordType::Bam, RecordType::Bam) => true,
+            (RecordType::Fastq, RecordType::Fastq) => {
+                MaybeCompressedWriter::codec(output, false)? == Codec::Bgzf
+            }
+            _ => false,
+        };
+        Ok(same_bgzf_format
+            && query_range.0 < query_range.1
+            && self.compression.is_none()
+            && self.cram_version.is_none()
+            && self.output_fmt_option.is_empty()
+            && !self.write_bai
+            && !self.tag_chunk
+            && self.max_output_bytes.is_none()
+            && self.transform_args.transforms()?.is_none()
+            && self.transform_args.name_rewrite().is_empty()
+            && self.validation == ValidationLevel::Strict
+            && split_index.skipped_ranges().is_empty()
+            && !split_index.is_approximate()
+            && split_index.offset_kind() != Some(OffsetKind::Byte)
+            && (self.stream_to.is_some()
+                || matches!(PathType::from_path(output)?, PathType::FilePath(_)))
+            && matches!(PathType::from_path(&self.input)?, PathType::FilePath(_))
+            && is_bgzf(&self.input)?)
+    }
+
+    /// Copy the query groups start_num_queries..stop_num_queries to writer_output as whole BGZF
+    /// blocks (see BgzfBlockCopier), locating the first and last record with the reader. The data
+    /// before the first record (the BAM header, or nothing for FASTQ) is copied first.
+    fn copy_blocks<R, Reader>(
+        &self,
+        reader: &mut Reader,
+        split_index: SplitIndex,
+        (start_num_queries, stop_num_queries): (usize, usize),
+        writer_output: &Path,
+    ) -> Result<ChunkResult>
+    where
+        R: ChunkableRecord,
+        Reader: ChunkableRecordReader<R>,
+    {
+        if reader.offset_kind() != OffsetKind::Virtual {
+            return Err(anyhow!(
+                "Can only copy BGZF blocks when seeking by virtual offsets."
+            ));
+        }
+        let header_end = reader.tell()?;
+        let start = reader.locate_query(split_index.clone(), start_num_queries)?;
+        let mut copier = BgzfBlockCopier::open(&self.input, None)?;
+        let (end_offset, end_read_index) = if stop_num_queries < split_index.num_queries() {
+            let end = reader.locate_query(split_index, stop_num_queries)?;
+            (end.offset, end.read_index)
         } else {
-            Ok(input_record_type.clone())
+            (copier.end_offset()?, split_index.num_reads())
+        };
+        let mut output = AtomicWriter::create(writer_output, |path| {
+            File::create(path).map_err(|err| anyhow!("Creating {writer_output:?}: {err}"))
+        })?;
+        copier.copy_range(0, header_end, &mut *output)?;
+        copier.copy_range(start.offset, end_offset, &mut *output)?;
+        output.write_all(&BGZF_EOF)?;
+        output.commit()?;
+        Ok(ChunkResult::Written {
+            reads: end_read_index - start.read_index,
+            queries: stop_num_queries - start_num_queries,
+        })
+    }
+
+    /// Stream the chunk as encoded output bytes (in the format it would be written to stdout),
+    /// extracting it on another thread, so callers that forward chunks elsewhere share the
+    /// record loop of write_chunk. Requires output to stdout.
+    pub(crate) fn stream_chunk(&self) -> Result<ChunkStream<()>> {
+        if !matches!(PathType::from_path(&self.output)?, PathType::Pipe)
+            || self.output_dir.is_some()
+            || self.write_bai
+        {
+            return Err(anyhow!(
+                "Can only stream a chunk that would be written to stdout, without --write-bai."
+            ));
         }
+        let mut get_chunk = self.clone();
+        ChunkStream::spawn(move |path| {
+            get_chunk.stream_to = Some(path.to_path_buf());
+            get_chunk.write_chunk()
+        })
+    }
+
+    /// Get the boundaries of the requested chunk from the index, for --dry-run.
+    fn chunk_boundaries(&self) -> Result<ChunkBoundaries> {
+        let split_index = Self::load_split_index(
+            self.index.clone(),
+            self.input.clone(),
+            self.uncompressed_mirror,
+        )?;
+        let (start_num_queries, stop_num_queries) = self.query_range(&split_index)?;
+        split_index.chunk_boundaries(start_num_queries, stop_num_queries)
     }
 
     /// Skip to the beginning of the requested chunk, then write the chunk to the desired output.
     fn write_chunk(&self) -> Result<()> {
+        if let Some(ref region_set) = self.region_set {
+            return self.write_region_chunk(region_set);
+        }
         // Load SplitIndex
-        let split_index = Self::load_split_index(self.index.clone(), self.input.clone())?;
+        let split_index = Self::load_split_index(
+            self.index.clone(),
+            self.input.clone(),
+            self.uncompressed_mirror,
+        )?;
+        let (start_num_queries, stop_num_queries) = self.query_range(&split_index)?;
+        let skipped_ranges = split_index.skipped_ranges().to_vec();
+        if let (None, Some(chunk_index), Some(num_chunks)) =
+            (self.query_start, self.chunk_index, self.num_chunks)
+            && let Some(warning) = Self::sparse_bins_warning(&split_index, chunk_index, num_chunks)?
+        {
+            warn!("{warning}");
+        }
+        if let Some(warning) = Self::long_read_warning(
+            &split_index,
+            start_num_queries,
+            stop_num_queries,
+            self.long_read_length,
+        ) {
+            warn!("{warning}");
+        }
 
         // get input record type
-        let input_record_type = RecordType::from_path(self.input.clone()).ok_or_else(|| {
+        let input_record_type = RecordType::detect(&self.input)?.ok_or_else(|| {
             anyhow!("Input type must be FASTQ or SAM/BAM/CRAM. Cannot read from stdin.")
         })?;
         // get output record type
-        let output_record_type = self.get_output_record_type(&input_record_type)?;
+        let output_spec = self.get_output_spec(input_record_type)?;
+        let output_record_type = output_spec.record_type;
+        let output = self.output_path(output_spec)?;
+        let max_output_bytes = self.max_output_bytes.map(NonZero::get);
+        if self.write_md5 && !PathType::from_path(&output)?.is_regular_file() {
+            return Err(anyhow!(
+                "--write-md5 requires a local output file, not a pipe."
+            ));
+        }
+        if self.write_bai
+            && (!input_record_type.is_sam_family() || output_record_type != RecordType::Bam)
+        {
+            return Err(anyhow!(
+                "--write-bai requires SAM/BAM/CRAM input and BAM output."
+            ));
+        }
+        if self.block_align
+            && (output_record_type != RecordType::Fastq
+                || !PathType::from_path(&output)?.is_regular_file()
+                || !output_spec.is_bgzf(self.compression))
+        {
+            return Err(anyhow!(
+                "--block-align requires BGZF-compressed FASTQ output to a local file, not a pipe."
+            ));
+        }
+        let mut transforms = self.transform_args.transforms()?;
+        if transforms
+            .as_ref()
+            .is_some_and(|transforms| transforms.uses_flags())
+            && !input_record_type.is_sam_family()
+        {
+            return Err(anyhow!(
+                "Filtering by SAM flags requires SAM/BAM/CRAM input."
+            ));
+        }
+        // translated reads are renamed as they are translated, others by a final transform
+        let name_rewrite = self.transform_args.name_rewrite();
+        name_rewrite.check_output(output_record_type)?;
+        if input_record_type.is_sam_family() == output_record_type.is_sam_family()
+            && !name_rewrite.is_empty()
+        {
+            transforms
+                .get_or_insert_with(TransformPipeline::new)
+                .push(Transform::Rename(Rename::new(name_rewrite)));
+        }
+        if let Some(ref transforms) = transforms {
+            transforms.check_output(output_record_type)?;
+        }
+        let checksummed = self.checksummed_output(&output, self.block_align)?;
+        let (writer_output, compression) =
+            self.writer_output(&output, output_record_type, &checksummed);
+        let mut heartbeat = self
+            .heartbeat
+            .as_ref()
+            .map(|path| Heartbeat::new(path, Duration::from_secs(self.heartbeat_interval)));
+        if let Some(ref mut heartbeat) = heartbeat {
+            heartbeat.beat("running", None, 0, 0);
+        }
 
-        if input_record_type == RecordType::Bam {
+        let threads = self.thread_split(input_record_type, output_spec, compression);
+        let progress = self.progress(&split_index, start_num_queries, stop_num_queries);
+        let copy_blocks = self.can_copy_blocks(
+            input_record_type,
+            output_record_type,
+            &output,
+            &split_index,
+            (start_num_queries, stop_num_queries),
+        )?;
+        let chunk_result = if copy_blocks {
+            info!(
+                "Copying whole BGZF blocks of {input_record_type} input without decoding records."
+            );
+            let query_range = (start_num_queries, stop_num_queries);
+            if input_record_type.is_sam_family() {
+                let mut reader =
+                    get_bam_reader(self.input.clone(), self.ref_fasta.clone(), threads.reader)?;
+                self.copy_blocks(&mut reader, split_index, query_range, &writer_output)?
+            } else {
+                let mut reader = get_fastq_reader(self.input.clone(), threads.reader)?;
+                self.copy_blocks(&mut reader, split_index, query_range, &writer_output)?
+            }
+        } else if input_record_type.is_sam_family() {
             // reading from SAM/BAM/CRAM
-            let mut reader =
-                get_bam_reader(self.input.clone(), self.ref_fasta.clone(), self.threads)?;
-            if output_record_type == RecordType::Bam {
+            let mut reader = ValidatingReader::new(
+                get_sam_reader(self.input.clone(), self.ref_fasta.clone(), threads.reader)?,
+                self.validation,
+            );
+            reader.skip_ranges(&skipped_ranges);
+            if let Some(ref transforms) = transforms {
+                transforms.check_input(Some(reader.get_ref().header()))?;
+            }
+            if output_record_type.is_sam_family() {
                 // Reading from SAM/BAM/CRAM and writing to SAM/BAM/CRAM
-                let default_format = if let Some(ref output_format) = self.output_format {
-                    output_format.clone()
-                } else {
-                    self.input
-                        .clone()
-                        .extension()
-                        .ok_or_else(|| anyhow!("Input has no extension."))?
-                        .to_str()
-                        .ok_or_else(|| anyhow!("Input extension cannot convert to str"))?
-                        .to_ascii_lowercase()
-                };
-                let writer_spec = SamWriterSpec::new(self.output.clone())
-                    .header_from_reader(&reader)
-                    .format_from_path_or_default(default_format)?
-                    .threads(self.threads)
+                let writer_spec = SamWriterSpec::new(writer_output.clone())
+                    .header_from_view(reader.get_ref().header())
+                    .format(output_record_type.into())
+                    .threads(threads.writer)
                     .reference_fasta(self.ref_fasta.clone())
-                    .compression(self.compression)
+                    .compression(compression)
+                    .cram_version(self.cram_version)
+                    .format_options(&self.output_fmt_option)
+                    .write_bai(self.write_bai)
+                    .bai_output(Some(output.clone()))
                     .to_owned();
-                let mut writer = writer_spec.get_bam_writer()?;
-                // Write the chunk
-                let mut fast_forward_info =
-                    reader.fast_forward(split_index, self.chunk_index, self.num_chunks)?;
-                if let Some(ref mut actual_fast_forward_info) = fast_forward_info {
-                    actual_fast_forward_info.write_chunk(&mut writer)?;
-                } else {
-                    warn!("Chunk {} is empty.", self.chunk_index)
+                let mut reader = HeartbeatReader::new(
+                    ProgressReader::new(TaggingReader::new(reader, self.record_tags()), progress),
+                    heartbeat.as_ref(),
+                );
+                let mut bam_writer = writer_spec.get_bam_writer()?;
+                let mut counting_writer = CountingBamWriter::counting_as_bam(&mut bam_writer);
+                let mut quota_writer = ByteQuotaWriter::new(&mut counting_writer, max_output_bytes);
+                let mut writer = HeartbeatWriter::new(&mut quota_writer, heartbeat.as_mut());
+                let chunk_result = match reader.fast_forward_to_queries(
+                    split_index,
+                    start_num_queries,
+                    stop_num_queries,
+                )? {
+                    Some(mut fast_forward_info) => fast_forward_info
+                        .max_inflight_records(self.max_inflight_records)
+                        .transform(transforms.as_mut().map(|transforms| transforms as _))
+                        .write_chunk(&mut writer)?,
+                    None => ChunkResult::Empty,
                 };
+                bam_writer.finish()?;
+                chunk_result
             } else {
                 // Reading from SAM/BAM/CRAM and translating to FASTQ
-                let mut writer =
-                    get_fastq_writer(self.output.clone(), self.compression, self.threads)?;
-                // Write the chunk
-                let mut fast_forward_info =
-                    reader.fast_forward(split_index, self.chunk_index, self.num_chunks)?;
-                if let Some(ref mut actual_fast_forward_info) = fast_forward_info {
-                    actual_fast_forward_info.translate_and_write_chunk(&mut writer)?;
-                } else {
-                    warn!("Chunk {} is empty.", self.chunk_index)
+                let header_view = reader.get_ref().header().clone();
+                let mut reader =
+                    HeartbeatReader::new(ProgressReader::new(reader, progress), heartbeat.as_ref());
+                let mut fastq_writer = get_fastq_writer_for(
+                    writer_output.clone(),
+                    &output,
+                    compression,
+                    threads.writer,
+                )?;
+                let mut quota_writer = ByteQuotaWriter::new(&mut fastq_writer, max_output_bytes);
+                let mut writer = HeartbeatWriter::new(&mut quota_writer, heartbeat.as_mut());
+                let chunk_result = match reader.fast_forward_to_queries(
+                    split_index,
+                    start_num_queries,
+                    stop_num_queries,
+                )? {
+                    Some(mut fast_forward_info) => fast_forward_info
+                        .max_inflight_records(self.max_inflight_records)
+                        .transform(transforms.as_mut().map(|transforms| transforms as _))
+                        .translate_and_write_chunk(
+                            &mut writer,
+                            &self.translate_options(Some(&header_view)),
+                        )?,
+                    None => ChunkResult::Empty,
                 };
+                fastq_writer.finish()?;
+                chunk_result
             }
         } else {
             // reading from FASTQ
-            let mut reader = get_fastq_reader(self.input.clone(), self.threads)?;
-            let mut fast_forward_info =
-                reader.fast_forward(split_index, self.chunk_index, self.num_chunks)?;
-
+            let mut reader = ValidatingReader::new(
+                get_fastq_reader(self.input.clone(), threads.reader)?,
+                self.validation,
+            );
+            reader.skip_ranges(&skipped_ranges);
+            if let Some(ref transforms) = transforms {
+                transforms.check_input(None)?;
+            }
             if output_record_type == RecordType::Fastq {
                 // reading from FASTQ and writing to FASTQ
-                let mut writer =
-                    get_fastq_writer(self.output.clone(), self.compression, self.threads)?;
-                // Write the chunk
-                if let Some(ref mut actual_fast_forward_info) = fast_forward_info {
-                    actual_fast_forward_info.write_chunk(&mut writer)?;
-                } else {
-                    warn!("Chunk {} is empty.", self.chunk_index)
+                let mut reader = HeartbeatReader::new(
+                    ProgressReader::new(TaggingReader::new(reader, self.record_tags()), progress),
+                    heartbeat.as_ref(),
+                );
+                let mut fastq_writer = get_fastq_writer_for(
+                    writer_output.clone(),
+                    &output,
+                    compression,
+                    threads.writer,
+                )?;
+                let mut quota_writer = ByteQuotaWriter::new(&mut fastq_writer, max_output_bytes);
+                let mut writer = HeartbeatWriter::new(&mut quota_writer, heartbeat.as_mut());
+                let chunk_result = match reader.fast_forward_to_queries(
+                    split_index,
+                    start_num_queries,
+                    stop_num_queries,
+                )? {
+                    Some(mut fast_forward_info) => fast_forward_info
+                        .max_inflight_records(self.max_inflight_records)
+                        .transform(transforms.as_mut().map(|transforms| transforms as _))
+                        .write_chunk(&mut writer)?,
+                    None => ChunkResult::Empty,
                 };
+                fastq_writer.finish()?;
+                chunk_result
             } else {
                 // Reading from FASTQ and translating to SAM/BAM/CRAM
                 // Should only be able to get here if output_format is specified;
-                let default_format = self
-                    .output_format
-                    .clone()
-                    .ok_or_else(|| anyhow!("Unspecified output format, should be unreachable."))?;
-                // TODO: set minimal header, maybe allow sample ID, set query-group order, or similar?
-                let writer_spec = SamWriterSpec::new(self.output.clone())
-                    .format_from_path_or_default(default_format)?
-                    .threads(self.threads)
+                // TODO: maybe allow sample ID, set query-group order, or similar?
+                // unaligned records, without a reference or @SQ lines
+                let writer_spec = SamWriterSpec::new(writer_output.clone())
+                    .header(Header::new())
+                    .format(output_record_type.into())
+                    .threads(threads.writer)
                     .reference_fasta(self.ref_fasta.clone())
-                    .compression(self.compression)
+                    .compression(compression)
+                    .cram_version(self.cram_version)
+                    .format_options(&self.output_fmt_option)
                     .to_owned();
-                let mut writer = writer_spec.get_bam_writer()?;
-                // Write the chunk
-                if let Some(ref mut actual_fast_forward_info) = fast_forward_info {
-                    actual_fast_forward_info.translate_and_write_chunk(&mut writer)?;
-                } else {
-                    warn!("Chunk {} is empty.", self.chunk_index)
+                let mut reader =
+                    HeartbeatReader::new(ProgressReader::new(reader, progress), heartbeat.as_ref());
+                let mut bam_writer = writer_spec.get_bam_writer()?;
+                let mut counting_writer = CountingBamWriter::counting_as_bam(&mut bam_writer);
+                let mut quota_writer = ByteQuotaWriter::new(&mut counting_writer, max_output_bytes);
+                let mut writer = HeartbeatWriter::new(&mut quota_writer, heartbeat.as_mut());
+                let chunk_result = match reader.fast_forward_to_queries(
+                    split_index,
+                    start_num_queries,
+                    stop_num_queries,
+                )? {
+                    Some(mut fast_forward_info) => fast_forward_info
+                        .max_inflight_records(self.max_inflight_records)
+                        .transform(transforms.as_mut().map(|transforms| transforms as _))
+                        .translate_and_write_chunk(&mut writer, &self.translate_options(None))?,
+                    None => ChunkResult::Empty,
                 };
+                bam_writer.finish()?;
+                chunk_result
+            }
+        };
+        let checksum = checksummed.map(ChecksummedOutput::finish).transpose()?;
+        if self.block_align && checksum.is_none() {
+            strip_bgzf_eof(&output)?;
+        }
+        match chunk_result {
+            ChunkResult::Empty => warn!("{} is empty.", self.range_description()),
+            ChunkResult::Written { reads, queries } => info!(
+                "{}: wrote {reads} reads from {queries} queries.",
+                self.range_description()
+            ),
+        }
+        if let Some(ref transforms) = transforms {
+            for line in transforms.to_string().lines() {
+                info!("{line}");
+            }
+        }
+        let query_stop = match chunk_result {
+            ChunkResult::Empty => stop_num_queries,
+            ChunkResult::Written { queries, .. } => start_num_queries + queries,
+        };
+        if query_stop < stop_num_queries {
+            warn!(
+                "Output reached --max-output-bytes, stopped before query {query_stop} of \
+                {start_num_queries}..{stop_num_queries}. Resume with --query-start {query_stop} \
+                --query-count {}.",
+                stop_num_queries - query_stop
+            );
+        }
+        if let Some(ref mut heartbeat) = heartbeat {
+            heartbeat.beat(
+                "done",
+                heartbeat.input_offset(),
+                chunk_result.num_reads(),
+                chunk_result.num_queries(),
+            );
+        }
+        self.record_output(
+            &output,
+            start_num_queries,
+            query_stop,
+            chunk_result,
+            checksum,
+        )
+    }
+
+    /// Copy output to a local file through a ChecksummedOutput if it needs checksums, for its .md5
+    /// sidecar or its entry in the manifest, so they are computed as it is written. If
+    /// strip_bgzf_eof, the output's final BGZF end-of-file block is left out (for --block-align).
+    fn checksummed_output(
+        &self,
+        output: &Path,
+        strip_bgzf_eof: bool,
+    ) -> Result<Option<ChecksummedOutput>> {
+        if self.stream_to.is_none()
+            && (self.write_md5 || self.manifest.is_some())
+            && PathType::from_path(output)?.is_regular_file()
+        {
+            Ok(Some(ChecksummedOutput::create(output, strip_bgzf_eof)?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Get the path writers open for output, and the compression level to write it with: the
+    /// stream (written like stdout, so BAM/CRAM defaults to no compression), the pipe of a
+    /// ChecksummedOutput, or else the output itself.
+    fn writer_output(
+        &self,
+        output: &Path,
+        output_record_type: RecordType,
+        checksummed: &Option<ChecksummedOutput>,
+    ) -> (PathBuf, Option<u32>) {
+        match (&self.stream_to, checksummed) {
+            (Some(stream_to), _) => (
+                stream_to.clone(),
+                self.compression.or(Some(0)
+                    .filter(|_| matches!(output_record_type, RecordType::Bam | RecordType::Cram))),
+            ),
+            (None, Some(checksummed)) => (checksummed.path().to_path_buf(), self.compression),
+            (None, None) => (output.to_path_buf(), self.compression),
+        }
+    }
+
+    /// Record the written chunk with its checksum (see checksummed_output): write its .md5 sidecar
+    /// and its entry in the manifest, if either was requested.
+    fn record_output(
+        &self,
+        output: &Path,
+        start_num_queries: usize,
+        stop_num_queries: usize,
+        chunk_result: ChunkResult,
+        checksum: Option<ChunkChecksum>,
+    ) -> Result<()> {
+        if self.write_md5
+            && let Some(ref checksum) = checksum
+        {
+            checksum.write_sidecar(output)?;
+        }
+        if let Some(ref manifest) = self.manifest {
+            let mut entry =
+                ManifestEntry::new(output, start_num_queries, stop_num_queries, chunk_result);
+            if self.output_dir.is_some() {
+                entry.namespace(Some(&self.chunk_namespace()?));
+            }
+            if let (None, Some(chunk_index), Some(num_chunks)) =
+                (self.query_start, self.chunk_index, self.num_chunks)
+            {
+                entry.chunk(chunk_index, num_chunks);
             }
+            if let Some(checksum) = checksum {
+                entry.set_checksum(checksum);
+            }
+            entry.append_to(manifest)?;
         }
         Ok(())
     }
+
+    /// Write the chunk of --region-set: the reads starting in its genomic regions, fetched through
+    /// the input's BAI/CSI/CRAI index. Each read counts as its own query group.
+    fn write_region_chunk(&self, region_set: &RegionSet) -> Result<()> {
+        let input_record_type = match RecordType::detect(&self.input)? {
+            Some(record_type @ (RecordType::Bam | RecordType::Cram)) => record_type,
+            _ => return Err(anyhow!("--region-set requires BAM or CRAM input.")),
+        };
+        let chunk_regions = region_set.chunk_regions(&self.input, self.num_chunks)?;
+        let chunk_index = self
+            .chunk_index
+            .ok_or_else(|| anyhow!("--region-set requires --chunk-index."))?;
+        let regions = chunk_regions.get(chunk_index).ok_or_else(|| {
+            anyhow!(
+                "Chunk index {chunk_index} is out of range for {} region chunks.",
+                chunk_regions.len()
+            )
+        })?;
+        // BED region sets may leave the number of chunks implied
+        let get_chunk = GetChunk {
+            num_chunks: NonZero::new(chunk_regions.len()),
+            ..self.clone()
+        };
+        let output_spec = get_chunk.get_output_spec(RecordType::Bam)?;
+        let output_record_type = output_spec.record_type;
+        let output = get_chunk.output_path(output_spec)?;
+        if self.write_md5 && !PathType::from_path(&output)?.is_regular_file() {
+            return Err(anyhow!(
+                "--write-md5 requires a local output file, not a pipe."
+            ));
+        }
+        if self.write_bai && output_record_type != RecordType::Bam {
+            return Err(anyhow!("--write-bai requires BAM output."));
+        }
+        let mut transforms = self.transform_args.transforms()?;
+        let name_rewrite = self.transform_args.name_rewrite();
+        name_rewrite.check_output(output_record_type)?;
+        if output_record_type.is_sam_family() && !name_rewrite.is_empty() {
+            transforms
+                .get_or_insert_with(TransformPipeline::new)
+                .push(Transform::Rename(Rename::new(name_rewrite)));
+        }
+        if let Some(ref transforms) = transforms {
+            transforms.check_output(output_record_type)?;
+        }
+        let checksummed = self.checksummed_output(&output, false)?;
+        let (writer_output, compression) =
+            self.writer_output(&output, output_record_type, &checksummed);
+        let mut heartbeat = self
+            .heartbeat
+            .as_ref()
+            .map(|path| Heartbeat::new(path, Duration::from_secs(self.heartbeat_interval)));
+        if let Some(ref mut heartbeat) = heartbeat {
+            heartbeat.beat("running", None, 0, 0);
+        }
+        info!(
+            "Chunk {chunk_index}: extracting reads starting in {} region(s).",
+            regions.len()
+        );
+
+        let threads = self.thread_split(input_record_type, output_spec, compression);
+        let mut reader =
+            get_indexed_bam_reader(&self.input, self.ref_fasta.as_ref(), threads.reader)?;
+        if let Some(ref transforms) = transforms {
+            transforms.check_input(Some(reader.header()))?;
+        }
+        let transform = transforms.as_mut().map(|transforms| transforms as _);
+        let chunk_result = if output_record_type.is_sam_family() {
+            let mut bam_writer = SamWriterSpec::new(writer_output)
+                .header_from_view(reader.header())
+                .format(output_record_type.into())
+                .threads(threads.writer)
+                .reference_fasta(self.ref_fasta.clone())
+                .compression(compression)
+                .cram_version(self.cram_version)
+                .format_options(&self.output_fmt_option)
+                .write_bai(self.write_bai)
+                .bai_output(Some(output.clone()))
+                .to_owned()
+                .get_bam_writer()?;
+            let mut writer = HeartbeatWriter::new(&mut bam_writer, heartbeat.as_mut());
+            let chunk_result = write_region_reads(
+                &mut reader,
+                regions,
+                &mut writer,
+                transform,
+                self.keep_mates,
+            )?;
+            bam_writer.finish()?;
+            chunk_result
+        } else {
+            let translate_options = self.translate_options(Some(reader.header()));
+            let mut fastq_writer =
+                get_fastq_writer_for(writer_output, &output, compression, threads.writer)?;
+            let mut translating_writer =
+                TranslatingWriter::<FastqRecord, _>::new(&mut fastq_writer, translate_options);
+            let mut writer = HeartbeatWriter::new(&mut translating_writer, heartbeat.as_mut());
+            let chunk_result = write_region_reads(
+                &mut reader,
+                regions,
+                &mut writer,
+                transform,
+                self.keep_mates,
+            )?;
+            fastq_writer.finish()?;
+            chunk_result
+        };
+        match chunk_result {
+            ChunkResult::Empty => warn!("Chunk {chunk_index} is empty."),
+            ChunkResult::Written { reads, .. } => {
+                info!("Chunk {chunk_index}: wrote {reads} reads.")
+            }
+        }
+        if let Some(ref transforms) = transforms {
+            for line in transforms.to_string().lines() {
+                info!("{line}");
+            }
+        }
+        if let Some(ref mut heartbeat) = heartbeat {
+            heartbeat.beat(
+                "done",
+                None,
+                chunk_result.num_reads(),
+                chunk_result.num_queries(),
+            );
+        }
+        let checksum = checksummed.map(ChecksummedOutput::finish).transpose()?;
+        get_chunk.record_output(
+            &output,
+            0,
+            chunk_result.num_queries(),
+            chunk_result,
+            checksum,
+        )
+    }
 }
 
 /// Implement the Command trait for `GetChunk` struct.
 impl Command for GetChunk {
     /// Execute the get-chunk command to extract a specific chunk from the input file.
     fn execute(&self) -> Result<()> {
-        info!("Using {} thread(s)", self.threads);
-        self.write_chunk()
+        if self.dry_run {
+            println!("{}", self.chunk_boundaries()?.to_json());
+            return Ok(());
+        }
+        if matches!(PathType::from_path(&self.output)?, PathType::Pipe) && self.output_dir.is_none()
+        {
+            let mut stream = self.stream_chunk()?;
+            io::copy(&mut stream, &mut io::stdout().lock())?;
+            stream.finish()
+        } else {
+            self.write_chunk()
+        }
     }
 }
 
-#[cfg(test)]
+#[cfg(all(test, feature = "synthetic"))]
 mod tests {
-    use super::{GetChunk, get_bam_reader};
-    use crate::{commands::index::Index, test_utils::random_bam::QueryType};
+    use super::{
+        DEFAULT_MAX_INFLIGHT_RECORDS, DEFAULT_MISSING_QUALITY, GetChunk, OutputFormat, OutputSpec,
+        PathType, RecordType, SPLIT_INDEX_EXTENSION, SplitIndex, ValidationLevel, get_bam_reader,
+    };
+    use crate::{
+        commands::{command::Command, index::Index},
+        test_utils::{
+            http_server::TestHttpServer,
+            random_bam::{QueryType, RandomBam},
+        },
+    };
     use anyhow::Result;
     use clap::Parser;
+    use noodles_bgzf::io::Writer as NoodlesBgzfWriter;
     use rstest::rstest;
     use rust_htslib::{
-        bam::{Header, Read as BamRead, Record as BamRecord},
+        bam::{
+            Format, Header, IndexedReader, Read as BamRead, Record as BamRecord,
+            Writer as BamWriter,
+            header::HeaderRecord,
+            index as bam_index,
+            record::{Aux, Cigar, CigarString},
+        },
         errors::Error as HtslibErr,
     };
+    use split_reads::{
+        manifest::ChunkChecksum,
+        maybe_compressed_io::{BGZF_EOF, read_decompressed},
+        thread_budget::Threads,
+    };
     use std::{
-        collections::HashSet,
+        collections::{HashMap, HashSet},
         fmt::Debug,
+        fs::{self, File},
+        io::{Read, Write},
         iter::zip,
         num::NonZero,
         panic,
@@ -264,11 +1308,7 @@ mod tests {
                     "Qname {previous_qname} is split between chunks"
                 )
             }
-            last_qname = if let Some(qname) = chunk_queries.last() {
-                Some(qname.to_owned())
-            } else {
-                None
-            };
+            last_qname = chunk_queries.last().map(|qname| qname.to_owned());
             chunk_lengths.push(
                 chunk_queries
                     .iter()
@@ -282,9 +1322,9 @@ mod tests {
     }
 
     /// Get query names from chunk
-    fn get_chunk_queries(chunk_records: &Vec<BamRecord>) -> Vec<String> {
+    fn get_chunk_queries(chunk_records: &[BamRecord]) -> Vec<String> {
         chunk_records
-            .into_iter()
+            .iter()
             .map(|rec| String::from_utf8_lossy(rec.qname()).to_string())
             .collect()
     }
@@ -308,11 +1348,42 @@ mod tests {
                 index: Some(index_path.as_ref().to_path_buf()),
                 ref_fasta: None::<PathBuf>,
                 output: output.clone(),
-                output_format: Some("bam".to_string()),
-                threads: NonZero::<usize>::new(1usize).unwrap(),
-                chunk_index: chunk,
-                num_chunks: NonZero::<usize>::new(num_chunks).unwrap(),
+                output_format: Some(OutputFormat::Bam),
+                threads: Some(Threads::Count(NonZero::<usize>::MIN)),
+                reader_threads: None,
+                writer_threads: None,
+                chunk_index: Some(chunk),
+                num_chunks: NonZero::<usize>::new(num_chunks),
+                query_start: None,
+                query_count: None,
+                region_set: None,
+                keep_mates: false,
                 compression: Some(0u32),
+                cram_version: None,
+                output_fmt_option: Vec::new(),
+                missing_quality: DEFAULT_MISSING_QUALITY,
+                strict: false,
+                stamp_read_group: false,
+                stamp_sample: false,
+                copy_tags: Vec::new(),
+                uncompressed_mirror: false,
+                output_dir: None,
+                namespace: None,
+                validation: ValidationLevel::Strict,
+                manifest: None,
+                max_output_bytes: None,
+                block_align: false,
+                write_bai: false,
+                write_md5: false,
+                heartbeat: None,
+                heartbeat_interval: 30,
+                progress: false,
+                tag_chunk: false,
+                long_read_length: None,
+                max_inflight_records: NonZero::new(DEFAULT_MAX_INFLIGHT_RECORDS).unwrap(),
+                dry_run: false,
+                transform_args: Box::default(),
+                stream_to: None,
             };
             command.write_chunk()?;
             chunk_bams.push(output.into_boxed_path().into_path_buf());
@@ -320,7 +1391,7 @@ mod tests {
         Ok(chunk_bams)
     }
 
-    fn assert_records_equal(test_record: &BamRecord, truth_record: &BamRecord) -> () {
+    fn assert_records_equal(test_record: &BamRecord, truth_record: &BamRecord) {
         assert!(
             test_record.qname() == truth_record.qname(),
             "Test qname != truth qname ({:?} != {:?})",
@@ -352,10 +1423,10 @@ mod tests {
         );
     }
 
-    fn assert_vecs_equal<T, F>(test_vec: &Vec<T>, truth_vec: &Vec<T>, check_values_equal: F) -> ()
+    fn assert_vecs_equal<T, F>(test_vec: &Vec<T>, truth_vec: &Vec<T>, check_values_equal: F)
     where
         T: PartialEq + Debug + std::panic::RefUnwindSafe,
-        F: Fn(&T, &T) -> () + std::panic::RefUnwindSafe,
+        F: Fn(&T, &T) + std::panic::RefUnwindSafe,
     {
         let idx: usize = 0;
         for (x1, x2) in zip(test_vec, truth_vec) {
@@ -417,11 +1488,8 @@ mod tests {
     ) -> Result<()> {
         let temp_dir = TempDir::new()?;
         let temp_path: PathBuf = temp_dir.path().to_path_buf();
-        let output_path: Option<PathBuf> = if let Some(output_filename) = output {
-            Some(temp_path.join(output_filename))
-        } else {
-            None
-        };
+        let output_path: Option<PathBuf> =
+            output.map(|output_filename| temp_path.join(output_filename));
         let (random_bam, num_reads) = query_type.random_bam(&temp_path, test_case.num_queries)?;
 
         let num_bins_str = test_case.num_bins.to_string();
@@ -469,4 +1537,1632 @@ mod tests {
         }
         Ok(())
     }
+
+    /// Test that arbitrary query ranges, including one running past the end of the input,
+    /// recapitulate the original BAM.
+    #[rstest(query_type => [QueryType::Single, QueryType::Paired, QueryType::Grouped])]
+    fn test_query_ranges_recapitulate_bam(query_type: QueryType) -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let temp_path: PathBuf = temp_dir.path().to_path_buf();
+        let num_queries = 100usize;
+        let (random_bam, num_reads) = query_type.random_bam(&temp_path, num_queries)?;
+        let index_tool = Index::try_parse_from([
+            "index",
+            "--input",
+            random_bam.to_str().unwrap(),
+            "--num-bins",
+            "7",
+        ])?;
+        let index = index_tool.index_reads()?;
+
+        let query_ranges = [(0usize, 13usize), (13, 1), (14, 50), (64, 1000)];
+        let mut range_bams: Vec<PathBuf> = Vec::with_capacity(query_ranges.len());
+        for (query_start, query_count) in query_ranges {
+            let output = temp_path.join(format!("range_{query_start}.bam"));
+            let query_start = query_start.to_string();
+            let query_count = query_count.to_string();
+            let command = GetChunk::try_parse_from([
+                "get-chunk",
+                "--input",
+                random_bam.to_str().unwrap(),
+                "--index",
+                index.to_str().unwrap(),
+                "--output",
+                output.to_str().unwrap(),
+                "--query-start",
+                query_start.as_str(),
+                "--query-count",
+                query_count.as_str(),
+            ])?;
+            command.write_chunk()?;
+            range_bams.push(output);
+        }
+
+        let (_, truth_records) = load_truth_bam(random_bam)?;
+        let (_, range_records, range_lengths) = load_chunk_bams(range_bams, num_reads)?;
+        assert_vecs_equal(&range_records, &truth_records, assert_records_equal);
+        assert_eq!(range_lengths, vec![13, 1, 50, 36]);
+        Ok(())
+    }
+
+    /// Test that chunks written to an output directory get structured names, and that reusing a
+    /// namespace for a different input is an error.
+    #[test]
+    fn test_output_dir_namespaces() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let temp_path: PathBuf = temp_dir.path().to_path_buf();
+        let output_dir = temp_path.join("chunks");
+        let (single_bam, _) = QueryType::Single.random_bam(&temp_path, 20)?;
+        let (paired_bam, _) = QueryType::Paired.random_bam(&temp_path, 20)?;
+        for bam in [&single_bam, &paired_bam] {
+            Index::try_parse_from(["index", "--input", bam.to_str().unwrap()])?.index_reads()?;
+        }
+        let get_chunk = |bam: &PathBuf, namespace: &str, chunk_index: &str| {
+            GetChunk::try_parse_from([
+                "get-chunk",
+                "--input",
+                bam.to_str().unwrap(),
+                "--output-dir",
+                output_dir.to_str().unwrap(),
+                "--namespace",
+                namespace,
+                "-c",
+                chunk_index,
+                "-n",
+                "12",
+            ])
+            .map_err(anyhow::Error::from)
+            .and_then(|command| command.write_chunk())
+        };
+        get_chunk(&single_bam, "sample1", "3")?;
+        get_chunk(&single_bam, "sample1", "11")?;
+        get_chunk(&paired_bam, "sample2", "3")?;
+        assert!(output_dir.join("sample1.chunk-03-of-12.bam").is_file());
+        assert!(output_dir.join("sample1.chunk-11-of-12.bam").is_file());
+        assert!(output_dir.join("sample2.chunk-03-of-12.bam").is_file());
+        assert!(get_chunk(&paired_bam, "sample1", "4").is_err());
+        assert!(!output_dir.join("sample1.chunk-04-of-12.bam").exists());
+        Ok(())
+    }
+
+    /// Test that requesting more chunks than index bins reports the scan overhead.
+    #[test]
+    fn test_sparse_bins_warning() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let temp_path: PathBuf = temp_dir.path().to_path_buf();
+        let (random_bam, _) = QueryType::Single.random_bam(&temp_path, 100)?;
+        Index::try_parse_from([
+            "index",
+            "--input",
+            random_bam.to_str().unwrap(),
+            "--num-bins",
+            "5",
+        ])?
+        .index_reads()?;
+        let split_index = GetChunk::load_split_index(None::<PathBuf>, random_bam.clone(), false)?;
+        let num_chunks = |n: usize| NonZero::new(n).unwrap();
+        assert!(
+            GetChunk::sparse_bins_warning(&split_index, 1, num_chunks(split_index.len()))?
+                .is_none()
+        );
+        let warning = GetChunk::sparse_bins_warning(&split_index, 1, num_chunks(50))?.unwrap();
+        assert!(warning.contains("--num-bins 50"), "{warning}");
+        Ok(())
+    }
+
+    /// Test that every chunk appends a manifest line, and the read counts add up.
+    #[rstest(query_type => [QueryType::Single, QueryType::Paired, QueryType::Grouped])]
+    fn test_chunk_manifest(query_type: QueryType) -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let temp_path: PathBuf = temp_dir.path().to_path_buf();
+        let output_dir = temp_path.join("chunks");
+        let manifest = temp_path.join("manifest.jsonl");
+        let (random_bam, num_reads) = query_type.random_bam(&temp_path, 50)?;
+        let bam_str = random_bam.to_str().unwrap();
+        Index::try_parse_from(["index", "--input", bam_str])?.index_reads()?;
+        for chunk_index in 0..4 {
+            GetChunk::try_parse_from([
+                "get-chunk",
+                "--input",
+                bam_str,
+                "--output-dir",
+                output_dir.to_str().unwrap(),
+                "--manifest",
+                manifest.to_str().unwrap(),
+                "-c",
+                &chunk_index.to_string(),
+                "-n",
+                "4",
+            ])?
+            .write_chunk()?;
+        }
+        let manifest_text = std::fs::read_to_string(&manifest)?;
+        let lines: Vec<&str> = manifest_text.lines().collect();
+        assert_eq!(lines.len(), 4);
+        let json_field = |line: &str, key: &str| -> String {
+            let start = line.find(&format!("\"{key}\": ")).unwrap() + key.len() + 4;
+            line[start..]
+                .split([',', '}'])
+                .next()
+                .unwrap()
+                .trim_matches('"')
+                .to_string()
+        };
+        let mut manifest_reads = 0usize;
+        for (chunk_index, line) in lines.iter().enumerate() {
+            assert_eq!(json_field(line, "chunk_index"), chunk_index.to_string());
+            assert!(json_field(line, "namespace").starts_with("random-"));
+            let path = PathBuf::from(json_field(line, "path"));
+            assert_eq!(
+                json_field(line, "bytes"),
+                std::fs::metadata(&path)?.len().to_string()
+            );
+            assert_eq!(json_field(line, "md5").len(), 32);
+            manifest_reads += json_field(line, "num_reads").parse::<usize>()?;
+        }
+        assert_eq!(manifest_reads, num_reads);
+        Ok(())
+    }
+
+    /// Test that a streamed chunk has the same bytes as the chunk written to a file, and that only
+    /// stdout output can be streamed.
+    #[test]
+    fn test_stream_chunk() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let temp_path: PathBuf = temp_dir.path().to_path_buf();
+        let (random_bam, _) = QueryType::Paired.random_bam(&temp_path, 500)?;
+        let bam_str = random_bam.to_str().unwrap();
+        Index::try_parse_from(["index", "--input", bam_str])?.index_reads()?;
+        let chunk_args = ["-c", "1", "-n", "3", "-t", "1", "-C", "0"];
+        let chunk_bam = temp_path.join("chunk.bam");
+        GetChunk::try_parse_from(
+            [
+                "get-chunk",
+                "-i",
+                bam_str,
+                "-o",
+                chunk_bam.to_str().unwrap(),
+            ]
+            .into_iter()
+            .chain(chunk_args),
+        )?
+        .write_chunk()?;
+        let get_chunk = GetChunk::try_parse_from(
+            ["get-chunk", "-i", bam_str, "-o", "-"]
+                .into_iter()
+                .chain(chunk_args),
+        )?;
+        let mut stream = get_chunk.stream_chunk()?;
+        let mut streamed = Vec::new();
+        stream.read_to_end(&mut streamed)?;
+        stream.finish()?;
+        assert_eq!(streamed, std::fs::read(&chunk_bam)?);
+
+        assert!(
+            GetChunk::try_parse_from(
+                [
+                    "get-chunk",
+                    "-i",
+                    bam_str,
+                    "-o",
+                    chunk_bam.to_str().unwrap()
+                ]
+                .into_iter()
+                .chain(chunk_args),
+            )?
+            .stream_chunk()
+            .is_err()
+        );
+        Ok(())
+    }
+
+    /// Test that a chunk written to a pre-created FIFO, read concurrently by another thread, has
+    /// the same bytes as the chunk written to a file, and that checksums need a regular file.
+    #[test]
+    fn test_fifo_chunk() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let temp_path: PathBuf = temp_dir.path().to_path_buf();
+        let (random_bam, _) = QueryType::Paired.random_bam(&temp_path, 500)?;
+        let bam_str = random_bam.to_str().unwrap();
+        Index::try_parse_from(["index", "--input", bam_str])?.index_reads()?;
+        let chunk_args = ["-c", "1", "-n", "3", "-t", "1"];
+        let chunk_bam = temp_path.join("chunk.bam");
+        GetChunk::try_parse_from(
+            [
+                "get-chunk",
+                "-i",
+                bam_str,
+                "-o",
+                chunk_bam.to_str().unwrap(),
+            ]
+            .into_iter()
+            .chain(chunk_args),
+        )?
+        .write_chunk()?;
+
+        let fifo = temp_path.join("fifo").join("chunk.bam");
+        std::fs::create_dir(fifo.parent().unwrap())?;
+        assert!(
+            std::process::Command::new("mkfifo")
+                .arg(&fifo)
+                .status()?
+                .success()
+        );
+        assert!(!PathType::from_path(&fifo)?.is_regular_file());
+        let reader = {
+            let fifo = fifo.clone();
+            std::thread::spawn(move || std::fs::read(fifo))
+        };
+        let manifest = temp_path.join("manifest.jsonl");
+        GetChunk::try_parse_from(
+            [
+                "get-chunk",
+                "-i",
+                bam_str,
+                "-o",
+                fifo.to_str().unwrap(),
+                "--manifest",
+                manifest.to_str().unwrap(),
+            ]
+            .into_iter()
+            .chain(chunk_args),
+        )?
+        .write_chunk()?;
+        assert_eq!(reader.join().unwrap()?, std::fs::read(&chunk_bam)?);
+        assert!(std::fs::read_to_string(&manifest)?.contains("\"md5\": null"));
+        assert_eq!(std::fs::read_dir(fifo.parent().unwrap())?.count(), 1);
+
+        let fifo_md5 = GetChunk::try_parse_from(
+            [
+                "get-chunk",
+                "-i",
+                bam_str,
+                "-o",
+                fifo.to_str().unwrap(),
+                "--write-md5",
+            ]
+            .into_iter()
+            .chain(chunk_args),
+        )?;
+        assert!(fifo_md5.write_chunk().is_err());
+        Ok(())
+    }
+
+    /// Test that chunks copied as whole BGZF blocks hold the same header and records as decoded
+    /// chunks, including the last chunk, which runs to the end of the input.
+    #[rstest(query_type => [QueryType::Single, QueryType::Paired, QueryType::Grouped])]
+    fn test_copy_blocks(query_type: QueryType) -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let temp_path: PathBuf = temp_dir.path().to_path_buf();
+        let (random_bam, num_reads) = query_type.random_bam(&temp_path, 3000)?;
+        let bam_str = random_bam.to_str().unwrap();
+        Index::try_parse_from(["index", "--input", bam_str, "-n", "20"])?.index_reads()?;
+        let mut copied_bams = Vec::new();
+        for chunk_index in 0..3 {
+            let chunk_args =
+                ["-c", &chunk_index.to_string(), "-n", "3", "-t", "1"].map(String::from);
+            let decoded = temp_path.join(format!("decoded{chunk_index}.bam"));
+            let copied = temp_path.join(format!("copied{chunk_index}.bam"));
+            for (output, compression) in [(&decoded, Some("6")), (&copied, None)] {
+                let mut args = vec!["get-chunk", "-i", bam_str, "-o", output.to_str().unwrap()];
+                args.extend(compression.map(|level| ["-C", level]).iter().flatten());
+                args.extend(chunk_args.iter().map(String::as_str));
+                let command = GetChunk::try_parse_from(args)?;
+                let split_index = GetChunk::load_split_index(None::<PathBuf>, &random_bam, false)?;
+                let query_range = command.query_range(&split_index)?;
+                assert_eq!(
+                    command.can_copy_blocks(
+                        RecordType::Bam,
+                        RecordType::Bam,
+                        output,
+                        &split_index,
+                        query_range
+                    )?,
+                    compression.is_none()
+                );
+                command.write_chunk()?;
+            }
+            let (decoded_headers, decoded_records, _) = load_chunk_bams(vec![decoded], num_reads)?;
+            let (copied_headers, copied_records, _) =
+                load_chunk_bams(vec![copied.clone()], num_reads)?;
+            assert_eq!(copied_headers[0].to_bytes(), decoded_headers[0].to_bytes());
+            assert_vecs_equal(&copied_records, &decoded_records, assert_records_equal);
+            assert!(std::fs::read(&copied)?.ends_with(&BGZF_EOF));
+            copied_bams.push(copied);
+        }
+        let (_, truth_records) = load_truth_bam(&random_bam)?;
+        let (_, copied_records, _) = load_chunk_bams(copied_bams, num_reads)?;
+        assert_vecs_equal(&copied_records, &truth_records, assert_records_equal);
+        Ok(())
+    }
+
+    /// Test that chunks of bgzipped FASTQ copied as whole BGZF blocks concatenate into the input.
+    #[test]
+    fn test_copy_fastq_blocks() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let temp_path: PathBuf = temp_dir.path().to_path_buf();
+        let fastq = temp_path.join("reads.fastq.gz");
+        let mut writer = NoodlesBgzfWriter::new(File::create(&fastq)?);
+        for read in 0..5000 {
+            writer.write_all(format!("@read{read}\nACGTACGTAC\n+\nIIIIIIIIII\n").as_bytes())?;
+        }
+        writer.finish()?;
+        let fastq_str = fastq.to_str().unwrap();
+        Index::try_parse_from(["index", "-i", fastq_str, "-t", "1", "-n", "10"])?.index_reads()?;
+        let mut concatenated = Vec::new();
+        for chunk_index in 0..4 {
+            let chunk = temp_path.join(format!("chunk{chunk_index}.fastq.gz"));
+            GetChunk::try_parse_from([
+                "get-chunk",
+                "-i",
+                fastq_str,
+                "-o",
+                chunk.to_str().unwrap(),
+                "-t",
+                "1",
+                "-c",
+                &chunk_index.to_string(),
+                "-n",
+                "4",
+            ])?
+            .write_chunk()?;
+            let chunk_fastq = read_decompressed(chunk.to_str().unwrap())?;
+            assert_eq!(
+                chunk_fastq
+                    .split(|&c| c == b'\n')
+                    .filter(|line| line.starts_with(b"@"))
+                    .count(),
+                1250
+            );
+            concatenated.extend(chunk_fastq);
+        }
+        assert_eq!(concatenated, read_decompressed(fastq_str)?);
+        Ok(())
+    }
+
+    /// Test converting FASTQ chunks to each SAM format, which validates the writer spec, with pair
+    /// flags from "/1" and "/2" query name suffixes or from an R1 file name.
+    #[rstest]
+    #[case::sam("sam")]
+    #[case::bam("bam")]
+    #[case::cram("cram")]
+    fn test_fastq_to_sam_formats(#[case] extension: &str) -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let temp_path: PathBuf = temp_dir.path().to_path_buf();
+        let paired = temp_path.join("paired.fastq");
+        let reads: String = (0..100)
+            .flat_map(|pair| [1, 2].map(|end| format!("@pair{pair}/{end}\nACGT\n+\nIIII\n")))
+            .collect();
+        fs::write(&paired, reads)?;
+        let first_ends = temp_path.join("sample_R1_001.fastq.gz");
+        let mut writer = NoodlesBgzfWriter::new(File::create(&first_ends)?);
+        for read in 0..100 {
+            writer.write_all(format!("@read{read}\nACGT\n+\nIIII\n").as_bytes())?;
+        }
+        writer.finish()?;
+
+        let get_records = |fastq: &Path| -> Result<Vec<BamRecord>> {
+            let fastq_str = fastq.to_str().unwrap();
+            Index::try_parse_from(["index", "-i", fastq_str])?.index_reads()?;
+            let chunk = temp_path.join(format!(
+                "{}.chunk1.{extension}",
+                fastq.file_name().unwrap().to_str().unwrap()
+            ));
+            GetChunk::try_parse_from([
+                "get-chunk",
+                "-i",
+                fastq_str,
+                "-o",
+                chunk.to_str().unwrap(),
+                "-c",
+                "1",
+                "-n",
+                "4",
+            ])?
+            .write_chunk()?;
+            let mut reader = get_bam_reader(&chunk, None::<PathBuf>, NonZero::new(1).unwrap())?;
+            let records = reader.records().collect::<Result<Vec<_>, _>>()?;
+            assert!(!records.is_empty());
+            for record in &records {
+                assert!(record.is_paired() && record.is_unmapped() && record.is_mate_unmapped());
+            }
+            Ok(records)
+        };
+
+        // mates keep a shared name, without the suffix
+        let records = get_records(&paired)?;
+        assert_eq!(records.len() % 2, 0);
+        for mates in records.chunks(2) {
+            assert_eq!(mates[0].qname(), mates[1].qname());
+            assert!(!mates[0].qname().contains(&b'/'));
+            assert!(mates[0].is_first_in_template() && !mates[0].is_last_in_template());
+            assert!(mates[1].is_last_in_template() && !mates[1].is_first_in_template());
+        }
+
+        let records = get_records(&first_ends)?;
+        for record in &records {
+            assert!(record.is_first_in_template() && !record.is_last_in_template());
+        }
+        Ok(())
+    }
+
+    /// Test that --tag-chunk tags every record of BAM chunks with its chunk index, and adds it to
+    /// the comments of FASTQ chunks.
+    #[test]
+    fn test_tag_chunk() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let temp_path: PathBuf = temp_dir.path().to_path_buf();
+        let (random_bam, num_reads) = QueryType::Paired.random_bam(&temp_path, 200)?;
+        let bam_str = random_bam.to_str().unwrap();
+        Index::try_parse_from(["index", "--input", bam_str])?.index_reads()?;
+        let mut num_tagged = 0usize;
+        for chunk_index in 0..3 {
+            let chunk = temp_path.join(format!("chunk{chunk_index}.bam"));
+            GetChunk::try_parse_from([
+                "get-chunk",
+                "-i",
+                bam_str,
+                "-o",
+                chunk.to_str().unwrap(),
+                "-c",
+                &chunk_index.to_string(),
+                "-n",
+                "3",
+                "--tag-chunk",
+            ])?
+            .write_chunk()?;
+            let (_, records) = load_truth_bam(&chunk)?;
+            for record in &records {
+                assert_eq!(record.aux(b"ch")?, Aux::I32(chunk_index));
+            }
+            num_tagged += records.len();
+        }
+        assert_eq!(num_tagged, num_reads);
+
+        let fastq = temp_path.join("reads.fastq");
+        let reads: String = (0..300)
+            .map(|read| format!("@read{read} 1:N:0:ACGT\nACGTACGTAC\n+\nIIIIIIIIII\n"))
+            .collect();
+        std::fs::write(&fastq, reads)?;
+        Index::try_parse_from(["index", "-i", fastq.to_str().unwrap()])?.index_reads()?;
+        let fastq_chunk = temp_path.join("chunk1.fastq");
+        GetChunk::try_parse_from([
+            "get-chunk",
+            "-i",
+            fastq.to_str().unwrap(),
+            "-o",
+            fastq_chunk.to_str().unwrap(),
+            "-c",
+            "1",
+            "-n",
+            "3",
+            "--tag-chunk",
+        ])?
+        .write_chunk()?;
+        let fastq_text = std::fs::read_to_string(&fastq_chunk)?;
+        let names: Vec<&str> = fastq_text.lines().step_by(4).collect();
+        assert_eq!(names.len(), 100);
+        assert_eq!(names[0], "@read100 1:N:0:ACGT\tch:i:1");
+        assert!(
+            names.iter().all(|name| name.ends_with("\tch:i:1")),
+            "{names:?}"
+        );
+
+        assert!(
+            GetChunk::try_parse_from([
+                "get-chunk",
+                "-i",
+                bam_str,
+                "--query-start",
+                "0",
+                "--query-count",
+                "5",
+                "--tag-chunk",
+            ])
+            .is_err()
+        );
+        Ok(())
+    }
+
+    /// Test that reads are filtered by flags, length, and mean quality, and that flag filters need
+    /// SAM/BAM/CRAM input.
+    #[test]
+    fn test_read_filters() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let temp_path: PathBuf = temp_dir.path().to_path_buf();
+        let (random_bam, num_reads) = QueryType::Paired.random_bam(&temp_path, 200)?;
+        let bam_str = random_bam.to_str().unwrap();
+        Index::try_parse_from(["index", "--input", bam_str])?.index_reads()?;
+        let chunk = temp_path.join("first-of-pair.bam");
+        GetChunk::try_parse_from([
+            "get-chunk",
+            "-i",
+            bam_str,
+            "-o",
+            chunk.to_str().unwrap(),
+            "-c",
+            "0",
+            "-n",
+            "1",
+            "--exclude-flags",
+            "0x80",
+        ])?
+        .write_chunk()?;
+        let (_, records) = load_truth_bam(&chunk)?;
+        assert_eq!(records.len(), num_reads / 2);
+        assert!(records.iter().all(|record| record.is_first_in_template()));
+
+        // every third read is short, and every fifth read is low quality
+        let fastq = temp_path.join("reads.fastq");
+        let reads: String = (0..300)
+            .map(|read| {
+                let length = if read % 3 == 0 { 5 } else { 10 };
+                let qual = if read % 5 == 0 { "+" } else { "I" };
+                format!(
+                    "@read{read}\n{}\n+\n{}\n",
+                    "A".repeat(length),
+                    qual.repeat(length)
+                )
+            })
+            .collect();
+        std::fs::write(&fastq, reads)?;
+        let fastq_str = fastq.to_str().unwrap();
+        Index::try_parse_from(["index", "-i", fastq_str])?.index_reads()?;
+        let fastq_chunk = temp_path.join("filtered.fastq");
+        let filter_args = ["--min-length", "8", "--min-mean-qual", "20"];
+        GetChunk::try_parse_from(
+            [
+                "get-chunk",
+                "-i",
+                fastq_str,
+                "-o",
+                fastq_chunk.to_str().unwrap(),
+                "-c",
+                "0",
+                "-n",
+                "1",
+            ]
+            .into_iter()
+            .chain(filter_args),
+        )?
+        .write_chunk()?;
+        let fastq_text = std::fs::read_to_string(&fastq_chunk)?;
+        let names: Vec<&str> = fastq_text.lines().step_by(4).collect();
+        let expected: Vec<String> = (0..300)
+            .filter(|read| read % 3 != 0 && read % 5 != 0)
+            .map(|read| format!("@read{read}"))
+            .collect();
+        assert_eq!(names, expected);
+
+        assert!(
+            GetChunk::try_parse_from([
+                "get-chunk",
+                "-i",
+                fastq_str,
+                "-c",
+                "0",
+                "-n",
+                "1",
+                "-f",
+                "1"
+            ])?
+            .write_chunk()
+            .is_err()
+        );
+        Ok(())
+    }
+
+    /// Test that reads are trimmed by fixed lengths and at an adapter before they are filtered, and
+    /// that the same chain of --transform options does the same.
+    #[test]
+    fn test_trim_reads() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let temp_path: PathBuf = temp_dir.path().to_path_buf();
+        // odd reads end in an adapter
+        let fastq = temp_path.join("reads.fastq");
+        let reads: String = (0..100)
+            .map(|read| {
+                let seq = if read % 2 == 1 {
+                    "GGACGTACGTACGTAGATCGGAAGAGCAC"
+                } else {
+                    "GGACGTACGTACGTACGTACGTACGTACG"
+                };
+                format!("@read{read}\n{seq}\n+\n{}\n", "I".repeat(seq.len()))
+            })
+            .collect();
+        std::fs::write(&fastq, reads)?;
+        let fastq_str = fastq.to_str().unwrap();
+        Index::try_parse_from(["index", "-i", fastq_str])?.index_reads()?;
+        let chunk = temp_path.join("trimmed.fastq");
+        GetChunk::try_parse_from([
+            "get-chunk",
+            "-i",
+            fastq_str,
+            "-o",
+            chunk.to_str().unwrap(),
+            "-c",
+            "0",
+            "-n",
+            "1",
+            "--trim-start",
+            "2",
+            "--trim-end",
+            "1",
+            "--adapter",
+            "AGATCGGAAGAGC",
+            "--min-length",
+            "20",
+        ])?
+        .write_chunk()?;
+        let chunk_text = std::fs::read_to_string(&chunk)?;
+        let lines: Vec<&str> = chunk_text.lines().collect();
+        assert_eq!(lines.len(), 50 * 4);
+        assert_eq!(lines[1], "ACGTACGTACGTACGTACGTACGTAC");
+        assert_eq!(lines[3].len(), lines[1].len());
+
+        // the same as a chain of transforms, renaming the reads kept
+        let chained = temp_path.join("chained.fastq");
+        GetChunk::try_parse_from([
+            "get-chunk",
+            "-i",
+            fastq_str,
+            "-o",
+            chained.to_str().unwrap(),
+            "-c",
+            "0",
+            "-n",
+            "1",
+            "--transform",
+            "trim:start=2,end=1,adapter=AGATCGGAAGAGC",
+            "--transform",
+            "filter:min-length=20",
+            "--transform",
+            "rename:prefix=s1_",
+        ])?
+        .write_chunk()?;
+        assert_eq!(
+            std::fs::read_to_string(&chained)?,
+            chunk_text.replace('@', "@s1_")
+        );
+
+        // trimming input that may be aligned fails before any output is written
+        let sam = temp_path.join("aligned.sam");
+        std::fs::write(
+            &sam,
+            "@SQ\tSN:chr1\tLN:1000\nread0\t0\tchr1\t100\t60\t4M\t*\t0\t0\tACGT\tIIII\n",
+        )?;
+        let sam_str = sam.to_str().unwrap();
+        Index::try_parse_from(["index", "-i", sam_str])?.index_reads()?;
+        let trimmed_sam = temp_path.join("trimmed.sam");
+        let err = GetChunk::try_parse_from([
+            "get-chunk",
+            "-i",
+            sam_str,
+            "-o",
+            trimmed_sam.to_str().unwrap(),
+            "-c",
+            "0",
+            "-n",
+            "1",
+            "--trim-start",
+            "1",
+        ])?
+        .write_chunk()
+        .unwrap_err();
+        assert!(err.to_string().contains("Cannot trim"), "{err}");
+        assert!(!trimmed_sam.exists());
+        Ok(())
+    }
+
+    /// Test that read names are rewritten, using the flags of BAM records and the comments of
+    /// FASTQ records, and that pair suffixes are only added to FASTQ output.
+    #[test]
+    fn test_rename_reads() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let temp_path: PathBuf = temp_dir.path().to_path_buf();
+        let (random_bam, num_reads) = QueryType::Paired.random_bam(&temp_path, 100)?;
+        let bam_str = random_bam.to_str().unwrap();
+        Index::try_parse_from(["index", "--input", bam_str])?.index_reads()?;
+        let get_chunk = |output: &Path, args: &[&str]| -> Result<()> {
+            GetChunk::try_parse_from(
+                [
+                    "get-chunk",
+                    "-i",
+                    bam_str,
+                    "-o",
+                    output.to_str().unwrap(),
+                    "-c",
+                    "0",
+                    "-n",
+                    "1",
+                    "--rename-prefix",
+                    "s1_",
+                ]
+                .into_iter()
+                .chain(args.iter().copied()),
+            )?
+            .write_chunk()?;
+            Ok(())
+        };
+        let chunk = temp_path.join("renamed.bam");
+        get_chunk(&chunk, &[])?;
+        let (_, records) = load_truth_bam(&chunk)?;
+        assert_eq!(records.len(), num_reads);
+        assert!(
+            records
+                .iter()
+                .all(|record| record.qname().starts_with(b"s1_"))
+        );
+
+        // mates keep one query name in BAM, so only FASTQ output gets pair suffixes
+        let suffixed = temp_path.join("renamed.fastq");
+        get_chunk(&suffixed, &["--pair-suffix"])?;
+        let names: Vec<String> = std::fs::read_to_string(&suffixed)?
+            .lines()
+            .step_by(4)
+            .map(str::to_string)
+            .collect();
+        assert_eq!(names.len(), num_reads);
+        for (record, name) in records.iter().zip(&names) {
+            let suffix = if record.is_first_in_template() {
+                "/1"
+            } else {
+                "/2"
+            };
+            assert!(name.starts_with("@s1_") && name.ends_with(suffix), "{name}");
+        }
+        for args in [
+            ["--pair-suffix"].as_slice(),
+            &["--transform", "rename:pair-suffix=true"],
+        ] {
+            let unpaired = temp_path.join("unpaired.bam");
+            let err = get_chunk(&unpaired, args).unwrap_err();
+            assert!(err.to_string().contains("only be added to FASTQ"), "{err}");
+            assert!(!unpaired.exists());
+        }
+
+        let fastq = temp_path.join("reads.fastq");
+        let reads: String = (0..10)
+            .map(|read| format!("@read{read} 1:N:0:ACGT\nACGTACGTAC\n+\nIIIIIIIIII\n"))
+            .collect();
+        std::fs::write(&fastq, reads)?;
+        let fastq_str = fastq.to_str().unwrap();
+        Index::try_parse_from(["index", "-i", fastq_str])?.index_reads()?;
+        for (args, first_name) in [
+            (["--replace-whitespace", "_"], "@read0_1:N:0:ACGT"),
+            (["--strip-comment", "--pair-suffix"], "@read0"),
+        ] {
+            let chunk = temp_path.join(format!("renamed-{}.fastq", &args[0][2..]));
+            GetChunk::try_parse_from(
+                [
+                    "get-chunk",
+                    "-i",
+                    fastq_str,
+                    "-o",
+                    chunk.to_str().unwrap(),
+                    "-c",
+                    "0",
+                    "-n",
+                    "1",
+                ]
+                .into_iter()
+                .chain(args),
+            )?
+            .write_chunk()?;
+            let fastq_text = std::fs::read_to_string(&chunk)?;
+            assert_eq!(fastq_text.lines().next(), Some(first_name));
+            assert_eq!(fastq_text.lines().count(), 40);
+        }
+        Ok(())
+    }
+
+    /// Test that --max-output-bytes stops on a query group boundary, and that resuming from the
+    /// stop point recovers the rest of the input.
+    #[rstest(query_type => [QueryType::Single, QueryType::Paired, QueryType::Grouped])]
+    fn test_max_output_bytes(query_type: QueryType) -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let temp_path: PathBuf = temp_dir.path().to_path_buf();
+        let num_queries = 3000usize;
+        let (random_bam, num_reads) = query_type.random_bam(&temp_path, num_queries)?;
+        let bam_str = random_bam.to_str().unwrap();
+        Index::try_parse_from(["index", "--input", bam_str])?.index_reads()?;
+        let manifest = temp_path.join("manifest.jsonl");
+        let limited_bam = temp_path.join("limited.bam");
+        GetChunk::try_parse_from([
+            "get-chunk",
+            "--input",
+            bam_str,
+            "--output",
+            limited_bam.to_str().unwrap(),
+            "--threads",
+            "1",
+            "--manifest",
+            manifest.to_str().unwrap(),
+            "--max-output-bytes",
+            "100000",
+            "-c",
+            "0",
+            "-n",
+            "1",
+        ])?
+        .write_chunk()?;
+        let (_, limited_records, limited_lengths) =
+            load_chunk_bams(vec![limited_bam.clone()], num_reads)?;
+        let query_stop = limited_lengths[0];
+        assert!(0 < query_stop && query_stop < num_queries);
+        // the quota counts uncompressed bytes, so the compressed chunk is smaller
+        assert!(std::fs::metadata(&limited_bam)?.len() < 100000);
+        let manifest_text = std::fs::read_to_string(&manifest)?;
+        assert!(manifest_text.contains(&format!("\"query_stop\": {query_stop},")));
+
+        let rest_bam = temp_path.join("rest.bam");
+        GetChunk::try_parse_from([
+            "get-chunk",
+            "--input",
+            bam_str,
+            "--output",
+            rest_bam.to_str().unwrap(),
+            "--query-start",
+            &query_stop.to_string(),
+            "--query-count",
+            &(num_queries - query_stop).to_string(),
+        ])?
+        .write_chunk()?;
+        let (_, truth_records) = load_truth_bam(&random_bam)?;
+        let (_, chunk_records, _) = load_chunk_bams(vec![limited_bam, rest_bam], num_reads)?;
+        assert!(limited_records.len() < chunk_records.len());
+        assert_vecs_equal(&chunk_records, &truth_records, assert_records_equal);
+        Ok(())
+    }
+
+    /// Test that --block-align chunks concatenate, with one end-of-file block, into a valid copy
+    /// of the input, and are checksummed without the end-of-file block.
+    #[test]
+    fn test_block_align() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let temp_path: PathBuf = temp_dir.path().to_path_buf();
+        let fastq = temp_path.join("reads.fastq.gz");
+        let mut writer = NoodlesBgzfWriter::new(File::create(&fastq)?);
+        for read in 0..300 {
+            writer.write_all(format!("@read{read}\nACGTACGTAC\n+\nIIIIIIIIII\n").as_bytes())?;
+        }
+        writer.finish()?;
+        let fastq_str = fastq.to_str().unwrap();
+        Index::try_parse_from(["index", "-i", fastq_str, "-t", "1"])?.index_reads()?;
+        let mut concatenated = Vec::new();
+        for chunk_index in 0..3 {
+            let chunk = temp_path.join(format!("chunk{chunk_index}.fastq.gz"));
+            GetChunk::try_parse_from([
+                "get-chunk",
+                "-i",
+                fastq_str,
+                "-o",
+                chunk.to_str().unwrap(),
+                "-t",
+                "1",
+                "-c",
+                &chunk_index.to_string(),
+                "-n",
+                "3",
+                "--block-align",
+                "--write-md5",
+            ])?
+            .write_chunk()?;
+            let bytes = std::fs::read(&chunk)?;
+            assert!(!bytes.ends_with(&BGZF_EOF));
+            // checksummed as written, without the stripped end-of-file block
+            assert_eq!(
+                ChunkChecksum::read_sidecar(&chunk)?,
+                ChunkChecksum::from_path(&chunk)?.md5
+            );
+            concatenated.extend(bytes);
+        }
+        concatenated.extend(BGZF_EOF);
+        let combined = temp_path.join("combined.fastq.gz");
+        std::fs::write(&combined, concatenated)?;
+        assert_eq!(
+            read_decompressed(combined.to_str().unwrap())?,
+            read_decompressed(fastq_str)?
+        );
+
+        let uncompressed = temp_path.join("chunk.fastq");
+        assert!(
+            GetChunk::try_parse_from([
+                "get-chunk",
+                "-i",
+                fastq_str,
+                "-o",
+                uncompressed.to_str().unwrap(),
+                "-c",
+                "0",
+                "-n",
+                "3",
+                "--block-align",
+            ])?
+            .write_chunk()
+            .is_err()
+        );
+        Ok(())
+    }
+
+    /// Test that --write-bai indexes a chunk of a coordinate-sorted BAM as it is written, also when
+    /// it is checksummed on its way to the file, and is rejected for SAM output.
+    #[test]
+    fn test_write_bai() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let temp_path: PathBuf = temp_dir.path().to_path_buf();
+        let bam = temp_path.join("sorted.bam");
+        let mut header = Header::new();
+        header.push_record(
+            HeaderRecord::new(b"HD")
+                .push_tag(b"VN", "1.6")
+                .push_tag(b"SO", "coordinate"),
+        );
+        header.push_record(
+            HeaderRecord::new(b"SQ")
+                .push_tag(b"SN", "chr1")
+                .push_tag(b"LN", 100000),
+        );
+        let mut writer = BamWriter::from_path(&bam, &header, Format::Bam)?;
+        let cigar = CigarString(vec![Cigar::Match(10)]);
+        for read in 0..400 {
+            let mut record = BamRecord::new();
+            record.set(
+                format!("read{read}").as_bytes(),
+                Some(&cigar),
+                b"ACGTACGTAC",
+                &[30; 10],
+            );
+            record.set_tid(0);
+            record.set_pos(read * 100);
+            writer.write(&record)?;
+        }
+        drop(writer);
+        let bam_str = bam.to_str().unwrap();
+        Index::try_parse_from(["index", "-i", bam_str, "-t", "1"])?.index_reads()?;
+
+        let chunk = temp_path.join("chunk1.bam");
+        GetChunk::try_parse_from([
+            "get-chunk",
+            "-i",
+            bam_str,
+            "-o",
+            chunk.to_str().unwrap(),
+            "-t",
+            "1",
+            "-c",
+            "1",
+            "-n",
+            "2",
+            "--write-bai",
+            "--write-md5",
+        ])?
+        .write_chunk()?;
+        assert!(chunk.with_added_extension("bai").exists());
+        assert_eq!(
+            ChunkChecksum::read_sidecar(&chunk)?,
+            ChunkChecksum::from_path(&chunk)?.md5
+        );
+        let mut reader = IndexedReader::from_path(&chunk)?;
+        reader.fetch(("chr1", 35000, 35500))?;
+        let positions: Vec<i64> = reader
+            .records()
+            .map(|record| Ok(record?.pos()))
+            .collect::<Result<Vec<i64>>>()?;
+        assert_eq!(positions, [35000, 35100, 35200, 35300, 35400]);
+
+        assert!(
+            GetChunk::try_parse_from([
+                "get-chunk",
+                "-i",
+                bam_str,
+                "-o",
+                temp_path.join("chunk1.sam").to_str().unwrap(),
+                "-c",
+                "1",
+                "-n",
+                "2",
+                "--write-bai",
+            ])?
+            .write_chunk()
+            .is_err()
+        );
+        Ok(())
+    }
+
+    /// Test extracting chunks from BAM and bgzipped FASTQ served over HTTP, finding their indices
+    /// next to them on the server.
+    #[test]
+    fn test_remote_chunks() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let temp_path: PathBuf = temp_dir.path().to_path_buf();
+        let (random_bam, num_reads) = QueryType::Paired.random_bam(&temp_path, 500)?;
+        let bam_str = random_bam.to_str().unwrap();
+        let local_index =
+            Index::try_parse_from(["index", "-i", bam_str, "-n", "10"])?.index_reads()?;
+        let (_, truth_records) = load_truth_bam(&random_bam)?;
+        let fastq = temp_path.join("reads.fastq.gz");
+        let mut fastq_writer = NoodlesBgzfWriter::new(File::create(&fastq)?);
+        for record in &truth_records {
+            let qual: Vec<u8> = record.qual().iter().map(|q| q + 33).collect();
+            fastq_writer.write_all(b"@")?;
+            for line in [record.qname(), &record.seq().as_bytes(), b"+", &qual] {
+                fastq_writer.write_all(line)?;
+                fastq_writer.write_all(b"\n")?;
+            }
+        }
+        fastq_writer.finish()?;
+        Index::try_parse_from(["index", "-i", fastq.to_str().unwrap(), "-n", "10"])?
+            .index_reads()?;
+        let server = TestHttpServer::serve(&temp_path)?;
+
+        let bam_url = server.url("random-paired-500.bam");
+        let index_url = PathType::from_path(&bam_url)?
+            .default_index(SPLIT_INDEX_EXTENSION)?
+            .unwrap();
+        assert_eq!(index_url, PathBuf::from(format!("{bam_url}.si")));
+        let remote_index = SplitIndex::read(&index_url)?;
+        assert!(remote_index == SplitIndex::read(&local_index)?);
+
+        let num_chunks = 3;
+        let mut chunk_bams: Vec<PathBuf> = Vec::with_capacity(num_chunks);
+        for chunk_index in 0..num_chunks {
+            let output = temp_path.join(format!("remote.{chunk_index}.bam"));
+            GetChunk::try_parse_from([
+                "get-chunk",
+                "-i",
+                &bam_url,
+                "-o",
+                output.to_str().unwrap(),
+                "-c",
+                &chunk_index.to_string(),
+                "-n",
+                &num_chunks.to_string(),
+            ])?
+            .write_chunk()?;
+            chunk_bams.push(output);
+        }
+        let (_, chunk_records, _) = load_chunk_bams(chunk_bams, num_reads)?;
+        assert_vecs_equal(&chunk_records, &truth_records, assert_records_equal);
+
+        let fastq_chunk = temp_path.join("remote.fastq");
+        GetChunk::try_parse_from([
+            "get-chunk",
+            "-i",
+            &server.url("reads.fastq.gz"),
+            "-o",
+            fastq_chunk.to_str().unwrap(),
+            "-c",
+            "1",
+            "-n",
+            "2",
+        ])?
+        .write_chunk()?;
+        let fastq_text = std::fs::read_to_string(&fastq_chunk)?;
+        let first_qname = fastq_text.lines().next().unwrap();
+        let expected_qname = format!(
+            "@{}",
+            String::from_utf8_lossy(truth_records[num_reads / 2].qname())
+        );
+        assert_eq!(fastq_text.lines().count(), 4 * num_reads / 2);
+        assert!(first_qname.starts_with(&expected_qname), "{first_qname}");
+        Ok(())
+    }
+
+    /// Test extracting chunks from SAM mirrors of a BAM indexed with --sam-mirror, including a
+    /// mirror with a different header, and that indices without SAM offsets are rejected.
+    #[test]
+    fn test_sam_mirror() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let temp_path: PathBuf = temp_dir.path().to_path_buf();
+        let (random_bam, num_reads) = QueryType::Paired.random_bam(&temp_path, 500)?;
+        let bam_str = random_bam.to_str().unwrap();
+        let (header, truth_records) = load_truth_bam(&random_bam)?;
+        let mirror = temp_path.join("mirror.sam");
+        let mut writer = BamWriter::from_path(&mirror, &header, Format::Sam)?;
+        for record in &truth_records {
+            writer.write(record)?;
+        }
+        drop(writer);
+
+        let index = temp_path.join("mirrored.bam.si");
+        let index_str = index.to_str().unwrap();
+        Index::try_parse_from(["index", "-i", bam_str, "-I", index_str, "--sam-mirror"])?
+            .index_reads()?;
+        let get_mirror_chunk = |input: &Path, index: &str, chunk_index: usize| -> Result<PathBuf> {
+            let output = temp_path.join(format!(
+                "{}.{}.chunk{chunk_index}.bam",
+                input.file_stem().unwrap().to_str().unwrap(),
+                Path::new(index).file_stem().unwrap().to_str().unwrap()
+            ));
+            GetChunk::try_parse_from([
+                "get-chunk",
+                "-i",
+                input.to_str().unwrap(),
+                "-I",
+                index,
+                "--uncompressed-mirror",
+                "-o",
+                output.to_str().unwrap(),
+                "-c",
+                &chunk_index.to_string(),
+                "-n",
+                "4",
+            ])?
+            .write_chunk()?;
+            Ok(output)
+        };
+        let chunks = (0..4)
+            .map(|chunk_index| get_mirror_chunk(&mirror, index_str, chunk_index))
+            .collect::<Result<Vec<_>>>()?;
+        let (_, chunk_records, _) = load_chunk_bams(chunks, num_reads)?;
+        assert_vecs_equal(&chunk_records, &truth_records, assert_records_equal);
+
+        // offsets are shifted to where the mirror's records start, so its header may differ
+        let mut other_header = header.clone();
+        other_header.push_comment(b"added by another tool");
+        let other_mirror = temp_path.join("other_mirror.sam");
+        let mut writer = BamWriter::from_path(&other_mirror, &other_header, Format::Sam)?;
+        for record in &truth_records {
+            writer.write(record)?;
+        }
+        drop(writer);
+        let chunks = (0..4)
+            .map(|chunk_index| get_mirror_chunk(&other_mirror, index_str, chunk_index))
+            .collect::<Result<Vec<_>>>()?;
+        let (_, chunk_records, _) = load_chunk_bams(chunks, num_reads)?;
+        assert_vecs_equal(&chunk_records, &truth_records, assert_records_equal);
+
+        let plain_index = Index::try_parse_from(["index", "-i", bam_str])?.index_reads()?;
+        let err = get_mirror_chunk(&mirror, plain_index.to_str().unwrap(), 0).unwrap_err();
+        assert!(format!("{err:#}").contains("no uncompressed offsets"));
+        Ok(())
+    }
+
+    /// Test writing an index and chunks to URLs, uploaded to a server with HTTP PUT.
+    #[cfg(feature = "cloud-output")]
+    #[test]
+    fn test_url_outputs() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let temp_path: PathBuf = temp_dir.path().to_path_buf();
+        let served = temp_path.join("served");
+        fs::create_dir(&served)?;
+        let (random_bam, num_reads) = QueryType::Paired.random_bam(&temp_path, 100)?;
+        let bam_str = random_bam.to_str().unwrap();
+        let local_index =
+            Index::try_parse_from(["index", "-i", bam_str, "-n", "4"])?.index_reads()?;
+        let server = TestHttpServer::serve(&served)?;
+
+        let index_url = server.url("uploaded.bam.si");
+        SplitIndex::read(&local_index)?.write(&index_url)?;
+        assert!(
+            SplitIndex::read(served.join("uploaded.bam.si"))? == SplitIndex::read(&local_index)?
+        );
+
+        let (_, truth_records) = load_truth_bam(&random_bam)?;
+        let chunk_url = server.url("chunk.bam");
+        GetChunk::try_parse_from([
+            "get-chunk",
+            "-i",
+            bam_str,
+            "-o",
+            &chunk_url,
+            "-c",
+            "0",
+            "-n",
+            "1",
+        ])?
+        .write_chunk()?;
+        let (_, chunk_records, _) = load_chunk_bams(vec![served.join("chunk.bam")], num_reads)?;
+        assert_vecs_equal(&chunk_records, &truth_records, assert_records_equal);
+
+        let fastq_url = server.url("chunk.fastq.gz");
+        GetChunk::try_parse_from([
+            "get-chunk",
+            "-i",
+            bam_str,
+            "-o",
+            &fastq_url,
+            "-c",
+            "0",
+            "-n",
+            "1",
+        ])?
+        .write_chunk()?;
+        let fastq_text = String::from_utf8(read_decompressed(
+            served.join("chunk.fastq.gz").to_str().unwrap(),
+        )?)?;
+        assert_eq!(fastq_text.lines().count(), 4 * num_reads);
+        Ok(())
+    }
+
+    /// Test that inputs are read by their content rather than their extension: a BAM named
+    /// "reads.out" is indexed and chunked as BAM.
+    #[test]
+    fn test_extensionless_input() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let temp_path = temp_dir.path();
+        let (random_bam, num_reads) = QueryType::Paired.random_bam(&temp_path, 500)?;
+        let (_, truth_records) = load_truth_bam(&random_bam)?;
+        let reads_out = temp_path.join("reads.out");
+        std::fs::copy(&random_bam, &reads_out)?;
+        assert_eq!(RecordType::detect(&reads_out)?, Some(RecordType::Bam));
+        Index::try_parse_from(["index", "-i", reads_out.to_str().unwrap(), "-n", "10"])?
+            .index_reads()?;
+
+        let mut chunk_bams = Vec::new();
+        for chunk_index in 0..3 {
+            let output = temp_path.join(format!("chunk{chunk_index}.bam"));
+            GetChunk::try_parse_from([
+                "get-chunk",
+                "-i",
+                reads_out.to_str().unwrap(),
+                "-o",
+                output.to_str().unwrap(),
+                "-c",
+                &chunk_index.to_string(),
+                "-n",
+                "3",
+            ])?
+            .write_chunk()?;
+            chunk_bams.push(output);
+        }
+        let (_, chunk_records, _) = load_chunk_bams(chunk_bams, num_reads)?;
+        assert_vecs_equal(&chunk_records, &truth_records, assert_records_equal);
+        Ok(())
+    }
+
+    /// Test that the output type comes from the output path (including ".fastq.gz"), then
+    /// --output-format, then the input type, and that conflicting options are an error.
+    #[test]
+    fn test_output_type_resolution() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let temp_path = temp_dir.path();
+        let (random_bam, num_reads) = QueryType::Paired.random_bam(&temp_path, 100)?;
+        Index::try_parse_from(["index", "-i", random_bam.to_str().unwrap(), "-n", "10"])?
+            .index_reads()?;
+        let get_chunk = |output: &Path, output_format: Option<&str>| -> Result<()> {
+            let mut args = vec![
+                "get-chunk",
+                "-i",
+                random_bam.to_str().unwrap(),
+                "-o",
+                output.to_str().unwrap(),
+                "-c",
+                "0",
+                "-n",
+                "1",
+            ];
+            if let Some(output_format) = output_format {
+                args.extend(["-O", output_format]);
+            }
+            GetChunk::try_parse_from(args)?.write_chunk()
+        };
+
+        let fastq_gz = temp_path.join("chunk.fastq.gz");
+        get_chunk(&fastq_gz, None)?;
+        let fastq_text = String::from_utf8(read_decompressed(fastq_gz.to_str().unwrap())?)?;
+        assert_eq!(fastq_text.lines().count(), 4 * num_reads);
+
+        let extensionless = temp_path.join("chunk");
+        get_chunk(&extensionless, Some("fastq"))?;
+        let fastq_text = std::fs::read_to_string(&extensionless)?;
+        assert!(fastq_text.starts_with('@'));
+        assert_eq!(fastq_text.lines().count(), 4 * num_reads);
+
+        let default_bam = temp_path.join("chunk.out");
+        get_chunk(&default_bam, None)?;
+        let (_, chunk_records, _) = load_chunk_bams(vec![default_bam], num_reads)?;
+        assert_eq!(chunk_records.len(), num_reads);
+
+        assert!(get_chunk(&temp_path.join("conflict.bam"), Some("fastq")).is_err());
+        assert!(get_chunk(&temp_path.join("chunk.bam.gz"), None).is_err());
+        Ok(())
+    }
+
+    /// Test that --threads is divided between reading and writing by codec and level, that
+    /// --reader-threads and --writer-threads override either side, and that chunks extract with
+    /// the overrides.
+    #[test]
+    fn test_thread_split() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let temp_path = temp_dir.path();
+        let (random_bam, num_reads) = QueryType::Paired.random_bam(&temp_path, 100)?;
+        Index::try_parse_from(["index", "-i", random_bam.to_str().unwrap(), "-n", "10"])?
+            .index_reads()?;
+        let output = temp_path.join("chunk.bam");
+        let args = [
+            "get-chunk",
+            "-i",
+            random_bam.to_str().unwrap(),
+            "-o",
+            output.to_str().unwrap(),
+            "-c",
+            "0",
+            "-n",
+            "1",
+            "-t",
+            "10",
+        ];
+        let split = |extra: &[&str], output: &str, compression| -> Result<(usize, usize)> {
+            let command = GetChunk::try_parse_from(args.iter().chain(extra))?;
+            let output_spec = OutputSpec::resolve(output, None, RecordType::Bam)?;
+            let threads = command.thread_split(RecordType::Bam, output_spec, compression);
+            Ok((threads.reader.get(), threads.writer.get()))
+        };
+        assert_eq!(split(&[], "chunk.bam", None)?, (2, 8));
+        assert_eq!(split(&[], "chunk.bam", Some(1))?, (3, 7));
+        assert_eq!(split(&[], "chunk.sam", None)?, (9, 1));
+        assert_eq!(split(&[], "chunk.fastq", None)?, (9, 1));
+        assert_eq!(
+            split(&["--reader-threads", "4"], "chunk.bam", None)?,
+            (4, 6)
+        );
+        assert_eq!(
+            split(&["--writer-threads", "3"], "chunk.bam", None)?,
+            (7, 3)
+        );
+
+        // plain SAM input needs no decompression threads
+        let sam = temp_path.join("reads.sam");
+        fs::write(&sam, "@HD\tVN:1.6\n")?;
+        let command = GetChunk::try_parse_from([
+            "get-chunk",
+            "-i",
+            sam.to_str().unwrap(),
+            "-o",
+            output.to_str().unwrap(),
+            "-c",
+            "0",
+            "-n",
+            "1",
+            "-t",
+            "10",
+        ])?;
+        let threads = command.thread_split(
+            RecordType::Sam,
+            OutputSpec::resolve("chunk.bam", None, RecordType::Sam)?,
+            None,
+        );
+        assert_eq!((threads.reader.get(), threads.writer.get()), (1, 9));
+
+        GetChunk::try_parse_from(args.iter().chain(&[
+            "--reader-threads",
+            "2",
+            "--writer-threads",
+            "2",
+        ]))?
+        .write_chunk()?;
+        let (_, chunk_records, _) = load_chunk_bams(vec![output], num_reads)?;
+        assert_eq!(chunk_records.len(), num_reads);
+        Ok(())
+    }
+
+    /// Test that --dry-run reports the query range of the chunk it would extract, and its bytes
+    /// within the input, without writing the output.
+    #[test]
+    fn test_dry_run() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let temp_path: PathBuf = temp_dir.path().to_path_buf();
+        let (random_bam, _) = QueryType::Paired.random_bam(&temp_path, 500)?;
+        let bam_str = random_bam.to_str().unwrap();
+        Index::try_parse_from(["index", "-i", bam_str, "-n", "10"])?.index_reads()?;
+        let output = temp_path.join("chunk.bam");
+        let args = [
+            "get-chunk",
+            "-i",
+            bam_str,
+            "-c",
+            "1",
+            "-n",
+            "3",
+            "-o",
+            output.to_str().unwrap(),
+            "--dry-run",
+        ];
+        let command = GetChunk::try_parse_from(args)?;
+        command.execute()?;
+        assert!(!output.exists());
+
+        let split_index = SplitIndex::read(format!("{bam_str}.si"))?;
+        let boundaries = command.chunk_boundaries()?;
+        assert_eq!(
+            (boundaries.start_query, boundaries.stop_query),
+            command.query_range(&split_index)?
+        );
+        assert!(boundaries.seek_offset.is_some());
+        let file_size = std::fs::metadata(&random_bam)?.len();
+        assert!(
+            boundaries
+                .approx_bytes
+                .is_some_and(|bytes| bytes > 0 && bytes < file_size)
+        );
+        Ok(())
+    }
+
+    /// Write records to a coordinate-sorted BAM on chr1 (200 kb), chr2 (100 kb) and chr3 (1 kb),
+    /// sorting them first, and build its BAI.
+    fn write_sorted_bam(bam: &Path, mut records: Vec<BamRecord>) -> Result<()> {
+        let mut header = Header::new();
+        header.push_record(
+            HeaderRecord::new(b"HD")
+                .push_tag(b"VN", "1.6")
+                .push_tag(b"SO", "coordinate"),
+        );
+        for (name, length) in [("chr1", 200000), ("chr2", 100000), ("chr3", 1000)] {
+            header.push_record(
+                HeaderRecord::new(b"SQ")
+                    .push_tag(b"SN", name)
+                    .push_tag(b"LN", length),
+            );
+        }
+        // unplaced reads (tid -1) sort last
+        records.sort_by_key(|record| (record.tid() < 0, record.tid(), record.pos()));
+        let mut writer = BamWriter::from_path(bam, &header, Format::Bam)?;
+        for record in &records {
+            writer.write(record)?;
+        }
+        drop(writer);
+        bam_index::build(bam, None, bam_index::Type::Bai, 1)?;
+        Ok(())
+    }
+
+    /// Test that --region-set chunks hold the reads starting in their regions: auto regions
+    /// partition the placed reads into chunks of similar size, and BED regions select their reads.
+    #[test]
+    fn test_region_set() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let temp_path: PathBuf = temp_dir.path().to_path_buf();
+        let bam = temp_path.join("sorted.bam");
+        let bam_str = bam.to_str().unwrap();
+        let cigar = CigarString(vec![Cigar::Match(50)]);
+        let mut records = Vec::new();
+        let mut placed: Vec<(i32, i64, String)> = Vec::new();
+        for read in 0..3050i64 {
+            let qname = format!("read{read:05}");
+            let mut record = BamRecord::new();
+            if read < 3000 {
+                record.set(qname.as_bytes(), Some(&cigar), &[b'A'; 50], &[30; 50]);
+                let tid = if read < 2000 { 0 } else { 1 };
+                record.set_tid(tid);
+                record.set_pos((read % 2000) * 100);
+                placed.push((tid, record.pos(), qname));
+            } else {
+                record.set(qname.as_bytes(), None, &[b'A'; 50], &[30; 50]);
+                record.set_tid(-1);
+                record.set_pos(-1);
+                record.set_unmapped();
+            }
+            record.set_mtid(-1);
+            record.set_mpos(-1);
+            records.push(record);
+        }
+        write_sorted_bam(&bam, records)?;
+
+        let get_chunk = |region_set: &str, chunk_index: usize, num_chunks: Option<&str>| {
+            let stem = Path::new(region_set).file_stem().unwrap().to_str().unwrap();
+            let chunk = temp_path.join(format!("{stem}.{chunk_index}.sam"));
+            let mut args = vec!["get-chunk", "-i", bam_str, "--region-set", region_set, "-c"];
+            let chunk_index = chunk_index.to_string();
+            args.extend([chunk_index.as_str(), "-o", chunk.to_str().unwrap()]);
+            if let Some(num_chunks) = num_chunks {
+                args.extend(["-n", num_chunks]);
+            }
+            GetChunk::try_parse_from(args)?.execute()?;
+            let (_, records) = load_truth_bam(&chunk)?;
+            Ok(records
+                .iter()
+                .map(|record| String::from_utf8_lossy(record.qname()).to_string())
+                .collect::<Vec<_>>())
+        };
+
+        // auto regions partition the placed reads, in order, into similar chunks
+        let chunks: Vec<Vec<String>> = (0..4)
+            .map(|chunk_index| get_chunk("auto", chunk_index, Some("4")))
+            .collect::<Result<_>>()?;
+        for chunk in &chunks {
+            assert!(
+                chunk.len() > 250 && chunk.len() < 1500,
+                "{:?} reads",
+                chunks.iter().map(Vec::len).collect::<Vec<_>>()
+            );
+        }
+        let truth: Vec<String> = placed.iter().map(|(_, _, qname)| qname.clone()).collect();
+        assert_eq!(chunks.concat(), truth);
+        assert!(get_chunk("auto", 0, None).is_err());
+
+        // BED regions select the reads starting in them, one chunk per region
+        let bed = temp_path.join("regions.bed");
+        std::fs::write(&bed, "chr1\t0\t1025\nchr2\t5000\t10000\nchr3\t0\t1000\n")?;
+        let bed_str = bed.to_str().unwrap();
+        assert_eq!(get_chunk(bed_str, 0, None)?.len(), 11);
+        let expected: Vec<String> = placed
+            .iter()
+            .filter(|(tid, pos, _)| *tid == 1 && (5000..10000).contains(pos))
+            .map(|(_, _, qname)| qname.clone())
+            .collect();
+        assert_eq!(get_chunk(bed_str, 1, Some("3"))?, expected);
+        assert!(get_chunk(bed_str, 2, None)?.is_empty());
+        assert!(get_chunk(bed_str, 1, Some("2")).is_err());
+        assert!(get_chunk(bed_str, 3, None).is_err());
+        Ok(())
+    }
+
+    /// Test that --keep-mates puts both mates of every pair in the chunk of the leftmost mate,
+    /// including pairs spanning chunks and references, with each chunk still sorted.
+    #[test]
+    fn test_keep_mates() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let temp_path: PathBuf = temp_dir.path().to_path_buf();
+        let bam = temp_path.join("paired.bam");
+        let bam_str = bam.to_str().unwrap();
+        let cigar = CigarString(vec![Cigar::Match(50)]);
+        let mut records = Vec::new();
+        for pair in 0..1000i64 {
+            let qname = format!("pair{pair:04}");
+            let first = (0, pair * 190);
+            let second = if pair % 50 == 0 {
+                (1, pair * 90)
+            } else {
+                (0, (pair * 190 + 300 + (pair % 7) * 3000).min(199000))
+            };
+            for (is_first, (tid, pos), (mtid, mpos)) in
+                [(true, first, second), (false, second, first)]
+            {
+                let mut record = BamRecord::new();
+                record.set(qname.as_bytes(), Some(&cigar), &[b'A'; 50], &[30; 50]);
+                record.set_tid(tid);
+                record.set_pos(pos);
+                record.set_mtid(mtid);
+                record.set_mpos(mpos);
+                record.set_paired();
+                if is_first {
+                    record.set_first_in_template();
+                } else {
+                    record.set_last_in_template();
+                }
+                records.push(record);
+            }
+        }
+        write_sorted_bam(&bam, records)?;
+
+        let mut chunk_of_pair: HashMap<String, usize> = HashMap::new();
+        for chunk_index in 0..4usize {
+            let chunk = temp_path.join(format!("chunk{chunk_index}.bam"));
+            GetChunk::try_parse_from([
+                "get-chunk",
+                "-i",
+                bam_str,
+                "--region-set",
+                "auto",
+                "--keep-mates",
+                "-c",
+                &chunk_index.to_string(),
+                "-n",
+                "4",
+                "-o",
+                chunk.to_str().unwrap(),
+            ])?
+            .execute()?;
+            let (_, chunk_records) = load_truth_bam(&chunk)?;
+            assert!(!chunk_records.is_empty());
+            let positions: Vec<(i32, i64)> = chunk_records
+                .iter()
+                .map(|record| (record.tid(), record.pos()))
+                .collect();
+            assert!(positions.is_sorted(), "chunk {chunk_index} is not sorted");
+            let mut counts: HashMap<String, usize> = HashMap::new();
+            for record in &chunk_records {
+                let qname = String::from_utf8_lossy(record.qname()).to_string();
+                *counts.entry(qname).or_default() += 1;
+            }
+            for (qname, count) in counts {
+                assert_eq!(count, 2, "chunk {chunk_index} has {count} mates of {qname}");
+                assert_eq!(chunk_of_pair.insert(qname, chunk_index), None);
+            }
+        }
+        assert_eq!(chunk_of_pair.len(), 1000);
+        assert!(GetChunk::try_parse_from(["get-chunk", "-i", bam_str, "--keep-mates"]).is_err());
+        Ok(())
+    }
 }
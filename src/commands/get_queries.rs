@@ -0,0 +1,267 @@
+use crate::commands::{command::Command, get_chunk::GetChunk};
+use anyhow::{Result, anyhow};
+use clap::{Parser, value_parser};
+use log::{info, warn};
+use split_reads::{
+    chunkable::{ChunkableRecord, ChunkableRecordReader, ChunkableRecordWriter},
+    maybe_compressed_io::open_file,
+    output_spec::OutputSpec,
+    sam_writer_spec::SamWriterSpec,
+    split_index::SplitIndex,
+    thread_budget::{READ_WRITE_POOLS, Threads},
+    util::{RecordType, get_fastq_reader, get_fastq_writer, get_sam_reader},
+};
+use std::{
+    collections::HashSet,
+    io::{BufRead, BufReader},
+    num::NonZero,
+    path::{Path, PathBuf},
+};
+
+/// Extract all records for a list of query names from a query-grouped FASTQ or SAM/BAM/CRAM that
+/// has a split-index (".si") file. If the index was built with --qnames and the input is sorted by
+/// query name, only bins that can contain the requested queries are read.
+#[derive(Parser, Debug)]
+#[command(version, verbatim_doc_comment)]
+pub(crate) struct GetQueries {
+    /// Input FASTQ or SAM/BAM/CRAM to extract from. Cannot read from stdin, because it is not
+    /// seekable.
+    #[clap(long, short = 'i', required = true)]
+    input: PathBuf,
+
+    /// Index for input reads file, built by split-reads index. Defaults to input path with extra
+    /// ".si" extension.
+    #[clap(long, short = 'I', required = false, default_value = None)]
+    index: Option<PathBuf>,
+
+    /// File with one query name per line. A leading '@' and anything after the first whitespace
+    /// are ignored, so FASTQ headers may be used directly. Use "-" for stdin.
+    #[clap(long, short = 'q', required = true)]
+    query_names: PathBuf,
+
+    /// Reference FASTA (required for CRAMs)
+    #[clap(long, short = 'R', required = false, default_value = None)]
+    ref_fasta: Option<PathBuf>,
+
+    /// Output path for extracted records, in the same record type as the input. Use "-" (or omit)
+    /// for stdout.
+    #[clap(long, short = 'o', required = false, default_value = "-")]
+    output: PathBuf,
+
+    /// Compression level for output compressed formats. Default to 0 for writing to stdout .
+    #[clap(long, short = 'C', required = false, value_parser = value_parser!(u32).range(..=9))]
+    compression: Option<u32>,
+
+    /// Number of threads to use for reading or writing. Defaults to "auto", which divides the CPUs
+    /// available to this process between reading and writing.
+    #[clap(long, short = 't', required = false)]
+    threads: Option<Threads>,
+}
+
+/// Read the requested query names, one per line.
+fn read_query_names<P>(path: P) -> Result<HashSet<Vec<u8>>>
+where
+    P: AsRef<Path>,
+{
+    let reader = BufReader::new(open_file(path, false)?);
+    let mut query_names: HashSet<Vec<u8>> = HashSet::new();
+    for line in reader.split(b'\n') {
+        let line = line?;
+        let line = line.strip_prefix(b"@").unwrap_or(&line);
+        let qname = line
+            .split(u8::is_ascii_whitespace)
+            .next()
+            .unwrap_or_default();
+        if !qname.is_empty() {
+            query_names.insert(qname.to_vec());
+        }
+    }
+    Ok(query_names)
+}
+
+/// Write every record whose query name was requested, and record which query names were found.
+/// If the index can prune bins by query name, only read those bins, otherwise scan the whole file.
+fn write_queries<R, Reader, Writer>(
+    reader: &mut Reader,
+    writer: &mut Writer,
+    split_index: &SplitIndex,
+    query_names: &HashSet<Vec<u8>>,
+) -> Result<HashSet<Vec<u8>>>
+where
+    R: ChunkableRecord,
+    Reader: ChunkableRecordReader<R>,
+    Writer: ChunkableRecordWriter<R>,
+{
+    let mut found: HashSet<Vec<u8>> = HashSet::with_capacity(query_names.len());
+    let mut record = R::new();
+    if let Some(bins) = split_index.bins_for_qnames(query_names.iter().map(Vec::as_slice)) {
+        info!(
+            "Searching {} of {} bins for {} queries.",
+            bins.len(),
+            split_index.len(),
+            query_names.len()
+        );
+        for bin in bins {
+            reader.seek_to_range(&bin)?;
+            let mut num_reads = bin.num_previous_reads;
+            while num_reads < bin.num_end_reads {
+                reader.read_no_missing(&mut record, &mut num_reads)?;
+                if query_names.contains(record.qname()) {
+                    writer.write(&record)?;
+                    found.insert(record.qname().to_vec());
+                }
+            }
+        }
+    } else {
+        info!("Index cannot prune bins by query name, scanning all reads.");
+        while let Some(result) = reader.read_into(&mut record) {
+            result?;
+            if query_names.contains(record.qname()) {
+                writer.write(&record)?;
+                found.insert(record.qname().to_vec());
+            } else if found.len() == query_names.len() {
+                // input is query-grouped, so every requested query is complete
+                break;
+            }
+        }
+    }
+    Ok(found)
+}
+
+impl GetQueries {
+    /// Get the number of threads for each of the reading and writing thread pools.
+    fn threads(&self) -> NonZero<usize> {
+        Threads::per_pool(self.threads, READ_WRITE_POOLS)
+    }
+
+    /// Load the index and query names, then write all records for the requested queries.
+    fn write_queries(&self) -> Result<()> {
+        let split_index =
+            GetChunk::load_split_index(self.index.clone(), self.input.clone(), false)?;
+        let query_names = read_query_names(&self.query_names)?;
+        let input_record_type = RecordType::detect(&self.input)?.ok_or_else(|| {
+            anyhow!("Input type must be FASTQ or SAM/BAM/CRAM. Cannot read from stdin.")
+        })?;
+        if let Some(output_record_type) =
+            OutputSpec::from_path(&self.output)?.map(|output_spec| output_spec.record_type)
+            && output_record_type.is_sam_family() != input_record_type.is_sam_family()
+        {
+            return Err(anyhow!(
+                "Output type ({output_record_type}) must match input type ({input_record_type})."
+            ));
+        }
+
+        let found = if input_record_type.is_sam_family() {
+            let mut reader =
+                get_sam_reader(self.input.clone(), self.ref_fasta.clone(), self.threads())?;
+            let mut writer = SamWriterSpec::new(self.output.clone())
+                .header_from_view(reader.header())
+                .format_from_path_or_default(input_record_type.into())?
+                .threads(self.threads())
+                .reference_fasta(self.ref_fasta.clone())
+                .compression(self.compression)
+                .get_bam_writer()?;
+            let found = write_queries(&mut reader, &mut writer, &split_index, &query_names)?;
+            writer.finish()?;
+            found
+        } else {
+            let mut reader = get_fastq_reader(self.input.clone(), self.threads())?;
+            let mut writer =
+                get_fastq_writer(self.output.clone(), self.compression, self.threads())?;
+            let found = write_queries(&mut reader, &mut writer, &split_index, &query_names)?;
+            writer.finish()?;
+            found
+        };
+        if found.len() < query_names.len() {
+            warn!(
+                "Found {} of {} requested queries.",
+                found.len(),
+                query_names.len()
+            );
+        } else {
+            info!("Found all {} requested queries.", query_names.len());
+        }
+        Ok(())
+    }
+}
+
+/// Implement the Command trait for `GetQueries` struct.
+impl Command for GetQueries {
+    /// Execute the get-queries command to extract the records of the requested queries.
+    fn execute(&self) -> Result<()> {
+        Threads::warn_if_oversubscribed(self.threads, READ_WRITE_POOLS);
+        info!("Using {} thread(s)", self.threads());
+        self.write_queries()
+    }
+}
+
+#[cfg(all(test, feature = "synthetic"))]
+mod tests {
+    use super::GetQueries;
+    use crate::{
+        commands::index::Index,
+        test_utils::random_bam::{QueryType, RandomBam},
+    };
+    use anyhow::Result;
+    use clap::Parser;
+    use rstest::rstest;
+    use rust_htslib::bam::{Read, Reader, Record};
+    use std::{fs, path::PathBuf};
+    use tempfile::TempDir;
+
+    /// Test that exactly the records of the requested queries are extracted, in file order, with
+    /// and without query names in the index.
+    #[rstest(query_type => [QueryType::Single, QueryType::Paired, QueryType::Grouped],
+        qnames => [false, true])]
+    fn test_get_queries(query_type: QueryType, qnames: bool) -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let temp_path: PathBuf = temp_dir.path().to_path_buf();
+        let (random_bam, _) = query_type.random_bam(&temp_path, 100)?;
+        let bam_str = random_bam.to_str().unwrap();
+        let mut index_args = vec!["index", "--input", bam_str, "--num-bins", "10"];
+        if qnames {
+            index_args.push("--qnames");
+        }
+        Index::try_parse_from(index_args)?.index_reads()?;
+
+        let truth_records = Reader::from_path(&random_bam)?
+            .records()
+            .collect::<Result<Vec<Record>, _>>()?;
+        let mut requested: Vec<Vec<u8>> = truth_records
+            .iter()
+            .map(|record| record.qname().to_vec())
+            .step_by(7)
+            .collect();
+        requested.dedup();
+        let query_names = temp_path.join("query_names.txt");
+        let mut query_names_text: Vec<u8> = requested.join(b"\n".as_slice());
+        query_names_text.extend(b"\nnot-a-query\n");
+        fs::write(&query_names, query_names_text)?;
+
+        let output = temp_path.join("queries.bam");
+        GetQueries::try_parse_from([
+            "get-queries",
+            "-i",
+            bam_str,
+            "-q",
+            query_names.to_str().unwrap(),
+            "-o",
+            output.to_str().unwrap(),
+            "-t",
+            "1",
+        ])?
+        .write_queries()?;
+
+        let expected: Vec<Vec<u8>> = truth_records
+            .iter()
+            .filter(|record| requested.contains(&record.qname().to_vec()))
+            .map(|record| record.qname().to_vec())
+            .collect();
+        let extracted: Vec<Vec<u8>> = Reader::from_path(&output)?
+            .records()
+            .map(|record| record.map(|record| record.qname().to_vec()))
+            .collect::<Result<Vec<Vec<u8>>, _>>()?;
+        assert_eq!(extracted, expected);
+        Ok(())
+    }
+}
@@ -1,15 +1,39 @@
 use crate::commands::command::Command;
 use anyhow::{Result, anyhow};
-use clap::{Parser, builder::PossibleValuesParser, value_parser};
-use log::info;
-use rust_htslib::bam::Writer as BamWriter;
+use clap::{Parser, value_parser};
+use log::{info, warn};
+use rust_htslib::bam::{Format, Header, Record as BamRecord};
 use split_reads::{
+    atomic_output::check_output,
+    bam_index::build_approximate_index,
+    bin_sizing::{adaptive_num_bins, check_num_bins, file_size},
+    chunkable::{ChunkableRecordReader, CountingBamWriter, GroupBy, OffsetKind, TranslatingWriter},
+    fastq::FastqRecord,
+    file_fingerprint::FileFingerprint,
+    heartbeat::Heartbeat,
+    maybe_compressed_io::TeeDownload,
+    memory_budget::MemoryBudget,
+    output_spec::OutputSpec,
     path_type::PathType,
-    sam_writer_spec::SamWriterSpec,
-    split_index::{SPLIT_INDEX_EXTENSION, SplitIndex},
-    util::{RecordType, get_bam_reader, get_fastq_reader, get_fastq_writer},
+    progress::{Progress, ProgressReader, ProgressUnit},
+    query_grouping::{GroupingCheck, QueryGroupingChecker},
+    resync::{ResyncFormat, is_bgzf},
+    sam_text::SamMirrorReader,
+    sam_writer_spec::{OutputFormat, SamWriterSpec},
+    split_index::{Checkpointer, SPLIT_INDEX_EXTENSION, SplitIndex},
+    thread_budget::Threads,
+    translate_options::{PairEnd, TranslateOptions, parse_tag_name},
+    util::{RecordType, get_bam_reader, get_fastq_reader, get_fastq_writer, get_sam_reader},
+    validation::{ValidatingReader, ValidationLevel},
 };
-use std::{num::NonZero, path::PathBuf};
+use std::{
+    num::NonZero,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+/// Extension added to the index path for indexing checkpoints
+const CHECKPOINT_EXTENSION: &str = "ckpt";
 
 /// Index SAM,BAM, or CRAM. Save to split-index (".si") file for rapid extraction of chunks.
 #[derive(Parser, Debug)]
@@ -28,39 +52,172 @@ pub(crate) struct Index {
     #[clap(long, short = 'R', required = false, default_value = None)]
     ref_fasta: Option<PathBuf>,
 
-    /// Output path for pass-through SAM.
+    /// Output path for pass-through SAM/BAM/CRAM or FASTQ. May convert SAM/BAM/CRAM input to FASTQ,
+    /// or FASTQ input to unaligned BAM.
     #[clap(long, short = 'o', required = false, default_value = None)]
     output: Option<PathBuf>,
 
-    /// Output format type. When specifying file output file names, the extension (.sam, .bam, or
-    /// .cram) determines format, so this setting will only have an effect when writing to stdout
-    #[clap(long, short = 'O', required = false, default_value_t = String::from("bam"), value_parser = PossibleValuesParser::new(["sam", "bam", "cram", "fastq"]))]
-    output_format: String,
+    /// Output format type. Output paths with a recognized extension (.sam, .bam, .cram, .fastq,
+    /// .fq, optionally followed by .gz for FASTQ) determine the format, so this setting is for
+    /// stdout, named pipes and other extensionless outputs, and is an error if it disagrees with
+    /// the extension. If left unspecified, write FASTQ for FASTQ input and BAM otherwise.
+    #[clap(long, short = 'O', required = false, value_enum)]
+    output_format: Option<OutputFormat>,
 
     /// Compression level for output compressed formats. Default to 0 for writing to stdout .
     #[clap(long, short = 'C', required = false, value_parser = value_parser!(u32).range(..=9))]
     compression: Option<u32>,
 
-    /// Number of bins to retain in final index file.
-    #[clap(long, short = 'n', required = false, default_value_t = NonZero::new(10000usize).unwrap())]
+    /// Number of bins to retain in final index file. Warns with a suggested value if the number is
+    /// extreme for the size of the input file.
+    #[clap(long, short = 'n', required = false, default_value_t = NonZero::new(10000usize).unwrap(), conflicts_with = "bin_spacing")]
     num_bins: NonZero<usize>,
 
-    /// Number of threads to use for reading BAM
-    #[clap(long, short = 't', required = false, default_value_t = NonZero::new(num_cpus::get()).unwrap_or(NonZero::new(1usize).unwrap()))]
-    threads: NonZero<usize>,
+    /// Instead of a fixed number of bins, choose the number of bins to give roughly this many bytes
+    /// of input per bin (e.g. 8388608 for one bin per 8 MB). Requires a local input file.
+    #[clap(long, required = false, default_value = None)]
+    bin_spacing: Option<NonZero<u64>>,
+
+    /// Store the first and last query name of each bin in the index. If the input is sorted by
+    /// query name, this lets get-queries skip bins that cannot contain the requested queries.
+    #[clap(long, required = false, default_value_t = false)]
+    qnames: bool,
+
+    /// Store the length of the longest read of each bin in the index, so that get-chunk and tell
+    /// can warn about chunks holding ultra-long reads (which may dominate downstream memory, e.g.
+    /// of assemblers), and tell can plan chunks that isolate them.
+    #[clap(long, required = false, default_value_t = false)]
+    max_read_lengths: bool,
+
+    /// Also store where each bin starts in a SAM copy of a BAM or CRAM input (e.g. written by
+    /// "samtools view -h"), so that get-chunk --uncompressed-mirror can extract chunks from the
+    /// copy. Formats every read as SAM, which slows indexing.
+    #[clap(long, required = false, default_value_t = false, conflicts_with_all = ["output", "partitions", "skip_errors", "checkpoint_interval", "from_bai"])]
+    sam_mirror: bool,
+
+    /// Key that consecutive reads are grouped by, so that chunks never split a group: "qname",
+    /// "qname-prefix:<delimiter>" for the query name up to the first delimiter (e.g. a UMI or
+    /// barcode prefix before ':'), or "tag:<tag>" for the value of an optional field (e.g.
+    /// "tag:MI" or "tag:CB", falling back to the query name for reads without it). The input must
+    /// be grouped by the key. Indices grouped by another key can't be searched by query name.
+    #[clap(long, required = false, default_value = "qname")]
+    group_by: GroupBy,
+
+    /// Group consecutive reads by the value of this optional field, e.g. CB to count cell barcodes
+    /// as query groups, so that no barcode spans two chunks. Shorthand for --group-by tag:<TAG>.
+    #[clap(long, required = false, default_value = None, value_parser = parse_tag_name, conflicts_with = "group_by")]
+    group_by_tag: Option<[u8; 2]>,
+
+    /// Validation of SAM/BAM/CRAM records: "strict" errors on the first malformed record,
+    /// "lenient" warns and skips records that htslib rejects as invalid but are complete. Chunks
+    /// must be extracted with the same validation level.
+    #[clap(long, required = false, default_value = "strict")]
+    validation: ValidationLevel,
+
+    /// Maximum number of corrupt records to skip in a local BGZF-compressed BAM or FASTQ. After
+    /// each, indexing resumes at the first record found in a following BGZF block, and the skipped
+    /// range is recorded in the index so that get-chunk skips it too.
+    #[clap(long, required = false, default_value_t = 0, conflicts_with = "output")]
+    skip_errors: usize,
+
+    /// Save a checkpoint of the partial index (with ".ckpt" added to the index path) at most once
+    /// per this many seconds, so that an interrupted run can continue with --resume. Requires a
+    /// local input file and index path.
+    #[clap(long, required = false, default_value = None, conflicts_with = "output")]
+    checkpoint_interval: Option<NonZero<u64>>,
+
+    /// Continue indexing from the last checkpoint, if there is one. Errors if the checkpoint was
+    /// saved while indexing a different input.
+    #[clap(
+        long,
+        required = false,
+        default_value_t = false,
+        requires = "checkpoint_interval"
+    )]
+    resume: bool,
+
+    /// Index a local BGZF-compressed BAM or FASTQ in this many byte-range partitions in parallel,
+    /// one thread each, then merge the partial indices. Each partition starts at the first record
+    /// found in its first BGZF block.
+    #[clap(long, required = false, default_value = None, conflicts_with_all = ["output", "skip_errors", "checkpoint_interval"])]
+    partitions: Option<NonZero<usize>>,
+
+    /// Save a local copy of a remote input to this path while indexing it, so one download
+    /// produces both the local file and its index. The index describes the local copy, and
+    /// defaults to its path with an added ".si" suffix. The copy is written atomically, so a failed
+    /// download leaves none behind. Unix-like systems only.
+    #[clap(long, required = false, default_value = None, conflicts_with_all = ["output", "partitions", "skip_errors", "checkpoint_interval"])]
+    localize: Option<PathBuf>,
+
+    /// Check that the input is grouped by query name, because input that isn't silently produces
+    /// chunks that split query groups. "error" (the default without a value) stops at the first
+    /// query name that reappears after other queries, "warn" logs it and counts the rest.
+    #[clap(long, required = false, default_value = None, num_args = 0..=1, default_missing_value = "error", conflicts_with = "partitions")]
+    assert_query_grouped: Option<GroupingCheck>,
+
+    /// Build an approximate index of a local coordinate-sorted BAM from its BAI or CSI index,
+    /// without reading its records. Bins start at evenly spaced reference positions and their
+    /// read counts are estimated from the index, so chunks are only roughly even in size. Chunks
+    /// end at bin boundaries rather than between query groups, so they split query groups (e.g.
+    /// read pairs, whose mates are far apart in coordinate order): requires --allow-split-pairs.
+    #[clap(long, required = false, default_value_t = false, requires = "allow_split_pairs", conflicts_with_all = ["output", "partitions", "localize", "skip_errors", "checkpoint_interval", "assert_query_grouped", "group_by_tag", "qnames", "max_read_lengths"])]
+    from_bai: bool,
+
+    /// Accept that chunks extracted with the index may split query groups, as chunks of --from-bai
+    /// indices do.
+    #[clap(long, required = false, default_value_t = false, requires = "from_bai")]
+    allow_split_pairs: bool,
+
+    /// Memory in MiB for remembering query names with --assert-query-grouped. Rarely, a query is
+    /// reported because it collides with remembered names: about 3 bytes per query group makes
+    /// that about a 1 in 70000 chance per query group.
+    #[clap(long, required = false, default_value_t = NonZero::new(512usize).unwrap())]
+    grouping_filter_mib: NonZero<usize>,
+
+    /// Number of threads to use for reading or writing. Defaults to "auto", which divides the CPUs
+    /// available to this process between reading and writing.
+    #[clap(long, short = 't', required = false)]
+    threads: Option<Threads>,
 
     /// Time in seconds between log updates
     #[clap(long, required = false, default_value_t = 30)]
     update_interval: u64,
+
+    /// Rewrite this small JSON file with the status, input offset, read and query counts, and a
+    /// timestamp at least every --heartbeat-interval seconds while indexing, so external watchdogs
+    /// can detect a stalled job.
+    #[clap(long, required = false, default_value = None)]
+    heartbeat: Option<PathBuf>,
+
+    /// Time in seconds between heartbeats
+    #[clap(long, required = false, default_value_t = 30, requires = "heartbeat")]
+    heartbeat_interval: u64,
+
+    /// Show a progress line on stderr with the bytes of input indexed (out of the file size, for
+    /// local files), reads per second, and an estimated time remaining. Not shown with
+    /// --partitions.
+    #[clap(long, required = false, default_value_t = false)]
+    progress: bool,
 }
 
 impl Index {
+    /// Get the number of thread pools working at once: one reading the input, and one writing
+    /// the pass-through output if there is one.
+    fn thread_pools(&self) -> NonZero<usize> {
+        NonZero::new(1 + usize::from(self.output.is_some())).unwrap()
+    }
+
+    /// Get the number of threads for each thread pool.
+    fn threads(&self) -> NonZero<usize> {
+        Threads::per_pool(self.threads, self.thread_pools())
+    }
+
     /// Get the output index path that will be used
     fn get_index_path(&self) -> Result<PathBuf> {
         if let Some(specified_index_path) = self.index.clone() {
             // user specified the index path
             Ok(specified_index_path)
-        } else if let Some(actual_output_path) = self.output.clone() {
+        } else if let Some(actual_output_path) = self.output.clone().or(self.localize.clone()) {
             PathType::from_path(actual_output_path)?
                 .default_index(SPLIT_INDEX_EXTENSION)?
                 .ok_or_else(|| {
@@ -75,82 +232,550 @@ impl Index {
         }
     }
 
-    /// Get the type of Record that will be used. Check for consistency if writing pass-through.
-    fn get_record_type(&self) -> Result<RecordType> {
-        let maybe_input_type = RecordType::from_path(self.input.clone());
-        let maybe_output_type = if let Some(ref actual_output_path) = self.output {
-            RecordType::from_path(actual_output_path)
+    /// Get the number of bins to retain in the final index, either as requested or adapted to the
+    /// input file size, and capped to fit the memory budget. Warn if the requested number is
+    /// extreme for the input file size, or doesn't fit the budget.
+    fn get_num_bins(&self) -> Result<NonZero<usize>> {
+        let input_size = file_size(&self.input)?;
+        let num_bins = if let Some(bin_spacing) = self.bin_spacing {
+            let input_size = input_size.ok_or_else(|| {
+                anyhow!("--bin-spacing requires a local input file with known size.")
+            })?;
+            let num_bins = adaptive_num_bins(input_size, bin_spacing);
+            info!("Using {num_bins} bins for {input_size} byte input.");
+            num_bins
         } else {
-            None
+            if let Some(input_size) = input_size
+                && let Some(warning) = check_num_bins(self.num_bins, input_size)
+            {
+                warn!("{warning}");
+            }
+            self.num_bins
         };
-        match (maybe_input_type, maybe_output_type) {
-            (Some(input_type), Some(output_type)) => {
-                if input_type == output_type {
-                    Ok(input_type)
-                } else {
-                    Err(anyhow!(
-                        "Input type ({input_type}) and output type ({output_type}) do not match."
-                    ))
-                }
+        let budget = MemoryBudget::current();
+        match (
+            budget.max_memory(),
+            NonZero::new(budget.index_bins(num_bins.get())),
+        ) {
+            (Some(max_memory), Some(capped)) if capped < num_bins => {
+                warn!(
+                    "Using {capped} bins instead of {num_bins} to fit --max-memory {max_memory}."
+                );
+                Ok(capped)
             }
-            (Some(input_type), None) => Ok(input_type),
-            (None, Some(output_type)) => Ok(output_type),
-            (None, None) => {
-                if self.output_format == "fastq" {
-                    Ok(RecordType::Fastq)
-                } else {
-                    Ok(RecordType::Bam)
-                }
+            _ => Ok(num_bins),
+        }
+    }
+
+    /// Get the path of the input to resynchronize in after corrupt records, or None if not
+    /// skipping errors. Only local BGZF files can be resynchronized.
+    fn resync_path(&self) -> Result<Option<PathBuf>> {
+        if self.skip_errors == 0 {
+            return Ok(None);
+        }
+        match PathType::from_path(&self.input)? {
+            PathType::FilePath(path) if is_bgzf(&path)? => Ok(Some(path)),
+            _ => Err(anyhow!(
+                "--skip-errors requires a local BGZF-compressed BAM or FASTQ input."
+            )),
+        }
+    }
+
+    /// Get the Checkpointer to save checkpoints next to the index (loading the last one if
+    /// resuming), or None if not checkpointing.
+    fn get_checkpointer(&self, index_path: &Path) -> Result<Option<Checkpointer>> {
+        let Some(checkpoint_interval) = self.checkpoint_interval else {
+            return Ok(None);
+        };
+        let (PathType::FilePath(input), PathType::FilePath(index_path)) = (
+            PathType::from_path(&self.input)?,
+            PathType::from_path(index_path)?,
+        ) else {
+            return Err(anyhow!(
+                "--checkpoint-interval requires a local input file and index path."
+            ));
+        };
+        let mut checkpoint_path = index_path.into_os_string();
+        checkpoint_path.push(format!(".{CHECKPOINT_EXTENSION}"));
+        let mut checkpointer = Checkpointer::new(
+            checkpoint_path,
+            Duration::from_secs(checkpoint_interval.into()),
+        );
+        checkpointer.fingerprint(FileFingerprint::from_path(&input)?);
+        if self.resume {
+            checkpointer.resume()?;
+        }
+        Ok(Some(checkpointer))
+    }
+
+    /// If localizing, start downloading the remote input and return the download, whose stream
+    /// path is indexed in place of the input. Otherwise return None.
+    fn start_localize(&self) -> Result<Option<TeeDownload>> {
+        let Some(ref local_path) = self.localize else {
+            return Ok(None);
+        };
+        let PathType::UrlPath(url) = PathType::from_path(&self.input)? else {
+            return Err(anyhow!("--localize requires a remote (URL) input."));
+        };
+        if !PathType::from_path(local_path)?.is_regular_file() {
+            return Err(anyhow!(
+                "--localize requires a local file path, not a pipe."
+            ));
+        }
+        info!("Saving {url} to {local_path:?} while indexing.");
+        Ok(Some(TeeDownload::start(url.as_str(), local_path)?))
+    }
+
+    /// Get the SAM/BAM/CRAM format of the pass-through output.
+    fn output_sam_format(output_type: RecordType) -> Result<Format> {
+        output_type
+            .sam_format()
+            .ok_or_else(|| anyhow!("{output_type} is not a SAM format, should be unreachable."))
+    }
+
+    /// Check that records can be located in the pass-through output once it is written.
+    /// Compressed output must be a local file, so that its BGZF blocks can be read back, and
+    /// converted output must be BAM or FASTQ, whose writers report record positions. SAM and CRAM
+    /// output keeps the input's offsets, so it must be of the input's type.
+    fn check_output_path(&self, record_type: RecordType) -> Result<()> {
+        let (Some(output), Some(output_spec)) =
+            (self.output.as_ref(), self.get_output_spec(record_type)?)
+        else {
+            return Ok(());
+        };
+        let output_type = output_spec.record_type;
+        if record_type.is_sam_family() != output_type.is_sam_family()
+            && !matches!(output_type, RecordType::Bam | RecordType::Fastq)
+        {
+            return Err(anyhow!(
+                "Cannot convert {record_type} to {output_type} while indexing, only to BAM or \
+                FASTQ."
+            ));
+        }
+        if matches!(output_type, RecordType::Sam | RecordType::Cram) && output_type != record_type {
+            return Err(anyhow!(
+                "Cannot convert {record_type} to {output_type} while indexing: the index of \
+                {output_type} output holds the input's offsets, which only fit output of the \
+                input's type. Write BAM or {record_type} instead."
+            ));
+        }
+        if output_spec.is_compressed(self.compression) && !output_spec.is_bgzf(self.compression) {
+            return Err(anyhow!(
+                "Compressed pass-through FASTQ must be BGZF, not plain gzip, so that the index \
+                can locate records in it."
+            ));
+        }
+        let compressed =
+            output_type == RecordType::Bam || output_spec.is_compressed(self.compression);
+        if compressed && !PathType::from_path(output)?.is_regular_file() {
+            return Err(anyhow!(
+                "Compressed pass-through output must be a local file, not a pipe, so that the \
+                index can locate records in it."
+            ));
+        }
+        Ok(())
+    }
+
+    /// Convert index offsets, taken from the pass-through writer as positions in the uncompressed
+    /// output, to offsets in the finished output. SAM and CRAM writers can't report positions, so
+    /// those indices keep offsets from the input. Records translated from input_type hash
+    /// differently from the input records, so the index can't check the first read of each bin.
+    fn resolve_output_offsets(
+        &self,
+        split_index: &mut SplitIndex,
+        output: &Path,
+        input_type: RecordType,
+        output_type: RecordType,
+    ) -> Result<()> {
+        let first_record_offset = match output_type {
+            RecordType::Sam | RecordType::Cram => return Ok(()),
+            RecordType::Bam => {
+                let mut reader = get_bam_reader(output, None::<PathBuf>, self.threads())?;
+                ChunkableRecordReader::<BamRecord>::tell(&mut reader)?
+            }
+            RecordType::Fastq => 0,
+        };
+        if input_type.is_sam_family() != output_type.is_sam_family() {
+            split_index.clear_first_record_hashes();
+        }
+        split_index.set_output_uncompressed_offsets(output_type == RecordType::Fastq);
+        match PathType::from_path(output)? {
+            PathType::FilePath(path) => {
+                split_index.resolve_output_offsets(path, first_record_offset)
+            }
+            // uncompressed output elsewhere, so positions are already byte offsets
+            _ => {
+                split_index.set_offset_kind(Some(OffsetKind::Byte));
+                Ok(())
             }
         }
     }
 
+    /// Get the key that reads are grouped by
+    fn get_group_by(&self) -> GroupBy {
+        self.group_by_tag.map_or(self.group_by, GroupBy::Tag)
+    }
+
+    /// Get a checker for grouping of the input by the grouping key, if requested.
+    fn grouping_checker(&self) -> Option<QueryGroupingChecker> {
+        self.assert_query_grouped.map(|check| {
+            let mut grouping_checker =
+                QueryGroupingChecker::new(check, self.grouping_filter_mib.get() << 20);
+            grouping_checker.group_by(self.get_group_by());
+            grouping_checker
+        })
+    }
+
+    /// Get the progress of indexing, if requested: bytes of the input read, out of its size if it
+    /// is a local file.
+    fn progress(&self) -> Result<Option<Progress>> {
+        if !self.progress {
+            return Ok(None);
+        }
+        Ok(Some(Progress::new(
+            "Indexing",
+            ProgressUnit::Bytes,
+            file_size(&self.input)?,
+        )))
+    }
+
+    /// Build the split index in parallel partitions of a local BGZF input
+    fn build_parallel(
+        &self,
+        record_type: RecordType,
+        partitions: NonZero<usize>,
+        num_bins: NonZero<usize>,
+    ) -> Result<SplitIndex> {
+        let path = match PathType::from_path(&self.input)? {
+            PathType::FilePath(path) if is_bgzf(&path)? => path,
+            _ => {
+                return Err(anyhow!(
+                    "--partitions requires a local BGZF-compressed BAM or FASTQ input."
+                ));
+            }
+        };
+        // each partition gets its own thread, so don't add decompression threads
+        let threads = NonZero::new(1usize).unwrap();
+        let group_by = self.get_group_by();
+        if record_type.is_sam_family() {
+            SplitIndex::build_parallel(
+                &path,
+                ResyncFormat::Bam,
+                || {
+                    Ok(ValidatingReader::new(
+                        get_bam_reader(&path, self.ref_fasta.clone(), threads)?,
+                        self.validation,
+                    ))
+                },
+                partitions,
+                num_bins,
+                group_by,
+            )
+        } else {
+            SplitIndex::build_parallel(
+                &path,
+                ResyncFormat::Fastq,
+                || {
+                    Ok(ValidatingReader::new(
+                        get_fastq_reader(&path, threads)?,
+                        self.validation,
+                    ))
+                },
+                partitions,
+                num_bins,
+                group_by,
+            )
+        }
+    }
+
+    /// Get the type of Record that will be read: detected from the input, or if it can't be (e.g.
+    /// stdin), the same as the pass-through output.
+    fn get_record_type(&self) -> Result<RecordType> {
+        if let Some(input_type) = RecordType::detect(&self.input)? {
+            Ok(input_type)
+        } else {
+            Ok(self
+                .get_output_spec(RecordType::Bam)?
+                .map_or(self.default_output_type(RecordType::Bam), |spec| {
+                    spec.record_type
+                }))
+        }
+    }
+
+    /// Resolve the pass-through output, or None if there is no output. Uses the output path
+    /// extensions if recognized, otherwise the default output type.
+    fn get_output_spec(&self, record_type: RecordType) -> Result<Option<OutputSpec>> {
+        self.output
+            .as_ref()
+            .map(|output| {
+                OutputSpec::resolve(
+                    output,
+                    self.output_format,
+                    self.default_output_type(record_type),
+                )
+            })
+            .transpose()
+    }
+
+    /// Get the pass-through output record type to use when the output path has no recognized
+    /// extension: the output_format option if specified, otherwise FASTQ for FASTQ input and BAM
+    /// for SAM/BAM/CRAM input.
+    fn default_output_type(&self, record_type: RecordType) -> RecordType {
+        match self.output_format {
+            Some(output_format) => output_format.into(),
+            None if record_type.is_sam_family() => RecordType::Bam,
+            None => RecordType::Fastq,
+        }
+    }
+
+    /// Build an approximate index from the BAI/CSI index of the input BAM, and write it to the
+    /// requested index path
+    fn index_from_bai(&self, index_path: PathBuf) -> Result<PathBuf> {
+        if RecordType::detect(&self.input)? != Some(RecordType::Bam) {
+            return Err(anyhow!("--from-bai requires a BAM input."));
+        }
+        let mut split_index = build_approximate_index(&self.input, self.get_num_bins()?)?;
+        split_index.set_fingerprint(FileFingerprint::from_path(&self.input)?);
+        info!(
+            "Indexed about {} reads into {} bins without reading them. Chunks may split query \
+            groups.",
+            split_index.num_reads(),
+            split_index.len()
+        );
+        split_index.write(index_path.clone())?;
+        Ok(index_path)
+    }
+
     /// Build the split index, then downsize to the requested number of bins and write to requested
     /// index path
     pub fn index_reads(&self) -> Result<PathBuf> {
         // First ensure that the output path is well-specified
         let index_path = self.get_index_path()?;
+        check_output(&index_path)?;
+        if self.from_bai {
+            return self.index_from_bai(index_path);
+        }
         let record_type = self.get_record_type()?;
+        if self.sam_mirror && !matches!(record_type, RecordType::Bam | RecordType::Cram) {
+            return Err(anyhow!("--sam-mirror requires BAM or CRAM input."));
+        }
+        let output_type = self
+            .get_output_spec(record_type)?
+            .map(|output_spec| output_spec.record_type);
+        let num_bins = self.get_num_bins()?;
+        let group_by = self.get_group_by();
+        let mut checkpointer = self.get_checkpointer(&index_path)?;
+        self.check_output_path(record_type)?;
+        let mut download = self.start_localize()?;
+        let mut heartbeat = self
+            .heartbeat
+            .as_ref()
+            .map(|path| Heartbeat::new(path, Duration::from_secs(self.heartbeat_interval)));
+        if let Some(ref mut heartbeat) = heartbeat {
+            heartbeat.beat("running", None, 0, 0);
+        }
+        let input = download.as_ref().map_or(self.input.clone(), |download| {
+            download.stream_path().to_path_buf()
+        });
 
         // Build and downsample the index
-        let split_index = if record_type == RecordType::Bam {
+        let mut split_index = if let Some(partitions) = self.partitions {
+            self.build_parallel(record_type, partitions, num_bins)?
+        } else if record_type.is_sam_family() {
             // read (and possibly write) SAM/BAM/CRAM
-            let reader = get_bam_reader(self.input.clone(), self.ref_fasta.clone(), self.threads)?;
-            let writer: Option<BamWriter> = if let Some(ref output) = self.output {
-                Some(
-                    SamWriterSpec::new(output)
-                        .header_from_reader(&reader)
-                        .format_from_path_or_default(self.output_format.clone())?
-                        .threads(self.threads)
-                        .reference_fasta(self.ref_fasta.clone().as_ref())
-                        .compression(self.compression)
-                        .get_bam_writer()?,
-                )
-            } else {
-                None
-            };
-            SplitIndex::build(reader, writer, self.num_bins, self.update_interval)?
+            let mut reader = ValidatingReader::new(
+                get_sam_reader(input, self.ref_fasta.clone(), self.threads())?,
+                self.validation,
+            );
+            if let Some(ref mut download) = download {
+                download.release_stream();
+            }
+            if let Some(grouping_checker) = self.grouping_checker() {
+                reader.check_query_grouping(grouping_checker);
+            }
+            if let Some(resync_path) = self.resync_path()? {
+                let (input, ref_fasta, threads) =
+                    (self.input.clone(), self.ref_fasta.clone(), self.threads());
+                reader.skip_errors(
+                    self.skip_errors,
+                    resync_path,
+                    ResyncFormat::Bam,
+                    move || get_sam_reader(input.clone(), ref_fasta.clone(), threads),
+                );
+            }
+            let header = reader.get_ref().header().clone();
+            let reader = SamMirrorReader::new(reader, self.sam_mirror.then_some(&header));
+            match (self.output.as_ref(), output_type) {
+                (Some(output), Some(RecordType::Fastq)) => {
+                    // translate to FASTQ while indexing
+                    let mut fastq_writer =
+                        get_fastq_writer(output, self.compression, self.threads())?;
+                    let writer = TranslatingWriter::<FastqRecord, _>::new(
+                        &mut fastq_writer,
+                        TranslateOptions::new(),
+                    );
+                    let split_index = SplitIndex::build_resumable(
+                        ProgressReader::new(reader, self.progress()?),
+                        Some(writer),
+                        num_bins,
+                        self.update_interval,
+                        group_by,
+                        checkpointer.as_mut(),
+                        heartbeat.as_mut(),
+                    )?;
+                    fastq_writer.finish()?;
+                    split_index
+                }
+                (output, output_type) => {
+                    let mut bam_writer =
+                        if let (Some(output), Some(output_type)) = (output, output_type) {
+                            let format = Self::output_sam_format(output_type)?;
+                            let writer = SamWriterSpec::new(output)
+                                .header_from_view(&header)
+                                .format(output_type.into())
+                                .threads(self.threads())
+                                .reference_fasta(self.ref_fasta.clone().as_ref())
+                                .compression(self.compression)
+                                .get_bam_writer()?;
+                            Some((writer, format))
+                        } else {
+                            None
+                        };
+                    let split_index = SplitIndex::build_resumable(
+                        ProgressReader::new(reader, self.progress()?),
+                        bam_writer
+                            .as_mut()
+                            .map(|(writer, format)| CountingBamWriter::new(writer, *format)),
+                        num_bins,
+                        self.update_interval,
+                        group_by,
+                        checkpointer.as_mut(),
+                        heartbeat.as_mut(),
+                    )?;
+                    if let Some((writer, _)) = bam_writer {
+                        writer.finish()?;
+                    }
+                    split_index
+                }
+            }
         } else {
             // read (and possibly write) FASTQ
-            let reader = get_fastq_reader(self.input.clone(), self.threads)?;
-            let writer = if let Some(ref output) = self.output {
-                Some(get_fastq_writer(output, self.compression, self.threads)?)
-            } else {
-                None
-            };
-            SplitIndex::build(reader, writer, self.num_bins, self.update_interval)?
+            let mut reader =
+                ValidatingReader::new(get_fastq_reader(input, self.threads())?, self.validation);
+            if let Some(ref mut download) = download {
+                download.release_stream();
+            }
+            if let Some(grouping_checker) = self.grouping_checker() {
+                reader.check_query_grouping(grouping_checker);
+            }
+            if let Some(resync_path) = self.resync_path()? {
+                let (input, threads) = (self.input.clone(), self.threads());
+                reader.skip_errors(
+                    self.skip_errors,
+                    resync_path,
+                    ResyncFormat::Fastq,
+                    move || get_fastq_reader(input.clone(), threads),
+                );
+            }
+            match (self.output.as_ref(), output_type) {
+                (Some(output), Some(output_type)) if output_type.is_sam_family() => {
+                    // translate to unaligned BAM while indexing, without a reference or @SQ lines
+                    let format = Self::output_sam_format(output_type)?;
+                    let mut bam_writer = SamWriterSpec::new(output)
+                        .header(Header::new())
+                        .format(output_type.into())
+                        .threads(self.threads())
+                        .compression(self.compression)
+                        .get_bam_writer()?;
+                    let writer = TranslatingWriter::<BamRecord, _>::new(
+                        CountingBamWriter::new(&mut bam_writer, format),
+                        TranslateOptions::new()
+                            .pair_end(PairEnd::from_path(&self.input))
+                            .to_owned(),
+                    );
+                    let split_index = SplitIndex::build_resumable(
+                        ProgressReader::new(reader, self.progress()?),
+                        Some(writer),
+                        num_bins,
+                        self.update_interval,
+                        group_by,
+                        checkpointer.as_mut(),
+                        heartbeat.as_mut(),
+                    )?;
+                    bam_writer.finish()?;
+                    split_index
+                }
+                (output, _) => {
+                    let mut fastq_writer = if let Some(output) = output {
+                        Some(get_fastq_writer(output, self.compression, self.threads())?)
+                    } else {
+                        None
+                    };
+                    let split_index = SplitIndex::build_resumable(
+                        ProgressReader::new(reader, self.progress()?),
+                        fastq_writer.as_mut(),
+                        num_bins,
+                        self.update_interval,
+                        group_by,
+                        checkpointer.as_mut(),
+                        heartbeat.as_mut(),
+                    )?;
+                    if let Some(fastq_writer) = fastq_writer {
+                        fastq_writer.finish()?;
+                    }
+                    split_index
+                }
+            }
         };
+        if let (Some(output), Some(output_type)) = (self.output.as_ref(), output_type) {
+            self.resolve_output_offsets(&mut split_index, output, record_type, output_type)?;
+        }
         info!(
             "Indexed {} reads and {} queries into  {} raw bins.",
             split_index.num_reads(),
             split_index.num_queries(),
             split_index.len()
         );
-        let downsized_index = split_index.downsize_reads(self.num_bins)?;
+        if !split_index.skipped_ranges().is_empty() {
+            warn!(
+                "Skipped {} corrupt range(s). Reads in them are missing from the index and chunks.",
+                split_index.skipped_ranges().len()
+            );
+        }
+        let mut downsized_index = split_index.downsize_reads(num_bins)?;
         info!("Downsized index to {} bins", downsized_index.len());
+        if !self.qnames {
+            downsized_index.clear_qnames();
+        }
+        if !self.max_read_lengths {
+            downsized_index.clear_max_read_lengths();
+        }
+
+        if let Some(download) = download {
+            let num_bytes = download.finish()?;
+            info!(
+                "Saved {num_bytes} bytes to {:?}.",
+                self.localize.as_ref().unwrap()
+            );
+        }
+
+        // Fingerprint the file that the index offsets refer to, so stale indices can be detected
+        let indexed_path = self
+            .output
+            .as_ref()
+            .or(self.localize.as_ref())
+            .unwrap_or(&self.input);
+        downsized_index.set_fingerprint(FileFingerprint::from_path(indexed_path)?);
 
         // Write the downsized index
+        let (num_reads, num_queries) = (downsized_index.num_reads(), downsized_index.num_queries());
         downsized_index.write(index_path.clone())?;
+        if let Some(checkpointer) = checkpointer {
+            checkpointer.remove()?;
+        }
+        if let Some(ref mut heartbeat) = heartbeat {
+            heartbeat.beat("done", None, num_reads, num_queries);
+        }
         Ok(index_path)
     }
 }
@@ -159,20 +784,41 @@ impl Index {
 impl Command for Index {
     /// Execute the index command to build and write a split-index file.
     fn execute(&self) -> Result<()> {
-        info!("Using {} thread(s)", self.threads);
+        Threads::warn_if_oversubscribed(self.threads, self.thread_pools());
+        info!("Using {} thread(s)", self.threads());
         self.index_reads()?;
         Ok(())
     }
 }
 
-#[cfg(test)]
+#[cfg(all(test, feature = "synthetic"))]
 mod tests {
-    use super::{SplitIndex, get_bam_reader};
-    use crate::test_utils::random_bam::QueryType;
+    use super::{Header, Index, OffsetKind, SplitIndex, TeeDownload, get_bam_reader};
+    #[cfg(feature = "cloud-output")]
+    use crate::test_utils::http_server::TestHttpServer;
+    use crate::{
+        commands::{command::Command, get_chunk::GetChunk},
+        test_utils::random_bam::{QueryType, RandomBam},
+    };
     use anyhow::Result;
+    use clap::Parser;
+    use noodles_bgzf::io::Writer as NoodlesBgzfWriter;
     use rstest::rstest;
-    use rust_htslib::bam::Writer as BamWriter;
-    use std::{cmp::min, num::NonZero, path::PathBuf};
+    use rust_htslib::bam::{
+        Format, Read as BamRead, Reader as BamReader, Record as BamRecord, Writer as BamWriter,
+        header::HeaderRecord,
+        index as bam_index,
+        record::{Aux, Cigar, CigarString},
+    };
+    use split_reads::maybe_compressed_io::MaybeCompressedReader;
+    use std::{
+        cmp::min,
+        collections::BTreeMap,
+        fs::{self, File},
+        io::{Read, Write},
+        num::NonZero,
+        path::PathBuf,
+    };
     use tempfile::TempDir;
 
     /// Detailed assertiton of expected SplitIndex structure
@@ -302,6 +948,633 @@ mod tests {
             ),
             true,
         );
+        // bam_builder puts every read in read group "A"
+        let expected_read_groups: BTreeMap<Vec<u8>, usize> = if num_reads > 0 {
+            BTreeMap::from([(b"A".to_vec(), num_reads)])
+        } else {
+            BTreeMap::new()
+        };
+        assert_eq!(raw_split_index.read_groups(), &expected_read_groups);
+        assert_eq!(final_split_index.read_groups(), &expected_read_groups);
+        Ok(())
+    }
+
+    /// Test that --skip-errors indexes around a corrupt BGZF block, and that get-chunk skips the
+    /// same range.
+    #[test]
+    fn test_skip_errors() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let fastq = temp_dir.path().join("damaged.fastq.gz");
+        let num_reads = 400;
+        let mut writer = NoodlesBgzfWriter::new(File::create(&fastq)?);
+        for read in 0..num_reads {
+            writer.write_all(format!("@read{read}\nACGTACGTAC\n+\nIIIIIIIIII\n").as_bytes())?;
+            if read % 50 == 49 {
+                writer.flush()?;
+            }
+        }
+        writer.finish()?;
+        // corrupt the deflated data of the third block
+        let mut bytes = fs::read(&fastq)?;
+        let mut block_start = 0;
+        for _ in 0..2 {
+            let block_size = u16::from_le_bytes([bytes[block_start + 16], bytes[block_start + 17]]);
+            block_start += block_size as usize + 1;
+        }
+        for byte in &mut bytes[block_start + 20..block_start + 40] {
+            *byte = !*byte;
+        }
+        fs::write(&fastq, bytes)?;
+        let fastq_str = fastq.to_str().unwrap();
+
+        assert!(
+            Index::try_parse_from(["index", "-i", fastq_str, "-t", "1"])?
+                .index_reads()
+                .is_err()
+        );
+        let index_path =
+            Index::try_parse_from(["index", "-i", fastq_str, "-t", "1", "--skip-errors", "1"])?
+                .index_reads()?;
+        let split_index = SplitIndex::read(&index_path)?;
+        assert_eq!(split_index.skipped_ranges().len(), 1);
+        assert!(split_index.num_reads() < num_reads);
+        assert!(split_index.num_reads() >= num_reads - 52);
+
+        let mut chunk_reads = 0;
+        for chunk_index in 0..3 {
+            let output = temp_dir.path().join(format!("chunk{chunk_index}.fastq"));
+            GetChunk::try_parse_from([
+                "get-chunk",
+                "-i",
+                fastq_str,
+                "-o",
+                output.to_str().unwrap(),
+                "-t",
+                "1",
+                "-c",
+                &chunk_index.to_string(),
+                "-n",
+                "3",
+            ])?
+            .execute()?;
+            chunk_reads += fs::read_to_string(&output)?.lines().count() / 4;
+        }
+        assert_eq!(chunk_reads, split_index.num_reads());
+        Ok(())
+    }
+
+    /// Test that --assert-query-grouped passes grouped input, and errors at (or warns about) the
+    /// first query that reappears after other queries.
+    #[test]
+    fn test_assert_query_grouped() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let record = |read: usize| format!("@read{read}\nACGT\n+\nIIII\n");
+        let grouped = temp_dir.path().join("grouped.fastq");
+        fs::write(
+            &grouped,
+            (0..100).map(|read| record(read / 2)).collect::<String>(),
+        )?;
+        let ungrouped = temp_dir.path().join("ungrouped.fastq");
+        fs::write(
+            &ungrouped,
+            (0..100).map(|read| record(read % 50)).collect::<String>(),
+        )?;
+        let index = |input: &PathBuf, check: &str| {
+            Index::try_parse_from([
+                "index",
+                "-i",
+                input.to_str().unwrap(),
+                &format!("--assert-query-grouped={check}"),
+                "--grouping-filter-mib",
+                "1",
+            ])?
+            .index_reads()
+        };
+        index(&grouped, "error")?;
+        let err = index(&ungrouped, "error").unwrap_err();
+        // 10 records of 19 bytes and 40 of 20 bytes before read0 reappears
+        assert!(
+            err.to_string().contains("Query read0 at offset 990"),
+            "{err}"
+        );
+        index(&ungrouped, "warn")?;
+        Ok(())
+    }
+
+    /// Test that indexing with --group-by-tag CB counts barcodes as query groups, so chunks hold
+    /// complete barcodes, and that --assert-query-grouped checks barcodes reappearing.
+    #[test]
+    fn test_group_by_tag() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let write_bam = |path: &PathBuf, barcodes: &[usize]| -> Result<()> {
+            let mut writer = BamWriter::from_path(path, &Header::new(), Format::Bam)?;
+            for (read, barcode) in barcodes.iter().enumerate() {
+                let mut record = BamRecord::new();
+                record.set(format!("read{read}").as_bytes(), None, b"ACGT", &[30; 4]);
+                record.set_unmapped();
+                record.push_aux(b"CB", Aux::String(&format!("CELL{barcode:03}")))?;
+                writer.write(&record)?;
+            }
+            Ok(())
+        };
+        let sorted = temp_dir.path().join("sorted.bam");
+        let barcodes: Vec<usize> = (0..40)
+            .flat_map(|barcode| [barcode].repeat(1 + barcode % 5))
+            .collect();
+        write_bam(&sorted, &barcodes)?;
+        Index::try_parse_from([
+            "index",
+            "-i",
+            sorted.to_str().unwrap(),
+            "-n",
+            "20",
+            "--group-by-tag",
+            "CB",
+            "--assert-query-grouped",
+        ])?
+        .index_reads()?;
+        let split_index = SplitIndex::read(temp_dir.path().join("sorted.bam.si"))?;
+        assert_eq!(split_index.num_queries(), 40);
+        assert_eq!(split_index.num_reads(), barcodes.len());
+
+        let mut chunk_barcodes: Vec<Vec<String>> = Vec::new();
+        for chunk_index in 0..6 {
+            let chunk = temp_dir.path().join(format!("chunk{chunk_index}.bam"));
+            GetChunk::try_parse_from([
+                "get-chunk",
+                "-i",
+                sorted.to_str().unwrap(),
+                "-o",
+                chunk.to_str().unwrap(),
+                "-c",
+                &chunk_index.to_string(),
+                "-n",
+                "6",
+            ])?
+            .execute()?;
+            let mut barcodes = Vec::new();
+            for record in BamReader::from_path(&chunk)?.records() {
+                let record = record?;
+                let Aux::String(barcode) = record.aux(b"CB")? else {
+                    panic!("CB tag is not a string");
+                };
+                barcodes.push(barcode.to_string());
+            }
+            chunk_barcodes.push(barcodes);
+        }
+        assert_eq!(chunk_barcodes.concat().len(), barcodes.len());
+        for pair in chunk_barcodes.windows(2) {
+            if let (Some(last), Some(first)) = (pair[0].last(), pair[1].first()) {
+                assert_ne!(last, first, "barcode split between chunks");
+            }
+        }
+
+        let unsorted = temp_dir.path().join("unsorted.bam");
+        write_bam(&unsorted, &[0, 0, 1, 2, 0])?;
+        let err = Index::try_parse_from([
+            "index",
+            "-i",
+            unsorted.to_str().unwrap(),
+            "--group-by",
+            "tag:CB",
+            "--assert-query-grouped",
+        ])?
+        .index_reads()
+        .unwrap_err();
+        assert!(
+            err.to_string().starts_with("Group CELL000 at offset")
+                && err.to_string().contains("not grouped by tag:CB"),
+            "{err}"
+        );
+        Ok(())
+    }
+
+    /// Test that index and get-chunk leave a heartbeat with their final status and counts.
+    #[test]
+    fn test_heartbeat() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let (random_bam, num_reads) = QueryType::Paired.random_bam(&temp_dir.path(), 1000)?;
+        let heartbeat = temp_dir.path().join("heartbeat.json");
+        let args = [
+            "--heartbeat",
+            heartbeat.to_str().unwrap(),
+            "--heartbeat-interval",
+            "0",
+        ];
+        Index::try_parse_from(
+            ["index", "-i", random_bam.to_str().unwrap()]
+                .iter()
+                .chain(&args),
+        )?
+        .index_reads()?;
+        let json = fs::read_to_string(&heartbeat)?;
+        assert!(json.starts_with("{\"status\": \"done\""), "{json}");
+        assert!(
+            json.contains(&format!("\"num_reads\": {num_reads},")),
+            "{json}"
+        );
+
+        let chunk = temp_dir.path().join("chunk.bam");
+        GetChunk::try_parse_from(
+            [
+                "get-chunk",
+                "-i",
+                random_bam.to_str().unwrap(),
+                "-o",
+                chunk.to_str().unwrap(),
+                "-c",
+                "0",
+                "-n",
+                "1",
+            ]
+            .iter()
+            .chain(&args),
+        )?
+        .execute()?;
+        let json = fs::read_to_string(&heartbeat)?;
+        assert!(json.starts_with("{\"status\": \"done\""), "{json}");
+        assert!(
+            json.contains(&format!("\"num_reads\": {num_reads},")),
+            "{json}"
+        );
+        Ok(())
+    }
+
+    /// Test that pass-through indices locate records in the output even when it is compressed
+    /// differently from the input: SAM and BAM to a recompressed BAM, and FASTQ to bgzipped FASTQ.
+    /// Also test converting BAM to bgzipped FASTQ and FASTQ to BAM while indexing.
+    #[test]
+    fn test_pass_through_offsets() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let temp_path = temp_dir.path();
+        let (random_bam, _) = QueryType::Paired.random_bam(&temp_path, 3000)?;
+        let mut reader = get_bam_reader(&random_bam, None::<PathBuf>, NonZero::new(1).unwrap())?;
+        let header = Header::from_template(reader.header());
+        let random_sam = temp_path.join("random.sam");
+        let mut sam_writer = BamWriter::from_path(&random_sam, &header, Format::Sam)?;
+        let mut truth_qnames: Vec<Vec<u8>> = Vec::new();
+        for record in reader.records() {
+            let record = record?;
+            sam_writer.write(&record)?;
+            truth_qnames.push(record.qname().to_vec());
+        }
+        drop(sam_writer);
+        let fastq = temp_path.join("reads.fastq");
+        let fastq_text: String = (0..3000)
+            .map(|read| format!("@read{read}\nACGTACGTAC\n+\nIIIIIIIIII\n"))
+            .collect();
+        fs::write(&fastq, &fastq_text)?;
+
+        let chunk_bytes =
+            |input: &PathBuf, output: &str, chunk_extension: &str| -> Result<Vec<Vec<u8>>> {
+                let output = temp_path.join(output);
+                Index::try_parse_from([
+                    "index",
+                    "-i",
+                    input.to_str().unwrap(),
+                    "-o",
+                    output.to_str().unwrap(),
+                    "-C",
+                    "9",
+                    "-n",
+                    "20",
+                ])?
+                .index_reads()?;
+                (0..7)
+                    .map(|chunk_index| {
+                        let chunk = PathBuf::from(format!(
+                            "{}.chunk{chunk_index}.{chunk_extension}",
+                            output.to_str().unwrap()
+                        ));
+                        GetChunk::try_parse_from([
+                            "get-chunk",
+                            "-i",
+                            output.to_str().unwrap(),
+                            "-o",
+                            chunk.to_str().unwrap(),
+                            "-c",
+                            &chunk_index.to_string(),
+                            "-n",
+                            "7",
+                        ])?
+                        .execute()?;
+                        Ok(chunk)
+                    })
+                    .collect::<Result<Vec<PathBuf>>>()?
+                    .iter()
+                    .map(|chunk| {
+                        if chunk_extension == "bam" {
+                            let mut reader =
+                                get_bam_reader(chunk, None::<PathBuf>, NonZero::new(1).unwrap())?;
+                            reader
+                                .records()
+                                .map(|record| Ok(record?.qname().to_vec()))
+                                .collect::<Result<Vec<Vec<u8>>>>()
+                        } else {
+                            Ok(vec![fs::read(chunk)?])
+                        }
+                    })
+                    .collect::<Result<Vec<Vec<Vec<u8>>>>>()
+                    .map(|chunks| chunks.concat())
+            };
+        assert_eq!(
+            chunk_bytes(&random_bam, "from-bam.bam", "bam")?,
+            truth_qnames
+        );
+        assert_eq!(
+            chunk_bytes(&random_sam, "from-sam.bam", "bam")?,
+            truth_qnames
+        );
+        assert_eq!(
+            chunk_bytes(&fastq, "from-fastq.fastq.gz", "fastq")?.concat(),
+            fastq_text.as_bytes()
+        );
+
+        // converted output, with every record in exactly one chunk
+        let converted_fastq = chunk_bytes(&random_bam, "converted.fastq.gz", "fastq")?.concat();
+        let mut decompressed = Vec::new();
+        MaybeCompressedReader::new(
+            temp_path.join("converted.fastq.gz"),
+            NonZero::new(1).unwrap(),
+        )?
+        .read_to_end(&mut decompressed)?;
+        assert_eq!(converted_fastq, decompressed);
+        let converted_qnames: Vec<Vec<u8>> = decompressed
+            .split(|byte| *byte == b'\n')
+            .step_by(4)
+            .filter(|line| !line.is_empty())
+            .map(|line| line[1..].to_vec())
+            .collect();
+        assert_eq!(converted_qnames, truth_qnames);
+        let fastq_qnames: Vec<Vec<u8>> = (0..3000)
+            .map(|read| format!("read{read}").into_bytes())
+            .collect();
+        assert_eq!(chunk_bytes(&fastq, "converted.bam", "bam")?, fastq_qnames);
+
+        // converting FASTQ to SAM or CRAM can't locate records
+        let index_to = |input: &PathBuf, output: &str| {
+            Index::try_parse_from([
+                "index",
+                "-i",
+                input.to_str().unwrap(),
+                "-o",
+                temp_path.join(output).to_str().unwrap(),
+            ])?
+            .index_reads()
+        };
+        assert!(index_to(&fastq, "converted.sam").is_err());
+        // nor can converting BAM to SAM or CRAM, whose indices would keep the BAM's offsets
+        for (input, output) in [
+            (&random_bam, "converted.sam"),
+            (&random_sam, "converted.cram"),
+        ] {
+            let err = index_to(input, output).unwrap_err();
+            assert!(
+                err.to_string().contains("holds the input's offsets"),
+                "{err}"
+            );
+        }
+        let paired_fastq = temp_path.join("paired.fastq");
+        fs::write(
+            &paired_fastq,
+            "@read0/1\nACGT\n+\nIIII\n@read0/2\nACGT\n+\nIIII\n",
+        )?;
+        // removing mate suffixes keeps each pair in one query group
+        index_to(&paired_fastq, "paired.bam")?;
+        let mut reader = get_bam_reader(
+            temp_path.join("paired.bam"),
+            None::<PathBuf>,
+            NonZero::new(1).unwrap(),
+        )?;
+        let qnames = reader
+            .records()
+            .map(|record| Ok(record?.qname().to_vec()))
+            .collect::<Result<Vec<Vec<u8>>>>()?;
+        assert_eq!(qnames, [b"read0", b"read0"]);
+        Ok(())
+    }
+
+    /// Test that plain-text SAM is indexed by byte offsets, and that its chunks hold every record
+    /// exactly once, in order.
+    #[test]
+    fn test_plain_sam() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let temp_path = temp_dir.path();
+        let (random_bam, _) = QueryType::Paired.random_bam(&temp_path, 2000)?;
+        let mut reader = get_bam_reader(&random_bam, None::<PathBuf>, NonZero::new(1).unwrap())?;
+        let header = Header::from_template(reader.header());
+        let random_sam = temp_path.join("random.sam");
+        let mut sam_writer = BamWriter::from_path(&random_sam, &header, Format::Sam)?;
+        let mut truth_qnames: Vec<Vec<u8>> = Vec::new();
+        for record in reader.records() {
+            let record = record?;
+            sam_writer.write(&record)?;
+            truth_qnames.push(record.qname().to_vec());
+        }
+        drop(sam_writer);
+
+        Index::try_parse_from(["index", "-i", random_sam.to_str().unwrap(), "-n", "20"])?
+            .index_reads()?;
+        let split_index = SplitIndex::read(temp_path.join("random.sam.si"))?;
+        assert_eq!(split_index.offset_kind(), Some(OffsetKind::Byte));
+        assert_eq!(split_index.num_reads(), truth_qnames.len());
+
+        let mut chunk_qnames = Vec::new();
+        for chunk_index in 0..5 {
+            let chunk = temp_path.join(format!("chunk{chunk_index}.bam"));
+            GetChunk::try_parse_from([
+                "get-chunk",
+                "-i",
+                random_sam.to_str().unwrap(),
+                "-o",
+                chunk.to_str().unwrap(),
+                "-c",
+                &chunk_index.to_string(),
+                "-n",
+                "5",
+            ])?
+            .execute()?;
+            let mut reader = get_bam_reader(&chunk, None::<PathBuf>, NonZero::new(1).unwrap())?;
+            for record in reader.records() {
+                chunk_qnames.push(record?.qname().to_vec());
+            }
+        }
+        assert_eq!(chunk_qnames, truth_qnames);
+        Ok(())
+    }
+
+    /// Test that indexing a BAM in parallel partitions counts the same reads, queries, and read
+    /// groups as indexing it in one pass.
+    #[rstest(query_type => [QueryType::Single, QueryType::Paired, QueryType::Grouped])]
+    fn test_parallel_partitions(query_type: QueryType) -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let (random_bam, num_reads) = query_type.random_bam(&temp_dir.path(), 20000)?;
+        let bam_str = random_bam.to_str().unwrap();
+        let sequential = SplitIndex::read(
+            Index::try_parse_from(["index", "-i", bam_str, "-t", "1", "-n", "100"])?
+                .index_reads()?,
+        )?;
+        let parallel_index = temp_dir.path().join("parallel.bam.si");
+        let parallel = SplitIndex::read(
+            Index::try_parse_from([
+                "index",
+                "-i",
+                bam_str,
+                "-I",
+                parallel_index.to_str().unwrap(),
+                "-n",
+                "100",
+                "--partitions",
+                "4",
+            ])?
+            .index_reads()?,
+        )?;
+        assert_eq!(parallel.num_reads(), num_reads);
+        assert_eq!(parallel.num_queries(), sequential.num_queries());
+        assert_eq!(parallel.read_groups(), sequential.read_groups());
+        Ok(())
+    }
+
+    /// Test that a BAM streamed through a TeeDownload indexes the same as its local copy, and
+    /// that --localize rejects local inputs.
+    #[cfg(unix)]
+    #[test]
+    fn test_localize_stream() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let (random_bam, _) = QueryType::Paired.random_bam(&temp_dir.path(), 2000)?;
+        let local_copy = temp_dir.path().join("local.bam");
+        let num_bins = NonZero::new(20).unwrap();
+        let threads = NonZero::new(1).unwrap();
+
+        let mut download = TeeDownload::start(random_bam.to_str().unwrap(), &local_copy)?;
+        let reader = get_bam_reader(download.stream_path(), None::<PathBuf>, threads)?;
+        download.release_stream();
+        let streamed = SplitIndex::build(reader, None::<BamWriter>, num_bins, u64::MAX)?;
+        download.finish()?;
+        assert_eq!(fs::read(&local_copy)?, fs::read(&random_bam)?);
+        let reader = get_bam_reader(&local_copy, None::<PathBuf>, threads)?;
+        let local = SplitIndex::build(reader, None::<BamWriter>, num_bins, u64::MAX)?;
+        assert!(streamed == local);
+
+        let localize = Index::try_parse_from([
+            "index",
+            "-i",
+            random_bam.to_str().unwrap(),
+            "--localize",
+            local_copy.to_str().unwrap(),
+        ])?;
+        assert!(localize.index_reads().is_err());
+        Ok(())
+    }
+
+    /// Test that indexing a remote BAM uploads its default index next to it, never touching local
+    /// disk.
+    #[cfg(feature = "cloud-output")]
+    #[test]
+    fn test_remote_index_output() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let (random_bam, _) = QueryType::Paired.random_bam(&temp_dir.path(), 500)?;
+        let local_index =
+            Index::try_parse_from(["index", "-i", random_bam.to_str().unwrap(), "-n", "4"])?
+                .index_reads()?;
+        let served = temp_dir.path().join("served");
+        fs::create_dir(&served)?;
+        fs::copy(&random_bam, served.join("remote.bam"))?;
+        let server = TestHttpServer::serve(&served)?;
+
+        let remote_bam = server.url("remote.bam");
+        let index_path =
+            Index::try_parse_from(["index", "-i", &remote_bam, "-n", "4"])?.index_reads()?;
+        assert_eq!(index_path, PathBuf::from(server.url("remote.bam.si")));
+        // a remote input can't be fingerprinted, but the bins match the local input's
+        let mut expected = SplitIndex::read(&local_index)?;
+        expected.set_fingerprint(None);
+        assert!(SplitIndex::read(served.join("remote.bam.si"))? == expected);
+        Ok(())
+    }
+
+    /// Test building an approximate index of a coordinate-sorted BAM from its BAI, and that chunks
+    /// extracted with it hold every read once, in order, including the unplaced reads at the end.
+    #[test]
+    fn test_from_bai() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let bam = temp_dir.path().join("sorted.bam");
+        let bam_str = bam.to_str().unwrap();
+        let mut header = Header::new();
+        header.push_record(
+            HeaderRecord::new(b"HD")
+                .push_tag(b"VN", "1.6")
+                .push_tag(b"SO", "coordinate"),
+        );
+        for (name, length) in [("chr1", 200000), ("chr2", 100000)] {
+            header.push_record(
+                HeaderRecord::new(b"SQ")
+                    .push_tag(b"SN", name)
+                    .push_tag(b"LN", length),
+            );
+        }
+        let mut writer = BamWriter::from_path(&bam, &header, Format::Bam)?;
+        let cigar = CigarString(vec![Cigar::Match(50)]);
+        let mut record = BamRecord::new();
+        let mut truth_qnames: Vec<Vec<u8>> = Vec::new();
+        for read in 0..3050 {
+            let qname = format!("read{read:05}");
+            if read < 3000 {
+                record.set(qname.as_bytes(), Some(&cigar), &[b'A'; 50], &[30; 50]);
+                record.set_tid(if read < 2000 { 0 } else { 1 });
+                record.set_pos((read % 2000) * 100);
+            } else {
+                record.set(qname.as_bytes(), None, &[b'A'; 50], &[30; 50]);
+                record.set_tid(-1);
+                record.set_pos(-1);
+                record.set_unmapped();
+            }
+            record.set_mtid(-1);
+            record.set_mpos(-1);
+            writer.write(&record)?;
+            truth_qnames.push(qname.into_bytes());
+        }
+        drop(writer);
+        bam_index::build(&bam, None, bam_index::Type::Bai, 1)?;
+
+        assert!(Index::try_parse_from(["index", "-i", bam_str, "--from-bai"]).is_err());
+        Index::try_parse_from([
+            "index",
+            "-i",
+            bam_str,
+            "--from-bai",
+            "--allow-split-pairs",
+            "-n",
+            "20",
+        ])?
+        .index_reads()?;
+        let split_index = SplitIndex::read(format!("{bam_str}.si"))?;
+        assert!(split_index.is_approximate());
+        assert_eq!(split_index.num_reads(), 3050);
+        assert!(split_index.len() > 3, "{} bins", split_index.len());
+
+        let mut qnames: Vec<Vec<u8>> = Vec::new();
+        for chunk_index in 0..3 {
+            let chunk = temp_dir.path().join(format!("chunk{chunk_index}.bam"));
+            GetChunk::try_parse_from([
+                "get-chunk",
+                "-i",
+                bam_str,
+                "-c",
+                &chunk_index.to_string(),
+                "-n",
+                "3",
+                "-o",
+                chunk.to_str().unwrap(),
+            ])?
+            .execute()?;
+            let num_qnames = qnames.len();
+            for record in BamReader::from_path(&chunk)?.records() {
+                qnames.push(record?.qname().to_vec());
+            }
+            assert!(qnames.len() > num_qnames, "chunk {chunk_index} is empty");
+        }
+        assert!(qnames == truth_qnames);
         Ok(())
     }
 }
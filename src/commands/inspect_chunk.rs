@@ -0,0 +1,336 @@
+use crate::commands::command::Command;
+use anyhow::{Result, anyhow};
+use clap::Parser;
+use log::info;
+use split_reads::{
+    chunkable::{ChunkableRecord, ChunkableRecordReader},
+    manifest::json_string,
+    query_grouping::{GroupingCheck, QueryGroupingChecker},
+    thread_budget::{READING_POOLS, Threads},
+    util::{RecordType, get_bam_reader, get_fastq_reader},
+};
+use std::{num::NonZero, path::PathBuf};
+
+#[derive(clap::ValueEnum, Clone, Copy, Default, Debug, PartialEq)]
+pub enum InspectFormat {
+    #[default]
+    Json,
+    Tsv,
+}
+
+/// SAM flags of a read that is paired, first of its pair, and last of its pair
+const FLAG_PAIRED: u16 = 0x1;
+const FLAG_FIRST: u16 = 0x40;
+const FLAG_LAST: u16 = 0x80;
+
+/// SAM flags of secondary and supplementary alignments, which don't count towards complete pairs
+const FLAG_NOT_PRIMARY: u16 = 0x100 | 0x800;
+
+/// Summary of an extracted chunk, for checking that it is a whole number of intact query groups.
+#[derive(Clone, Debug, Default, PartialEq)]
+struct ChunkInspection {
+    num_reads: usize,
+    num_queries: usize,
+    num_bases: u64,
+    first_qname: Option<Vec<u8>>,
+    last_qname: Option<Vec<u8>>,
+    /// Query groups whose query name reappears after other query groups
+    num_regrouped: usize,
+    /// Query groups of paired reads missing their first or last primary mate
+    num_incomplete_pairs: usize,
+    /// Flags seen so far in the current query group, OR-ed over its primary reads
+    query_flags: u16,
+}
+
+impl ChunkInspection {
+    /// Add a record. Records of a query group must be added together, then finish_query called.
+    fn add<R>(&mut self, record: &R)
+    where
+        R: ChunkableRecord,
+    {
+        if self.last_qname.as_deref() != Some(record.qname()) {
+            self.finish_query();
+            self.num_queries += 1;
+            if self.first_qname.is_none() {
+                self.first_qname = Some(record.qname().to_vec());
+            }
+            self.last_qname = Some(record.qname().to_vec());
+        }
+        self.num_reads += 1;
+        self.num_bases += record.seq_len() as u64;
+        if let Some(flags) = record.flags()
+            && flags & FLAG_NOT_PRIMARY == 0
+        {
+            self.query_flags |= flags;
+        }
+    }
+
+    /// Finish the current query group, counting it if it is an incomplete pair.
+    fn finish_query(&mut self) {
+        let both_mates = FLAG_FIRST | FLAG_LAST;
+        if self.query_flags & FLAG_PAIRED != 0 && self.query_flags & both_mates != both_mates {
+            self.num_incomplete_pairs += 1;
+        }
+        self.query_flags = 0;
+    }
+
+    /// Mean read length, if there are reads
+    fn mean_length(&self) -> Option<f64> {
+        (self.num_reads > 0).then(|| self.num_bases as f64 / self.num_reads as f64)
+    }
+
+    /// True if every query group is whole: none reappears, and no pair is missing a mate
+    fn is_intact(&self) -> bool {
+        self.num_regrouped == 0 && self.num_incomplete_pairs == 0
+    }
+
+    /// Get the names and values of each statistic, with missing values as None. Query names are
+    /// raw (lossily decoded as UTF-8), to be JSON-quoted by to_json.
+    fn fields(&self) -> [(&'static str, Option<String>); 8] {
+        let text = |qname: &Option<Vec<u8>>| {
+            qname
+                .as_ref()
+                .map(|qname| String::from_utf8_lossy(qname).into_owned())
+        };
+        [
+            ("num_reads", Some(self.num_reads.to_string())),
+            ("num_queries", Some(self.num_queries.to_string())),
+            ("first_qname", text(&self.first_qname)),
+            ("last_qname", text(&self.last_qname)),
+            (
+                "mean_length",
+                self.mean_length().map(|mean| mean.to_string()),
+            ),
+            ("regrouped_queries", Some(self.num_regrouped.to_string())),
+            (
+                "incomplete_pairs",
+                Some(self.num_incomplete_pairs.to_string()),
+            ),
+            ("intact", Some(self.is_intact().to_string())),
+        ]
+    }
+
+    /// Format as a JSON object, quoting the query names
+    fn to_json(&self) -> String {
+        let fields: Vec<String> = self
+            .fields()
+            .into_iter()
+            .map(|(name, value)| {
+                let value = match value {
+                    Some(qname) if name.ends_with("_qname") => json_string(&qname),
+                    Some(value) => value,
+                    None => "null".to_string(),
+                };
+                format!("\"{name}\": {value}")
+            })
+            .collect();
+        format!("{{{}}}", fields.join(", "))
+    }
+
+    /// Format as TSV lines of name and value
+    fn to_tsv(&self) -> String {
+        let lines: Vec<String> = self
+            .fields()
+            .into_iter()
+            .map(|(name, value)| format!("{name}\t{}", value.as_deref().unwrap_or("NA")))
+            .collect();
+        lines.join("\n")
+    }
+}
+
+/// Summarize an extracted chunk (FASTQ or SAM/BAM/CRAM): its numbers of reads and query groups,
+/// first and last query names, mean read length, and whether its query groups are intact, i.e.
+/// no query name reappears after other query groups and no read pair is missing a mate. A quick
+/// QC step when downstream failures may come from bad shards.
+#[derive(Parser, Debug)]
+#[command(version, verbatim_doc_comment)]
+pub(crate) struct InspectChunk {
+    /// Chunk file to inspect.
+    #[clap(long, short = 'i', required = true)]
+    input: PathBuf,
+
+    /// Reference FASTA (required for CRAMs)
+    #[clap(long, short = 'R', required = false, default_value = None)]
+    ref_fasta: Option<PathBuf>,
+
+    /// Format to print the summary in.
+    #[clap(long, short = 'f', required = false, default_value_t, value_enum)]
+    format: InspectFormat,
+
+    /// Memory in MiB for remembering query names, to find reappearing ones. Rarely, a query is
+    /// reported because it collides with remembered names: about 3 bytes per query group makes
+    /// that about a 1 in 70000 chance per query group.
+    #[clap(long, required = false, default_value_t = NonZero::new(64usize).unwrap())]
+    grouping_filter_mib: NonZero<usize>,
+
+    /// Number of threads to use for reading. Use "auto" to use every CPU available
+    /// to this process (after CPU affinity and cgroup limits). Defaults to all of them.
+    #[clap(long, short = 't', required = false)]
+    threads: Option<Threads>,
+}
+
+impl InspectChunk {
+    /// Get the number of threads for reading.
+    fn threads(&self) -> NonZero<usize> {
+        Threads::per_pool(self.threads, READING_POOLS)
+    }
+
+    /// Read every record of the chunk and summarize it.
+    fn inspect_with<R, Reader>(&self, reader: &mut Reader) -> Result<ChunkInspection>
+    where
+        R: ChunkableRecord,
+        Reader: ChunkableRecordReader<R>,
+    {
+        let mut inspection = ChunkInspection::default();
+        let mut grouping_checker =
+            QueryGroupingChecker::new(GroupingCheck::Warn, self.grouping_filter_mib.get() << 20);
+        let mut record = R::new();
+        while let Some(result) = reader.read_into(&mut record) {
+            result?;
+            grouping_checker.check(record.qname(), None)?;
+            inspection.add(&record);
+        }
+        inspection.finish_query();
+        inspection.num_regrouped = grouping_checker.num_regrouped();
+        Ok(inspection)
+    }
+
+    /// Open the reader for the input record type, and summarize the chunk.
+    fn inspect(&self) -> Result<ChunkInspection> {
+        let input_record_type = RecordType::detect(&self.input)?.ok_or_else(|| {
+            anyhow!("Input type must be FASTQ or SAM/BAM/CRAM. Cannot read from stdin.")
+        })?;
+        if input_record_type.is_sam_family() {
+            let mut reader =
+                get_bam_reader(self.input.clone(), self.ref_fasta.clone(), self.threads())?;
+            self.inspect_with(&mut reader)
+        } else {
+            let mut reader = get_fastq_reader(self.input.clone(), self.threads())?;
+            self.inspect_with(&mut reader)
+        }
+    }
+}
+
+/// Implement the Command trait for `InspectChunk` struct.
+impl Command for InspectChunk {
+    /// Execute the inspect-chunk command to print the chunk summary.
+    fn execute(&self) -> Result<()> {
+        Threads::warn_if_oversubscribed(self.threads, READING_POOLS);
+        info!("Using {} thread(s)", self.threads());
+        let inspection = self.inspect()?;
+        match self.format {
+            InspectFormat::Json => println!("{}", inspection.to_json()),
+            InspectFormat::Tsv => println!("{}", inspection.to_tsv()),
+        }
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "synthetic"))]
+mod tests {
+    use super::{ChunkInspection, InspectChunk};
+    use crate::{
+        commands::{command::Command, get_chunk::GetChunk, index::Index},
+        test_utils::random_bam::{QueryType, RandomBam},
+    };
+    use anyhow::Result;
+    use clap::Parser;
+    use rstest::rstest;
+    use rust_htslib::bam::{Format, Header, Read, Reader, Record, Writer};
+    use tempfile::TempDir;
+
+    /// Test that extracted chunks are intact, and that chunks with a missing mate or a split
+    /// query group are not.
+    #[rstest(query_type => [QueryType::Single, QueryType::Paired, QueryType::Grouped])]
+    fn test_inspect_chunk(query_type: QueryType) -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let (random_bam, _) = query_type.random_bam(&temp_dir.path(), 100)?;
+        let bam_str = random_bam.to_str().unwrap();
+        Index::try_parse_from(["index", "-i", bam_str, "-n", "10"])?.index_reads()?;
+        let chunk = temp_dir.path().join("chunk.bam");
+        let chunk_str = chunk.to_str().unwrap();
+        GetChunk::try_parse_from([
+            "get-chunk",
+            "-i",
+            bam_str,
+            "-c",
+            "1",
+            "-n",
+            "3",
+            "-o",
+            chunk_str,
+        ])?
+        .execute()?;
+        let records: Vec<Record> = Reader::from_path(&chunk)?
+            .records()
+            .collect::<Result<_, _>>()?;
+
+        let inspection =
+            InspectChunk::try_parse_from(["inspect-chunk", "-i", chunk_str])?.inspect()?;
+        assert_eq!(inspection.num_reads, records.len());
+        assert_eq!(inspection.first_qname.as_deref(), Some(records[0].qname()));
+        assert_eq!(
+            inspection.last_qname.as_deref(),
+            Some(records.last().unwrap().qname())
+        );
+        assert_eq!(
+            inspection.mean_length(),
+            Some(
+                records.iter().map(|record| record.seq_len()).sum::<usize>() as f64
+                    / records.len() as f64
+            )
+        );
+        assert!(inspection.is_intact());
+        assert!(inspection.to_json().contains("\"intact\": true"));
+
+        let header = Header::from_template(Reader::from_path(&chunk)?.header());
+        let inspect_records = |records: &[&Record]| -> Result<_> {
+            let broken = temp_dir.path().join("broken.bam");
+            let mut writer = Writer::from_path(&broken, &header, Format::Bam)?;
+            for &record in records {
+                writer.write(record)?;
+            }
+            drop(writer);
+            InspectChunk::try_parse_from(["inspect-chunk", "-i", broken.to_str().unwrap()])?
+                .inspect()
+        };
+
+        // a read of the first query group reappears at the end
+        let mut regrouped: Vec<&Record> = records.iter().collect();
+        regrouped.push(&records[0]);
+        let inspection = inspect_records(&regrouped)?;
+        assert_eq!(inspection.num_regrouped, 1);
+        assert!(!inspection.is_intact());
+        assert!(inspection.to_tsv().contains("intact\tfalse"));
+
+        if query_type == QueryType::Paired {
+            // the first pair is missing a mate
+            let inspection = inspect_records(&records.iter().skip(1).collect::<Vec<_>>())?;
+            assert_eq!(inspection.num_incomplete_pairs, 1);
+            assert!(!inspection.is_intact());
+        }
+        Ok(())
+    }
+
+    /// Test that query names are JSON-escaped in JSON output, and written as they are in TSV.
+    #[test]
+    fn test_inspection_formats() {
+        let qname = "read\"1\\é".as_bytes().to_vec();
+        let inspection = ChunkInspection {
+            num_reads: 1,
+            num_queries: 1,
+            first_qname: Some(qname.clone()),
+            last_qname: Some(qname),
+            ..Default::default()
+        };
+        let json = inspection.to_json();
+        assert!(
+            json.contains(r#""first_qname": "read\"1\\é", "last_qname""#),
+            "{json}"
+        );
+        assert!(json.contains("\"mean_length\": 0, "));
+        let tsv = inspection.to_tsv();
+        assert!(tsv.contains("first_qname\tread\"1\\é\n"), "{tsv}");
+        assert!(tsv.contains("mean_length\t0\n"));
+    }
+}
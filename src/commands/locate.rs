@@ -0,0 +1,185 @@
+use crate::commands::{command::Command, get_chunk::GetChunk};
+use anyhow::{Result, anyhow};
+use clap::Parser;
+use split_reads::{
+    chunkable::{ChunkableRecord, ChunkableRecordReader, Location},
+    split_index::SplitIndex,
+    thread_budget::{READING_POOLS, Threads},
+    util::{RecordType, get_fastq_reader, get_sam_reader},
+};
+use std::{num::NonZero, path::PathBuf};
+
+/// Print the location of a query group or read, found by its 0-based index in the reads file.
+/// Prints tab-separated qname, query index, read index, and file offset (a virtual offset for
+/// compressed files).
+#[derive(Parser, Debug)]
+#[command(version, verbatim_doc_comment)]
+pub(crate) struct Locate {
+    /// Input FASTQ or SAM/BAM/CRAM to search. Cannot read from stdin, because it is not seekable.
+    #[clap(long, short = 'i', required = true)]
+    input: PathBuf,
+
+    /// Index for input reads file, built by split-reads index. Defaults to input path with extra
+    /// ".si" extension.
+    #[clap(long, short = 'I', required = false, default_value = None)]
+    index: Option<PathBuf>,
+
+    /// Reference FASTA (required for CRAMs)
+    #[clap(long, short = 'R', required = false, default_value = None)]
+    ref_fasta: Option<PathBuf>,
+
+    /// 0-based index of the query group to locate. Prints its first read.
+    #[clap(
+        long,
+        short = 'q',
+        required_unless_present_any = ["read_index", "qname"],
+        conflicts_with_all = ["read_index", "qname"]
+    )]
+    query_index: Option<usize>,
+
+    /// 0-based index of the read to locate.
+    #[clap(long, short = 'r', required = false, default_value = None, conflicts_with = "qname")]
+    read_index: Option<usize>,
+
+    /// Query name to locate. Prints its first read. Requires an index built with --qnames on a
+    /// file sorted by query name.
+    #[clap(long, short = 'n', required = false, default_value = None)]
+    qname: Option<String>,
+
+    /// Number of threads to use for reading. Use "auto" to use every CPU available
+    /// to this process (after CPU affinity and cgroup limits). Defaults to all of them.
+    #[clap(long, short = 't', required = false)]
+    threads: Option<Threads>,
+}
+
+impl Locate {
+    /// Get the number of threads for reading.
+    fn threads(&self) -> NonZero<usize> {
+        Threads::per_pool(self.threads, READING_POOLS)
+    }
+
+    /// Find the requested query group or read with the supplied reader.
+    fn locate_with<R, Reader>(
+        &self,
+        reader: &mut Reader,
+        split_index: SplitIndex,
+    ) -> Result<Location>
+    where
+        R: ChunkableRecord,
+        Reader: ChunkableRecordReader<R>,
+    {
+        match (self.query_index, self.read_index, self.qname.as_ref()) {
+            (Some(query_index), _, _) => reader.locate_query(split_index, query_index),
+            (None, Some(read_index), _) => reader.locate_read(split_index, read_index),
+            (None, None, Some(qname)) => {
+                let split_range = split_index
+                    .find_query(qname.as_bytes())?
+                    .ok_or_else(|| anyhow!("Query {qname} is not in the index."))?;
+                reader
+                    .locate_qname(&split_range, qname.as_bytes())
+                    .map_err(|_| anyhow!("Query {qname} is not in the reads file."))
+            }
+            (None, None, None) => Err(anyhow!(
+                "Must specify --query-index, --read-index, or --qname."
+            )),
+        }
+    }
+
+    /// Load the index, then find the requested query group or read.
+    fn locate(&self) -> Result<Location> {
+        let split_index =
+            GetChunk::load_split_index(self.index.clone(), self.input.clone(), false)?;
+        let record_type = RecordType::detect(&self.input)?.ok_or_else(|| {
+            anyhow!("Input type must be FASTQ or SAM/BAM/CRAM. Cannot read from stdin.")
+        })?;
+        if record_type.is_sam_family() {
+            let mut reader =
+                get_sam_reader(self.input.clone(), self.ref_fasta.clone(), self.threads())?;
+            self.locate_with(&mut reader, split_index)
+        } else {
+            let mut reader = get_fastq_reader(self.input.clone(), self.threads())?;
+            self.locate_with(&mut reader, split_index)
+        }
+    }
+}
+
+/// Implement the Command trait for `Locate` struct.
+impl Command for Locate {
+    /// Execute the locate command to print the location of a query group or read.
+    fn execute(&self) -> Result<()> {
+        Threads::warn_if_oversubscribed(self.threads, READING_POOLS);
+        let location = self.locate()?;
+        println!(
+            "{}\t{}\t{}\t{}",
+            String::from_utf8_lossy(&location.qname),
+            location.query_index,
+            location.read_index,
+            location.offset
+        );
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "synthetic"))]
+mod tests {
+    use super::Locate;
+    use crate::{
+        commands::index::Index,
+        test_utils::random_bam::{QueryType, RandomBam},
+    };
+    use anyhow::Result;
+    use clap::Parser;
+    use rstest::rstest;
+    use rust_htslib::bam::{Read, Reader, Record};
+    use std::path::PathBuf;
+    use tempfile::TempDir;
+
+    /// Test that every query group and read is located with the correct qname and indices, by index
+    /// and by query name.
+    #[rstest(query_type => [QueryType::Single, QueryType::Paired, QueryType::Grouped])]
+    fn test_locate(query_type: QueryType) -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let temp_path: PathBuf = temp_dir.path().to_path_buf();
+        let (random_bam, _) = query_type.random_bam(&temp_path, 50)?;
+        let bam_str = random_bam.to_str().unwrap();
+        Index::try_parse_from(["index", "--input", bam_str, "--num-bins", "7", "--qnames"])?
+            .index_reads()?;
+
+        let truth_records = Reader::from_path(&random_bam)?
+            .records()
+            .collect::<Result<Vec<Record>, _>>()?;
+        let mut query_index: usize = 0;
+        for (read_index, record) in truth_records.iter().enumerate() {
+            if read_index > 0 && record.qname() != truth_records[read_index - 1].qname() {
+                query_index += 1;
+            }
+            let read_index_str = read_index.to_string();
+            let location =
+                Locate::try_parse_from(["locate", "-i", bam_str, "-r", &read_index_str])?
+                    .locate()?;
+            assert_eq!(location.qname, record.qname());
+            assert_eq!(location.read_index, read_index);
+            assert_eq!(location.query_index, query_index);
+
+            let query_index_str = query_index.to_string();
+            let location =
+                Locate::try_parse_from(["locate", "-i", bam_str, "-q", &query_index_str])?
+                    .locate()?;
+            assert_eq!(location.qname, record.qname());
+            assert_eq!(location.query_index, query_index);
+            assert!(location.read_index <= read_index);
+
+            let qname = String::from_utf8(record.qname().to_vec())?;
+            let by_qname =
+                Locate::try_parse_from(["locate", "-i", bam_str, "-n", &qname])?.locate()?;
+            assert_eq!(by_qname, location);
+        }
+
+        let missing = Locate::try_parse_from(["locate", "-i", bam_str, "-n", "missing"])?;
+        assert!(missing.locate().is_err());
+
+        let past_end = Locate::try_parse_from(["locate", "-i", bam_str, "-q", "50"])?;
+        assert!(past_end.locate().is_err());
+        Ok(())
+    }
+}
@@ -1,6 +1,21 @@
+pub mod batch;
+#[cfg(feature = "synthetic")]
+pub mod bench;
+pub mod cat;
+pub mod collate;
 pub mod command;
 pub mod get_chunk;
+pub mod get_queries;
 pub mod index;
+pub mod inspect_chunk;
+pub mod locate;
+pub mod plan;
+pub mod prune;
+pub mod stats;
+pub mod stream_split;
+pub mod subsample;
 pub mod tell;
 pub mod test_fastq;
 pub mod test_seq_io;
+pub mod transform_args;
+pub mod verify_chunk;
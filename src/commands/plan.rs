@@ -0,0 +1,181 @@
+use crate::commands::{
+    command::Command,
+    tell::{ChunkPlan, num_chunks_for_size},
+};
+use anyhow::{Result, anyhow};
+use clap::Parser;
+use log::info;
+use split_reads::split_index::SplitIndex;
+use std::{fs, io::Write, num::NonZero, path::PathBuf};
+
+#[derive(clap::ValueEnum, Clone, Copy, Default, Debug, PartialEq)]
+pub enum PlanFormat {
+    /// One line of get-chunk arguments per chunk: "--chunk-index i --num-chunks n"
+    #[default]
+    Args,
+    /// JSON array with an object per chunk: its index, query range, approximate reads, and
+    /// get-chunk arguments
+    Json,
+}
+
+/// Write the get-chunk arguments of every chunk of a split into a file, for workflow scatter
+/// blocks (e.g. WDL `read_lines` or `read_json`, Nextflow `splitText`) to consume directly. The
+/// number of chunks is given, or chosen for a target chunk size as `tell --suggest-chunks` does.
+#[derive(Parser, Debug)]
+#[command(version, verbatim_doc_comment)]
+pub(crate) struct Plan {
+    /// Input path for Index file. Use "-" for stdin.
+    #[clap(long, short = 'I', required = true)]
+    index: PathBuf,
+
+    /// Number of chunks to split into.
+    #[clap(long, short = 'n', required = false, default_value = None, conflicts_with_all = ["queries_per_chunk", "bytes_per_chunk"])]
+    num_chunks: Option<NonZero<usize>>,
+
+    /// Target number of query groups per chunk, instead of --num-chunks.
+    #[clap(long, required = false, default_value = None, conflicts_with = "bytes_per_chunk")]
+    queries_per_chunk: Option<NonZero<usize>>,
+
+    /// Target number of bytes of the indexed file per chunk, instead of --num-chunks. Requires an
+    /// index that recorded the file size.
+    #[clap(long, required = false, default_value = None)]
+    bytes_per_chunk: Option<NonZero<u64>>,
+
+    /// Format of the argument file.
+    #[clap(long, short = 'f', required = false, default_value_t, value_enum)]
+    format: PlanFormat,
+
+    /// Output path for the argument file. Use "-" (or omit) for stdout.
+    #[clap(long, short = 'o', required = false, default_value = "-")]
+    output: PathBuf,
+}
+
+impl Plan {
+    /// Get the number of chunks, given or chosen for the target chunk size.
+    fn num_chunks(&self, split_index: &SplitIndex) -> Result<NonZero<usize>> {
+        match self.num_chunks {
+            Some(num_chunks) => Ok(num_chunks),
+            None => num_chunks_for_size(split_index, self.queries_per_chunk, self.bytes_per_chunk)?
+                .ok_or_else(|| {
+                    anyhow!("Requires --num-chunks, --queries-per-chunk, or --bytes-per-chunk.")
+                }),
+        }
+    }
+
+    /// Get the text of the argument file for the split-index.
+    fn plan(&self, split_index: &SplitIndex) -> Result<String> {
+        let num_chunks = self.num_chunks(split_index)?;
+        info!("Planning {num_chunks} chunks.");
+        let args =
+            |chunk_index: usize| format!("--chunk-index {chunk_index} --num-chunks {num_chunks}");
+        let chunk_plans = ChunkPlan::plan_chunks(split_index, num_chunks)?;
+        Ok(match self.format {
+            PlanFormat::Args => chunk_plans
+                .iter()
+                .map(|chunk_plan| format!("{}\n", args(chunk_plan.chunk_index)))
+                .collect(),
+            PlanFormat::Json => {
+                let chunks: Vec<String> = chunk_plans
+                    .iter()
+                    .map(|chunk_plan| {
+                        format!(
+                            "{{\"chunk_index\": {}, \"num_chunks\": {num_chunks}, \
+                            \"start_query\": {}, \"stop_query\": {}, \"approx_reads\": {}, \
+                            \"args\": \"{}\"}}",
+                            chunk_plan.chunk_index,
+                            chunk_plan.start_query,
+                            chunk_plan.stop_query,
+                            chunk_plan.approx_reads,
+                            args(chunk_plan.chunk_index)
+                        )
+                    })
+                    .collect();
+                format!("[{}]\n", chunks.join(", "))
+            }
+        })
+    }
+}
+
+/// Implement the Command trait for `Plan` struct.
+impl Command for Plan {
+    /// Execute the plan command to write the argument file.
+    fn execute(&self) -> Result<()> {
+        let split_index = SplitIndex::read(&self.index)?;
+        let plan = self.plan(&split_index)?;
+        if self.output.as_os_str() == "-" {
+            std::io::stdout().lock().write_all(plan.as_bytes())?;
+        } else {
+            fs::write(&self.output, plan)
+                .map_err(|err| anyhow!("Writing {:?}: {err}", self.output))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "synthetic"))]
+mod tests {
+    use super::Plan;
+    use crate::commands::{command::Command, index::Index};
+    use crate::test_utils::random_bam::{QueryType, RandomBam};
+    use anyhow::Result;
+    use clap::Parser;
+    use rstest::rstest;
+    use std::fs;
+    use tempfile::TempDir;
+
+    /// Test that argument files have a line or object per chunk, by chunk count or target size.
+    #[rstest]
+    #[case::num_chunks(&["-n", "4"], "args", 4)]
+    #[case::queries_per_chunk(&["--queries-per-chunk", "30"], "args", 4)]
+    #[case::json(&["-n", "3"], "json", 3)]
+    fn test_plan(
+        #[case] size_args: &[&str],
+        #[case] format: &str,
+        #[case] expected_num_chunks: usize,
+    ) -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let (random_bam, _) = QueryType::Paired.random_bam(&temp_dir.path(), 100)?;
+        let bam_str = random_bam.to_str().unwrap();
+        Index::try_parse_from(["index", "-i", bam_str, "-n", "10"])?.index_reads()?;
+        let index = format!("{bam_str}.si");
+        let output = temp_dir.path().join("chunks.txt");
+        let mut args = vec![
+            "plan",
+            "-I",
+            &index,
+            "-f",
+            format,
+            "-o",
+            output.to_str().unwrap(),
+        ];
+        args.extend(size_args);
+        Plan::try_parse_from(args)?.execute()?;
+        let plan = fs::read_to_string(&output)?;
+        if format == "args" {
+            let expected: String = (0..expected_num_chunks)
+                .map(|chunk_index| {
+                    format!("--chunk-index {chunk_index} --num-chunks {expected_num_chunks}\n")
+                })
+                .collect();
+            assert_eq!(plan, expected);
+        } else {
+            assert!(plan.starts_with('[') && plan.ends_with("]\n"));
+            assert_eq!(plan.matches("\"chunk_index\"").count(), expected_num_chunks);
+            assert!(plan.contains(
+                "{\"chunk_index\": 0, \"num_chunks\": 3, \"start_query\": 0, \"stop_query\": 33, \
+                \"approx_reads\": 66, \"args\": \"--chunk-index 0 --num-chunks 3\"}"
+            ));
+        }
+
+        assert!(
+            Plan::try_parse_from(["plan", "-I", &index])?
+                .execute()
+                .is_err()
+        );
+        assert!(
+            Plan::try_parse_from(["plan", "-I", &index, "-n", "3", "--queries-per-chunk", "3"])
+                .is_err()
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,113 @@
+use crate::commands::command::Command;
+use anyhow::Result;
+use clap::Parser;
+use log::info;
+use split_reads::split_index::{IndexSection, SplitIndex};
+use std::path::PathBuf;
+
+/// Remove optional sections from a split-index, keeping its bins, to make a minimal index for
+/// distribution. Chunks extracted with the pruned index are the same. `tell --tell sections` lists
+/// the sections an index has.
+#[derive(Parser, Debug)]
+#[command(version, verbatim_doc_comment)]
+pub(crate) struct Prune {
+    /// Input path for Index file. Use "-" for stdin.
+    #[clap(long, short = 'I', required = true)]
+    index: PathBuf,
+
+    /// Output path for the pruned Index file. Use "-" for stdout. Defaults to overwriting the
+    /// input index.
+    #[clap(long, short = 'o', required = false, default_value = None)]
+    output: Option<PathBuf>,
+
+    /// Sections to remove. Defaults to the metadata that chunk extraction doesn't use. Removing
+    /// the fingerprint or first-record-hashes stops get-chunk from checking that the reads file
+    /// matches the index.
+    #[clap(
+        long,
+        short = 's',
+        required = false,
+        default_value = "qnames,max-read-lengths,read-groups",
+        value_enum,
+        value_delimiter = ','
+    )]
+    sections: Vec<IndexSection>,
+}
+
+impl Prune {
+    /// Read the index, remove the requested sections, and return the pruned index.
+    fn prune(&self) -> Result<SplitIndex> {
+        let mut split_index = SplitIndex::read(&self.index)?;
+        for &section in &self.sections {
+            if split_index.sections().contains(&section) {
+                split_index.remove_section(section)?;
+                info!("Removed {} section.", section.name());
+            }
+        }
+        Ok(split_index)
+    }
+}
+
+/// Implement the Command trait for `Prune` struct.
+impl Command for Prune {
+    /// Execute the prune command to write the pruned split-index.
+    fn execute(&self) -> Result<()> {
+        let split_index = self.prune()?;
+        match &self.output {
+            Some(output) => split_index.write(output)?,
+            None => split_index.rewrite(&self.index)?,
+        };
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "synthetic"))]
+mod tests {
+    use super::Prune;
+    use crate::commands::{command::Command, index::Index};
+    use crate::test_utils::random_bam::{QueryType, RandomBam};
+    use anyhow::Result;
+    use clap::Parser;
+    use split_reads::split_index::{IndexSection, SplitIndex};
+    use tempfile::TempDir;
+
+    /// Test that pruning removes the requested sections and keeps the bins.
+    #[test]
+    fn test_prune() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let (random_bam, _) = QueryType::Paired.random_bam(&temp_dir.path(), 100)?;
+        let bam_str = random_bam.to_str().unwrap();
+        Index::try_parse_from(["index", "-i", bam_str, "-n", "10", "--qnames"])?.index_reads()?;
+        let index = format!("{bam_str}.si");
+        let original = SplitIndex::read(&index)?;
+        assert!(original.sections().contains(&IndexSection::Qnames));
+
+        let pruned_path = temp_dir.path().join("pruned.si");
+        let pruned_str = pruned_path.to_str().unwrap();
+        Prune::try_parse_from(["prune", "-I", &index, "-o", pruned_str])?.execute()?;
+        let pruned = SplitIndex::read(&pruned_path)?;
+        assert!(!pruned.sections().contains(&IndexSection::Qnames));
+        assert!(pruned.sections().contains(&IndexSection::Fingerprint));
+        assert!(pruned.sections().contains(&IndexSection::FirstRecordHashes));
+        assert_eq!(pruned.len(), original.len());
+        assert_eq!(
+            pruned.get_split_record_num_queries(),
+            original.get_split_record_num_queries()
+        );
+
+        Prune::try_parse_from([
+            "prune",
+            "-I",
+            pruned_str,
+            "-s",
+            "fingerprint,offset-kind,first-record-hashes",
+        ])?
+        .execute()?;
+        assert_eq!(
+            SplitIndex::read(&pruned_path)?.sections(),
+            [IndexSection::Records]
+        );
+        assert!(Prune::try_parse_from(["prune", "-I", pruned_str, "-s", "records"]).is_err());
+        Ok(())
+    }
+}
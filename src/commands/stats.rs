@@ -0,0 +1,184 @@
+use crate::commands::{command::Command, get_chunk::GetChunk};
+use anyhow::{Result, anyhow};
+use clap::Parser;
+use log::{info, warn};
+use split_reads::{
+    chunkable::{ChunkableRecord, ChunkableRecordReader, FastForwardIndex},
+    read_stats::ReadStats,
+    thread_budget::{READING_POOLS, Threads},
+    util::{RecordType, get_fastq_reader, get_sam_reader},
+};
+use std::{num::NonZero, path::PathBuf};
+
+#[derive(clap::ValueEnum, Clone, Copy, Default, Debug, PartialEq)]
+pub enum StatsFormat {
+    #[default]
+    Json,
+    Tsv,
+}
+
+/// Compute read length distribution, mean base quality, GC content, and query group size
+/// distribution of a FASTQ or SAM/BAM/CRAM in a single streaming pass. Optionally restrict to one
+/// chunk using the split-index.
+#[derive(Parser, Debug)]
+#[command(version, verbatim_doc_comment)]
+pub(crate) struct Stats {
+    /// Input FASTQ or SAM/BAM/CRAM.
+    #[clap(long, short = 'i', required = true)]
+    input: PathBuf,
+
+    /// Index for input reads file, built by split-reads index. Only used with --chunk-index.
+    /// Defaults to input path with extra ".si" extension.
+    #[clap(long, short = 'I', required = false, default_value = None)]
+    index: Option<PathBuf>,
+
+    /// Reference FASTA (required for CRAMs)
+    #[clap(long, short = 'R', required = false, default_value = None)]
+    ref_fasta: Option<PathBuf>,
+
+    /// Only compute statistics for this chunk (0, 1, ..., num_chunks - 1). Requires --num-chunks.
+    #[clap(long, short = 'c', required = false, default_value = None, requires = "num_chunks")]
+    chunk_index: Option<usize>,
+
+    /// Number of chunks in total input file. Requires --chunk-index.
+    #[clap(long, short = 'n', required = false, default_value = None, requires = "chunk_index")]
+    num_chunks: Option<NonZero<usize>>,
+
+    /// Format to print statistics in.
+    #[clap(long, short = 'f', required = false, default_value_t, value_enum)]
+    format: StatsFormat,
+
+    /// Number of threads to use for reading. Use "auto" to use every CPU available
+    /// to this process (after CPU affinity and cgroup limits). Defaults to all of them.
+    #[clap(long, short = 't', required = false)]
+    threads: Option<Threads>,
+}
+
+impl Stats {
+    /// Get the number of threads for reading.
+    fn threads(&self) -> NonZero<usize> {
+        Threads::per_pool(self.threads, READING_POOLS)
+    }
+
+    /// Accumulate statistics over the requested chunk, or over the whole file.
+    fn stats_with<R, Reader>(&self, reader: &mut Reader) -> Result<ReadStats>
+    where
+        R: ChunkableRecord + Send,
+        Reader: ChunkableRecordReader<R> + Send,
+    {
+        let mut stats = ReadStats::new();
+        if let (Some(chunk_index), Some(num_chunks)) = (self.chunk_index, self.num_chunks) {
+            let split_index =
+                GetChunk::load_split_index(self.index.clone(), self.input.clone(), false)?;
+            if let Some(warning) =
+                GetChunk::sparse_bins_warning(&split_index, chunk_index, num_chunks)?
+            {
+                warn!("{warning}");
+            }
+            let start_num_queries = split_index.get_chunk_query_start(chunk_index, num_chunks)?;
+            let stop_num_queries =
+                split_index.get_chunk_query_start(chunk_index + 1, num_chunks)?;
+            if reader
+                .write_queries(split_index, start_num_queries, stop_num_queries, &mut stats)?
+                .is_empty()
+            {
+                warn!("Chunk {chunk_index} is empty.")
+            }
+        } else {
+            let mut record = R::new();
+            while let Some(result) = reader.read_into(&mut record) {
+                result?;
+                stats.add(&record);
+            }
+        }
+        stats.finish_query();
+        Ok(stats)
+    }
+
+    /// Open the reader for the input record type, and compute statistics.
+    fn stats(&self) -> Result<ReadStats> {
+        let input_record_type = RecordType::detect(&self.input)?.ok_or_else(|| {
+            anyhow!("Input type must be FASTQ or SAM/BAM/CRAM. Cannot read from stdin.")
+        })?;
+        if input_record_type.is_sam_family() {
+            let mut reader =
+                get_sam_reader(self.input.clone(), self.ref_fasta.clone(), self.threads())?;
+            self.stats_with(&mut reader)
+        } else {
+            let mut reader = get_fastq_reader(self.input.clone(), self.threads())?;
+            self.stats_with(&mut reader)
+        }
+    }
+}
+
+/// Implement the Command trait for `Stats` struct.
+impl Command for Stats {
+    /// Execute the stats command to print read statistics.
+    fn execute(&self) -> Result<()> {
+        Threads::warn_if_oversubscribed(self.threads, READING_POOLS);
+        info!("Using {} thread(s)", self.threads());
+        let stats = self.stats()?;
+        match self.format {
+            StatsFormat::Json => println!("{}", stats.to_json()),
+            StatsFormat::Tsv => println!("{}", stats.to_tsv()),
+        }
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "synthetic"))]
+mod tests {
+    use super::Stats;
+    use crate::{
+        commands::index::Index,
+        test_utils::random_bam::{QueryType, RandomBam},
+    };
+    use anyhow::Result;
+    use clap::Parser;
+    use rstest::rstest;
+    use rust_htslib::bam::{Read, Reader, Record};
+    use split_reads::read_stats::ReadStats;
+    use std::path::PathBuf;
+    use tempfile::TempDir;
+
+    /// Test that statistics over all chunks add up to the statistics of the whole file.
+    #[rstest(query_type => [QueryType::Single, QueryType::Paired, QueryType::Grouped])]
+    fn test_chunk_stats_add_up(query_type: QueryType) -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let temp_path: PathBuf = temp_dir.path().to_path_buf();
+        let (random_bam, _) = query_type.random_bam(&temp_path, 100)?;
+        let bam_str = random_bam.to_str().unwrap();
+        Index::try_parse_from(["index", "--input", bam_str, "--num-bins", "10"])?.index_reads()?;
+
+        let mut truth = ReadStats::new();
+        for record in Reader::from_path(&random_bam)?.records() {
+            let record: Record = record?;
+            truth.add(&record);
+        }
+        truth.finish_query();
+
+        let whole = Stats::try_parse_from(["stats", "-i", bam_str])?.stats()?;
+        assert_eq!(whole, truth);
+
+        let (mut num_reads, mut num_queries, mut num_bases) = (0, 0, 0);
+        for chunk_index in 0..3 {
+            let chunk = Stats::try_parse_from([
+                "stats",
+                "-i",
+                bam_str,
+                "-c",
+                &chunk_index.to_string(),
+                "-n",
+                "3",
+            ])?
+            .stats()?;
+            num_reads += chunk.num_reads();
+            num_queries += chunk.num_queries();
+            num_bases += chunk.num_bases();
+        }
+        assert_eq!(num_reads, truth.num_reads());
+        assert_eq!(num_queries, truth.num_queries());
+        assert_eq!(num_bases, truth.num_bases());
+        Ok(())
+    }
+}
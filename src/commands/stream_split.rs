@@ -0,0 +1,286 @@
+use crate::commands::command::Command;
+use anyhow::{Result, anyhow};
+use clap::{Parser, value_parser};
+use log::{info, warn};
+use split_reads::{
+    chunk_naming::{ChunkNamer, default_namespace},
+    chunkable::{
+        ChunkResult, ChunkableRecord, ChunkableRecordReader, ChunkableRecordWriter, GroupBy,
+    },
+    output_spec::OutputSpec,
+    path_type::PathType,
+    sam_writer_spec::{OutputFormat, SamWriterSpec},
+    stream_split::RoundRobinWriter,
+    thread_budget::{READ_WRITE_POOLS, Threads},
+    util::{RecordType, get_fastq_reader, get_fastq_writer, get_sam_reader},
+};
+use std::{num::NonZero, path::PathBuf};
+
+/// Split a FASTQ or SAM/BAM/CRAM into chunks in one pass, without an index, by dealing whole query
+/// groups out to the chunks in turn. For streams that can't be indexed first, e.g. reads piped
+/// from a basecaller. Chunks are named like get-chunk --output-dir chunks, e.g.
+/// "<namespace>.chunk-03-of-10.bam". The input must be grouped by query (see index --group-by).
+#[derive(Parser, Debug)]
+#[command(version, verbatim_doc_comment)]
+pub(crate) struct StreamSplit {
+    /// Input FASTQ or SAM/BAM/CRAM to split. Use "-" (or omit) for stdin.
+    #[clap(long, short = 'i', required = false, default_value = "-")]
+    input: PathBuf,
+
+    /// Input format, for stdin and other inputs whose format can't be detected. Defaults to
+    /// SAM/BAM/CRAM, which htslib tells apart by itself.
+    #[clap(long, short = 'F', required = false, value_enum)]
+    input_format: Option<OutputFormat>,
+
+    /// Reference FASTA (required for CRAMs)
+    #[clap(long, short = 'R', required = false, default_value = None)]
+    ref_fasta: Option<PathBuf>,
+
+    /// Directory to write the chunks to.
+    #[clap(long, required = true)]
+    output_dir: PathBuf,
+
+    /// Prefix of chunk file names. Defaults to the input file name up to the first '.', so is
+    /// required for stdin.
+    #[clap(long, required = false, default_value = None)]
+    namespace: Option<String>,
+
+    /// Number of chunks to split into.
+    #[clap(long, short = 'n', required = true)]
+    num_chunks: NonZero<usize>,
+
+    /// Number of consecutive query groups dealt to each chunk before moving on to the next. 1 deals
+    /// query groups round-robin, larger blocks keep runs of neighboring query groups together.
+    #[clap(long, required = false, default_value_t = NonZero::<usize>::MIN)]
+    block_size: NonZero<usize>,
+
+    /// Key that consecutive reads are grouped by, so that chunks never split a group (see index
+    /// --group-by).
+    #[clap(long, required = false, default_value = "qname")]
+    group_by: GroupBy,
+
+    /// Output format type. Defaults to the input type. FASTQ can't be converted to SAM/BAM/CRAM or
+    /// back.
+    #[clap(long, short = 'O', required = false, value_enum)]
+    output_format: Option<OutputFormat>,
+
+    /// Compression level for output compressed formats.
+    #[clap(long, short = 'C', required = false, value_parser = value_parser!(u32).range(..=9))]
+    compression: Option<u32>,
+
+    /// Number of threads to use for reading, and shared by the chunk writers. Defaults to "auto",
+    /// which divides the CPUs available to this process between reading and writing.
+    #[clap(long, short = 't', required = false)]
+    threads: Option<Threads>,
+}
+
+impl StreamSplit {
+    /// Get the number of threads for the reading thread pool, and for all writers together.
+    fn threads(&self) -> NonZero<usize> {
+        Threads::per_pool(self.threads, READ_WRITE_POOLS)
+    }
+
+    /// Get the number of threads for each chunk writer: an equal share of the writing threads.
+    fn writer_threads(&self) -> NonZero<usize> {
+        NonZero::new(self.threads().get() / self.num_chunks.get()).unwrap_or(NonZero::<usize>::MIN)
+    }
+
+    /// Get the type of the input: detected from the input if possible, otherwise --input-format,
+    /// otherwise SAM/BAM/CRAM.
+    fn input_record_type(&self) -> Result<RecordType> {
+        Ok(RecordType::detect(&self.input)?
+            .or(self.input_format.map(RecordType::from))
+            .unwrap_or(RecordType::Bam))
+    }
+
+    /// Get the path of every chunk, after registering the namespace in the output directory.
+    fn chunk_paths(&self, output_spec: OutputSpec) -> Result<Vec<PathBuf>> {
+        let namespace = match (&self.namespace, PathType::from_path(&self.input)?) {
+            (Some(namespace), _) => namespace.clone(),
+            (None, PathType::Pipe) => Err(anyhow!("Reading from stdin requires --namespace."))?,
+            (None, _) => default_namespace(&self.input).ok_or_else(|| {
+                anyhow!("Cannot get a namespace from the input path, specify --namespace.")
+            })?,
+        };
+        let namer = ChunkNamer::new(&namespace)?;
+        namer.register(&self.output_dir, &self.input)?;
+        let extension = output_spec.extension(self.compression);
+        Ok((0..self.num_chunks.get())
+            .map(|chunk_index| {
+                self.output_dir.join(namer.chunk_file_name(
+                    chunk_index,
+                    self.num_chunks,
+                    &extension,
+                ))
+            })
+            .collect())
+    }
+
+    /// Deal every query group read from reader out to writers, returning the writers to be
+    /// finished, with what was written to each.
+    fn split_with<R, Reader, W>(
+        &self,
+        reader: &mut Reader,
+        writers: Vec<W>,
+    ) -> Result<(Vec<W>, Vec<ChunkResult>)>
+    where
+        R: ChunkableRecord,
+        Reader: ChunkableRecordReader<R>,
+        RoundRobinWriter<W>: ChunkableRecordWriter<R>,
+    {
+        let mut writer = RoundRobinWriter::new(writers, self.block_size, self.group_by)?;
+        let mut record = R::new();
+        while let Some(result) = reader.read_into(&mut record) {
+            result?;
+            writer.write(&record)?;
+        }
+        Ok(writer.into_parts())
+    }
+
+    /// Open the reader and the chunk writers for the input record type, then split.
+    fn split(&self) -> Result<Vec<ChunkResult>> {
+        let input_record_type = self.input_record_type()?;
+        let output_spec = OutputSpec::resolve("-", self.output_format, input_record_type)?;
+        if output_spec.record_type.is_sam_family() != input_record_type.is_sam_family() {
+            return Err(anyhow!(
+                "Output type ({}) must match input type ({input_record_type}).",
+                output_spec.record_type
+            ));
+        }
+        let chunk_paths = self.chunk_paths(output_spec)?;
+        if input_record_type.is_sam_family() {
+            let mut reader =
+                get_sam_reader(self.input.clone(), self.ref_fasta.clone(), self.threads())?;
+            let writers = chunk_paths
+                .iter()
+                .map(|chunk_path| {
+                    SamWriterSpec::new(chunk_path.clone())
+                        .header_from_view(reader.header())
+                        .format(output_spec.record_type.into())
+                        .threads(self.writer_threads())
+                        .reference_fasta(self.ref_fasta.clone())
+                        .compression(self.compression)
+                        .get_bam_writer()
+                })
+                .collect::<Result<Vec<_>>>()?;
+            let (writers, results) = self.split_with(&mut reader, writers)?;
+            for writer in writers {
+                writer.finish()?;
+            }
+            Ok(results)
+        } else {
+            let mut reader = get_fastq_reader(self.input.clone(), self.threads())?;
+            let writers = chunk_paths
+                .iter()
+                .map(|chunk_path| {
+                    get_fastq_writer(chunk_path, self.compression, self.writer_threads())
+                })
+                .collect::<Result<Vec<_>>>()?;
+            let (writers, results) = self.split_with(&mut reader, writers)?;
+            for writer in writers {
+                writer.finish()?;
+            }
+            Ok(results)
+        }
+    }
+}
+
+/// Implement the Command trait for `StreamSplit` struct.
+impl Command for StreamSplit {
+    /// Execute the stream-split command to deal query groups out to chunks in one pass.
+    fn execute(&self) -> Result<()> {
+        Threads::warn_if_oversubscribed(self.threads, READ_WRITE_POOLS);
+        info!("Using {} thread(s)", self.threads());
+        for (chunk_index, result) in self.split()?.iter().enumerate() {
+            match result {
+                ChunkResult::Empty => warn!("Chunk {chunk_index} is empty."),
+                ChunkResult::Written { reads, queries } => {
+                    info!("Chunk {chunk_index}: wrote {reads} reads from {queries} queries.")
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "synthetic"))]
+mod tests {
+    use super::StreamSplit;
+    use crate::test_utils::random_bam::{QueryType, RandomBam};
+    use anyhow::Result;
+    use clap::Parser;
+    use rstest::rstest;
+    use rust_htslib::bam::{Read, Reader};
+    use std::path::Path;
+    use tempfile::TempDir;
+
+    /// Group the query names of every record in a BAM into runs of equal names
+    fn load_query_groups<P>(bam: P) -> Result<Vec<(Vec<u8>, usize)>>
+    where
+        P: AsRef<Path>,
+    {
+        let mut groups: Vec<(Vec<u8>, usize)> = Vec::new();
+        for record in Reader::from_path(bam)?.records() {
+            let qname = record?.qname().to_vec();
+            match groups.last_mut() {
+                Some((last, count)) if *last == qname => *count += 1,
+                _ => groups.push((qname, 1)),
+            }
+        }
+        Ok(groups)
+    }
+
+    /// Test that stream-split deals whole query groups to the chunks in turn, in blocks.
+    #[rstest(block_size => [1, 4])]
+    fn test_stream_split(block_size: usize) -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let (random_bam, _) = QueryType::Paired.random_bam(&temp_dir.path(), 100)?;
+        let output_dir = temp_dir.path().join("chunks");
+        let results = StreamSplit::try_parse_from([
+            "stream-split",
+            "-i",
+            random_bam.to_str().unwrap(),
+            "--output-dir",
+            output_dir.to_str().unwrap(),
+            "-n",
+            "3",
+            "--block-size",
+            &block_size.to_string(),
+            "-O",
+            "bam",
+        ])?
+        .split()?;
+        assert_eq!(
+            results
+                .iter()
+                .map(|result| result.num_queries())
+                .sum::<usize>(),
+            100
+        );
+
+        let truth = load_query_groups(&random_bam)?;
+        let namespace = random_bam.file_name().unwrap().to_str().unwrap();
+        let namespace = namespace.split('.').next().unwrap();
+        for (chunk_index, result) in results.iter().enumerate() {
+            let chunk = output_dir.join(format!("{namespace}.chunk-{chunk_index}-of-3.bam"));
+            let expected: Vec<(Vec<u8>, usize)> = truth
+                .iter()
+                .enumerate()
+                .filter(|(query, _)| (query / block_size) % 3 == chunk_index)
+                .map(|(_, group)| group.clone())
+                .collect();
+            assert_eq!(load_query_groups(&chunk)?, expected);
+            assert_eq!(result.num_queries(), expected.len());
+        }
+
+        let stdin_split = StreamSplit::try_parse_from([
+            "stream-split",
+            "--output-dir",
+            output_dir.to_str().unwrap(),
+            "-n",
+            "3",
+        ])?;
+        assert!(stdin_split.split().is_err());
+        Ok(())
+    }
+}
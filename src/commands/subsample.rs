@@ -0,0 +1,276 @@
+use crate::commands::{command::Command, get_chunk::GetChunk};
+use anyhow::{Result, anyhow};
+use clap::{Parser, value_parser};
+use log::{info, warn};
+use split_reads::{
+    chunkable::{ChunkableRecord, ChunkableRecordReader, ChunkableRecordWriter, FastForwardIndex},
+    output_spec::OutputSpec,
+    rng::RandomFeature,
+    sam_writer_spec::SamWriterSpec,
+    subsample::{QuerySubsampler, SubsamplingWriter},
+    thread_budget::{READ_WRITE_POOLS, Threads},
+    util::{RecordType, get_fastq_reader, get_fastq_writer, get_sam_reader},
+};
+use std::{num::NonZero, path::PathBuf};
+
+/// Emit a deterministic, seeded fraction of the query groups of a FASTQ or SAM/BAM/CRAM, keeping
+/// all reads of a selected query together. Optionally restrict to one chunk using the split-index.
+/// The same queries are selected whether the whole file or each chunk is subsampled.
+#[derive(Parser, Debug)]
+#[command(version, verbatim_doc_comment)]
+pub(crate) struct Subsample {
+    /// Input FASTQ or SAM/BAM/CRAM to subsample.
+    #[clap(long, short = 'i', required = true)]
+    input: PathBuf,
+
+    /// Index for input reads file, built by split-reads index. Only used with --chunk-index.
+    /// Defaults to input path with extra ".si" extension.
+    #[clap(long, short = 'I', required = false, default_value = None)]
+    index: Option<PathBuf>,
+
+    /// Reference FASTA (required for CRAMs)
+    #[clap(long, short = 'R', required = false, default_value = None)]
+    ref_fasta: Option<PathBuf>,
+
+    /// Output path for subsampled reads, in the same record type as the input. Use "-" (or omit)
+    /// for stdout.
+    #[clap(long, short = 'o', required = false, default_value = "-")]
+    output: PathBuf,
+
+    /// Fraction of query groups to keep, between 0 and 1.
+    #[clap(long, short = 'p', required = true)]
+    fraction: f64,
+
+    /// Seed for selecting query groups. The same seed always selects the same queries. Defaults to
+    /// the global --seed, or 0.
+    #[clap(long, short = 's', required = false, default_value = None)]
+    seed: Option<u64>,
+
+    /// Only subsample this chunk (0, 1, ..., num_chunks - 1). Requires --num-chunks.
+    #[clap(long, short = 'c', required = false, default_value = None, requires = "num_chunks")]
+    chunk_index: Option<usize>,
+
+    /// Number of chunks in total input file. Requires --chunk-index.
+    #[clap(long, short = 'n', required = false, default_value = None, requires = "chunk_index")]
+    num_chunks: Option<NonZero<usize>>,
+
+    /// Compression level for output compressed formats. Default to 0 for writing to stdout .
+    #[clap(long, short = 'C', required = false, value_parser = value_parser!(u32).range(..=9))]
+    compression: Option<u32>,
+
+    /// Number of threads to use for reading or writing. Defaults to "auto", which divides the CPUs
+    /// available to this process between reading and writing.
+    #[clap(long, short = 't', required = false)]
+    threads: Option<Threads>,
+}
+
+impl Subsample {
+    /// Get the number of threads for each of the reading and writing thread pools.
+    fn threads(&self) -> NonZero<usize> {
+        Threads::per_pool(self.threads, READ_WRITE_POOLS)
+    }
+
+    /// Write the selected query groups of the requested chunk, or of the whole file.
+    fn subsample_with<R, Reader, Writer>(
+        &self,
+        reader: &mut Reader,
+        writer: &mut Writer,
+    ) -> Result<()>
+    where
+        R: ChunkableRecord + Send,
+        Reader: ChunkableRecordReader<R> + Send,
+        Writer: ChunkableRecordWriter<R>,
+    {
+        let subsampler =
+            QuerySubsampler::new(self.fraction, RandomFeature::Subsample.seed(self.seed))?;
+        let mut writer = SubsamplingWriter::new(writer, subsampler);
+        if let (Some(chunk_index), Some(num_chunks)) = (self.chunk_index, self.num_chunks) {
+            let split_index =
+                GetChunk::load_split_index(self.index.clone(), self.input.clone(), false)?;
+            if let Some(warning) =
+                GetChunk::sparse_bins_warning(&split_index, chunk_index, num_chunks)?
+            {
+                warn!("{warning}");
+            }
+            let start_num_queries = split_index.get_chunk_query_start(chunk_index, num_chunks)?;
+            let stop_num_queries =
+                split_index.get_chunk_query_start(chunk_index + 1, num_chunks)?;
+            if reader
+                .write_queries(
+                    split_index,
+                    start_num_queries,
+                    stop_num_queries,
+                    &mut writer,
+                )?
+                .is_empty()
+            {
+                warn!("Chunk {chunk_index} is empty.")
+            }
+        } else {
+            let mut record = R::new();
+            while let Some(result) = reader.read_into(&mut record) {
+                result?;
+                writer.write(&record)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Open the reader and writer for the input record type, then subsample.
+    fn subsample(&self) -> Result<()> {
+        let input_record_type = RecordType::detect(&self.input)?.ok_or_else(|| {
+            anyhow!("Input type must be FASTQ or SAM/BAM/CRAM. Cannot read from stdin.")
+        })?;
+        if let Some(output_record_type) =
+            OutputSpec::from_path(&self.output)?.map(|output_spec| output_spec.record_type)
+            && output_record_type.is_sam_family() != input_record_type.is_sam_family()
+        {
+            return Err(anyhow!(
+                "Output type ({output_record_type}) must match input type ({input_record_type})."
+            ));
+        }
+        if input_record_type.is_sam_family() {
+            let mut reader =
+                get_sam_reader(self.input.clone(), self.ref_fasta.clone(), self.threads())?;
+            let mut writer = SamWriterSpec::new(self.output.clone())
+                .header_from_view(reader.header())
+                .format_from_path_or_default(input_record_type.into())?
+                .threads(self.threads())
+                .reference_fasta(self.ref_fasta.clone())
+                .compression(self.compression)
+                .get_bam_writer()?;
+            self.subsample_with(&mut reader, &mut writer)?;
+            writer.finish()
+        } else {
+            let mut reader = get_fastq_reader(self.input.clone(), self.threads())?;
+            let mut writer =
+                get_fastq_writer(self.output.clone(), self.compression, self.threads())?;
+            self.subsample_with(&mut reader, &mut writer)?;
+            writer.finish()
+        }
+    }
+}
+
+/// Implement the Command trait for `Subsample` struct.
+impl Command for Subsample {
+    /// Execute the subsample command to write a fraction of the query groups.
+    fn execute(&self) -> Result<()> {
+        Threads::warn_if_oversubscribed(self.threads, READ_WRITE_POOLS);
+        info!("Using {} thread(s)", self.threads());
+        self.subsample()
+    }
+
+    fn default_seed(&mut self, seed: u64) {
+        self.seed.get_or_insert(seed);
+    }
+}
+
+#[cfg(all(test, feature = "synthetic"))]
+mod tests {
+    use super::Subsample;
+    use crate::{
+        Args,
+        commands::index::Index,
+        test_utils::random_bam::{QueryType, RandomBam},
+    };
+    use anyhow::Result;
+    use clap::Parser;
+    use rstest::rstest;
+    use rust_htslib::bam::{Read, Reader};
+    use std::{
+        collections::HashMap,
+        path::{Path, PathBuf},
+    };
+    use tempfile::TempDir;
+
+    /// Load the query names of every record in a BAM
+    fn load_qnames<P>(bam: P) -> Result<Vec<Vec<u8>>>
+    where
+        P: AsRef<Path>,
+    {
+        Ok(Reader::from_path(bam)?
+            .records()
+            .map(|record| record.map(|record| record.qname().to_vec()))
+            .collect::<Result<Vec<Vec<u8>>, _>>()?)
+    }
+
+    /// Count the number of reads in each query group
+    fn count_reads(qnames: &[Vec<u8>]) -> HashMap<&[u8], usize> {
+        let mut counts: HashMap<&[u8], usize> = HashMap::new();
+        for qname in qnames {
+            *counts.entry(qname.as_slice()).or_default() += 1;
+        }
+        counts
+    }
+
+    /// Test that subsampling keeps whole query groups, and that subsampling chunks selects the
+    /// same queries as subsampling the whole file.
+    #[rstest(query_type => [QueryType::Single, QueryType::Paired, QueryType::Grouped])]
+    fn test_subsample(query_type: QueryType) -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let temp_path: PathBuf = temp_dir.path().to_path_buf();
+        let (random_bam, _) = query_type.random_bam(&temp_path, 200)?;
+        let bam_str = random_bam.to_str().unwrap();
+        Index::try_parse_from(["index", "--input", bam_str, "--num-bins", "10"])?.index_reads()?;
+
+        let subsample = |output: &Path, chunk: Option<(usize, usize)>| -> Result<()> {
+            let mut args = vec![
+                "subsample".to_string(),
+                "-i".to_string(),
+                bam_str.to_string(),
+                "-o".to_string(),
+                output.to_str().unwrap().to_string(),
+                "-p".to_string(),
+                "0.3".to_string(),
+                "--seed".to_string(),
+                "7".to_string(),
+            ];
+            if let Some((chunk_index, num_chunks)) = chunk {
+                args.extend([
+                    "-c".to_string(),
+                    chunk_index.to_string(),
+                    "-n".to_string(),
+                    num_chunks.to_string(),
+                ]);
+            }
+            Subsample::try_parse_from(args)?.subsample()
+        };
+        let whole = temp_path.join("whole.bam");
+        subsample(&whole, None)?;
+        let whole_qnames = load_qnames(&whole)?;
+
+        let mut chunk_qnames: Vec<Vec<u8>> = Vec::new();
+        for chunk_index in 0..3 {
+            let chunk = temp_path.join(format!("chunk_{chunk_index}.bam"));
+            subsample(&chunk, Some((chunk_index, 3)))?;
+            chunk_qnames.extend(load_qnames(&chunk)?);
+        }
+        assert_eq!(chunk_qnames, whole_qnames);
+
+        let truth_qnames = load_qnames(&random_bam)?;
+        let truth_counts = count_reads(&truth_qnames);
+        let whole_counts = count_reads(&whole_qnames);
+        assert!(!whole_counts.is_empty() && whole_counts.len() < truth_counts.len());
+        for (qname, count) in whole_counts {
+            assert_eq!(truth_counts[qname], count, "Query group was split");
+        }
+
+        // the global --seed selects the same queries as the subcommand's own --seed
+        let global = temp_path.join("global.bam");
+        Args::try_parse_from([
+            "split-reads",
+            "--seed",
+            "7",
+            "subsample",
+            "-i",
+            bam_str,
+            "-o",
+            global.to_str().unwrap(),
+            "-p",
+            "0.3",
+        ])?
+        .execute()?;
+        assert_eq!(load_qnames(&global)?, whole_qnames);
+        Ok(())
+    }
+}
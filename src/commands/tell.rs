@@ -1,9 +1,15 @@
-use crate::commands::command::Command;
-use anyhow::Result;
+use crate::commands::{command::Command, get_chunk::GetChunk};
+use anyhow::{Result, anyhow};
 use clap::Parser;
+use log::warn;
 use serde::Serialize;
-use split_reads::split_index::SplitIndex;
-use std::path::PathBuf;
+use split_reads::{
+    chunkable::{ChunkableRecord, ChunkableRecordReader, FastForwardIndex},
+    manifest::json_string,
+    split_index::{SplitIndex, SplitRecord},
+    util::{RecordType, get_fastq_reader, get_sam_reader},
+};
+use std::{num::NonZero, path::PathBuf};
 
 #[derive(clap::ValueEnum, Clone, Default, Debug, Serialize)]
 #[serde(rename_all = "kebab-case")]
@@ -12,6 +18,291 @@ pub enum TellWhich {
     #[default]
     NumQueries,
     NumReads,
+    ReadGroups,
+    Sections,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Default, Debug, PartialEq)]
+pub enum TellFormat {
+    /// Only the value selected by --tell
+    #[default]
+    Plain,
+    /// All statistics as a JSON object
+    Json,
+    /// All statistics as lines of name and value
+    Tsv,
+}
+
+/// Summary of a split-index, for machine-readable output.
+#[derive(Clone, Debug, PartialEq)]
+struct IndexSummary {
+    num_bins: usize,
+    num_queries: usize,
+    num_reads: usize,
+    first_offset: Option<u64>,
+    last_offset: Option<u64>,
+    mean_queries_per_bin: f64,
+}
+
+impl IndexSummary {
+    /// Summarize the split-index
+    fn new(split_index: &SplitIndex) -> Self {
+        let offset_span = split_index.offset_span();
+        IndexSummary {
+            num_bins: split_index.len(),
+            num_queries: split_index.num_queries(),
+            num_reads: split_index.num_reads(),
+            first_offset: offset_span.map(|(first, _)| first),
+            last_offset: offset_span.map(|(_, last)| last),
+            mean_queries_per_bin: split_index.num_queries() as f64
+                / split_index.len().max(1) as f64,
+        }
+    }
+
+    /// Get the names and values of each statistic, with missing values as None
+    fn fields(&self) -> [(&'static str, Option<String>); 6] {
+        [
+            ("num_bins", Some(self.num_bins.to_string())),
+            ("num_queries", Some(self.num_queries.to_string())),
+            ("num_reads", Some(self.num_reads.to_string())),
+            ("first_offset", self.first_offset.map(|o| o.to_string())),
+            ("last_offset", self.last_offset.map(|o| o.to_string())),
+            (
+                "mean_queries_per_bin",
+                Some(self.mean_queries_per_bin.to_string()),
+            ),
+        ]
+    }
+
+    /// Format as a JSON object
+    fn to_json(&self) -> String {
+        let fields: Vec<String> = self
+            .fields()
+            .into_iter()
+            .map(|(name, value)| {
+                format!(
+                    "\"{name}\": {}",
+                    value.unwrap_or_else(|| "null".to_string())
+                )
+            })
+            .collect();
+        format!("{{{}}}", fields.join(", "))
+    }
+
+    /// Format as TSV lines of name and value
+    fn to_tsv(&self) -> String {
+        let lines: Vec<String> = self
+            .fields()
+            .into_iter()
+            .map(|(name, value)| format!("{name}\t{}", value.unwrap_or_else(|| "NA".to_string())))
+            .collect();
+        lines.join("\n")
+    }
+}
+
+/// Planned extent of one chunk, for suggesting chunk counts.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct ChunkPlan {
+    pub(crate) chunk_index: usize,
+    pub(crate) start_query: usize,
+    pub(crate) stop_query: usize,
+    pub(crate) approx_reads: usize,
+    /// Approximate size in the indexed file, if the index knows the file size
+    pub(crate) approx_bytes: Option<u64>,
+}
+
+impl ChunkPlan {
+    /// Plan every chunk when splitting the indexed file into num_chunks chunks. Bytes are
+    /// apportioned by reads, assuming reads are spread evenly through the file.
+    pub(crate) fn plan_chunks(
+        split_index: &SplitIndex,
+        num_chunks: NonZero<usize>,
+    ) -> Result<Vec<Self>> {
+        let boundaries = (0..=num_chunks.get())
+            .map(|chunk_index| split_index.get_chunk_query_start(chunk_index, num_chunks))
+            .collect::<Result<Vec<usize>>>()?;
+        Ok(Self::plan_between(split_index, &boundaries))
+    }
+
+    /// Plan chunks as plan_chunks does, but also start and stop a chunk around each of the query
+    /// groups long_queries (0-based query indices, e.g. those holding ultra-long reads), so that
+    /// each gets a chunk of its own. Those chunks are uneven, so they must be extracted with
+    /// --query-start and --query-count.
+    fn plan_isolating_chunks(
+        split_index: &SplitIndex,
+        num_chunks: NonZero<usize>,
+        long_queries: &[usize],
+    ) -> Result<Vec<Self>> {
+        let mut boundaries = (0..=num_chunks.get())
+            .map(|chunk_index| split_index.get_chunk_query_start(chunk_index, num_chunks))
+            .collect::<Result<Vec<usize>>>()?;
+        for &query_index in long_queries {
+            boundaries.extend([query_index, query_index + 1]);
+        }
+        boundaries.sort_unstable();
+        boundaries.dedup();
+        Ok(Self::plan_between(split_index, &boundaries))
+    }
+
+    /// Plan one chunk between each pair of consecutive query boundaries.
+    fn plan_between(split_index: &SplitIndex, boundaries: &[usize]) -> Vec<Self> {
+        let file_size = split_index
+            .fingerprint()
+            .map(|fingerprint| fingerprint.size);
+        let num_reads = split_index.num_reads().max(1);
+        boundaries
+            .windows(2)
+            .enumerate()
+            .map(|(chunk_index, window)| {
+                let (start_query, stop_query) = (window[0], window[1]);
+                let reads_before = |num_queries: usize| {
+                    split_index
+                        .estimate_num_reads_before(num_queries)
+                        .unwrap_or_else(|| split_index.num_reads())
+                };
+                let approx_reads = reads_before(stop_query) - reads_before(start_query);
+                ChunkPlan {
+                    chunk_index,
+                    start_query,
+                    stop_query,
+                    approx_reads,
+                    approx_bytes: file_size.map(|file_size| {
+                        (file_size as u128 * approx_reads as u128 / num_reads as u128) as u64
+                    }),
+                }
+            })
+            .collect()
+    }
+
+    /// Header for the TSV table of chunk plans
+    const TSV_HEADER: &str = "chunk_index\tstart_query\tstop_query\tapprox_reads\tapprox_bytes";
+
+    /// Format as a TSV line
+    fn to_tsv(&self) -> String {
+        format!(
+            "{}\t{}\t{}\t{}\t{}",
+            self.chunk_index,
+            self.start_query,
+            self.stop_query,
+            self.approx_reads,
+            self.approx_bytes
+                .map_or("NA".to_string(), |bytes| bytes.to_string())
+        )
+    }
+
+    /// Format as a JSON object
+    fn to_json(&self) -> String {
+        format!(
+            "{{\"chunk_index\": {}, \"start_query\": {}, \"stop_query\": {}, \"approx_reads\": {}, \
+            \"approx_bytes\": {}}}",
+            self.chunk_index,
+            self.start_query,
+            self.stop_query,
+            self.approx_reads,
+            self.approx_bytes
+                .map_or("null".to_string(), |bytes| bytes.to_string())
+        )
+    }
+}
+
+/// Header for the TSV table of index bins
+const BINS_TSV_HEADER: &str = "bin\toffset\tuncompressed_offset\tcumulative_queries\t\
+    cumulative_reads\tfirst_qname\tlast_qname\tmax_read_length";
+
+/// Get the names and values of each field of an index bin, with missing values as None. Query
+/// names are raw (lossily decoded as UTF-8), to be JSON-quoted by dump_bins.
+fn bin_fields(bin_index: usize, bin: &SplitRecord) -> [(&'static str, Option<String>); 8] {
+    let text = |qname: &[u8]| String::from_utf8_lossy(qname).into_owned();
+    let qname_range = bin.qname_range.as_ref();
+    [
+        ("bin", Some(bin_index.to_string())),
+        ("offset", Some(bin.offset.to_string())),
+        (
+            "uncompressed_offset",
+            bin.uncompressed_offset.map(|offset| offset.to_string()),
+        ),
+        ("cumulative_queries", Some(bin.num_queries.to_string())),
+        ("cumulative_reads", Some(bin.num_reads.to_string())),
+        ("first_qname", qname_range.map(|(first, _)| text(first))),
+        ("last_qname", qname_range.map(|(_, last)| text(last))),
+        (
+            "max_read_length",
+            bin.max_read_length.map(|length| length.to_string()),
+        ),
+    ]
+}
+
+/// Format every bin of the split-index as a TSV table (whose header starts with "#" in plain
+/// format) or a JSON array of objects.
+fn dump_bins(split_index: &SplitIndex, format: TellFormat) -> String {
+    let bins = split_index
+        .bins()
+        .iter()
+        .enumerate()
+        .map(|(bin_index, bin)| bin_fields(bin_index, bin));
+    if format == TellFormat::Json {
+        let objects: Vec<String> = bins
+            .map(|fields| {
+                let fields: Vec<String> = fields
+                    .into_iter()
+                    .map(|(name, value)| {
+                        let value = match value {
+                            Some(qname) if name.ends_with("_qname") => json_string(&qname),
+                            Some(value) => value,
+                            None => "null".to_string(),
+                        };
+                        format!("\"{name}\": {value}")
+                    })
+                    .collect();
+                format!("{{{}}}", fields.join(", "))
+            })
+            .collect();
+        return format!("[{}]", objects.join(", "));
+    }
+    let header = match format {
+        TellFormat::Plain => format!("#{BINS_TSV_HEADER}"),
+        _ => BINS_TSV_HEADER.to_string(),
+    };
+    let rows = bins.map(|fields| {
+        let values: Vec<String> = fields
+            .into_iter()
+            .map(|(_, value)| value.unwrap_or_else(|| "NA".to_string()))
+            .collect();
+        values.join("\t")
+    });
+    std::iter::once(header)
+        .chain(rows)
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+/// Get the number of chunks that splits the indexed file into chunks of about queries_per_chunk
+/// query groups, or else of about bytes_per_chunk bytes, or None if neither is given.
+pub(crate) fn num_chunks_for_size(
+    split_index: &SplitIndex,
+    queries_per_chunk: Option<NonZero<usize>>,
+    bytes_per_chunk: Option<NonZero<u64>>,
+) -> Result<Option<NonZero<usize>>> {
+    let num_chunks = match (queries_per_chunk, bytes_per_chunk) {
+        (Some(queries_per_chunk), _) => split_index.num_queries().div_ceil(queries_per_chunk.get()),
+        (_, Some(bytes_per_chunk)) => {
+            let file_size = split_index
+                .fingerprint()
+                .ok_or_else(|| {
+                    anyhow!(
+                        "Index does not record the file size, so cannot plan chunks by bytes. \
+                        Use --queries-per-chunk."
+                    )
+                })?
+                .size;
+            usize::try_from(file_size.div_ceil(bytes_per_chunk.get())).unwrap_or(usize::MAX)
+        }
+        _ => return Ok(None),
+    };
+    // more chunks than queries would only add empty chunks
+    Ok(Some(
+        NonZero::new(num_chunks.min(split_index.num_queries())).unwrap_or(NonZero::<usize>::MIN),
+    ))
 }
 
 /// Tell some basic stats as derived from a split-index file.
@@ -22,20 +313,211 @@ pub(crate) struct Tell {
     #[clap(long, short = 'I', required = true)]
     index: PathBuf,
 
-    /// Number of bins to retain in final index file.
+    /// Which statistic to print in plain format. "read-groups" prints each read group ID and its
+    /// number of reads, one per line; "sections" prints the name of each section of the index.
     #[clap(long, short = 't', required = false, default_value_t, value_enum)]
     tell: TellWhich,
+
+    /// Output format. "plain" prints only the --tell value, "json" and "tsv" print all
+    /// statistics (bins, queries, reads, offsets of the first and last bins, mean queries per bin).
+    #[clap(long, short = 'f', required = false, default_value_t, value_enum)]
+    format: TellFormat,
+
+    /// Instead of index statistics, recommend a number of chunks for the target
+    /// --queries-per-chunk or --bytes-per-chunk, and print a table of the planned chunks. In plain
+    /// format, the first line is the recommended number of chunks.
+    #[clap(long, required = false, default_value_t = false)]
+    suggest_chunks: bool,
+
+    /// Target number of query groups per chunk for --suggest-chunks.
+    #[clap(long, required = false, default_value = None, requires = "suggest_chunks", conflicts_with = "bytes_per_chunk")]
+    queries_per_chunk: Option<NonZero<usize>>,
+
+    /// Target number of bytes of the indexed file per chunk for --suggest-chunks. Requires an index
+    /// that recorded the file size.
+    #[clap(long, required = false, default_value = None, requires = "suggest_chunks")]
+    bytes_per_chunk: Option<NonZero<u64>>,
+
+    /// Give every query group holding an ultra-long read a chunk of its own in --suggest-chunks,
+    /// so no other chunk holds one. The index bins with such reads are found by their longest read,
+    /// then scanned in --input to find the query groups. The first line of plain output is then the
+    /// number of planned chunks, to be extracted with --query-start and --query-count. Requires an
+    /// index built with --max-read-lengths.
+    #[clap(
+        long,
+        required = false,
+        default_value_t = false,
+        requires_all = ["suggest_chunks", "input"]
+    )]
+    isolate_long_reads: bool,
+
+    /// Indexed reads file, scanned by --isolate-long-reads.
+    #[clap(long, short = 'i', required = false, default_value = None)]
+    input: Option<PathBuf>,
+
+    /// Reference FASTA (required for CRAM --input)
+    #[clap(long, short = 'R', required = false, default_value = None)]
+    ref_fasta: Option<PathBuf>,
+
+    /// Reads longer than this count as ultra-long, for warnings and --isolate-long-reads. Defaults
+    /// to 10 times the median over index bins of their longest read.
+    #[clap(long, required = false, default_value = None)]
+    long_read_length: Option<NonZero<usize>>,
+
+    /// Instead of index statistics, print every bin of the index: its file offset (and offset
+    /// into the uncompressed stream, if recorded), the cumulative numbers of queries and reads at
+    /// its end, and its first and last query names and longest read, if recorded. Plain and TSV
+    /// formats print a table, JSON an array of objects.
+    #[clap(
+        long,
+        required = false,
+        default_value_t = false,
+        conflicts_with = "suggest_chunks"
+    )]
+    dump_bins: bool,
 }
 
 impl Tell {
-    /// Build the split index, then downsize to the requested number of bins and write to requested
-    /// output path
+    /// Get the recommended number of chunks for the requested target chunk size.
+    fn suggested_num_chunks(&self, split_index: &SplitIndex) -> Result<NonZero<usize>> {
+        num_chunks_for_size(split_index, self.queries_per_chunk, self.bytes_per_chunk)?.ok_or_else(
+            || anyhow!("--suggest-chunks requires --queries-per-chunk or --bytes-per-chunk."),
+        )
+    }
+
+    /// Find the 0-based indices of the query groups holding a read longer than long_read_length,
+    /// scanning the bins of --input whose longest read is.
+    fn long_queries(
+        &self,
+        split_index: &SplitIndex,
+        long_read_length: usize,
+    ) -> Result<Vec<usize>> {
+        let input = self
+            .input
+            .clone()
+            .ok_or_else(|| anyhow!("--isolate-long-reads requires --input."))?;
+        let record_type = RecordType::detect(&input)?.ok_or_else(|| {
+            anyhow!("Input type must be FASTQ or SAM/BAM/CRAM. Cannot read from stdin.")
+        })?;
+        let one_thread = NonZero::<usize>::MIN;
+        if record_type.is_sam_family() {
+            let mut reader = get_sam_reader(input, self.ref_fasta.clone(), one_thread)?;
+            Self::long_queries_with(&mut reader, split_index, long_read_length)
+        } else {
+            let mut reader = get_fastq_reader(input, one_thread)?;
+            Self::long_queries_with(&mut reader, split_index, long_read_length)
+        }
+    }
+
+    /// Find the query groups holding a read longer than long_read_length with the supplied reader.
+    fn long_queries_with<R, Reader>(
+        reader: &mut Reader,
+        split_index: &SplitIndex,
+        long_read_length: usize,
+    ) -> Result<Vec<usize>>
+    where
+        R: ChunkableRecord,
+        Reader: ChunkableRecordReader<R>,
+    {
+        let mut long_queries = Vec::new();
+        for (start_query, _) in split_index.long_read_bins(long_read_length) {
+            let split_range = split_index
+                .get_record_for_num_queries(start_query + 1)
+                .ok_or_else(|| anyhow!("Query {start_query} is past the end of the index."))?;
+            long_queries.extend(reader.find_long_queries(&split_range, long_read_length)?);
+        }
+        Ok(long_queries)
+    }
+
+    /// Print the recommended number of chunks and the table of planned chunks.
+    fn suggest_chunks(&self, split_index: &SplitIndex) -> Result<()> {
+        let mut num_chunks = self.suggested_num_chunks(split_index)?;
+        let chunk_plans = if self.isolate_long_reads {
+            let long_read_length = self
+                .long_read_length
+                .map(NonZero::get)
+                .or_else(|| split_index.long_read_threshold())
+                .ok_or_else(|| {
+                    anyhow!(
+                        "Index does not record read lengths, so cannot isolate long reads. \
+                        Rebuild it with `split-reads index --max-read-lengths`."
+                    )
+                })?;
+            let long_queries = self.long_queries(split_index, long_read_length)?;
+            let chunk_plans =
+                ChunkPlan::plan_isolating_chunks(split_index, num_chunks, &long_queries)?;
+            num_chunks = NonZero::new(chunk_plans.len()).unwrap_or(num_chunks);
+            chunk_plans
+        } else {
+            let chunk_plans = ChunkPlan::plan_chunks(split_index, num_chunks)?;
+            for chunk_plan in chunk_plans.iter() {
+                if let Some(warning) = GetChunk::long_read_warning(
+                    split_index,
+                    chunk_plan.start_query,
+                    chunk_plan.stop_query,
+                    self.long_read_length,
+                ) {
+                    warn!("Chunk {}: {warning}", chunk_plan.chunk_index);
+                }
+            }
+            chunk_plans
+        };
+        match self.format {
+            TellFormat::Plain => {
+                println!("{num_chunks}");
+                println!("#{}", ChunkPlan::TSV_HEADER);
+                for chunk_plan in chunk_plans.iter() {
+                    println!("{}", chunk_plan.to_tsv());
+                }
+            }
+            TellFormat::Json => {
+                let chunks: Vec<String> = chunk_plans.iter().map(ChunkPlan::to_json).collect();
+                println!(
+                    "{{\"num_chunks\": {num_chunks}, \"chunks\": [{}]}}",
+                    chunks.join(", ")
+                );
+            }
+            TellFormat::Tsv => {
+                println!("{}", ChunkPlan::TSV_HEADER);
+                for chunk_plan in chunk_plans.iter() {
+                    println!("{}", chunk_plan.to_tsv());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Read the split index, then print the requested statistics.
     fn tell(&self) -> Result<()> {
         let split_index = SplitIndex::read(self.index.clone())?;
-        match self.tell.clone() {
-            TellWhich::NumBins => println!("{}", split_index.len()),
-            TellWhich::NumQueries => println!("{}", split_index.num_queries()),
-            TellWhich::NumReads => println!("{}", split_index.num_reads()),
+        if self.suggest_chunks {
+            return self.suggest_chunks(&split_index);
+        }
+        if self.dump_bins {
+            println!("{}", dump_bins(&split_index, self.format));
+            return Ok(());
+        }
+        match self.format {
+            TellFormat::Plain => match self.tell.clone() {
+                TellWhich::NumBins => println!("{}", split_index.len()),
+                TellWhich::NumQueries => println!("{}", split_index.num_queries()),
+                TellWhich::NumReads => println!("{}", split_index.num_reads()),
+                TellWhich::Sections => {
+                    for section in split_index.sections() {
+                        println!("{}", section.name());
+                    }
+                }
+                TellWhich::ReadGroups => {
+                    if split_index.read_groups().is_empty() {
+                        warn!("Index has no read group counts.");
+                    }
+                    for (read_group, count) in split_index.read_groups() {
+                        println!("{}\t{count}", String::from_utf8_lossy(read_group));
+                    }
+                }
+            },
+            TellFormat::Json => println!("{}", IndexSummary::new(&split_index).to_json()),
+            TellFormat::Tsv => println!("{}", IndexSummary::new(&split_index).to_tsv()),
         }
         Ok(())
     }
@@ -48,3 +530,218 @@ impl Command for Tell {
         self.tell()
     }
 }
+
+#[cfg(all(test, feature = "synthetic"))]
+mod tests {
+    use super::{ChunkPlan, IndexSummary, Tell, TellFormat, dump_bins};
+    use crate::{
+        commands::index::Index,
+        test_utils::random_bam::{QueryType, RandomBam},
+    };
+    use anyhow::Result;
+    use clap::Parser;
+    use split_reads::{
+        fastq::{FastqReader, FastqRecord, FastqWriter},
+        split_index::SplitIndex,
+    };
+    use std::{io::Cursor, num::NonZero, path::PathBuf};
+    use tempfile::TempDir;
+
+    /// Test that JSON and TSV output include every statistic, with missing values marked.
+    #[test]
+    fn test_index_summary_formats() {
+        let summary = IndexSummary {
+            num_bins: 4,
+            num_queries: 10,
+            num_reads: 20,
+            first_offset: Some(0),
+            last_offset: None,
+            mean_queries_per_bin: 2.5,
+        };
+        assert_eq!(
+            summary.to_json(),
+            "{\"num_bins\": 4, \"num_queries\": 10, \"num_reads\": 20, \"first_offset\": 0, \
+            \"last_offset\": null, \"mean_queries_per_bin\": 2.5}"
+        );
+        assert_eq!(
+            summary.to_tsv(),
+            "num_bins\t4\nnum_queries\t10\nnum_reads\t20\nfirst_offset\t0\nlast_offset\tNA\n\
+            mean_queries_per_bin\t2.5"
+        );
+    }
+
+    /// Test that suggested chunks meet the target size and cover every query and read.
+    #[test]
+    fn test_suggest_chunks() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let temp_path: PathBuf = temp_dir.path().to_path_buf();
+        let (random_bam, num_reads) = QueryType::Paired.random_bam(&temp_path, 100)?;
+        let bam_str = random_bam.to_str().unwrap();
+        Index::try_parse_from(["index", "--input", bam_str, "--num-bins", "10"])?.index_reads()?;
+        let index = format!("{bam_str}.si");
+        let split_index = SplitIndex::read(&index)?;
+
+        let tell = Tell::try_parse_from([
+            "tell",
+            "-I",
+            &index,
+            "--suggest-chunks",
+            "--queries-per-chunk",
+            "30",
+        ])?;
+        let num_chunks = tell.suggested_num_chunks(&split_index)?;
+        assert_eq!(num_chunks.get(), 4);
+        let chunk_plans = ChunkPlan::plan_chunks(&split_index, num_chunks)?;
+        let starts: Vec<usize> = chunk_plans.iter().map(|plan| plan.start_query).collect();
+        assert_eq!(starts, [0, 25, 50, 75]);
+        assert_eq!(chunk_plans.last().unwrap().stop_query, 100);
+        // paired reads, so exactly two reads per query
+        assert!(chunk_plans.iter().all(|plan| plan.approx_reads == 50));
+        assert_eq!(
+            chunk_plans
+                .iter()
+                .map(|plan| plan.approx_reads)
+                .sum::<usize>(),
+            num_reads
+        );
+        assert!(chunk_plans.iter().all(|plan| plan.approx_bytes.is_some()));
+
+        let file_size = split_index.fingerprint().unwrap().size;
+        let tell = Tell::try_parse_from([
+            "tell",
+            "-I",
+            &index,
+            "--suggest-chunks",
+            "--bytes-per-chunk",
+            &file_size.div_ceil(4).to_string(),
+        ])?;
+        assert_eq!(tell.suggested_num_chunks(&split_index)?.get(), 4);
+
+        assert!(
+            Tell::try_parse_from(["tell", "-I", &index, "--suggest-chunks"])?
+                .suggested_num_chunks(&split_index)
+                .is_err()
+        );
+        Ok(())
+    }
+
+    /// Test that --isolate-long-reads gives the query group with an ultra-long read a chunk of its
+    /// own, without the rest of its bin, and requires an index with read lengths and the input.
+    #[test]
+    fn test_isolate_long_reads() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let fastq = temp_dir.path().join("reads.fastq");
+        let text: String = (0..100)
+            .map(|index| {
+                let length = if index == 42 { 1000 } else { 10 };
+                format!(
+                    "@read{index:03}\n{}\n+\n{}\n",
+                    "A".repeat(length),
+                    "I".repeat(length)
+                )
+            })
+            .collect();
+        std::fs::write(&fastq, text)?;
+        let fastq_str = fastq.to_str().unwrap();
+        let index = format!("{fastq_str}.si");
+        let index_args = ["index", "--input", fastq_str, "--num-bins", "10"];
+        Index::try_parse_from(index_args)?.index_reads()?;
+        let tell_args = [
+            "tell",
+            "-I",
+            &index,
+            "--suggest-chunks",
+            "--queries-per-chunk",
+            "50",
+            "--isolate-long-reads",
+            "-i",
+            fastq_str,
+        ];
+        assert!(Tell::try_parse_from(tell_args)?.tell().is_err());
+        assert!(Tell::try_parse_from(&tell_args[..tell_args.len() - 2]).is_err());
+
+        std::fs::remove_file(&index)?;
+        Index::try_parse_from(index_args.into_iter().chain(["--max-read-lengths"]))?
+            .index_reads()?;
+        let split_index = SplitIndex::read(&index)?;
+        let tell = Tell::try_parse_from(tell_args)?;
+        let num_chunks = tell.suggested_num_chunks(&split_index)?;
+        let long_queries = tell.long_queries(&split_index, 100)?;
+        assert_eq!(long_queries, [42]);
+        let chunk_plans =
+            ChunkPlan::plan_isolating_chunks(&split_index, num_chunks, &long_queries)?;
+        let long_chunks: Vec<&ChunkPlan> = chunk_plans
+            .iter()
+            .filter(|plan| plan.start_query <= 42 && 42 < plan.stop_query)
+            .collect();
+        assert_eq!(long_chunks.len(), 1);
+        assert_eq!(
+            (long_chunks[0].start_query, long_chunks[0].stop_query),
+            (42, 43)
+        );
+        // the rest of the long read's bin (queries 40 to 49) stays with neighboring chunks
+        assert!(chunk_plans.iter().any(|plan| plan.stop_query == 42));
+        assert!(chunk_plans.iter().any(|plan| plan.start_query == 43));
+        assert_eq!(chunk_plans.first().unwrap().start_query, 0);
+        assert_eq!(chunk_plans.last().unwrap().stop_query, 100);
+        assert!(
+            chunk_plans
+                .windows(2)
+                .all(|pair| pair[0].stop_query == pair[1].start_query)
+        );
+        Ok(())
+    }
+
+    /// Test that dumped bins have a row or object per bin, ending at the total query and read
+    /// counts.
+    #[test]
+    fn test_dump_bins() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let (random_bam, num_reads) = QueryType::Paired.random_bam(&temp_dir.path(), 100)?;
+        let bam_str = random_bam.to_str().unwrap();
+        let index = Index::try_parse_from(["index", "-i", bam_str, "-n", "10", "--qnames"])?
+            .index_reads()?;
+        let split_index = SplitIndex::read(index)?;
+        assert!(
+            Tell::try_parse_from(["tell", "-I", bam_str, "--dump-bins", "--suggest-chunks"])
+                .is_err()
+        );
+
+        let tsv = dump_bins(&split_index, TellFormat::Tsv);
+        let rows: Vec<Vec<&str>> = tsv.lines().map(|line| line.split('\t').collect()).collect();
+        assert_eq!(rows.len(), split_index.len() + 1);
+        assert_eq!(rows[0][3], "cumulative_queries");
+        let last = rows.last().unwrap();
+        assert_eq!(last[3], split_index.num_queries().to_string());
+        assert_eq!(last[4], num_reads.to_string());
+        assert_ne!(rows[1][5], "NA");
+        assert!(dump_bins(&split_index, TellFormat::Plain).starts_with("#bin\toffset\t"));
+
+        let json = dump_bins(&split_index, TellFormat::Json);
+        assert_eq!(json.matches("\"bin\": ").count(), split_index.len());
+        assert!(json.starts_with("[{\"bin\": 0, \"offset\": "));
+        assert!(json.contains("\"first_qname\": \""));
+        Ok(())
+    }
+
+    /// Test that query names of dumped bins are JSON-escaped in JSON, and written as they are in
+    /// TSV.
+    #[test]
+    fn test_dump_bins_escaping() -> Result<()> {
+        let fastq = "@read\"1\\é\nACGT\n+\nIIII\n";
+        let split_index = SplitIndex::build::<FastqRecord, _, FastqWriter<Vec<u8>>>(
+            FastqReader::new(Cursor::new(fastq)),
+            None,
+            NonZero::new(1).unwrap(),
+            u64::MAX,
+        )?;
+        let json = dump_bins(&split_index, TellFormat::Json);
+        assert!(
+            json.contains(r#""first_qname": "read\"1\\é", "last_qname": "read\"1\\é""#),
+            "{json}"
+        );
+        let tsv = dump_bins(&split_index, TellFormat::Tsv);
+        assert!(tsv.contains("\tread\"1\\é\tread\"1\\é\t"), "{tsv}");
+        Ok(())
+    }
+}
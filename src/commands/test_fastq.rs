@@ -28,14 +28,18 @@ impl TestFastq {
         let mut reader = FastqReader::new(buf);
         let mut num_records: usize = 0;
         let mut num_queries: usize = 0;
-        let mut qname = reader.next().ok_or_else(|| anyhow!("No records"))??.name;
+        let mut qname = reader
+            .next()
+            .ok_or_else(|| anyhow!("No records"))??
+            .qname()
+            .to_vec();
         num_records += 1;
         num_queries += 1;
         for record in reader {
             let record = record?;
             num_records += 1;
-            if record.name != qname {
-                qname = record.name;
+            if record.qname() != qname {
+                qname = record.qname().to_vec();
                 num_queries += 1;
             }
         }
@@ -0,0 +1,134 @@
+use anyhow::Result;
+use clap::{Args, value_parser};
+use split_reads::{
+    read_filter::{ReadFilter, parse_flags},
+    transform::{MAX_ADAPTER_MISMATCHES, Transform, TransformPipeline, Trim, parse_whitespace},
+    translate_options::NameRewrite,
+};
+
+/// Options filtering and transforming the reads written by commands that extract them, shared
+/// so each command spells them the same way.
+#[derive(Args, Clone, Debug, PartialEq)]
+pub(crate) struct TransformArgs {
+    /// Only write reads with at least this many bases.
+    #[clap(long, required = false, default_value = None)]
+    min_length: Option<usize>,
+
+    /// Only write reads whose mean Phred base quality is at least this. Reads without qualities
+    /// are removed.
+    #[clap(long, required = false, default_value = None)]
+    min_mean_qual: Option<f64>,
+
+    /// Only write reads with all of these SAM flags set, in decimal or hexadecimal (e.g. "0x40").
+    /// Requires SAM/BAM/CRAM input.
+    #[clap(long, short = 'f', required = false, default_value = None, value_parser = parse_flags)]
+    include_flags: Option<u16>,
+
+    /// Only write reads with none of these SAM flags set, in decimal or hexadecimal (e.g.
+    /// "0x900"). Requires SAM/BAM/CRAM input.
+    #[clap(long, short = 'F', required = false, default_value = None, value_parser = parse_flags)]
+    exclude_flags: Option<u16>,
+
+    /// Trim this many bases from the start of every read.
+    #[clap(long, required = false, default_value_t = 0)]
+    trim_start: usize,
+
+    /// Trim this many bases from the end of every read.
+    #[clap(long, required = false, default_value_t = 0)]
+    trim_end: usize,
+
+    /// Trim this adapter sequence, and everything after it, from the 3' end of reads. The adapter
+    /// is found where the end of the read matches its start, by at least 3 bases. Reads are
+    /// trimmed before they are filtered. Aligned reads can't be trimmed.
+    #[clap(long, required = false, default_value = None)]
+    adapter: Option<String>,
+
+    /// Mismatches allowed when matching the adapter, in overlaps of at least 10 bases (shorter
+    /// overlaps must match exactly).
+    #[clap(long, required = false, default_value_t = 1, value_parser = value_parser!(u8).range(..=MAX_ADAPTER_MISMATCHES as i64), requires = "adapter")]
+    adapter_mismatches: u8,
+
+    /// Add this prefix to every read name, e.g. "sampleA_".
+    #[clap(long, required = false, default_value = None)]
+    rename_prefix: Option<String>,
+
+    /// Remove FASTQ comments (header text after the read name).
+    #[clap(long, required = false, default_value_t = false)]
+    strip_comment: bool,
+
+    /// Replace whitespace in read names with this character, joining any FASTQ comment to the
+    /// name (unless it is stripped), e.g. "_".
+    #[clap(long, required = false, default_value = None, value_parser = parse_whitespace)]
+    replace_whitespace: Option<u8>,
+
+    /// Add "/1" or "/2" to the names of reads flagged as first or second of a pair. Requires
+    /// FASTQ output.
+    #[clap(long, required = false, default_value_t = false)]
+    pair_suffix: bool,
+
+    /// Apply a built-in transform to every read, after the options above. Repeat to chain
+    /// transforms, which are applied in the order given. Each is NAME or NAME:KEY=VALUE,...:
+    /// "filter" (keys min-length, min-mean-qual, include-flags, exclude-flags), "trim" (keys
+    /// start, end, adapter, mismatches), "rename" (keys prefix, strip-comment, whitespace,
+    /// pair-suffix), or "uppercase".
+    #[clap(long = "transform", required = false, value_name = "SPEC")]
+    transforms: Vec<Transform>,
+}
+
+impl TransformArgs {
+    /// Get the transforms of the reads written: trimming, then filtering, then any chained
+    /// transforms. None if no transform option was given.
+    pub(crate) fn transforms(&self) -> Result<Option<TransformPipeline>> {
+        let mut trim = Trim::new();
+        trim.start(self.trim_start)
+            .end(self.trim_end)
+            .adapter(self.adapter.as_deref().map(str::as_bytes))
+            .max_mismatches(self.adapter_mismatches as usize)?;
+        let mut read_filter = ReadFilter::new();
+        read_filter
+            .min_length(self.min_length.unwrap_or(0))
+            .min_mean_qual(self.min_mean_qual)
+            .include_flags(self.include_flags.unwrap_or(0))
+            .exclude_flags(self.exclude_flags.unwrap_or(0));
+        let mut transforms = TransformPipeline::new();
+        transforms
+            .push(Transform::Trim(trim))
+            .push(Transform::Filter(read_filter));
+        for transform in &self.transforms {
+            transforms.push(transform.clone());
+        }
+        Ok((!transforms.is_empty()).then_some(transforms))
+    }
+
+    /// Get the rewriting of the names of the reads written. Names are rewritten last, after any
+    /// transforms.
+    pub(crate) fn name_rewrite(&self) -> NameRewrite {
+        NameRewrite::new()
+            .strip_comment(self.strip_comment)
+            .whitespace(self.replace_whitespace)
+            .pair_suffix(self.pair_suffix)
+            .prefix(self.rename_prefix.as_deref().unwrap_or_default().as_bytes())
+            .to_owned()
+    }
+}
+
+/// Default to the command line defaults: no filters or transforms.
+impl Default for TransformArgs {
+    fn default() -> Self {
+        TransformArgs {
+            min_length: None,
+            min_mean_qual: None,
+            include_flags: None,
+            exclude_flags: None,
+            trim_start: 0,
+            trim_end: 0,
+            adapter: None,
+            adapter_mismatches: 1,
+            rename_prefix: None,
+            strip_comment: false,
+            replace_whitespace: None,
+            pair_suffix: false,
+            transforms: Vec::new(),
+        }
+    }
+}
@@ -0,0 +1,164 @@
+use crate::commands::command::Command;
+use anyhow::{Result, anyhow};
+use clap::Parser;
+use log::{error, info};
+use split_reads::{
+    error_category::{Categorize, ErrorCategory},
+    manifest::{ChunkChecksum, ManifestEntry},
+};
+use std::path::{Path, PathBuf};
+
+/// Check copies of extracted chunks against the sizes and checksums recorded when they were
+/// written, e.g. after transferring them to or from cloud storage. Chunks are checked against
+/// their entries in a get-chunk --manifest, or without one, against the .md5 files written by
+/// get-chunk --write-md5. Chunks may be local files or URLs. Fails if any chunk doesn't match.
+#[derive(Parser, Debug)]
+#[command(version, verbatim_doc_comment)]
+pub(crate) struct VerifyChunk {
+    /// Chunk files or URLs to verify.
+    #[clap(required = true)]
+    chunks: Vec<PathBuf>,
+
+    /// Manifest written by get-chunk --manifest. Each chunk is checked against the entry with its
+    /// path, or if none has it (e.g. the chunk was moved), the entry with its file name.
+    #[clap(long, short = 'm', required = false, default_value = None)]
+    manifest: Option<PathBuf>,
+}
+
+impl VerifyChunk {
+    /// Find the manifest entry of chunk: by path, or else by file name if exactly one entry has it.
+    fn find_entry<'a>(entries: &'a [ManifestEntry], chunk: &Path) -> Result<&'a ManifestEntry> {
+        if let Some(entry) = entries.iter().find(|entry| entry.path() == chunk) {
+            return Ok(entry);
+        }
+        let mut named = entries
+            .iter()
+            .filter(|entry| entry.path().file_name() == chunk.file_name());
+        match (named.next(), named.next()) {
+            (Some(entry), None) => Ok(entry),
+            (Some(_), Some(_)) => Err(anyhow!(
+                "Several manifest entries are named like {chunk:?}. Verify it by its full path."
+            )),
+            (None, _) => Err(anyhow!("Chunk {chunk:?} is not in the manifest.")),
+        }
+    }
+
+    /// Verify one chunk against its manifest entry, or its MD5 sidecar if there is no manifest.
+    fn verify_chunk(&self, entries: Option<&[ManifestEntry]>, chunk: &Path) -> Result<()> {
+        let checksum = ChunkChecksum::from_path(chunk)?;
+        match entries {
+            Some(entries) => Self::find_entry(entries, chunk)?.verify(&checksum),
+            None => {
+                let md5 = ChunkChecksum::read_sidecar(chunk)?;
+                if md5 == checksum.md5 {
+                    Ok(())
+                } else {
+                    Err(anyhow!(
+                        "Chunk {chunk:?} does not match its .md5 file: md5 {}, expected {md5}",
+                        checksum.md5
+                    ))
+                    .categorize(ErrorCategory::ChecksumMismatch)
+                }
+            }
+        }
+    }
+
+    /// Verify every chunk, logging each failure, and return the first error if any failed.
+    fn verify(&self) -> Result<()> {
+        let entries = self
+            .manifest
+            .as_ref()
+            .map(ManifestEntry::read_all)
+            .transpose()?;
+        let mut first_error = None;
+        for chunk in &self.chunks {
+            match self.verify_chunk(entries.as_deref(), chunk) {
+                Ok(()) => info!("{chunk:?}: OK"),
+                Err(err) => {
+                    error!("{chunk:?}: {err}");
+                    first_error.get_or_insert(err);
+                }
+            }
+        }
+        first_error.map_or(Ok(()), Err)
+    }
+}
+
+/// Implement the Command trait for `VerifyChunk` struct.
+impl Command for VerifyChunk {
+    /// Execute the verify-chunk command to check chunks against their recorded checksums.
+    fn execute(&self) -> Result<()> {
+        self.verify()
+    }
+}
+
+#[cfg(all(test, feature = "synthetic"))]
+mod tests {
+    use super::VerifyChunk;
+    use crate::{
+        commands::{command::Command, get_chunk::GetChunk, index::Index},
+        test_utils::random_bam::{QueryType, RandomBam},
+    };
+    use anyhow::Result;
+    use clap::Parser;
+    use split_reads::error_category::ErrorCategory;
+    use std::fs;
+    use tempfile::TempDir;
+
+    /// Test verifying chunks against the manifest and their .md5 files, after moving them, and
+    /// that a corrupted copy fails with a checksum mismatch.
+    #[test]
+    fn test_verify_chunk() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let (random_bam, _) = QueryType::Paired.random_bam(&temp_dir.path(), 100)?;
+        let bam_str = random_bam.to_str().unwrap();
+        Index::try_parse_from(["index", "-i", bam_str, "-n", "10"])?.index_reads()?;
+        let manifest = temp_dir.path().join("manifest.jsonl");
+        let manifest_str = manifest.to_str().unwrap();
+        let chunks: Vec<String> = (0..2)
+            .map(|chunk_index| {
+                let chunk = temp_dir.path().join(format!("chunk{chunk_index}.bam"));
+                GetChunk::try_parse_from([
+                    "get-chunk",
+                    "-i",
+                    bam_str,
+                    "-c",
+                    &chunk_index.to_string(),
+                    "-n",
+                    "2",
+                    "-o",
+                    chunk.to_str().unwrap(),
+                    "--manifest",
+                    manifest_str,
+                    "--write-md5",
+                ])?
+                .execute()?;
+                Ok(chunk.to_str().unwrap().to_string())
+            })
+            .collect::<Result<_>>()?;
+        assert!(fs::read_to_string(format!("{}.md5", chunks[0]))?.ends_with("  chunk0.bam\n"));
+        let verify = |args: &[&str]| -> Result<()> {
+            VerifyChunk::try_parse_from([&["verify-chunk"], args].concat())?.verify()
+        };
+        verify(&["-m", manifest_str, &chunks[0], &chunks[1]])?;
+        verify(&[&chunks[0], &chunks[1]])?;
+
+        // a copy elsewhere is found in the manifest by its file name
+        let copy_dir = temp_dir.path().join("copies");
+        fs::create_dir(&copy_dir)?;
+        let copy = copy_dir.join("chunk1.bam");
+        let copy_str = copy.to_str().unwrap();
+        fs::copy(&chunks[1], &copy)?;
+        verify(&["-m", manifest_str, copy_str])?;
+
+        let mut bytes = fs::read(&copy)?;
+        let last = bytes.len() - 30;
+        bytes[last] ^= 0xff;
+        fs::write(&copy, bytes)?;
+        let err = verify(&["-m", manifest_str, &chunks[0], copy_str]).unwrap_err();
+        assert_eq!(ErrorCategory::of(&err), ErrorCategory::ChecksumMismatch);
+        assert!(verify(&[copy_str]).is_err());
+        assert!(verify(&["-m", manifest_str, bam_str]).is_err());
+        Ok(())
+    }
+}
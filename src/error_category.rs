@@ -0,0 +1,178 @@
+use crate::manifest::json_string;
+use anyhow::{Error, Result};
+use std::{error::Error as StdError, fmt, io};
+
+/// Category of a failure, deciding the exit code of the command line tool, so that workflow
+/// engines can tell failures worth retrying (e.g. remote IO) from ones that are not (e.g. a
+/// corrupt index).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrorCategory {
+    /// Any failure not in another category
+    Other,
+    /// The split-index is corrupt, of an unknown version, or does not match the reads file
+    BadIndex,
+    /// The reads file ends before the records the index expects
+    TruncatedInput,
+    /// Opening or transferring a remote URL failed, which may succeed if retried
+    RemoteIo,
+    /// Local file IO failed, e.g. a missing file or a full disk
+    Io,
+    /// A chunk's size or checksum doesn't match what was recorded when it was written
+    ChecksumMismatch,
+}
+
+impl ErrorCategory {
+    /// Exit code of the command line tool. 2 is left for invalid arguments, as clap uses it.
+    pub fn exit_code(self) -> u8 {
+        match self {
+            ErrorCategory::Other => 1,
+            ErrorCategory::BadIndex => 3,
+            ErrorCategory::TruncatedInput => 4,
+            ErrorCategory::RemoteIo => 5,
+            ErrorCategory::Io => 6,
+            ErrorCategory::ChecksumMismatch => 7,
+        }
+    }
+
+    /// Name for machine-readable error reports
+    pub fn name(self) -> &'static str {
+        match self {
+            ErrorCategory::Other => "other",
+            ErrorCategory::BadIndex => "bad-index",
+            ErrorCategory::TruncatedInput => "truncated-input",
+            ErrorCategory::RemoteIo => "remote-io",
+            ErrorCategory::Io => "io",
+            ErrorCategory::ChecksumMismatch => "checksum-mismatch",
+        }
+    }
+
+    /// Category of failing to open or read the requested path: remote IO for URLs, else local IO
+    pub fn for_path(path: &str) -> Self {
+        if path.contains("://") {
+            ErrorCategory::RemoteIo
+        } else {
+            ErrorCategory::Io
+        }
+    }
+
+    /// Get the category of an error: the innermost category given with Categorize::categorize,
+    /// or else one guessed from any IO error it was caused by.
+    pub fn of(err: &Error) -> Self {
+        let categorized = err
+            .chain()
+            .filter_map(|cause| cause.downcast_ref::<CategorizedError>())
+            .last();
+        if let Some(categorized) = categorized {
+            return categorized.category;
+        }
+        match err
+            .chain()
+            .find_map(|cause| cause.downcast_ref::<io::Error>())
+        {
+            Some(io_err) if io_err.kind() == io::ErrorKind::UnexpectedEof => {
+                ErrorCategory::TruncatedInput
+            }
+            Some(_) => ErrorCategory::Io,
+            None => ErrorCategory::Other,
+        }
+    }
+}
+
+/// Error tagged with its category. Displays as the error itself, so tagging doesn't change error
+/// messages.
+#[derive(Debug)]
+struct CategorizedError {
+    category: ErrorCategory,
+    error: Error,
+}
+
+impl fmt::Display for CategorizedError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&self.error, f)
+    }
+}
+
+impl StdError for CategorizedError {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        self.error.source()
+    }
+}
+
+/// Tag the error of a Result with its ErrorCategory.
+pub trait Categorize<T> {
+    fn categorize(self, category: ErrorCategory) -> Result<T>;
+}
+
+impl<T, E> Categorize<T> for std::result::Result<T, E>
+where
+    E: Into<Error>,
+{
+    fn categorize(self, category: ErrorCategory) -> Result<T> {
+        self.map_err(|error| {
+            Error::new(CategorizedError {
+                category,
+                error: error.into(),
+            })
+        })
+    }
+}
+
+/// Report an error as a JSON object: its category, exit code, message, and the messages of the
+/// errors that caused it, outermost first.
+pub fn error_json(err: &Error) -> String {
+    let category = ErrorCategory::of(err);
+    let context: Vec<String> = err
+        .chain()
+        .skip(1)
+        .map(|cause| json_string(&cause.to_string()))
+        .collect();
+    format!(
+        "{{\"category\": {}, \"exit_code\": {}, \"message\": {}, \"context\": [{}]}}",
+        json_string(category.name()),
+        category.exit_code(),
+        json_string(&err.to_string()),
+        context.join(", ")
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Categorize, ErrorCategory, error_json};
+    use crate::split_index::SplitIndex;
+    use anyhow::{Context, Result, anyhow};
+    use std::{fs, io};
+    use tempfile::TempDir;
+
+    /// Test that categories survive added context without changing messages, and that
+    /// uncategorized IO errors get a category.
+    #[test]
+    fn test_error_category() -> Result<()> {
+        let err = Err::<(), _>(anyhow!("no more records"))
+            .categorize(ErrorCategory::TruncatedInput)
+            .context("Reading chunk 3")
+            .unwrap_err();
+        assert_eq!(ErrorCategory::of(&err), ErrorCategory::TruncatedInput);
+        assert_eq!(format!("{err:#}"), "Reading chunk 3: no more records");
+        assert_eq!(
+            error_json(&err),
+            "{\"category\": \"truncated-input\", \"exit_code\": 4, \"message\": \
+            \"Reading chunk 3\", \"context\": [\"no more records\"]}"
+        );
+
+        let err = Err::<(), _>(io::Error::new(io::ErrorKind::UnexpectedEof, "eof"))
+            .context("Reading")
+            .unwrap_err();
+        assert_eq!(ErrorCategory::of(&err), ErrorCategory::TruncatedInput);
+        let err = anyhow!(io::Error::from(io::ErrorKind::PermissionDenied));
+        assert_eq!(ErrorCategory::of(&err), ErrorCategory::Io);
+        assert_eq!(ErrorCategory::of(&anyhow!("oops")), ErrorCategory::Other);
+
+        let temp_dir = TempDir::new()?;
+        let not_index = temp_dir.path().join("reads.si");
+        fs::write(&not_index, "@read1\nACGT\n+\nIIII\n")?;
+        let err = SplitIndex::read(&not_index).err().unwrap();
+        assert_eq!(ErrorCategory::of(&err), ErrorCategory::BadIndex);
+        assert!(err.to_string().starts_with("Not a split-index"));
+        Ok(())
+    }
+}
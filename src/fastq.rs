@@ -1,10 +1,19 @@
+use crate::atomic_output::AtomicWriter;
+use crate::maybe_compressed_io::MaybeCompressedWriter;
 use crate::seekable_split::Split;
 use anyhow::{Result, anyhow};
 use std::io::{BufRead, Result as IoResult, Seek, Write};
 
+/// Maximum length of any single FASTQ line (name, sequence, separator, or qualities). This matches
+/// the longest sequence a BAM record can hold, and guards against unbounded memory use when a file
+/// that is not FASTQ is read by mistake.
+pub const MAX_LINE_LENGTH: usize = i32::MAX as usize;
+
 /// Struct for holding fastq records
 #[derive(Clone, Debug)]
 pub struct FastqRecord {
+    /// Header line without the leading '@': the query name, optionally followed by whitespace and
+    /// a comment.
     pub name: Vec<u8>,
     pub sequence: Vec<u8>,
     pub separator: Vec<u8>,
@@ -16,6 +25,41 @@ impl FastqRecord {
     pub fn len(&self) -> usize {
         self.sequence.len()
     }
+
+    /// Get the query name: the header up to the first whitespace.
+    pub fn qname(&self) -> &[u8] {
+        match self.name.iter().position(u8::is_ascii_whitespace) {
+            Some(pos) => &self.name[..pos],
+            None => &self.name,
+        }
+    }
+
+    /// Get the query name without a trailing "/1" or "/2" mate suffix, which old-style paired FASTQ
+    /// appends to the names of the first and second reads of a pair.
+    pub fn mate_qname(&self) -> &[u8] {
+        let qname = self.qname();
+        match qname {
+            [name @ .., b'/', b'1' | b'2'] if !name.is_empty() => name,
+            _ => qname,
+        }
+    }
+
+    /// Get the header comment: everything after the first whitespace, if present.
+    pub fn comment(&self) -> Option<&[u8]> {
+        self.name
+            .iter()
+            .position(u8::is_ascii_whitespace)
+            .map(|pos| &self.name[pos + 1..])
+    }
+
+    /// Replace the header comment. An empty comment removes it.
+    pub fn set_comment(&mut self, comment: &[u8]) {
+        self.name.truncate(self.qname().len());
+        if !comment.is_empty() {
+            self.name.push(b'\t');
+            self.name.extend_from_slice(comment);
+        }
+    }
     /// Unused, should never be true, but keeps clippy happy
     pub fn is_empty(&self) -> bool {
         self.sequence.is_empty()
@@ -47,30 +91,56 @@ impl<R: BufRead> FastqReader<R> {
     /// Create new `FastqReader` from base reader object
     pub fn new(reader: R) -> Self {
         FastqReader {
-            split: Split::new(reader, b'\n'),
+            split: Split::new(reader, b'\n').with_max_len(MAX_LINE_LENGTH),
         }
     }
 
+    /// Get a reference to the underlying reader.
+    pub fn get_ref(&self) -> &R {
+        self.split.get_ref()
+    }
+
+    /// Get the offset into the uncompressed stream of the next record, if known. It is only known
+    /// when reading from the start of the stream without seeking.
+    pub fn uncompressed_position(&self) -> Option<u64> {
+        self.split.position()
+    }
+
     /// While reading a record, handle possible missing / incomplete data
-    fn unwrap_next(&mut self) -> Result<Vec<u8>> {
-        match self.split.next() {
-            None => Err(anyhow!("Incomplete fastq record")),
-            Some(Ok(vec)) => Ok(vec),
-            Some(Err(err)) => Err(anyhow!("{err}")),
+    fn read_line_into(&mut self, line: &mut Vec<u8>) -> Result<()> {
+        match self.split.read_next_into(line) {
+            Ok(true) => Ok(()),
+            Ok(false) => Err(anyhow!("Incomplete fastq record")),
+            Err(err) => Err(anyhow!("{err}")),
+        }
+    }
+
+    /// Read the next fastq record into an existing record, reusing its buffers. Return None if
+    /// there are no more records.
+    pub fn read_record_into(&mut self, record: &mut FastqRecord) -> Option<Result<()>> {
+        match self.split.read_next_into(&mut record.name) {
+            Ok(false) => None,
+            Err(err) => Some(Err(anyhow!("{err}"))),
+            Ok(true) => Some(
+                Self::strip_header_prefix(&mut record.name)
+                    .and_then(|_| self.read_line_into(&mut record.sequence))
+                    .and_then(|_| self.read_line_into(&mut record.separator))
+                    .and_then(|_| self.read_line_into(&mut record.qualities)),
+            ),
         }
     }
 
-    /// Get the next fastq record
-    fn next_fastq_record(&mut self, name: Vec<u8>) -> Result<FastqRecord> {
-        let sequence = self.unwrap_next()?;
-        let separator = self.unwrap_next()?;
-        let qualities = self.unwrap_next()?;
-        Ok(FastqRecord {
-            name,
-            sequence,
-            separator,
-            qualities,
-        })
+    /// Remove the leading '@' from a header line, or error if it is missing
+    fn strip_header_prefix(header: &mut Vec<u8>) -> Result<()> {
+        if header.first() == Some(&b'@') {
+            header.remove(0);
+            Ok(())
+        } else {
+            Err(anyhow!(
+                "Malformed fastq header: {}",
+                String::from_utf8_lossy(header)
+            ))
+        }
     }
 }
 
@@ -86,28 +156,38 @@ impl<R: BufRead> Iterator for FastqReader<R> {
     type Item = Result<FastqRecord>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        match self.split.next() {
-            None => None,
-            Some(Err(err)) => Some(Err(anyhow!("{err}"))),
-            Some(Ok(name)) => Some(self.next_fastq_record(name)),
-        }
+        let mut record = FastqRecord::new();
+        self.read_record_into(&mut record)
+            .map(|result| result.map(|_| record))
     }
 }
 
 /// Public struct for writing fastq records
 pub struct FastqWriter<W: Write> {
     inner: W,
+    /// Number of bytes written so far
+    position: u64,
 }
 
 /// impl FastqWriter, just write out the four lines separated by newlines
 impl<W: Write> FastqWriter<W> {
     const NEWLINE: [u8; 1] = [b'\n'];
+    const HEADER_PREFIX: [u8; 1] = [b'@'];
 
     pub fn new(writer: W) -> Self {
-        FastqWriter { inner: writer }
+        FastqWriter {
+            inner: writer,
+            position: 0,
+        }
+    }
+
+    /// Get the number of bytes written so far, i.e. the position of the next record.
+    pub fn position(&self) -> u64 {
+        self.position
     }
 
     pub fn write(&mut self, fastq_record: &FastqRecord) -> Result<()> {
+        self.inner.write_all(&Self::HEADER_PREFIX)?;
         self.inner.write_all(&fastq_record.name)?;
         self.inner.write_all(&Self::NEWLINE)?;
 
@@ -119,6 +199,115 @@ impl<W: Write> FastqWriter<W> {
 
         self.inner.write_all(&fastq_record.qualities)?;
         self.inner.write_all(&Self::NEWLINE)?;
+        self.position += (Self::HEADER_PREFIX.len()
+            + fastq_record.name.len()
+            + fastq_record.sequence.len()
+            + fastq_record.separator.len()
+            + fastq_record.qualities.len()
+            + 4 * Self::NEWLINE.len()) as u64;
+        Ok(())
+    }
+
+    /// Flush buffered records to the underlying writer.
+    pub fn flush(&mut self) -> Result<()> {
+        Ok(self.inner.flush()?)
+    }
+}
+
+impl AtomicWriter<FastqWriter<MaybeCompressedWriter>> {
+    /// Flush and close the writer, ending compressed output, then rename the output onto its path.
+    pub fn finish(self) -> Result<()> {
+        self.commit_with(|mut writer| Ok(writer.inner.finish()?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FastqReader, FastqRecord, FastqWriter};
+    use crate::seekable_split::Split;
+    use anyhow::Result;
+    use std::io::Cursor;
+
+    /// Make a FASTQ record with a qname of `name_len` characters and a read of `read_len` bases.
+    fn long_record(name_len: usize, read_len: usize) -> FastqRecord {
+        FastqRecord {
+            name: vec![b'N'; name_len],
+            sequence: b"ACGT".repeat(read_len / 4),
+            separator: b"+".to_vec(),
+            qualities: vec![b'I'; 4 * (read_len / 4)],
+        }
+    }
+
+    /// Test that ultralong reads and long qnames round-trip through the writer and reader.
+    #[test]
+    fn test_long_records_round_trip() -> Result<()> {
+        let records = [
+            long_record(1000, 4 << 20),
+            long_record(10, 100),
+            long_record(100_000, 1 << 20),
+        ];
+        let mut bytes: Vec<u8> = Vec::new();
+        let mut writer = FastqWriter::new(&mut bytes);
+        for record in records.iter() {
+            writer.write(record)?;
+        }
+        let reader = FastqReader::new(Cursor::new(bytes));
+        let read_records = reader.collect::<Result<Vec<FastqRecord>>>()?;
+        assert_eq!(read_records.len(), records.len());
+        for (read_record, record) in read_records.iter().zip(records.iter()) {
+            assert_eq!(read_record.name, record.name);
+            assert_eq!(read_record.sequence, record.sequence);
+            assert_eq!(read_record.qualities, record.qualities);
+        }
+        Ok(())
+    }
+
+    /// Test that lines longer than the maximum length are an error rather than unbounded reads.
+    #[test]
+    fn test_max_line_length() {
+        let mut split =
+            Split::new(Cursor::new(b"ACGT\nACGTA\nACG".to_vec()), b'\n').with_max_len(4);
+        let mut line: Vec<u8> = Vec::new();
+        assert!(split.read_next_into(&mut line).unwrap());
+        assert_eq!(line, b"ACGT");
+        assert!(split.read_next_into(&mut line).is_err());
+    }
+
+    /// Test that truncated records are an error.
+    #[test]
+    fn test_truncated_record() {
+        let mut reader = FastqReader::new(Cursor::new(b"@read1\nACGT\n+\n".to_vec()));
+        assert!(reader.next().unwrap().is_err());
+    }
+
+    /// Test that the query name and comment are split from the header.
+    #[test]
+    fn test_qname_and_comment() -> Result<()> {
+        let mut reader = FastqReader::new(Cursor::new(
+            b"@read1 1:N:0:ACGT\nACGT\n+\nIIII\n@read2\nACGT\n+\nIIII\n".to_vec(),
+        ));
+        let mut record = reader.next().unwrap()?;
+        assert_eq!(record.qname(), b"read1");
+        assert_eq!(record.comment(), Some(b"1:N:0:ACGT".as_slice()));
+        record.set_comment(b"RG:Z:rg1");
+        assert_eq!(record.name, b"read1\tRG:Z:rg1");
+
+        let mut record = reader.next().unwrap()?;
+        assert_eq!(record.qname(), b"read2");
+        assert_eq!(record.comment(), None);
+        record.set_comment(b"");
+        assert_eq!(record.name, b"read2");
+        assert_eq!(record.mate_qname(), b"read2");
+
+        for (name, mate_qname) in [
+            ("read3/1", "read3"),
+            ("read3/2 1:N:0", "read3"),
+            ("read3/3", "read3/3"),
+            ("/1", "/1"),
+        ] {
+            record.name = name.as_bytes().to_vec();
+            assert_eq!(record.mate_qname(), mate_qname.as_bytes(), "{name}");
+        }
         Ok(())
     }
 }
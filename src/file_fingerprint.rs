@@ -0,0 +1,128 @@
+use crate::path_type::PathType;
+use anyhow::{Result, anyhow};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::File,
+    io::{Read, Seek, SeekFrom},
+    path::Path,
+};
+use xxhash_rust::xxh3::Xxh3;
+
+/// Number of bytes hashed at each end of the file.
+const CHECKSUM_SPAN: u64 = 1 << 20;
+
+/// Identifying information about an indexed reads file, used to detect stale indices.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq)]
+pub struct FileFingerprint {
+    /// Size of the file in bytes
+    pub size: u64,
+    /// xxh3 hash of the first and last megabyte of the file
+    pub checksum: Option<u64>,
+}
+
+impl FileFingerprint {
+    /// Fingerprint the file at the requested path. Pipes and URLs cannot be fingerprinted, so
+    /// return None for them.
+    pub fn from_path<P>(path: P) -> Result<Option<Self>>
+    where
+        P: AsRef<Path>,
+    {
+        match PathType::from_path(path)? {
+            PathType::Pipe | PathType::UrlPath(_) => Ok(None),
+            PathType::FilePath(file_path) => {
+                let mut file = File::open(&file_path)
+                    .map_err(|err| anyhow!("Opening {file_path:?} to fingerprint: {err}"))?;
+                let size = file.metadata()?.len();
+                let checksum = Self::checksum(&mut file, size)?;
+                Ok(Some(FileFingerprint {
+                    size,
+                    checksum: Some(checksum),
+                }))
+            }
+        }
+    }
+
+    /// Hash the first and last CHECKSUM_SPAN bytes of the file (the whole file if it is small).
+    fn checksum(file: &mut File, size: u64) -> Result<u64> {
+        let mut hasher = Xxh3::new();
+        let mut buf: Vec<u8> = Vec::with_capacity(CHECKSUM_SPAN as usize);
+        file.by_ref().take(CHECKSUM_SPAN).read_to_end(&mut buf)?;
+        hasher.update(&buf);
+        if size > CHECKSUM_SPAN {
+            let tail_start = size.saturating_sub(CHECKSUM_SPAN).max(CHECKSUM_SPAN);
+            file.seek(SeekFrom::Start(tail_start))?;
+            buf.clear();
+            file.by_ref().take(CHECKSUM_SPAN).read_to_end(&mut buf)?;
+            hasher.update(&buf);
+        }
+        Ok(hasher.digest())
+    }
+
+    /// Check that this fingerprint matches the file at the requested path. Files that cannot be
+    /// fingerprinted (pipes, URLs) are not checked.
+    pub fn verify<P>(&self, path: P) -> Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        let Some(actual) = Self::from_path(path.as_ref())? else {
+            return Ok(());
+        };
+        if actual.size != self.size {
+            Err(anyhow!(
+                "Index is stale: {:?} has size {} but the index expects {}.",
+                path.as_ref(),
+                actual.size,
+                self.size
+            ))
+        } else if let (Some(expected), Some(found)) = (self.checksum, actual.checksum)
+            && expected != found
+        {
+            Err(anyhow!(
+                "Index is stale: {:?} checksum does not match the index.",
+                path.as_ref()
+            ))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CHECKSUM_SPAN, FileFingerprint};
+    use anyhow::Result;
+    use std::{fs, io::Write};
+    use tempfile::NamedTempFile;
+
+    /// Test that fingerprints verify against the same file, and detect changed size or contents.
+    #[test]
+    fn test_verify() -> Result<()> {
+        for len in [10usize, 3 * CHECKSUM_SPAN as usize] {
+            let mut file = NamedTempFile::new()?;
+            file.write_all(&vec![b'A'; len])?;
+            file.flush()?;
+            let fingerprint = FileFingerprint::from_path(file.path())?.unwrap();
+            assert_eq!(fingerprint.size, len as u64);
+            fingerprint.verify(file.path())?;
+
+            // same size, different contents at the end
+            let mut contents = vec![b'A'; len];
+            contents[len - 1] = b'C';
+            fs::write(file.path(), &contents)?;
+            assert!(fingerprint.verify(file.path()).is_err());
+
+            // different size
+            contents.push(b'A');
+            fs::write(file.path(), &contents)?;
+            assert!(fingerprint.verify(file.path()).is_err());
+        }
+        Ok(())
+    }
+
+    /// Test that pipes are not fingerprinted.
+    #[test]
+    fn test_pipe_has_no_fingerprint() -> Result<()> {
+        assert!(FileFingerprint::from_path("-")?.is_none());
+        Ok(())
+    }
+}
@@ -0,0 +1,169 @@
+use crate::{path_type::PathType, util::RecordType};
+use anyhow::{Result, anyhow};
+use noodles_bgzf::io::Reader as BgzfReader;
+use std::{
+    fs::{self, File},
+    io::Read,
+    path::Path,
+};
+
+/// Number of (decompressed) bytes sniffed from the start of a file: enough for magic numbers and
+/// the first line of text formats
+const SNIFF_LENGTH: u64 = 4096;
+
+/// First bytes of gzipped (including BGZF) files
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+
+/// First bytes of decompressed BAM
+const BAM_MAGIC: &[u8] = b"BAM\x01";
+
+/// First bytes of CRAM
+const CRAM_MAGIC: &[u8] = b"CRAM";
+
+/// Minimum number of tab-separated fields in a SAM record
+const NUM_SAM_FIELDS: usize = 11;
+
+/// Detect the type of records in a file from its first bytes, decompressing BGZF: BAM and CRAM by
+/// their magic numbers, SAM by its header (or, without one, by its tab-separated fields), and FASTQ
+/// by its leading '@'. Return None if the content isn't recognized, or the file can't be sniffed
+/// without consuming it (stdin, URLs, and special files such as FIFOs from process substitution).
+/// Errors for FASTA, which holds reference sequences rather than reads.
+pub fn detect_record_type<P>(path: P) -> Result<Option<RecordType>>
+where
+    P: AsRef<Path>,
+{
+    let PathType::FilePath(file_path) = PathType::from_path(path.as_ref())? else {
+        return Ok(None);
+    };
+    if !fs::metadata(&file_path).is_ok_and(|metadata| metadata.is_file()) {
+        return Ok(None);
+    }
+    let mut start = Vec::new();
+    File::open(&file_path)?
+        .take(SNIFF_LENGTH)
+        .read_to_end(&mut start)?;
+    if start.starts_with(GZIP_MAGIC) {
+        let mut decompressed = Vec::new();
+        // plain (non-BGZF) gzip can't be read as reads anyway, so isn't recognized
+        match BgzfReader::new(File::open(&file_path)?)
+            .take(SNIFF_LENGTH)
+            .read_to_end(&mut decompressed)
+        {
+            Ok(_) => sniff(&decompressed, &file_path),
+            Err(_) => Ok(None),
+        }
+    } else {
+        sniff(&start, &file_path)
+    }
+}
+
+/// Detect the type of records from the start of the decompressed contents of path
+fn sniff(start: &[u8], path: &Path) -> Result<Option<RecordType>> {
+    if start.starts_with(BAM_MAGIC) {
+        return Ok(Some(RecordType::Bam));
+    } else if start.starts_with(CRAM_MAGIC) {
+        return Ok(Some(RecordType::Cram));
+    }
+    let first_line = start.split(|&c| c == b'\n').next().unwrap_or_default();
+    match first_line.first() {
+        Some(b'>') => Err(anyhow!(
+            "{path:?} is FASTA, which holds sequences without qualities rather than reads."
+        )),
+        Some(b'@') if is_sam_header_line(first_line) => Ok(Some(RecordType::Sam)),
+        Some(b'@') => Ok(Some(RecordType::Fastq)),
+        Some(_) if first_line.split(|&c| c == b'\t').count() >= NUM_SAM_FIELDS => {
+            Ok(Some(RecordType::Sam))
+        }
+        _ => Ok(None),
+    }
+}
+
+/// Return true if line starts as SAM header lines do: '@', a two-letter record type (e.g. HD, SQ,
+/// RG, PG or CO), then a tab.
+fn is_sam_header_line(line: &[u8]) -> bool {
+    line.len() >= 4
+        && line[1].is_ascii_alphabetic()
+        && line[2].is_ascii_alphanumeric()
+        && line[3] == b'\t'
+}
+
+#[cfg(test)]
+mod tests {
+    use super::detect_record_type;
+    use crate::util::RecordType;
+    use anyhow::Result;
+    use noodles_bgzf::io::Writer as NoodlesBgzfWriter;
+    use rust_htslib::bam::{Format, Header, Record, Writer as BamWriter, header::HeaderRecord};
+    use std::{
+        fs::{self, File},
+        io::Write,
+        path::Path,
+    };
+    use tempfile::TempDir;
+
+    /// Write a SAM/BAM/CRAM with one unaligned read
+    fn write_reads(path: &Path, format: Format) -> Result<()> {
+        let mut header = Header::new();
+        header.push_record(HeaderRecord::new(b"HD").push_tag(b"VN", "1.6"));
+        let mut writer = BamWriter::from_path(path, &header, format)?;
+        let mut record = Record::new();
+        record.set(b"read1", None, b"ACGT", &[30; 4]);
+        record.set_unmapped();
+        writer.write(&record)?;
+        Ok(())
+    }
+
+    /// Test detecting each format regardless of extension, and falling back to the extension for
+    /// files that can't be sniffed.
+    #[test]
+    fn test_detect_record_type() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let path = |name: &str| temp_dir.path().join(name);
+
+        write_reads(&path("bam.out"), Format::Bam)?;
+        write_reads(&path("cram.sam"), Format::Cram)?;
+        write_reads(&path("sam"), Format::Sam)?;
+        assert_eq!(detect_record_type(path("bam.out"))?, Some(RecordType::Bam));
+        assert_eq!(
+            detect_record_type(path("cram.sam"))?,
+            Some(RecordType::Cram)
+        );
+        assert_eq!(detect_record_type(path("sam"))?, Some(RecordType::Sam));
+        fs::write(
+            path("headerless.txt"),
+            "read1\t4\t*\t0\t0\t*\t*\t0\t0\tACGT\tIIII\n",
+        )?;
+        assert_eq!(
+            detect_record_type(path("headerless.txt"))?,
+            Some(RecordType::Sam)
+        );
+
+        let fastq = b"@read1 BC:Z:ACGT\nACGT\n+\nIIII\n";
+        fs::write(path("reads.bam"), fastq)?;
+        assert_eq!(
+            detect_record_type(path("reads.bam"))?,
+            Some(RecordType::Fastq)
+        );
+        let mut writer = NoodlesBgzfWriter::new(File::create(path("reads"))?);
+        writer.write_all(fastq)?;
+        writer.finish()?;
+        assert_eq!(detect_record_type(path("reads"))?, Some(RecordType::Fastq));
+        assert_eq!(RecordType::detect(path("reads"))?, Some(RecordType::Fastq));
+
+        fs::write(path("ref.fq"), ">chr1\nACGT\n")?;
+        assert!(detect_record_type(path("ref.fq")).is_err());
+        fs::write(path("notes.fq"), "not reads\n")?;
+        assert_eq!(detect_record_type(path("notes.fq"))?, None);
+        assert_eq!(
+            RecordType::detect(path("notes.fq"))?,
+            Some(RecordType::Fastq)
+        );
+        assert_eq!(detect_record_type("-")?, None);
+        assert_eq!(detect_record_type(path("missing.bam"))?, None);
+        assert_eq!(
+            RecordType::detect(path("missing.bam"))?,
+            Some(RecordType::Bam)
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,310 @@
+use crate::{
+    chunkable::{
+        ChunkableRecord, ChunkableRecordReader, ChunkableRecordWriter, OffsetKind, SplitRange,
+    },
+    manifest::json_string,
+    resync::SkippedRange,
+};
+use anyhow::{Result, anyhow};
+use log::warn;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// Number of reads between updates of the input offset by a HeartbeatReader, so that telling
+/// offsets doesn't slow reading down
+const OFFSET_INTERVAL_READS: usize = 256;
+
+/// Value of a shared input offset before the reader has reported one
+const UNKNOWN_OFFSET: u64 = u64::MAX;
+
+/// Small JSON status file rewritten periodically during long jobs, so external watchdogs can tell
+/// a stalled job (whose heartbeat stops changing) from a slow one, e.g. on flaky network
+/// filesystems. Each beat records the status, input offset (if known), read and query counts, and
+/// a timestamp.
+pub struct Heartbeat {
+    /// Path of the heartbeat file
+    path: PathBuf,
+    /// Minimum time between beats
+    interval: Duration,
+    /// Time the job started
+    started: SystemTime,
+    /// Time of the last beat (or when the job started)
+    last_beat: SystemTime,
+    /// Offset in the input file last reported by a HeartbeatReader, which may read on another
+    /// thread than the one beating
+    input_offset: Arc<AtomicU64>,
+}
+
+impl Heartbeat {
+    /// Create a new Heartbeat, writing to path at most once per interval.
+    pub fn new<P>(path: P, interval: Duration) -> Self
+    where
+        P: AsRef<Path>,
+    {
+        let now = SystemTime::now();
+        Heartbeat {
+            path: path.as_ref().to_path_buf(),
+            interval,
+            started: now,
+            last_beat: now,
+            input_offset: Arc::new(AtomicU64::new(UNKNOWN_OFFSET)),
+        }
+    }
+
+    /// Get the offset in the input file last reported by a HeartbeatReader, if any
+    pub fn input_offset(&self) -> Option<u64> {
+        Some(self.input_offset.load(Ordering::Relaxed)).filter(|&offset| offset != UNKNOWN_OFFSET)
+    }
+
+    /// Return true if it's time for another beat
+    pub fn is_due(&self) -> bool {
+        SystemTime::now()
+            .duration_since(self.last_beat)
+            .is_ok_and(|elapsed| elapsed >= self.interval)
+    }
+
+    /// Format a beat as a single-line JSON object
+    fn to_json(
+        &self,
+        status: &str,
+        offset: Option<u64>,
+        num_reads: usize,
+        num_queries: usize,
+        now: SystemTime,
+    ) -> Result<String> {
+        let timestamp = now.duration_since(UNIX_EPOCH)?.as_secs();
+        let elapsed = now
+            .duration_since(self.started)
+            .unwrap_or_default()
+            .as_secs();
+        Ok(format!(
+            "{{\"status\": {}, \"offset\": {}, \"num_reads\": {num_reads}, \
+            \"num_queries\": {num_queries}, \"timestamp\": {timestamp}, \
+            \"elapsed_seconds\": {elapsed}}}\n",
+            json_string(status),
+            offset.map_or("null".to_string(), |offset| offset.to_string())
+        ))
+    }
+
+    /// Write a beat now. Write to a temporary file and then rename it, so monitors never read a
+    /// partial heartbeat. A failed beat only warns, because the job itself may be fine.
+    pub fn beat(
+        &mut self,
+        status: &str,
+        offset: Option<u64>,
+        num_reads: usize,
+        num_queries: usize,
+    ) {
+        let now = SystemTime::now();
+        self.last_beat = now;
+        let mut temp_path = self.path.clone().into_os_string();
+        temp_path.push(".tmp");
+        let result = self
+            .to_json(status, offset, num_reads, num_queries, now)
+            .and_then(|json| Ok(fs::write(&temp_path, json)?))
+            .and_then(|_| {
+                fs::rename(&temp_path, &self.path).map_err(|err| anyhow!("Renaming: {err}"))
+            });
+        if let Err(err) = result {
+            warn!("Writing heartbeat {:?}: {err}", self.path);
+        }
+    }
+}
+
+/// Get the offset in the input file the reader has reached: the compressed file offset of a
+/// virtual offset, so that heartbeats can be compared with the file size.
+pub fn reader_offset<R, Reader>(reader: &mut Reader) -> Option<u64>
+where
+    R: ChunkableRecord,
+    Reader: ChunkableRecordReader<R>,
+{
+    let offset = reader.tell().ok()?;
+    Some(match reader.offset_kind() {
+        OffsetKind::Virtual => OffsetKind::split_virtual_offset(offset).0,
+        OffsetKind::Byte => offset,
+    })
+}
+
+/// Reader that reports the input offset it has reached to a Heartbeat every so often, for a
+/// HeartbeatWriter (possibly on another thread) to beat with. With no heartbeat, it just passes
+/// records on.
+pub struct HeartbeatReader<Reader> {
+    inner: Reader,
+    input_offset: Option<Arc<AtomicU64>>,
+    num_reads: usize,
+}
+
+impl<Reader> HeartbeatReader<Reader> {
+    /// Create a new HeartbeatReader wrapping the supplied reader.
+    pub fn new(inner: Reader, heartbeat: Option<&Heartbeat>) -> Self {
+        HeartbeatReader {
+            inner,
+            input_offset: heartbeat.map(|heartbeat| heartbeat.input_offset.clone()),
+            num_reads: 0,
+        }
+    }
+}
+
+/// Implement ChunkableRecordReader for HeartbeatReader, updating the input offset every so often.
+impl<R, Reader> ChunkableRecordReader<R> for HeartbeatReader<Reader>
+where
+    R: ChunkableRecord,
+    Reader: ChunkableRecordReader<R>,
+{
+    fn tell(&mut self) -> Result<u64> {
+        self.inner.tell()
+    }
+
+    fn seek(&mut self, offset: u64) -> Result<()> {
+        self.inner.seek(offset)
+    }
+
+    fn offset_kind(&self) -> OffsetKind {
+        self.inner.offset_kind()
+    }
+
+    fn seek_to_range(&mut self, split_range: &SplitRange) -> Result<()> {
+        self.inner.seek_to_range(split_range)
+    }
+
+    fn tell_uncompressed(&mut self) -> Result<Option<u64>> {
+        self.inner.tell_uncompressed()
+    }
+
+    fn read_into(&mut self, record: &mut R) -> Option<Result<()>> {
+        let result = self.inner.read_into(record);
+        if let Some(ref input_offset) = self.input_offset
+            && let Some(Ok(())) = result
+        {
+            self.num_reads += 1;
+            if self.num_reads % OFFSET_INTERVAL_READS == 1
+                && let Some(offset) = reader_offset(&mut self.inner)
+            {
+                input_offset.store(offset, Ordering::Relaxed);
+            }
+        }
+        result
+    }
+
+    fn take_skipped_ranges(&mut self) -> Vec<SkippedRange> {
+        self.inner.take_skipped_ranges()
+    }
+}
+
+/// Writer that beats a Heartbeat with the number of reads and queries written, and the input
+/// offset reached by its HeartbeatReader, at most once per heartbeat interval. With no heartbeat,
+/// it just passes records on.
+pub struct HeartbeatWriter<'a, W> {
+    writer: &'a mut W,
+    heartbeat: Option<&'a mut Heartbeat>,
+    num_reads: usize,
+    num_queries: usize,
+    /// Query name of the last record written
+    last_qname: Vec<u8>,
+}
+
+impl<'a, W> HeartbeatWriter<'a, W> {
+    /// Create a new HeartbeatWriter wrapping the supplied writer.
+    pub fn new(writer: &'a mut W, heartbeat: Option<&'a mut Heartbeat>) -> Self {
+        HeartbeatWriter {
+            writer,
+            heartbeat,
+            num_reads: 0,
+            num_queries: 0,
+            last_qname: Vec::new(),
+        }
+    }
+}
+
+/// Implement ChunkableRecordWriter for HeartbeatWriter, so it can be used anywhere records are
+/// written.
+impl<R, W> ChunkableRecordWriter<R> for HeartbeatWriter<'_, W>
+where
+    R: ChunkableRecord,
+    W: ChunkableRecordWriter<R>,
+{
+    fn write(&mut self, record: &R) -> Result<()> {
+        self.writer.write(record)?;
+        let Some(ref mut heartbeat) = self.heartbeat else {
+            return Ok(());
+        };
+        self.num_reads += 1;
+        if self.num_reads == 1 || record.group_qname() != self.last_qname {
+            self.num_queries += 1;
+            self.last_qname = record.group_qname().to_vec();
+        }
+        if heartbeat.is_due() {
+            let offset = heartbeat.input_offset();
+            heartbeat.beat("running", offset, self.num_reads, self.num_queries);
+        }
+        Ok(())
+    }
+
+    fn is_full(&mut self) -> bool {
+        self.writer.is_full()
+    }
+
+    fn tell(&mut self) -> Result<Option<u64>> {
+        self.writer.tell()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Heartbeat, HeartbeatReader, HeartbeatWriter};
+    use crate::{
+        chunkable::{ChunkableRecord, ChunkableRecordReader, ChunkableRecordWriter},
+        fastq::{FastqReader, FastqRecord, FastqWriter},
+    };
+    use anyhow::Result;
+    use std::{fs, io::Cursor, time::Duration};
+    use tempfile::TempDir;
+
+    /// Test that beats are written as escaped JSON, and that a HeartbeatWriter counts reads and
+    /// queries and reports the offset its HeartbeatReader reached in the input.
+    #[test]
+    fn test_heartbeat() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let path = temp_dir.path().join("job.heartbeat.json");
+        let mut heartbeat = Heartbeat::new(&path, Duration::ZERO);
+        assert!(heartbeat.is_due());
+        heartbeat.beat("running", Some(1234), 10, 5);
+        let json = fs::read_to_string(&path)?;
+        assert!(json.starts_with("{\"status\": \"running\", \"offset\": 1234, \"num_reads\": 10"));
+        heartbeat.beat("say \"hi\"\\", None, 0, 0);
+        let json = fs::read_to_string(&path)?;
+        assert!(
+            json.starts_with(r#"{"status": "say \"hi\"\\", "offset": null"#),
+            "{json}"
+        );
+
+        let input = b"@a\nA\n+\nI\n@b\nA\n+\nI\n";
+        let mut reader =
+            HeartbeatReader::new(FastqReader::new(Cursor::new(input)), Some(&heartbeat));
+        let mut record = FastqRecord::new();
+        assert_eq!(heartbeat.input_offset(), None);
+        reader.read_into(&mut record).transpose()?;
+        assert_eq!(heartbeat.input_offset(), Some(9));
+
+        let mut fastq_writer = FastqWriter::new(Vec::new());
+        let mut writer = HeartbeatWriter::new(&mut fastq_writer, Some(&mut heartbeat));
+        for qname in [b"a", b"a", b"b"] {
+            record.set_fields(qname, b"A", b"I")?;
+            writer.write(&record)?;
+        }
+        let json = fs::read_to_string(&path)?;
+        assert!(
+            json.contains("\"offset\": 9, \"num_reads\": 3, \"num_queries\": 2"),
+            "{json}"
+        );
+        assert!(!temp_dir.path().join("job.heartbeat.json.tmp").exists());
+        Ok(())
+    }
+}
@@ -1,9 +1,85 @@
+//! Split FASTQ and SAM/BAM/CRAM reads files into chunks without splitting query groups (reads
+//! sharing a query name, e.g. read pairs), by first building a small split-index.
+//!
+//! Most tools need only [`Splitter`]: open a reads file, index it (or load an index built by
+//! `split-reads index`), and write chunks:
+//!
+//! ```no_run
+//! # use std::num::NonZero;
+//! # fn main() -> anyhow::Result<()> {
+//! split_reads::Splitter::open("reads.bam")?
+//!     .index(NonZero::new(1000).unwrap())?
+//!     .chunk(3, NonZero::new(10).unwrap())?
+//!     .write_to("reads.3.bam")?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! The lower-level pieces are [`SplitIndex`], which maps query and read counts to file offsets,
+//! and the [`ChunkableRecord`], [`ChunkableRecordReader`] and [`ChunkableRecordWriter`] traits,
+//! which read and write chunks of any record type. Tools not written in Rust can use the C API of
+//! the split-reads-ffi crate.
+//!
+//! # Thread safety
+//!
+//! Splitters, readers, writers and indexes hold no shared state, so separate ones can be used
+//! from separate threads at once, e.g. to extract every chunk in parallel. CRAM readers and
+//! writers of the same reference share its decoded sequences through [`reference_cache`], which
+//! htslib locks while loading them. The one piece of process-wide setup is htslib's CA
+//! certificate bundle for remote URLs, an environment variable that
+//! [`util::configure_remote_access`] sets once; call it before starting threads if several
+//! threads will open URLs. Whether outputs are written atomically is also process-wide, set with
+//! [`atomic_output::set_atomic_output`], as is the codec of compressed FASTQ outputs, set with
+//! [`maybe_compressed_io::set_output_codec`].
+
+pub mod atomic_output;
+pub mod bam_index;
+pub mod bin_sizing;
+pub mod block_copy;
+pub mod chunk_naming;
+pub mod chunk_stream;
 pub mod chunkable;
+pub mod error_category;
 pub mod fastq;
+pub mod file_fingerprint;
+pub mod format_detect;
+pub mod heartbeat;
+pub mod manifest;
 pub mod maybe_compressed_io;
+pub mod memory_budget;
+pub mod output_quota;
+pub mod output_spec;
 pub mod path_type;
+pub mod progress;
+pub mod qname_hash;
+pub mod query_grouping;
+pub mod read_filter;
+pub mod read_stats;
+pub mod record_tags;
+pub mod reference_cache;
+pub mod reference_resolver;
+pub mod regions;
+pub mod resync;
+pub mod rng;
+pub mod sam_text;
+pub mod sam_writer;
 pub mod sam_writer_spec;
 pub mod seekable_chain;
 pub mod seekable_split;
 pub mod split_index;
+pub mod splitter;
+pub mod stream_split;
+pub mod subsample;
+#[cfg(feature = "synthetic")]
+pub mod synthetic;
+pub mod thread_budget;
+pub mod transform;
+pub mod translate_options;
 pub mod util;
+pub mod validation;
+#[cfg(feature = "zstd")]
+pub mod zstd_process;
+
+pub use chunkable::{ChunkResult, ChunkableRecord, ChunkableRecordReader, ChunkableRecordWriter};
+pub use split_index::SplitIndex;
+pub use splitter::{Chunk, Splitter};
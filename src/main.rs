@@ -6,16 +6,34 @@ pub mod commands;
 
 use anyhow::Result;
 use clap::Parser;
+use commands::batch::Batch;
+#[cfg(feature = "synthetic")]
+use commands::bench::Bench;
+use commands::cat::Cat;
+use commands::collate::Collate;
 use commands::command::Command;
 use commands::get_chunk::GetChunk;
+use commands::get_queries::GetQueries;
 use commands::index::Index;
+use commands::inspect_chunk::InspectChunk;
+use commands::locate::Locate;
+use commands::plan::Plan;
+use commands::prune::Prune;
+use commands::stats::Stats;
+use commands::stream_split::StreamSplit;
+use commands::subsample::Subsample;
 use commands::tell::Tell;
 use commands::test_fastq::TestFastq;
 use commands::test_seq_io::TestSeqIo;
+use commands::verify_chunk::VerifyChunk;
 use enum_dispatch::enum_dispatch;
-use std::sync::LazyLock;
+use split_reads::atomic_output::{set_atomic_output, set_overwrite};
+use split_reads::error_category::{ErrorCategory, error_json};
+use split_reads::maybe_compressed_io::{Codec, set_output_codec};
+use split_reads::memory_budget::{ByteSize, set_max_memory};
+use std::{fs, path::PathBuf, process::ExitCode, sync::LazyLock};
 
-#[cfg(test)]
+#[cfg(all(test, feature = "synthetic"))]
 mod test_utils;
 
 pub mod built_info {
@@ -41,6 +59,44 @@ static VERSION: LazyLock<String> = LazyLock::new(|| {
 #[derive(Parser, Debug)]
 #[clap(version = VERSION.as_str(), term_width=0)]
 struct Args {
+    /// Seed for every randomized choice (e.g. subsampling, collate buckets), so that output is
+    /// exactly reproducible across runs and machines. A subcommand's own --seed overrides it.
+    #[clap(long, required = false, default_value = None)]
+    seed: Option<u64>,
+
+    /// On failure, write a JSON object with the error's category, exit code, message, and
+    /// context to this path, for workflow engines to decide whether to retry.
+    #[clap(long, required = false, default_value = None)]
+    error_json: Option<PathBuf>,
+
+    /// Write outputs directly to their paths, instead of to "<output>.tmp.<pid>" and renaming
+    /// them once complete. For filesystems where renaming is unsupported or slow.
+    #[clap(long, required = false)]
+    no_atomic: bool,
+
+    /// Overwrite outputs that already exist. By default, a command fails rather than replace an
+    /// existing non-empty output, so re-running a pipeline can't destroy earlier results.
+    #[clap(long, required = false, conflicts_with = "no_clobber")]
+    force: bool,
+
+    /// Refuse to overwrite outputs that already exist and are non-empty (the default).
+    #[clap(long, required = false)]
+    no_clobber: bool,
+
+    /// Codec of compressed FASTQ outputs. Outputs ending in ".gz" or ".bgz" use it, as do outputs
+    /// whose path doesn't say (e.g. stdout) unless it's none; outputs ending in ".zst" need zstd.
+    /// Plain gzip and zstd output can't be indexed. Defaults to bgzf, compressing outputs whose
+    /// path doesn't say by the compression level.
+    #[clap(long, required = false, value_enum)]
+    output_codec: Option<Codec>,
+
+    /// Memory budget, e.g. "2GiB", capping the job queues of reading and writing threads (by
+    /// lowering thread counts), the records buffered between reading and writing, the bins of
+    /// in-memory split indices, and I/O buffers. For running many commands at once on a node.
+    /// Unlimited by default.
+    #[clap(long, required = false, default_value = None)]
+    max_memory: Option<ByteSize>,
+
     #[clap(subcommand)]
     subcommand: Subcommand,
 }
@@ -51,15 +107,64 @@ struct Args {
 enum Subcommand {
     Index(Index),
     GetChunk(GetChunk),
+    GetQueries(GetQueries),
     Tell(Tell),
+    Plan(Plan),
+    Locate(Locate),
+    Prune(Prune),
+    Subsample(Subsample),
+    Collate(Collate),
+    Cat(Cat),
+    StreamSplit(StreamSplit),
+    Stats(Stats),
+    InspectChunk(InspectChunk),
+    VerifyChunk(VerifyChunk),
+    Batch(Batch),
+    #[cfg(feature = "synthetic")]
+    Bench(Bench),
     TestSeqIo(TestSeqIo),
     TestFastq(TestFastq),
 }
 
-fn main() -> Result<()> {
+impl Args {
+    /// Run the subcommand, giving it the global --seed, --no-atomic, --force, --output-codec and
+    /// --max-memory.
+    fn execute(&mut self) -> Result<()> {
+        if self.no_atomic {
+            set_atomic_output(false);
+        }
+        if let Some(codec) = self.output_codec {
+            set_output_codec(codec);
+        }
+        set_max_memory(self.max_memory);
+        set_overwrite(self.force);
+        if let Some(seed) = self.seed {
+            self.subcommand.default_seed(seed);
+        }
+        self.subcommand.execute()
+    }
+}
+
+/// Run the command line tool, exiting with the code of the failure's ErrorCategory.
+fn main() -> ExitCode {
     env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
-    let args: Args = Args::parse();
-    args.subcommand.execute()
+    if std::env::args().any(|arg| arg.contains("://")) {
+        // set up remote access while still single-threaded
+        split_reads::util::configure_remote_access();
+    }
+    let mut args: Args = Args::parse();
+    match args.execute() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("Error: {err:?}");
+            if let Some(error_json_path) = &args.error_json
+                && let Err(write_err) = fs::write(error_json_path, error_json(&err) + "\n")
+            {
+                eprintln!("Unable to write error JSON to {error_json_path:?}: {write_err}");
+            }
+            ExitCode::from(ErrorCategory::of(&err).exit_code())
+        }
+    }
 }
 
 #[cfg(test)]
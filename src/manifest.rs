@@ -0,0 +1,796 @@
+use crate::{
+    atomic_output::AtomicWriter,
+    chunkable::ChunkResult,
+    error_category::{Categorize, ErrorCategory},
+    maybe_compressed_io::{BGZF_EOF, RawUrlReader, open_file},
+    path_type::PathType,
+};
+use anyhow::{Result, anyhow};
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, BufWriter, PipeWriter, Read, Write},
+    num::NonZero,
+    os::fd::AsRawFd,
+    path::{Path, PathBuf},
+    sync::mpsc::{self, Sender},
+    thread::{self, JoinHandle},
+};
+use xxhash_rust::xxh3::Xxh3;
+
+/// Per-round shift amounts for MD5
+const MD5_SHIFTS: [u32; 16] = [7, 12, 17, 22, 5, 9, 14, 20, 4, 11, 16, 23, 6, 10, 15, 21];
+
+/// Minimal streaming MD5, so manifests can record the checksum most scatter-gather tools expect
+/// without another dependency.
+pub(crate) struct Md5 {
+    state: [u32; 4],
+    constants: [u32; 64],
+    buffer: Vec<u8>,
+    len: u64,
+}
+
+impl Md5 {
+    pub(crate) fn new() -> Self {
+        let mut constants = [0u32; 64];
+        for (index, constant) in constants.iter_mut().enumerate() {
+            *constant = (((index + 1) as f64).sin().abs() * 2f64.powi(32)) as u32;
+        }
+        Md5 {
+            state: [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476],
+            constants,
+            buffer: Vec::with_capacity(64),
+            len: 0,
+        }
+    }
+
+    /// Hash one 64-byte block
+    fn process_block(&mut self, block: &[u8]) {
+        let words: Vec<u32> = block
+            .chunks_exact(4)
+            .map(|word| u32::from_le_bytes(word.try_into().unwrap()))
+            .collect();
+        let [mut a, mut b, mut c, mut d] = self.state;
+        for index in 0..64 {
+            let (f, word_index) = match index / 16 {
+                0 => ((b & c) | (!b & d), index),
+                1 => ((d & b) | (!d & c), (5 * index + 1) % 16),
+                2 => (b ^ c ^ d, (3 * index + 5) % 16),
+                _ => (c ^ (b | !d), (7 * index) % 16),
+            };
+            let f = f
+                .wrapping_add(a)
+                .wrapping_add(self.constants[index])
+                .wrapping_add(words[word_index]);
+            a = d;
+            d = c;
+            c = b;
+            b = b.wrapping_add(f.rotate_left(MD5_SHIFTS[(index / 16) * 4 + index % 4]));
+        }
+        for (state, value) in self.state.iter_mut().zip([a, b, c, d]) {
+            *state = state.wrapping_add(value);
+        }
+    }
+
+    pub(crate) fn update(&mut self, mut bytes: &[u8]) {
+        self.len += bytes.len() as u64;
+        if !self.buffer.is_empty() {
+            let take = (64 - self.buffer.len()).min(bytes.len());
+            self.buffer.extend(&bytes[..take]);
+            bytes = &bytes[take..];
+            if self.buffer.len() < 64 {
+                return;
+            }
+            let block = std::mem::take(&mut self.buffer);
+            self.process_block(&block);
+        }
+        let mut blocks = bytes.chunks_exact(64);
+        for block in blocks.by_ref() {
+            self.process_block(block);
+        }
+        self.buffer.extend(blocks.remainder());
+    }
+
+    /// Finish hashing and return the digest as lowercase hex
+    pub(crate) fn hex_digest(mut self) -> String {
+        let bit_len = self.len.wrapping_mul(8);
+        let mut padding = vec![0x80u8];
+        padding.resize((119 - (self.len % 64) as usize) % 64 + 1, 0);
+        padding.extend(bit_len.to_le_bytes());
+        self.update(&padding);
+        self.state
+            .iter()
+            .flat_map(|word| word.to_le_bytes())
+            .map(|byte| format!("{byte:02x}"))
+            .collect()
+    }
+}
+
+/// Extension of the MD5 sidecar written next to a chunk, in the format of `md5sum`
+pub const MD5_SIDECAR_EXTENSION: &str = "md5";
+
+/// Size and checksums of a chunk's bytes: MD5, which most scatter-gather tools expect, and xxh3,
+/// which is much faster to verify.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ChunkChecksum {
+    pub bytes: u64,
+    pub md5: String,
+    pub xxh3: String,
+}
+
+impl ChunkChecksum {
+    /// Checksum everything read from reader, hashing both checksums in one pass.
+    pub fn from_reader<R: Read>(mut reader: R) -> Result<Self> {
+        let mut checksum_writer = ChecksumWriter::new(io::sink());
+        io::copy(&mut reader, &mut checksum_writer)?;
+        Ok(checksum_writer.checksum())
+    }
+
+    /// Checksum the chunk at a local path or URL. URLs are read through htslib, as inputs are.
+    pub fn from_path<P>(path: P) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+        match PathType::from_path(path)? {
+            PathType::FilePath(file_path) => File::open(&file_path)
+                .map_err(|err| anyhow!("Opening {file_path:?} to checksum: {err}"))
+                .categorize(ErrorCategory::Io)
+                .and_then(Self::from_reader),
+            PathType::UrlPath(url) => Self::from_reader(RawUrlReader::open(url.as_str())?),
+            PathType::Pipe => Err(anyhow!("Cannot checksum a chunk written to stdout.")),
+        }
+    }
+
+    /// Get the path of the MD5 sidecar of the chunk at path: "<path>.md5"
+    pub fn sidecar_path<P>(path: P) -> PathBuf
+    where
+        P: AsRef<Path>,
+    {
+        path.as_ref().with_added_extension(MD5_SIDECAR_EXTENSION)
+    }
+
+    /// Write the MD5 sidecar of the local chunk at path, in the format of `md5sum` so that
+    /// `md5sum -c` can also check it.
+    pub fn write_sidecar<P>(&self, path: P) -> Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+        let file_name = path.file_name().unwrap_or(path.as_os_str());
+        let sidecar = Self::sidecar_path(path);
+        fs::write(
+            &sidecar,
+            format!("{}  {}\n", self.md5, file_name.to_string_lossy()),
+        )
+        .map_err(|err| anyhow!("Writing {sidecar:?}: {err}"))
+    }
+
+    /// Read the MD5 from the sidecar of the chunk at path.
+    pub fn read_sidecar<P>(path: P) -> Result<String>
+    where
+        P: AsRef<Path>,
+    {
+        let sidecar = Self::sidecar_path(path);
+        let text = fs::read_to_string(&sidecar)
+            .map_err(|err| anyhow!("Reading {sidecar:?}: {err}"))
+            .categorize(ErrorCategory::Io)?;
+        text.split_whitespace()
+            .next()
+            .filter(|md5| md5.len() == 32 && md5.chars().all(|c| c.is_ascii_hexdigit()))
+            .map(str::to_ascii_lowercase)
+            .ok_or_else(|| anyhow!("{sidecar:?} does not start with an MD5."))
+    }
+}
+
+/// Writer that checksums everything written through it on its way to the inner writer, hashing
+/// both checksums in one pass.
+pub struct ChecksumWriter<W> {
+    inner: W,
+    md5: Md5,
+    xxh3: Xxh3,
+    bytes: u64,
+}
+
+impl<W: Write> ChecksumWriter<W> {
+    pub fn new(inner: W) -> Self {
+        ChecksumWriter {
+            inner,
+            md5: Md5::new(),
+            xxh3: Xxh3::new(),
+            bytes: 0,
+        }
+    }
+
+    /// Get the checksum of everything written so far.
+    pub fn checksum(self) -> ChunkChecksum {
+        ChunkChecksum {
+            bytes: self.bytes,
+            md5: self.md5.hex_digest(),
+            xxh3: format!("{:016x}", self.xxh3.digest()),
+        }
+    }
+}
+
+impl<W: Write> Write for ChecksumWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let num_written = self.inner.write(buf)?;
+        self.md5.update(&buf[..num_written]);
+        self.xxh3.update(&buf[..num_written]);
+        self.bytes += num_written as u64;
+        Ok(num_written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Output to a local file that is checksummed as it is written, so the chunk never has to be read
+/// back. Writers open a pipe by its path (htslib opens its own file, see ChunkStream), and a
+/// background thread copies what they write to the file through a ChecksumWriter. The file is
+/// written atomically (see AtomicWriter), and only committed by finish, so output abandoned after
+/// an error leaves no partial file.
+pub struct ChecksummedOutput {
+    /// Path of the pipe that writers open
+    pipe_path: PathBuf,
+    /// Write end of the pipe, held until the writers are done with it
+    pipe: Option<PipeWriter>,
+    /// Tells the copier that the output is complete, so it commits the file
+    commit: Option<Sender<()>>,
+    /// Background thread copying the pipe to the file, returning its checksum
+    copier: Option<JoinHandle<Result<ChunkChecksum>>>,
+}
+
+impl ChecksummedOutput {
+    /// Start copying to the local file at path. If strip_bgzf_eof, a final BGZF end-of-file block
+    /// is left out of the file (see strip_bgzf_eof). Fails, as AtomicWriter::create does, if path
+    /// may not be overwritten.
+    pub fn create<P>(path: P, strip_bgzf_eof: bool) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let (mut stream, pipe) = io::pipe()?;
+        let pipe_path = PathBuf::from(format!("/dev/fd/{}", pipe.as_raw_fd()));
+        let mut file =
+            AtomicWriter::create(path, |path| Ok(BufWriter::new(open_file(path, true)?)))?;
+        let (commit, committed) = mpsc::channel();
+        let copier = thread::Builder::new()
+            .name("checksummed-output".to_string())
+            .spawn(move || {
+                let mut checksum_writer = ChecksumWriter::new(&mut *file);
+                if strip_bgzf_eof {
+                    copy_without_bgzf_eof(&mut stream, &mut checksum_writer)?;
+                } else {
+                    io::copy(&mut stream, &mut checksum_writer)?;
+                }
+                let checksum = checksum_writer.checksum();
+                // an abandoned output is dropped, removing its temporary file
+                committed
+                    .recv()
+                    .map_err(|_| anyhow!("Output {:?} was abandoned.", file.path()))?;
+                file.commit_with(|mut file| Ok(file.flush()?))?;
+                Ok(checksum)
+            })?;
+        Ok(ChecksummedOutput {
+            pipe_path,
+            pipe: Some(pipe),
+            commit: Some(commit),
+            copier: Some(copier),
+        })
+    }
+
+    /// Get the path for writers to open.
+    pub fn path(&self) -> &Path {
+        &self.pipe_path
+    }
+
+    /// Once the writers have closed the pipe, wait for the copy to finish, commit the file, and
+    /// return its checksum.
+    pub fn finish(mut self) -> Result<ChunkChecksum> {
+        // the copier sees end-of-file once the last write end is closed
+        self.pipe = None;
+        if let Some(commit) = self.commit.take() {
+            let _ = commit.send(());
+        }
+        self.join()
+    }
+
+    /// Wait for the copier and return its result.
+    fn join(&mut self) -> Result<ChunkChecksum> {
+        self.copier
+            .take()
+            .ok_or_else(|| anyhow!("Checksummed output was already finished."))?
+            .join()
+            .unwrap_or_else(|_| Err(anyhow!("Checksummed output copier panicked.")))
+    }
+}
+
+/// Abandon output that was never finished: the copier removes its temporary file.
+impl Drop for ChecksummedOutput {
+    fn drop(&mut self) {
+        self.pipe = None;
+        self.commit = None;
+        if self.copier.is_some() {
+            let _ = self.join();
+        }
+    }
+}
+
+/// Copy reader to writer, leaving out the final BGZF end-of-file block if there is one.
+fn copy_without_bgzf_eof<R: Read, W: Write>(reader: &mut R, writer: &mut W) -> Result<()> {
+    let mut buf = vec![0u8; 1 << 16];
+    // the last bytes read, held back until it's known whether they end the stream
+    let mut held = Vec::with_capacity(buf.len() + BGZF_EOF.len());
+    loop {
+        let num_read = reader.read(&mut buf)?;
+        if num_read == 0 {
+            if held != BGZF_EOF {
+                writer.write_all(&held)?;
+            }
+            return Ok(());
+        }
+        held.extend_from_slice(&buf[..num_read]);
+        let num_ready = held.len().saturating_sub(BGZF_EOF.len());
+        writer.write_all(&held[..num_ready])?;
+        held.drain(..num_ready);
+    }
+}
+
+/// Quote a string for JSON
+pub fn json_string(value: &str) -> String {
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\t' => quoted.push_str("\\t"),
+            c if c.is_control() => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Parse a single-line JSON object of strings, numbers, booleans and nulls (such as a manifest
+/// entry) into its keys and values. Strings are unescaped, other values kept as text, and nulls
+/// are None.
+pub(crate) fn parse_json_object(line: &str) -> Result<Vec<(String, Option<String>)>> {
+    let invalid = || anyhow!("Invalid JSON object: {line}");
+    let mut rest = line
+        .trim()
+        .strip_prefix('{')
+        .and_then(|rest| rest.strip_suffix('}'))
+        .ok_or_else(invalid)?
+        .trim_start();
+    let mut fields = Vec::new();
+    while !rest.is_empty() {
+        let (key, after_key) = parse_json_string(rest).ok_or_else(invalid)?;
+        rest = after_key
+            .trim_start()
+            .strip_prefix(':')
+            .ok_or_else(invalid)?
+            .trim_start();
+        let value = if rest.starts_with('"') {
+            let (value, after_value) = parse_json_string(rest).ok_or_else(invalid)?;
+            rest = after_value;
+            Some(value)
+        } else {
+            let end = rest.find([',', ' ', '\t']).unwrap_or(rest.len());
+            let value = &rest[..end];
+            rest = &rest[end..];
+            (value != "null").then(|| value.to_string())
+        };
+        fields.push((key, value));
+        rest = rest.trim_start();
+        if let Some(after_comma) = rest.strip_prefix(',') {
+            rest = after_comma.trim_start();
+        } else if !rest.is_empty() {
+            return Err(invalid());
+        }
+    }
+    Ok(fields)
+}
+
+/// Parse the JSON string at the start of text, returning it unescaped and the text after it
+fn parse_json_string(text: &str) -> Option<(String, &str)> {
+    let mut chars = text.strip_prefix('"')?.char_indices();
+    let mut value = String::new();
+    while let Some((index, c)) = chars.next() {
+        match c {
+            '"' => return Some((value, &text[index + 2..])),
+            '\\' => match chars.next()?.1 {
+                'n' => value.push('\n'),
+                't' => value.push('\t'),
+                'r' => value.push('\r'),
+                'b' => value.push('\u{8}'),
+                'f' => value.push('\u{c}'),
+                'u' => {
+                    let hex: String = (0..4)
+                        .filter_map(|_| chars.next())
+                        .map(|(_, c)| c)
+                        .collect();
+                    value.push(char::from_u32(u32::from_str_radix(&hex, 16).ok()?)?);
+                }
+                escaped => value.push(escaped),
+            },
+            c => value.push(c),
+        }
+    }
+    None
+}
+
+/// Format an optional value for JSON
+fn json_optional<T: ToString>(value: Option<T>) -> String {
+    value.map_or("null".to_string(), |value| value.to_string())
+}
+
+/// Description of one written chunk, for downstream scatter-gather steps and provenance tracking.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ManifestEntry {
+    path: PathBuf,
+    namespace: Option<String>,
+    chunk: Option<(usize, NonZero<usize>)>,
+    query_start: usize,
+    query_stop: usize,
+    num_queries: usize,
+    num_reads: usize,
+    bytes: Option<u64>,
+    md5: Option<String>,
+    xxh3: Option<String>,
+}
+
+impl ManifestEntry {
+    /// Create a new ManifestEntry for the chunk of queries query_start..query_stop written to path.
+    pub fn new<P>(path: P, query_start: usize, query_stop: usize, chunk_result: ChunkResult) -> Self
+    where
+        P: AsRef<Path>,
+    {
+        ManifestEntry {
+            path: path.as_ref().to_path_buf(),
+            namespace: None,
+            chunk: None,
+            query_start,
+            query_stop,
+            num_queries: chunk_result.num_queries(),
+            num_reads: chunk_result.num_reads(),
+            bytes: None,
+            md5: None,
+            xxh3: None,
+        }
+    }
+
+    /// Parse an entry from a line of a manifest, as written by to_json.
+    pub fn from_json(line: &str) -> Result<Self> {
+        let mut entry = ManifestEntry::new("", 0, 0, ChunkResult::Empty);
+        let mut chunk_index = None;
+        let mut num_chunks = None;
+        let parse_number = |key: &str, value: &str| -> Result<usize> {
+            value
+                .parse()
+                .map_err(|_| anyhow!("Manifest {key} {value:?} is not a number."))
+        };
+        for (key, value) in parse_json_object(line)? {
+            let Some(value) = value else {
+                continue;
+            };
+            match key.as_str() {
+                "path" => entry.path = PathBuf::from(value),
+                "namespace" => entry.namespace = Some(value),
+                "chunk_index" => chunk_index = Some(parse_number(&key, &value)?),
+                "num_chunks" => num_chunks = NonZero::new(parse_number(&key, &value)?),
+                "query_start" => entry.query_start = parse_number(&key, &value)?,
+                "query_stop" => entry.query_stop = parse_number(&key, &value)?,
+                "num_queries" => entry.num_queries = parse_number(&key, &value)?,
+                "num_reads" => entry.num_reads = parse_number(&key, &value)?,
+                "bytes" => entry.bytes = Some(parse_number(&key, &value)? as u64),
+                "md5" => entry.md5 = Some(value),
+                "xxh3" => entry.xxh3 = Some(value),
+                _ => {}
+            }
+        }
+        entry.chunk = chunk_index.zip(num_chunks);
+        Ok(entry)
+    }
+
+    /// Read every entry of a manifest file.
+    pub fn read_all<P>(manifest: P) -> Result<Vec<Self>>
+    where
+        P: AsRef<Path>,
+    {
+        fs::read_to_string(manifest.as_ref())
+            .map_err(|err| anyhow!("Reading manifest {:?}: {err}", manifest.as_ref()))
+            .categorize(ErrorCategory::Io)?
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(Self::from_json)
+            .collect()
+    }
+
+    /// Get the path the chunk was written to
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Check the checksum of a copy of the chunk against the size and checksums recorded in this
+    /// entry, erroring for any that differ or if none were recorded.
+    pub fn verify(&self, checksum: &ChunkChecksum) -> Result<()> {
+        if self.bytes.is_none() && self.md5.is_none() && self.xxh3.is_none() {
+            return Err(anyhow!(
+                "Manifest has no size or checksum for {:?}, which was written to stdout or a URL.",
+                self.path
+            ));
+        }
+        let mut mismatches = Vec::new();
+        if let Some(bytes) = self.bytes.filter(|&bytes| bytes != checksum.bytes) {
+            mismatches.push(format!("{} bytes, expected {bytes}", checksum.bytes));
+        }
+        for (name, recorded, actual) in [
+            ("md5", &self.md5, &checksum.md5),
+            ("xxh3", &self.xxh3, &checksum.xxh3),
+        ] {
+            if let Some(recorded) = recorded.as_ref().filter(|recorded| *recorded != actual) {
+                mismatches.push(format!("{name} {actual}, expected {recorded}"));
+            }
+        }
+        if mismatches.is_empty() {
+            Ok(())
+        } else {
+            Err(anyhow!(
+                "Chunk {:?} does not match the manifest: {}",
+                self.path,
+                mismatches.join("; ")
+            ))
+            .categorize(ErrorCategory::ChecksumMismatch)
+        }
+    }
+
+    /// Set the namespace of the input the chunk was split from
+    pub fn namespace(&mut self, namespace: Option<&str>) -> &mut Self {
+        self.namespace = namespace.map(str::to_string);
+        self
+    }
+
+    /// Set the chunk index and number of chunks, if the chunk was requested that way
+    pub fn chunk(&mut self, chunk_index: usize, num_chunks: NonZero<usize>) -> &mut Self {
+        self.chunk = Some((chunk_index, num_chunks));
+        self
+    }
+
+    /// Record the size and checksums of the written chunk. Chunks written to stdout, URLs or
+    /// FIFOs are left without them.
+    pub fn checksum_output(&mut self) -> Result<&mut Self> {
+        let path_type = PathType::from_path(&self.path)?;
+        if let PathType::FilePath(ref file_path) = path_type
+            && path_type.is_regular_file()
+        {
+            self.set_checksum(ChunkChecksum::from_path(file_path)?);
+        }
+        Ok(self)
+    }
+
+    /// Record an already computed size and checksums of the written chunk
+    pub fn set_checksum(&mut self, checksum: ChunkChecksum) -> &mut Self {
+        self.bytes = Some(checksum.bytes);
+        self.md5 = Some(checksum.md5);
+        self.xxh3 = Some(checksum.xxh3);
+        self
+    }
+
+    /// Format as a single-line JSON object
+    pub fn to_json(&self) -> String {
+        format!(
+            "{{\"path\": {}, \"namespace\": {}, \"chunk_index\": {}, \"num_chunks\": {}, \
+            \"query_start\": {}, \"query_stop\": {}, \"num_queries\": {}, \"num_reads\": {}, \
+            \"bytes\": {}, \"md5\": {}, \"xxh3\": {}}}",
+            json_string(&self.path.to_string_lossy()),
+            json_optional(self.namespace.as_deref().map(json_string)),
+            json_optional(self.chunk.map(|(chunk_index, _)| chunk_index)),
+            json_optional(self.chunk.map(|(_, num_chunks)| num_chunks)),
+            self.query_start,
+            self.query_stop,
+            self.num_queries,
+            self.num_reads,
+            json_optional(self.bytes),
+            json_optional(self.md5.as_deref().map(json_string)),
+            json_optional(self.xxh3.as_deref().map(json_string)),
+        )
+    }
+
+    /// Append this entry to a manifest file, as one JSON object per line (JSON Lines). Each entry
+    /// is a single append, so chunk-producing commands running in parallel can share a manifest.
+    pub fn append_to<P>(&self, manifest: P) -> Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(manifest.as_ref())
+            .map_err(|err| anyhow!("Opening manifest {:?}: {err}", manifest.as_ref()))?;
+        file.write_all(format!("{}\n", self.to_json()).as_bytes())?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        ChecksummedOutput, ChunkChecksum, ManifestEntry, Md5, json_string, parse_json_object,
+    };
+    use crate::{
+        chunkable::ChunkResult, error_category::ErrorCategory, maybe_compressed_io::BGZF_EOF,
+    };
+    use anyhow::Result;
+    use std::{fs, num::NonZero};
+    use tempfile::TempDir;
+    use xxhash_rust::xxh3::xxh3_64;
+
+    /// Test MD5 against known digests, including inputs that span several blocks and updates.
+    #[test]
+    fn test_md5() {
+        let md5 = |bytes: &[u8]| {
+            let mut md5 = Md5::new();
+            md5.update(bytes);
+            md5.hex_digest()
+        };
+        assert_eq!(md5(b""), "d41d8cd98f00b204e9800998ecf8427e");
+        assert_eq!(md5(b"abc"), "900150983cd24fb0d6963f7d28e17f72");
+        assert_eq!(
+            md5(b"The quick brown fox jumps over the lazy dog"),
+            "9e107d9d372bb6826bd81d3542a419d6"
+        );
+        let long = b"1234567890".repeat(8);
+        assert_eq!(md5(&long), "57edf4a22be3c955ac49da2e2107b67a");
+        let mut split = Md5::new();
+        split.update(&long[..7]);
+        split.update(&long[7..70]);
+        split.update(&long[70..]);
+        assert_eq!(split.hex_digest(), "57edf4a22be3c955ac49da2e2107b67a");
+    }
+
+    /// Test that entries record the chunk and output checksum, and are appended as JSON lines.
+    #[test]
+    fn test_manifest_entries() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let chunk_path = temp_dir.path().join("sample\"1.chunk.fastq");
+        fs::write(&chunk_path, b"abc")?;
+        let manifest = temp_dir.path().join("manifest.jsonl");
+        ManifestEntry::new(
+            &chunk_path,
+            10,
+            20,
+            ChunkResult::Written {
+                reads: 20,
+                queries: 10,
+            },
+        )
+        .namespace(Some("sample1"))
+        .chunk(1, NonZero::new(5).unwrap())
+        .checksum_output()?
+        .append_to(&manifest)?;
+        ManifestEntry::new("-", 20, 20, ChunkResult::Empty).append_to(&manifest)?;
+
+        let lines: Vec<String> = fs::read_to_string(&manifest)?
+            .lines()
+            .map(str::to_string)
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(
+            lines[0],
+            format!(
+                "{{\"path\": {}, \"namespace\": \"sample1\", \"chunk_index\": 1, \
+                \"num_chunks\": 5, \"query_start\": 10, \"query_stop\": 20, \"num_queries\": 10, \
+                \"num_reads\": 20, \"bytes\": 3, \"md5\": \"900150983cd24fb0d6963f7d28e17f72\", \
+                \"xxh3\": \"{:016x}\"}}",
+                json_string(&chunk_path.to_string_lossy()),
+                xxh3_64(b"abc")
+            )
+        );
+        assert!(lines[1].contains("\"num_reads\": 0, \"bytes\": null, \"md5\": null"));
+        assert!(json_string(&chunk_path.to_string_lossy()).contains("sample\\\"1"));
+        Ok(())
+    }
+
+    /// Test that entries read back from a manifest verify matching copies of the chunk and reject
+    /// changed ones, and that MD5 sidecars round trip.
+    #[test]
+    fn test_chunk_checksum() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let chunk_path = temp_dir.path().join("chunk\t1.fastq");
+        fs::write(&chunk_path, b"@read1\nACGT\n+\nIIII\n")?;
+        let manifest = temp_dir.path().join("manifest.jsonl");
+        ManifestEntry::new(&chunk_path, 0, 1, ChunkResult::Empty)
+            .chunk(0, NonZero::new(2).unwrap())
+            .checksum_output()?
+            .append_to(&manifest)?;
+        let entries = ManifestEntry::read_all(&manifest)?;
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].path(), chunk_path);
+        assert_eq!(entries[0].chunk, Some((0, NonZero::new(2).unwrap())));
+
+        let checksum = ChunkChecksum::from_path(&chunk_path)?;
+        assert_eq!(checksum.bytes, 19);
+        entries[0].verify(&checksum)?;
+        checksum.write_sidecar(&chunk_path)?;
+        assert_eq!(ChunkChecksum::read_sidecar(&chunk_path)?, checksum.md5);
+        assert!(
+            fs::read_to_string(temp_dir.path().join("chunk\t1.fastq.md5"))?
+                .ends_with("  chunk\t1.fastq\n")
+        );
+
+        fs::write(&chunk_path, b"@read1\nACGA\n+\nIIII\n")?;
+        let err = entries[0]
+            .verify(&ChunkChecksum::from_path(&chunk_path)?)
+            .unwrap_err();
+        assert_eq!(ErrorCategory::of(&err), ErrorCategory::ChecksumMismatch);
+        assert!(err.to_string().contains("md5"), "{err}");
+        assert!(!err.to_string().contains("bytes"), "{err}");
+        assert!(
+            ManifestEntry::new("-", 0, 0, ChunkResult::Empty)
+                .verify(&checksum)
+                .is_err()
+        );
+
+        assert_eq!(
+            parse_json_object("{\"a\": \"x,\\\"y\\u0041\", \"b\": null, \"c\": 12}")?,
+            vec![
+                ("a".to_string(), Some("x,\"yA".to_string())),
+                ("b".to_string(), None),
+                ("c".to_string(), Some("12".to_string())),
+            ]
+        );
+        assert!(parse_json_object("{\"a\": 1 \"b\": 2}").is_err());
+        Ok(())
+    }
+    /// Test that every JSON escape is unescaped, including those json_string never writes, and
+    /// that strings with control characters round trip.
+    #[test]
+    fn test_json_escapes() -> Result<()> {
+        assert_eq!(
+            parse_json_object("{\"a\": \"1\\r2\\b3\\f4\\n5\\t6\\/7\\\\8\\\"9\"}")?,
+            vec![(
+                "a".to_string(),
+                Some("1\r2\u{8}3\u{c}4\n5\t6/7\\8\"9".to_string())
+            )]
+        );
+        let value = "a\rb\u{8}c\u{c}d\u{1}e\"é";
+        assert_eq!(
+            parse_json_object(&format!("{{\"a\": {}}}", json_string(value)))?,
+            vec![("a".to_string(), Some(value.to_string()))]
+        );
+        Ok(())
+    }
+
+    /// Test that output written to the pipe of a ChecksummedOutput is checksummed as it is copied
+    /// to its file, optionally without a final BGZF end-of-file block, and that abandoned output
+    /// leaves no file.
+    #[test]
+    fn test_checksummed_output() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let chunk_path = temp_dir.path().join("chunk.fastq");
+        let output = ChecksummedOutput::create(&chunk_path, false)?;
+        fs::write(output.path(), b"@read1\nACGT\n+\nIIII\n")?;
+        let checksum = output.finish()?;
+        assert_eq!(checksum, ChunkChecksum::from_path(&chunk_path)?);
+        assert_eq!(checksum.bytes, 19);
+
+        let stripped_path = temp_dir.path().join("chunk.fastq.gz");
+        let output = ChecksummedOutput::create(&stripped_path, true)?;
+        let mut bytes = vec![7u8; 100_000];
+        bytes.extend(BGZF_EOF);
+        fs::write(output.path(), &bytes)?;
+        let checksum = output.finish()?;
+        assert_eq!(fs::read(&stripped_path)?, &bytes[..100_000]);
+        assert_eq!(checksum, ChunkChecksum::from_path(&stripped_path)?);
+
+        let abandoned_path = temp_dir.path().join("abandoned.fastq");
+        let output = ChecksummedOutput::create(&abandoned_path, false)?;
+        fs::write(output.path(), b"@read1\n")?;
+        drop(output);
+        assert_eq!(fs::read_dir(temp_dir.path())?.count(), 2);
+        Ok(())
+    }
+}
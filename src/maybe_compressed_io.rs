@@ -1,19 +1,84 @@
-use crate::seekable_chain::Chain;
+#[cfg(feature = "zstd")]
+use crate::zstd_process::{ZstdReader, ZstdWriter, check_zstd_command};
+use crate::{
+    atomic_output::AtomicWriter,
+    error_category::{Categorize, ErrorCategory},
+    memory_budget::MemoryBudget,
+    output_spec::OutputSpec,
+    path_type::PathType,
+    seekable_chain::Chain,
+    util::{configure_remote_access, configure_remote_output},
+};
 use anyhow::{Result, anyhow};
+use flate2::{bufread::MultiGzDecoder, write::GzEncoder};
 use noodles_bgzf::{
     VirtualPosition,
-    io::{MultithreadedReader, MultithreadedWriter, Seek as NoodlesSeek},
+    io::{
+        MultithreadedReader, MultithreadedWriter, Seek as NoodlesSeek,
+        multithreaded_writer::Builder as MultithreadedWriterBuilder,
+        writer::CompressionLevel as BgzfCompressionLevel,
+    },
+};
+use rust_htslib::{
+    bgzf::{CompressionLevel, Writer as HtsBgzfWriter},
+    htslib,
 };
 use std::{
+    ffi::CString,
     fs::{File, OpenOptions, create_dir_all},
-    io::{BufRead, BufReader, BufWriter, Cursor, Read, Seek, SeekFrom, Write},
+    io::{BufRead, BufReader, BufWriter, Cursor, PipeReader, Read, Seek, SeekFrom, Write, pipe},
     num::NonZero,
-    path::Path,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    thread::{self, JoinHandle},
 };
+use url::Url;
 
 /// First bytes of gzipped file
 const BGZIP_MAGIC_NUMBER: [u8; 2] = [0x1fu8, 0x8bu8];
 
+/// First bytes of a zstd-compressed file
+const ZSTD_MAGIC_NUMBER: [u8; 4] = [0x28u8, 0xb5u8, 0x2fu8, 0xfdu8];
+
+/// Number of bytes read to tell BGZF from plain gzip: through the "BC" extra subfield that marks a
+/// BGZF block
+const BGZF_HEADER_PREFIX_LEN: usize = 14;
+
+/// Codec of compressed FASTQ output, if set with set_output_codec
+static OUTPUT_CODEC: Mutex<Option<Codec>> = Mutex::new(None);
+
+/// Compression codec for FASTQ output
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Codec {
+    /// Blocked gzip, which is gzip that can be indexed and seeked in (the default)
+    #[default]
+    Bgzf,
+    /// Plain gzip, as one stream. Smaller, but can't be indexed or read in parallel.
+    Gzip,
+    /// zstd, compressed by the zstd command. Needs split-reads built with the zstd feature, and
+    /// like plain gzip, can't be indexed.
+    Zstd,
+    /// Uncompressed
+    None,
+}
+
+/// Set the codec of every FASTQ output of the process. Outputs whose path doesn't say whether they
+/// are compressed (e.g. stdout) are compressed with it unless it's none.
+pub fn set_output_codec(codec: Codec) {
+    *OUTPUT_CODEC.lock().unwrap_or_else(|err| err.into_inner()) = Some(codec);
+}
+
+/// Get the codec set with set_output_codec, if any.
+pub fn output_codec() -> Option<Codec> {
+    *OUTPUT_CODEC.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+/// Empty BGZF block that marks the end of a BGZF file
+pub const BGZF_EOF: [u8; 28] = [
+    0x1f, 0x8b, 0x08, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00, 0xff, 0x06, 0x00, 0x42, 0x43, 0x02, 0x00,
+    0x1b, 0x00, 0x03, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+];
+
 /// Helper function to get a File object that can be read from or written to, given the supplied
 /// path. The path may be "-", in which case we will read from stdin or write to stdout
 pub fn open_file<P: AsRef<Path>>(path: P, for_writing: bool) -> Result<File> {
@@ -29,7 +94,11 @@ pub fn open_file<P: AsRef<Path>>(path: P, for_writing: bool) -> Result<File> {
             .open(default)
             .map_err(|err| anyhow!("Opening {default}: {err}"))
     } else {
-        if for_writing && let Some(parent_dir) = path.as_ref().parent() {
+        // special files such as FIFOs must already exist, so their directory does too
+        if for_writing
+            && PathType::from_path(path.as_ref())?.is_regular_file()
+            && let Some(parent_dir) = path.as_ref().parent()
+        {
             create_dir_all(parent_dir)?
         }
         OpenOptions::new()
@@ -44,13 +113,370 @@ pub fn open_file<P: AsRef<Path>>(path: P, for_writing: bool) -> Result<File> {
     }
 }
 
+/// Remove the end-of-file block from the end of the local BGZF file at path, so that its blocks
+/// can be concatenated with other files' blocks before a single final end-of-file block. Returns
+/// true if there was an end-of-file block to remove.
+pub fn strip_bgzf_eof<P: AsRef<Path>>(path: P) -> Result<bool> {
+    let mut file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(path.as_ref())
+        .map_err(|err| anyhow!("Opening {:?}: {err}", path.as_ref()))?;
+    let len = file.metadata()?.len();
+    let Some(eof_start) = len.checked_sub(BGZF_EOF.len() as u64) else {
+        return Ok(false);
+    };
+    let mut tail = [0u8; BGZF_EOF.len()];
+    file.seek(SeekFrom::Start(eof_start))?;
+    file.read_exact(&mut tail)?;
+    if tail != BGZF_EOF {
+        return Ok(false);
+    }
+    file.set_len(eof_start)?;
+    Ok(true)
+}
+
+/// Open a writer to a cloud URL (e.g. "gs://" or "s3://"). Uploads go through htslib's remote
+/// file support, so credentials come from the same environment variables as samtools (e.g.
+/// GCS_OAUTH_TOKEN or AWS_ACCESS_KEY_ID). Output is BGZF-compressed if `compressed`, otherwise
+/// plain.
+pub fn open_url_writer(url: &Url, compressed: bool) -> Result<HtsBgzfWriter> {
+    let level = if compressed {
+        CompressionLevel::Default
+    } else {
+        CompressionLevel::Uncompressed
+    };
+    open_url_writer_with_level(url, level)
+}
+
+/// Open a writer to a cloud URL as open_url_writer does, BGZF-compressed at level.
+pub fn open_url_writer_with_level(url: &Url, level: CompressionLevel) -> Result<HtsBgzfWriter> {
+    configure_remote_output(url)?;
+    HtsBgzfWriter::from_path_with_level(url.as_str(), level)
+        .map_err(|err| anyhow!("Opening {url} for writing: {err}"))
+        .categorize(ErrorCategory::RemoteIo)
+}
+
+/// Read the whole (possibly BGZF-compressed) file at a URL or local path, or "-" for stdin, and
+/// return its decompressed bytes. Unlike rust_htslib's bgzf::Reader, always closes the file. Local
+/// paths are passed to htslib as bytes, so they needn't be valid UTF-8.
+pub fn read_decompressed<P: AsRef<Path>>(path: P) -> Result<Vec<u8>> {
+    let path = path.as_ref();
+    let category = path
+        .to_str()
+        .map_or(ErrorCategory::Io, ErrorCategory::for_path);
+    if category == ErrorCategory::RemoteIo {
+        configure_remote_access();
+    }
+    let c_path = CString::new(path.as_os_str().as_encoded_bytes())?;
+    // SAFETY: both arguments are valid NUL-terminated strings
+    let inner = unsafe { htslib::bgzf_open(c_path.as_ptr(), c"r".as_ptr()) };
+    if inner.is_null() {
+        return Err(anyhow!("file could not be opened: {path:?}")).categorize(category);
+    }
+    let mut bytes: Vec<u8> = Vec::new();
+    let mut buf = [0u8; 65536];
+    let result = loop {
+        // SAFETY: inner is a valid open BGZF, and buf has room for buf.len() bytes
+        let num_read = unsafe { htslib::bgzf_read(inner, buf.as_mut_ptr().cast(), buf.len()) };
+        match usize::try_from(num_read) {
+            Ok(0) => break Ok(bytes),
+            Ok(num_read) => bytes.extend_from_slice(&buf[..num_read]),
+            Err(_) => break Err(anyhow!("Error reading {path:?}")).categorize(category),
+        }
+    };
+    // SAFETY: inner is a valid open BGZF, and is not used after this
+    unsafe { htslib::bgzf_close(inner) };
+    result
+}
+
+/// htslib's code for BGZF compression, as returned by bgzf_compression
+const HTSLIB_BGZF_COMPRESSION: i32 = 2;
+
+/// Seekable reader for a BGZF file at a URL (http(s), ftp, s3, gs) or local path. htslib fetches
+/// remote files with HTTP range requests, so seeking to a virtual offset only downloads the file
+/// from that block on, rather than localizing the whole file. Reads directly from htslib's block
+/// buffer, so the position is always an exact virtual offset.
+pub struct RangedBgzfReader {
+    inner: *mut htslib::BGZF,
+}
+
+// SAFETY: the BGZF handle is owned by the RangedBgzfReader and only used through &mut self, so
+// it is never accessed from two threads at once.
+unsafe impl Send for RangedBgzfReader {}
+
+impl RangedBgzfReader {
+    /// Open the BGZF file at the requested URL or path. Errors if the file is not BGZF, because
+    /// other files cannot be seeked by virtual offset.
+    pub fn open(url: &str) -> Result<Self> {
+        if url.contains("://") {
+            configure_remote_access();
+        }
+        let c_url = CString::new(url)?;
+        // SAFETY: both arguments are valid NUL-terminated strings
+        let inner = unsafe { htslib::bgzf_open(c_url.as_ptr(), c"r".as_ptr()) };
+        if inner.is_null() {
+            return Err(anyhow!("Unable to open {url}")).categorize(ErrorCategory::for_path(url));
+        }
+        let reader = RangedBgzfReader { inner };
+        // SAFETY: inner is a valid open BGZF
+        if unsafe { htslib::bgzf_compression(reader.inner) } != HTSLIB_BGZF_COMPRESSION {
+            return Err(anyhow!(
+                "{url} is not BGZF-compressed, so it cannot be read by virtual offset. Compress \
+                it with bgzip."
+            ));
+        }
+        Ok(reader)
+    }
+
+    /// Get the virtual offset of the next unread byte
+    fn virtual_position(&self) -> u64 {
+        // SAFETY: inner is a valid open BGZF for the lifetime of self
+        let bgzf = unsafe { &*self.inner };
+        ((bgzf.block_address as u64) << 16) | (bgzf.block_offset as u64 & 0xFFFF)
+    }
+}
+
+/// impl Drop for RangedBgzfReader, closing the htslib file
+impl Drop for RangedBgzfReader {
+    fn drop(&mut self) {
+        // SAFETY: inner is a valid open BGZF, and is not used after this
+        unsafe { htslib::bgzf_close(self.inner) };
+    }
+}
+
+/// impl BufRead for RangedBgzfReader, serving data straight from htslib's current block
+impl BufRead for RangedBgzfReader {
+    fn fill_buf(&mut self) -> std::io::Result<&[u8]> {
+        loop {
+            // SAFETY: inner is a valid open BGZF for the lifetime of self
+            let bgzf = unsafe { &*self.inner };
+            if bgzf.block_offset < bgzf.block_length {
+                // SAFETY: uncompressed_block holds block_length valid bytes
+                return Ok(unsafe {
+                    std::slice::from_raw_parts(
+                        (bgzf.uncompressed_block as *const u8).add(bgzf.block_offset as usize),
+                        (bgzf.block_length - bgzf.block_offset) as usize,
+                    )
+                });
+            }
+            // Current block is used up (or was seeked to its end), so load the next one. As with
+            // bgzf_read, an empty block marks the end of the file.
+            // SAFETY: inner is a valid open BGZF
+            if unsafe { htslib::bgzf_read_block(self.inner) } != 0 {
+                return Err(std::io::Error::other(format!(
+                    "Error reading BGZF block at virtual offset {}",
+                    self.virtual_position()
+                )));
+            }
+            // SAFETY: inner is a valid open BGZF
+            if unsafe { (*self.inner).block_length } == 0 {
+                return Ok(&[]);
+            }
+        }
+    }
+
+    fn consume(&mut self, amt: usize) {
+        // SAFETY: inner is a valid open BGZF, and amt is at most the length returned by fill_buf
+        unsafe {
+            (*self.inner).block_offset += amt as i32;
+            (*self.inner).uncompressed_address += amt as i64;
+        }
+    }
+}
+
+/// impl Read for RangedBgzfReader, via BufRead
+impl Read for RangedBgzfReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let available = self.fill_buf()?;
+        let num_read = available.len().min(buf.len());
+        buf[..num_read].copy_from_slice(&available[..num_read]);
+        self.consume(num_read);
+        Ok(num_read)
+    }
+}
+
+/// impl Seek for RangedBgzfReader, using virtual offsets like the compressed MaybeCompressedReader
+impl Seek for RangedBgzfReader {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        match pos {
+            SeekFrom::Start(virtual_offset) => {
+                // SAFETY: inner is a valid open BGZF
+                if unsafe { htslib::bgzf_seek(self.inner, virtual_offset as i64, 0) } < 0 {
+                    Err(std::io::Error::other(format!(
+                        "Unable to seek to virtual offset {virtual_offset}"
+                    )))
+                } else {
+                    Ok(virtual_offset)
+                }
+            }
+            SeekFrom::Current(0) => Ok(self.virtual_position()),
+            _ => Err(std::io::Error::other("Cannot SeekFrom other than Start")),
+        }
+    }
+}
+
+/// Reader for the raw (possibly compressed) bytes of a file at a URL (http(s), ftp, s3, gs) or
+/// local path, fetched through htslib's remote file support.
+pub struct RawUrlReader {
+    inner: *mut htslib::BGZF,
+}
+
+// SAFETY: the BGZF handle is owned by the RawUrlReader and only used through &mut self, so it is
+// never accessed from two threads at once.
+unsafe impl Send for RawUrlReader {}
+
+impl RawUrlReader {
+    /// Open the file at the requested URL or path.
+    pub fn open(url: &str) -> Result<Self> {
+        if url.contains("://") {
+            configure_remote_access();
+        }
+        let c_url = CString::new(url)?;
+        // SAFETY: both arguments are valid NUL-terminated strings
+        let inner = unsafe { htslib::bgzf_open(c_url.as_ptr(), c"r".as_ptr()) };
+        if inner.is_null() {
+            return Err(anyhow!("Unable to open {url}")).categorize(ErrorCategory::for_path(url));
+        }
+        Ok(RawUrlReader { inner })
+    }
+}
+
+/// impl Drop for RawUrlReader, closing the htslib file
+impl Drop for RawUrlReader {
+    fn drop(&mut self) {
+        // SAFETY: inner is a valid open BGZF, and is not used after this
+        unsafe { htslib::bgzf_close(self.inner) };
+    }
+}
+
+/// impl Read for RawUrlReader, passing the file bytes through without decompressing them
+impl Read for RawUrlReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        // SAFETY: inner is a valid open BGZF, and buf has room for buf.len() bytes
+        let num_read =
+            unsafe { htslib::bgzf_raw_read(self.inner, buf.as_mut_ptr().cast(), buf.len()) };
+        usize::try_from(num_read)
+            .map_err(|_| std::io::Error::other("Error reading raw bytes from remote file"))
+    }
+}
+
+/// Writer that copies everything written to it to two writers, e.g. to save a stream to a local
+/// file while another consumer reads it.
+pub struct TeeWriter<A: Write, B: Write> {
+    first: A,
+    second: B,
+}
+
+impl<A: Write, B: Write> TeeWriter<A, B> {
+    /// Create a new TeeWriter writing to both `first` and `second`.
+    pub fn new(first: A, second: B) -> Self {
+        TeeWriter { first, second }
+    }
+}
+
+/// impl Write for TeeWriter, writing every byte to both writers
+impl<A: Write, B: Write> Write for TeeWriter<A, B> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.first.write_all(buf)?;
+        self.second.write_all(buf)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.first.flush()?;
+        self.second.flush()
+    }
+}
+
+/// Download a remote file to a local path in the background, while also streaming its bytes
+/// through a pipe, so that one network pass can both localize and process (e.g. index) the file.
+/// Readers open the pipe by its path, like a local file. The stream cannot be seeked. The local
+/// copy is written atomically (see AtomicWriter), so a failed download leaves no partial file.
+pub struct TeeDownload {
+    /// Path of the pipe that the bytes stream through
+    stream_path: PathBuf,
+    /// Read end of the pipe, held until the consumer has opened its own
+    stream: Option<PipeReader>,
+    /// Background thread copying the file, returning the number of bytes copied
+    copier: JoinHandle<Result<u64>>,
+}
+
+impl TeeDownload {
+    /// Start downloading the file at `url` (or a local path) to `local_path`. Only supported on
+    /// Unix-like systems, where the pipe can be opened by a /dev/fd path.
+    pub fn start<P: AsRef<Path>>(url: &str, local_path: P) -> Result<Self> {
+        let (stream, stream_writer) = pipe()?;
+        let stream_path = pipe_path(&stream)?;
+        let mut source = RawUrlReader::open(url)?;
+        let mut local_file = AtomicWriter::create(local_path, |path| {
+            Ok(BufWriter::new(open_file(path, true)?))
+        })?;
+        let copier = thread::spawn(move || {
+            let mut tee = TeeWriter::new(&mut *local_file, stream_writer);
+            let num_bytes = std::io::copy(&mut source, &mut tee)?;
+            tee.flush()?;
+            drop(tee);
+            local_file.commit_with(|mut local_file| Ok(local_file.flush()?))?;
+            Ok(num_bytes)
+        });
+        Ok(TeeDownload {
+            stream_path,
+            stream: Some(stream),
+            copier,
+        })
+    }
+
+    /// Get the path to open to read the streamed bytes.
+    pub fn stream_path(&self) -> &Path {
+        &self.stream_path
+    }
+
+    /// Close this handle's end of the stream, once the consumer has opened the stream path. After
+    /// this, the download fails instead of blocking if the consumer stops reading early.
+    pub fn release_stream(&mut self) {
+        self.stream = None;
+    }
+
+    /// Wait for the download to finish, returning the number of bytes written to the local path.
+    pub fn finish(mut self) -> Result<u64> {
+        self.release_stream();
+        self.copier
+            .join()
+            .map_err(|_| anyhow!("Download thread panicked"))?
+            .map_err(|err| anyhow!("Error downloading to local file: {err}"))
+    }
+}
+
+/// Get the path that opens the read end of a pipe.
+#[cfg(unix)]
+fn pipe_path(stream: &PipeReader) -> Result<PathBuf> {
+    use std::os::fd::AsRawFd;
+    Ok(PathBuf::from(format!("/dev/fd/{}", stream.as_raw_fd())))
+}
+
+/// Pipes can't be opened by path without /dev/fd.
+#[cfg(not(unix))]
+fn pipe_path(_stream: &PipeReader) -> Result<PathBuf> {
+    Err(anyhow!(
+        "Streaming a download while saving it needs /dev/fd, which only Unix-like systems have."
+    ))
+}
+
 /// Type alias for the ChainReader that is used by Compressed or Uncompressed readers.
 type Inner = Chain<Cursor<Vec<u8>>, File>;
 
 /// Enum for a file that may or may not be compressed.
 pub enum MaybeCompressedReader {
     Compressed(MultithreadedReader<Inner>),
+    /// Plain (not BGZF) gzip, which can only be read from the start
+    Gzip(Box<BufReader<MultiGzDecoder<BufReader<Inner>>>>),
     Uncompressed(BufReader<Inner>),
+    /// BGZF file at a URL, read with range requests
+    Remote(RangedBgzfReader),
+    /// zstd, which like plain gzip can only be read from the start
+    #[cfg(feature = "zstd")]
+    Zstd(BufReader<ZstdReader>),
 }
 
 impl MaybeCompressedReader {
@@ -60,27 +486,72 @@ impl MaybeCompressedReader {
         input_path: P,
         decompression_threads: NonZero<usize>,
     ) -> Result<MaybeCompressedReader> {
+        if let PathType::UrlPath(url) = PathType::from_path(input_path.as_ref())? {
+            return Ok(MaybeCompressedReader::Remote(RangedBgzfReader::open(
+                url.as_str(),
+            )?));
+        }
+        let input_path = input_path.as_ref();
         let mut input_file = open_file(input_path, false)?;
-        let mut first_bytes = [0u8; 2];
-        input_file.read_exact(&mut first_bytes)?;
-        let mut first_bytes_cursor = Cursor::new(first_bytes.into());
+        let mut first_bytes = Vec::with_capacity(BGZF_HEADER_PREFIX_LEN);
+        (&mut input_file)
+            .take(BGZF_HEADER_PREFIX_LEN as u64)
+            .read_to_end(&mut first_bytes)?;
+        let is_gzip = first_bytes.starts_with(&BGZIP_MAGIC_NUMBER);
+        let is_bgzf = is_gzip && is_bgzf_header(&first_bytes);
+        let is_zstd = first_bytes.starts_with(&ZSTD_MAGIC_NUMBER);
+        if is_zstd && cfg!(not(feature = "zstd")) {
+            return Err(anyhow!(
+                "{input_path:?} is zstd-compressed, which needs split-reads built with the zstd \
+                feature. Decompress it with zstd -d, or recompress it with bgzip."
+            ));
+        }
+        let mut first_bytes_cursor = Cursor::new(first_bytes);
         first_bytes_cursor.seek(SeekFrom::Start(0))?;
         let chain: Inner = Chain::new(first_bytes_cursor, input_file)?;
-        if first_bytes == BGZIP_MAGIC_NUMBER {
-            // it's gzipped, unzip with requested number of threads
+        let buffer_capacity = MemoryBudget::current().io_buffer();
+        #[cfg(feature = "zstd")]
+        if is_zstd {
+            // zstd, decompressed in one stream by the zstd command
+            return Ok(MaybeCompressedReader::Zstd(BufReader::with_capacity(
+                buffer_capacity,
+                ZstdReader::new(chain)?,
+            )));
+        }
+        if is_bgzf {
+            // it's BGZF, unzip with requested number of threads
             Ok(MaybeCompressedReader::Compressed(
                 MultithreadedReader::with_worker_count(decompression_threads, chain),
             ))
+        } else if is_gzip {
+            // it's plain gzip, which can only be unzipped in one stream
+            Ok(MaybeCompressedReader::Gzip(Box::new(
+                BufReader::with_capacity(
+                    buffer_capacity,
+                    MultiGzDecoder::new(BufReader::with_capacity(buffer_capacity, chain)),
+                ),
+            )))
         } else {
             // it's not gzipped, read plain text single-threaded
-            Ok(MaybeCompressedReader::Uncompressed(BufReader::new(chain)))
+            Ok(MaybeCompressedReader::Uncompressed(
+                BufReader::with_capacity(buffer_capacity, chain),
+            ))
         }
     }
 }
 
+/// Return true if the gzip header starting first_bytes is a BGZF block header: one with extra
+/// fields (FLG.FEXTRA) whose first subfield is "BC".
+fn is_bgzf_header(first_bytes: &[u8]) -> bool {
+    first_bytes.len() >= BGZF_HEADER_PREFIX_LEN
+        && first_bytes[3] & 0x04 != 0
+        && first_bytes[12..14] == *b"BC"
+}
+
 /// impl Seek trait for MaybeCompressedReader
 /// - Compressed readers use VirtualPosition for seeking,
 /// - Uncompressed readers use normal offset
+/// - Plain gzip readers can't seek
 impl Seek for MaybeCompressedReader {
     fn seek(&mut self, pos: std::io::SeekFrom) -> std::io::Result<u64> {
         match self {
@@ -96,7 +567,15 @@ impl Seek for MaybeCompressedReader {
                 SeekFrom::Current(0) => Ok(reader.virtual_position().into()),
                 _ => Err(std::io::Error::other("Cannot SeekFrom other than Start")),
             },
+            Self::Gzip(_) => Err(std::io::Error::other(
+                "Cannot seek in plain gzip (not BGZF) input. Recompress it with bgzip.",
+            )),
             Self::Uncompressed(reader) => reader.seek(pos),
+            Self::Remote(reader) => reader.seek(pos),
+            #[cfg(feature = "zstd")]
+            Self::Zstd(_) => Err(std::io::Error::other(
+                "Cannot seek in zstd input. Recompress it with bgzip.",
+            )),
         }
     }
 }
@@ -106,7 +585,11 @@ impl Read for MaybeCompressedReader {
     fn read(&mut self, buf: &mut [u8]) -> Result<usize, std::io::Error> {
         match self {
             MaybeCompressedReader::Compressed(inner) => inner.read(buf),
+            MaybeCompressedReader::Gzip(inner) => inner.read(buf),
             MaybeCompressedReader::Uncompressed(inner) => inner.read(buf),
+            MaybeCompressedReader::Remote(inner) => inner.read(buf),
+            #[cfg(feature = "zstd")]
+            MaybeCompressedReader::Zstd(inner) => inner.read(buf),
         }
     }
 }
@@ -116,14 +599,22 @@ impl BufRead for MaybeCompressedReader {
     fn fill_buf(&mut self) -> std::io::Result<&[u8]> {
         match self {
             MaybeCompressedReader::Compressed(inner) => inner.fill_buf(),
+            MaybeCompressedReader::Gzip(inner) => inner.fill_buf(),
             MaybeCompressedReader::Uncompressed(inner) => inner.fill_buf(),
+            MaybeCompressedReader::Remote(inner) => inner.fill_buf(),
+            #[cfg(feature = "zstd")]
+            MaybeCompressedReader::Zstd(inner) => inner.fill_buf(),
         }
     }
 
     fn consume(&mut self, amt: usize) {
         match self {
             MaybeCompressedReader::Compressed(inner) => inner.consume(amt),
+            MaybeCompressedReader::Gzip(inner) => inner.consume(amt),
             MaybeCompressedReader::Uncompressed(inner) => inner.consume(amt),
+            MaybeCompressedReader::Remote(inner) => inner.consume(amt),
+            #[cfg(feature = "zstd")]
+            MaybeCompressedReader::Zstd(inner) => inner.consume(amt),
         }
     }
 }
@@ -131,7 +622,14 @@ impl BufRead for MaybeCompressedReader {
 /// Enum for writing a single fastq (regardless of read organization). Either compressed or not
 pub enum MaybeCompressedWriter {
     Compressed(BufWriter<MultithreadedWriter<File>>),
+    /// Plain gzip, written as one stream
+    Gzip(BufWriter<GzEncoder<File>>),
     Uncompressed(BufWriter<File>),
+    /// Writer to a cloud URL, compressed or not
+    Remote(BufWriter<HtsBgzfWriter>),
+    /// zstd, compressed by the zstd command
+    #[cfg(feature = "zstd")]
+    Zstd(BufWriter<ZstdWriter>),
 }
 
 impl MaybeCompressedWriter {
@@ -144,25 +642,141 @@ impl MaybeCompressedWriter {
         compressed: bool,
         threads: NonZero<usize>,
     ) -> Result<MaybeCompressedWriter> {
+        let codec = MaybeCompressedWriter::codec(input_path.as_ref(), compressed)?;
+        MaybeCompressedWriter::with_codec(input_path, codec, None, threads)
+    }
+
+    /// Create new writer, compressed if `compressed` regardless of the path's extension, e.g. to
+    /// write to a temporary path whose extension doesn't match the output's.
+    pub fn with_compression<P: AsRef<Path>>(
+        input_path: P,
+        compressed: bool,
+        threads: NonZero<usize>,
+    ) -> Result<MaybeCompressedWriter> {
+        let codec = match output_codec() {
+            Some(codec) if compressed && codec != Codec::None => codec,
+            _ if compressed => Codec::Bgzf,
+            _ => Codec::None,
+        };
+        MaybeCompressedWriter::with_codec(input_path, codec, None, threads)
+    }
+
+    /// Create new writer compressed with codec, regardless of the path's extension, at the given
+    /// compression level (0-9), or the codec's default level if None. URLs can only be written
+    /// BGZF-compressed or uncompressed.
+    pub fn with_codec<P: AsRef<Path>>(
+        input_path: P,
+        codec: Codec,
+        level: Option<u32>,
+        threads: NonZero<usize>,
+    ) -> Result<MaybeCompressedWriter> {
+        let bgzf_level = match level {
+            Some(level) => u8::try_from(level)
+                .ok()
+                .and_then(BgzfCompressionLevel::new)
+                .ok_or_else(|| anyhow!("Compression level {level} is out of range."))?,
+            None => BgzfCompressionLevel::default(),
+        };
+        let buffer_capacity = MemoryBudget::current().io_buffer();
+        if let PathType::UrlPath(url) = PathType::from_path(input_path.as_ref())? {
+            let hts_level = match codec {
+                Codec::Bgzf => CompressionLevel::Level(bgzf_level.get() as i8),
+                Codec::Gzip => Err(anyhow!(
+                    "Cannot write plain gzip to {url}, only BGZF or uncompressed."
+                ))?,
+                Codec::Zstd => Err(anyhow!(
+                    "Cannot write zstd to {url}, only BGZF or uncompressed."
+                ))?,
+                Codec::None => CompressionLevel::Uncompressed,
+            };
+            return Ok(MaybeCompressedWriter::Remote(BufWriter::with_capacity(
+                buffer_capacity,
+                open_url_writer_with_level(&url, hts_level)?,
+            )));
+        }
+        #[cfg(feature = "zstd")]
+        if codec == Codec::Zstd {
+            check_zstd_command()?;
+        }
         let fastq_file = open_file(input_path.as_ref(), true)?;
-        if MaybeCompressedWriter::is_compressed(input_path, compressed) {
-            Ok(MaybeCompressedWriter::Compressed(BufWriter::new(
-                MultithreadedWriter::with_worker_count(threads, fastq_file),
-            )))
-        } else {
-            Ok(MaybeCompressedWriter::Uncompressed(BufWriter::new(
-                fastq_file,
-            )))
+        match codec {
+            Codec::Bgzf => Ok(MaybeCompressedWriter::Compressed(BufWriter::with_capacity(
+                buffer_capacity,
+                MultithreadedWriterBuilder::default()
+                    .set_compression_level(bgzf_level)
+                    .set_worker_count(threads)
+                    .build_from_writer(fastq_file),
+            ))),
+            Codec::Gzip => Ok(MaybeCompressedWriter::Gzip(BufWriter::with_capacity(
+                buffer_capacity,
+                GzEncoder::new(fastq_file, bgzf_level.into()),
+            ))),
+            Codec::None => Ok(MaybeCompressedWriter::Uncompressed(
+                BufWriter::with_capacity(buffer_capacity, fastq_file),
+            )),
+            #[cfg(feature = "zstd")]
+            Codec::Zstd => Ok(MaybeCompressedWriter::Zstd(BufWriter::with_capacity(
+                buffer_capacity,
+                ZstdWriter::new(fastq_file, level, threads)?,
+            ))),
+            #[cfg(not(feature = "zstd"))]
+            Codec::Zstd => Err(anyhow!(
+                "Cannot write zstd to {:?}: split-reads was built without the zstd feature.",
+                input_path.as_ref()
+            )),
+        }
+    }
+
+    /// Determine the codec of output to a path. When the path ends in ".gz" or ".bgz", compress
+    /// with the codec set by set_output_codec (BGZF by default), when it ends in ".zst", with
+    /// zstd, and when it ends in ".fastq" or ".fq", don't. Otherwise (e.g. stdout or a named pipe),
+    /// use the codec set by set_output_codec, or else BGZF if `compressed`.
+    pub fn codec<P: AsRef<Path>>(input_path: P, compressed: bool) -> Result<Codec> {
+        let input_path = input_path.as_ref();
+        let is_zstd_path = OutputSpec::is_zstd_path(input_path);
+        match (OutputSpec::path_compression(input_path), output_codec()) {
+            (Some(true), Some(Codec::None)) => Err(anyhow!(
+                "Output {input_path:?} has a compressed extension, but the output codec is none."
+            )),
+            (Some(true), Some(codec)) if (codec == Codec::Zstd) != is_zstd_path => Err(anyhow!(
+                "The extension of output {input_path:?} doesn't match the output codec {codec:?}."
+            )),
+            (Some(true), _) if is_zstd_path => Ok(Codec::Zstd),
+            (Some(true), codec) => Ok(codec.unwrap_or_default()),
+            (Some(false), _) => Ok(Codec::None),
+            (None, Some(codec)) => Ok(codec),
+            (None, None) if compressed => Ok(Codec::Bgzf),
+            (None, None) => Ok(Codec::None),
         }
     }
 
-    /// Determine if output is compressed. When writing to a real path, make compressed if the path
-    /// ends in ".gz" or ".bgz", uncompressed otherwise. When writing to stdout, obey `compressed`
-    /// boolean.
-    fn is_compressed<P: AsRef<Path>>(input_path: P, compressed: bool) -> bool {
-        match input_path.as_ref().extension() {
-            Some(os_str) => (os_str == "gz") || (os_str == "bgz"),
-            None => compressed,
+    /// Determine if output is compressed, with the codec that `codec` decides.
+    pub fn is_compressed<P: AsRef<Path>>(input_path: P, compressed: bool) -> bool {
+        Self::codec(input_path, compressed).is_ok_and(|codec| codec != Codec::None)
+    }
+}
+
+impl MaybeCompressedWriter {
+    /// Flush buffered data, then end compressed output (with the BGZF end-of-file block, or the
+    /// gzip trailer), returning any error instead of leaving the ending to Drop, which ignores
+    /// errors.
+    pub fn finish(&mut self) -> std::io::Result<()> {
+        match self {
+            MaybeCompressedWriter::Compressed(inner) => {
+                inner.flush()?;
+                inner.get_mut().finish().map(|_| ())
+            }
+            MaybeCompressedWriter::Gzip(inner) => {
+                inner.flush()?;
+                inner.get_mut().try_finish()
+            }
+            MaybeCompressedWriter::Uncompressed(inner) => inner.flush(),
+            MaybeCompressedWriter::Remote(inner) => inner.flush(),
+            #[cfg(feature = "zstd")]
+            MaybeCompressedWriter::Zstd(inner) => {
+                inner.flush()?;
+                inner.get_mut().finish()
+            }
         }
     }
 }
@@ -172,14 +786,282 @@ impl Write for MaybeCompressedWriter {
     fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
         match self {
             MaybeCompressedWriter::Compressed(inner) => inner.write(buf),
+            MaybeCompressedWriter::Gzip(inner) => inner.write(buf),
             MaybeCompressedWriter::Uncompressed(inner) => inner.write(buf),
+            MaybeCompressedWriter::Remote(inner) => inner.write(buf),
+            #[cfg(feature = "zstd")]
+            MaybeCompressedWriter::Zstd(inner) => inner.write(buf),
         }
     }
 
     fn flush(&mut self) -> std::io::Result<()> {
         match self {
             MaybeCompressedWriter::Compressed(inner) => inner.flush(),
+            MaybeCompressedWriter::Gzip(inner) => inner.flush(),
             MaybeCompressedWriter::Uncompressed(inner) => inner.flush(),
+            MaybeCompressedWriter::Remote(inner) => inner.flush(),
+            #[cfg(feature = "zstd")]
+            MaybeCompressedWriter::Zstd(inner) => inner.flush(),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        Codec, MaybeCompressedReader, MaybeCompressedWriter, RangedBgzfReader, TeeDownload,
+        ZSTD_MAGIC_NUMBER,
+    };
+    use crate::{
+        chunkable::ChunkableRecordReader,
+        fastq::{FastqReader, FastqRecord, FastqWriter},
+        split_index::SplitIndex,
+        util::get_fastq_reader,
+    };
+    use anyhow::Result;
+    use noodles_bgzf::io::Writer as NoodlesBgzfWriter;
+    use rand::{Rng, SeedableRng, rngs::StdRng};
+    use std::{
+        fs::{self, File},
+        io::Write,
+        num::NonZero,
+    };
+    use tempfile::TempDir;
+
+    /// Test that FASTQ written with each codec is complete once the writer is finished, and reads
+    /// back through MaybeCompressedReader, which tells BGZF from plain gzip, and that zstd input is
+    /// a clear error.
+    #[test]
+    fn test_codecs() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let threads = NonZero::new(2).unwrap();
+        assert_eq!(
+            MaybeCompressedWriter::codec("reads.fastq.gz", false)?,
+            Codec::Bgzf
+        );
+        assert_eq!(MaybeCompressedWriter::codec("reads.fq", true)?, Codec::None);
+        assert_eq!(MaybeCompressedWriter::codec("-", true)?, Codec::Bgzf);
+        assert_eq!(MaybeCompressedWriter::codec("-", false)?, Codec::None);
+        assert_eq!(
+            MaybeCompressedWriter::codec("reads.fastq.zst", false)?,
+            Codec::Zstd
+        );
+
+        let text: String = (0..1000)
+            .map(|read| format!("@read{read}\nACGTACGTAC\n+\nIIIIIIIIII\n"))
+            .collect();
+        for codec in [Codec::Bgzf, Codec::Gzip, Codec::None] {
+            let fastq = temp_dir.path().join(format!("{codec:?}.fastq"));
+            let mut writer = MaybeCompressedWriter::with_codec(&fastq, codec, None, threads)?;
+            writer.write_all(text.as_bytes())?;
+            // the file is read before the writer is dropped, so finish must have ended it
+            writer.finish()?;
+            let bytes = fs::read(&fastq)?;
+            assert_eq!(bytes.starts_with(&[0x1f, 0x8b]), codec != Codec::None);
+            assert_eq!(
+                bytes.len() > 14 && bytes[12..14] == *b"BC",
+                codec == Codec::Bgzf
+            );
+            let mut reader = MaybeCompressedReader::new(&fastq, threads)?;
+            assert_eq!(
+                matches!(reader, MaybeCompressedReader::Gzip(_)),
+                codec == Codec::Gzip
+            );
+            let mut decoded = String::new();
+            std::io::Read::read_to_string(&mut reader, &mut decoded)?;
+            assert_eq!(decoded, text);
+            drop(writer);
+        }
+
+        // errors ending the output are returned rather than lost when the writer is dropped
+        #[cfg(target_os = "linux")]
+        for codec in [Codec::Bgzf, Codec::Gzip] {
+            let mut writer = MaybeCompressedWriter::with_codec("/dev/full", codec, None, threads)?;
+            writer.write_all(text.as_bytes())?;
+            assert!(writer.finish().is_err(), "{codec:?}");
+        }
+
+        let zstd = temp_dir.path().join("reads.fastq.zst");
+        fs::write(&zstd, [ZSTD_MAGIC_NUMBER.as_slice(), &[0u8; 16]].concat())?;
+        #[cfg(not(feature = "zstd"))]
+        {
+            let err = MaybeCompressedReader::new(&zstd, threads).err().unwrap();
+            assert!(err.to_string().contains("zstd feature"), "{err}");
+            assert!(MaybeCompressedWriter::with_codec(&zstd, Codec::Zstd, None, threads).is_err());
+        }
+        // corrupt zstd is an error at the end of reading, not a short read
+        #[cfg(feature = "zstd")]
+        assert!(
+            std::io::Read::read_to_end(
+                &mut MaybeCompressedReader::new(&zstd, threads)?,
+                &mut Vec::new()
+            )
+            .is_err()
+        );
+        Ok(())
+    }
+
+    /// Test that FASTQ written with the zstd codec is zstd-compressed once the writer is finished,
+    /// and reads back through MaybeCompressedReader, which can't seek in it.
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn test_zstd_codec() -> Result<()> {
+        use std::io::{Read, Seek, SeekFrom};
+        let temp_dir = TempDir::new()?;
+        let threads = NonZero::new(2).unwrap();
+        let text: String = (0..1000)
+            .map(|read| format!("@read{read}\nACGTACGTAC\n+\nIIIIIIIIII\n"))
+            .collect();
+        let fastq = temp_dir.path().join("reads.fastq.zst");
+        let mut writer = MaybeCompressedWriter::with_codec(&fastq, Codec::Zstd, Some(3), threads)?;
+        writer.write_all(text.as_bytes())?;
+        writer.finish()?;
+        let bytes = fs::read(&fastq)?;
+        assert!(bytes.starts_with(&ZSTD_MAGIC_NUMBER));
+        assert!(bytes.len() < text.len() / 4);
+
+        let mut reader = MaybeCompressedReader::new(&fastq, threads)?;
+        assert!(matches!(reader, MaybeCompressedReader::Zstd(_)));
+        let mut decoded = String::new();
+        reader.read_to_string(&mut decoded)?;
+        assert_eq!(decoded, text);
+        assert!(reader.seek(SeekFrom::Start(0)).is_err());
+
+        // a reader dropped before the end stops the zstd command
+        let mut reader = MaybeCompressedReader::new(&fastq, threads)?;
+        reader.read_exact(&mut [0u8; 10])?;
+        drop(reader);
+        Ok(())
+    }
+
+    /// Test that FASTQ is compressed at the requested level by both codecs, so that level 1 writes
+    /// larger (faster) output than level 9, and level 0 larger still.
+    #[test]
+    fn test_compression_levels() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let threads = NonZero::new(2).unwrap();
+        let mut rng = StdRng::seed_from_u64(1080);
+        let sequence = "ACGT".repeat(25);
+        let text: String = (0..2000)
+            .map(|read| {
+                let quals: String = (0..100).map(|_| rng.random_range('!'..'A')).collect();
+                format!("@read{read}\n{sequence}\n+\n{quals}\n")
+            })
+            .collect();
+        for codec in [Codec::Bgzf, Codec::Gzip] {
+            let mut sizes = Vec::new();
+            for level in [0, 1, 9] {
+                let fastq = temp_dir.path().join(format!("{codec:?}.{level}.fastq.gz"));
+                let mut writer =
+                    MaybeCompressedWriter::with_codec(&fastq, codec, Some(level), threads)?;
+                writer.write_all(text.as_bytes())?;
+                writer.flush()?;
+                drop(writer);
+                let mut decoded = String::new();
+                std::io::Read::read_to_string(
+                    &mut MaybeCompressedReader::new(&fastq, threads)?,
+                    &mut decoded,
+                )?;
+                assert_eq!(decoded, text);
+                sizes.push(fs::metadata(&fastq)?.len());
+            }
+            assert!(
+                sizes[0] > sizes[1] && sizes[1] > sizes[2],
+                "{codec:?}: {sizes:?}"
+            );
+        }
+        let fastq = temp_dir.path().join("bad.fastq.gz");
+        assert!(MaybeCompressedWriter::with_codec(&fastq, Codec::Bgzf, Some(10), threads).is_err());
+        Ok(())
+    }
+
+    /// Test that seeking the htslib-backed reader to offsets from the noodles-backed reader reads
+    /// the same records, including across block boundaries.
+    #[test]
+    fn test_ranged_bgzf_reader_seeks() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let fastq = temp_dir.path().join("reads.fastq.gz");
+        let mut writer = NoodlesBgzfWriter::new(File::create(&fastq)?);
+        for read in 0..100 {
+            writer.write_all(format!("@read{read}\nACGTACGT\n+\nIIIIIIII\n").as_bytes())?;
+            if read % 7 == 6 {
+                writer.flush()?;
+            }
+        }
+        writer.finish()?;
+
+        let mut local = FastqReader::new(MaybeCompressedReader::new(
+            &fastq,
+            NonZero::new(1).unwrap(),
+        )?);
+        let mut expected = Vec::new();
+        let mut record = FastqRecord::new();
+        loop {
+            let offset = local.tell()?;
+            let Some(result) = local.read_into(&mut record) else {
+                break;
+            };
+            result?;
+            expected.push((offset, record.qname().to_vec()));
+        }
+        assert_eq!(expected.len(), 100);
+
+        let mut ranged = FastqReader::new(MaybeCompressedReader::Remote(RangedBgzfReader::open(
+            fastq.to_str().unwrap(),
+        )?));
+        for (offset, expected_qname) in expected.iter().rev().step_by(3) {
+            ChunkableRecordReader::<FastqRecord>::seek(&mut ranged, *offset)?;
+            ranged.read_into(&mut record).unwrap()?;
+            assert_eq!(record.qname(), expected_qname.as_slice());
+        }
+        Ok(())
+    }
+
+    /// Test that a TeeDownload saves an exact local copy while the streamed bytes are indexed, and
+    /// that the index matches one built from the local copy.
+    #[cfg(unix)]
+    #[test]
+    fn test_tee_download() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let fastq = temp_dir.path().join("reads.fastq.gz");
+        let mut writer = NoodlesBgzfWriter::new(File::create(&fastq)?);
+        for read in 0..5000 {
+            writer.write_all(format!("@read{read}\nACGTACGTAC\n+\nIIIIIIIIII\n").as_bytes())?;
+        }
+        writer.finish()?;
+        let local_copy = temp_dir.path().join("local.fastq.gz");
+        let threads = NonZero::new(1).unwrap();
+        let num_bins = NonZero::new(10).unwrap();
+
+        let mut download = TeeDownload::start(fastq.to_str().unwrap(), &local_copy)?;
+        let reader = get_fastq_reader(download.stream_path(), threads)?;
+        download.release_stream();
+        let streamed_index = SplitIndex::build(reader, None::<FastqWriter<File>>, num_bins, 60)?;
+        assert_eq!(download.finish()?, fs::metadata(&fastq)?.len());
+        assert_eq!(fs::read(&local_copy)?, fs::read(&fastq)?);
+
+        let reader = get_fastq_reader(&local_copy, threads)?;
+        let local_index = SplitIndex::build(reader, None::<FastqWriter<File>>, num_bins, 60)?;
+        assert_eq!(streamed_index.num_queries(), 5000);
+        assert!(streamed_index == local_index);
+        Ok(())
+    }
+
+    /// Test that a download that fails, here because nothing reads the stream, leaves no partial
+    /// local copy behind.
+    #[cfg(unix)]
+    #[test]
+    fn test_tee_download_failure() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let source = temp_dir.path().join("reads.fastq");
+        fs::write(&source, "@read\nACGT\n+\nIIII\n".repeat(100000))?;
+        let local_copy = temp_dir.path().join("local.fastq");
+
+        let mut download = TeeDownload::start(source.to_str().unwrap(), &local_copy)?;
+        download.release_stream();
+        assert!(download.finish().is_err());
+        assert!(!local_copy.exists());
+        assert_eq!(fs::read_dir(temp_dir.path())?.count(), 1);
+        Ok(())
+    }
+}
@@ -0,0 +1,220 @@
+use crate::{progress::format_bytes, split_index::SplitRecord};
+use anyhow::{Error, Result, anyhow};
+use log::debug;
+use std::{
+    fmt,
+    num::NonZero,
+    str::FromStr,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+/// Memory budget of the process in bytes, or 0 if unlimited
+static MAX_MEMORY: AtomicU64 = AtomicU64::new(0);
+
+/// Memory assumed for each BGZF/CRAM worker thread: the jobs queued in and out of it, which for
+/// CRAM are whole containers of slices.
+pub const WORKER_THREAD_BYTES: u64 = 16 << 20;
+
+/// Memory assumed for each record read but not yet written by a chunk pipeline, with its tags
+pub const INFLIGHT_RECORD_BYTES: u64 = 4 << 10;
+
+/// Memory assumed for each bin of an in-memory SplitIndex: the SplitRecord and its query names
+pub const SPLIT_RECORD_BYTES: u64 = size_of::<SplitRecord>() as u64 + 128;
+
+/// Capacity of the buffer of each buffered reader or writer without a budget
+pub const IO_BUFFER_BYTES: u64 = 64 << 10;
+
+/// Smallest buffer a budget caps buffered readers and writers to, below which reading and
+/// writing would take a system call every few records
+pub const MIN_IO_BUFFER_BYTES: u64 = 4 << 10;
+
+/// Value of a --max-memory option: a number of bytes, optionally fractional and followed by a unit,
+/// e.g. "2GiB" or "512M". K, M, G and T (with or without "iB") are binary units, and KB, MB, GB and
+/// TB decimal ones.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ByteSize(pub NonZero<u64>);
+
+impl FromStr for ByteSize {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid =
+            || anyhow!("Memory size must be a positive number of bytes, e.g. \"2GiB\", not {s:?}.");
+        let trimmed = s.trim();
+        let split = trimmed
+            .find(|c: char| c.is_ascii_alphabetic())
+            .unwrap_or(trimmed.len());
+        let (number, unit) = trimmed.split_at(split);
+        let multiplier: u64 = match unit.to_ascii_lowercase().as_str() {
+            "" | "b" => 1,
+            "k" | "kib" => 1 << 10,
+            "m" | "mib" => 1 << 20,
+            "g" | "gib" => 1 << 30,
+            "t" | "tib" => 1 << 40,
+            "kb" => 1_000,
+            "mb" => 1_000_000,
+            "gb" => 1_000_000_000,
+            "tb" => 1_000_000_000_000,
+            _ => return Err(invalid()),
+        };
+        let number: f64 = number.trim().parse().map_err(|_| invalid())?;
+        let bytes = number * multiplier as f64;
+        if !bytes.is_finite() || bytes >= u64::MAX as f64 {
+            return Err(invalid());
+        }
+        NonZero::new(bytes as u64).map(ByteSize).ok_or_else(invalid)
+    }
+}
+
+impl fmt::Display for ByteSize {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", format_bytes(self.0.get()))
+    }
+}
+
+/// Set the memory budget of the whole process, or None (the default) for no budget. Half of it is
+/// for the queues of BGZF/CRAM worker threads, a quarter for records buffered between reading and
+/// writing, and a quarter for in-memory SplitIndex bins. Buffered readers and writers, small beside
+/// these, each get at most 1/1024 of it.
+pub fn set_max_memory(max_memory: Option<ByteSize>) {
+    MAX_MEMORY.store(
+        max_memory.map_or(0, |bytes| bytes.0.get()),
+        Ordering::Relaxed,
+    );
+}
+
+/// Memory budget, capping the memory that grows with threads, buffered records and index bins.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct MemoryBudget {
+    max_memory: Option<ByteSize>,
+}
+
+impl MemoryBudget {
+    /// Create a budget of max_memory, or no budget if None.
+    pub fn new(max_memory: Option<ByteSize>) -> Self {
+        MemoryBudget { max_memory }
+    }
+
+    /// Get the budget of the whole process (see set_max_memory).
+    pub fn current() -> Self {
+        MemoryBudget::new(NonZero::new(MAX_MEMORY.load(Ordering::Relaxed)).map(ByteSize))
+    }
+
+    /// Get the maximum memory, if there is a budget.
+    pub fn max_memory(&self) -> Option<ByteSize> {
+        self.max_memory
+    }
+
+    /// Number of items of item_bytes each that fit in fraction (1 / divisor) of the budget, or
+    /// None if there is no budget. Always at least one.
+    fn items(&self, divisor: u64, item_bytes: u64) -> Option<usize> {
+        let max_memory = self.max_memory?.0.get();
+        Some(((max_memory / divisor / item_bytes) as usize).max(1))
+    }
+
+    /// Cap the threads of each of num_pools worker thread pools working at once, so that their
+    /// job queues fit in half the budget between them.
+    pub fn worker_threads(
+        &self,
+        threads: NonZero<usize>,
+        num_pools: NonZero<usize>,
+    ) -> NonZero<usize> {
+        let Some(max_threads) = self.items(2 * num_pools.get() as u64, WORKER_THREAD_BYTES) else {
+            return threads;
+        };
+        if threads.get() > max_threads {
+            debug!("Capping {threads} worker threads to {max_threads} to fit --max-memory.");
+        }
+        NonZero::new(threads.get().min(max_threads)).unwrap_or(NonZero::<usize>::MIN)
+    }
+
+    /// Cap the records read but not yet written by a chunk pipeline, so they fit in a quarter of
+    /// the budget.
+    pub fn inflight_records(&self, max_inflight_records: usize) -> usize {
+        self.items(4, INFLIGHT_RECORD_BYTES)
+            .map_or(max_inflight_records, |max_records| {
+                max_inflight_records.min(max_records)
+            })
+    }
+
+    /// Cap the number of bins of an in-memory SplitIndex, so they fit in a quarter of the budget.
+    pub fn index_bins(&self, num_bins: usize) -> usize {
+        self.items(4, SPLIT_RECORD_BYTES)
+            .map_or(num_bins, |max_bins| num_bins.min(max_bins))
+    }
+
+    /// Get the capacity of the buffer of a buffered reader or writer: IO_BUFFER_BYTES, capped to
+    /// 1/1024 of the budget but no less than MIN_IO_BUFFER_BYTES.
+    pub fn io_buffer(&self) -> usize {
+        self.items(1024, 1)
+            .map_or(IO_BUFFER_BYTES as usize, |max_bytes| {
+                max_bytes.clamp(MIN_IO_BUFFER_BYTES as usize, IO_BUFFER_BYTES as usize)
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        ByteSize, INFLIGHT_RECORD_BYTES, IO_BUFFER_BYTES, MIN_IO_BUFFER_BYTES, MemoryBudget,
+        SPLIT_RECORD_BYTES, WORKER_THREAD_BYTES,
+    };
+    use anyhow::Result;
+    use std::num::NonZero;
+
+    /// Test parsing memory sizes with binary and decimal units, and rejecting invalid ones.
+    #[test]
+    fn test_byte_size() -> Result<()> {
+        let bytes = |s: &str| -> Result<u64> { Ok(s.parse::<ByteSize>()?.0.get()) };
+        assert_eq!(bytes("2GiB")?, 2 << 30);
+        assert_eq!(bytes("2G")?, 2 << 30);
+        assert_eq!(bytes("512 MiB")?, 512 << 20);
+        assert_eq!(bytes("1.5k")?, 1536);
+        assert_eq!(bytes("3GB")?, 3_000_000_000);
+        assert_eq!(bytes("4096")?, 4096);
+        assert_eq!(bytes("1TiB")?, 1 << 40);
+        for invalid in ["", "0", "GiB", "-1G", "2 PiB", "lots", "1e30T"] {
+            assert!(invalid.parse::<ByteSize>().is_err(), "{invalid:?}");
+        }
+        assert_eq!("1.5GiB".parse::<ByteSize>()?.to_string(), "1.5 GiB");
+        Ok(())
+    }
+
+    /// Test that worker threads, inflight records, index bins and I/O buffers are capped to their
+    /// shares of a budget, at least one each (or the smallest buffer), and left alone without a
+    /// budget.
+    #[test]
+    fn test_memory_budget() {
+        let count = |count: usize| NonZero::new(count).unwrap();
+        let unlimited = MemoryBudget::new(None);
+        assert_eq!(unlimited.worker_threads(count(64), count(2)), count(64));
+        assert_eq!(unlimited.inflight_records(1536), 1536);
+        assert_eq!(unlimited.index_bins(1 << 30), 1 << 30);
+        assert_eq!(unlimited.io_buffer(), IO_BUFFER_BYTES as usize);
+
+        let budget = MemoryBudget::new(Some("8GiB".parse().unwrap()));
+        // half of 8 GiB is 256 workers of 16 MiB, split between two pools
+        assert_eq!(WORKER_THREAD_BYTES, 16 << 20);
+        assert_eq!(budget.worker_threads(count(512), count(2)), count(128));
+        assert_eq!(budget.worker_threads(count(512), count(1)), count(256));
+        assert_eq!(budget.worker_threads(count(8), count(2)), count(8));
+        assert_eq!(
+            budget.inflight_records(usize::MAX),
+            ((8u64 << 30) / 4 / INFLIGHT_RECORD_BYTES) as usize
+        );
+        assert_eq!(budget.inflight_records(1536), 1536);
+        assert_eq!(
+            budget.index_bins(usize::MAX),
+            ((8u64 << 30) / 4 / SPLIT_RECORD_BYTES) as usize
+        );
+        assert_eq!(budget.io_buffer(), IO_BUFFER_BYTES as usize);
+        let small = MemoryBudget::new(Some("16MiB".parse().unwrap()));
+        assert_eq!(small.io_buffer(), 16 << 10);
+
+        let tiny = MemoryBudget::new(Some("1KiB".parse().unwrap()));
+        assert_eq!(tiny.worker_threads(count(16), count(2)), count(1));
+        assert_eq!(tiny.inflight_records(1536), 1);
+        assert_eq!(tiny.index_bins(1000), 1);
+        assert_eq!(tiny.io_buffer(), MIN_IO_BUFFER_BYTES as usize);
+    }
+}
@@ -0,0 +1,84 @@
+use crate::chunkable::{ChunkableRecord, ChunkableRecordWriter};
+use anyhow::Result;
+
+/// Writer that reports itself full once the records written reach a byte quota, so chunk writing
+/// can stop at the next query group boundary. With no quota, it is never full.
+///
+/// Bytes are counted by the wrapped writer's tell(), as the uncompressed bytes of the records
+/// written, so the count never lags what is still buffered or held by a compressor. The output
+/// overshoots the quota by at most the last query group, and compressed output ends up smaller
+/// than the quota.
+pub struct ByteQuotaWriter<'a, W> {
+    writer: &'a mut W,
+    max_bytes: Option<u64>,
+}
+
+impl<'a, W> ByteQuotaWriter<'a, W> {
+    /// Create a new ByteQuotaWriter wrapping the supplied writer, which must track its position
+    /// (see ChunkableRecordWriter::tell) for the quota to apply.
+    pub fn new(writer: &'a mut W, max_bytes: Option<u64>) -> Self {
+        ByteQuotaWriter { writer, max_bytes }
+    }
+}
+
+/// Implement ChunkableRecordWriter for ByteQuotaWriter, so it can be used anywhere records are
+/// written.
+impl<R, W> ChunkableRecordWriter<R> for ByteQuotaWriter<'_, W>
+where
+    R: ChunkableRecord,
+    W: ChunkableRecordWriter<R>,
+{
+    fn write(&mut self, record: &R) -> Result<()> {
+        self.writer.write(record)
+    }
+
+    fn is_full(&mut self) -> bool {
+        if self.writer.is_full() {
+            return true;
+        }
+        let Some(max_bytes) = self.max_bytes else {
+            return false;
+        };
+        matches!(self.writer.tell(), Ok(Some(position)) if position >= max_bytes)
+    }
+
+    fn tell(&mut self) -> Result<Option<u64>> {
+        self.writer.tell()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ByteQuotaWriter;
+    use crate::{
+        chunkable::{ChunkableRecord, ChunkableRecordWriter},
+        fastq::{FastqRecord, FastqWriter},
+    };
+    use anyhow::Result;
+
+    /// Test that the quota is full exactly once the bytes written reach it, before anything is
+    /// flushed, and that without a quota the writer is never full.
+    #[test]
+    fn test_byte_quota() -> Result<()> {
+        let mut record = FastqRecord::new();
+        // 24 bytes per record: "@read\nACGTACG\n+\nIIIIIII\n"
+        record.set_fields(b"read", b"ACGTACG", &[40; 7])?;
+        let mut fastq_writer = FastqWriter::new(Vec::new());
+        let mut writer = ByteQuotaWriter::new(&mut fastq_writer, Some(72));
+        for _ in 0..2 {
+            writer.write(&record)?;
+            assert!(!writer.is_full());
+        }
+        writer.write(&record)?;
+        assert!(writer.is_full());
+        assert_eq!(writer.tell()?, Some(72));
+
+        let mut fastq_writer = FastqWriter::new(Vec::new());
+        let mut writer = ByteQuotaWriter::new(&mut fastq_writer, None);
+        for _ in 0..10 {
+            writer.write(&record)?;
+        }
+        assert!(!writer.is_full());
+        Ok(())
+    }
+}
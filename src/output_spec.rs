@@ -0,0 +1,205 @@
+use crate::{
+    maybe_compressed_io::{Codec, output_codec},
+    sam_writer_spec::OutputFormat,
+    util::RecordType,
+};
+use anyhow::{Result, anyhow};
+use std::path::Path;
+
+/// Extension of zstd-compressed files
+const ZSTD_EXTENSION: &str = "zst";
+
+/// Type of records to write to an output, and whether FASTQ output is compressed. Resolved from
+/// the output path's extensions (including a compression suffix, e.g. ".fastq.gz"), then the
+/// --output-format option, then a default, so that commands agree on what stdout, named pipes and
+/// extensionless paths get.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct OutputSpec {
+    /// Type of records to write
+    pub record_type: RecordType,
+    /// Whether the path asks for compressed FASTQ (a ".gz", ".bgz" or ".zst" suffix) or
+    /// uncompressed (".fastq" or ".fq"). None if it doesn't say (e.g. stdout, a named pipe, or
+    /// SAM/BAM/CRAM, whose compression is part of their format), leaving it to the compression
+    /// level.
+    pub compressed: Option<bool>,
+    /// Whether the path asks for zstd-compressed FASTQ (a ".zst" suffix)
+    pub zstd: bool,
+}
+
+impl OutputSpec {
+    /// Resolve the output to path: from its extensions if they are recognized, otherwise from
+    /// output_format if given, otherwise default_type. Errors if the extensions and output_format
+    /// disagree.
+    pub fn resolve<P>(
+        path: P,
+        output_format: Option<OutputFormat>,
+        default_type: RecordType,
+    ) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        match (Self::from_path(path.as_ref())?, output_format) {
+            (Some(spec), Some(output_format)) if spec.record_type != output_format.into() => {
+                Err(anyhow!(
+                    "Output {:?} is {} by its extension, but --output-format is {}.",
+                    path.as_ref(),
+                    spec.record_type,
+                    output_format
+                ))
+            }
+            (Some(spec), _) => Ok(spec),
+            (None, output_format) => Ok(OutputSpec {
+                record_type: output_format.map_or(default_type, RecordType::from),
+                compressed: None,
+                zstd: false,
+            }),
+        }
+    }
+
+    /// Get the output from the extensions of path alone (see RecordType::from_path_extensions):
+    /// ".fastq", ".fq", ".sam", ".bam", or ".cram", and for FASTQ a ".gz", ".bgz" or ".zst" suffix.
+    /// None if the extensions aren't recognized. Errors for a compression suffix on SAM/BAM/CRAM or on a
+    /// format that isn't reads (e.g. ".vcf.gz").
+    pub fn from_path<P>(path: P) -> Result<Option<Self>>
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+        match RecordType::from_path_extensions(path) {
+            Some((record_type, true)) if record_type.is_sam_family() => Err(anyhow!(
+                "Output {path:?} adds a compression suffix to {record_type}, which can't be \
+                written."
+            )),
+            Some((record_type, compressed)) => Ok(Some(OutputSpec {
+                record_type,
+                compressed: (record_type == RecordType::Fastq).then_some(compressed),
+                zstd: Self::is_zstd_path(path),
+            })),
+            None if Self::path_compression(path) == Some(true) => Err(anyhow!(
+                "Output {path:?} is a compressed file of another kind, not reads."
+            )),
+            None => Ok(None),
+        }
+    }
+
+    /// Get whether the extension of path asks for compressed FASTQ (".gz", ".bgz" or ".zst") or
+    /// uncompressed (".fastq" or ".fq"), or None if it doesn't say.
+    pub fn path_compression<P>(path: P) -> Option<bool>
+    where
+        P: AsRef<Path>,
+    {
+        let extension = path.as_ref().extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "gz" | "bgz" | ZSTD_EXTENSION => Some(true),
+            "fastq" | "fq" => Some(false),
+            _ => None,
+        }
+    }
+
+    /// Return true if the extension of path asks for zstd-compressed FASTQ (".zst").
+    pub fn is_zstd_path<P>(path: P) -> bool
+    where
+        P: AsRef<Path>,
+    {
+        path.as_ref()
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .is_some_and(|extension| extension.eq_ignore_ascii_case(ZSTD_EXTENSION))
+    }
+
+    /// Return true if FASTQ output is compressed: as the path asks, or if it doesn't say, unless
+    /// the output codec is none (see set_output_codec), or if that isn't set, if the compression
+    /// level is above 0. Always false for SAM/BAM/CRAM.
+    pub fn is_compressed(&self, compression: Option<u32>) -> bool {
+        self.record_type == RecordType::Fastq
+            && self.compressed.unwrap_or_else(|| {
+                output_codec().map_or(compression.is_some_and(|level| level > 0), |codec| {
+                    codec != Codec::None
+                })
+            })
+    }
+
+    /// Return true if FASTQ output is BGZF-compressed, so that it can be indexed and its blocks
+    /// copied: compressed (see is_compressed), and not with the plain gzip or zstd codec.
+    pub fn is_bgzf(&self, compression: Option<u32>) -> bool {
+        self.is_compressed(compression)
+            && !self.zstd
+            && !matches!(output_codec(), Some(Codec::Gzip | Codec::Zstd))
+    }
+
+    /// Get the file extension for output of this spec, e.g. "bam", "fastq.gz", or with the zstd
+    /// codec, "fastq.zst".
+    pub fn extension(&self, compression: Option<u32>) -> String {
+        if self.is_compressed(compression) && output_codec() == Some(Codec::Zstd) {
+            format!("{}.{ZSTD_EXTENSION}", self.record_type.extension())
+        } else if self.is_compressed(compression) {
+            format!("{}.gz", self.record_type.extension())
+        } else {
+            self.record_type.extension().to_string()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::OutputSpec;
+    use crate::{sam_writer_spec::OutputFormat, util::RecordType};
+    use anyhow::Result;
+    use rstest::rstest;
+
+    /// Test resolving outputs by extension, double extension, --output-format, and default.
+    #[rstest]
+    #[case("chunk.bam", None, Some((RecordType::Bam, None)))]
+    #[case("chunk.BAM", Some(OutputFormat::Bam), Some((RecordType::Bam, None)))]
+    #[case("chunk.sam", None, Some((RecordType::Sam, None)))]
+    #[case("chunk.cram", None, Some((RecordType::Cram, None)))]
+    #[case("chunk.fq", Some(OutputFormat::Fastq), Some((RecordType::Fastq, Some(false))))]
+    #[case("chunk.fastq.gz", None, Some((RecordType::Fastq, Some(true))))]
+    #[case("chunk.fq.bgz", None, Some((RecordType::Fastq, Some(true))))]
+    #[case("chunk.gz", None, Some((RecordType::Fastq, Some(true))))]
+    #[case("chunk.fastq.zst", None, Some((RecordType::Fastq, Some(true))))]
+    #[case("chunk.bam.zst", None, None)]
+    #[case("-", None, Some((RecordType::Sam, None)))]
+    #[case("-", Some(OutputFormat::Fastq), Some((RecordType::Fastq, None)))]
+    #[case("/tmp/fifo", Some(OutputFormat::Cram), Some((RecordType::Cram, None)))]
+    #[case("chunk.out", None, Some((RecordType::Sam, None)))]
+    #[case("chunk.bam", Some(OutputFormat::Fastq), None)]
+    #[case("chunk.fastq.gz", Some(OutputFormat::Bam), None)]
+    #[case("chunk.bam.gz", None, None)]
+    #[case("chunk.sam.gz", None, None)]
+    #[case("calls.vcf.gz", Some(OutputFormat::Fastq), None)]
+    fn test_resolve(
+        #[case] path: &str,
+        #[case] output_format: Option<OutputFormat>,
+        #[case] expected: Option<(RecordType, Option<bool>)>,
+    ) -> Result<()> {
+        let resolved = OutputSpec::resolve(path, output_format, RecordType::Sam);
+        match expected {
+            Some((record_type, compressed)) => assert_eq!(
+                resolved?,
+                OutputSpec {
+                    record_type,
+                    compressed,
+                    zstd: path.ends_with(".zst"),
+                }
+            ),
+            None => assert!(resolved.is_err(), "{path} resolved to {resolved:?}"),
+        }
+        Ok(())
+    }
+
+    /// Test deciding FASTQ compression from the path, falling back to the compression level.
+    #[test]
+    fn test_is_compressed() -> Result<()> {
+        let resolve = |path| OutputSpec::resolve(path, None, RecordType::Fastq);
+        assert!(resolve("chunk.fastq.gz")?.is_compressed(None));
+        assert!(!resolve("chunk.fastq")?.is_compressed(Some(6)));
+        assert!(resolve("-")?.is_compressed(Some(6)));
+        assert!(!resolve("-")?.is_compressed(Some(0)));
+        assert!(!resolve("-")?.is_compressed(None));
+        assert!(!resolve("chunk.bam")?.is_compressed(Some(6)));
+        assert_eq!(resolve("fifo")?.extension(Some(1)), "fastq.gz");
+        assert_eq!(resolve("chunk.bam")?.extension(Some(1)), "bam");
+        Ok(())
+    }
+}
@@ -1,5 +1,6 @@
 use anyhow::{Result, anyhow};
 use std::{
+    fs,
     path::{Path, PathBuf},
     str::FromStr,
 };
@@ -12,7 +13,18 @@ pub enum PathType {
     UrlPath(Url),
 }
 
-const URL_PREFIXES: [&str; 5] = ["s3://", "gcs://", "ftp://", "http://", "https://"];
+const URL_PREFIXES: [&str; 7] = [
+    "s3://",
+    "gs://",
+    "gcs://",
+    "gs+http://",
+    "ftp://",
+    "http://",
+    "https://",
+];
+
+/// Scheme htslib uses for Google Cloud Storage. "gcs://" is accepted as an alias for it.
+const GCS_SCHEME: &str = "gs";
 
 impl PathType {
     /// Form PathType enum from input PathBuf object, e.g. a clap input argument
@@ -27,7 +39,7 @@ impl PathType {
                 .into_iter()
                 .any(|prefix| path_str.starts_with(prefix))
             {
-                PathType::UrlPath(Url::parse(path_str)?)
+                PathType::UrlPath(Self::parse_url(path_str)?)
             } else {
                 PathType::FilePath(path.as_ref().into())
             }
@@ -36,7 +48,28 @@ impl PathType {
         })
     }
 
-    /// Form default index file location from path to main file
+    /// Parse a URL, rewriting "gcs://" to the "gs://" scheme that htslib understands.
+    fn parse_url(url_str: &str) -> Result<Url> {
+        match url_str.strip_prefix("gcs://") {
+            Some(rest) => Ok(Url::parse(&format!("{GCS_SCHEME}://{rest}"))?),
+            None => Ok(Url::parse(url_str)?),
+        }
+    }
+
+    /// Return true for local paths that are, or will be created as, regular files, which can be
+    /// re-read, truncated and renamed. False for stdin/stdout, URLs, and existing special files
+    /// such as FIFOs (named pipes), which are written in one sequential pass.
+    pub fn is_regular_file(&self) -> bool {
+        match self {
+            Self::FilePath(path) => fs::metadata(path).map_or(true, |metadata| metadata.is_file()),
+            Self::Pipe | Self::UrlPath(_) => false,
+        }
+    }
+
+    /// Form default index file location from path to main file. The index extension is added to
+    /// the full file name rather than replacing any of its extensions, so that e.g. "reads.fastq.gz"
+    /// and "reads.bam" are indexed by "reads.fastq.gz.si" and "reads.bam.si". For URLs it is added
+    /// to the path, before any query string.
     pub fn default_index(&self, index_extension: &'static str) -> Result<Option<PathBuf>> {
         match self {
             Self::Pipe => Ok(None),
@@ -52,12 +85,42 @@ impl PathType {
                 if local_si.exists() {
                     Ok(Some(local_si))
                 } else {
-                    Ok(Some(
-                        PathBuf::from_str(url.as_str())?.with_added_extension(index_extension),
-                    ))
+                    let mut index_url = url.clone();
+                    index_url.set_path(&format!("{}.{index_extension}", url.path()));
+                    Ok(Some(PathBuf::from(index_url.as_str())))
                 }
             }
             Self::FilePath(path_buf) => Ok(Some(path_buf.with_added_extension(index_extension))),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::PathType;
+    use anyhow::Result;
+    use std::path::PathBuf;
+
+    /// Test that GCS URLs are normalized to "gs://", and their default index lives beside them.
+    #[test]
+    fn test_gcs_paths() -> Result<()> {
+        let PathType::UrlPath(url) = PathType::from_path("gcs://bucket/dir/reads.bam")? else {
+            panic!("gcs:// path should be a URL");
+        };
+        assert_eq!(url.as_str(), "gs://bucket/dir/reads.bam");
+        let path_type = PathType::from_path("gs://bucket/dir/reads.bam")?;
+        assert_eq!(
+            path_type.default_index("si")?,
+            Some(PathBuf::from("gs://bucket/dir/reads.bam.si"))
+        );
+        assert_eq!(
+            PathType::from_path("https://host/reads.fastq.gz?token=abc")?.default_index("si")?,
+            Some(PathBuf::from("https://host/reads.fastq.gz.si?token=abc"))
+        );
+        assert_eq!(
+            PathType::from_path("dir/reads.fq.bgz")?.default_index("si")?,
+            Some(PathBuf::from("dir/reads.fq.bgz.si"))
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,307 @@
+use crate::{
+    chunkable::{ChunkableRecord, ChunkableRecordReader, OffsetKind, SplitRange},
+    resync::SkippedRange,
+};
+use anyhow::Result;
+use std::{
+    io::{IsTerminal, Write, stderr},
+    time::{Duration, Instant},
+};
+
+/// Number of reads between checks of whether the progress line is due, so that timing and
+/// telling offsets don't slow reading down
+const CHECK_INTERVAL_READS: usize = 256;
+
+/// Time between redraws of the progress line on a terminal
+const TERMINAL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Time between progress lines when stderr is not a terminal (e.g. a workflow log)
+const LOG_INTERVAL: Duration = Duration::from_secs(10);
+
+/// What progress is measured in, and so what the total is a total of
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ProgressUnit {
+    /// Bytes of the (compressed) reads file
+    Bytes,
+    /// Reads read
+    Reads,
+}
+
+/// Progress line on stderr with the amount done, the total and percentage (if the total is
+/// known), reads per second, and an ETA. On a terminal the line is redrawn in place; otherwise a
+/// new line is printed every so often, so logs don't fill with redraws.
+#[derive(Debug)]
+pub struct Progress {
+    /// What the job is doing, e.g. "Indexing"
+    label: String,
+    unit: ProgressUnit,
+    total: Option<u64>,
+    done: u64,
+    num_reads: usize,
+    started: Instant,
+    last_draw: Option<Instant>,
+    interval: Duration,
+    is_terminal: bool,
+}
+
+impl Progress {
+    /// Create a new Progress, measured in unit, towards total if it is known.
+    pub fn new(label: &str, unit: ProgressUnit, total: Option<u64>) -> Self {
+        let is_terminal = stderr().is_terminal();
+        Progress {
+            label: label.to_string(),
+            unit,
+            total,
+            done: 0,
+            num_reads: 0,
+            started: Instant::now(),
+            last_draw: None,
+            interval: if is_terminal {
+                TERMINAL_INTERVAL
+            } else {
+                LOG_INTERVAL
+            },
+            is_terminal,
+        }
+    }
+
+    /// Update with the offset reached in the (compressed) reads file, if known, and the number of
+    /// reads so far, drawing the progress line if it is due.
+    pub fn update(&mut self, offset: Option<u64>, num_reads: usize) {
+        self.set(offset, num_reads);
+        let now = Instant::now();
+        if self
+            .last_draw
+            .is_none_or(|last_draw| now.duration_since(last_draw) >= self.interval)
+        {
+            self.last_draw = Some(now);
+            self.draw(now.duration_since(self.started), false);
+        }
+    }
+
+    /// Record the offset reached, if known, and the number of reads so far, without drawing.
+    fn set(&mut self, offset: Option<u64>, num_reads: usize) {
+        self.num_reads = num_reads;
+        self.done = match self.unit {
+            ProgressUnit::Bytes => offset.unwrap_or(self.done),
+            ProgressUnit::Reads => num_reads as u64,
+        };
+    }
+
+    /// Draw the final progress line, ending it on a terminal.
+    pub fn finish(&mut self) {
+        if self.last_draw.is_some() {
+            self.draw(self.started.elapsed(), true);
+            self.last_draw = None;
+        }
+    }
+
+    /// Write the progress line to stderr. Failures are ignored, as progress is only cosmetic.
+    fn draw(&self, elapsed: Duration, finished: bool) {
+        let line = self.format(elapsed);
+        let mut stderr = stderr().lock();
+        let _ = if !self.is_terminal {
+            writeln!(stderr, "{line}")
+        } else if finished {
+            writeln!(stderr, "\r{line}\x1b[K")
+        } else {
+            write!(stderr, "\r{line}\x1b[K")
+        };
+        let _ = stderr.flush();
+    }
+
+    /// Format the progress line after elapsed time
+    fn format(&self, elapsed: Duration) -> String {
+        let total = self.total.filter(|&total| total > 0);
+        // read totals come from index bins, so they are only approximate
+        let amount = match (self.unit, total) {
+            (ProgressUnit::Bytes, Some(total)) => {
+                format!("{} of {}", format_bytes(self.done), format_bytes(total))
+            }
+            (ProgressUnit::Bytes, None) => format_bytes(self.done),
+            (ProgressUnit::Reads, Some(total)) => format!("{} of ~{total} reads", self.done),
+            (ProgressUnit::Reads, None) => format!("{} reads", self.done),
+        };
+        let seconds = elapsed.as_secs_f64();
+        let mut line = format!("{}: {amount}", self.label);
+        if let Some(total) = total {
+            let fraction = (self.done as f64 / total as f64).min(1.0);
+            line.push_str(&format!(" ({:.1}%)", 100.0 * fraction));
+            if fraction > 0.0 && seconds > 0.0 {
+                let remaining = seconds * (1.0 - fraction) / fraction;
+                line.push_str(&format!(", ETA {}", format_duration(remaining)));
+            }
+        }
+        if seconds > 0.0 {
+            line.push_str(&format!(", {:.0} reads/s", self.num_reads as f64 / seconds));
+        }
+        line
+    }
+}
+
+/// Format a number of bytes with a binary unit, e.g. "1.5 GiB"
+pub(crate) fn format_bytes(num_bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut value = num_bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{num_bytes} B")
+    } else {
+        format!("{value:.1} {}", UNITS[unit])
+    }
+}
+
+/// Format a number of seconds as hours, minutes and seconds, e.g. "1:02:03" or "2:03"
+fn format_duration(seconds: f64) -> String {
+    let seconds = seconds.round() as u64;
+    let (hours, minutes, seconds) = (seconds / 3600, seconds / 60 % 60, seconds % 60);
+    if hours > 0 {
+        format!("{hours}:{minutes:02}:{seconds:02}")
+    } else {
+        format!("{minutes}:{seconds:02}")
+    }
+}
+
+/// Reader that reports the progress of reading to a Progress: the offset reached in the
+/// compressed file and the number of reads. With no Progress, it just passes records on.
+pub struct ProgressReader<Reader> {
+    inner: Reader,
+    progress: Option<Progress>,
+    num_reads: usize,
+}
+
+impl<Reader> ProgressReader<Reader> {
+    /// Create a new ProgressReader wrapping the supplied reader.
+    pub fn new(inner: Reader, progress: Option<Progress>) -> Self {
+        ProgressReader {
+            inner,
+            progress,
+            num_reads: 0,
+        }
+    }
+
+    /// Get a reference to the underlying reader, e.g. to get its header
+    pub fn get_ref(&self) -> &Reader {
+        &self.inner
+    }
+}
+
+/// End the progress line when reading is done.
+impl<Reader> Drop for ProgressReader<Reader> {
+    fn drop(&mut self) {
+        if let Some(ref mut progress) = self.progress {
+            progress.set(None, self.num_reads);
+            progress.finish();
+        }
+    }
+}
+
+/// Implement ChunkableRecordReader for ProgressReader, updating the progress every so often.
+impl<R, Reader> ChunkableRecordReader<R> for ProgressReader<Reader>
+where
+    R: ChunkableRecord,
+    Reader: ChunkableRecordReader<R>,
+{
+    fn tell(&mut self) -> Result<u64> {
+        self.inner.tell()
+    }
+
+    fn seek(&mut self, offset: u64) -> Result<()> {
+        self.inner.seek(offset)
+    }
+
+    fn offset_kind(&self) -> OffsetKind {
+        self.inner.offset_kind()
+    }
+
+    fn seek_to_range(&mut self, split_range: &SplitRange) -> Result<()> {
+        self.inner.seek_to_range(split_range)
+    }
+
+    fn tell_uncompressed(&mut self) -> Result<Option<u64>> {
+        self.inner.tell_uncompressed()
+    }
+
+    fn read_into(&mut self, record: &mut R) -> Option<Result<()>> {
+        let result = self.inner.read_into(record);
+        if let Some(ref mut progress) = self.progress
+            && let Some(Ok(())) = result
+        {
+            self.num_reads += 1;
+            if self.num_reads % CHECK_INTERVAL_READS == 1 {
+                let offset = self
+                    .inner
+                    .tell()
+                    .ok()
+                    .map(|offset| match self.inner.offset_kind() {
+                        OffsetKind::Virtual => OffsetKind::split_virtual_offset(offset).0,
+                        OffsetKind::Byte => offset,
+                    });
+                progress.update(offset, self.num_reads);
+            }
+        }
+        result
+    }
+
+    fn take_skipped_ranges(&mut self) -> Vec<SkippedRange> {
+        self.inner.take_skipped_ranges()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Progress, ProgressReader, ProgressUnit, format_bytes, format_duration};
+    use crate::{
+        chunkable::ChunkableRecordReader,
+        fastq::{FastqReader, FastqRecord},
+    };
+    use std::{io::Cursor, time::Duration};
+
+    /// Test the progress line with and without a known total, and that a ProgressReader counts
+    /// reads and bytes.
+    #[test]
+    fn test_progress() {
+        assert_eq!(format_bytes(1000), "1000 B");
+        assert_eq!(format_bytes(3 << 29), "1.5 GiB");
+        assert_eq!(format_duration(3723.4), "1:02:03");
+        assert_eq!(format_duration(59.6), "1:00");
+
+        let mut progress = Progress::new("Indexing", ProgressUnit::Bytes, Some(4 << 20));
+        progress.update(Some(0), 0);
+        progress.update(Some(1 << 20), 1000);
+        assert_eq!(
+            progress.format(Duration::from_secs(10)),
+            "Indexing: 1.0 MiB of 4.0 MiB (25.0%), ETA 0:30, 100 reads/s"
+        );
+        let mut progress = Progress::new("Extracting", ProgressUnit::Reads, None);
+        progress.update(None, 50);
+        assert_eq!(
+            progress.format(Duration::from_secs(5)),
+            "Extracting: 50 reads, 10 reads/s"
+        );
+        progress.total = Some(200);
+        assert_eq!(
+            progress.format(Duration::from_secs(5)),
+            "Extracting: 50 of ~200 reads (25.0%), ETA 0:15, 10 reads/s"
+        );
+
+        let fastq: String = (0..300)
+            .map(|i| format!("@r{i}\nACGT\n+\nIIII\n"))
+            .collect();
+        let progress = Progress::new("Indexing", ProgressUnit::Bytes, Some(fastq.len() as u64));
+        let mut reader =
+            ProgressReader::new(FastqReader::new(Cursor::new(fastq.clone())), Some(progress));
+        let mut record = FastqRecord::new();
+        while let Some(result) = reader.read_into(&mut record) {
+            result.unwrap();
+        }
+        assert_eq!(reader.num_reads, 300);
+        let progress = reader.progress.as_ref().unwrap();
+        assert_eq!(progress.num_reads, 257);
+        assert!(progress.done > 0 && progress.done < fastq.len() as u64);
+    }
+}
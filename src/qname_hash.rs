@@ -0,0 +1,68 @@
+use std::num::NonZero;
+use xxhash_rust::xxh3::xxh3_64_with_seed;
+
+/// Hash of query names shared by every feature that makes decisions keyed on query name (e.g.
+/// subsampling), so they all agree on the same query and are reproducible across runs and
+/// platforms. Implementations must depend only on the query name bytes and their own settings.
+pub trait QnameHasher {
+    /// Hash a query name to a u64.
+    fn hash_qname(&self, qname: &[u8]) -> u64;
+
+    /// Assign a query name to one of `num_buckets` buckets, uniformly if the hash is uniform.
+    fn bucket(&self, qname: &[u8], num_buckets: NonZero<usize>) -> usize {
+        // multiply-shift maps the full u64 range evenly onto the buckets without modulo bias
+        ((self.hash_qname(qname) as u128 * num_buckets.get() as u128) >> 64) as usize
+    }
+}
+
+/// The default QnameHasher: seeded xxh3, which is fast and gives the same hash on every platform.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Xxh3QnameHasher {
+    /// Seed for the hash. Different seeds give independent hashes.
+    seed: u64,
+}
+
+impl Xxh3QnameHasher {
+    /// Create a new Xxh3QnameHasher with the supplied seed.
+    pub fn new(seed: u64) -> Self {
+        Xxh3QnameHasher { seed }
+    }
+
+    /// Get the seed of this hasher.
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+}
+
+impl QnameHasher for Xxh3QnameHasher {
+    fn hash_qname(&self, qname: &[u8]) -> u64 {
+        xxh3_64_with_seed(qname, self.seed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{QnameHasher, Xxh3QnameHasher};
+    use std::num::NonZero;
+
+    /// Test that hashes are pinned across releases and platforms, and buckets are balanced.
+    #[test]
+    fn test_xxh3_qname_hasher() {
+        let hasher = Xxh3QnameHasher::new(7);
+        assert_eq!(hasher.hash_qname(b"read1"), 17747783889685364704);
+        assert_ne!(
+            Xxh3QnameHasher::default().hash_qname(b"read1"),
+            hasher.hash_qname(b"read1")
+        );
+
+        let num_buckets = NonZero::new(4).unwrap();
+        let mut counts = [0usize; 4];
+        for index in 0..10000 {
+            counts[hasher.bucket(format!("read{index}").as_bytes(), num_buckets)] += 1;
+        }
+        assert!(
+            counts.iter().all(|count| (2200..2800).contains(count)),
+            "unbalanced buckets {counts:?}"
+        );
+    }
+}
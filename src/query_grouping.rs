@@ -0,0 +1,222 @@
+use crate::{
+    chunkable::{ChunkableRecord, GroupBy},
+    qname_hash::{QnameHasher, Xxh3QnameHasher},
+};
+use anyhow::{Error, Result, anyhow};
+use log::warn;
+use std::str::FromStr;
+
+/// Number of hash functions used by QnameBloomFilter. Optimal for about 23 bits (3 bytes) per
+/// query name, where about 1 in 70000 names that weren't inserted are reported.
+const NUM_BLOOM_HASHES: usize = 16;
+
+/// What to do when a query name reappears after a different query name intervened.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GroupingCheck {
+    /// Error at the first query name that reappears
+    Error,
+    /// Warn at the first query name that reappears, then report the total at the end
+    Warn,
+}
+
+impl FromStr for GroupingCheck {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "error" => Ok(GroupingCheck::Error),
+            "warn" => Ok(GroupingCheck::Warn),
+            _ => Err(anyhow!(
+                "Unknown grouping check {s:?}, expected \"error\" or \"warn\"."
+            )),
+        }
+    }
+}
+
+/// Fixed-size Bloom filter of query names. Never misses a name that was inserted, but may report
+/// a name that wasn't, more often as it fills.
+pub struct QnameBloomFilter {
+    bits: Vec<u64>,
+    /// Independent hashers, combined by double hashing to get each bit
+    hashers: [Xxh3QnameHasher; 2],
+}
+
+impl QnameBloomFilter {
+    /// Create a new empty filter using about num_bytes of memory.
+    pub fn new(num_bytes: usize) -> Self {
+        QnameBloomFilter {
+            bits: vec![0u64; num_bytes.div_ceil(8).max(1)],
+            hashers: [Xxh3QnameHasher::new(0), Xxh3QnameHasher::new(1)],
+        }
+    }
+
+    /// Get the index of each bit for this query name.
+    fn bit_indices(&self, qname: &[u8]) -> [u64; NUM_BLOOM_HASHES] {
+        let num_bits = self.bits.len() as u64 * 64;
+        let first = self.hashers[0].hash_qname(qname);
+        let step = self.hashers[1].hash_qname(qname) | 1;
+        std::array::from_fn(|index| {
+            first.wrapping_add((index as u64).wrapping_mul(step)) % num_bits
+        })
+    }
+
+    /// Add a query name to the filter.
+    pub fn insert(&mut self, qname: &[u8]) {
+        for bit in self.bit_indices(qname) {
+            self.bits[(bit / 64) as usize] |= 1 << (bit % 64);
+        }
+    }
+
+    /// Return true if the query name may have been inserted, false if it certainly wasn't.
+    pub fn contains(&self, qname: &[u8]) -> bool {
+        self.bit_indices(qname)
+            .into_iter()
+            .all(|bit| self.bits[(bit / 64) as usize] & (1 << (bit % 64)) != 0)
+    }
+}
+
+/// Checks that records are grouped by query name, i.e. that no query name reappears after a
+/// different query name intervened, because a file that isn't grouped silently produces chunks
+/// that split query groups. Finished query groups are remembered in a QnameBloomFilter, so a
+/// reported query may (rarely) be a filter collision rather than a true reappearance. When reads
+/// are grouped by another key (see GroupBy), the keys are checked instead.
+pub struct QueryGroupingChecker {
+    check: GroupingCheck,
+    /// Key that records must be grouped by
+    group_by: GroupBy,
+    finished_qnames: QnameBloomFilter,
+    /// Query name of the current query group
+    last_qname: Option<Vec<u8>>,
+    /// Number of query groups whose query name reappeared
+    num_regrouped: usize,
+}
+
+impl QueryGroupingChecker {
+    /// Create a new QueryGroupingChecker remembering query names in about filter_bytes of memory.
+    pub fn new(check: GroupingCheck, filter_bytes: usize) -> Self {
+        QueryGroupingChecker {
+            check,
+            group_by: GroupBy::QueryName,
+            finished_qnames: QnameBloomFilter::new(filter_bytes),
+            last_qname: None,
+            num_regrouped: 0,
+        }
+    }
+
+    /// Set the key that records must be grouped by. Defaults to the query name.
+    pub fn group_by(&mut self, group_by: GroupBy) -> &mut Self {
+        self.group_by = group_by;
+        self
+    }
+
+    /// Get the number of query groups whose query name reappeared so far
+    pub fn num_regrouped(&self) -> usize {
+        self.num_regrouped
+    }
+
+    /// Check the grouping key of the next record, read at offset (if known), as check does.
+    pub fn check_record<R>(&mut self, record: &R, offset: Option<u64>) -> Result<()>
+    where
+        R: ChunkableRecord,
+    {
+        let key = self.group_by.key(record);
+        self.check(&key, offset)
+    }
+
+    /// Check the query name (or other grouping key) of the next record, read at offset (if
+    /// known). Errors at the first reappearing query name if the check is GroupingCheck::Error.
+    pub fn check(&mut self, qname: &[u8], offset: Option<u64>) -> Result<()> {
+        if self.last_qname.as_deref() == Some(qname) {
+            return Ok(());
+        }
+        if let Some(last_qname) = self.last_qname.replace(qname.to_vec()) {
+            self.finished_qnames.insert(&last_qname);
+        }
+        if !self.finished_qnames.contains(qname) {
+            return Ok(());
+        }
+        self.num_regrouped += 1;
+        let offset = offset.map_or("unknown".to_string(), |offset| offset.to_string());
+        let message = match self.group_by {
+            GroupBy::QueryName => format!(
+                "Query {} at offset {offset} reappears after other queries, so the input is not \
+                grouped by query name.",
+                String::from_utf8_lossy(qname)
+            ),
+            group_by => format!(
+                "Group {} at offset {offset} reappears after other groups, so the input is not \
+                grouped by {group_by}.",
+                String::from_utf8_lossy(qname)
+            ),
+        };
+        match self.check {
+            GroupingCheck::Error => Err(anyhow!("{message}")),
+            GroupingCheck::Warn => {
+                if self.num_regrouped == 1 {
+                    warn!("{message}");
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Warn with the total number of reappearing query names, if there were any.
+    pub fn report(&self) {
+        if self.num_regrouped > 0 {
+            warn!(
+                "{} query group(s) reappear after other queries. Chunks will split them.",
+                self.num_regrouped
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{GroupingCheck, QnameBloomFilter, QueryGroupingChecker};
+    use anyhow::Result;
+
+    /// Test that the filter finds inserted names, and rarely finds others when not overfull.
+    #[test]
+    fn test_qname_bloom_filter() {
+        let mut filter = QnameBloomFilter::new(16 * 1024);
+        let qnames: Vec<String> = (0..10000).map(|index| format!("read{index}")).collect();
+        for qname in qnames.iter().step_by(2) {
+            filter.insert(qname.as_bytes());
+        }
+        assert!(
+            qnames
+                .iter()
+                .step_by(2)
+                .all(|qname| filter.contains(qname.as_bytes()))
+        );
+        let false_positives = qnames
+            .iter()
+            .skip(1)
+            .step_by(2)
+            .filter(|qname| filter.contains(qname.as_bytes()))
+            .count();
+        assert!(false_positives < 50, "{false_positives} false positives");
+    }
+
+    /// Test that grouped query names pass and a reappearing one errors or is counted.
+    #[test]
+    fn test_query_grouping_checker() -> Result<()> {
+        let grouped: [&[u8]; 5] = [b"a", b"a", b"b", b"c", b"c"];
+        let mut checker = QueryGroupingChecker::new(GroupingCheck::Error, 1024);
+        for (offset, qname) in grouped.iter().enumerate() {
+            checker.check(qname, Some(offset as u64))?;
+        }
+        let err = checker.check(b"a", Some(5)).unwrap_err();
+        assert!(err.to_string().contains("offset 5"), "{err}");
+
+        let mut checker = QueryGroupingChecker::new(GroupingCheck::Warn, 1024);
+        for qname in [b"a", b"b", b"a", b"a", b"b"] {
+            checker.check(qname, None)?;
+        }
+        assert_eq!(checker.num_regrouped(), 2);
+        assert_eq!("Warn".parse::<GroupingCheck>()?, GroupingCheck::Warn);
+        assert!("ignore".parse::<GroupingCheck>().is_err());
+        Ok(())
+    }
+}
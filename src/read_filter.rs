@@ -0,0 +1,234 @@
+use crate::chunkable::{ChunkableRecord, RecordTransform, TransformResult};
+use anyhow::{Result, anyhow};
+use std::fmt;
+
+/// Reason a read was filtered out, in the order the checks are made
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FilterReason {
+    /// Shorter than the minimum length
+    Length,
+    /// Mean base quality below the minimum, or no qualities to check
+    Quality,
+    /// Missing a required SAM flag, or having an excluded one
+    Flags,
+}
+
+/// Per-record filter applied while writing a chunk, by length, mean base quality, and SAM flags,
+/// so QC-style pipelines don't need a separate filtering pass. Reads are filtered individually,
+/// so a query group may lose some of its reads (e.g. one mate of a pair). It counts the reads it
+/// passes and removes, for a summary once the chunk is written.
+///
+/// This builder-style struct allows setting each filter separately; with none set, every read
+/// passes.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ReadFilter {
+    /// Minimum number of bases
+    pub min_length: usize,
+    /// Minimum mean Phred base quality
+    pub min_mean_qual: Option<f64>,
+    /// SAM flags that must all be set. Records without flags (FASTQ) pass.
+    pub include_flags: u16,
+    /// SAM flags that must all be unset. Records without flags (FASTQ) pass.
+    pub exclude_flags: u16,
+    /// Number of reads that passed
+    num_passed: usize,
+    /// Number of reads removed for being too short, low quality, or by flags
+    num_removed: [usize; 3],
+}
+
+impl ReadFilter {
+    /// Create a new ReadFilter that passes every read.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the minimum number of bases.
+    pub fn min_length(&mut self, min_length: usize) -> &mut Self {
+        self.min_length = min_length;
+        self
+    }
+
+    /// Set the minimum mean Phred base quality. Reads without qualities are removed.
+    pub fn min_mean_qual(&mut self, min_mean_qual: Option<f64>) -> &mut Self {
+        self.min_mean_qual = min_mean_qual;
+        self
+    }
+
+    /// Set SAM flags that must all be set, as `samtools view -f` does.
+    pub fn include_flags(&mut self, include_flags: u16) -> &mut Self {
+        self.include_flags = include_flags;
+        self
+    }
+
+    /// Set SAM flags that must all be unset, as `samtools view -F` does.
+    pub fn exclude_flags(&mut self, exclude_flags: u16) -> &mut Self {
+        self.exclude_flags = exclude_flags;
+        self
+    }
+
+    /// Return true if the filter would remove reads, i.e. any filter is set
+    pub fn is_active(&self) -> bool {
+        self.min_length > 0
+            || self.min_mean_qual.is_some()
+            || self.include_flags != 0
+            || self.exclude_flags != 0
+    }
+
+    /// Return true if the filter tests SAM flags, which only SAM/BAM/CRAM records have
+    pub fn uses_flags(&self) -> bool {
+        self.include_flags != 0 || self.exclude_flags != 0
+    }
+
+    /// Get the reason record should be removed, or None if it passes.
+    pub fn check<R>(&self, record: &R) -> Option<FilterReason>
+    where
+        R: ChunkableRecord,
+    {
+        if record.seq_len() < self.min_length {
+            return Some(FilterReason::Length);
+        }
+        if let Some(min_mean_qual) = self.min_mean_qual {
+            match record.qual() {
+                Some(qual) if !qual.is_empty() => {
+                    let total: u64 = qual.iter().map(|&q| q as u64).sum();
+                    if (total as f64) < min_mean_qual * qual.len() as f64 {
+                        return Some(FilterReason::Quality);
+                    }
+                }
+                _ => return Some(FilterReason::Quality),
+            }
+        }
+        if let Some(flags) = record.flags()
+            && (flags & self.include_flags != self.include_flags || flags & self.exclude_flags != 0)
+        {
+            return Some(FilterReason::Flags);
+        }
+        None
+    }
+
+    /// Return true if record passes the filter, counting it as passed or removed.
+    pub fn pass<R>(&mut self, record: &R) -> bool
+    where
+        R: ChunkableRecord,
+    {
+        match self.check(record) {
+            None => {
+                self.num_passed += 1;
+                true
+            }
+            Some(reason) => {
+                self.num_removed[reason as usize] += 1;
+                false
+            }
+        }
+    }
+
+    /// Get the number of reads that passed
+    pub fn num_passed(&self) -> usize {
+        self.num_passed
+    }
+
+    /// Get the number of reads removed for reason
+    pub fn num_removed(&self, reason: FilterReason) -> usize {
+        self.num_removed[reason as usize]
+    }
+}
+
+/// Filter records, dropping those that don't pass
+impl<R> RecordTransform<R> for ReadFilter
+where
+    R: ChunkableRecord,
+{
+    fn transform(&mut self, record: &mut R) -> Result<TransformResult> {
+        Ok(if self.pass(record) {
+            TransformResult::Keep
+        } else {
+            TransformResult::Drop
+        })
+    }
+}
+
+/// Summarize the reads removed, e.g. "Filtered out 12 of 400 reads: 5 too short, 7 low quality,
+/// 0 by flags."
+impl fmt::Display for ReadFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let num_removed: usize = self.num_removed.iter().sum();
+        write!(
+            f,
+            "Filtered out {num_removed} of {} reads: {} too short, {} low quality, {} by flags.",
+            self.num_passed + num_removed,
+            self.num_removed(FilterReason::Length),
+            self.num_removed(FilterReason::Quality),
+            self.num_removed(FilterReason::Flags),
+        )
+    }
+}
+
+/// Parse SAM flags given in decimal or, with a "0x" prefix, hexadecimal (e.g. "0x900" or "2304").
+pub fn parse_flags(flags: &str) -> Result<u16> {
+    match flags
+        .strip_prefix("0x")
+        .or_else(|| flags.strip_prefix("0X"))
+    {
+        Some(hex) => u16::from_str_radix(hex, 16),
+        None => flags.parse(),
+    }
+    .map_err(|err| anyhow!("Invalid SAM flags {flags:?}: {err}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FilterReason, ReadFilter, parse_flags};
+    use crate::{chunkable::ChunkableRecord, fastq::FastqRecord};
+    use anyhow::Result;
+    use rust_htslib::bam::Record;
+
+    /// Test each filter, and that removed reads are counted by reason.
+    #[test]
+    fn test_read_filter() -> Result<()> {
+        let mut record = Record::new();
+        record.set(
+            b"read1",
+            None,
+            b"ACGTACGT",
+            &[30, 30, 30, 30, 20, 20, 20, 20],
+        );
+        record.set_flags(0x1 | 0x40);
+
+        let mut read_filter = ReadFilter::new();
+        assert!(!read_filter.is_active());
+        assert_eq!(read_filter.check(&record), None);
+        read_filter.min_length(9);
+        assert_eq!(read_filter.check(&record), Some(FilterReason::Length));
+        read_filter.min_length(8).min_mean_qual(Some(25.0));
+        assert_eq!(read_filter.check(&record), None);
+        read_filter.min_mean_qual(Some(25.5));
+        assert_eq!(read_filter.check(&record), Some(FilterReason::Quality));
+        read_filter
+            .min_mean_qual(None)
+            .include_flags(parse_flags("0x41")?)
+            .exclude_flags(parse_flags("2304")?);
+        assert!(read_filter.uses_flags());
+        assert_eq!(read_filter.check(&record), None);
+        record.set_flags(0x1 | 0x40 | 0x100);
+        assert_eq!(read_filter.check(&record), Some(FilterReason::Flags));
+        record.set_flags(0x1 | 0x80);
+        assert_eq!(read_filter.check(&record), Some(FilterReason::Flags));
+        assert!(parse_flags("0xg").is_err());
+
+        let mut fastq = FastqRecord::new();
+        fastq.set_fields(b"read2", b"ACGTACGT", &[10; 8])?;
+        assert_eq!(read_filter.check(&fastq), None);
+        read_filter.min_mean_qual(Some(20.0));
+        assert!(!read_filter.pass(&fastq));
+        assert!(!read_filter.pass(&record));
+        read_filter.min_mean_qual(None).include_flags(0);
+        assert!(read_filter.pass(&record));
+        assert_eq!(read_filter.num_passed(), 1);
+        assert_eq!(
+            read_filter.to_string(),
+            "Filtered out 2 of 3 reads: 0 too short, 1 low quality, 1 by flags."
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,212 @@
+use crate::chunkable::{ChunkableRecord, ChunkableRecordWriter};
+use anyhow::Result;
+use std::collections::BTreeMap;
+
+/// Summary statistics of a stream of query-grouped reads, accumulated in a single pass: read length
+/// distribution, mean base quality, GC content, and query group size distribution.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ReadStats {
+    /// Number of reads with each read length
+    read_lengths: BTreeMap<usize, usize>,
+    /// Number of query groups with each number of reads
+    query_sizes: BTreeMap<usize, usize>,
+    /// Total number of bases
+    num_bases: u64,
+    /// Number of G or C bases
+    num_gc_bases: u64,
+    /// Number of A, C, G, or T bases, so that Ns don't dilute GC content
+    num_called_bases: u64,
+    /// Sum of Phred qualities, over bases that have qualities
+    quality_sum: u64,
+    /// Number of bases that have qualities
+    num_quality_bases: u64,
+    /// Query name and number of reads of the query group currently being read
+    current_query: Option<(Vec<u8>, usize)>,
+}
+
+impl ReadStats {
+    /// Create a new, empty ReadStats
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a read. Reads must be grouped by query name.
+    pub fn add<R>(&mut self, record: &R)
+    where
+        R: ChunkableRecord,
+    {
+        match &mut self.current_query {
+            Some((qname, num_reads)) if qname.as_slice() == record.group_qname() => *num_reads += 1,
+            _ => {
+                self.finish_query();
+                self.current_query = Some((record.group_qname().to_vec(), 1));
+            }
+        }
+        let seq = record.seq();
+        *self.read_lengths.entry(seq.len()).or_default() += 1;
+        self.num_bases += seq.len() as u64;
+        for base in seq.iter() {
+            match base.to_ascii_uppercase() {
+                b'G' | b'C' => {
+                    self.num_gc_bases += 1;
+                    self.num_called_bases += 1;
+                }
+                b'A' | b'T' => self.num_called_bases += 1,
+                _ => {}
+            }
+        }
+        if let Some(qual) = record.qual() {
+            self.quality_sum += qual.iter().map(|&q| q as u64).sum::<u64>();
+            self.num_quality_bases += qual.len() as u64;
+        }
+    }
+
+    /// Count the query group currently being read. Called automatically when a new query starts,
+    /// and must be called after the last read.
+    pub fn finish_query(&mut self) {
+        if let Some((_, num_reads)) = self.current_query.take() {
+            *self.query_sizes.entry(num_reads).or_default() += 1;
+        }
+    }
+
+    /// Get the number of reads
+    pub fn num_reads(&self) -> usize {
+        self.read_lengths.values().sum()
+    }
+
+    /// Get the number of completed query groups
+    pub fn num_queries(&self) -> usize {
+        self.query_sizes.values().sum()
+    }
+
+    /// Get the total number of bases
+    pub fn num_bases(&self) -> u64 {
+        self.num_bases
+    }
+
+    /// Get the number of reads with each read length
+    pub fn read_lengths(&self) -> &BTreeMap<usize, usize> {
+        &self.read_lengths
+    }
+
+    /// Get the number of query groups with each number of reads
+    pub fn query_sizes(&self) -> &BTreeMap<usize, usize> {
+        &self.query_sizes
+    }
+
+    /// Get the mean Phred base quality, or None if no bases had qualities
+    pub fn mean_quality(&self) -> Option<f64> {
+        (self.num_quality_bases > 0)
+            .then(|| self.quality_sum as f64 / self.num_quality_bases as f64)
+    }
+
+    /// Get the fraction of called (A, C, G, T) bases that are G or C, or None if there are none
+    pub fn gc_fraction(&self) -> Option<f64> {
+        (self.num_called_bases > 0).then(|| self.num_gc_bases as f64 / self.num_called_bases as f64)
+    }
+
+    /// Format as a JSON object
+    pub fn to_json(&self) -> String {
+        let optional = |value: Option<f64>| value.map_or("null".to_string(), |v| v.to_string());
+        let histogram = |counts: &BTreeMap<usize, usize>| {
+            counts
+                .iter()
+                .map(|(key, count)| format!("\"{key}\": {count}"))
+                .collect::<Vec<String>>()
+                .join(", ")
+        };
+        format!(
+            "{{\"num_reads\": {}, \"num_queries\": {}, \"num_bases\": {}, \"mean_quality\": {}, \
+            \"gc_fraction\": {}, \"read_lengths\": {{{}}}, \"query_sizes\": {{{}}}}}",
+            self.num_reads(),
+            self.num_queries(),
+            self.num_bases,
+            optional(self.mean_quality()),
+            optional(self.gc_fraction()),
+            histogram(&self.read_lengths),
+            histogram(&self.query_sizes),
+        )
+    }
+
+    /// Format as TSV lines of section, key, and value. Summary values are in the "summary"
+    /// section, and histograms have one line per bin.
+    pub fn to_tsv(&self) -> String {
+        let optional = |value: Option<f64>| value.map_or("NA".to_string(), |v| v.to_string());
+        let mut lines: Vec<String> = vec![
+            format!("summary\tnum_reads\t{}", self.num_reads()),
+            format!("summary\tnum_queries\t{}", self.num_queries()),
+            format!("summary\tnum_bases\t{}", self.num_bases),
+            format!("summary\tmean_quality\t{}", optional(self.mean_quality())),
+            format!("summary\tgc_fraction\t{}", optional(self.gc_fraction())),
+        ];
+        lines.extend(
+            self.read_lengths
+                .iter()
+                .map(|(length, count)| format!("read_length\t{length}\t{count}")),
+        );
+        lines.extend(
+            self.query_sizes
+                .iter()
+                .map(|(size, count)| format!("query_size\t{size}\t{count}")),
+        );
+        lines.join("\n")
+    }
+}
+
+/// Implement ChunkableRecordWriter for ReadStats, so statistics can be accumulated anywhere records
+/// are written, e.g. for a single chunk.
+impl<R> ChunkableRecordWriter<R> for ReadStats
+where
+    R: ChunkableRecord,
+{
+    fn write(&mut self, record: &R) -> Result<()> {
+        self.add(record);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ReadStats;
+    use crate::{chunkable::ChunkableRecord, fastq::FastqRecord};
+    use anyhow::Result;
+
+    /// Make a FASTQ record
+    fn record(qname: &[u8], seq: &[u8], qual: &[u8]) -> Result<FastqRecord> {
+        let mut record = FastqRecord::new();
+        record.set_fields(qname, seq, qual)?;
+        Ok(record)
+    }
+
+    /// Test that read lengths, qualities, GC content and query sizes are accumulated.
+    #[test]
+    fn test_read_stats() -> Result<()> {
+        let mut stats = ReadStats::new();
+        assert_eq!(stats.mean_quality(), None);
+        stats.add(&record(b"q1", b"ACGT", &[10, 20, 30, 40])?);
+        stats.add(&record(b"q1", b"GGCN", &[10, 10, 10, 10])?);
+        stats.add(&record(b"q2", b"AAAAAT", &[40; 6])?);
+        stats.finish_query();
+
+        assert_eq!(stats.num_reads(), 3);
+        assert_eq!(stats.num_queries(), 2);
+        assert_eq!(stats.num_bases(), 14);
+        assert_eq!(stats.read_lengths().get(&4), Some(&2));
+        assert_eq!(stats.read_lengths().get(&6), Some(&1));
+        assert_eq!(stats.query_sizes().get(&1), Some(&1));
+        assert_eq!(stats.query_sizes().get(&2), Some(&1));
+        assert_eq!(stats.mean_quality(), Some(380.0 / 14.0));
+        assert_eq!(stats.gc_fraction(), Some(5.0 / 13.0));
+
+        let json = stats.to_json();
+        assert!(json.contains("\"num_reads\": 3"), "{json}");
+        assert!(
+            json.contains("\"query_sizes\": {\"1\": 1, \"2\": 1}"),
+            "{json}"
+        );
+        let tsv = stats.to_tsv();
+        assert!(tsv.lines().any(|line| line == "read_length\t4\t2"), "{tsv}");
+        assert!(tsv.lines().all(|line| line.split('\t').count() == 3));
+        Ok(())
+    }
+}
@@ -0,0 +1,123 @@
+use crate::{
+    chunkable::{ChunkableRecord, ChunkableRecordReader, OffsetKind, SplitRange},
+    resync::SkippedRange,
+};
+use anyhow::Result;
+
+/// Tag of the optional field recording which chunk a record was extracted in
+pub const CHUNK_TAG: &str = "ch";
+
+/// Get the optional field ("ch:i:<chunk_index>") recording that a record was extracted in chunk
+/// chunk_index.
+pub fn chunk_tag(chunk_index: usize) -> Vec<u8> {
+    format!("{CHUNK_TAG}:i:{chunk_index}").into_bytes()
+}
+
+/// Reader that adds SAM optional fields ("TG:T:value") to every record it reads: as aux fields of
+/// SAM/BAM/CRAM records, or as tab-separated comment fields of FASTQ records. Fields with the same
+/// tag are replaced, so re-extracting a chunk of a chunk keeps one field per tag.
+pub struct TaggingReader<Reader> {
+    inner: Reader,
+    tags: Vec<Vec<u8>>,
+}
+
+impl<Reader> TaggingReader<Reader> {
+    /// Create a new TaggingReader wrapping the supplied reader. With no tags, records are read
+    /// unchanged.
+    pub fn new(inner: Reader, tags: Vec<Vec<u8>>) -> Self {
+        TaggingReader { inner, tags }
+    }
+
+    /// Get a reference to the underlying reader, e.g. to get its header
+    pub fn get_ref(&self) -> &Reader {
+        &self.inner
+    }
+}
+
+/// Implement ChunkableRecordReader for TaggingReader, tagging each record after reading it.
+impl<R, Reader> ChunkableRecordReader<R> for TaggingReader<Reader>
+where
+    R: ChunkableRecord,
+    Reader: ChunkableRecordReader<R>,
+{
+    fn tell(&mut self) -> Result<u64> {
+        self.inner.tell()
+    }
+
+    fn seek(&mut self, offset: u64) -> Result<()> {
+        self.inner.seek(offset)
+    }
+
+    fn offset_kind(&self) -> OffsetKind {
+        self.inner.offset_kind()
+    }
+
+    fn seek_to_range(&mut self, split_range: &SplitRange) -> Result<()> {
+        self.inner.seek_to_range(split_range)
+    }
+
+    fn tell_uncompressed(&mut self) -> Result<Option<u64>> {
+        self.inner.tell_uncompressed()
+    }
+
+    fn read_into(&mut self, record: &mut R) -> Option<Result<()>> {
+        let result = self.inner.read_into(record);
+        if let Some(Ok(())) = result {
+            for tag in &self.tags {
+                if let Err(err) = record.push_tag(tag) {
+                    return Some(Err(err));
+                }
+            }
+        }
+        result
+    }
+
+    fn take_skipped_ranges(&mut self) -> Vec<SkippedRange> {
+        self.inner.take_skipped_ranges()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{TaggingReader, chunk_tag};
+    use crate::{
+        chunkable::{ChunkableRecord, ChunkableRecordReader},
+        fastq::{FastqReader, FastqRecord},
+    };
+    use anyhow::Result;
+    use rust_htslib::bam::{Record as BamRecord, record::Aux};
+    use std::io::Cursor;
+
+    /// Test that FASTQ records get a comment field and BAM records an aux field, replacing any
+    /// earlier field with the same tag.
+    #[test]
+    fn test_tagging_reader() -> Result<()> {
+        let fastq = b"@read1\nACGT\n+\nIIII\n@read2 1:N:0:ACGT\tch:i:9\nACGT\n+\nIIII\n";
+        let mut reader =
+            TaggingReader::new(FastqReader::new(Cursor::new(fastq)), vec![chunk_tag(3)]);
+        let mut record = FastqRecord::new();
+        let mut comments: Vec<Vec<u8>> = Vec::new();
+        while let Some(result) = reader.read_into(&mut record) {
+            result?;
+            comments.push(record.comment().unwrap_or_default().to_vec());
+        }
+        assert_eq!(
+            comments,
+            [b"ch:i:3".to_vec(), b"1:N:0:ACGT\tch:i:3".to_vec()]
+        );
+
+        let mut bam = <BamRecord as ChunkableRecord>::new();
+        bam.set_fields(b"read1", b"ACGT", &[30; 4])?;
+        bam.push_aux(b"RG", Aux::String("A"))?;
+        for chunk_index in [9, 3] {
+            ChunkableRecord::push_tag(&mut bam, &chunk_tag(chunk_index))?;
+        }
+        assert_eq!(
+            bam.tags(),
+            Some(vec![b"RG:Z:A".to_vec(), b"ch:i:3".to_vec()])
+        );
+        assert!(ChunkableRecord::push_tag(&mut bam, b"ch:B:c,1").is_err());
+        assert!(ChunkableRecord::push_tag(&mut bam, b"chunk").is_err());
+        Ok(())
+    }
+}
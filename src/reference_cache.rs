@@ -0,0 +1,258 @@
+use anyhow::{Result, anyhow};
+use log::debug;
+use rust_htslib::{
+    bam::{HeaderView, Read},
+    htslib,
+};
+use std::{
+    collections::{HashMap, hash_map::Entry},
+    ffi::CString,
+    fs,
+    path::{Path, PathBuf},
+    sync::{LazyLock, Mutex},
+};
+use xxhash_rust::xxh3::Xxh3;
+
+/// Shared references of the process, by reference FASTA and header sequences
+static CACHE: LazyLock<Mutex<HashMap<CacheKey, SharedReference>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// htslib maps reference sequences to header target ids when a reference is loaded, so handles
+/// can only share a reference if their headers list the same sequences in the same order.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+struct CacheKey {
+    fasta: PathBuf,
+    /// Hash of the name and length of every @SQ line, in order
+    sequences: u64,
+}
+
+impl CacheKey {
+    fn new(fasta: &Path, header: &HeaderView) -> Self {
+        let mut hasher = Xxh3::new();
+        for tid in 0..header.target_count() {
+            hasher.update(header.tid2name(tid));
+            hasher.update(b"\t");
+            hasher.update(&header.target_len(tid).unwrap_or_default().to_le_bytes());
+            hasher.update(b"\n");
+        }
+        CacheKey {
+            fasta: fs::canonicalize(fasta).unwrap_or_else(|_| fasta.to_path_buf()),
+            sequences: hasher.digest(),
+        }
+    }
+}
+
+/// CRAM reference sequences decoded by htslib, held by a CRAM handle writing to /dev/null that is
+/// kept open so the sequences outlive the readers and writers sharing them. htslib counts the
+/// handles using them, and locks them while loading sequences, so sharing is thread-safe.
+struct SharedReference {
+    anchor: *mut htslib::htsFile,
+}
+
+// SAFETY: the anchor is only used to get its refs_t, which htslib locks while it's being loaded,
+// and to close it once, on drop.
+unsafe impl Send for SharedReference {}
+
+impl SharedReference {
+    /// Open an anchor handle holding the reference FASTA, mapped to the sequences of header.
+    fn open(fasta: &Path, header: &HeaderView) -> Result<Self> {
+        // SAFETY: both arguments are valid NUL-terminated strings
+        let anchor = unsafe { htslib::hts_open(c"/dev/null".as_ptr(), c"wc".as_ptr()) };
+        if anchor.is_null() {
+            return Err(anyhow!(
+                "Opening a handle to share CRAM reference {fasta:?}."
+            ));
+        }
+        // from here on, dropping closes the anchor
+        let shared = SharedReference { anchor };
+        // SAFETY: anchor is a valid open htsFile, and header a valid header. no_ref stops htslib
+        // loading every sequence to fill in missing M5 tags when the header is written, which is
+        // when it maps the sequences to the header.
+        unsafe {
+            set_fai_filename(anchor, fasta)?;
+            if htslib::hts_set_opt(anchor, htslib::hts_fmt_option_CRAM_OPT_NO_REF, 1i32) != 0
+                || htslib::sam_hdr_write(anchor, header.inner_ptr()) != 0
+            {
+                return Err(anyhow!("Loading CRAM reference {fasta:?} to share."));
+            }
+        }
+        debug!("Loaded CRAM reference {fasta:?} to share between readers and writers.");
+        Ok(shared)
+    }
+
+    /// Switch hts_file over to these reference sequences, dropping its own.
+    ///
+    /// # Safety
+    /// hts_file must be a valid open CRAM htsFile, whose header lists the same sequences as the
+    /// header this reference was opened with.
+    unsafe fn share_with(&self, hts_file: *mut htslib::htsFile) -> Result<()> {
+        // SAFETY: anchor is a valid open CRAM htsFile, so it has a refs_t, and hts_file is too
+        let refs = unsafe { htslib::cram_get_refs(self.anchor) };
+        if refs.is_null()
+            || unsafe {
+                htslib::hts_set_opt(hts_file, htslib::hts_fmt_option_CRAM_OPT_SHARED_REF, refs)
+            } != 0
+        {
+            return Err(anyhow!("Sharing CRAM reference sequences."));
+        }
+        Ok(())
+    }
+}
+
+/// impl Drop for SharedReference, closing the anchor. htslib frees the reference sequences once
+/// no handle uses them.
+impl Drop for SharedReference {
+    fn drop(&mut self) {
+        // SAFETY: anchor is a valid open htsFile, and is not used after this
+        unsafe { htslib::hts_close(self.anchor) };
+    }
+}
+
+/// Set the reference FASTA of hts_file, as rust_htslib's set_reference does.
+///
+/// # Safety
+/// hts_file must be a valid open htsFile.
+unsafe fn set_fai_filename(hts_file: *mut htslib::htsFile, fasta: &Path) -> Result<()> {
+    let c_fasta = CString::new(
+        fasta
+            .to_str()
+            .ok_or_else(|| anyhow!("Reference path {fasta:?} is not valid UTF-8."))?,
+    )?;
+    // SAFETY: hts_file is a valid open htsFile, and c_fasta is NUL-terminated
+    if unsafe { htslib::hts_set_fai_filename(hts_file, c_fasta.as_ptr()) } != 0 {
+        return Err(anyhow!("Setting reference {fasta:?}."));
+    }
+    Ok(())
+}
+
+/// Return true if hts_file is a CRAM.
+///
+/// # Safety
+/// hts_file must be a valid open htsFile.
+unsafe fn is_cram(hts_file: *mut htslib::htsFile) -> bool {
+    // SAFETY: hts_file is a valid open htsFile, whose format is always set
+    unsafe { (*htslib::hts_get_format(hts_file)).format == htslib::htsExactFormat_cram }
+}
+
+/// Switch the CRAM hts_file, with header, over to the process-wide decoded sequences of the
+/// reference fasta, loading them on first use. Does nothing for other formats, or headers without
+/// sequences.
+///
+/// # Safety
+/// hts_file must be a valid open htsFile. Writers must have written header already, as htslib
+/// remaps the reference to it then.
+pub(crate) unsafe fn share_reference(
+    hts_file: *mut htslib::htsFile,
+    header: &HeaderView,
+    fasta: &Path,
+) -> Result<()> {
+    // SAFETY: hts_file is a valid open htsFile
+    if header.target_count() == 0 || !unsafe { is_cram(hts_file) } {
+        return Ok(());
+    }
+    let mut cache = CACHE
+        .lock()
+        .map_err(|_| anyhow!("CRAM reference cache is poisoned."))?;
+    let key = CacheKey::new(fasta, header);
+    let shared = match cache.entry(key) {
+        Entry::Occupied(entry) => entry.into_mut(),
+        Entry::Vacant(entry) => entry.insert(SharedReference::open(fasta, header)?),
+    };
+    // SAFETY: hts_file is a valid open CRAM htsFile, and its header matches the key
+    unsafe { shared.share_with(hts_file) }
+}
+
+/// Set the reference FASTA of a SAM/BAM/CRAM reader. CRAM readers of the same reference (and
+/// header sequences) share its sequences process-wide, so they're decoded once however many
+/// readers there are, e.g. when extracting chunks in parallel.
+pub fn set_reference<R, P>(reader: &mut R, fasta: P) -> Result<()>
+where
+    R: Read,
+    P: AsRef<Path>,
+{
+    let hts_file = reader.htsfile();
+    // SAFETY: the reader owns a valid open htsFile, whose header it has read
+    unsafe {
+        if is_cram(hts_file) && reader.header().target_count() > 0 {
+            share_reference(hts_file, reader.header(), fasta.as_ref())
+        } else {
+            set_fai_filename(hts_file, fasta.as_ref())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CACHE, CacheKey};
+    use crate::{
+        sam_writer_spec::{OutputFormat, SamWriterSpec},
+        util::get_bam_reader,
+    };
+    use anyhow::Result;
+    use rust_htslib::{
+        bam::{Header, Read, Record, header::HeaderRecord},
+        htslib,
+    };
+    use std::{fs, num::NonZero, path::Path};
+    use tempfile::TempDir;
+
+    /// Write a CRAM of one read on each reference sequence, listed in the header in order
+    fn write_cram(path: &Path, fasta: &Path, sequences: &[&str]) -> Result<()> {
+        let mut header = Header::new();
+        header.push_record(HeaderRecord::new(b"HD").push_tag(b"VN", "1.6"));
+        for name in sequences {
+            header.push_record(
+                HeaderRecord::new(b"SQ")
+                    .push_tag(b"SN", name)
+                    .push_tag(b"LN", 16),
+            );
+        }
+        let mut writer = SamWriterSpec::new(path)
+            .header(header)
+            .format(OutputFormat::Cram)
+            .reference_fasta(Some(fasta))
+            .get_bam_writer()?;
+        for tid in 0..sequences.len() {
+            let mut record = Record::new();
+            record.set(b"read1", None, b"ACGT", &[30, 30, 30, 30]);
+            record.set_tid(tid as i32);
+            record.set_pos(2);
+            writer.write(&record)?;
+        }
+        writer.finish()
+    }
+
+    /// Test that CRAM readers of one reference and header share its decoded sequences, readers of
+    /// another header get their own, and both decode their reads.
+    #[test]
+    fn test_shared_reference() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let fasta = temp_dir.path().join("ref.fa");
+        fs::write(&fasta, ">chr1\nACACGTACGTACGTAC\n>chr2\nTTACGTTTTTTTTTTT\n")?;
+        let both = temp_dir.path().join("both.cram");
+        write_cram(&both, &fasta, &["chr1", "chr2"])?;
+        let swapped = temp_dir.path().join("swapped.cram");
+        write_cram(&swapped, &fasta, &["chr2", "chr1"])?;
+
+        let readers = [&both, &both, &swapped]
+            .iter()
+            .map(|cram| get_bam_reader(cram, Some(&fasta), NonZero::<usize>::MIN))
+            .collect::<Result<Vec<_>>>()?;
+        // SAFETY: every reader owns a valid open CRAM htsFile
+        let refs: Vec<_> = readers
+            .iter()
+            .map(|reader| unsafe { htslib::cram_get_refs(reader.htsfile()) })
+            .collect();
+        assert_eq!(refs[0], refs[1]);
+        assert_ne!(refs[0], refs[2]);
+        let key = CacheKey::new(&fasta, readers[0].header());
+        assert!(CACHE.lock().unwrap().contains_key(&key));
+
+        for mut reader in readers {
+            for record in reader.records() {
+                assert_eq!(record?.seq().as_bytes(), b"ACGT");
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,261 @@
+use crate::{manifest::Md5, maybe_compressed_io::read_decompressed};
+use anyhow::{Result, anyhow};
+use log::info;
+use rust_htslib::bam::HeaderView;
+use std::{
+    env, fs,
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+};
+use xxhash_rust::xxh3::Xxh3;
+
+/// EBI CRAM reference registry, serving each reference sequence by the MD5 of its bases
+pub const DEFAULT_REGISTRY: &str = "https://www.ebi.ac.uk/ena/cram/md5/%s";
+
+/// Environment variable overriding the reference cache directory
+pub const CACHE_DIR_VARIABLE: &str = "SPLIT_READS_REF_CACHE";
+
+/// Environment variable overriding the registry URL ("%s" is replaced by the MD5). Set it empty
+/// to only use sequences already in the cache.
+pub const REGISTRY_VARIABLE: &str = "SPLIT_READS_REF_REGISTRY";
+
+/// Bases per line in FASTAs assembled from cached sequences
+const FASTA_LINE_LENGTH: usize = 60;
+
+/// Finds the reference sequences of a CRAM by the MD5s ("M5" tags) of its @SQ header lines,
+/// looking first in a local cache directory and then in a reference registry (by default the EBI
+/// CRAM reference registry), downloading and caching what's missing. The cache uses htslib's
+/// REF_CACHE layout (e.g. "ab/cd/abcd..."), so with the default directory it is shared with
+/// samtools. Sequences are assembled into a FASTA (also cached) to use as the reference.
+#[derive(Clone, Debug)]
+pub struct ReferenceResolver {
+    cache_dir: PathBuf,
+    /// URL pattern of the registry, with "%s" standing for the MD5, or None to not download
+    registry: Option<String>,
+}
+
+impl ReferenceResolver {
+    /// Create a new ReferenceResolver caching in cache_dir, and downloading from the EBI registry.
+    pub fn new<P>(cache_dir: P) -> Self
+    where
+        P: AsRef<Path>,
+    {
+        ReferenceResolver {
+            cache_dir: cache_dir.as_ref().to_path_buf(),
+            registry: Some(DEFAULT_REGISTRY.to_string()),
+        }
+    }
+
+    /// Create a ReferenceResolver configured from the environment: the cache directory is
+    /// SPLIT_READS_REF_CACHE, or else htslib's default ($XDG_CACHE_HOME/hts-ref or
+    /// $HOME/.cache/hts-ref), and the registry is SPLIT_READS_REF_REGISTRY if set. Returns None
+    /// if no cache directory can be found.
+    pub fn from_env() -> Option<Self> {
+        let cache_dir = env::var_os(CACHE_DIR_VARIABLE)
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("XDG_CACHE_HOME").map(|dir| PathBuf::from(dir).join("hts-ref")))
+            .or_else(|| {
+                env::var_os("HOME").map(|dir| PathBuf::from(dir).join(".cache").join("hts-ref"))
+            })?;
+        let mut resolver = Self::new(cache_dir);
+        if let Ok(registry) = env::var(REGISTRY_VARIABLE) {
+            resolver.registry((!registry.is_empty()).then_some(registry));
+        }
+        Some(resolver)
+    }
+
+    /// Set the registry URL pattern ("%s" is replaced by the MD5), or None to not download.
+    pub fn registry(&mut self, registry: Option<String>) -> &mut Self {
+        self.registry = registry;
+        self
+    }
+
+    /// Get the cache path of the sequence with this MD5.
+    pub fn sequence_path(&self, md5: &str) -> PathBuf {
+        self.cache_dir.join(&md5[..2]).join(&md5[2..4]).join(md5)
+    }
+
+    /// Get the cached sequence with this MD5, downloading it from the registry if needed.
+    fn fetch_sequence(&self, md5: &str) -> Result<PathBuf> {
+        let path = self.sequence_path(md5);
+        if path.exists() {
+            return Ok(path);
+        }
+        let registry = self.registry.as_ref().ok_or_else(|| {
+            anyhow!(
+                "Reference sequence {md5} is not in the cache {:?}.",
+                self.cache_dir
+            )
+        })?;
+        let url = registry.replace("%s", md5);
+        info!("Downloading reference sequence {md5} from {url}");
+        let mut sequence = read_decompressed(&url)
+            .map_err(|err| anyhow!("Downloading reference sequence {md5}: {err}"))?;
+        sequence.retain(|base| !base.is_ascii_whitespace());
+        sequence.make_ascii_uppercase();
+        let mut hasher = Md5::new();
+        hasher.update(&sequence);
+        let digest = hasher.hex_digest();
+        if digest != md5 {
+            return Err(anyhow!(
+                "Downloaded reference sequence from {url} has MD5 {digest}, not {md5}."
+            ));
+        }
+        write_atomically(&path, |writer| Ok(writer.write_all(&sequence)?))?;
+        Ok(path)
+    }
+
+    /// Get a FASTA holding every reference sequence of a SAM/BAM/CRAM header, in header order,
+    /// assembling it from cached (or downloaded) sequences if it isn't cached already. Every @SQ
+    /// line needs an M5 tag.
+    pub fn resolve(&self, header: &HeaderView) -> Result<PathBuf> {
+        let sequences = header_sequences(header)?;
+        let mut hasher = Xxh3::new();
+        for (name, md5) in &sequences {
+            hasher.update(name.as_bytes());
+            hasher.update(b"\t");
+            hasher.update(md5.as_bytes());
+            hasher.update(b"\n");
+        }
+        let fasta = self
+            .cache_dir
+            .join("split-reads-fasta")
+            .join(format!("{:016x}.fa", hasher.digest()));
+        if fasta.exists() {
+            return Ok(fasta);
+        }
+        let sequence_paths = sequences
+            .iter()
+            .map(|(_, md5)| self.fetch_sequence(md5))
+            .collect::<Result<Vec<PathBuf>>>()?;
+        write_atomically(&fasta, |writer| {
+            for ((name, _), sequence_path) in sequences.iter().zip(&sequence_paths) {
+                writeln!(writer, ">{name}")?;
+                for line in fs::read(sequence_path)?.chunks(FASTA_LINE_LENGTH) {
+                    writer.write_all(line)?;
+                    writer.write_all(b"\n")?;
+                }
+            }
+            Ok(())
+        })?;
+        info!("Assembled reference FASTA {fasta:?}");
+        Ok(fasta)
+    }
+}
+
+/// Get the name and MD5 of every reference sequence in the header.
+fn header_sequences(header: &HeaderView) -> Result<Vec<(String, String)>> {
+    String::from_utf8_lossy(header.as_bytes())
+        .lines()
+        .filter(|line| line.starts_with("@SQ\t"))
+        .map(|line| {
+            let tag = |key: &str| {
+                line.split('\t')
+                    .find_map(|field| field.strip_prefix(key))
+                    .map(str::to_string)
+            };
+            let name = tag("SN:").ok_or_else(|| anyhow!("Header line has no name: {line}"))?;
+            let md5 = tag("M5:")
+                .map(|md5| md5.to_ascii_lowercase())
+                .filter(|md5| md5.len() == 32 && md5.bytes().all(|c| c.is_ascii_hexdigit()))
+                .ok_or_else(|| {
+                    anyhow!("Reference sequence {name} has no valid M5 tag, cannot look it up.")
+                })?;
+            Ok((name, md5))
+        })
+        .collect()
+}
+
+/// Write a file by writing a temporary file next to it and renaming it into place, so that
+/// concurrent readers never see a partial file.
+fn write_atomically<F>(path: &Path, write: F) -> Result<()>
+where
+    F: FnOnce(&mut BufWriter<fs::File>) -> Result<()>,
+{
+    if let Some(parent_dir) = path.parent() {
+        fs::create_dir_all(parent_dir)?;
+    }
+    let mut temp_path = path.as_os_str().to_owned();
+    temp_path.push(format!(".tmp{}", std::process::id()));
+    let mut writer = BufWriter::new(fs::File::create(&temp_path)?);
+    write(&mut writer)?;
+    writer.flush()?;
+    drop(writer);
+    fs::rename(&temp_path, path).map_err(|err| anyhow!("Renaming into {path:?}: {err}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ReferenceResolver;
+    use crate::manifest::Md5;
+    use anyhow::Result;
+    use rust_htslib::bam::{Header, HeaderView, header::HeaderRecord};
+    use std::fs;
+    use tempfile::TempDir;
+
+    /// Get the MD5 of a sequence as lowercase hex
+    fn md5(sequence: &[u8]) -> String {
+        let mut hasher = Md5::new();
+        hasher.update(sequence);
+        hasher.hex_digest()
+    }
+
+    /// Test that sequences are downloaded once from the registry, checked, cached, and assembled
+    /// into a FASTA in header order.
+    #[test]
+    fn test_resolve() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let registry_dir = temp_dir.path().join("registry");
+        fs::create_dir(&registry_dir)?;
+        let sequences: [(&str, &[u8]); 2] = [("chr1", b"ACGTACGTNN"), ("chr2", b"GGGCCCAAATTT")];
+        let mut header = Header::new();
+        for (name, sequence) in sequences {
+            fs::write(registry_dir.join(md5(sequence)), sequence)?;
+            header.push_record(
+                HeaderRecord::new(b"SQ")
+                    .push_tag(b"SN", name)
+                    .push_tag(b"LN", sequence.len())
+                    .push_tag(b"M5", md5(sequence)),
+            );
+        }
+        let header = HeaderView::from_header(&header);
+        let mut resolver = ReferenceResolver::new(temp_dir.path().join("cache"));
+        resolver.registry(Some(format!("{}/%s", registry_dir.to_str().unwrap())));
+        let fasta = resolver.resolve(&header)?;
+        assert_eq!(
+            fs::read_to_string(&fasta)?,
+            ">chr1\nACGTACGTNN\n>chr2\nGGGCCCAAATTT\n"
+        );
+        let chr1_md5 = md5(sequences[0].1);
+        assert!(resolver.sequence_path(&chr1_md5).exists());
+        assert!(resolver.sequence_path(&chr1_md5).ends_with(format!(
+            "{}/{}/{chr1_md5}",
+            &chr1_md5[..2],
+            &chr1_md5[2..4]
+        )));
+
+        // cached sequences are used without the registry, and bad downloads are rejected
+        fs::remove_file(&fasta)?;
+        resolver.registry(None);
+        assert_eq!(resolver.resolve(&header)?, fasta);
+        let mut bad_header = Header::new();
+        bad_header.push_record(
+            HeaderRecord::new(b"SQ")
+                .push_tag(b"SN", "chr3")
+                .push_tag(b"LN", 4)
+                .push_tag(b"M5", md5(b"TTTT")),
+        );
+        fs::write(registry_dir.join(md5(b"TTTT")), b"AAAA")?;
+        assert!(
+            resolver
+                .resolve(&HeaderView::from_header(&bad_header))
+                .is_err()
+        );
+        resolver.registry(Some(format!("{}/%s", registry_dir.to_str().unwrap())));
+        let err = resolver
+            .resolve(&HeaderView::from_header(&bad_header))
+            .unwrap_err();
+        assert!(err.to_string().contains("has MD5"), "{err}");
+        Ok(())
+    }
+}
@@ -0,0 +1,478 @@
+use crate::{
+    bam_index::{BamIndex, spread_reads},
+    chunkable::{ChunkResult, ChunkableRecordWriter, RecordTransform, TransformResult},
+    path_type::PathType,
+    util::RecordType,
+};
+use anyhow::{Result, anyhow};
+use log::warn;
+use rust_htslib::bam::{HeaderView, IndexedReader, Read, Record as BamRecord};
+use std::{
+    collections::{BTreeMap, HashSet},
+    fmt, fs,
+    num::NonZero,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+/// Number of index windows per region when computing equal-coverage regions, so region
+/// boundaries can fall between the read counts of the windows
+const WINDOWS_PER_REGION: u64 = 64;
+
+/// Genomic interval of a reference sequence, 0-based and half-open as in BED
+#[derive(Clone, Debug, PartialEq)]
+pub struct Region {
+    pub contig: String,
+    pub start: u64,
+    pub end: u64,
+}
+
+/// Display as a 1-based samtools-style region, e.g. "chr1:1-1000"
+impl fmt::Display for Region {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}-{}", self.contig, self.start + 1, self.end)
+    }
+}
+
+/// Regions defining the chunks of a coordinate-sorted BAM/CRAM: computed from its BAI/CSI index,
+/// or one chunk per line of a BED file.
+#[derive(Clone, Debug, PartialEq)]
+pub enum RegionSet {
+    /// Regions of about equal read counts, computed from the BAI/CSI index
+    Auto,
+    /// Regions read from a BED file, one per chunk
+    Bed(PathBuf),
+}
+
+/// Parse "auto" as RegionSet::Auto, anything else as the path to a BED file
+impl FromStr for RegionSet {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "auto" => Ok(RegionSet::Auto),
+            _ => Ok(RegionSet::Bed(PathBuf::from(s))),
+        }
+    }
+}
+
+impl RegionSet {
+    /// Get the regions of every chunk of the BAM/CRAM at path. Auto regions need num_chunks, BED
+    /// regions are one chunk per line, and if num_chunks is given it must match.
+    pub fn chunk_regions<P>(
+        &self,
+        path: P,
+        num_chunks: Option<NonZero<usize>>,
+    ) -> Result<Vec<Vec<Region>>>
+    where
+        P: AsRef<Path>,
+    {
+        match self {
+            RegionSet::Auto => {
+                let num_chunks = num_chunks
+                    .ok_or_else(|| anyhow!("--region-set auto requires --num-chunks."))?;
+                equal_coverage_regions(path, num_chunks)
+            }
+            RegionSet::Bed(bed) => {
+                let regions = read_bed(bed)?;
+                if let Some(num_chunks) = num_chunks
+                    && num_chunks.get() != regions.len()
+                {
+                    return Err(anyhow!(
+                        "--num-chunks is {num_chunks}, but {bed:?} has {} regions.",
+                        regions.len()
+                    ));
+                }
+                Ok(regions.into_iter().map(|region| vec![region]).collect())
+            }
+        }
+    }
+}
+
+/// Read the regions of a BED file, in file order. Only the first three columns are used, and
+/// blank, comment ("#"), "track" and "browser" lines are skipped.
+pub fn read_bed<P>(path: P) -> Result<Vec<Region>>
+where
+    P: AsRef<Path>,
+{
+    let path = path.as_ref();
+    let text = fs::read_to_string(path)
+        .map_err(|err| anyhow!("Could not read BED file {path:?}: {err}"))?;
+    let mut regions = Vec::new();
+    for (line_index, line) in text.lines().enumerate() {
+        if line.trim().is_empty()
+            || line.starts_with('#')
+            || line.starts_with("track")
+            || line.starts_with("browser")
+        {
+            continue;
+        }
+        let mut fields = line.split('\t');
+        let (Some(contig), Some(start), Some(end)) = (fields.next(), fields.next(), fields.next())
+        else {
+            return Err(anyhow!(
+                "Line {} of {path:?} has fewer than 3 tab-separated fields.",
+                line_index + 1
+            ));
+        };
+        let (start, end) = match (start.trim().parse::<u64>(), end.trim().parse::<u64>()) {
+            (Ok(start), Ok(end)) if start < end => (start, end),
+            _ => {
+                return Err(anyhow!(
+                    "Line {} of {path:?} is not a non-empty interval: {line:?}",
+                    line_index + 1
+                ));
+            }
+        };
+        regions.push(Region {
+            contig: contig.to_string(),
+            start,
+            end,
+        });
+    }
+    if regions.is_empty() {
+        return Err(anyhow!("BED file {path:?} has no regions."));
+    }
+    Ok(regions)
+}
+
+/// Split the reference sequences of a local coordinate-sorted BAM into num_chunks sets of
+/// consecutive regions with about equal numbers of reads, estimated from its BAI/CSI index as in
+/// build_approximate_index. Every position of every reference is in exactly one chunk's regions.
+/// Unplaced reads are in none, so extracting every chunk skips them. CRAM is rejected: its CRAI
+/// index has no per-reference read counts to estimate from.
+pub fn equal_coverage_regions<P>(path: P, num_chunks: NonZero<usize>) -> Result<Vec<Vec<Region>>>
+where
+    P: AsRef<Path>,
+{
+    let PathType::FilePath(path) = PathType::from_path(path.as_ref())? else {
+        return Err(anyhow!(
+            "Can only compute regions of a local BAM from its BAI/CSI index."
+        ));
+    };
+    if RecordType::detect(&path)? == Some(RecordType::Cram) {
+        return Err(anyhow!(
+            "--region-set auto needs a BAM with a BAI/CSI index, but {path:?} is CRAM. Use a BED \
+            region set for CRAM."
+        ));
+    }
+    let bam_index = BamIndex::load(&path)?;
+    let reader = IndexedReader::from_path(&path)?;
+    let header = reader.header();
+    let references: Vec<(String, u64)> = (0..header.target_count())
+        .map(|tid| {
+            (
+                String::from_utf8_lossy(header.tid2name(tid)).to_string(),
+                header.target_len(tid).unwrap_or_default(),
+            )
+        })
+        .collect();
+    let window = references
+        .iter()
+        .map(|(_, length)| length)
+        .sum::<u64>()
+        .div_ceil(num_chunks.get() as u64 * WINDOWS_PER_REGION)
+        .max(1);
+
+    // the starting offsets of each reference's windows, None for windows without reads
+    let window_offsets: Vec<Vec<Option<u64>>> = (0u32..)
+        .zip(references.iter())
+        .map(|(tid, (_, length))| {
+            (0..*length)
+                .step_by(window as usize)
+                .map(|beg| {
+                    (bam_index.num_reads(tid) > 0)
+                        .then(|| bam_index.first_offset(tid, beg, beg + 1))
+                        .flatten()
+                })
+                .collect()
+        })
+        .collect();
+    let end_offset = bam_index
+        .unplaced_offset()
+        .unwrap_or(fs::metadata(&path)?.len() << 16);
+    // estimated reads in each window: each reference's reads spread by their span of the file
+    let mut next_offset = end_offset;
+    let mut window_reads: Vec<Vec<u64>> = vec![Vec::new(); references.len()];
+    for (tid, offsets) in window_offsets.iter().enumerate().rev() {
+        let reference_end = next_offset;
+        let mut starts: Vec<u64> = offsets
+            .iter()
+            .rev()
+            .map(|offset| {
+                next_offset = offset.unwrap_or(next_offset).min(next_offset);
+                next_offset
+            })
+            .collect();
+        starts.reverse();
+        let spans: Vec<u64> = starts
+            .iter()
+            .zip(starts.iter().skip(1).chain([&reference_end]))
+            .map(|(start, stop)| stop.saturating_sub(*start))
+            .collect();
+        let mut previous = 0;
+        window_reads[tid] = spread_reads(bam_index.num_reads(tid as u32), &spans)
+            .into_iter()
+            .map(|cumulative| {
+                let reads = cumulative - previous;
+                previous = cumulative;
+                reads
+            })
+            .collect();
+    }
+
+    // assign windows to chunks in order by the reads before them, merging them into regions
+    let total_reads: u64 = window_reads.iter().flatten().sum();
+    let mut chunks: Vec<Vec<Region>> = vec![Vec::new(); num_chunks.get()];
+    let mut previous_reads = 0u64;
+    for ((contig, length), reads) in references.iter().zip(window_reads) {
+        for (beg, reads) in (0..*length).step_by(window as usize).zip(reads) {
+            let chunk_index = (previous_reads as u128 * num_chunks.get() as u128
+                / total_reads.max(1) as u128) as usize;
+            previous_reads += reads;
+            let end = (beg + window).min(*length);
+            let regions = &mut chunks[chunk_index.min(num_chunks.get() - 1)];
+            match regions.last_mut() {
+                Some(region) if region.contig == *contig && region.end == beg => region.end = end,
+                _ => regions.push(Region {
+                    contig: contig.clone(),
+                    start: beg,
+                    end,
+                }),
+            }
+        }
+    }
+    Ok(chunks)
+}
+
+/// Most bases between mate positions that are fetched together when pulling in mates
+const MATE_FETCH_GAP: i64 = 10_000;
+
+/// Get the tid, start and end of each region, checking its contig is in the header.
+fn region_spans(header: &HeaderView, regions: &[Region]) -> Result<Vec<(u32, u64, u64)>> {
+    regions
+        .iter()
+        .map(|region| {
+            header
+                .tid(region.contig.as_bytes())
+                .map(|tid| (tid, region.start, region.end))
+                .ok_or_else(|| anyhow!("Region {region} is on a contig not in the header."))
+        })
+        .collect()
+}
+
+/// Return true if a read at position pos of reference tid starts in one of spans.
+fn starts_in(spans: &[(u32, u64, u64)], tid: i32, pos: i64) -> bool {
+    spans.iter().any(|&(span_tid, start, end)| {
+        span_tid as i32 == tid && (start..end).contains(&(pos as u64))
+    })
+}
+
+/// Return true if record is the primary alignment of a paired read whose mate has a position,
+/// so must be kept with it.
+fn has_placed_mate(record: &BamRecord) -> bool {
+    record.is_paired() && !record.is_secondary() && !record.is_supplementary() && record.mtid() >= 0
+}
+
+/// Return true if record is the leftmost mate of its pair, whose chunk writes the pair. Mates at
+/// the same position are led by the first in the template.
+fn is_leftmost_mate(record: &BamRecord) -> bool {
+    let (position, mate_position) = ((record.tid(), record.pos()), (record.mtid(), record.mpos()));
+    position < mate_position || (position == mate_position && record.is_first_in_template())
+}
+
+/// Fetch the mates of reads starting in spans whose leftmost mate is there but whose other mate
+/// starts elsewhere, sorted by position. This takes one pass over the spans to find the mates,
+/// then fetches their positions, several at a time when they are close.
+fn fetch_mates(reader: &mut IndexedReader, spans: &[(u32, u64, u64)]) -> Result<Vec<BamRecord>> {
+    let mut record = BamRecord::new();
+    let mut requests: BTreeMap<(i32, i64), HashSet<Vec<u8>>> = BTreeMap::new();
+    for &(tid, start, end) in spans {
+        reader.fetch((tid, start as i64, end as i64))?;
+        while let Some(result) = reader.read(&mut record) {
+            result?;
+            if starts_in(spans, record.tid(), record.pos())
+                && has_placed_mate(&record)
+                && is_leftmost_mate(&record)
+                && !starts_in(spans, record.mtid(), record.mpos())
+            {
+                requests
+                    .entry((record.mtid(), record.mpos()))
+                    .or_default()
+                    .insert(record.qname().to_vec());
+            }
+        }
+    }
+
+    let mut mates = Vec::new();
+    let positions: Vec<(i32, i64)> = requests.keys().copied().collect();
+    let mut run_start = 0;
+    while run_start < positions.len() {
+        let (tid, first_pos) = positions[run_start];
+        let mut run_stop = run_start + 1;
+        while run_stop < positions.len()
+            && positions[run_stop].0 == tid
+            && positions[run_stop].1 - positions[run_stop - 1].1 <= MATE_FETCH_GAP
+        {
+            run_stop += 1;
+        }
+        reader.fetch((tid, first_pos, positions[run_stop - 1].1 + 1))?;
+        while let Some(result) = reader.read(&mut record) {
+            result?;
+            if record.is_secondary() || record.is_supplementary() {
+                continue;
+            }
+            if let Some(qnames) = requests.get_mut(&(record.tid(), record.pos()))
+                && qnames.remove(record.qname())
+            {
+                mates.push(record.clone());
+            }
+        }
+        run_start = run_stop;
+    }
+    let num_missing: usize = requests.values().map(HashSet::len).sum();
+    if num_missing > 0 {
+        warn!("{num_missing} mates were not found at the positions their mates give for them.");
+    }
+    mates.sort_by_key(|mate| (mate.tid(), mate.pos()));
+    Ok(mates)
+}
+
+/// Write the reads of regions from an indexed BAM/CRAM. Only reads whose alignment starts in a
+/// region are written, so regions that don't overlap never share a read, and regions covering the
+/// genome write every placed read once. Reads are written in region order, each transformed first
+/// if a transform is given, and each counts as its own query group.
+///
+/// If keep_mates, each read pair is instead written by the regions its leftmost mate starts in,
+/// along with its other mate wherever that starts, so chunks never split a pair. Pulled-in mates
+/// are merged into the output by position, and a pair counts as one query group. Mates without
+/// a position are left with the unplaced reads.
+pub fn write_region_reads<W>(
+    reader: &mut IndexedReader,
+    regions: &[Region],
+    writer: &mut W,
+    mut transform: Option<&mut dyn RecordTransform<BamRecord>>,
+    keep_mates: bool,
+) -> Result<ChunkResult>
+where
+    W: ChunkableRecordWriter<BamRecord>,
+{
+    let spans = region_spans(reader.header(), regions)?;
+    let mut mates = if keep_mates {
+        fetch_mates(reader, &spans)?
+    } else {
+        Vec::new()
+    }
+    .into_iter()
+    .peekable();
+    let (mut num_reads, mut num_queries) = (0usize, 0usize);
+    let mut write = |record: &mut BamRecord, is_query: bool| -> Result<()> {
+        if let Some(ref mut transform) = transform
+            && transform.transform(record)? == TransformResult::Drop
+        {
+            return Ok(());
+        }
+        writer.write(record)?;
+        num_reads += 1;
+        num_queries += usize::from(is_query);
+        Ok(())
+    };
+
+    let mut record = BamRecord::new();
+    for &(tid, start, end) in &spans {
+        reader.fetch((tid, start as i64, end as i64))?;
+        while let Some(result) = reader.read(&mut record) {
+            result?;
+            if record.tid() != tid as i32 || (record.pos() as u64) < start {
+                continue;
+            }
+            let is_query = if keep_mates && has_placed_mate(&record) {
+                if !is_leftmost_mate(&record) && !starts_in(&spans, record.mtid(), record.mpos()) {
+                    // the pair is written by the chunk of its leftmost mate
+                    continue;
+                }
+                is_leftmost_mate(&record)
+            } else {
+                true
+            };
+            while let Some(mut mate) =
+                mates.next_if(|mate| (mate.tid(), mate.pos()) < (record.tid(), record.pos()))
+            {
+                write(&mut mate, false)?;
+            }
+            write(&mut record, is_query)?;
+        }
+    }
+    for mut mate in mates {
+        write(&mut mate, false)?;
+    }
+    if num_reads == 0 {
+        Ok(ChunkResult::Empty)
+    } else {
+        Ok(ChunkResult::Written {
+            reads: num_reads,
+            queries: num_queries,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Region, RegionSet, equal_coverage_regions, read_bed};
+    use anyhow::Result;
+    use std::{fs, num::NonZero, path::PathBuf};
+    use tempfile::TempDir;
+
+    /// Test reading BED regions, skipping headers and comments, and rejecting bad intervals.
+    #[test]
+    fn test_read_bed() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let bed = temp_dir.path().join("regions.bed");
+        fs::write(
+            &bed,
+            "track name=test\n# comment\nchr1\t0\t1000\tfirst\n\nchr2\t500\t600\n",
+        )?;
+        let regions = read_bed(&bed)?;
+        assert_eq!(
+            regions,
+            vec![
+                Region {
+                    contig: "chr1".to_string(),
+                    start: 0,
+                    end: 1000
+                },
+                Region {
+                    contig: "chr2".to_string(),
+                    start: 500,
+                    end: 600
+                }
+            ]
+        );
+        assert_eq!(regions[1].to_string(), "chr2:501-600");
+        assert_eq!(
+            RegionSet::chunk_regions(&RegionSet::Bed(bed.clone()), "reads.bam", None)?.len(),
+            2
+        );
+        fs::write(&bed, "chr1\t100\t100\n")?;
+        assert!(read_bed(&bed).is_err());
+        fs::write(&bed, "chr1 0 100\n")?;
+        assert!(read_bed(&bed).is_err());
+        assert_eq!("auto".parse::<RegionSet>()?, RegionSet::Auto);
+        assert_eq!(
+            "a.bed".parse::<RegionSet>()?,
+            RegionSet::Bed(PathBuf::from("a.bed"))
+        );
+        Ok(())
+    }
+
+    /// Test that auto regions reject CRAM, whose CRAI has no read counts, with a clear error.
+    #[test]
+    fn test_auto_regions_reject_cram() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let cram = temp_dir.path().join("reads.cram");
+        fs::write(&cram, b"CRAM\x03\x00")?;
+        let err = equal_coverage_regions(&cram, NonZero::new(2).unwrap()).unwrap_err();
+        assert!(err.to_string().contains("is CRAM"), "{err}");
+        Ok(())
+    }
+}
@@ -0,0 +1,266 @@
+use anyhow::{Result, anyhow};
+use noodles_bgzf::{
+    VirtualPosition,
+    io::{Reader as NoodlesBgzfReader, Seek as NoodlesSeek},
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::File,
+    io::{BufRead, Read, Seek, SeekFrom},
+    path::Path,
+};
+
+/// First bytes of every BGZF block header: gzip magic, deflate, FEXTRA flag
+pub(crate) const BGZF_HEADER_START: [u8; 4] = [0x1f, 0x8b, 0x08, 0x04];
+
+/// Extra subfield identifying a BGZF block ("BC", length 2), at bytes 12..16 of the header
+pub(crate) const BGZF_EXTRA_SUBFIELD: [u8; 4] = [b'B', b'C', 0x02, 0x00];
+
+/// Number of raw bytes read at a time while scanning for the next block
+const SCAN_WINDOW: usize = 1 << 20;
+
+/// Number of uncompressed bytes past the candidate block to read, so that candidate records can be
+/// checked against the record that follows them
+const LOOKAHEAD: u64 = 1 << 20;
+
+/// Largest BAM record considered plausible while resynchronizing
+const MAX_BAM_BLOCK_SIZE: u32 = 1 << 26;
+
+/// Range of a file skipped after a corrupt record, as ChunkableRecordReader offsets.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq)]
+pub struct SkippedRange {
+    /// Offset of the corrupt record
+    pub start: u64,
+    /// Offset of the first record after resynchronizing, or None if no record was found before
+    /// the end of the file
+    pub resume: Option<u64>,
+}
+
+/// Record layout to look for when resynchronizing within decompressed BGZF data.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ResyncFormat {
+    Bam,
+    Fastq,
+}
+
+impl ResyncFormat {
+    /// Return true if a record plausibly starts at data[start..], and is followed by another
+    /// plausible record or the end of the data.
+    fn is_record_start(&self, data: &[u8], start: usize) -> bool {
+        match self {
+            ResyncFormat::Bam => bam_record_end(data, start)
+                .is_some_and(|end| end == data.len() || bam_record_end(data, end).is_some()),
+            ResyncFormat::Fastq => {
+                (start == 0 || data[start - 1] == b'\n')
+                    && fastq_record_end(data, start).is_some_and(|end| {
+                        end == data.len() || fastq_record_end(data, end).is_some()
+                    })
+            }
+        }
+    }
+}
+
+/// Read a little-endian u32 from data at start, if it is long enough
+fn read_u32(data: &[u8], start: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(
+        data.get(start..start + 4)?.try_into().ok()?,
+    ))
+}
+
+/// If a plausible BAM record starts at data[start..], return the offset just past it.
+fn bam_record_end(data: &[u8], start: usize) -> Option<usize> {
+    let block_size = read_u32(data, start)?;
+    if !(32..=MAX_BAM_BLOCK_SIZE).contains(&block_size) {
+        return None;
+    }
+    let fields = data.get(start + 4..start + 36)?;
+    let ref_id = i32::from_le_bytes(fields[0..4].try_into().ok()?);
+    let pos = i32::from_le_bytes(fields[4..8].try_into().ok()?);
+    let l_read_name = fields[8] as usize;
+    let n_cigar_op = u16::from_le_bytes(fields[12..14].try_into().ok()?) as usize;
+    let l_seq = u32::from_le_bytes(fields[16..20].try_into().ok()?) as usize;
+    let next_ref_id = i32::from_le_bytes(fields[20..24].try_into().ok()?);
+    let next_pos = i32::from_le_bytes(fields[24..28].try_into().ok()?);
+    if ref_id < -1 || next_ref_id < -1 || pos < -1 || next_pos < -1 || l_read_name < 2 {
+        return None;
+    }
+    let min_block_size = 32 + l_read_name + 4 * n_cigar_op + l_seq.div_ceil(2) + l_seq;
+    if (block_size as usize) < min_block_size {
+        return None;
+    }
+    let read_name = data.get(start + 36..start + 36 + l_read_name)?;
+    let (terminator, name) = read_name.split_last()?;
+    if *terminator != 0 || !name.iter().all(|c| c.is_ascii_graphic() && *c != b'@') {
+        return None;
+    }
+    Some(start + 4 + block_size as usize)
+}
+
+/// If a plausible FASTQ record starts at data[start..], return the offset just past it.
+fn fastq_record_end(data: &[u8], start: usize) -> Option<usize> {
+    let mut lines: [&[u8]; 4] = [&[]; 4];
+    let mut position = start;
+    for line in lines.iter_mut() {
+        let length = data.get(position..)?.iter().position(|&c| c == b'\n')?;
+        *line = &data[position..position + length];
+        position += length + 1;
+    }
+    let [name, sequence, separator, qualities] = lines;
+    let is_plausible = name.len() > 1
+        && name[0] == b'@'
+        && separator.first() == Some(&b'+')
+        && sequence.len() == qualities.len()
+        && sequence.iter().all(u8::is_ascii_alphabetic)
+        && qualities.iter().all(|c| (b'!'..=b'~').contains(c));
+    is_plausible.then_some(position)
+}
+
+/// Find the compressed offset of the first BGZF block starting after the block at
+/// compressed_offset, or None if there are no more blocks.
+fn next_block_offset(file: &mut File, compressed_offset: u64) -> Result<Option<u64>> {
+    let mut window_start = compressed_offset + 1;
+    let mut window = vec![0u8; SCAN_WINDOW];
+    loop {
+        file.seek(SeekFrom::Start(window_start))?;
+        let mut window_len = 0;
+        while window_len < window.len() {
+            let num_read = file.read(&mut window[window_len..])?;
+            if num_read == 0 {
+                break;
+            }
+            window_len += num_read;
+        }
+        let window = &window[..window_len];
+        if let Some(position) = window.windows(16).position(|header| {
+            header[..4] == BGZF_HEADER_START && header[12..16] == BGZF_EXTRA_SUBFIELD
+        }) {
+            return Ok(Some(window_start + position as u64));
+        }
+        if window_len < SCAN_WINDOW {
+            return Ok(None);
+        }
+        // overlap windows so that headers spanning the boundary are found
+        window_start += (window_len - 15) as u64;
+    }
+}
+
+/// Return true if the file at path starts with a BGZF block, so it can be resynchronized.
+pub fn is_bgzf<P>(path: P) -> Result<bool>
+where
+    P: AsRef<Path>,
+{
+    let mut file =
+        File::open(path.as_ref()).map_err(|err| anyhow!("Opening {:?}: {err}", path.as_ref()))?;
+    let mut header = [0u8; 16];
+    Ok(file.read_exact(&mut header).is_ok()
+        && header[..4] == BGZF_HEADER_START
+        && header[12..16] == BGZF_EXTRA_SUBFIELD)
+}
+
+/// List the BGZF blocks of the file at path, as (compressed offset, uncompressed length) pairs,
+/// reading only the block headers and footers. Assumes the "BC" subfield is the only extra field,
+/// as written by htslib and noodles.
+pub fn bgzf_blocks<P>(path: P) -> Result<Vec<(u64, u64)>>
+where
+    P: AsRef<Path>,
+{
+    let mut file =
+        File::open(path.as_ref()).map_err(|err| anyhow!("Opening {:?}: {err}", path.as_ref()))?;
+    let file_size = file.metadata()?.len();
+    let mut blocks: Vec<(u64, u64)> = Vec::new();
+    let mut compressed_offset = 0u64;
+    let mut header = [0u8; 18];
+    let mut footer = [0u8; 4];
+    while compressed_offset < file_size {
+        file.seek(SeekFrom::Start(compressed_offset))?;
+        file.read_exact(&mut header)?;
+        if header[..4] != BGZF_HEADER_START || header[12..16] != BGZF_EXTRA_SUBFIELD {
+            return Err(anyhow!(
+                "{:?} has no BGZF block at offset {compressed_offset}",
+                path.as_ref()
+            ));
+        }
+        let block_size = u16::from_le_bytes([header[16], header[17]]) as u64 + 1;
+        file.seek(SeekFrom::Start(compressed_offset + block_size - 4))?;
+        file.read_exact(&mut footer)?;
+        blocks.push((compressed_offset, u32::from_le_bytes(footer) as u64));
+        compressed_offset += block_size;
+    }
+    Ok(blocks)
+}
+
+/// After a corrupt record at virtual offset failed_offset in the BGZF file at path, find the
+/// virtual offset of the first plausible record in the following BGZF blocks. Returns None if no
+/// record is found before the end of the file.
+pub fn find_resync_offset<P>(
+    path: P,
+    failed_offset: u64,
+    format: ResyncFormat,
+) -> Result<Option<u64>>
+where
+    P: AsRef<Path>,
+{
+    let open = || {
+        File::open(path.as_ref())
+            .map_err(|err| anyhow!("Opening {:?} to resynchronize: {err}", path.as_ref()))
+    };
+    let mut file = open()?;
+    let mut reader = NoodlesBgzfReader::new(open()?);
+    let mut compressed_offset = VirtualPosition::from(failed_offset).compressed();
+    while let Some(block_offset) = next_block_offset(&mut file, compressed_offset)? {
+        compressed_offset = block_offset;
+        let block_start = VirtualPosition::try_from((block_offset, 0))
+            .map_err(|err| anyhow!("Block offset {block_offset} out of range: {err}"))?;
+        if reader.seek_to_virtual_position(block_start).is_err() {
+            continue;
+        }
+        // a corrupt block can't be decompressed, so move on to the next one
+        let Ok(block_len) = reader.fill_buf().map(|block| block.len()) else {
+            continue;
+        };
+        let mut data = Vec::new();
+        // errors in later blocks just shorten the lookahead
+        let _ = (&mut reader)
+            .take(block_len as u64 + LOOKAHEAD)
+            .read_to_end(&mut data);
+        if let Some(start) = (0..block_len).find(|&start| format.is_record_start(&data, start)) {
+            return Ok(Some(u64::from(VirtualPosition::try_from((
+                block_offset,
+                start as u16,
+            ))?)));
+        }
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ResyncFormat, find_resync_offset};
+    use anyhow::Result;
+    use noodles_bgzf::{VirtualPosition, io::Writer as NoodlesBgzfWriter};
+    use std::{fs::File, io::Write};
+    use tempfile::TempDir;
+
+    /// Test that resynchronizing a FASTQ skips the corrupt block, and starts at the first whole
+    /// record in the next one.
+    #[test]
+    fn test_find_resync_offset() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let path = temp_dir.path().join("reads.fastq.gz");
+        let mut writer = NoodlesBgzfWriter::new(File::create(&path)?);
+        writer.write_all(b"@r1\nACGT\n+\nIIII\n@r2\nAC")?;
+        writer.flush()?;
+        writer.write_all(b"GT\n+\nIIII\n@r3\nACGT\n+\nIIII\n")?;
+        writer.finish()?;
+
+        let resume = find_resync_offset(&path, 0, ResyncFormat::Fastq)?.unwrap();
+        let resume = VirtualPosition::from(resume);
+        assert!(resume.compressed() > 0);
+        assert_eq!(resume.uncompressed(), b"GT\n+\nIIII\n".len() as u16);
+        assert_eq!(
+            find_resync_offset(&path, resume.into(), ResyncFormat::Fastq)?,
+            None
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,74 @@
+use log::debug;
+use std::{
+    env,
+    hash::{BuildHasher, RandomState},
+};
+use xxhash_rust::xxh3::xxh3_64_with_seed;
+
+/// Seed of randomized features when no --seed is given
+pub const DEFAULT_SEED: u64 = 0;
+
+/// Environment variable seeding randomly generated test data, to reproduce a failing test
+pub const TEST_SEED_VARIABLE: &str = "SPLIT_READS_TEST_SEED";
+
+/// Features that make random (but seeded) choices. Each gets its own seed derived from --seed, so
+/// that their choices are independent of each other but all reproduced by the same --seed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RandomFeature {
+    /// Selecting query groups to keep when subsampling
+    Subsample,
+    /// Salting the hash that assigns query groups to collate buckets
+    CollateBuckets,
+    /// Generating test data
+    TestData,
+}
+
+impl RandomFeature {
+    /// Name hashed with the seed to derive this feature's seed
+    fn name(self) -> &'static str {
+        match self {
+            RandomFeature::Subsample => "subsample",
+            RandomFeature::CollateBuckets => "collate-buckets",
+            RandomFeature::TestData => "test-data",
+        }
+    }
+
+    /// Get the seed of this feature from the --seed option, or DEFAULT_SEED. Subsampling uses the
+    /// seed itself, so that queries selected with a --seed stay the same across releases.
+    pub fn seed(self, seed: Option<u64>) -> u64 {
+        let seed = seed.unwrap_or(DEFAULT_SEED);
+        match self {
+            RandomFeature::Subsample => seed,
+            _ => xxh3_64_with_seed(self.name().as_bytes(), seed),
+        }
+    }
+}
+
+/// Get the seed for generating test data, from SPLIT_READS_TEST_SEED if set, otherwise from a
+/// fresh random seed. The seed is logged at debug level, to reproduce a failing test.
+pub fn test_data_seed() -> u64 {
+    let seed = env::var(TEST_SEED_VARIABLE)
+        .ok()
+        .and_then(|seed| seed.parse().ok())
+        .unwrap_or_else(|| RandomState::new().hash_one(DEFAULT_SEED));
+    debug!("Generating test data with {TEST_SEED_VARIABLE}={seed}");
+    RandomFeature::TestData.seed(Some(seed))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DEFAULT_SEED, RandomFeature};
+
+    /// Test that feature seeds are pinned, independent of each other, and that subsampling uses
+    /// the seed itself.
+    #[test]
+    fn test_feature_seeds() {
+        assert_eq!(RandomFeature::Subsample.seed(None), DEFAULT_SEED);
+        assert_eq!(RandomFeature::Subsample.seed(Some(7)), 7);
+        let collate = RandomFeature::CollateBuckets.seed(Some(7));
+        assert_eq!(collate, RandomFeature::CollateBuckets.seed(Some(7)));
+        assert_ne!(collate, RandomFeature::CollateBuckets.seed(Some(8)));
+        assert_ne!(collate, RandomFeature::TestData.seed(Some(7)));
+        assert_ne!(collate, 7);
+    }
+}
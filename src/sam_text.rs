@@ -0,0 +1,467 @@
+use crate::{
+    chunkable::{ChunkableRecordReader, OffsetKind, OffsetKindSource, SplitRange},
+    error_category::{Categorize, ErrorCategory},
+    fastq::MAX_LINE_LENGTH,
+    maybe_compressed_io::MaybeCompressedReader,
+    resync::SkippedRange,
+    seekable_split::Split,
+};
+use anyhow::{Result, anyhow};
+use rust_htslib::{
+    bam::{HeaderView, Read as BamRead, Reader as BamReader, Record as BamRecord},
+    errors::Error as HtslibError,
+    htslib,
+};
+use std::{
+    ffi::CString,
+    io::{BufRead, Seek, SeekFrom},
+};
+
+/// Reader for plain-text SAM that splits lines itself (as FastqReader does) and parses each with
+/// htslib's SAM parser. Unlike htslib's reader, its offsets into uncompressed SAM are plain byte
+/// offsets, which are cheap to tell and seek to; offsets into BGZF-compressed SAM are virtual
+/// offsets, as htslib's are. The header is read when the reader is created.
+pub struct SamTextReader<R: BufRead> {
+    split: Split<R>,
+    header: HeaderView,
+    /// Current line, NUL-terminated while it is parsed
+    line: Vec<u8>,
+}
+
+// SAFETY: the HeaderView is owned by the SamTextReader and only read, never shared, so it is never
+// accessed from two threads at once.
+unsafe impl<R: BufRead + Send> Send for SamTextReader<R> {}
+
+impl<R: BufRead> SamTextReader<R> {
+    /// Create a new SamTextReader, reading the header lines from the start of reader.
+    pub fn new(mut reader: R) -> Result<Self> {
+        let mut header_text = Vec::new();
+        while reader.fill_buf()?.first() == Some(&b'@') {
+            reader.read_until(b'\n', &mut header_text)?;
+            if header_text.last() != Some(&b'\n') {
+                header_text.push(b'\n');
+            }
+        }
+        let header_len = header_text.len() as u64;
+        Ok(SamTextReader {
+            split: Split::new(reader, b'\n')
+                .with_max_len(MAX_LINE_LENGTH)
+                .with_position(header_len),
+            header: parse_header(header_text)?,
+            line: Vec::new(),
+        })
+    }
+
+    /// Get the header read from the start of the SAM.
+    pub fn header(&self) -> &HeaderView {
+        &self.header
+    }
+
+    /// Get a reference to the underlying reader.
+    pub fn get_ref(&self) -> &R {
+        self.split.get_ref()
+    }
+
+    /// Read the next SAM record into an existing record. Return None if there are no more
+    /// records. Lines that can't be parsed yield htslib's invalid record error, so that lenient
+    /// validation can skip them as it does for htslib's reader.
+    pub fn read_record_into(&mut self, record: &mut BamRecord) -> Option<Result<()>> {
+        loop {
+            match self.split.read_next_into(&mut self.line) {
+                Ok(false) => return None,
+                Err(err) => return Some(Err(anyhow!("{err}"))),
+                Ok(true) => {
+                    if self.line.last() == Some(&b'\r') {
+                        self.line.pop();
+                    }
+                    if !self.line.is_empty() {
+                        return Some(self.parse_line(record));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Parse the current line into record
+    fn parse_line(&mut self, record: &mut BamRecord) -> Result<()> {
+        let len = self.line.len();
+        self.line.push(0);
+        let mut text = htslib::kstring_t {
+            l: len,
+            m: self.line.len(),
+            s: self.line.as_mut_ptr().cast(),
+        };
+        // SAFETY: text is a NUL-terminated buffer owned by self.line, which htslib doesn't free or
+        // grow, and the header and record are valid htslib structs.
+        let status = unsafe {
+            htslib::sam_parse1(
+                &mut text,
+                self.header.inner_ptr() as *mut htslib::bam_hdr_t,
+                record.inner_mut(),
+            )
+        };
+        if status == 0 {
+            Ok(())
+        } else {
+            // htslib splits fields by writing NULs over tabs, so the query name ends at either
+            let qname = self.line.split(|&c| c == b'\t' || c == 0).next();
+            Err(
+                anyhow::Error::new(HtslibError::BamInvalidRecord).context(format!(
+                    "Invalid SAM record {}",
+                    String::from_utf8_lossy(qname.unwrap_or_default())
+                )),
+            )
+        }
+    }
+}
+
+/// Parse SAM header text, erroring rather than crashing if htslib rejects it.
+fn parse_header(header_text: Vec<u8>) -> Result<HeaderView> {
+    let header_text = CString::new(header_text)?;
+    let len = header_text.as_bytes().len();
+    // SAFETY: header_text is NUL-terminated, and htslib copies the lines it parses.
+    let inner = unsafe { htslib::sam_hdr_parse(len, header_text.as_ptr()) };
+    if inner.is_null() {
+        Err(anyhow!("Invalid SAM header"))
+    } else {
+        Ok(HeaderView::new(inner))
+    }
+}
+
+/// Implement ChunkableRecordReader trait for native SAM readers.
+impl<R: BufRead + Seek + OffsetKindSource> ChunkableRecordReader<BamRecord> for SamTextReader<R> {
+    fn tell(&mut self) -> Result<u64> {
+        Ok(self.split.stream_position()?)
+    }
+    fn tell_uncompressed(&mut self) -> Result<Option<u64>> {
+        Ok(self.split.position())
+    }
+    fn offset_kind(&self) -> OffsetKind {
+        self.get_ref().offset_kind()
+    }
+    fn seek(&mut self, offset: u64) -> Result<()> {
+        self.split.seek(SeekFrom::Start(offset))?;
+        Ok(())
+    }
+
+    /// Seek to the start of an index bin. Indices of plain-text SAM that don't record their offset
+    /// kind were built with htslib's reader, whose offsets into plain SAM are virtual offsets
+    /// rather than this reader's byte offsets, so they are rejected rather than trusted.
+    fn seek_to_range(&mut self, split_range: &SplitRange) -> Result<()> {
+        if self.offset_kind() == OffsetKind::Byte && split_range.offset_kind.is_none() {
+            return Err(anyhow!(
+                "Index of plain-text SAM does not record its offset kind, so it was built by an \
+                older version with incompatible offsets. Re-index the SAM."
+            ))
+            .categorize(ErrorCategory::BadIndex);
+        }
+        self.offset_kind().check_seekable(split_range.offset_kind)?;
+        self.seek(split_range.offset)
+    }
+
+    fn read_into(&mut self, record: &mut BamRecord) -> Option<Result<()>> {
+        self.read_record_into(record)
+    }
+}
+
+/// Offsets of records in a SAM copy of BAM/CRAM reads (e.g. written by `samtools view -h`),
+/// measured by formatting each record as SAM.
+struct SamMirror {
+    header: HeaderView,
+    /// Offset of the next record in the SAM copy
+    offset: u64,
+    /// Buffer htslib formats each record into, grown (and allocated) by htslib
+    text: htslib::kstring_t,
+}
+
+impl SamMirror {
+    /// Start measuring at the first record after the header
+    fn new(header: &HeaderView) -> Self {
+        let header = header.clone();
+        // SAFETY: the header is a valid htslib header
+        let header_len = unsafe { htslib::sam_hdr_length(header.inner_ptr() as *mut _) };
+        SamMirror {
+            header,
+            offset: header_len as u64,
+            text: htslib::kstring_t {
+                l: 0,
+                m: 0,
+                s: std::ptr::null_mut(),
+            },
+        }
+    }
+
+    /// Advance past record, the next record of the reads
+    fn advance(&mut self, record: &BamRecord) -> Result<()> {
+        // SAFETY: the header and record are valid htslib structs, and text is empty or was
+        // allocated by htslib, which grows it as needed
+        let len =
+            unsafe { htslib::sam_format1(self.header.inner_ptr(), record.inner(), &mut self.text) };
+        if len < 0 {
+            return Err(anyhow!(
+                "Could not format record {} as SAM",
+                String::from_utf8_lossy(record.qname())
+            ));
+        }
+        // the line and its newline
+        self.offset += len as u64 + 1;
+        Ok(())
+    }
+}
+
+impl Drop for SamMirror {
+    fn drop(&mut self) {
+        // SAFETY: text.s is NULL or was allocated by htslib, and is not used again
+        unsafe { htslib::free(self.text.s.cast()) };
+    }
+}
+
+/// Reader of BAM/CRAM records that can report, as its uncompressed offsets, where each record
+/// would start in a SAM copy of the reads (e.g. written by `samtools view -h`). An index built
+/// through it can then extract chunks from such a SAM mirror, even if its header differs (see
+/// SplitIndex::into_uncompressed). Every record is formatted as SAM to measure it,
+/// which costs about as much as writing the mirror, so without a header it just passes records
+/// through.
+pub struct SamMirrorReader<Reader> {
+    inner: Reader,
+    mirror: Option<SamMirror>,
+}
+
+// SAFETY: the SamMirror's header and text buffer are owned by the SamMirrorReader and never
+// shared, so they are never accessed from two threads at once.
+unsafe impl<Reader: Send> Send for SamMirrorReader<Reader> {}
+
+impl<Reader> SamMirrorReader<Reader> {
+    /// Create a new SamMirrorReader wrapping a reader positioned at its first record. Measure SAM
+    /// offsets if given the header of the reads.
+    pub fn new(inner: Reader, header: Option<&HeaderView>) -> Self {
+        SamMirrorReader {
+            inner,
+            mirror: header.map(SamMirror::new),
+        }
+    }
+}
+
+/// Implement ChunkableRecordReader for SamMirrorReader, measuring each record as SAM after reading
+/// it. Seeking would lose track of the SAM offsets, so it is an error while measuring.
+impl<Reader> ChunkableRecordReader<BamRecord> for SamMirrorReader<Reader>
+where
+    Reader: ChunkableRecordReader<BamRecord>,
+{
+    fn tell(&mut self) -> Result<u64> {
+        self.inner.tell()
+    }
+
+    fn tell_uncompressed(&mut self) -> Result<Option<u64>> {
+        match self.mirror {
+            Some(ref mirror) => Ok(Some(mirror.offset)),
+            None => self.inner.tell_uncompressed(),
+        }
+    }
+
+    fn offset_kind(&self) -> OffsetKind {
+        self.inner.offset_kind()
+    }
+
+    fn seek(&mut self, offset: u64) -> Result<()> {
+        if self.mirror.is_some() {
+            return Err(anyhow!(
+                "Cannot seek while measuring offsets of a SAM mirror."
+            ));
+        }
+        self.inner.seek(offset)
+    }
+
+    fn seek_to_range(&mut self, split_range: &SplitRange) -> Result<()> {
+        if self.mirror.is_some() {
+            return Err(anyhow!(
+                "Cannot seek while measuring offsets of a SAM mirror."
+            ));
+        }
+        self.inner.seek_to_range(split_range)
+    }
+
+    fn read_into(&mut self, record: &mut BamRecord) -> Option<Result<()>> {
+        let result = self.inner.read_into(record);
+        if let (Some(Ok(())), Some(mirror)) = (&result, self.mirror.as_mut())
+            && let Err(err) = mirror.advance(record)
+        {
+            return Some(Err(err));
+        }
+        result
+    }
+
+    fn take_skipped_ranges(&mut self) -> Vec<SkippedRange> {
+        self.inner.take_skipped_ranges()
+    }
+}
+
+/// Reader of SAM/BAM/CRAM records: plain-text SAM files are read by SamTextReader, everything
+/// else by htslib.
+pub enum SamReader {
+    Htslib(BamReader),
+    /// Boxed, as it is much larger than htslib's reader, which keeps its state on the heap
+    Text(Box<SamTextReader<MaybeCompressedReader>>),
+}
+
+impl SamReader {
+    /// Get the header of the reads.
+    pub fn header(&self) -> &HeaderView {
+        match self {
+            SamReader::Htslib(reader) => reader.header(),
+            SamReader::Text(reader) => reader.header(),
+        }
+    }
+}
+
+/// Implement ChunkableRecordReader for SamReader, delegating to the reader in use.
+impl ChunkableRecordReader<BamRecord> for SamReader {
+    fn tell(&mut self) -> Result<u64> {
+        match self {
+            SamReader::Htslib(reader) => ChunkableRecordReader::tell(reader),
+            SamReader::Text(reader) => reader.tell(),
+        }
+    }
+    fn tell_uncompressed(&mut self) -> Result<Option<u64>> {
+        match self {
+            SamReader::Htslib(reader) => reader.tell_uncompressed(),
+            SamReader::Text(reader) => reader.tell_uncompressed(),
+        }
+    }
+    fn offset_kind(&self) -> OffsetKind {
+        match self {
+            SamReader::Htslib(reader) => reader.offset_kind(),
+            SamReader::Text(reader) => reader.offset_kind(),
+        }
+    }
+    fn seek(&mut self, offset: u64) -> Result<()> {
+        match self {
+            SamReader::Htslib(reader) => ChunkableRecordReader::seek(reader, offset),
+            SamReader::Text(reader) => reader.seek(offset),
+        }
+    }
+    fn seek_to_range(&mut self, split_range: &SplitRange) -> Result<()> {
+        match self {
+            SamReader::Htslib(reader) => reader.seek_to_range(split_range),
+            SamReader::Text(reader) => reader.seek_to_range(split_range),
+        }
+    }
+
+    fn read_into(&mut self, record: &mut BamRecord) -> Option<Result<()>> {
+        match self {
+            SamReader::Htslib(reader) => reader.read_into(record),
+            SamReader::Text(reader) => reader.read_into(record),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SamTextReader;
+    use crate::{
+        chunkable::{ChunkableRecordReader, GroupBy, OffsetKind, SplitRange},
+        error_category::ErrorCategory,
+        progress::ProgressReader,
+        record_tags::TaggingReader,
+        validation::{ValidatingReader, ValidationLevel},
+    };
+    use anyhow::Result;
+    use rust_htslib::bam::{Record, record::Aux};
+    use std::io::Cursor;
+
+    /// Test reading the header and records, that offsets are byte offsets of the records, and
+    /// that seeking to an offset reads the record there.
+    #[test]
+    fn test_sam_text_reader() -> Result<()> {
+        let header = "@HD\tVN:1.6\tSO:unsorted\n@SQ\tSN:chr1\tLN:1000\n";
+        let records = [
+            "read1\t77\t*\t0\t0\t*\t*\t0\t0\tACGT\tIIII\tCB:Z:AAAC\n",
+            "read1\t141\t*\t0\t0\t*\t*\t0\t0\tTTGA\t####\n",
+            "read2\t0\tchr1\t100\t60\t4M\t*\t0\t0\tGGCC\t*\r\n",
+        ];
+        let sam = format!("{header}{}\n", records.concat());
+        let mut reader = SamTextReader::new(Cursor::new(sam.clone()))?;
+        assert_eq!(reader.header().target_names(), vec![b"chr1"]);
+        assert_eq!(reader.offset_kind(), OffsetKind::Byte);
+
+        let mut record = Record::new();
+        let mut offsets = Vec::new();
+        let mut qnames = Vec::new();
+        loop {
+            let offset = reader.tell()?;
+            assert_eq!(reader.tell_uncompressed()?, Some(offset));
+            match reader.read_into(&mut record) {
+                None => break,
+                Some(result) => result?,
+            }
+            offsets.push(offset);
+            qnames.push(record.qname().to_vec());
+        }
+        assert_eq!(qnames, [b"read1", b"read1", b"read2"]);
+        assert_eq!(offsets[0], header.len() as u64);
+        assert_eq!(
+            offsets[2],
+            (header.len() + records[0].len() + records[1].len()) as u64
+        );
+
+        reader.seek(offsets[0])?;
+        reader.read_into(&mut record).unwrap()?;
+        assert_eq!(record.seq().as_bytes(), b"ACGT");
+        assert_eq!(record.aux(b"CB")?, Aux::String("AAAC"));
+        reader.seek(offsets[2])?;
+        reader.read_into(&mut record).unwrap()?;
+        assert_eq!(record.pos(), 99);
+        assert_eq!(record.seq_len(), 4);
+
+        let bad_sam = format!("{header}read3\tnot-a-flag\n");
+        let mut reader = SamTextReader::new(Cursor::new(bad_sam))?;
+        let err = reader.read_into(&mut record).unwrap().unwrap_err();
+        assert_eq!(err.to_string(), "Invalid SAM record read3");
+        assert!(SamTextReader::new(Cursor::new("@SQ\tLN:1000\n")).is_err());
+        Ok(())
+    }
+
+    /// Test that seeking to an index bin needs byte offsets: indices without an offset kind,
+    /// built with htslib's virtual offsets, and indices of virtual offsets are rejected, also
+    /// through the readers get-chunk wraps around it.
+    #[test]
+    fn test_seek_to_range() -> Result<()> {
+        let header = "@HD\tVN:1.6\n";
+        let sam = format!("{header}read1\t4\t*\t0\t0\t*\t*\t0\t0\tACGT\tIIII\n");
+        let mut reader = SamTextReader::new(Cursor::new(sam))?;
+        let split_range = |offset_kind: Option<OffsetKind>| SplitRange {
+            offset: header.len() as u64,
+            offset_kind,
+            num_previous_queries: 0,
+            num_end_queries: 1,
+            num_previous_reads: 0,
+            num_end_reads: 1,
+            group_by: GroupBy::QueryName,
+            first_record_hash: None,
+        };
+        reader.seek_to_range(&split_range(Some(OffsetKind::Byte)))?;
+        let mut record = Record::new();
+        reader.read_into(&mut record).unwrap()?;
+        assert_eq!(record.qname(), b"read1");
+
+        let err = reader.seek_to_range(&split_range(None)).unwrap_err();
+        assert!(err.to_string().contains("Re-index"), "{err}");
+        assert_eq!(ErrorCategory::of(&err), ErrorCategory::BadIndex);
+        assert!(
+            reader
+                .seek_to_range(&split_range(Some(OffsetKind::Virtual)))
+                .is_err()
+        );
+
+        let mut wrapped = ProgressReader::new(
+            TaggingReader::new(
+                ValidatingReader::new(reader, ValidationLevel::Strict),
+                Vec::new(),
+            ),
+            None,
+        );
+        let err = wrapped.seek_to_range(&split_range(None)).unwrap_err();
+        assert!(err.to_string().contains("Re-index"), "{err}");
+        Ok(())
+    }
+}
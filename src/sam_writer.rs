@@ -0,0 +1,188 @@
+use crate::{
+    atomic_output::AtomicWriter, chunkable::ChunkableRecordWriter, reference_cache::share_reference,
+};
+use anyhow::{Result, anyhow};
+use rust_htslib::{
+    bam::{Format, Header, HeaderView, Record},
+    htslib,
+};
+use std::{ffi::CString, path::Path, ptr};
+
+/// htslib min_shift that selects a BAI index (rather than CSI)
+const BAI_MIN_SHIFT: i32 = 0;
+
+/// SAM/BAM/CRAM writer opened directly through htslib. Unlike rust_htslib's Writer, htslib format
+/// options (e.g. CRAM "version=3.1" or "lossy_names=1") can be applied after opening the file and
+/// before the header is written, which is when htslib requires them.
+pub struct SamWriter {
+    hts_file: *mut htslib::htsFile,
+    header: HeaderView,
+    /// Path of the index being built while writing, if any. htslib keeps a pointer to it until the
+    /// index is saved, so it must outlive that.
+    index_path: Option<CString>,
+}
+
+// SAFETY: the htsFile and header are owned by the SamWriter and only used through &mut self, so
+// they are never accessed from two threads at once.
+unsafe impl Send for SamWriter {}
+
+impl SamWriter {
+    /// Open output (a file path, URL, or "-" for stdout) to write in the given format, set the
+    /// reference FASTA if supplied, apply each htslib format option (as "key=value", in order),
+    /// then write the header. CRAM output shares the decoded reference sequences with other CRAM
+    /// readers and writers of the same reference (see reference_cache).
+    pub fn new<P>(
+        output: &str,
+        format: Format,
+        header: &Header,
+        reference_fasta: Option<P>,
+        options: &[String],
+    ) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let mode = match format {
+            Format::Sam => c"w",
+            Format::Bam => c"wb",
+            Format::Cram => c"wc",
+        };
+        let c_output = CString::new(output)?;
+        // SAFETY: both arguments are valid NUL-terminated strings
+        let hts_file = unsafe { htslib::hts_open(c_output.as_ptr(), mode.as_ptr()) };
+        if hts_file.is_null() {
+            return Err(anyhow!("Opening {output} for writing."));
+        }
+        // from here on, dropping the writer closes the file
+        let writer = SamWriter {
+            hts_file,
+            header: HeaderView::from_header(header),
+            index_path: None,
+        };
+        if let Some(fasta) = &reference_fasta {
+            let fasta = fasta.as_ref();
+            let c_fasta = CString::new(
+                fasta
+                    .to_str()
+                    .ok_or_else(|| anyhow!("Reference path {fasta:?} is not valid UTF-8."))?,
+            )?;
+            // SAFETY: hts_file is a valid open htsFile, and c_fasta is NUL-terminated
+            if unsafe { htslib::hts_set_fai_filename(writer.hts_file, c_fasta.as_ptr()) } != 0 {
+                return Err(anyhow!("Setting reference {fasta:?} for {output}."));
+            }
+        }
+        writer.apply_options(options)?;
+        // SAFETY: hts_file is a valid open htsFile, and header is a valid header
+        if unsafe { htslib::sam_hdr_write(writer.hts_file, writer.header.inner_ptr()) } != 0 {
+            return Err(anyhow!("Writing header to {output}."));
+        }
+        if let Some(fasta) = reference_fasta {
+            // SAFETY: hts_file is a valid open htsFile, whose header was just written
+            unsafe { share_reference(writer.hts_file, &writer.header, fasta.as_ref())? };
+        }
+        Ok(writer)
+    }
+
+    /// Apply htslib format options, each "key=value".
+    fn apply_options(&self, options: &[String]) -> Result<()> {
+        let mut hts_options: *mut htslib::hts_opt = ptr::null_mut();
+        let mut result = Ok(());
+        for option in options {
+            let c_option = CString::new(option.as_str())?;
+            // SAFETY: hts_options is a valid (possibly empty) option list, and c_option is
+            // NUL-terminated
+            if unsafe { htslib::hts_opt_add(&mut hts_options, c_option.as_ptr()) } != 0 {
+                result = Err(anyhow!(
+                    "Unknown or malformed htslib format option {option:?}."
+                ));
+                break;
+            }
+        }
+        // SAFETY: hts_file is a valid open htsFile, and hts_options a valid option list
+        if result.is_ok() && unsafe { htslib::hts_opt_apply(self.hts_file, hts_options) } != 0 {
+            result = Err(anyhow!("Applying htslib format options {options:?}."));
+        }
+        // SAFETY: hts_options is a valid option list, and is not used after this
+        unsafe { htslib::hts_opt_free(hts_options) };
+        result
+    }
+
+    /// Build a BAI index at index_path while writing, instead of indexing the output afterwards.
+    /// Must be called before any record is written, and records must then be written in
+    /// coordinate order. The index is written by finish() (or when the writer is dropped).
+    pub fn build_index(&mut self, index_path: &str) -> Result<()> {
+        let c_index_path = CString::new(index_path)?;
+        // SAFETY: hts_file is a valid open htsFile, header is a valid header, and c_index_path is
+        // NUL-terminated and kept alive in self.index_path until the index is saved
+        if unsafe {
+            htslib::sam_idx_init(
+                self.hts_file,
+                self.header.inner_ptr() as *mut htslib::sam_hdr_t,
+                BAI_MIN_SHIFT,
+                c_index_path.as_ptr(),
+            )
+        } != 0
+        {
+            return Err(anyhow!("Starting index {index_path}."));
+        }
+        self.index_path = Some(c_index_path);
+        Ok(())
+    }
+
+    /// Write the index being built, if any.
+    fn save_index(&mut self) -> Result<()> {
+        if let Some(index_path) = self.index_path.take() {
+            // SAFETY: hts_file is a valid open htsFile with an index initialized by build_index()
+            if unsafe { htslib::sam_idx_save(self.hts_file) } != 0 {
+                return Err(anyhow!("Writing index {index_path:?}."));
+            }
+        }
+        Ok(())
+    }
+
+    /// Finish writing: write the index being built, if any, and close the file. Unlike dropping
+    /// the writer, reports errors writing the index.
+    pub fn finish(mut self) -> Result<()> {
+        self.save_index()
+    }
+
+    /// Get the header of records being written.
+    pub fn header(&self) -> &HeaderView {
+        &self.header
+    }
+
+    /// Write one record.
+    pub fn write(&mut self, record: &Record) -> Result<()> {
+        // SAFETY: hts_file is a valid open htsFile, and header and record are valid
+        if unsafe { htslib::sam_write1(self.hts_file, self.header.inner_ptr(), &record.inner) } < 0
+        {
+            return Err(anyhow!(
+                "Writing record {}.",
+                String::from_utf8_lossy(record.qname())
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl AtomicWriter<SamWriter> {
+    /// Finish writing as SamWriter::finish does, then rename the output onto its path.
+    pub fn finish(self) -> Result<()> {
+        self.commit_with(SamWriter::finish)
+    }
+}
+
+impl Drop for SamWriter {
+    fn drop(&mut self) {
+        // errors can't be reported from drop; finish() reports them
+        let _ = self.save_index();
+        // SAFETY: hts_file is a valid open htsFile, and is not used after this
+        unsafe { htslib::hts_close(self.hts_file) };
+    }
+}
+
+/// Implement ChunkableRecordWriter trait for SamWriter.
+impl ChunkableRecordWriter<Record> for SamWriter {
+    fn write(&mut self, record: &Record) -> Result<()> {
+        SamWriter::write(self, record)
+    }
+}
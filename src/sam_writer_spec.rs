@@ -1,50 +1,112 @@
-use crate::path_type::PathType;
-use anyhow::{Result, anyhow};
-use rust_htslib::bam::{CompressionLevel, Format, Header, Read, Reader, Writer};
-use std::{num::NonZero, path::Path};
+use crate::{
+    atomic_output::AtomicWriter,
+    output_spec::OutputSpec,
+    path_type::PathType,
+    sam_writer::SamWriter,
+    util::{RecordType, configure_remote_output, is_coordinate_sorted, is_unaligned},
+};
+use anyhow::{Error, Result, anyhow};
+use rust_htslib::bam::{Format, Header, HeaderView, Read, Reader};
+use std::{fmt, num::NonZero, path::Path, str::FromStr};
 
-/// Convert a format string to an htslib Format enum.
-///
-/// # Arguments
-/// * `format_str` - The format string ("bam", "cram", or "sam")
-///
-/// # Errors
-/// Returns an error if the format string is not recognized.
-fn str_to_format(format_str: &str) -> Result<Format> {
-    match format_str.to_ascii_lowercase().as_str() {
-        "bam" => Ok(Format::Bam),
-        "cram" => Ok(Format::Cram),
-        "sam" => Ok(Format::Sam),
-        _ => Err(anyhow!("Unknown Sam format: {format_str}")),
+/// Default compression level for unaligned (uBAM) output. Unaligned reads are usually an
+/// intermediate on the way to an aligner, so favor speed over size.
+const UNALIGNED_COMPRESSION_LEVEL: u32 = 1;
+
+/// Output format of reads, for choosing a writer. Parsed from the command line (e.g. "-O bam")
+/// as well as used to configure a SamWriterSpec, so that bad format strings are rejected when
+/// arguments are parsed rather than when a writer is created.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OutputFormat {
+    Sam,
+    Bam,
+    Cram,
+    Fastq,
+}
+
+impl OutputFormat {
+    /// Get the output format from a path extension (.sam, .bam, .cram, .fastq, ...), or None if
+    /// the extension isn't recognized. Errors if the extensions can't be written (see
+    /// OutputSpec::from_path).
+    pub fn from_path<P>(path: P) -> Result<Option<Self>>
+    where
+        P: AsRef<Path>,
+    {
+        Ok(OutputSpec::from_path(path)?.map(|output_spec| output_spec.record_type.into()))
+    }
+
+    /// Get the htslib format, or None for FASTQ.
+    pub fn sam_format(self) -> Option<Format> {
+        RecordType::from(self).sam_format()
     }
 }
 
-/// Get the appropriate output format from the specified output path.
-///
-/// If the output path has a recognized extension (.bam, .cram, .sam), uses that format.
-/// Otherwise falls back to the provided default format string.
-///
-/// # Arguments
-/// * `output` - The output path
-/// * `default_format` - The default format string to use if extension is not recognized
-///
-/// # Errors
-/// Returns an error if neither the extension nor default format is recognized.
-pub fn get_format<P>(output: P, default_format: String) -> Result<Format>
-where
-    P: AsRef<Path>,
-{
-    if let Some(extension) = output.as_ref().extension() {
-        if let Some(extension_str) = extension.to_str() {
-            str_to_format(extension_str).or_else(|_| str_to_format(&default_format))
-        } else {
-            str_to_format(&default_format)
+impl From<RecordType> for OutputFormat {
+    fn from(record_type: RecordType) -> Self {
+        match record_type {
+            RecordType::Sam => OutputFormat::Sam,
+            RecordType::Bam => OutputFormat::Bam,
+            RecordType::Cram => OutputFormat::Cram,
+            RecordType::Fastq => OutputFormat::Fastq,
         }
-    } else {
-        str_to_format(&default_format)
     }
 }
 
+impl From<OutputFormat> for RecordType {
+    fn from(output_format: OutputFormat) -> Self {
+        match output_format {
+            OutputFormat::Sam => RecordType::Sam,
+            OutputFormat::Bam => RecordType::Bam,
+            OutputFormat::Cram => RecordType::Cram,
+            OutputFormat::Fastq => RecordType::Fastq,
+        }
+    }
+}
+
+impl fmt::Display for OutputFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        RecordType::from(*self).fmt(f)
+    }
+}
+
+/// CRAM format version to write. htslib writes 3.0 by default; 3.1 adds newer codecs that
+/// compress better (especially for archival), but older readers can't decode it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CramVersion {
+    V2_1,
+    V3_0,
+    V3_1,
+}
+
+impl FromStr for CramVersion {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "2.1" => Ok(CramVersion::V2_1),
+            "3.0" | "3" => Ok(CramVersion::V3_0),
+            "3.1" => Ok(CramVersion::V3_1),
+            _ => Err(anyhow!(
+                "Unsupported CRAM version {s:?}, expected 2.1, 3.0, or 3.1."
+            )),
+        }
+    }
+}
+
+impl fmt::Display for CramVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CramVersion::V2_1 => write!(f, "2.1"),
+            CramVersion::V3_0 => write!(f, "3.0"),
+            CramVersion::V3_1 => write!(f, "3.1"),
+        }
+    }
+}
+
+/// htslib format options that let CRAM be written without a reference FASTA: "no_ref" stores
+/// bases verbatim, and "embed_ref=2" embeds a reference generated from the reads.
+const NO_REFERENCE_OPTIONS: [&str; 3] = ["no_ref", "no_ref=1", "embed_ref=2"];
+
 /// Options for configuring a SAM/BAM/CRAM writer.
 ///
 /// This builder-style struct allows setting optional parameters for writing SAM/BAM/CRAM files.
@@ -56,6 +118,14 @@ pub struct SamWriterOptions<P> {
     threads: Option<NonZero<usize>>,
     /// Compression level (0-9)
     compression: Option<u32>,
+    /// CRAM format version
+    cram_version: Option<CramVersion>,
+    /// Additional htslib format options, each "key=value" (like samtools --output-fmt-option)
+    format_options: Vec<String>,
+    /// Build a BAI index alongside the output while writing
+    write_bai: bool,
+    /// Path the BAI is named for, if not the output (e.g. when the output is a pipe to it)
+    bai_output: Option<P>,
 }
 
 /// Builder for creating a SAM/BAM/CRAM writer with custom configuration.
@@ -68,7 +138,7 @@ pub struct SamWriterSpec<P> {
     /// SAM/BAM/CRAM header (required for writer creation)
     header: Option<Header>,
     /// Output format (required for writer creation)
-    format: Option<Format>,
+    format: Option<OutputFormat>,
     /// Additional writer options that may remain unspecified
     options: SamWriterOptions<P>,
 }
@@ -83,6 +153,10 @@ where
             reference_fasta: None,
             threads: None,
             compression: None,
+            cram_version: None,
+            format_options: Vec::new(),
+            write_bai: false,
+            bai_output: None,
         }
     }
 
@@ -103,6 +177,30 @@ where
         self.compression = Some(compression);
         self
     }
+
+    /// Set the CRAM format version.
+    pub fn cram_version(&mut self, cram_version: CramVersion) -> &mut Self {
+        self.cram_version = Some(cram_version);
+        self
+    }
+
+    /// Add an htslib format option, e.g. "lossy_names=1" or "embed_ref=1".
+    pub fn format_option(&mut self, format_option: String) -> &mut Self {
+        self.format_options.push(format_option);
+        self
+    }
+
+    /// Set whether to build a BAI index alongside the output while writing.
+    pub fn write_bai(&mut self, write_bai: bool) -> &mut Self {
+        self.write_bai = write_bai;
+        self
+    }
+
+    /// Name the BAI for bai_output instead of the output.
+    pub fn bai_output(&mut self, bai_output: P) -> &mut Self {
+        self.bai_output = Some(bai_output);
+        self
+    }
 }
 
 impl<P> Default for SamWriterOptions<P>
@@ -136,18 +234,25 @@ where
 
     /// Set the header by copying from an existing SAM/BAM/CRAM reader.
     pub fn header_from_reader(&mut self, reader: &Reader) -> &mut Self {
-        self.header(Header::from_template(reader.header()))
+        self.header_from_view(reader.header())
+    }
+
+    /// Set the header by copying from the header of SAM/BAM/CRAM reads.
+    pub fn header_from_view(&mut self, header_view: &HeaderView) -> &mut Self {
+        self.header(Header::from_template(header_view))
     }
 
     /// Set the output format (BAM, CRAM, or SAM).
-    pub fn format(&mut self, format: Format) -> &mut Self {
+    pub fn format(&mut self, format: OutputFormat) -> &mut Self {
         self.format = Some(format);
         self
     }
 
-    /// Set the output format based on the output path, with a fallback default format.
-    pub fn format_from_path_or_default(&mut self, default: String) -> Result<&mut Self> {
-        Ok(self.format(get_format(self.output.clone(), default)?))
+    /// Set the output format from the output path extension, falling back to default if the
+    /// extension isn't recognized. Errors if the extensions can't be written.
+    pub fn format_from_path_or_default(&mut self, default: OutputFormat) -> Result<&mut Self> {
+        let format = OutputFormat::from_path(self.output.as_ref())?.unwrap_or(default);
+        Ok(self.format(format))
     }
 
     /// Set the reference FASTA file path (required for CRAM format).
@@ -172,40 +277,297 @@ where
         self
     }
 
-    /// Create and return a configured SAM/BAM/CRAM writer.
+    /// Set the CRAM format version. Only valid for CRAM output.
+    pub fn cram_version(&mut self, cram_version: Option<CramVersion>) -> &mut Self {
+        if let Some(version) = cram_version {
+            self.options.cram_version(version);
+        }
+        self
+    }
+
+    /// Add htslib format options, each "key=value", e.g. "lossy_names=1" or "embed_ref=1".
+    pub fn format_options(&mut self, format_options: &[String]) -> &mut Self {
+        for format_option in format_options {
+            self.options.format_option(format_option.clone());
+        }
+        self
+    }
+
+    /// Set whether to build a BAI index (the output path with ".bai" appended) while writing.
+    /// Only valid for coordinate-sorted BAM written to a local file.
+    pub fn write_bai(&mut self, write_bai: bool) -> &mut Self {
+        self.options.write_bai(write_bai);
+        self
+    }
+
+    /// Name the BAI for bai_output (with ".bai" appended) instead of the output, e.g. when the
+    /// output is a pipe whose bytes are copied to bai_output (see ChecksummedOutput).
+    pub fn bai_output(&mut self, bai_output: Option<P>) -> &mut Self {
+        if let Some(path) = bai_output {
+            self.options.bai_output(path);
+        }
+        self
+    }
+
+    /// Check that the spec can create a writer, without creating it.
     ///
     /// # Errors
-    /// Returns an error if the format or header has not been specified, or if the writer
-    /// cannot be created.
-    pub fn get_bam_writer(&self) -> Result<Writer> {
-        match (self.format, &self.header) {
-            (Some(ref format), Some(header)) => {
-                let mut compression = self.options.compression;
-                let mut writer = match PathType::from_path(self.output.as_ref())? {
-                    PathType::Pipe => {
-                        if compression.is_none() {
-                            compression = Some(0);
-                        }
-                        Ok(Writer::from_stdout(header, *format)?)
-                    }
-                    PathType::UrlPath(_) => Err(anyhow!("Cannot write directly to a cloud URL")),
-                    PathType::FilePath(file_path) => {
-                        Ok(Writer::from_path(file_path, header, *format)?)
-                    }
-                }?;
-                if let Some(threads) = self.options.threads {
-                    writer.set_threads(threads.into())?;
-                }
-                if let Some(ref fasta) = self.options.reference_fasta {
-                    writer.set_reference(fasta.as_ref())?;
-                }
-                if let Some(c) = compression {
-                    writer.set_compression_level(CompressionLevel::Level(c))?;
+    /// Returns an error if the format or header has not been specified, if the format is FASTQ,
+    /// if a compression level is set for SAM, if a CRAM version is set for other formats, if
+    /// aligned CRAM output has no reference (and no format option allowing that), or if a BAI is
+    /// requested for output that isn't coordinate-sorted BAM in a local file.
+    pub fn validate(&self) -> Result<Format> {
+        let output = self.output.as_ref();
+        let (format, header) = match (self.format, &self.header) {
+            (Some(format), Some(header)) => (format, HeaderView::from_header(header)),
+            (None, _) => return Err(anyhow!("format was not specified for SamWriterSpec")),
+            (_, None) => return Err(anyhow!("header was not specified for SamWriterSpec")),
+        };
+        let sam_format = format.sam_format().ok_or_else(|| {
+            anyhow!("SamWriterSpec writes SAM, BAM, or CRAM, but {output:?} is to be {format}.")
+        })?;
+        if let Some(level) = self.options.compression
+            && sam_format == Format::Sam
+        {
+            return Err(anyhow!(
+                "Compression level {level} was set, but {output:?} is written as uncompressed SAM."
+            ));
+        }
+        if let Some(version) = self.options.cram_version
+            && sam_format != Format::Cram
+        {
+            return Err(anyhow!(
+                "CRAM version {version} was set, but {output:?} is written as {format}, not CRAM."
+            ));
+        }
+        if sam_format == Format::Cram
+            && !is_unaligned(&header)
+            && self.options.reference_fasta.is_none()
+            && !self
+                .options
+                .format_options
+                .iter()
+                .any(|option| NO_REFERENCE_OPTIONS.contains(&option.as_str()))
+        {
+            return Err(anyhow!(
+                "Writing aligned reads to CRAM {output:?} requires a reference FASTA, or the \
+                format option no_ref=1 or embed_ref=2."
+            ));
+        }
+        let bai_output = self
+            .options
+            .bai_output
+            .as_ref()
+            .map_or(output, AsRef::as_ref);
+        if self.options.write_bai
+            && (sam_format != Format::Bam
+                || !PathType::from_path(bai_output)?.is_regular_file()
+                || !is_coordinate_sorted(&header))
+        {
+            return Err(anyhow!(
+                "Can only write a BAI for coordinate-sorted BAM written to a local file, not \
+                {bai_output:?}."
+            ));
+        }
+        Ok(sam_format)
+    }
+
+    /// Create and return a configured SAM/BAM/CRAM writer. Local files are written under a
+    /// temporary name (see AtomicWriter), so call finish once the output is complete.
+    ///
+    /// If the header has no @SQ lines (unaligned reads), the reference FASTA is ignored and
+    /// compressed formats default to a fast compression level.
+    ///
+    /// # Errors
+    /// Returns an error if the spec is not valid (see validate), or if the writer cannot be
+    /// created.
+    pub fn get_bam_writer(&self) -> Result<AtomicWriter<SamWriter>> {
+        let format = self.validate()?;
+        let header = self
+            .header
+            .as_ref()
+            .ok_or_else(|| anyhow!("header was not specified for SamWriterSpec"))?;
+        let mut compression = self.options.compression;
+        let unaligned = is_unaligned(&HeaderView::from_header(header));
+        let output = match PathType::from_path(self.output.as_ref())? {
+            PathType::Pipe => {
+                if compression.is_none() {
+                    compression = Some(0);
                 }
-                Ok(writer)
+                "-".to_string()
             }
-            (None, _) => Err(anyhow!("format was not specified for SamWriterSpec")),
-            (_, None) => Err(anyhow!("header was not specified for SamWriterSpec")),
+            PathType::UrlPath(url) => {
+                configure_remote_output(&url)?;
+                url.to_string()
+            }
+            PathType::FilePath(file_path) => file_path
+                .to_str()
+                .ok_or_else(|| anyhow!("Output path {file_path:?} is not valid UTF-8."))?
+                .to_string(),
+        };
+        if unaligned && compression.is_none() {
+            compression = Some(UNALIGNED_COMPRESSION_LEVEL);
+        }
+        let reference_fasta = self.options.reference_fasta.as_ref().filter(|_| !unaligned);
+        let mut format_options = Vec::new();
+        if let Some(threads) = self.options.threads {
+            format_options.push(format!("nthreads={threads}"));
+        }
+        if let Some(level) = compression.filter(|_| format != Format::Sam) {
+            format_options.push(format!("level={level}"));
+        }
+        if let Some(version) = self.options.cram_version {
+            format_options.push(format!("version={version}"));
+        }
+        format_options.extend(self.options.format_options.iter().cloned());
+        AtomicWriter::create(&output, |path| {
+            let path = path
+                .to_str()
+                .ok_or_else(|| anyhow!("Output path {path:?} is not valid UTF-8."))?;
+            let mut writer =
+                SamWriter::new(path, format, header, reference_fasta, &format_options)?;
+            if self.options.write_bai {
+                // the index is named for the final output, not the temporary one
+                let bai_output = match self.options.bai_output {
+                    Some(ref bai_output) => bai_output.as_ref().to_str().ok_or_else(|| {
+                        anyhow!("Output path {:?} is not valid UTF-8.", bai_output.as_ref())
+                    })?,
+                    None => &output,
+                };
+                writer.build_index(&format!("{bai_output}.bai"))?;
+            }
+            Ok(writer)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CramVersion, OutputFormat, SamWriterSpec};
+    use anyhow::Result;
+    use rust_htslib::bam::{Header, Read, Reader, Record, header::HeaderRecord};
+    use std::{fs, path::Path};
+    use tempfile::TempDir;
+
+    /// Write one aligned read to path with the given spec settings, returning any error.
+    fn write_aligned(
+        path: &Path,
+        format: OutputFormat,
+        reference_fasta: Option<&Path>,
+        cram_version: Option<CramVersion>,
+        format_options: &[String],
+    ) -> Result<()> {
+        let mut header = Header::new();
+        header.push_record(HeaderRecord::new(b"HD").push_tag(b"VN", "1.6"));
+        header.push_record(
+            HeaderRecord::new(b"SQ")
+                .push_tag(b"SN", "chr1")
+                .push_tag(b"LN", 16),
+        );
+        let mut writer = SamWriterSpec::new(path)
+            .header(header)
+            .format(format)
+            .reference_fasta(reference_fasta)
+            .cram_version(cram_version)
+            .format_options(format_options)
+            .get_bam_writer()?;
+        let mut record = Record::new();
+        record.set(b"read1", None, b"ACGT", &[30, 30, 30, 30]);
+        record.set_tid(0);
+        record.set_pos(2);
+        writer.write(&record)?;
+        writer.finish()
+    }
+
+    /// Test that CRAM version and format options reach htslib, and that aligned CRAM output
+    /// needs a reference.
+    #[test]
+    fn test_cram_options() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let fasta = temp_dir.path().join("ref.fa");
+        fs::write(&fasta, ">chr1\nACACGTACGTACGTAC\n")?;
+        for (version, minor) in [(CramVersion::V3_0, 0u8), (CramVersion::V3_1, 1u8)] {
+            let cram = temp_dir.path().join(format!("v{version}.cram"));
+            write_aligned(&cram, OutputFormat::Cram, Some(&fasta), Some(version), &[])?;
+            assert_eq!(fs::read(&cram)?[..6], [b'C', b'R', b'A', b'M', 3, minor]);
+            let mut reader = Reader::from_path(&cram)?;
+            reader.set_reference(&fasta)?;
+            assert_eq!(reader.records().count(), 1);
+        }
+
+        let cram = temp_dir.path().join("no_ref.cram");
+        let err = write_aligned(&cram, OutputFormat::Cram, None, None, &[]).unwrap_err();
+        assert!(err.to_string().contains("requires a reference"), "{err}");
+        write_aligned(
+            &cram,
+            OutputFormat::Cram,
+            None,
+            None,
+            &["no_ref=1".to_string()],
+        )?;
+
+        let bam = temp_dir.path().join("reads.bam");
+        assert!(
+            write_aligned(&bam, OutputFormat::Bam, None, Some(CramVersion::V3_1), &[]).is_err()
+        );
+        assert!(
+            write_aligned(
+                &bam,
+                OutputFormat::Bam,
+                None,
+                None,
+                &["no_such_option=1".to_string()]
+            )
+            .is_err()
+        );
+        assert!("4.0".parse::<CramVersion>().is_err());
+        Ok(())
+    }
+
+    /// Test that incompatible combinations are rejected by validate, before any output is
+    /// created, with errors naming the problem.
+    #[test]
+    fn test_validate() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let mut header = Header::new();
+        header.push_record(
+            HeaderRecord::new(b"SQ")
+                .push_tag(b"SN", "chr1")
+                .push_tag(b"LN", 16),
+        );
+        let cram = temp_dir.path().join("reads.cram");
+        let mut spec = SamWriterSpec::new(cram.as_path());
+        let err = spec.validate().unwrap_err();
+        assert!(
+            err.to_string().contains("format was not specified"),
+            "{err}"
+        );
+        spec.header(header.clone())
+            .format_from_path_or_default(OutputFormat::Bam)?;
+        let err = spec.validate().unwrap_err();
+        assert!(err.to_string().contains("requires a reference"), "{err}");
+        assert!(!cram.exists());
+
+        let sam = temp_dir.path().join("reads.sam");
+        let mut spec = SamWriterSpec::new(sam.as_path());
+        spec.header(header)
+            .format_from_path_or_default(OutputFormat::Bam)?;
+        spec.validate()?;
+        let err = spec.compression(Some(6)).validate().unwrap_err();
+        assert!(err.to_string().contains("uncompressed SAM"), "{err}");
+        let err = spec.format(OutputFormat::Fastq).validate().unwrap_err();
+        assert!(err.to_string().contains("FASTQ"), "{err}");
+
+        assert_eq!(
+            OutputFormat::from_path("reads.fq.gz")?,
+            Some(OutputFormat::Fastq)
+        );
+        assert_eq!(OutputFormat::from_path("reads")?, None);
+        for unwritable in ["reads.sam.gz", "reads.bam.gz", "variants.vcf.gz"] {
+            assert!(OutputFormat::from_path(unwritable).is_err(), "{unwritable}");
+            let mut spec = SamWriterSpec::new(Path::new(unwritable));
+            assert!(spec.format_from_path_or_default(OutputFormat::Bam).is_err());
         }
+        Ok(())
     }
 }
@@ -8,6 +8,9 @@ pub struct Chain<R1: Read, R2: Read> {
     initial_front_pos: u64,
     front_len: u64,
     initial_back_pos: u64,
+    /// Bytes read from or seeked past in the back reader, so that the position is known even if
+    /// the back reader is a pipe
+    back_len_read: u64,
 }
 
 /// impl new ChainReader
@@ -21,13 +24,17 @@ impl<R1: Read + Seek, R2: Read + Seek> Chain<R1, R2> {
     /// * `front` - The first reader to read from
     /// * `back` - The second reader to read from after the front reader is exhausted
     ///
+    /// The back reader may be unseekable (e.g. a pipe), in which case the Chain can report its
+    /// position but not seek.
+    ///
     /// # Errors
-    /// Returns an error if either reader's stream position cannot be determined.
+    /// Returns an error if the front reader's stream position cannot be determined.
     pub fn new(mut front: R1, mut back: R2) -> Result<Self> {
         let initial_front_pos = front.stream_position()?;
         let front_len = front.seek(SeekFrom::End(0))? - initial_front_pos;
         front.seek(SeekFrom::Start(initial_front_pos))?;
-        let initial_back_pos = back.stream_position()?;
+        // pipes have no position, so count from wherever they are now
+        let initial_back_pos = back.stream_position().unwrap_or(0);
         Ok(Chain {
             front,
             back,
@@ -35,6 +42,7 @@ impl<R1: Read + Seek, R2: Read + Seek> Chain<R1, R2> {
             initial_front_pos,
             front_len,
             initial_back_pos,
+            back_len_read: 0,
         })
     }
 }
@@ -43,7 +51,7 @@ impl<R1: Read + Seek, R2: Read + Seek> Chain<R1, R2> {
 impl<R1: Read + Seek, R2: Read + Seek> Seek for Chain<R1, R2> {
     fn stream_position(&mut self) -> Result<u64> {
         if self.past_front {
-            Ok(self.front_len + self.back.stream_position()? - self.initial_back_pos)
+            Ok(self.front_len + self.back_len_read)
         } else {
             Ok(self.front.stream_position()? - self.initial_front_pos)
         }
@@ -58,8 +66,10 @@ impl<R1: Read + Seek, R2: Read + Seek> Seek for Chain<R1, R2> {
                     self.back.seek(SeekFrom::Start(
                         pos_from_start - self.front_len + self.initial_back_pos,
                     ))?;
+                    self.back_len_read = pos_from_start - self.front_len;
                 } else {
                     self.back.seek(SeekFrom::Start(self.initial_back_pos))?;
+                    self.back_len_read = 0;
                     self.past_front = false;
                     self.front
                         .seek(SeekFrom::Start(pos_from_start + self.initial_front_pos))?;
@@ -85,7 +95,8 @@ impl<R1: Read + Seek, R2: Read + Seek> Seek for Chain<R1, R2> {
                     // Seek exactly to the end (or past it, which should throw error)
                     self.past_front = true;
                     self.front.seek(SeekFrom::End(0))?;
-                    Ok(self.front_len + self.back.seek(pos)? - self.initial_back_pos)
+                    self.back_len_read = self.back.seek(pos)? - self.initial_back_pos;
+                    Ok(self.front_len + self.back_len_read)
                 } else {
                     // Get stream length and convert to SeekFrom::start
                     let stream_length =
@@ -101,18 +112,16 @@ impl<R1: Read + Seek, R2: Read + Seek> Seek for Chain<R1, R2> {
 /// impl Read trait
 impl<R1: Read, R2: Read> Read for Chain<R1, R2> {
     fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
-        if self.past_front {
-            // Just read from the back
-            self.back.read(buf)
-        } else {
+        if !self.past_front {
             // Read from the front
             let num = self.front.read(buf)?;
-            if 0 == num && !buf.is_empty() {
-                self.past_front = true;
-                self.back.read(buf)
-            } else {
-                Ok(num)
+            if 0 < num || buf.is_empty() {
+                return Ok(num);
             }
+            self.past_front = true;
         }
+        let num = self.back.read(buf)?;
+        self.back_len_read += num as u64;
+        Ok(num)
     }
 }
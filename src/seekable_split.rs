@@ -1,10 +1,13 @@
-use std::io::{BufRead, Result, Seek};
+use std::io::{BufRead, Error, ErrorKind, Read, Result, Seek, SeekFrom};
 
 /// Struct for splitting a buffered reader by a delimiter byte,
 #[derive(Debug)]
 pub struct Split<B> {
     buf: B,
     delim: u8,
+    max_len: usize,
+    /// Number of bytes consumed from `buf` since the start of the stream, or None after seeking.
+    position: Option<u64>,
 }
 
 impl<B: BufRead> Split<B> {
@@ -14,13 +17,69 @@ impl<B: BufRead> Split<B> {
     /// * `buf` - The buffered reader to split
     /// * `delim` - The delimiter byte (e.g., b'\n' for newline-delimited records)
     pub fn new(buf: B, delim: u8) -> Self {
-        Self { buf, delim }
+        Self {
+            buf,
+            delim,
+            max_len: usize::MAX,
+            position: Some(0),
+        }
+    }
+
+    /// Limit the length of records (excluding the delimiter). Longer records yield an error
+    /// rather than growing the buffer without bound.
+    pub fn with_max_len(mut self, max_len: usize) -> Self {
+        self.max_len = max_len;
+        self
+    }
+
+    /// Set the position of the start of `buf` in the stream, e.g. when a header was already read
+    /// from it.
+    pub fn with_position(mut self, position: u64) -> Self {
+        self.position = Some(position);
+        self
+    }
+
+    /// Get a reference to the underlying reader.
+    pub fn get_ref(&self) -> &B {
+        &self.buf
+    }
+
+    /// Get the number of bytes consumed from the underlying reader since the start of the
+    /// stream. This is the offset into the uncompressed stream even when the underlying reader
+    /// decompresses. Unknown (None) after seeking, because seek positions may be virtual offsets.
+    pub fn position(&self) -> Option<u64> {
+        self.position
+    }
+
+    /// Read the next record into `record`, reusing its allocation. Return Ok(false) if there are
+    /// no more records.
+    pub fn read_next_into(&mut self, record: &mut Vec<u8>) -> Result<bool> {
+        record.clear();
+        let limit = self.max_len.saturating_add(1) as u64;
+        match (&mut self.buf).take(limit).read_until(self.delim, record)? {
+            0 => Ok(false),
+            n => {
+                self.position = self.position.map(|position| position + n as u64);
+                if record[record.len() - 1] == self.delim {
+                    record.pop();
+                } else if record.len() > self.max_len {
+                    return Err(Error::new(
+                        ErrorKind::InvalidData,
+                        format!("Record exceeds maximum length of {} bytes", self.max_len),
+                    ));
+                }
+                Ok(true)
+            }
+        }
     }
 }
 
 /// impl seek
 impl<B: BufRead + Seek> Seek for Split<B> {
-    fn seek(&mut self, pos: std::io::SeekFrom) -> Result<u64> {
+    fn seek(&mut self, pos: SeekFrom) -> Result<u64> {
+        if pos != SeekFrom::Current(0) {
+            self.position = None;
+        }
         self.buf.seek(pos)
     }
 }
@@ -31,14 +90,9 @@ impl<B: BufRead> Iterator for Split<B> {
 
     fn next(&mut self) -> Option<Result<Vec<u8>>> {
         let mut buf = Vec::new();
-        match self.buf.read_until(self.delim, &mut buf) {
-            Ok(0) => None,
-            Ok(_n) => {
-                if buf[buf.len() - 1] == self.delim {
-                    buf.pop();
-                }
-                Some(Ok(buf))
-            }
+        match self.read_next_into(&mut buf) {
+            Ok(false) => None,
+            Ok(true) => Some(Ok(buf)),
             Err(e) => Some(Err(e)),
         }
     }
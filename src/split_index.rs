@@ -1,30 +1,163 @@
 use crate::{
+    atomic_output::AtomicWriter,
     chunkable::{
-        ChunkableRecord, ChunkableRecordReader, ChunkableRecordWriter, FastForwardIndex, SplitRange,
+        ChunkableRecord, ChunkableRecordReader, ChunkableRecordWriter, FastForwardIndex, GroupBy,
+        OffsetKind, SplitRange, first_record_hash,
     },
+    error_category::{Categorize, ErrorCategory},
+    file_fingerprint::FileFingerprint,
+    heartbeat::{Heartbeat, reader_offset},
+    maybe_compressed_io::{open_url_writer, read_decompressed},
+    memory_budget::MemoryBudget,
     path_type::PathType,
+    resync::{ResyncFormat, SkippedRange, bgzf_blocks, find_resync_offset, is_bgzf},
 };
 use anyhow::{Result, anyhow};
 use bisection::bisect_left_by;
 use log::{debug, info, warn};
-use rust_htslib::bgzf::{Reader as BgzfReader, Writer as BgzfWriter};
+use noodles_bgzf::VirtualPosition;
+use rust_htslib::bgzf::Writer as BgzfWriter;
 use serde::{Deserialize, Serialize};
 use std::{
     cmp::max,
-    io::{Read, Write},
+    collections::BTreeMap,
+    fs,
+    io::Write,
     num::NonZero,
-    ops::RangeBounds,
-    path::Path,
+    ops::{RangeBounds, RangeInclusive},
+    path::{Path, PathBuf},
+    thread,
     time::{Duration, SystemTime},
     vec::Vec,
 };
 
+/// Binary magic number starting every serialized SplitIndex (inside its BGZF compression), before
+/// the text header. As in PNG, the high first byte and the CR LF, Ctrl-Z, LF tail catch 7-bit
+/// transfers and newline translation. The layout after it is the text header
+/// "split-index <version>\n", then (since version 3.0) tagged sections. The magic number was added
+/// in version 3.1.
+pub const SPLIT_INDEX_MAGIC: [u8; 8] = *b"\x89SIX\r\n\x1a\n";
+
+/// Start of the text header of SplitIndex files. Files written before the magic number start with
+/// it directly.
+const HEADER_FRONT: &[u8] = b"split-index ";
+
 /// Version string for SplitIndex header.
-const VERSION: &str = "1.0";
+const VERSION: &str = "3.1";
+
+/// Version string for SplitIndex files with tagged sections, written before the magic number was
+/// added. Their sections are read as in the current version.
+const VERSION_3_0: &str = "3.0";
+
+/// Version string for SplitIndex files with a fixed layout including the fingerprint.
+const VERSION_2: &str = "2.0";
+
+/// Version string for SplitIndex files written before the fingerprint was added.
+const VERSION_1: &str = "1.0";
+
+/// Tag identifying a section of the index. Sections are written as the tag, the payload length
+/// (u64), then the payload. As with PNG chunks, a tag starting with an uppercase letter marks a
+/// section that readers must understand. Readers skip other unknown sections, so optional data can
+/// be added without breaking older readers.
+type SectionTag = [u8; 4];
+
+/// Section holding the SplitRecords. Required.
+const RECORDS_SECTION: SectionTag = *b"RECS";
+
+/// Section holding the key records were grouped into query groups by, if not the query name.
+/// Required: readers that ignore it would split the grouped reads between chunks.
+const GROUP_BY_SECTION: SectionTag = *b"GRPB";
+
+/// Section marking an index whose counts are estimates (see SplitIndex::is_approximate). Required:
+/// readers that ignore it would extract chunks by counts that don't match the reads file.
+const APPROXIMATE_SECTION: SectionTag = *b"APRX";
+
+/// Section holding the fingerprint of the indexed reads file. Optional.
+const FINGERPRINT_SECTION: SectionTag = *b"fing";
+
+/// Section holding the offset of each SplitRecord in the uncompressed stream. Optional.
+const UNCOMPRESSED_OFFSETS_SECTION: SectionTag = *b"uoff";
+
+/// Section holding the kind of the SplitRecords' offsets (see OffsetKind). Optional: indices
+/// without it are trusted to match the reader.
+const OFFSET_KIND_SECTION: SectionTag = *b"okin";
+
+/// Section holding the first and last query name of each SplitRecord. Optional.
+const QNAMES_SECTION: SectionTag = *b"qnam";
+
+/// Section holding the length of the longest read of each SplitRecord. Optional.
+const MAX_READ_LENGTHS_SECTION: SectionTag = *b"mxln";
+
+/// Section holding the hash of the first read of each SplitRecord (see first_record_hash).
+/// Optional: readers that ignore it just can't check that the index matches the reads file.
+const FIRST_RECORD_HASHES_SECTION: SectionTag = *b"frhs";
+
+/// Section holding the number of reads in each read group. Optional.
+const READ_GROUPS_SECTION: SectionTag = *b"rdgp";
+
+/// Section holding the ranges skipped after corrupt records while indexing. Optional, but readers
+/// that ignore it will fail on the corrupt records.
+const SKIPPED_RANGES_SECTION: SectionTag = *b"skip";
+
+/// Section holding the point to resume building from, in checkpoints of a partially built index.
+/// Optional.
+const CHECKPOINT_SECTION: SectionTag = *b"ckpt";
+
+/// Sections of a serialized SplitIndex, as reported by tell and removed by prune. Only optional
+/// sections that chunk extraction can do without can be removed.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IndexSection {
+    /// The bins. Required.
+    #[value(skip)]
+    Records,
+    /// The key records were grouped by. Needed to extract whole groups.
+    #[value(skip)]
+    GroupBy,
+    /// Marks counts as estimates. Needed to extract chunks by bin offsets rather than counts.
+    #[value(skip)]
+    Approximate,
+    OffsetKind,
+    UncompressedOffsets,
+    Qnames,
+    MaxReadLengths,
+    FirstRecordHashes,
+    ReadGroups,
+    /// Ranges skipped after corrupt records. Needed to extract chunks past them.
+    #[value(skip)]
+    SkippedRanges,
+    Fingerprint,
+    /// Point to resume a partially built index from. Removing it would pass the partial index off
+    /// as complete.
+    #[value(skip)]
+    Checkpoint,
+}
+
+impl IndexSection {
+    /// Name of the section, as given to prune
+    pub fn name(self) -> &'static str {
+        match self {
+            IndexSection::Records => "records",
+            IndexSection::GroupBy => "group-by",
+            IndexSection::Approximate => "approximate",
+            IndexSection::OffsetKind => "offset-kind",
+            IndexSection::UncompressedOffsets => "uncompressed-offsets",
+            IndexSection::Qnames => "qnames",
+            IndexSection::MaxReadLengths => "max-read-lengths",
+            IndexSection::FirstRecordHashes => "first-record-hashes",
+            IndexSection::ReadGroups => "read-groups",
+            IndexSection::SkippedRanges => "skipped-ranges",
+            IndexSection::Fingerprint => "fingerprint",
+            IndexSection::Checkpoint => "checkpoint",
+        }
+    }
+}
 
 /// Default extension for split index files.
 pub const SPLIT_INDEX_EXTENSION: &str = "si";
 
+/// Factor of the typical longest read of a bin above which reads count as ultra-long outliers
+pub const LONG_READ_FACTOR: usize = 10;
+
 /// Drain range of bytes from the front of passed Vec, and return it as a new Vec
 fn split_off<R>(bytes: &mut Vec<u8>, range: R) -> Result<Vec<u8>>
 where
@@ -51,12 +184,84 @@ fn deserialize_u64(bytes: &mut Vec<u8>) -> Result<u64> {
     Ok(u64::from_le_bytes(u64_bytes.as_slice().try_into()?))
 }
 
+/// Deserialize a u32 from the bytes buffer, and shorten the buffer
+fn deserialize_u32(bytes: &mut Vec<u8>) -> Result<u32> {
+    let u32_bytes = split_off(bytes, ..size_of::<u32>())?;
+    Ok(u32::from_le_bytes(u32_bytes.as_slice().try_into()?))
+}
+
+/// Deserialize a bool from the bytes buffer, and shorten the buffer
+fn deserialize_bool(bytes: &mut Vec<u8>) -> Result<bool> {
+    match split_off(bytes, ..1)?[0] {
+        0 => Ok(false),
+        1 => Ok(true),
+        byte => Err(anyhow!("Invalid bool byte {byte}. Index record corrupted.")),
+    }
+}
+
+/// Serialize a section by appending its tag, payload length, and payload to bytes
+fn serialize_section(tag: SectionTag, payload: &[u8], bytes: &mut Vec<u8>) {
+    bytes.extend(tag);
+    bytes.extend((payload.len() as u64).to_le_bytes());
+    bytes.extend(payload);
+}
+
+/// Deserialize a section by draining its tag and payload from bytes
+fn deserialize_section(bytes: &mut Vec<u8>) -> Result<(SectionTag, Vec<u8>)> {
+    let tag: SectionTag = split_off(bytes, ..size_of::<SectionTag>())?
+        .as_slice()
+        .try_into()?;
+    let len: usize = deserialize_u64(bytes)?.try_into()?;
+    Ok((tag, split_off(bytes, ..len)?))
+}
+
+/// Serialize a length-prefixed byte string by appending to bytes
+fn serialize_bytes(value: &[u8], bytes: &mut Vec<u8>) {
+    bytes.extend((value.len() as u64).to_le_bytes());
+    bytes.extend(value);
+}
+
+/// Deserialize a length-prefixed byte string by draining from bytes
+fn deserialize_bytes(bytes: &mut Vec<u8>) -> Result<Vec<u8>> {
+    let len: usize = deserialize_u64(bytes)?.try_into()?;
+    split_off(bytes, ..len)
+}
+
+/// Serialize a fingerprint by appending to bytes
+fn serialize_fingerprint(fingerprint: &FileFingerprint, bytes: &mut Vec<u8>) {
+    bytes.extend(fingerprint.size.to_le_bytes());
+    bytes.push(fingerprint.checksum.is_some() as u8);
+    if let Some(checksum) = fingerprint.checksum {
+        bytes.extend(checksum.to_le_bytes());
+    }
+}
+
+/// Deserialize a fingerprint by draining from bytes
+fn deserialize_fingerprint(bytes: &mut Vec<u8>) -> Result<FileFingerprint> {
+    let size = deserialize_u64(bytes)?;
+    let checksum = if deserialize_bool(bytes)? {
+        Some(deserialize_u64(bytes)?)
+    } else {
+        None
+    };
+    Ok(FileFingerprint { size, checksum })
+}
+
 /// Struct for holding records in the SplitIndex. It represents a very small bin in the original
 /// reads file.
-#[derive(Clone, Copy, Serialize, Deserialize, PartialEq)]
-struct SplitRecord {
-    /// File offset at the first read in the bin
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct SplitRecord {
+    /// File offset at the first read in the bin. For compressed files this is a virtual offset.
     pub offset: u64,
+    /// Offset of the first read in the bin in the uncompressed stream, if the reader tracked it.
+    /// This allows seeking in a decompressed copy of the indexed file.
+    pub uncompressed_offset: Option<u64>,
+    /// Query names of the first and last query groups in the bin, if recorded.
+    pub qname_range: Option<(Vec<u8>, Vec<u8>)>,
+    /// Length of the longest read in the bin, if recorded.
+    pub max_read_length: Option<usize>,
+    /// Hash of the first read in the bin (see first_record_hash), if recorded.
+    pub first_record_hash: Option<u32>,
     /// Cumulative number of queries in the entire reads file at the end of the bin.
     pub num_queries: usize,
     /// Cumulative number of reads in the entire reads file at the end of the bin.
@@ -77,22 +282,589 @@ impl SplitRecord {
             offset: deserialize_u64(bytes)?,
             num_queries: deserialize_usize(bytes)?,
             num_reads: deserialize_usize(bytes)?,
+            uncompressed_offset: None,
+            qname_range: None,
+            max_read_length: None,
+            first_record_hash: None,
         })
     }
+
+    /// Record the query name of the last query group in the bin
+    fn set_last_qname(&mut self, qname: &[u8]) {
+        if let Some((_, last_qname)) = self.qname_range.as_mut() {
+            last_qname.clear();
+            last_qname.extend_from_slice(qname);
+        }
+    }
+
+    /// Count a read of length read_length towards the longest read in the bin
+    fn add_read_length(&mut self, read_length: usize) {
+        if let Some(max_read_length) = self.max_read_length.as_mut() {
+            *max_read_length = (*max_read_length).max(read_length);
+        }
+    }
+
+    /// Make this bin start where another SplitRecord starts, by copying both offsets, and the
+    /// first query name and read hash.
+    fn set_start_from(&mut self, other: &SplitRecord) {
+        self.offset = other.offset;
+        self.uncompressed_offset = other.uncompressed_offset;
+        self.first_record_hash = other.first_record_hash;
+        if let (Some((first_qname, _)), Some((other_first_qname, _))) =
+            (self.qname_range.as_mut(), other.qname_range.as_ref())
+        {
+            first_qname.clone_from(other_first_qname);
+        } else {
+            self.qname_range = None;
+        }
+    }
+}
+
+/// Point in the reads file to resume building a SplitIndex from, stored in checkpoints.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq)]
+struct ResumePoint {
+    /// Offset of the first record not yet indexed
+    offset: u64,
+    /// Cumulative number of queries at which to start the next bin
+    next_query_bin: usize,
+}
+
+/// Offset, query name and hash (see first_record_hash) of the first record of a query group
+type QueryStart = (u64, Vec<u8>, u32);
+
+/// SplitIndex of one partition of a reads file, built by SplitIndex::build_parallel.
+struct PartitionIndex {
+    /// Index of the partition, with counts starting from zero
+    split_index: SplitIndex,
+    /// Number of reads past the end of the partition that belong to its last query group
+    overrun_reads: usize,
+    /// Offset and query name of the first query group after the partition, or None at the end of
+    /// the file
+    next_query: Option<QueryStart>,
+    /// Hash of the first record of the partition (see first_record_hash)
+    start_hash: u32,
+    /// Hash of the first record at or after the end of the partition, which must be the first
+    /// record of the next partition, or None if the file ended first
+    end_hash: Option<u32>,
 }
 
 /// Struct for holding and manipulating all the SplitRecords for a reads file.
 #[derive(Serialize, Deserialize, Clone, PartialEq)]
 pub struct SplitIndex {
     split_records: Vec<SplitRecord>,
+    /// Kind of the offsets of split_records, or None for indices written before it was recorded
+    offset_kind: Option<OffsetKind>,
+    /// Fingerprint of the indexed reads file, if it could be taken
+    fingerprint: Option<FileFingerprint>,
+    /// Number of reads in each read group, for reads that have one
+    read_groups: BTreeMap<Vec<u8>, usize>,
+    /// Ranges of the reads file skipped after corrupt records
+    skipped_ranges: Vec<SkippedRange>,
+    /// Where to resume building, if this is a checkpoint of a partially built index. The last
+    /// SplitRecord is then the bin that was still being filled.
+    resume_point: Option<ResumePoint>,
+    /// Key records were grouped into query groups by. The query name ranges of the bins are then
+    /// ranges of this key.
+    group_by: GroupBy,
+    /// Whether the counts of the bins are estimates, e.g. from a BAM's BAI/CSI index
+    approximate: bool,
 }
 
 impl SplitIndex {
-    /// Create a new empty SplitIndex with specified capacity.
+    /// Create a new empty SplitIndex with room for num_records bins. The memory budget only caps
+    /// this initial allocation; bins added past it still grow the index.
     pub fn with_capacity(num_records: usize) -> Self {
         SplitIndex {
-            split_records: Vec::with_capacity(num_records),
+            split_records: Vec::with_capacity(MemoryBudget::current().index_bins(num_records)),
+            offset_kind: None,
+            fingerprint: None,
+            read_groups: BTreeMap::new(),
+            skipped_ranges: Vec::new(),
+            resume_point: None,
+            group_by: GroupBy::QueryName,
+            approximate: false,
+        }
+    }
+
+    /// Create an approximate index (see is_approximate) from bins whose counts are estimates, e.g.
+    /// from a BAM's BAI/CSI index (see bam_index::build_approximate_index). Errors unless the bins
+    /// have strictly increasing offsets and non-decreasing counts.
+    pub fn from_approximate_bins(
+        split_records: Vec<SplitRecord>,
+        offset_kind: OffsetKind,
+    ) -> Result<Self> {
+        if !split_records.windows(2).all(|pair| {
+            pair[0].offset < pair[1].offset
+                && pair[0].num_queries <= pair[1].num_queries
+                && pair[0].num_reads <= pair[1].num_reads
+        }) {
+            return Err(anyhow!(
+                "Approximate bins must have increasing offsets and counts."
+            ));
+        }
+        let mut split_index = SplitIndex::with_capacity(split_records.len());
+        split_index.split_records = split_records;
+        split_index.offset_kind = Some(offset_kind);
+        split_index.approximate = true;
+        Ok(split_index)
+    }
+
+    /// Return true if the counts of the bins are estimates, e.g. for an index built from a BAM's
+    /// BAI/CSI index. Chunks of approximate indices are extracted by bin offsets: each is every
+    /// record from the start of one bin to the start of another, so chunk sizes only roughly
+    /// follow the requested queries, and query groups may be split between chunks.
+    pub fn is_approximate(&self) -> bool {
+        self.approximate
+    }
+
+    /// Get the key records were grouped into query groups by
+    pub fn group_by(&self) -> GroupBy {
+        self.group_by
+    }
+
+    /// Get the kind of the offsets, if it was recorded
+    pub fn offset_kind(&self) -> Option<OffsetKind> {
+        self.offset_kind
+    }
+
+    /// Set the kind of the offsets
+    pub fn set_offset_kind(&mut self, offset_kind: Option<OffsetKind>) {
+        self.offset_kind = offset_kind;
+    }
+
+    /// Get the fingerprint of the indexed reads file
+    pub fn fingerprint(&self) -> Option<FileFingerprint> {
+        self.fingerprint
+    }
+
+    /// Set the fingerprint of the indexed reads file
+    pub fn set_fingerprint(&mut self, fingerprint: Option<FileFingerprint>) {
+        self.fingerprint = fingerprint;
+    }
+
+    /// Get the number of reads in each read group. Empty if the reads have no read groups, or the
+    /// index was built by an older version.
+    pub fn read_groups(&self) -> &BTreeMap<Vec<u8>, usize> {
+        &self.read_groups
+    }
+
+    /// Get the ranges of the reads file that were skipped after corrupt records while indexing.
+    /// Readers must jump over them to read the same records.
+    pub fn skipped_ranges(&self) -> &[SkippedRange] {
+        &self.skipped_ranges
+    }
+
+    /// Count a read in its read group
+    fn count_read_group(&mut self, read_group: &[u8]) {
+        if let Some(count) = self.read_groups.get_mut(read_group) {
+            *count += 1;
+        } else {
+            self.read_groups.insert(read_group.to_vec(), 1);
+        }
+    }
+
+    /// Check that the reads file at the requested path matches the indexed reads file. Indices
+    /// without a fingerprint (e.g. built from stdin, or older versions) cannot be checked.
+    pub fn verify_fingerprint<P>(&self, path: P) -> Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        if let Some(fingerprint) = self.fingerprint {
+            fingerprint.verify(path).categorize(ErrorCategory::BadIndex)
+        } else {
+            debug!("SplitIndex has no fingerprint, cannot verify reads file.");
+            Ok(())
+        }
+    }
+
+    /// Get the sections that serialize would write, in order.
+    pub fn sections(&self) -> Vec<IndexSection> {
+        [
+            (IndexSection::Records, true),
+            (IndexSection::GroupBy, self.group_by != GroupBy::QueryName),
+            (IndexSection::Approximate, self.approximate),
+            (IndexSection::OffsetKind, self.offset_kind.is_some()),
+            (
+                IndexSection::UncompressedOffsets,
+                self.has_uncompressed_offsets(),
+            ),
+            (IndexSection::Qnames, self.has_qnames()),
+            (IndexSection::MaxReadLengths, self.has_max_read_lengths()),
+            (
+                IndexSection::FirstRecordHashes,
+                self.has_first_record_hashes(),
+            ),
+            (IndexSection::ReadGroups, !self.read_groups.is_empty()),
+            (IndexSection::SkippedRanges, !self.skipped_ranges.is_empty()),
+            (IndexSection::Fingerprint, self.fingerprint.is_some()),
+            (IndexSection::Checkpoint, self.resume_point.is_some()),
+        ]
+        .into_iter()
+        .filter_map(|(section, present)| present.then_some(section))
+        .collect()
+    }
+
+    /// Remove an optional section, keeping the bins. Errors for sections that chunk extraction
+    /// needs.
+    pub fn remove_section(&mut self, section: IndexSection) -> Result<()> {
+        match section {
+            IndexSection::OffsetKind => self.offset_kind = None,
+            IndexSection::UncompressedOffsets => {
+                for split_record in self.split_records.iter_mut() {
+                    split_record.uncompressed_offset = None;
+                }
+            }
+            IndexSection::Qnames => self.clear_qnames(),
+            IndexSection::MaxReadLengths => self.clear_max_read_lengths(),
+            IndexSection::FirstRecordHashes => self.clear_first_record_hashes(),
+            IndexSection::ReadGroups => self.read_groups.clear(),
+            IndexSection::Fingerprint => self.fingerprint = None,
+            IndexSection::Records
+            | IndexSection::GroupBy
+            | IndexSection::Approximate
+            | IndexSection::SkippedRanges
+            | IndexSection::Checkpoint => {
+                return Err(anyhow!(
+                    "Cannot remove the {} section of an index.",
+                    section.name()
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Return true if every bin has an offset into the uncompressed stream
+    pub fn has_uncompressed_offsets(&self) -> bool {
+        !self.is_empty()
+            && self
+                .split_records
+                .iter()
+                .all(|split_record| split_record.uncompressed_offset.is_some())
+    }
+
+    /// Convert to an index for seeking in a decompressed copy of the indexed file (or a SAM copy
+    /// of an indexed BAM/CRAM), by replacing every offset with the corresponding uncompressed
+    /// offset. The first bin starts at the first record, so if records_start is given, offsets are
+    /// shifted to start there instead: a copy's records may start elsewhere than the indexed
+    /// file's, e.g. after a SAM header with an added @PG line.
+    pub fn into_uncompressed(mut self, records_start: Option<u64>) -> Result<Self> {
+        if !self.has_uncompressed_offsets() {
+            return Err(anyhow!(
+                "Index has no uncompressed offsets. They are only recorded when indexing FASTQ or \
+                plain SAM, or BAM/CRAM with --sam-mirror."
+            ));
+        }
+        if !self.skipped_ranges.is_empty() {
+            return Err(anyhow!(
+                "Index skipped corrupt ranges of the indexed file, which cannot be located in a \
+                decompressed copy."
+            ));
+        }
+        let first_offset = self.split_records[0]
+            .uncompressed_offset
+            .unwrap_or_default();
+        let records_start = records_start.unwrap_or(first_offset);
+        for split_record in self.split_records.iter_mut() {
+            split_record.offset =
+                split_record.uncompressed_offset.unwrap_or_default() - first_offset + records_start;
+        }
+        self.offset_kind = Some(OffsetKind::Byte);
+        // the fingerprint describes the indexed file, not the decompressed copy
+        self.fingerprint = None;
+        Ok(self)
+    }
+
+    /// Convert an index built while writing pass-through output, whose offsets are positions in
+    /// the uncompressed output counted from its first record (see ChunkableRecordWriter::tell),
+    /// into offsets for reading the finished output at path. first_record_offset is the reader
+    /// offset of the first record, after any header. Sets the offset kind to match the output.
+    pub fn resolve_output_offsets<P>(&mut self, path: P, first_record_offset: u64) -> Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        if !is_bgzf(&path)? {
+            for split_record in self.split_records.iter_mut() {
+                split_record.offset += first_record_offset;
+            }
+            self.offset_kind = Some(OffsetKind::Byte);
+            return Ok(());
+        }
+        self.offset_kind = Some(OffsetKind::Virtual);
+        let blocks = bgzf_blocks(&path)?;
+        let (first_block, first_record_in_block) =
+            OffsetKind::split_virtual_offset(first_record_offset);
+        let mut block_index = blocks
+            .iter()
+            .position(|(compressed_offset, _)| *compressed_offset == first_block)
+            .ok_or_else(|| anyhow!("{:?} has no block at the first record.", path.as_ref()))?;
+        // uncompressed position of the current block, counted from the start of the first block
+        let mut block_start = 0u64;
+        for split_record in self.split_records.iter_mut() {
+            let position = split_record.offset + u64::from(first_record_in_block);
+            while block_index < blocks.len() && position >= block_start + blocks[block_index].1 {
+                block_start += blocks[block_index].1;
+                block_index += 1;
+            }
+            let Some((compressed_offset, _)) = blocks.get(block_index) else {
+                return Err(anyhow!(
+                    "Output position {} is past the end of {:?}.",
+                    split_record.offset,
+                    path.as_ref()
+                ));
+            };
+            split_record.offset =
+                OffsetKind::virtual_offset(*compressed_offset, (position - block_start) as u16)?;
+        }
+        Ok(())
+    }
+
+    /// Set the uncompressed offset of each bin. There must be exactly one offset per bin.
+    fn set_uncompressed_offsets(&mut self, uncompressed_offsets: Vec<u64>) -> Result<()> {
+        if uncompressed_offsets.len() != self.len() {
+            return Err(anyhow!(
+                "Index has {} uncompressed offsets for {} bins. Index corrupted.",
+                uncompressed_offsets.len(),
+                self.len()
+            ));
+        }
+        for (split_record, uncompressed_offset) in
+            self.split_records.iter_mut().zip(uncompressed_offsets)
+        {
+            split_record.uncompressed_offset = Some(uncompressed_offset);
+        }
+        Ok(())
+    }
+
+    /// Return true if every bin records its first and last query name
+    pub fn has_qnames(&self) -> bool {
+        !self.is_empty()
+            && self
+                .split_records
+                .iter()
+                .all(|split_record| split_record.qname_range.is_some())
+    }
+
+    /// Remove the first and last query name of every bin, to keep the index small.
+    pub fn clear_qnames(&mut self) {
+        for split_record in self.split_records.iter_mut() {
+            split_record.qname_range = None;
+        }
+    }
+
+    /// Return true if every bin records the length of its longest read
+    pub fn has_max_read_lengths(&self) -> bool {
+        !self.is_empty()
+            && self
+                .split_records
+                .iter()
+                .all(|split_record| split_record.max_read_length.is_some())
+    }
+
+    /// Remove the longest read length of every bin, to keep the index small.
+    pub fn clear_max_read_lengths(&mut self) {
+        for split_record in self.split_records.iter_mut() {
+            split_record.max_read_length = None;
+        }
+    }
+
+    /// Return true if every bin records the hash of its first read
+    pub fn has_first_record_hashes(&self) -> bool {
+        !self.is_empty()
+            && self
+                .split_records
+                .iter()
+                .all(|split_record| split_record.first_record_hash.is_some())
+    }
+
+    /// Remove the first read hash of every bin, e.g. when the bins' offsets point into a file
+    /// whose records differ from the ones that were hashed.
+    pub fn clear_first_record_hashes(&mut self) {
+        for split_record in self.split_records.iter_mut() {
+            split_record.first_record_hash = None;
+        }
+    }
+
+    /// Get the length of the longest read in the bins overlapping the query groups with 0-based
+    /// indices start_num_queries..stop_num_queries, or None if the index does not record read
+    /// lengths.
+    pub fn max_read_length(
+        &self,
+        start_num_queries: usize,
+        stop_num_queries: usize,
+    ) -> Option<usize> {
+        if !self.has_max_read_lengths() {
+            return None;
+        }
+        let mut num_previous_queries = 0;
+        let mut max_read_length = 0;
+        for split_record in self.split_records.iter() {
+            if num_previous_queries >= stop_num_queries {
+                break;
+            }
+            if split_record.num_queries > start_num_queries {
+                max_read_length = max_read_length.max(split_record.max_read_length?);
+            }
+            num_previous_queries = split_record.num_queries;
+        }
+        Some(max_read_length)
+    }
+
+    /// Get the read length above which reads count as ultra-long outliers: LONG_READ_FACTOR times
+    /// the median over bins of their longest read. None if the index does not record read
+    /// lengths.
+    pub fn long_read_threshold(&self) -> Option<usize> {
+        if !self.has_max_read_lengths() {
+            return None;
+        }
+        let mut max_read_lengths: Vec<usize> = self
+            .split_records
+            .iter()
+            .filter_map(|split_record| split_record.max_read_length)
+            .collect();
+        let median_index = max_read_lengths.len() / 2;
+        let (_, median, _) = max_read_lengths.select_nth_unstable(median_index);
+        Some(median.saturating_mul(LONG_READ_FACTOR).max(1))
+    }
+
+    /// Get the query ranges (0-based start and stop query indices) of the bins whose longest read
+    /// is longer than threshold. Empty if the index does not record read lengths.
+    pub fn long_read_bins(&self, threshold: usize) -> Vec<(usize, usize)> {
+        if !self.has_max_read_lengths() {
+            return Vec::new();
+        }
+        let mut num_previous_queries = 0;
+        let mut long_read_bins = Vec::new();
+        for split_record in self.split_records.iter() {
+            if split_record.max_read_length.unwrap_or_default() > threshold {
+                long_read_bins.push((num_previous_queries, split_record.num_queries));
+            }
+            num_previous_queries = split_record.num_queries;
+        }
+        long_read_bins
+    }
+
+    /// Set the uncompressed offsets of an index whose offsets are still positions in the
+    /// uncompressed pass-through output (see ChunkableRecordWriter::tell), before resolving them.
+    /// Positions in FASTQ output are offsets into the decompressed FASTQ, other output has none.
+    pub fn set_output_uncompressed_offsets(&mut self, fastq_output: bool) {
+        for split_record in self.split_records.iter_mut() {
+            split_record.uncompressed_offset = fastq_output.then_some(split_record.offset);
+        }
+    }
+
+    /// Return the first and last query name of each bin, if every bin records them and the bins
+    /// are in strictly increasing (byte-wise) query name order. Only then can bins be searched by
+    /// query name. Indices grouped by another key record ranges of that key, so can't be.
+    fn sorted_qname_ranges(&self) -> Option<Vec<(&[u8], &[u8])>> {
+        if self.group_by != GroupBy::QueryName {
+            return None;
+        }
+        let qname_ranges = self
+            .split_records
+            .iter()
+            .map(|split_record| {
+                split_record
+                    .qname_range
+                    .as_ref()
+                    .map(|(first, last)| (first.as_slice(), last.as_slice()))
+            })
+            .collect::<Option<Vec<(&[u8], &[u8])>>>()?;
+        let sorted = qname_ranges.iter().all(|(first, last)| first <= last)
+            && qname_ranges.windows(2).all(|pair| pair[0].1 < pair[1].0);
+        sorted.then_some(qname_ranges)
+    }
+
+    /// Binary search sorted query name ranges for the index of the bin that could contain qname.
+    fn bin_index_for_qname(qname_ranges: &[(&[u8], &[u8])], qname: &[u8]) -> Option<usize> {
+        let index = bisect_left_by(qname_ranges, |(_, last)| (*last).cmp(qname));
+        qname_ranges
+            .get(index)
+            .is_some_and(|(first, _)| *first <= qname)
+            .then_some(index)
+    }
+
+    /// Find the SplitRange of the bin that could contain the requested query name, or None if no
+    /// bin can. Errors if the index cannot be searched by name, because it has no query names or
+    /// its bins are not sorted by name.
+    pub fn find_query(&self, qname: &[u8]) -> Result<Option<SplitRange>> {
+        let qname_ranges = self.sorted_qname_ranges().ok_or_else(|| {
+            anyhow!(
+                "Index cannot be searched by query name. Index a query-name sorted file with \
+                --qnames."
+            )
+        })?;
+        Ok(Self::bin_index_for_qname(&qname_ranges, qname)
+            .and_then(|index| self.index_to_bin_range(index)))
+    }
+
+    /// Get the SplitRanges of the bins that could contain any of the requested query names. Return
+    /// None if the index cannot prune bins by name (no query names recorded, or bins not sorted by
+    /// name), in which case every bin must be searched.
+    pub fn bins_for_qnames<'a, I>(&self, qnames: I) -> Option<Vec<SplitRange>>
+    where
+        I: IntoIterator<Item = &'a [u8]>,
+    {
+        let qname_ranges = self.sorted_qname_ranges()?;
+        let mut indices: Vec<usize> = qnames
+            .into_iter()
+            .filter_map(|qname| Self::bin_index_for_qname(&qname_ranges, qname))
+            .collect();
+        indices.sort_unstable();
+        indices.dedup();
+        indices
+            .into_iter()
+            .map(|index| self.index_to_bin_range(index))
+            .collect()
+    }
+
+    /// Set the first and last query name of each bin. There must be exactly one range per bin.
+    fn set_qname_ranges(&mut self, qname_ranges: Vec<(Vec<u8>, Vec<u8>)>) -> Result<()> {
+        if qname_ranges.len() != self.len() {
+            return Err(anyhow!(
+                "Index has {} query name ranges for {} bins. Index corrupted.",
+                qname_ranges.len(),
+                self.len()
+            ));
+        }
+        for (split_record, qname_range) in self.split_records.iter_mut().zip(qname_ranges) {
+            split_record.qname_range = Some(qname_range);
+        }
+        Ok(())
+    }
+
+    /// Set the longest read length of each bin. There must be exactly one length per bin.
+    fn set_max_read_lengths(&mut self, max_read_lengths: Vec<usize>) -> Result<()> {
+        if max_read_lengths.len() != self.len() {
+            return Err(anyhow!(
+                "Index has {} read lengths for {} bins. Index corrupted.",
+                max_read_lengths.len(),
+                self.len()
+            ));
         }
+        for (split_record, max_read_length) in self.split_records.iter_mut().zip(max_read_lengths) {
+            split_record.max_read_length = Some(max_read_length);
+        }
+        Ok(())
+    }
+
+    /// Set the first read hash of each bin. There must be exactly one hash per bin.
+    fn set_first_record_hashes(&mut self, first_record_hashes: Vec<u32>) -> Result<()> {
+        if first_record_hashes.len() != self.len() {
+            return Err(anyhow!(
+                "Index has {} first read hashes for {} bins. Index corrupted.",
+                first_record_hashes.len(),
+                self.len()
+            ));
+        }
+        for (split_record, hash) in self.split_records.iter_mut().zip(first_record_hashes) {
+            split_record.first_record_hash = Some(hash);
+        }
+        Ok(())
+    }
+
+    /// Get the bins of the index, in file order
+    pub fn bins(&self) -> &[SplitRecord] {
+        &self.split_records
     }
 
     /// Get the length of the index
@@ -125,15 +897,34 @@ impl SplitIndex {
         }
     }
 
+    /// Get the file offsets of the starts of the first and last bins, or None if the index is
+    /// empty.
+    pub fn offset_span(&self) -> Option<(u64, u64)> {
+        Some((
+            self.split_records.first()?.offset,
+            self.split_records.last()?.offset,
+        ))
+    }
+
     /// Add a new SplitRecord to the Index
     fn add_record(&mut self, split_record: SplitRecord) {
         self.split_records.push(split_record);
     }
 
-    /// Return a SplitRecord for the next bin
-    fn start_next_record(&self, offset: u64) -> SplitRecord {
+    /// Return a SplitRecord for the next bin, starting with the read hashed to first_record_hash
+    fn start_next_record(
+        &self,
+        offset: u64,
+        uncompressed_offset: Option<u64>,
+        first_qname: &[u8],
+        first_record_hash: u32,
+    ) -> SplitRecord {
         SplitRecord {
             offset,
+            uncompressed_offset,
+            qname_range: Some((first_qname.to_vec(), first_qname.to_vec())),
+            max_read_length: Some(0),
+            first_record_hash: Some(first_record_hash),
             num_queries: self.num_queries() + 1,
             num_reads: self.num_reads() + 1,
         }
@@ -145,19 +936,25 @@ impl SplitIndex {
             if index == 0 {
                 Some(SplitRange {
                     offset: split_record.offset,
+                    offset_kind: self.offset_kind,
                     num_previous_queries: 0,
                     num_end_queries: split_record.num_queries,
                     num_previous_reads: 0,
                     num_end_reads: split_record.num_reads,
+                    group_by: self.group_by,
+                    first_record_hash: split_record.first_record_hash,
                 })
             } else {
                 let previous_record = self.split_records.get(index - 1)?;
                 Some(SplitRange {
                     offset: split_record.offset,
+                    offset_kind: self.offset_kind,
                     num_previous_queries: previous_record.num_queries,
                     num_end_queries: split_record.num_queries,
                     num_previous_reads: previous_record.num_reads,
                     num_end_reads: split_record.num_reads,
+                    group_by: self.group_by,
+                    first_record_hash: split_record.first_record_hash,
                 })
             }
         } else {
@@ -168,27 +965,163 @@ impl SplitIndex {
 
     /// Serialize SplitIndex to bytes.
     pub fn serialize(self) -> Vec<u8> {
-        let mut bytes: Vec<u8> = format!("split-index {VERSION}\n").as_bytes().to_vec();
-        bytes.extend(&self.len().to_le_bytes());
-        for split_record in self.split_records {
-            split_record.serialize(&mut bytes);
+        let mut bytes: Vec<u8> = SPLIT_INDEX_MAGIC.to_vec();
+        bytes.extend(format!("split-index {VERSION}\n").as_bytes());
+        let mut payload: Vec<u8> = Vec::new();
+        payload.extend(&self.len().to_le_bytes());
+        for split_record in self.split_records.iter() {
+            split_record.serialize(&mut payload);
+        }
+        serialize_section(RECORDS_SECTION, &payload, &mut bytes);
+        if self.group_by != GroupBy::QueryName {
+            payload.clear();
+            self.group_by.serialize(&mut payload);
+            serialize_section(GROUP_BY_SECTION, &payload, &mut bytes);
+        }
+        if self.approximate {
+            serialize_section(APPROXIMATE_SECTION, &[], &mut bytes);
+        }
+        if let Some(offset_kind) = self.offset_kind {
+            serialize_section(OFFSET_KIND_SECTION, &[offset_kind.code()], &mut bytes);
+        }
+        if self.has_uncompressed_offsets() {
+            payload.clear();
+            for split_record in self.split_records.iter() {
+                payload.extend(
+                    split_record
+                        .uncompressed_offset
+                        .unwrap_or_default()
+                        .to_le_bytes(),
+                );
+            }
+            serialize_section(UNCOMPRESSED_OFFSETS_SECTION, &payload, &mut bytes);
+        }
+        if self.has_qnames() {
+            payload.clear();
+            for (first_qname, last_qname) in self
+                .split_records
+                .iter()
+                .filter_map(|split_record| split_record.qname_range.as_ref())
+            {
+                serialize_bytes(first_qname, &mut payload);
+                serialize_bytes(last_qname, &mut payload);
+            }
+            serialize_section(QNAMES_SECTION, &payload, &mut bytes);
+        }
+        if self.has_max_read_lengths() {
+            payload.clear();
+            for split_record in self.split_records.iter() {
+                payload.extend(
+                    (split_record.max_read_length.unwrap_or_default() as u64).to_le_bytes(),
+                );
+            }
+            serialize_section(MAX_READ_LENGTHS_SECTION, &payload, &mut bytes);
+        }
+        if self.has_first_record_hashes() {
+            payload.clear();
+            for split_record in self.split_records.iter() {
+                payload.extend(
+                    split_record
+                        .first_record_hash
+                        .unwrap_or_default()
+                        .to_le_bytes(),
+                );
+            }
+            serialize_section(FIRST_RECORD_HASHES_SECTION, &payload, &mut bytes);
+        }
+        if !self.read_groups.is_empty() {
+            payload.clear();
+            payload.extend((self.read_groups.len() as u64).to_le_bytes());
+            for (read_group, count) in self.read_groups.iter() {
+                serialize_bytes(read_group, &mut payload);
+                payload.extend((*count as u64).to_le_bytes());
+            }
+            serialize_section(READ_GROUPS_SECTION, &payload, &mut bytes);
+        }
+        if !self.skipped_ranges.is_empty() {
+            payload.clear();
+            for skipped_range in self.skipped_ranges.iter() {
+                payload.extend(skipped_range.start.to_le_bytes());
+                payload.extend(skipped_range.resume.unwrap_or(u64::MAX).to_le_bytes());
+            }
+            serialize_section(SKIPPED_RANGES_SECTION, &payload, &mut bytes);
+        }
+        if let Some(ref fingerprint) = self.fingerprint {
+            payload.clear();
+            serialize_fingerprint(fingerprint, &mut payload);
+            serialize_section(FINGERPRINT_SECTION, &payload, &mut bytes);
+        }
+        if let Some(resume_point) = self.resume_point {
+            payload.clear();
+            payload.extend(resume_point.offset.to_le_bytes());
+            payload.extend((resume_point.next_query_bin as u64).to_le_bytes());
+            serialize_section(CHECKPOINT_SECTION, &payload, &mut bytes);
         }
         bytes
     }
 
-    /// Write SplitIndex to the requested path.
+    /// Write SplitIndex to the requested path. Local files are written under a temporary name and
+    /// renamed once complete (see AtomicWriter). Fails if path is an existing non-empty file,
+    /// unless overwriting was allowed with set_overwrite.
     pub fn write<P>(self, path: P) -> Result<usize>
     where
         P: AsRef<Path>,
     {
-        let mut writer = match PathType::from_path(path)? {
+        let writer = AtomicWriter::create(path, Self::open_writer)?;
+        self.write_to(writer)
+    }
+
+    /// Write SplitIndex to the requested path, replacing any file there, e.g. to prune an index in
+    /// place.
+    pub fn rewrite<P>(self, path: P) -> Result<usize>
+    where
+        P: AsRef<Path>,
+    {
+        let writer = AtomicWriter::replace(path, Self::open_writer)?;
+        self.write_to(writer)
+    }
+
+    /// Open a BGZF writer to path
+    fn open_writer(path: &Path) -> Result<BgzfWriter> {
+        match PathType::from_path(path)? {
             PathType::Pipe => Ok(BgzfWriter::from_stdout()?),
             PathType::FilePath(file_path) => Ok(BgzfWriter::from_path(file_path)?),
-            PathType::UrlPath(_) => Err(anyhow!("Cannot write directly to a cloud URL")),
-        }?;
-        writer
+            PathType::UrlPath(url) => open_url_writer(&url, true),
+        }
+    }
+
+    /// Serialize SplitIndex into writer, then commit it.
+    fn write_to(self, mut writer: AtomicWriter<BgzfWriter>) -> Result<usize> {
+        let num_bytes = writer
             .write(&self.serialize())
-            .map_err(|err| anyhow!("{err}"))
+            .map_err(|err| anyhow!("{err}"))?;
+        writer.commit()?;
+        Ok(num_bytes)
+    }
+
+    /// Get the offset and uncompressed offset of the next record. If there is a pass-through
+    /// writer that can tell its position, offsets refer to the output (as uncompressed positions,
+    /// to be resolved by resolve_output_offsets), because the output may be compressed
+    /// differently from the input. Otherwise they come from the reader.
+    fn tell_offsets<Record, Reader, Writer>(
+        reader: &mut Reader,
+        writer: &mut Option<Writer>,
+    ) -> Result<(u64, Option<u64>)>
+    where
+        Record: ChunkableRecord,
+        Reader: ChunkableRecordReader<Record>,
+        Writer: ChunkableRecordWriter<Record>,
+    {
+        let uncompressed_offset = reader.tell_uncompressed()?;
+        match writer
+            .as_mut()
+            .map(|writer| writer.tell())
+            .transpose()?
+            .flatten()
+        {
+            Some(position) => Ok((position, uncompressed_offset.map(|_| position))),
+            None => Ok((reader.tell()?, uncompressed_offset)),
+        }
     }
 
     /// Build the SplitIndex. Never split query groups. Because the total number of records and
@@ -197,69 +1130,476 @@ impl SplitIndex {
     /// The number of actual bins grows logarithmically in the limit of large numbers of query
     /// groups. Later on the bins are interpolated down to the requested amount.
     pub fn build<Record, Reader, Writer>(
+        reader: Reader,
+        writer: Option<Writer>,
+        num_bins: NonZero<usize>,
+        update_interval: u64,
+    ) -> Result<SplitIndex>
+    where
+        Record: ChunkableRecord,
+        Reader: ChunkableRecordReader<Record>,
+        Writer: ChunkableRecordWriter<Record>,
+    {
+        Self::build_resumable(
+            reader,
+            writer,
+            num_bins,
+            update_interval,
+            GroupBy::QueryName,
+            None,
+            None,
+        )
+    }
+
+    /// Build the SplitIndex as in build, periodically saving checkpoints with the checkpointer. If
+    /// the checkpointer loaded a checkpoint, seek the reader to it and continue from there instead
+    /// of starting over. Checkpoints can't be taken while writing pass-through output, because the
+    /// output can't be rewound to the checkpoint. If there is a heartbeat, beat it with progress.
+    /// Records are grouped into query groups by group_by.
+    pub fn build_resumable<Record, Reader, Writer>(
         mut reader: Reader,
         mut writer: Option<Writer>,
         num_bins: NonZero<usize>,
         update_interval: u64,
+        group_by: GroupBy,
+        mut checkpointer: Option<&mut Checkpointer>,
+        mut heartbeat: Option<&mut Heartbeat>,
     ) -> Result<SplitIndex>
     where
         Record: ChunkableRecord,
         Reader: ChunkableRecordReader<Record>,
         Writer: ChunkableRecordWriter<Record>,
     {
+        if writer.is_some() && checkpointer.is_some() {
+            return Err(anyhow!(
+                "Cannot checkpoint indexing while writing pass-through output."
+            ));
+        }
         let mut record = Record::new();
-        let mut split_index = SplitIndex::with_capacity(num_bins.into());
-        let mut next_query_bin: usize = 1;
-        // In this and following calculation of offset, if there is a writer, it we should invoke
-        // writer.tell(). However
-        // 1. rust_htslib currently does not provide writer.tell
-        // 2. if the modality is identical, reader.tell() and writer.tell() yield the same values.
-        let mut offset: u64 = reader.tell()?;
+        let checkpoint = checkpointer
+            .as_deref_mut()
+            .and_then(|checkpointer| checkpointer.resume_from.take());
+        let (mut split_index, mut split_record, mut last_query_name, mut next_query_bin) =
+            if let Some(mut split_index) = checkpoint {
+                let (Some(resume_point), Some(split_record)) = (
+                    split_index.resume_point.take(),
+                    split_index.split_records.pop(),
+                ) else {
+                    return Err(anyhow!("Checkpoint has no point to resume from."));
+                };
+                if split_index.group_by != group_by {
+                    return Err(anyhow!(
+                        "Checkpoint groups reads by {}, not {group_by}.",
+                        split_index.group_by
+                    ));
+                }
+                reader
+                    .offset_kind()
+                    .check_seekable(split_index.offset_kind)?;
+                reader.seek(resume_point.offset)?;
+                info!(
+                    "Resuming from checkpoint after {} reads and {} queries.",
+                    split_record.num_reads, split_record.num_queries
+                );
+                let last_query_name = split_record
+                    .qname_range
+                    .as_ref()
+                    .map(|(_, last_qname)| last_qname.clone())
+                    .ok_or_else(|| anyhow!("Checkpoint has no query names."))?;
+                (
+                    split_index,
+                    split_record,
+                    last_query_name,
+                    resume_point.next_query_bin,
+                )
+            } else {
+                let mut split_index = SplitIndex::with_capacity(num_bins.into());
+                // pass-through output offsets get their kind when resolved
+                split_index.offset_kind = Some(reader.offset_kind());
+                split_index.group_by = group_by;
+                let (offset, uncompressed_offset) = Self::tell_offsets(&mut reader, &mut writer)?;
+                let Some(result) = reader.read_into(&mut record) else {
+                    warn!("Empty index: no reads");
+                    split_index.skipped_ranges = reader.take_skipped_ranges();
+                    return Ok(split_index);
+                };
+                result?;
+                if let Some(ref mut actual_bam_writer) = writer {
+                    actual_bam_writer.write(&record)?;
+                }
+                if let Some(read_group) = record.read_group() {
+                    split_index.count_read_group(read_group);
+                }
+                let key = group_by.key(&record);
+                let mut split_record = split_index.start_next_record(
+                    offset,
+                    uncompressed_offset,
+                    &key,
+                    first_record_hash(&record),
+                );
+                split_record.add_read_length(record.seq_len());
+                (split_index, split_record, key.into_owned(), 1)
+            };
+        let (mut offset, mut uncompressed_offset) = Self::tell_offsets(&mut reader, &mut writer)?;
         let mut last_update = SystemTime::now();
         let update_duration = Duration::from_secs(update_interval);
-        if let Some(result) = reader.read_into(&mut record) {
+        while let Some(result) = reader.read_into(&mut record) {
+            let now = SystemTime::now();
+            if now.duration_since(last_update)? > update_duration {
+                info!(
+                    "Indexed {} reads and {} queries.",
+                    split_record.num_reads, split_record.num_queries
+                );
+                last_update = now;
+            }
             result?;
             if let Some(ref mut actual_bam_writer) = writer {
                 actual_bam_writer.write(&record)?;
             }
-            let mut last_query_name: Vec<u8> = record.qname().to_vec();
-            let mut split_record = split_index.start_next_record(offset);
-            offset = reader.tell()?;
-            while let Some(result) = reader.read_into(&mut record) {
-                let now = SystemTime::now();
-                if now.duration_since(last_update)? > update_duration {
-                    info!(
-                        "Indexed {} reads and {} queries.",
-                        split_record.num_reads, split_record.num_queries
-                    );
-                    last_update = now;
-                }
-                result?;
-                if let Some(ref mut actual_bam_writer) = writer {
-                    actual_bam_writer.write(&record)?;
+            if let Some(read_group) = record.read_group() {
+                split_index.count_read_group(read_group);
+            }
+            let key = group_by.key(&record);
+            if *key == *last_query_name {
+                // inside a query group, do not update bin
+                split_record.num_reads += 1;
+            } else if split_record.num_queries < next_query_bin {
+                // new query group, but not time to change the bin yet
+                last_query_name = key.into_owned();
+                split_record.num_reads += 1;
+                split_record.num_queries += 1;
+            } else {
+                // time for a new bin and query goal
+                split_record.set_last_qname(&last_query_name);
+                split_index.add_record(split_record);
+                next_query_bin += max(1usize, split_index.num_queries() / num_bins);
+                split_record = split_index.start_next_record(
+                    offset,
+                    uncompressed_offset,
+                    &key,
+                    first_record_hash(&record),
+                );
+                last_query_name = key.into_owned();
+            }
+            split_record.add_read_length(record.seq_len());
+            (offset, uncompressed_offset) = Self::tell_offsets(&mut reader, &mut writer)?;
+            if let Some(heartbeat) = heartbeat.as_deref_mut()
+                && heartbeat.is_due()
+            {
+                heartbeat.beat(
+                    "running",
+                    reader_offset(&mut reader),
+                    split_record.num_reads,
+                    split_record.num_queries,
+                );
+            }
+            if let Some(checkpointer) = checkpointer.as_deref_mut()
+                && checkpointer.is_due()
+            {
+                split_index
+                    .skipped_ranges
+                    .extend(reader.take_skipped_ranges());
+                let mut checkpoint = split_index.clone();
+                let mut pending_record = split_record.clone();
+                pending_record.set_last_qname(&last_query_name);
+                checkpoint.add_record(pending_record);
+                checkpoint.resume_point = Some(ResumePoint {
+                    offset,
+                    next_query_bin,
+                });
+                checkpointer.save(checkpoint)?;
+            }
+        }
+        split_record.set_last_qname(&last_query_name);
+        split_index.add_record(split_record);
+        split_index
+            .skipped_ranges
+            .extend(reader.take_skipped_ranges());
+        Ok(split_index)
+    }
+
+    /// Build the SplitIndex of a local BGZF-compressed reads file in parallel. The file is divided
+    /// into num_partitions byte ranges, each range starts at the first record found in its first
+    /// BGZF block, and each is indexed by a separate reader from open in its own thread. The
+    /// partial indices are then merged, joining query groups that span partition boundaries.
+    /// Records are grouped into query groups by group_by.
+    pub fn build_parallel<Record, Reader, Open>(
+        path: &Path,
+        format: ResyncFormat,
+        open: Open,
+        num_partitions: NonZero<usize>,
+        num_bins: NonZero<usize>,
+        group_by: GroupBy,
+    ) -> Result<SplitIndex>
+    where
+        Record: ChunkableRecord,
+        Reader: ChunkableRecordReader<Record>,
+        Open: Fn() -> Result<Reader> + Sync,
+    {
+        let file_size = fs::metadata(path)
+            .map_err(|err| anyhow!("Getting size of {path:?}: {err}"))?
+            .len();
+        // start after any header
+        let mut starts = vec![open()?.tell()?];
+        let first_block = VirtualPosition::from(starts[0]).compressed();
+        for partition in 1..usize::from(num_partitions) {
+            let boundary =
+                (file_size as u128 * partition as u128 / num_partitions.get() as u128) as u64;
+            if boundary <= first_block {
+                continue;
+            }
+            let before_boundary = u64::from(
+                VirtualPosition::try_from((boundary - 1, 0))
+                    .map_err(|err| anyhow!("Partition boundary {boundary} out of range: {err}"))?,
+            );
+            if let Some(start) = find_resync_offset(path, before_boundary, format)?
+                && start > *starts.last().unwrap()
+            {
+                starts.push(start);
+            }
+        }
+        let ends: Vec<u64> = starts.iter().skip(1).copied().chain([u64::MAX]).collect();
+        info!("Indexing {path:?} in {} partitions.", starts.len());
+        let partitions = thread::scope(|scope| {
+            let workers: Vec<_> = starts
+                .iter()
+                .zip(ends)
+                .map(|(&start, end)| {
+                    let open = &open;
+                    scope.spawn(move || {
+                        Self::build_partition::<Record, _>(open()?, start, end, num_bins, group_by)
+                    })
+                })
+                .collect();
+            workers
+                .into_iter()
+                .map(|worker| {
+                    worker
+                        .join()
+                        .map_err(|_| anyhow!("Indexing thread panicked."))?
+                })
+                .collect::<Result<Vec<PartitionIndex>>>()
+        })?;
+        Self::check_partitions_adjoin(&partitions)?;
+        Ok(Self::merge_partitions(partitions, num_bins))
+    }
+
+    /// Check that each partition starts at the record where the one before it ended, so that no
+    /// record is skipped or counted twice, e.g. because a partition was started at an offset that
+    /// looked like a record boundary but wasn't.
+    fn check_partitions_adjoin(partitions: &[PartitionIndex]) -> Result<()> {
+        for (index, pair) in partitions.windows(2).enumerate() {
+            if pair[0].end_hash != Some(pair[1].start_hash) {
+                return Err(anyhow!(
+                    "Partition {} does not start at the record where partition {index} ends, so \
+                    the reads file can't be indexed in parallel. Index it without --partitions.",
+                    index + 1
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Index one partition of a reads file, starting at the record at offset start and ending
+    /// before the first record at or after offset end. Reads past end that belong to the last
+    /// query group are counted in it, so that query groups are never split.
+    fn build_partition<Record, Reader>(
+        mut reader: Reader,
+        start: u64,
+        end: u64,
+        num_bins: NonZero<usize>,
+        group_by: GroupBy,
+    ) -> Result<PartitionIndex>
+    where
+        Record: ChunkableRecord,
+        Reader: ChunkableRecordReader<Record>,
+    {
+        reader.seek(start)?;
+        let mut record = Record::new();
+        let mut split_index = SplitIndex::with_capacity(num_bins.into());
+        split_index.offset_kind = Some(reader.offset_kind());
+        split_index.group_by = group_by;
+        let Some(result) = reader.read_into(&mut record) else {
+            return Err(anyhow!(
+                "No record at start of partition at offset {start}."
+            ));
+        };
+        result?;
+        if let Some(read_group) = record.read_group() {
+            split_index.count_read_group(read_group);
+        }
+        let mut last_query_name: Vec<u8> = group_by.key(&record).into_owned();
+        let start_hash = first_record_hash(&record);
+        let mut split_record =
+            split_index.start_next_record(start, None, &last_query_name, start_hash);
+        split_record.add_read_length(record.seq_len());
+        let mut next_query_bin: usize = 1;
+        let mut overrun_reads: usize = 0;
+        let mut next_query: Option<QueryStart> = None;
+        let mut end_hash: Option<u32> = None;
+        loop {
+            let offset = reader.tell()?;
+            let Some(result) = reader.read_into(&mut record) else {
+                break;
+            };
+            result?;
+            let key = group_by.key(&record);
+            if offset >= end {
+                end_hash.get_or_insert_with(|| first_record_hash(&record));
+                if *key != *last_query_name {
+                    next_query = Some((offset, key.into_owned(), first_record_hash(&record)));
+                    break;
+                }
+                // finish the last query group. The next partition counts its read groups.
+                split_record.num_reads += 1;
+                split_record.add_read_length(record.seq_len());
+                overrun_reads += 1;
+                continue;
+            }
+            if let Some(read_group) = record.read_group() {
+                split_index.count_read_group(read_group);
+            }
+            if *key == *last_query_name {
+                split_record.num_reads += 1;
+            } else if split_record.num_queries < next_query_bin {
+                last_query_name = key.into_owned();
+                split_record.num_reads += 1;
+                split_record.num_queries += 1;
+            } else {
+                split_record.set_last_qname(&last_query_name);
+                split_index.add_record(split_record);
+                next_query_bin += max(1usize, split_index.num_queries() / num_bins);
+                split_record =
+                    split_index.start_next_record(offset, None, &key, first_record_hash(&record));
+                last_query_name = key.into_owned();
+            }
+            split_record.add_read_length(record.seq_len());
+        }
+        split_record.set_last_qname(&last_query_name);
+        split_index.add_record(split_record);
+        Ok(PartitionIndex {
+            split_index,
+            overrun_reads,
+            next_query,
+            start_hash,
+            end_hash,
+        })
+    }
+
+    /// Merge the indices of consecutive partitions, offsetting their counts. Reads at the start of
+    /// a partition that continue the previous partition's last query group were already counted,
+    /// so they are removed, and the partition's first bin starts at the next query group instead.
+    fn merge_partitions(partitions: Vec<PartitionIndex>, num_bins: NonZero<usize>) -> SplitIndex {
+        let mut merged = SplitIndex::with_capacity(num_bins.into());
+        let mut continued: Option<(usize, Option<QueryStart>)> = None;
+        for partition in partitions {
+            merged.offset_kind = partition.split_index.offset_kind;
+            merged.group_by = partition.split_index.group_by;
+            for (read_group, count) in partition.split_index.read_groups {
+                *merged.read_groups.entry(read_group).or_default() += count;
+            }
+            let mut split_records = partition.split_index.split_records;
+            let (mut skipped_queries, mut skipped_reads) = (0, 0);
+            if let Some((continued_reads, next_query)) = continued.take() {
+                let partition_reads = split_records.last().map_or(0, |record| record.num_reads);
+                if continued_reads >= partition_reads {
+                    // the whole partition continues the query group
+                    continued = Some((continued_reads - partition_reads, next_query));
+                    continue;
                 }
-                if record.qname() == last_query_name {
-                    // inside a query group, do not update bin
-                    split_record.num_reads += 1;
-                } else if split_record.num_queries < next_query_bin {
-                    // new query group, but not time to change the bin yet
-                    last_query_name = record.qname().to_vec();
-                    split_record.num_reads += 1;
-                    split_record.num_queries += 1;
-                } else {
-                    // time for a new bin and query goal
-                    last_query_name = record.qname().to_vec();
-                    split_index.add_record(split_record);
-                    next_query_bin += max(1usize, split_index.num_queries() / num_bins);
-                    split_record = split_index.start_next_record(offset);
+                if split_records[0].num_queries == 1 {
+                    // the first bin only holds the continued query group
+                    split_records.remove(0);
+                } else if let Some((offset, qname, hash)) = next_query {
+                    split_records[0].offset = offset;
+                    split_records[0].first_record_hash = Some(hash);
+                    if let Some((first_qname, _)) = split_records[0].qname_range.as_mut() {
+                        *first_qname = qname;
+                    }
                 }
-                offset = reader.tell()?;
+                (skipped_queries, skipped_reads) = (1, continued_reads);
+            }
+            let (num_previous_queries, num_previous_reads) =
+                (merged.num_queries(), merged.num_reads());
+            for mut split_record in split_records {
+                split_record.num_queries += num_previous_queries - skipped_queries;
+                split_record.num_reads += num_previous_reads - skipped_reads;
+                merged.add_record(split_record);
+            }
+            if partition.overrun_reads > 0 {
+                continued = Some((partition.overrun_reads, partition.next_query));
             }
-            split_index.add_record(split_record);
-        } else {
-            warn!("Empty index: no reads");
         }
-        Ok(split_index)
+        merged
+    }
+
+    /// Combine the indices of BGZF files into the index of their concatenation, without reading
+    /// any reads. Each part is the index of one file, with the compressed and uncompressed sizes
+    /// of the concatenation before it, which shift its offsets; its counts are shifted by the
+    /// totals of the previous parts. Query groups must not continue from one file to the next.
+    pub fn concatenate(parts: Vec<(SplitIndex, u64, u64)>) -> Result<Self> {
+        let mut concatenated =
+            SplitIndex::with_capacity(parts.iter().map(|part| part.0.len()).sum());
+        concatenated.offset_kind = Some(OffsetKind::Virtual);
+        concatenated.group_by = parts
+            .first()
+            .map(|part| part.0.group_by)
+            .unwrap_or_default();
+        for (split_index, compressed_start, uncompressed_start) in parts {
+            if split_index.resume_point.is_some() {
+                return Err(anyhow!(
+                    "Cannot concatenate a checkpoint of a partially built index."
+                ));
+            }
+            if split_index.group_by != concatenated.group_by {
+                return Err(anyhow!(
+                    "Cannot concatenate indices that group reads by {} and {}.",
+                    concatenated.group_by,
+                    split_index.group_by
+                ));
+            }
+            OffsetKind::Virtual.check_seekable(split_index.offset_kind)?;
+            concatenated.approximate |= split_index.approximate;
+            // virtual offsets keep the compressed offset of the block in their upper 48 bits
+            let shift = |offset: u64| offset + (compressed_start << 16);
+            for (read_group, count) in split_index.read_groups {
+                *concatenated.read_groups.entry(read_group).or_default() += count;
+            }
+            concatenated
+                .skipped_ranges
+                .extend(
+                    split_index
+                        .skipped_ranges
+                        .iter()
+                        .map(|skipped_range| SkippedRange {
+                            start: shift(skipped_range.start),
+                            resume: skipped_range.resume.map(shift),
+                        }),
+                );
+            let (num_previous_queries, num_previous_reads) =
+                (concatenated.num_queries(), concatenated.num_reads());
+            for mut split_record in split_index.split_records {
+                split_record.offset = shift(split_record.offset);
+                split_record.uncompressed_offset = split_record
+                    .uncompressed_offset
+                    .map(|offset| offset + uncompressed_start);
+                split_record.num_queries += num_previous_queries;
+                split_record.num_reads += num_previous_reads;
+                concatenated.add_record(split_record);
+            }
+        }
+        Ok(concatenated)
+    }
+
+    /// Get the longest read length of the bins in range merged into one, or None if any of them
+    /// does not record it.
+    fn merged_max_read_length(&self, range: RangeInclusive<usize>) -> Option<usize> {
+        self.split_records[range]
+            .iter()
+            .map(|split_record| split_record.max_read_length)
+            .try_fold(0, |max_read_length, read_length| {
+                Some(max_read_length.max(read_length?))
+            })
     }
 
     /// Downsize via interpolation to roughly evenly spaced bins of the requested size.
@@ -271,17 +1611,24 @@ impl SplitIndex {
             return Ok(self.clone());
         }
         let mut downsized = SplitIndex::with_capacity(num_bins.into());
+        downsized.set_fingerprint(self.fingerprint);
+        downsized.offset_kind = self.offset_kind;
+        downsized.group_by = self.group_by;
+        downsized.approximate = self.approximate;
+        downsized.read_groups = self.read_groups.clone();
+        downsized.skipped_ranges = self.skipped_ranges.clone();
         // the last bin *must* be the same, because it contains the total number of reads and
         // queries. All others are taken as close as possible to evenly-spaced
-        let mut last_offset = self
+        let mut bin_start: SplitRecord = self
             .split_records
             .first()
             .ok_or_else(|| anyhow!("No bins in original index. Should be unreachable."))?
-            .offset;
+            .clone();
+        let mut bin_start_index: usize = 0;
         let mut last_index: Option<usize> = None;
         for bin in 1..num_bins.into() {
             let target_num_queries: usize = self.get_chunk_query_start(bin, num_bins)?;
-            let mut index: usize = bisect_left_by(&self.split_records, |&record| {
+            let mut index: usize = bisect_left_by(&self.split_records, |record| {
                 record.num_queries.cmp(&target_num_queries)
             });
             if index > 0 &&
@@ -297,11 +1644,13 @@ impl SplitIndex {
             {
                 warn!("Original SplitIndex has few bins, so down-sizing is sparser than expected.")
             } else {
-                let mut new_record = self.split_records[index];
-                new_record.offset = last_offset;
+                let mut new_record = self.split_records[index].clone();
+                new_record.set_start_from(&bin_start);
+                new_record.max_read_length = self.merged_max_read_length(bin_start_index..=index);
                 downsized.add_record(new_record);
                 if index + 1 < self.len() {
-                    last_offset = self.split_records[index + 1].offset;
+                    bin_start = self.split_records[index + 1].clone();
+                    bin_start_index = index + 1;
                 } else {
                     // we somehow reached the end of the index early. Warn and return what we have
                     warn!(
@@ -313,21 +1662,27 @@ impl SplitIndex {
             }
         }
         if let Some(last_split_record) = self.split_records.last() {
-            let mut new_record = *last_split_record;
-            new_record.offset = last_offset;
+            let mut new_record = last_split_record.clone();
+            new_record.set_start_from(&bin_start);
+            new_record.max_read_length =
+                self.merged_max_read_length(bin_start_index..=self.len() - 1);
             downsized.add_record(new_record);
         }
         Ok(downsized)
     }
 
-    /// Parse the header and extract the version string.
+    /// Parse the header and extract the version string. The magic number is optional, because
+    /// older files start with the text header.
     fn check_header(bytes: &mut Vec<u8>) -> Result<String> {
+        if bytes.starts_with(&SPLIT_INDEX_MAGIC) {
+            bytes.drain(..SPLIT_INDEX_MAGIC.len());
+        }
         let pos = bytes
             .iter()
             .position(|c| *c == b'\n')
             .ok_or_else(|| anyhow!("Unable to parse header. Corrupted index or wrong file."))?;
         let mut header: Vec<u8> = bytes.drain(..=pos).collect();
-        let expected_front = b"split-index ";
+        let expected_front = HEADER_FRONT;
         if header.len() < expected_front.len() {
             Err(anyhow!(
                 "Unable to parse header. Corrupted index or wrong file."
@@ -346,14 +1701,8 @@ impl SplitIndex {
         }
     }
 
-    /// Deserialize SplitIndex from bytes
-    pub fn deserialize(bytes: &mut Vec<u8>) -> Result<Self> {
-        let version = Self::check_header(bytes)?;
-        // here we could use different loading routines in a hypothetical future with multiple
-        // versions of the index. For now we just assert it's equal to the expected
-        if version != VERSION {
-            return Err(anyhow!("Unknown split-index version: {version}"));
-        }
+    /// Deserialize the number of SplitRecords followed by the records themselves
+    fn deserialize_records(bytes: &mut Vec<u8>) -> Result<Self> {
         let len: usize = deserialize_usize(bytes)?;
         debug!("Got {len} records in SplitIndex");
         let mut split_index = SplitIndex::with_capacity(len);
@@ -363,19 +1712,268 @@ impl SplitIndex {
         Ok(split_index)
     }
 
+    /// Deserialize the sections of a current-version SplitIndex
+    fn deserialize_sections(bytes: &mut Vec<u8>) -> Result<Self> {
+        let mut split_index: Option<SplitIndex> = None;
+        let mut fingerprint: Option<FileFingerprint> = None;
+        let mut offset_kind: Option<OffsetKind> = None;
+        let mut uncompressed_offsets: Option<Vec<u64>> = None;
+        let mut qname_ranges: Option<Vec<(Vec<u8>, Vec<u8>)>> = None;
+        let mut max_read_lengths: Option<Vec<usize>> = None;
+        let mut first_record_hashes: Option<Vec<u32>> = None;
+        let mut read_groups: BTreeMap<Vec<u8>, usize> = BTreeMap::new();
+        let mut skipped_ranges: Vec<SkippedRange> = Vec::new();
+        let mut resume_point: Option<ResumePoint> = None;
+        let mut group_by = GroupBy::QueryName;
+        let mut approximate = false;
+        while !bytes.is_empty() {
+            let (tag, mut payload) = deserialize_section(bytes)?;
+            match tag {
+                RECORDS_SECTION => split_index = Some(Self::deserialize_records(&mut payload)?),
+                GROUP_BY_SECTION => group_by = GroupBy::deserialize(&payload)?,
+                APPROXIMATE_SECTION => approximate = true,
+                FINGERPRINT_SECTION => fingerprint = Some(deserialize_fingerprint(&mut payload)?),
+                OFFSET_KIND_SECTION => {
+                    let code = *payload
+                        .first()
+                        .ok_or_else(|| anyhow!("Empty offset kind section. Index corrupted."))?;
+                    offset_kind = Some(OffsetKind::from_code(code)?);
+                }
+                UNCOMPRESSED_OFFSETS_SECTION => {
+                    let mut offsets = Vec::with_capacity(payload.len() / size_of::<u64>());
+                    while !payload.is_empty() {
+                        offsets.push(deserialize_u64(&mut payload)?);
+                    }
+                    uncompressed_offsets = Some(offsets);
+                }
+                QNAMES_SECTION => {
+                    let mut ranges = Vec::new();
+                    while !payload.is_empty() {
+                        ranges.push((
+                            deserialize_bytes(&mut payload)?,
+                            deserialize_bytes(&mut payload)?,
+                        ));
+                    }
+                    qname_ranges = Some(ranges);
+                }
+                MAX_READ_LENGTHS_SECTION => {
+                    let mut lengths = Vec::with_capacity(payload.len() / size_of::<u64>());
+                    while !payload.is_empty() {
+                        lengths.push(deserialize_u64(&mut payload)?.try_into()?);
+                    }
+                    max_read_lengths = Some(lengths);
+                }
+                FIRST_RECORD_HASHES_SECTION => {
+                    let mut hashes = Vec::with_capacity(payload.len() / size_of::<u32>());
+                    while !payload.is_empty() {
+                        hashes.push(deserialize_u32(&mut payload)?);
+                    }
+                    first_record_hashes = Some(hashes);
+                }
+                READ_GROUPS_SECTION => {
+                    let len = deserialize_u64(&mut payload)?;
+                    for _ in 0..len {
+                        let read_group = deserialize_bytes(&mut payload)?;
+                        read_groups.insert(read_group, deserialize_u64(&mut payload)?.try_into()?);
+                    }
+                }
+                SKIPPED_RANGES_SECTION => {
+                    while !payload.is_empty() {
+                        let start = deserialize_u64(&mut payload)?;
+                        let resume = deserialize_u64(&mut payload)?;
+                        skipped_ranges.push(SkippedRange {
+                            start,
+                            resume: (resume != u64::MAX).then_some(resume),
+                        });
+                    }
+                }
+                CHECKPOINT_SECTION => {
+                    resume_point = Some(ResumePoint {
+                        offset: deserialize_u64(&mut payload)?,
+                        next_query_bin: deserialize_u64(&mut payload)?.try_into()?,
+                    });
+                }
+                _ if tag[0].is_ascii_uppercase() => {
+                    return Err(anyhow!(
+                        "Index has required section {} that this version of split-reads cannot \
+                        read. Update split-reads.",
+                        String::from_utf8_lossy(&tag)
+                    ));
+                }
+                _ => debug!(
+                    "Skipping unknown optional section {}",
+                    String::from_utf8_lossy(&tag)
+                ),
+            }
+        }
+        let mut split_index =
+            split_index.ok_or_else(|| anyhow!("Index has no records section. Index truncated."))?;
+        split_index.set_fingerprint(fingerprint);
+        split_index.offset_kind = offset_kind;
+        split_index.read_groups = read_groups;
+        split_index.skipped_ranges = skipped_ranges;
+        split_index.resume_point = resume_point;
+        split_index.group_by = group_by;
+        split_index.approximate = approximate;
+        if let Some(uncompressed_offsets) = uncompressed_offsets {
+            split_index.set_uncompressed_offsets(uncompressed_offsets)?;
+        }
+        if let Some(qname_ranges) = qname_ranges {
+            split_index.set_qname_ranges(qname_ranges)?;
+        }
+        if let Some(max_read_lengths) = max_read_lengths {
+            split_index.set_max_read_lengths(max_read_lengths)?;
+        }
+        if let Some(first_record_hashes) = first_record_hashes {
+            split_index.set_first_record_hashes(first_record_hashes)?;
+        }
+        Ok(split_index)
+    }
+
+    /// Deserialize SplitIndex from bytes
+    pub fn deserialize(bytes: &mut Vec<u8>) -> Result<Self> {
+        if !Self::is_split_index(bytes) {
+            return Err(anyhow!(
+                "Not a split-index: no split-index magic number or header. Corrupted index or \
+                wrong file."
+            ));
+        }
+        let version = Self::check_header(bytes)?;
+        match version.as_str() {
+            VERSION | VERSION_3_0 => Self::deserialize_sections(bytes),
+            VERSION_2 => {
+                // fixed layout: optional fingerprint, then records
+                let fingerprint = if deserialize_bool(bytes)? {
+                    Some(deserialize_fingerprint(bytes)?)
+                } else {
+                    None
+                };
+                let mut split_index = Self::deserialize_records(bytes)?;
+                split_index.set_fingerprint(fingerprint);
+                Ok(split_index)
+            }
+            // Version 1.0 indices have no fingerprint, but are otherwise identical to 2.0
+            VERSION_1 => Self::deserialize_records(bytes),
+            _ => Err(anyhow!("Unknown split-index version: {version}")),
+        }
+    }
+
+    /// Return true if the (decompressed) bytes look like the start of a serialized SplitIndex:
+    /// the magic number, or the text header that older files start with. Identifies indexes that
+    /// are renamed or piped without their ".si" extension.
+    pub fn is_split_index(bytes: &[u8]) -> bool {
+        bytes.starts_with(&SPLIT_INDEX_MAGIC) || bytes.starts_with(HEADER_FRONT)
+    }
+
     /// Read SplitIndex from the requested path or URL.
     pub fn read<P>(path: P) -> Result<Self>
     where
         P: AsRef<Path>,
     {
-        let mut reader: BgzfReader = match PathType::from_path(path)? {
-            PathType::Pipe => BgzfReader::from_stdin().map_err(|err| anyhow!("{err}")),
-            PathType::FilePath(file_path) => Ok(BgzfReader::from_path(file_path)?),
-            PathType::UrlPath(url) => Ok(BgzfReader::from_url(&url)?),
-        }?;
-        let mut buf: Vec<u8> = Vec::new();
-        reader.read_to_end(&mut buf)?;
-        Self::deserialize(&mut buf)
+        let mut buf = match PathType::from_path(path.as_ref())? {
+            PathType::Pipe => read_decompressed("-")?,
+            PathType::FilePath(file_path) => read_decompressed(file_path)?,
+            PathType::UrlPath(url) => read_decompressed(url.as_str())?,
+        };
+        Self::deserialize(&mut buf).categorize(ErrorCategory::BadIndex)
+    }
+
+    /// Estimate the number of reads that must be scanned past to reach the start of the query
+    /// group with 0-based index start_num_queries, assuming reads are spread evenly over the query
+    /// groups of its bin. Return None if past the end of the index.
+    pub fn estimate_scan_reads(&self, start_num_queries: usize) -> Option<usize> {
+        let split_range = self.get_record_for_num_queries(start_num_queries)?;
+        let bin_queries = split_range.num_end_queries - split_range.num_previous_queries;
+        let bin_reads = split_range.num_end_reads - split_range.num_previous_reads;
+        let skipped_queries = start_num_queries.saturating_sub(split_range.num_previous_queries);
+        Some((bin_reads as u128 * skipped_queries as u128 / bin_queries.max(1) as u128) as usize)
+    }
+
+    /// Estimate the number of reads before the query group with 0-based index num_queries,
+    /// interpolating within its bin. Exact at bin boundaries and at the end of the index.
+    pub fn estimate_num_reads_before(&self, num_queries: usize) -> Option<usize> {
+        let split_range = self.get_record_for_num_queries(num_queries)?;
+        Some(split_range.num_previous_reads + self.estimate_scan_reads(num_queries)?)
+    }
+
+    /// Get the boundaries of the chunk of query groups start_num_queries..stop_num_queries as far
+    /// as the index knows them, without reading the reads file.
+    pub fn chunk_boundaries(
+        &self,
+        start_num_queries: usize,
+        stop_num_queries: usize,
+    ) -> Result<ChunkBoundaries> {
+        if start_num_queries > stop_num_queries || stop_num_queries > self.num_queries() {
+            return Err(anyhow!(
+                "Invalid query range {start_num_queries}..{stop_num_queries} of {} queries.",
+                self.num_queries()
+            ));
+        }
+        let reads_before = |num_queries: usize| {
+            self.estimate_num_reads_before(num_queries)
+                .unwrap_or_else(|| self.num_reads())
+        };
+        let mut boundaries = ChunkBoundaries {
+            start_query: start_num_queries,
+            stop_query: stop_num_queries,
+            seek_offset: None,
+            offset_kind: self.offset_kind,
+            scan_reads: 0,
+            approx_reads: reads_before(stop_num_queries) - reads_before(start_num_queries),
+            approx_bytes: Some(0),
+        };
+        if start_num_queries == stop_num_queries {
+            return Ok(boundaries);
+        }
+        // the bins that extraction seeks to, as locate_query does, or for approximate indices, the
+        // bins that the chunk starts and stops at
+        let (start_bin, stop_bin) = if self.approximate {
+            (
+                self.get_approximate_bin(start_num_queries),
+                self.get_approximate_bin(stop_num_queries),
+            )
+        } else {
+            (
+                self.get_record_for_num_queries(start_num_queries + 1),
+                (stop_num_queries < self.num_queries())
+                    .then(|| self.get_record_for_num_queries(stop_num_queries + 1))
+                    .flatten(),
+            )
+        };
+        let start_bin = start_bin.ok_or_else(|| {
+            anyhow!("Query index {start_num_queries} is past the end of the index.")
+        })?;
+        if self.approximate {
+            // whole bins are read, with no reads to scan past
+            let stop_reads = stop_bin
+                .as_ref()
+                .map_or(self.num_reads(), |stop_bin| stop_bin.num_previous_reads);
+            boundaries.approx_reads = stop_reads - start_bin.num_previous_reads;
+            if stop_bin.as_ref().map(|stop_bin| stop_bin.offset) == Some(start_bin.offset) {
+                // both ends fall at the same bin boundary, so the chunk is empty
+                return Ok(boundaries);
+            }
+        } else {
+            boundaries.scan_reads =
+                reads_before(start_num_queries).saturating_sub(start_bin.num_previous_reads);
+        }
+        boundaries.seek_offset = Some(start_bin.offset);
+        let end_position = match stop_bin {
+            Some(stop_bin) => Some(self.file_position(stop_bin.offset)),
+            None => self.fingerprint.map(|fingerprint| fingerprint.size),
+        };
+        boundaries.approx_bytes = end_position
+            .map(|end_position| end_position.saturating_sub(self.file_position(start_bin.offset)));
+        Ok(boundaries)
+    }
+
+    /// Get the position in the file of an offset in the index: the compressed offset of the block
+    /// for virtual offsets, or the offset itself for byte offsets (or if the kind is unknown).
+    fn file_position(&self, offset: u64) -> u64 {
+        match self.offset_kind {
+            Some(OffsetKind::Virtual) => OffsetKind::split_virtual_offset(offset).0,
+            _ => offset,
+        }
     }
 
     /// Only used in tests, but tested in index tool, so can't have cfg(test)
@@ -388,12 +1986,38 @@ impl SplitIndex {
 impl FastForwardIndex for SplitIndex {
     /// Given a number of query groups, return the SplitRange for the bin containing that number.
     fn get_record_for_num_queries(&self, num_queries: usize) -> Option<SplitRange> {
-        let index: usize = bisect_left_by(&self.split_records, |&record| {
+        let index: usize = bisect_left_by(&self.split_records, |record| {
             record.num_queries.cmp(&num_queries)
         });
         self.index_to_bin_range(index)
     }
 
+    /// Given a number of reads, return the SplitRange for the bin containing that number.
+    fn get_record_for_num_reads(&self, num_reads: usize) -> Option<SplitRange> {
+        let index: usize = bisect_left_by(&self.split_records, |record| {
+            record.num_reads.cmp(&num_reads)
+        });
+        self.index_to_bin_range(index)
+    }
+
+    /// Return true if the counts of the bins are estimates
+    fn is_approximate(&self) -> bool {
+        self.approximate
+    }
+
+    /// Given a number of query groups, return the SplitRange for the bin that a chunk starting
+    /// there starts at: the first bin for 0, otherwise the bin estimated to hold the query group
+    /// with that 0-based index, or None at the end of the index.
+    fn get_approximate_bin(&self, num_queries: usize) -> Option<SplitRange> {
+        if num_queries >= self.num_queries() {
+            None
+        } else if num_queries == 0 {
+            self.index_to_bin_range(0)
+        } else {
+            self.get_record_for_num_queries(num_queries + 1)
+        }
+    }
+
     /// Given a chunk index and number of chunks, return the corresponding number of query groups
     /// that should have already been read before that chunk. It could also be viewed as the 0-based
     /// index of the query starting that chunk.
@@ -419,13 +2043,167 @@ impl FastForwardIndex for SplitIndex {
     }
 }
 
+/// Boundaries of a chunk of query groups as far as the split-index knows them, without reading
+/// the reads file: for checking chunking parameters cheaply and debugging unbalanced chunks.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ChunkBoundaries {
+    /// 0-based index of the first query group of the chunk
+    pub start_query: usize,
+    /// 0-based index of the first query group after the chunk
+    pub stop_query: usize,
+    /// Offset of the bin holding the first query group, which extraction seeks to. None for an
+    /// empty chunk.
+    pub seek_offset: Option<u64>,
+    /// Kind of seek_offset, or None if the index predates recording it
+    pub offset_kind: Option<OffsetKind>,
+    /// Estimated number of reads in that bin before the chunk, which extraction scans past
+    pub scan_reads: usize,
+    /// Estimated number of reads in the chunk
+    pub approx_reads: usize,
+    /// Approximate number of bytes of the file read to extract the chunk: from the start of its
+    /// first bin to the start of the bin holding the query after it, or the end of the file. None
+    /// if the chunk runs to the end of a file whose size the index didn't record.
+    pub approx_bytes: Option<u64>,
+}
+
+impl ChunkBoundaries {
+    /// Format as a single-line JSON object
+    pub fn to_json(&self) -> String {
+        let optional = |value: Option<u64>| value.map_or("null".to_string(), |v| v.to_string());
+        let offset_kind = match self.offset_kind {
+            Some(OffsetKind::Virtual) => "\"virtual\"",
+            Some(OffsetKind::Byte) => "\"byte\"",
+            None => "null",
+        };
+        format!(
+            "{{\"start_query\": {}, \"stop_query\": {}, \"num_queries\": {}, \
+            \"seek_offset\": {}, \"offset_kind\": {offset_kind}, \"scan_reads\": {}, \
+            \"approx_reads\": {}, \"approx_bytes\": {}}}",
+            self.start_query,
+            self.stop_query,
+            self.stop_query - self.start_query,
+            optional(self.seek_offset),
+            self.scan_reads,
+            self.approx_reads,
+            optional(self.approx_bytes),
+        )
+    }
+}
+
+/// Periodically saves the partially built SplitIndex and the reader offset while indexing, so that
+/// long indexing jobs can resume after an interruption instead of starting over.
+pub struct Checkpointer {
+    /// Path of the checkpoint file
+    path: PathBuf,
+    /// Minimum time between checkpoints
+    interval: Duration,
+    /// Time the last checkpoint was saved (or checkpointing started)
+    last_checkpoint: SystemTime,
+    /// Fingerprint of the reads file, so that checkpoints are only resumed on the same file
+    fingerprint: Option<FileFingerprint>,
+    /// Checkpoint loaded by resume, taken when building starts
+    resume_from: Option<SplitIndex>,
+}
+
+impl Checkpointer {
+    /// Create a new Checkpointer, saving to path at most once per interval.
+    pub fn new<P>(path: P, interval: Duration) -> Self
+    where
+        P: AsRef<Path>,
+    {
+        Checkpointer {
+            path: path.as_ref().to_path_buf(),
+            interval,
+            last_checkpoint: SystemTime::now(),
+            fingerprint: None,
+            resume_from: None,
+        }
+    }
+
+    /// Set the fingerprint of the reads file being indexed
+    pub fn fingerprint(&mut self, fingerprint: Option<FileFingerprint>) -> &mut Self {
+        self.fingerprint = fingerprint;
+        self
+    }
+
+    /// Load the checkpoint file, if it exists, so that building resumes from it. Errors if the
+    /// checkpoint was saved while indexing a different reads file.
+    pub fn resume(&mut self) -> Result<&mut Self> {
+        if !self.path.exists() {
+            info!("No checkpoint at {:?}, indexing from the start.", self.path);
+            return Ok(self);
+        }
+        let checkpoint = SplitIndex::read(&self.path)?;
+        if checkpoint.resume_point.is_none() {
+            return Err(anyhow!("{:?} is not an indexing checkpoint.", self.path));
+        }
+        if checkpoint.fingerprint != self.fingerprint {
+            return Err(anyhow!(
+                "Checkpoint {:?} was saved while indexing a different reads file.",
+                self.path
+            ));
+        }
+        self.resume_from = Some(checkpoint);
+        Ok(self)
+    }
+
+    /// Return true if it's time for another checkpoint
+    fn is_due(&mut self) -> bool {
+        let now = SystemTime::now();
+        let is_due = now
+            .duration_since(self.last_checkpoint)
+            .is_ok_and(|elapsed| elapsed >= self.interval);
+        if is_due {
+            self.last_checkpoint = now;
+        }
+        is_due
+    }
+
+    /// Save a checkpoint. Write to a temporary file and then rename it, so that an interruption
+    /// while saving leaves the previous checkpoint intact.
+    fn save(&mut self, mut checkpoint: SplitIndex) -> Result<()> {
+        checkpoint.set_fingerprint(self.fingerprint);
+        let mut temp_path = self.path.clone().into_os_string();
+        temp_path.push(".tmp");
+        checkpoint.rewrite(&temp_path)?;
+        fs::rename(&temp_path, &self.path)
+            .map_err(|err| anyhow!("Saving checkpoint {:?}: {err}", self.path))?;
+        debug!("Saved checkpoint {:?}", self.path);
+        Ok(())
+    }
+
+    /// Remove the checkpoint file once the index is finished, if it exists
+    pub fn remove(&self) -> Result<()> {
+        if self.path.exists() {
+            fs::remove_file(&self.path)
+                .map_err(|err| anyhow!("Removing checkpoint {:?}: {err}", self.path))?;
+        }
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod tests {
+    use crate::{chunkable::ChunkableRecordReader, util::get_fastq_reader};
     use anyhow::Result;
-    use std::u64;
-    use tempfile::NamedTempFile;
+    use noodles_bgzf::io::Writer as NoodlesBgzfWriter;
+    use rand::{Rng, SeedableRng, rngs::StdRng};
+    use std::{fs::File, io::Cursor, io::Write, num::NonZero, time::Duration};
+    use tempfile::{NamedTempFile, TempDir};
 
-    use crate::split_index::{SplitIndex, SplitRecord};
+    use crate::{
+        chunkable::{FastForwardIndex, GroupBy, OffsetKind},
+        fastq::{FastqReader, FastqRecord, FastqWriter},
+        file_fingerprint::FileFingerprint,
+        resync::ResyncFormat,
+        resync::SkippedRange,
+        rng::test_data_seed,
+        split_index::{
+            Checkpointer, ChunkBoundaries, IndexSection, LONG_READ_FACTOR, SPLIT_INDEX_MAGIC,
+            SplitIndex, SplitRecord, VERSION, VERSION_1, VERSION_2, VERSION_3_0,
+            serialize_fingerprint, serialize_section,
+        },
+    };
 
     /// For testing serialization, etc. Create a random nonsensical SplitRecord.
     fn random_split_record<R>(rng: &mut R) -> SplitRecord
@@ -436,19 +2214,83 @@ mod tests {
             offset: rng.random_range(u64::MIN..u64::MAX),
             num_queries: rng.random_range(0..usize::MAX),
             num_reads: rng.random_range(0..usize::MAX),
+            uncompressed_offset: None,
+            qname_range: None,
+            max_read_length: None,
+            first_record_hash: None,
         }
     }
 
     /// For testing serialization, etc. Create a random nonsensical SplitIndex.
     fn random_split_index(num_bins: usize) -> SplitIndex {
-        let mut rng = rand::rng();
+        let mut rng = StdRng::seed_from_u64(test_data_seed());
         let mut split_index = SplitIndex::with_capacity(num_bins);
+        let has_uncompressed_offsets = rng.random_bool(0.5);
+        let has_qnames = rng.random_bool(0.5);
+        let has_max_read_lengths = rng.random_bool(0.5);
+        let has_first_record_hashes = rng.random_bool(0.5);
         for _ in 0..num_bins {
-            split_index.add_record(random_split_record(&mut rng));
+            let mut split_record = random_split_record(&mut rng);
+            if has_uncompressed_offsets {
+                split_record.uncompressed_offset = Some(rng.random_range(u64::MIN..u64::MAX));
+            }
+            if has_qnames {
+                let first_qname = format!("read{}", rng.random_range(0..1000000)).into_bytes();
+                let last_qname = format!("read{}", rng.random_range(0..1000000)).into_bytes();
+                split_record.qname_range = Some((first_qname, last_qname));
+            }
+            if has_max_read_lengths {
+                split_record.max_read_length = Some(rng.random_range(0..1000000));
+            }
+            if has_first_record_hashes {
+                split_record.first_record_hash = Some(rng.random());
+            }
+            split_index.add_record(split_record);
+        }
+        if rng.random_bool(0.5) {
+            for read_group in 0..rng.random_range(1..5) {
+                split_index.read_groups.insert(
+                    format!("rg{read_group}").into_bytes(),
+                    rng.random_range(0..1000000),
+                );
+            }
+        }
+        if rng.random_bool(0.5) {
+            for _ in 0..rng.random_range(1..5) {
+                let start = rng.random_range(u64::MIN..u64::MAX - 1);
+                split_index.skipped_ranges.push(SkippedRange {
+                    start,
+                    resume: rng
+                        .random_bool(0.5)
+                        .then(|| rng.random_range(start..u64::MAX)),
+                });
+            }
         }
+        let checksum = if rng.random_bool(0.5) {
+            Some(rng.random_range(u64::MIN..u64::MAX))
+        } else {
+            None
+        };
+        split_index.set_fingerprint(Some(FileFingerprint {
+            size: rng.random_range(u64::MIN..u64::MAX),
+            checksum,
+        }));
         split_index
     }
 
+    /// Older index versions do not store optional sections, so remove them before comparing.
+    fn clear_optional_sections(split_index: &mut SplitIndex) {
+        for split_record in split_index.split_records.iter_mut() {
+            split_record.uncompressed_offset = None;
+        }
+        split_index.clear_qnames();
+        split_index.clear_max_read_lengths();
+        split_index.clear_first_record_hashes();
+        split_index.offset_kind = None;
+        split_index.read_groups.clear();
+        split_index.skipped_ranges.clear();
+    }
+
     /// Test that serializing then deserializing recapitulate the original SplitIndex.
     #[test]
     fn test_serialize_round_trip() -> Result<()> {
@@ -468,4 +2310,623 @@ mod tests {
         assert!(deserialized == split_index);
         Ok(())
     }
+
+    /// Test that indices at non-UTF-8 paths can be read.
+    #[test]
+    fn test_read_non_utf8_path() -> Result<()> {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+        let temp_dir = TempDir::new().expect("Could not create temp dir");
+        let written_path = temp_dir.path().join("index.si");
+        let index_path = temp_dir.path().join(OsStr::from_bytes(b"index\xff.si"));
+        let split_index: SplitIndex = random_split_index(100);
+        split_index.clone().write(&written_path)?;
+        std::fs::rename(&written_path, &index_path)?;
+        let deserialized = SplitIndex::read(&index_path)?;
+        assert!(deserialized == split_index);
+        Ok(())
+    }
+
+    /// Test that version 1.0 indices (without fingerprint) can still be read.
+    #[test]
+    fn test_deserialize_version_1() -> Result<()> {
+        let mut split_index: SplitIndex = random_split_index(100);
+        split_index.set_fingerprint(None);
+        clear_optional_sections(&mut split_index);
+        let mut bytes: Vec<u8> = format!("split-index {VERSION_1}\n").as_bytes().to_vec();
+        bytes.extend(&split_index.len().to_le_bytes());
+        for split_record in split_index.split_records.iter() {
+            split_record.serialize(&mut bytes);
+        }
+        let deserialized = SplitIndex::deserialize(&mut bytes)?;
+        assert!(deserialized == split_index);
+        Ok(())
+    }
+
+    /// Test that version 2.0 indices (fixed layout with fingerprint) can still be read.
+    #[test]
+    fn test_deserialize_version_2() -> Result<()> {
+        let mut split_index: SplitIndex = random_split_index(100);
+        clear_optional_sections(&mut split_index);
+        let mut bytes: Vec<u8> = format!("split-index {VERSION_2}\n").as_bytes().to_vec();
+        bytes.push(1u8);
+        serialize_fingerprint(&split_index.fingerprint.unwrap(), &mut bytes);
+        bytes.extend(&split_index.len().to_le_bytes());
+        for split_record in split_index.split_records.iter() {
+            split_record.serialize(&mut bytes);
+        }
+        let deserialized = SplitIndex::deserialize(&mut bytes)?;
+        assert!(deserialized == split_index);
+        Ok(())
+    }
+
+    /// Test that indices start with the magic number, that indices from before it was added can
+    /// still be read, and that other files are recognized as not indices.
+    #[test]
+    fn test_magic_number() -> Result<()> {
+        let split_index: SplitIndex = random_split_index(100);
+        let bytes = split_index.clone().serialize();
+        assert!(bytes.starts_with(&SPLIT_INDEX_MAGIC));
+        assert!(SplitIndex::is_split_index(&bytes));
+
+        let header = format!("split-index {VERSION}\n");
+        assert!(bytes[SPLIT_INDEX_MAGIC.len()..].starts_with(header.as_bytes()));
+
+        // version 3.0 indices have the same sections, without the magic number
+        let mut legacy = format!("split-index {VERSION_3_0}\n").into_bytes();
+        legacy.extend(&bytes[SPLIT_INDEX_MAGIC.len() + header.len()..]);
+        assert!(SplitIndex::is_split_index(&legacy));
+        assert!(SplitIndex::deserialize(&mut legacy)? == split_index);
+
+        let mut fastq = b"@read1\nACGT\n+\nIIII\n".to_vec();
+        assert!(!SplitIndex::is_split_index(&fastq));
+        match SplitIndex::deserialize(&mut fastq) {
+            Ok(_) => panic!("FASTQ should not deserialize as an index"),
+            Err(err) => assert!(err.to_string().starts_with("Not a split-index"), "{err}"),
+        }
+        Ok(())
+    }
+
+    /// Test that unknown optional sections are skipped, but unknown required sections are errors.
+    #[test]
+    fn test_unknown_sections() -> Result<()> {
+        let split_index: SplitIndex = random_split_index(100);
+        let mut bytes = split_index.clone().serialize();
+        serialize_section(*b"optn", b"some future data", &mut bytes);
+        let deserialized = SplitIndex::deserialize(&mut bytes.clone())?;
+        assert!(deserialized == split_index);
+
+        serialize_section(*b"REQD", b"some future data", &mut bytes);
+        match SplitIndex::deserialize(&mut bytes) {
+            Ok(_) => panic!("Unknown required section should be an error"),
+            Err(err) => assert!(err.to_string().contains("REQD")),
+        }
+
+        // the records section is required
+        let mut bytes: Vec<u8> = format!("split-index {VERSION}\n").as_bytes().to_vec();
+        serialize_section(*b"optn", b"some future data", &mut bytes);
+        assert!(SplitIndex::deserialize(&mut bytes).is_err());
+        Ok(())
+    }
+
+    /// Test that indexing an uncompressed FASTQ records uncompressed offsets equal to the file
+    /// offsets, and that they survive downsizing and serialization.
+    #[test]
+    fn test_uncompressed_offsets() -> Result<()> {
+        let fastq: Vec<u8> = (0..1000)
+            .flat_map(|index| format!("@read{index}\nACGT\n+\nIIII\n").into_bytes())
+            .collect();
+        let reader = FastqReader::new(Cursor::new(fastq));
+        let split_index = SplitIndex::build::<FastqRecord, _, FastqWriter<Vec<u8>>>(
+            reader,
+            None,
+            NonZero::new(100).unwrap(),
+            u64::MAX,
+        )?
+        .downsize_reads(NonZero::new(10).unwrap())?;
+        assert!(split_index.has_uncompressed_offsets());
+        let deserialized = SplitIndex::deserialize(&mut split_index.clone().serialize())?;
+        assert!(deserialized == split_index);
+        let uncompressed = split_index.clone().into_uncompressed(None)?;
+        assert!(uncompressed.fingerprint().is_none());
+        for (split_record, uncompressed_record) in split_index
+            .split_records
+            .iter()
+            .zip(uncompressed.split_records.iter())
+        {
+            assert_eq!(split_record.offset, uncompressed_record.offset);
+        }
+
+        let mut no_offsets = split_index;
+        clear_optional_sections(&mut no_offsets);
+        assert!(no_offsets.into_uncompressed(None).is_err());
+        Ok(())
+    }
+
+    /// Test that the offset kind is recorded and serialized, and that readers refuse to seek to
+    /// offsets of another kind.
+    #[test]
+    fn test_offset_kind() -> Result<()> {
+        let fastq: Vec<u8> = (0..100)
+            .flat_map(|index| format!("@read{index}\nACGT\n+\nIIII\n").into_bytes())
+            .collect();
+        let mut split_index = SplitIndex::build::<FastqRecord, _, FastqWriter<Vec<u8>>>(
+            FastqReader::new(Cursor::new(fastq.clone())),
+            None,
+            NonZero::new(10).unwrap(),
+            u64::MAX,
+        )?;
+        assert_eq!(split_index.offset_kind(), Some(OffsetKind::Byte));
+        let deserialized = SplitIndex::deserialize(&mut split_index.clone().serialize())?;
+        assert!(deserialized == split_index);
+
+        let mut reader = FastqReader::new(Cursor::new(fastq.clone()));
+        let mut writer = FastqWriter::new(Vec::new());
+        reader.write_queries(split_index.clone(), 50, 60, &mut writer)?;
+        split_index.set_offset_kind(Some(OffsetKind::Virtual));
+        let mut reader = FastqReader::new(Cursor::new(fastq));
+        let err = reader
+            .write_queries(split_index.clone(), 50, 60, &mut writer)
+            .unwrap_err();
+        assert!(err.to_string().contains("BGZF virtual offsets"), "{err}");
+        assert!(SplitIndex::concatenate(vec![(split_index, 0, 0)]).is_ok());
+
+        assert_eq!(OffsetKind::virtual_offset(3, 7)?, (3 << 16) | 7);
+        assert_eq!(OffsetKind::split_virtual_offset((3 << 16) | 7), (3, 7));
+        assert!(OffsetKind::virtual_offset(1 << 48, 0).is_err());
+        Ok(())
+    }
+
+    /// Test that query name ranges are recorded per bin, survive downsizing, and prune bins only
+    /// when the bins are sorted by name.
+    #[test]
+    fn test_bins_for_qnames() -> Result<()> {
+        let fastq: Vec<u8> = (0..1000)
+            .flat_map(|index| format!("@read{index:04}\nACGT\n+\nIIII\n").into_bytes())
+            .collect();
+        let reader = FastqReader::new(Cursor::new(fastq));
+        let split_index = SplitIndex::build::<FastqRecord, _, FastqWriter<Vec<u8>>>(
+            reader,
+            None,
+            NonZero::new(100).unwrap(),
+            u64::MAX,
+        )?
+        .downsize_reads(NonZero::new(10).unwrap())?;
+        assert!(split_index.has_qnames());
+        let deserialized = SplitIndex::deserialize(&mut split_index.clone().serialize())?;
+        assert!(deserialized == split_index);
+
+        let first = split_index.split_records.first().unwrap();
+        assert_eq!(first.qname_range.as_ref().unwrap().0, b"read0000");
+        let last = split_index.split_records.last().unwrap();
+        assert_eq!(last.qname_range.as_ref().unwrap().1, b"read0999");
+
+        let qnames: [&[u8]; 4] = [b"read0000", b"read0999", b"read0998", b"missing"];
+        let bins = split_index.bins_for_qnames(qnames).unwrap();
+        assert_eq!(bins.len(), 2);
+        assert_eq!(bins[0].num_previous_queries, 0);
+        assert_eq!(bins[1].num_end_queries, 1000);
+
+        let bin = split_index.find_query(b"read0500")?.unwrap();
+        assert!(bin.num_previous_queries <= 500 && 500 < bin.num_end_queries);
+        assert!(split_index.find_query(b"missing")?.is_none());
+        assert!(split_index.find_query(b"read")?.is_none());
+
+        // unsorted bins cannot be pruned
+        let mut unsorted = split_index.clone();
+        unsorted.split_records.swap(0, 1);
+        assert!(unsorted.bins_for_qnames(qnames).is_none());
+        assert!(unsorted.find_query(b"read0500").is_err());
+
+        // neither can bins without query names
+        let mut no_qnames = split_index;
+        no_qnames.clear_qnames();
+        assert!(!no_qnames.has_qnames());
+        assert!(no_qnames.bins_for_qnames(qnames).is_none());
+        Ok(())
+    }
+
+    /// Test that sections are reported as serialized, and that only optional sections can be
+    /// removed.
+    #[test]
+    fn test_remove_sections() -> Result<()> {
+        let mut split_index: SplitIndex = random_split_index(100);
+        split_index.set_offset_kind(Some(OffsetKind::Virtual));
+        split_index.set_fingerprint(Some(FileFingerprint {
+            size: 1,
+            checksum: Some(2),
+        }));
+        let sections = split_index.sections();
+        assert_eq!(sections[0], IndexSection::Records);
+        assert!(sections.contains(&IndexSection::Fingerprint));
+        assert_eq!(
+            SplitIndex::deserialize(&mut split_index.clone().serialize())?.sections(),
+            sections
+        );
+        for section in sections {
+            match section {
+                IndexSection::Records | IndexSection::SkippedRanges => {
+                    assert!(split_index.remove_section(section).is_err());
+                }
+                _ => split_index.remove_section(section)?,
+            }
+        }
+        let mut expected = vec![IndexSection::Records];
+        if !split_index.skipped_ranges.is_empty() {
+            expected.push(IndexSection::SkippedRanges);
+        }
+        assert_eq!(split_index.sections(), expected);
+        assert_eq!(
+            SplitIndex::deserialize(&mut split_index.clone().serialize())?.sections(),
+            expected
+        );
+        assert!(
+            split_index
+                .remove_section(IndexSection::Checkpoint)
+                .is_err()
+        );
+        Ok(())
+    }
+
+    /// Test that the longest read of each bin is recorded, merged when downsizing, and found by
+    /// query range and as an outlier.
+    #[test]
+    fn test_max_read_lengths() -> Result<()> {
+        // every read is 4 bases, except one 100-base read in query 512
+        let fastq: Vec<u8> = (0..1000)
+            .flat_map(|index| {
+                let length = if index == 512 { 100 } else { 4 };
+                format!(
+                    "@read{index:04}\n{}\n+\n{}\n",
+                    "A".repeat(length),
+                    "I".repeat(length)
+                )
+                .into_bytes()
+            })
+            .collect();
+        let reader = FastqReader::new(Cursor::new(fastq));
+        let split_index = SplitIndex::build::<FastqRecord, _, FastqWriter<Vec<u8>>>(
+            reader,
+            None,
+            NonZero::new(100).unwrap(),
+            u64::MAX,
+        )?
+        .downsize_reads(NonZero::new(10).unwrap())?;
+        assert!(split_index.has_max_read_lengths());
+        let deserialized = SplitIndex::deserialize(&mut split_index.clone().serialize())?;
+        assert!(deserialized == split_index);
+
+        assert_eq!(split_index.max_read_length(0, 1000), Some(100));
+        assert_eq!(split_index.max_read_length(512, 513), Some(100));
+        assert_eq!(split_index.max_read_length(0, 100), Some(4));
+        assert_eq!(split_index.max_read_length(900, 1000), Some(4));
+        assert_eq!(
+            split_index.long_read_threshold(),
+            Some(4 * LONG_READ_FACTOR)
+        );
+        let long_read_bins = split_index.long_read_bins(40);
+        assert_eq!(long_read_bins.len(), 1);
+        let (start, stop) = long_read_bins[0];
+        assert!(start <= 512 && 512 < stop, "{start}..{stop}");
+        assert!(split_index.long_read_bins(100).is_empty());
+
+        let mut no_lengths = split_index;
+        no_lengths.clear_max_read_lengths();
+        assert!(!no_lengths.has_max_read_lengths());
+        assert_eq!(no_lengths.max_read_length(0, 1000), None);
+        assert_eq!(no_lengths.long_read_threshold(), None);
+        assert!(no_lengths.long_read_bins(0).is_empty());
+        Ok(())
+    }
+
+    /// Test that the estimated scan overhead counts the reads before the start query in its bin.
+    #[test]
+    fn test_estimate_scan_reads() -> Result<()> {
+        let fastq: Vec<u8> = (0..1000)
+            .flat_map(|index| format!("@read{index:04}\nACGT\n+\nIIII\n").into_bytes())
+            .collect();
+        let split_index = SplitIndex::build::<FastqRecord, _, FastqWriter<Vec<u8>>>(
+            FastqReader::new(Cursor::new(fastq)),
+            None,
+            NonZero::new(100).unwrap(),
+            u64::MAX,
+        )?
+        .downsize_reads(NonZero::new(10).unwrap())?;
+        assert_eq!(split_index.estimate_scan_reads(0), Some(0));
+        for start_num_queries in [1, 99, 250, 555, 999] {
+            // one read per query, so the estimate is exact
+            let bin = split_index
+                .get_record_for_num_queries(start_num_queries)
+                .unwrap();
+            assert_eq!(
+                split_index.estimate_scan_reads(start_num_queries),
+                Some(start_num_queries - bin.num_previous_queries)
+            );
+        }
+        assert_eq!(split_index.estimate_scan_reads(1001), None);
+        assert_eq!(split_index.estimate_num_reads_before(0), Some(0));
+        assert_eq!(split_index.estimate_num_reads_before(555), Some(555));
+        assert_eq!(split_index.estimate_num_reads_before(1000), Some(1000));
+        Ok(())
+    }
+
+    /// Test that chunk boundaries seek to the bin holding the first query, and span the bytes up
+    /// to the bin holding the query after the chunk.
+    #[test]
+    fn test_chunk_boundaries() -> Result<()> {
+        let fastq: Vec<u8> = (0..1000)
+            .flat_map(|index| format!("@read{index:04}\nACGT\n+\nIIII\n").into_bytes())
+            .collect();
+        let mut split_index = SplitIndex::build::<FastqRecord, _, FastqWriter<Vec<u8>>>(
+            FastqReader::new(Cursor::new(fastq)),
+            None,
+            NonZero::new(100).unwrap(),
+            u64::MAX,
+        )?
+        .downsize_reads(NonZero::new(10).unwrap())?;
+        split_index.set_offset_kind(Some(OffsetKind::Byte));
+        // one read of 22 bytes per query
+        let start_bin = split_index.get_record_for_num_queries(251).unwrap();
+        let stop_bin = split_index.get_record_for_num_queries(501).unwrap();
+        assert_eq!(start_bin.offset, 22 * start_bin.num_previous_reads as u64);
+        let boundaries = split_index.chunk_boundaries(250, 500)?;
+        assert_eq!(
+            boundaries,
+            ChunkBoundaries {
+                start_query: 250,
+                stop_query: 500,
+                seek_offset: Some(start_bin.offset),
+                offset_kind: Some(OffsetKind::Byte),
+                scan_reads: 250 - start_bin.num_previous_reads,
+                approx_reads: 250,
+                approx_bytes: Some(stop_bin.offset - start_bin.offset),
+            }
+        );
+        assert!(boundaries.to_json().contains(&format!(
+            "\"seek_offset\": {}, \"offset_kind\": \"byte\"",
+            start_bin.offset
+        )));
+        let to_end = split_index.chunk_boundaries(900, 1000)?;
+        assert_eq!(to_end.approx_bytes, None);
+        assert_eq!(to_end.approx_reads, 100);
+        let empty = split_index.chunk_boundaries(1000, 1000)?;
+        assert_eq!((empty.seek_offset, empty.approx_reads), (None, 0));
+        assert!(split_index.chunk_boundaries(10, 1001).is_err());
+        Ok(())
+    }
+
+    /// Test that building from a checkpoint taken partway through (here, at the end of a
+    /// truncated copy, splitting a query group) gives the same bins as building in one pass.
+    #[test]
+    fn test_resume_from_checkpoint() -> Result<()> {
+        let records: Vec<Vec<u8>> = (0..200)
+            .map(|read| format!("@read{}\nACGT\n+\nIIII\n", read / 2).into_bytes())
+            .collect();
+        let fastq = records.concat();
+        let truncated_len = records[..37].concat().len();
+        let num_bins = NonZero::new(10).unwrap();
+        let temp_dir = TempDir::new()?;
+        let checkpoint_path = temp_dir.path().join("reads.fastq.si.ckpt");
+
+        let mut full = SplitIndex::build::<FastqRecord, _, FastqWriter<Vec<u8>>>(
+            FastqReader::new(Cursor::new(fastq.clone())),
+            None,
+            num_bins,
+            u64::MAX,
+        )?;
+        SplitIndex::build_resumable::<FastqRecord, _, FastqWriter<Vec<u8>>>(
+            FastqReader::new(Cursor::new(fastq[..truncated_len].to_vec())),
+            None,
+            num_bins,
+            u64::MAX,
+            GroupBy::QueryName,
+            Some(&mut Checkpointer::new(&checkpoint_path, Duration::ZERO)),
+            None,
+        )?;
+        let mut resumed = SplitIndex::build_resumable::<FastqRecord, _, FastqWriter<Vec<u8>>>(
+            FastqReader::new(Cursor::new(fastq)),
+            None,
+            num_bins,
+            u64::MAX,
+            GroupBy::QueryName,
+            Some(Checkpointer::new(&checkpoint_path, Duration::MAX).resume()?),
+            None,
+        )?;
+        assert_eq!(resumed.num_reads(), 200);
+        assert_eq!(resumed.num_queries(), 100);
+        // the reader can't track uncompressed offsets after seeking to the checkpoint
+        clear_optional_sections(&mut full);
+        clear_optional_sections(&mut resumed);
+        assert!(resumed == full);
+        Ok(())
+    }
+
+    /// Test that building in parallel partitions counts the same reads and queries as building in
+    /// one pass, and that its bins extract every record exactly once, including a query group that
+    /// spans several partitions.
+    #[test]
+    fn test_build_parallel() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let path = temp_dir.path().join("reads.fastq.gz");
+        let mut text = String::new();
+        let mut writer = NoodlesBgzfWriter::new(File::create(&path)?);
+        for read in 0..600 {
+            let qname = if (200..320).contains(&read) {
+                "long".to_string()
+            } else {
+                format!("q{}", read / 3)
+            };
+            let fastq = format!("@{qname}\nACGTACGT\n+\nIIIIIIII\n");
+            writer.write_all(fastq.as_bytes())?;
+            text.push_str(&fastq);
+            if read % 7 == 6 {
+                writer.flush()?;
+            }
+        }
+        writer.finish()?;
+        let threads = NonZero::new(1).unwrap();
+        let num_bins = NonZero::new(20).unwrap();
+
+        let sequential = SplitIndex::build::<FastqRecord, _, FastqWriter<Vec<u8>>>(
+            get_fastq_reader(&path, threads)?,
+            None,
+            num_bins,
+            u64::MAX,
+        )?;
+        let parallel = SplitIndex::build_parallel(
+            &path,
+            ResyncFormat::Fastq,
+            || get_fastq_reader(&path, threads),
+            NonZero::new(5).unwrap(),
+            num_bins,
+            GroupBy::QueryName,
+        )?;
+        assert_eq!(parallel.num_reads(), sequential.num_reads());
+        assert_eq!(parallel.num_queries(), sequential.num_queries());
+        assert!(parallel.split_records.windows(2).all(
+            |pair| pair[0].offset < pair[1].offset && pair[0].num_queries < pair[1].num_queries
+        ));
+
+        let mut extracted = Vec::new();
+        for query_start in (0..parallel.num_queries()).step_by(17) {
+            let query_stop = (query_start + 17).min(parallel.num_queries());
+            get_fastq_reader(&path, threads)?.write_queries(
+                parallel.clone(),
+                query_start,
+                query_stop,
+                &mut FastqWriter::new(&mut extracted),
+            )?;
+        }
+        assert_eq!(String::from_utf8(extracted)?, text);
+        Ok(())
+    }
+
+    /// Test that partitions must adjoin: one starting at the record where the previous ended
+    /// passes, and one starting a record later, or the last one ending before the end, fails.
+    #[test]
+    fn test_check_partitions_adjoin() -> Result<()> {
+        let fastq: String = (0..30)
+            .map(|read| format!("@q{read:02}\nACGT\n+\nIIII\n"))
+            .collect();
+        let record_offset = |read: u64| read * 17;
+        let partition = |start: u64, end: u64| {
+            SplitIndex::build_partition::<FastqRecord, _>(
+                FastqReader::new(Cursor::new(fastq.clone())),
+                record_offset(start),
+                end,
+                NonZero::new(4).unwrap(),
+                GroupBy::QueryName,
+            )
+        };
+        let adjoining = vec![
+            partition(0, record_offset(10))?,
+            partition(10, record_offset(20))?,
+            partition(20, u64::MAX)?,
+        ];
+        SplitIndex::check_partitions_adjoin(&adjoining)?;
+        let gap = vec![partition(0, record_offset(10))?, partition(11, u64::MAX)?];
+        let err = SplitIndex::check_partitions_adjoin(&gap).unwrap_err();
+        assert!(err.to_string().contains("Partition 1"), "{err}");
+        let short = vec![partition(25, u64::MAX)?, partition(26, u64::MAX)?];
+        assert!(SplitIndex::check_partitions_adjoin(&short).is_err());
+        Ok(())
+    }
+
+    /// Test that the mates of old-style paired FASTQ, named with "/1" and "/2" suffixes, are
+    /// grouped into one query, so that bins never split a pair.
+    #[test]
+    fn test_build_paired_fastq() -> Result<()> {
+        let fastq: String = (0..50)
+            .flat_map(|pair| [1, 2].map(|mate| format!("@pair{pair:02}/{mate}\nACGT\n+\nIIII\n")))
+            .collect();
+        let split_index = SplitIndex::build::<FastqRecord, _, FastqWriter<Vec<u8>>>(
+            FastqReader::new(Cursor::new(fastq)),
+            None,
+            NonZero::new(7).unwrap(),
+            u64::MAX,
+        )?;
+        assert_eq!(split_index.num_reads(), 100);
+        assert_eq!(split_index.num_queries(), 50);
+        assert!(
+            split_index
+                .split_records
+                .iter()
+                .all(|split_record| split_record.num_reads == 2 * split_record.num_queries)
+        );
+        Ok(())
+    }
+
+    /// Test that reads grouped by a query name prefix or a tag are counted and extracted as whole
+    /// groups, and that the grouping survives serialization but prevents searching by name.
+    #[test]
+    fn test_group_by() -> Result<()> {
+        let groups: Vec<String> = (0..60)
+            .map(|umi| {
+                (0..1 + umi % 4)
+                    .map(|read| format!("@umi{umi:02}:read{read}\nACGT\n+\nIIII\n"))
+                    .collect()
+            })
+            .collect();
+        let fastq = groups.concat();
+        let num_bins = NonZero::new(20).unwrap();
+        let split_index = SplitIndex::build_resumable::<FastqRecord, _, FastqWriter<Vec<u8>>>(
+            FastqReader::new(Cursor::new(fastq.clone())),
+            None,
+            num_bins,
+            u64::MAX,
+            GroupBy::QnamePrefix(b':'),
+            None,
+            None,
+        )?;
+        assert_eq!(split_index.num_queries(), 60);
+        assert_eq!(split_index.num_reads(), 150);
+        assert_eq!(split_index.group_by(), GroupBy::QnamePrefix(b':'));
+        assert!(split_index.sections().contains(&IndexSection::GroupBy));
+        assert!(split_index.find_query(b"umi00:read0").is_err());
+        assert!(SplitIndex::deserialize(&mut split_index.clone().serialize())? == split_index);
+        assert!(
+            split_index
+                .clone()
+                .remove_section(IndexSection::GroupBy)
+                .is_err()
+        );
+
+        for query_start in (0..60).step_by(7) {
+            let query_stop = (query_start + 7).min(60);
+            let mut extracted = Vec::new();
+            FastqReader::new(Cursor::new(fastq.clone())).write_queries(
+                split_index.clone(),
+                query_start,
+                query_stop,
+                &mut FastqWriter::new(&mut extracted),
+            )?;
+            assert_eq!(
+                String::from_utf8(extracted)?,
+                groups[query_start..query_stop].concat()
+            );
+        }
+
+        // reads without the tag are grouped by query name
+        let fastq: String = (0..30)
+            .map(|read| match read {
+                0..10 => format!("@r{read} CB:Z:AAAC\nACGT\n+\nIIII\n"),
+                10..20 => format!("@r{read}\nACGT\n+\nIIII\n"),
+                _ => format!("@r{read} CB:Z:GGTA\nACGT\n+\nIIII\n"),
+            })
+            .collect();
+        let split_index = SplitIndex::build_resumable::<FastqRecord, _, FastqWriter<Vec<u8>>>(
+            FastqReader::new(Cursor::new(fastq)),
+            None,
+            num_bins,
+            u64::MAX,
+            "tag:CB".parse()?,
+            None,
+            None,
+        )?;
+        assert_eq!(split_index.num_queries(), 12);
+        assert_eq!(split_index.group_by().to_string(), "tag:CB");
+        assert!("tag:C".parse::<GroupBy>().is_err());
+        assert!("qname-prefix:".parse::<GroupBy>().is_err());
+        Ok(())
+    }
 }
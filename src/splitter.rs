@@ -0,0 +1,415 @@
+use crate::{
+    chunkable::{ChunkResult, ChunkableRecordReader, FastForwardIndex},
+    fastq::{FastqRecord, FastqWriter},
+    file_fingerprint::FileFingerprint,
+    output_spec::OutputSpec,
+    path_type::PathType,
+    sam_writer_spec::{OutputFormat, SamWriterSpec},
+    split_index::{ChunkBoundaries, SPLIT_INDEX_EXTENSION, SplitIndex},
+    thread_budget::available_cpus,
+    transform::TransformPipeline,
+    translate_options::{PairEnd, TranslateOptions},
+    util::{RecordType, get_fastq_reader, get_fastq_writer, get_sam_reader},
+    validation::{ValidatingReader, ValidationLevel},
+};
+use anyhow::{Result, anyhow};
+use rust_htslib::bam::{Header, Record as BamRecord, Writer as BamWriter};
+use std::{
+    num::NonZero,
+    path::{Path, PathBuf},
+};
+
+/// Entry point for splitting a reads file (FASTQ or SAM/BAM/CRAM) into chunks from Rust, without
+/// the command line. Open the reads file, build or load its split-index, then pick a chunk and
+/// write it:
+///
+/// ```no_run
+/// # use split_reads::splitter::Splitter;
+/// # use std::num::NonZero;
+/// # fn main() -> anyhow::Result<()> {
+/// let mut splitter = Splitter::open("reads.bam")?;
+/// splitter.index(NonZero::new(1000).unwrap())?;
+/// for chunk_index in 0..4 {
+///     let chunk = splitter.chunk(chunk_index, NonZero::new(4).unwrap())?;
+///     chunk.write_to(format!("reads.{chunk_index}.bam"))?;
+/// }
+/// # Ok(())
+/// # }
+/// ```
+///
+/// Chunks never split query groups. Output format is chosen by the output path extension, so a
+/// BAM can be chunked straight to FASTQ and vice versa.
+pub struct Splitter {
+    input: PathBuf,
+    record_type: RecordType,
+    reference_fasta: Option<PathBuf>,
+    threads: NonZero<usize>,
+    compression: Option<u32>,
+    transforms: TransformPipeline,
+    split_index: Option<SplitIndex>,
+}
+
+impl Splitter {
+    /// Open the reads file at input, a local path or URL. Its type is detected from the content of
+    /// local files, and taken from the extension of URLs.
+    /// Nothing is read until it is indexed or a chunk is written.
+    pub fn open<P>(input: P) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let record_type = RecordType::detect(input.as_ref())?.ok_or_else(|| {
+            anyhow!(
+                "Cannot tell the type of {:?}: it must be FASTQ or SAM/BAM/CRAM.",
+                input.as_ref()
+            )
+        })?;
+        Ok(Splitter {
+            input: input.as_ref().to_path_buf(),
+            record_type,
+            reference_fasta: None,
+            threads: available_cpus(),
+            compression: None,
+            transforms: TransformPipeline::new(),
+            split_index: None,
+        })
+    }
+
+    /// Set the reference FASTA, required to read or write CRAM.
+    pub fn reference_fasta<P>(&mut self, reference_fasta: Option<P>) -> &mut Self
+    where
+        P: AsRef<Path>,
+    {
+        self.reference_fasta = reference_fasta.map(|path| path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Set the number of threads for each of the reading and writing thread pools. Defaults to
+    /// all available CPUs.
+    pub fn threads(&mut self, threads: NonZero<usize>) -> &mut Self {
+        self.threads = threads;
+        self
+    }
+
+    /// Set the compression level of compressed chunk output, or None for the default level.
+    pub fn compression(&mut self, compression: Option<u32>) -> &mut Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Set the transforms applied to every read of a chunk as it is written, e.g. filters and
+    /// trimming. Each chunk starts from a fresh copy, so their summaries count one chunk.
+    pub fn transforms(&mut self, transforms: TransformPipeline) -> &mut Self {
+        self.transforms = transforms;
+        self
+    }
+
+    /// Get the type of records in the reads file
+    pub fn record_type(&self) -> RecordType {
+        self.record_type
+    }
+
+    /// Index the reads file, reading it once, into num_bins bins (fewer for small files). Chunks
+    /// can be extracted from up to num_bins chunks without scanning past other chunks' reads.
+    pub fn index(&mut self, num_bins: NonZero<usize>) -> Result<&mut Self> {
+        let split_index = if self.record_type.is_sam_family() {
+            let reader = ValidatingReader::new(
+                get_sam_reader(&self.input, self.reference_fasta.as_ref(), self.threads)?,
+                ValidationLevel::Strict,
+            );
+            SplitIndex::build::<BamRecord, _, BamWriter>(reader, None, num_bins, u64::MAX)?
+        } else {
+            let reader = ValidatingReader::new(
+                get_fastq_reader(&self.input, self.threads)?,
+                ValidationLevel::Strict,
+            );
+            SplitIndex::build::<FastqRecord, _, FastqWriter<Vec<u8>>>(
+                reader,
+                None,
+                num_bins,
+                u64::MAX,
+            )?
+        };
+        let mut split_index = split_index.downsize_reads(num_bins)?;
+        split_index.clear_qnames();
+        split_index.clear_max_read_lengths();
+        split_index.set_fingerprint(FileFingerprint::from_path(&self.input)?);
+        self.split_index = Some(split_index);
+        Ok(self)
+    }
+
+    /// Load the split-index of the reads file built earlier (e.g. by `split-reads index`), from
+    /// index or by default the input path with an added ".si" extension. Errors if the index was
+    /// built from a different file.
+    pub fn load_index<P>(&mut self, index: Option<P>) -> Result<&mut Self>
+    where
+        P: AsRef<Path>,
+    {
+        let index = match index {
+            Some(index) => index.as_ref().to_path_buf(),
+            None => PathType::from_path(&self.input)?
+                .default_index(SPLIT_INDEX_EXTENSION)?
+                .ok_or_else(|| anyhow!("Cannot get a default index path for {:?}", self.input))?,
+        };
+        let split_index = SplitIndex::read(index)?;
+        split_index.verify_fingerprint(&self.input)?;
+        self.split_index = Some(split_index);
+        Ok(self)
+    }
+
+    /// Write the split-index to a path or URL, so that later runs (or the command line tools) can
+    /// load it instead of reading the whole file again.
+    pub fn write_index<P>(&self, path: P) -> Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        self.split_index()?.clone().write(path)?;
+        Ok(())
+    }
+
+    /// Get the split-index, once the reads file is indexed or its index is loaded.
+    pub fn split_index(&self) -> Result<&SplitIndex> {
+        self.split_index.as_ref().ok_or_else(|| {
+            anyhow!(
+                "{:?} is not indexed yet. Call index or load_index.",
+                self.input
+            )
+        })
+    }
+
+    /// Get chunk chunk_index (0, 1, ..., num_chunks - 1) of the reads file split into num_chunks
+    /// chunks with (nearly) equal numbers of query groups.
+    pub fn chunk(&self, chunk_index: usize, num_chunks: NonZero<usize>) -> Result<Chunk<'_>> {
+        if chunk_index >= num_chunks.get() {
+            return Err(anyhow!(
+                "Chunk index {chunk_index} must be less than the number of chunks {num_chunks}."
+            ));
+        }
+        let split_index = self.split_index()?;
+        Ok(Chunk {
+            splitter: self,
+            start_num_queries: split_index.get_chunk_query_start(chunk_index, num_chunks)?,
+            stop_num_queries: split_index.get_chunk_query_start(chunk_index + 1, num_chunks)?,
+        })
+    }
+
+    /// Get the chunk of query_count query groups starting at 0-based query index query_start,
+    /// cut short at the end of the file.
+    pub fn queries(&self, query_start: usize, query_count: usize) -> Result<Chunk<'_>> {
+        let num_queries = self.split_index()?.num_queries();
+        Ok(Chunk {
+            splitter: self,
+            start_num_queries: query_start.min(num_queries),
+            stop_num_queries: query_start.saturating_add(query_count).min(num_queries),
+        })
+    }
+}
+
+/// A range of query groups of a reads file, from Splitter::chunk or Splitter::queries.
+pub struct Chunk<'a> {
+    splitter: &'a Splitter,
+    start_num_queries: usize,
+    stop_num_queries: usize,
+}
+
+impl Chunk<'_> {
+    /// Get the 0-based indices of the first query group in the chunk and of the first one after
+    /// it.
+    pub fn query_range(&self) -> (usize, usize) {
+        (self.start_num_queries, self.stop_num_queries)
+    }
+
+    /// Get the boundaries of the chunk from the index alone (the offset extraction seeks to, and
+    /// estimated reads and bytes), e.g. to check a split before writing any chunk.
+    pub fn boundaries(&self) -> Result<ChunkBoundaries> {
+        self.splitter
+            .split_index()?
+            .chunk_boundaries(self.start_num_queries, self.stop_num_queries)
+    }
+
+    /// Write the chunk to a path or URL ("-" for stdout), in the format of its extension (or the
+    /// input's format for stdout and unknown extensions), and return the numbers of reads and
+    /// query groups written.
+    pub fn write_to<P>(&self, output: P) -> Result<ChunkResult>
+    where
+        P: AsRef<Path>,
+    {
+        let splitter = self.splitter;
+        let split_index = splitter.split_index()?.clone();
+        let input_type = splitter.record_type;
+        let output_type = OutputSpec::resolve(output.as_ref(), None, input_type)?.record_type;
+        let (start, stop) = self.query_range();
+        let skipped_ranges = split_index.skipped_ranges().to_vec();
+        let mut transforms = splitter.transforms.clone();
+        transforms.check_output(output_type)?;
+        if input_type.is_sam_family() {
+            let mut reader = ValidatingReader::new(
+                get_sam_reader(
+                    &splitter.input,
+                    splitter.reference_fasta.as_ref(),
+                    splitter.threads,
+                )?,
+                ValidationLevel::Strict,
+            );
+            reader.skip_ranges(&skipped_ranges);
+            transforms.check_input(Some(reader.get_ref().header()))?;
+            if output_type.is_sam_family() {
+                let mut writer = SamWriterSpec::new(output.as_ref())
+                    .header_from_view(reader.get_ref().header())
+                    .format(OutputFormat::from(output_type))
+                    .threads(splitter.threads)
+                    .reference_fasta(splitter.reference_fasta.as_deref())
+                    .compression(splitter.compression)
+                    .get_bam_writer()?;
+                let chunk_result = match reader.fast_forward_to_queries(split_index, start, stop)? {
+                    Some(mut fast_forward_info) => fast_forward_info
+                        .transform(Some(&mut transforms))
+                        .write_chunk(&mut writer)?,
+                    None => ChunkResult::Empty,
+                };
+                writer.finish()?;
+                Ok(chunk_result)
+            } else {
+                let mut writer =
+                    get_fastq_writer(output.as_ref(), splitter.compression, splitter.threads)?;
+                let chunk_result = match reader.fast_forward_to_queries(split_index, start, stop)? {
+                    Some(mut fast_forward_info) => fast_forward_info
+                        .transform(Some(&mut transforms))
+                        .translate_and_write_chunk::<FastqRecord, _>(
+                            &mut writer,
+                            &TranslateOptions::new(),
+                        )?,
+                    None => ChunkResult::Empty,
+                };
+                writer.finish()?;
+                Ok(chunk_result)
+            }
+        } else {
+            let mut reader = ValidatingReader::new(
+                get_fastq_reader(&splitter.input, splitter.threads)?,
+                ValidationLevel::Strict,
+            );
+            reader.skip_ranges(&skipped_ranges);
+            transforms.check_input(None)?;
+            if output_type.is_sam_family() {
+                // unaligned records, without a reference or @SQ lines
+                let mut writer = SamWriterSpec::new(output.as_ref())
+                    .header(Header::new())
+                    .format(OutputFormat::from(output_type))
+                    .threads(splitter.threads)
+                    .compression(splitter.compression)
+                    .get_bam_writer()?;
+                let chunk_result = match reader.fast_forward_to_queries(split_index, start, stop)? {
+                    Some(mut fast_forward_info) => fast_forward_info
+                        .transform(Some(&mut transforms))
+                        .translate_and_write_chunk::<BamRecord, _>(
+                            &mut writer,
+                            TranslateOptions::new().pair_end(PairEnd::from_path(&splitter.input)),
+                        )?,
+                    None => ChunkResult::Empty,
+                };
+                writer.finish()?;
+                Ok(chunk_result)
+            } else {
+                let mut writer =
+                    get_fastq_writer(output.as_ref(), splitter.compression, splitter.threads)?;
+                let chunk_result = match reader.fast_forward_to_queries(split_index, start, stop)? {
+                    Some(mut fast_forward_info) => fast_forward_info
+                        .transform(Some(&mut transforms))
+                        .write_chunk(&mut writer)?,
+                    None => ChunkResult::Empty,
+                };
+                writer.finish()?;
+                Ok(chunk_result)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Splitter;
+    use crate::{
+        chunkable::{ChunkResult, ChunkableRecordReader},
+        fastq::{FastqReader, FastqRecord},
+        transform::TransformPipeline,
+    };
+    use anyhow::Result;
+    use std::{fs, fs::File, io::BufReader, num::NonZero};
+    use tempfile::TempDir;
+
+    /// Test that chunks cover every query group once, in order, through a written and reloaded
+    /// index, that chunks need an index, and that transforms are applied.
+    #[test]
+    fn test_splitter() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let fastq = temp_dir.path().join("reads.fastq");
+        let text: String = (0..100)
+            .map(|index| format!("@read{index:03}\nACGT\n+\nIIII\n"))
+            .collect();
+        fs::write(&fastq, &text)?;
+        let mut splitter = Splitter::open(&fastq)?;
+        assert!(splitter.chunk(0, NonZero::new(3).unwrap()).is_err());
+        splitter.threads(NonZero::new(1).unwrap());
+        splitter
+            .index(NonZero::new(10).unwrap())?
+            .write_index(temp_dir.path().join("reads.fastq.si"))?;
+
+        let mut reloaded = Splitter::open(&fastq)?;
+        reloaded.load_index(None::<&str>)?;
+        assert!(reloaded.split_index()? == splitter.split_index()?);
+        let num_chunks = NonZero::new(3).unwrap();
+        assert!(reloaded.chunk(3, num_chunks).is_err());
+        let mut chunked = String::new();
+        for chunk_index in 0..3 {
+            let output = temp_dir.path().join(format!("chunk{chunk_index}.fastq"));
+            let chunk = reloaded.chunk(chunk_index, num_chunks)?;
+            let (start, stop) = chunk.query_range();
+            let boundaries = chunk.boundaries()?;
+            assert_eq!(boundaries.approx_reads, stop - start);
+            // 21 bytes per read, and bins of 10 reads
+            assert_eq!(
+                boundaries.seek_offset,
+                Some(21 * (start - start % 10) as u64)
+            );
+            assert_eq!(boundaries.scan_reads, start % 10);
+            let ChunkResult::Written { reads, queries } = chunk.write_to(&output)? else {
+                panic!("Chunk {chunk_index} is empty");
+            };
+            assert_eq!((reads, queries), (stop - start, stop - start));
+            chunked.push_str(&fs::read_to_string(&output)?);
+        }
+        assert_eq!(chunked, text);
+
+        // translate a range of queries to BAM and back, renaming reads on the way back
+        let bam = temp_dir.path().join("queries.bam");
+        assert_eq!(reloaded.queries(95, 10)?.query_range(), (95, 100));
+        reloaded.queries(95, 10)?.write_to(&bam)?;
+        let mut bam_splitter = Splitter::open(&bam)?;
+        let mut transforms = TransformPipeline::new();
+        transforms.push("rename:prefix=s1_".parse()?);
+        bam_splitter.transforms(transforms);
+        let back = temp_dir.path().join("back.fastq");
+        bam_splitter
+            .index(NonZero::new(2).unwrap())?
+            .chunk(0, NonZero::<usize>::MIN)?
+            .write_to(&back)?;
+        let mut reader = FastqReader::new(BufReader::new(File::open(&back)?));
+        let mut record = FastqRecord::new();
+        let mut names = Vec::new();
+        while let Some(result) = reader.read_into(&mut record) {
+            result?;
+            names.push(String::from_utf8(record.qname().to_vec())?);
+        }
+        assert_eq!(
+            names,
+            [
+                "s1_read095",
+                "s1_read096",
+                "s1_read097",
+                "s1_read098",
+                "s1_read099"
+            ]
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,158 @@
+use crate::chunkable::{
+    ChunkResult, ChunkableRecord, ChunkableRecordWriter, GroupBy, LastQueryName,
+};
+use anyhow::{Result, anyhow};
+use std::num::NonZero;
+
+/// Writer that deals whole query groups out to several writers in turn, block_size groups at a
+/// time, so a stream that can't be indexed (e.g. piped from a basecaller) is split in one pass.
+/// With a block size of 1 this is round-robin. Query groups are consecutive records with the same
+/// group_by key, so a query group that reappears later in the stream may go to another writer.
+pub struct RoundRobinWriter<W> {
+    writers: Vec<W>,
+    block_size: NonZero<usize>,
+    group_by: GroupBy,
+    last_query_name: LastQueryName,
+    /// Number of query groups started so far
+    num_queries: usize,
+    /// Reads and query groups written to each writer
+    counts: Vec<(usize, usize)>,
+}
+
+impl<W> RoundRobinWriter<W> {
+    /// Create a new RoundRobinWriter dealing query groups out to writers, in blocks of block_size.
+    pub fn new(writers: Vec<W>, block_size: NonZero<usize>, group_by: GroupBy) -> Result<Self> {
+        if writers.is_empty() {
+            return Err(anyhow!("Need at least one output to split into."));
+        }
+        let counts = vec![(0, 0); writers.len()];
+        Ok(RoundRobinWriter {
+            writers,
+            block_size,
+            group_by,
+            last_query_name: LastQueryName::default(),
+            num_queries: 0,
+            counts,
+        })
+    }
+
+    /// Get the index of the writer that the current query group goes to.
+    fn current(&self) -> usize {
+        (self.num_queries.saturating_sub(1) / self.block_size.get()) % self.writers.len()
+    }
+
+    /// Return the writers, to be finished, with what was written to each.
+    pub fn into_parts(self) -> (Vec<W>, Vec<ChunkResult>) {
+        let results = self
+            .counts
+            .into_iter()
+            .map(|(reads, queries)| {
+                if reads == 0 {
+                    ChunkResult::Empty
+                } else {
+                    ChunkResult::Written { reads, queries }
+                }
+            })
+            .collect();
+        (self.writers, results)
+    }
+}
+
+/// Implement ChunkableRecordWriter for RoundRobinWriter, so any record reader can feed it.
+impl<R, W> ChunkableRecordWriter<R> for RoundRobinWriter<W>
+where
+    R: ChunkableRecord,
+    W: ChunkableRecordWriter<R>,
+{
+    fn write(&mut self, record: &R) -> Result<()> {
+        let key = self.group_by.key(record);
+        let is_new_query = !self.last_query_name.matches(&key);
+        if is_new_query {
+            self.last_query_name.set(&key);
+            self.num_queries += 1;
+        }
+        let current = self.current();
+        self.counts[current].0 += 1;
+        self.counts[current].1 += usize::from(is_new_query);
+        self.writers[current].write(record)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RoundRobinWriter;
+    use crate::{
+        chunkable::{ChunkResult, ChunkableRecord, ChunkableRecordWriter, GroupBy},
+        fastq::FastqRecord,
+    };
+    use anyhow::Result;
+    use std::num::NonZero;
+
+    /// Writer that keeps the query names of records
+    struct QnameWriter(Vec<Vec<u8>>);
+
+    impl ChunkableRecordWriter<FastqRecord> for QnameWriter {
+        fn write(&mut self, record: &FastqRecord) -> Result<()> {
+            self.0.push(record.qname().to_vec());
+            Ok(())
+        }
+    }
+
+    /// Test dealing query groups round-robin and in blocks, never splitting a group.
+    #[test]
+    fn test_round_robin() -> Result<()> {
+        let split = |block_size: usize| -> Result<(Vec<Vec<String>>, Vec<ChunkResult>)> {
+            let writers = (0..3).map(|_| QnameWriter(Vec::new())).collect();
+            let mut writer = RoundRobinWriter::new(
+                writers,
+                NonZero::new(block_size).unwrap(),
+                GroupBy::QueryName,
+            )?;
+            let mut record = FastqRecord::new();
+            for (query, num_reads) in [1, 2, 1, 3, 2, 1, 2].iter().enumerate() {
+                for _ in 0..*num_reads {
+                    record.set_fields(format!("q{query}").as_bytes(), b"ACGT", b"IIII")?;
+                    writer.write(&record)?;
+                }
+            }
+            let (writers, results) = writer.into_parts();
+            let qnames = writers
+                .into_iter()
+                .map(|writer| {
+                    writer
+                        .0
+                        .iter()
+                        .map(|qname| String::from_utf8_lossy(qname).to_string())
+                        .collect()
+                })
+                .collect();
+            Ok((qnames, results))
+        };
+        let (qnames, results) = split(1)?;
+        assert_eq!(
+            qnames,
+            vec![
+                vec!["q0", "q3", "q3", "q3", "q6", "q6"],
+                vec!["q1", "q1", "q4", "q4"],
+                vec!["q2", "q5"],
+            ]
+        );
+        assert_eq!(
+            results[0],
+            ChunkResult::Written {
+                reads: 6,
+                queries: 3
+            }
+        );
+        let (qnames, results) = split(3)?;
+        assert_eq!(qnames[0], vec!["q0", "q1", "q1", "q2"]);
+        assert_eq!(qnames[1], vec!["q3", "q3", "q3", "q4", "q4", "q5"]);
+        assert_eq!(qnames[2], vec!["q6", "q6"]);
+        assert_eq!(results[2].num_queries(), 1);
+        let no_writers: Vec<QnameWriter> = Vec::new();
+        assert!(
+            RoundRobinWriter::new(no_writers, NonZero::<usize>::MIN, GroupBy::QueryName).is_err()
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,170 @@
+use crate::chunkable::{ChunkableRecord, ChunkableRecordWriter};
+use crate::qname_hash::{QnameHasher, Xxh3QnameHasher};
+use anyhow::{Result, anyhow};
+
+/// Deterministically select a fraction of query groups by hashing their query names. Because the
+/// decision depends only on the query name and seed, every read of a query group gets the same
+/// decision, and subsampling chunks separately selects the same queries as subsampling the whole
+/// file.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct QuerySubsampler<H = Xxh3QnameHasher> {
+    /// Keep queries whose hash is below this threshold. Wider than u64 so a fraction of 1 keeps
+    /// every query.
+    threshold: u128,
+    /// Hasher for query names
+    hasher: H,
+}
+
+impl QuerySubsampler {
+    /// Create a new QuerySubsampler keeping the requested fraction (between 0 and 1) of queries,
+    /// hashing query names with seeded xxh3.
+    pub fn new(fraction: f64, seed: u64) -> Result<Self> {
+        Self::with_hasher(fraction, Xxh3QnameHasher::new(seed))
+    }
+}
+
+impl<H: QnameHasher> QuerySubsampler<H> {
+    /// Create a new QuerySubsampler keeping the requested fraction (between 0 and 1) of queries,
+    /// hashing query names with the supplied hasher.
+    pub fn with_hasher(fraction: f64, hasher: H) -> Result<Self> {
+        if !(0.0..=1.0).contains(&fraction) {
+            return Err(anyhow!(
+                "Subsample fraction must be between 0 and 1, got {fraction}."
+            ));
+        }
+        Ok(QuerySubsampler {
+            threshold: (fraction * 2f64.powi(64)) as u128,
+            hasher,
+        })
+    }
+
+    /// Return true if the query with this name is selected.
+    pub fn keep(&self, qname: &[u8]) -> bool {
+        (self.hasher.hash_qname(qname) as u128) < self.threshold
+    }
+}
+
+/// Writer that only passes on records from selected query groups.
+pub struct SubsamplingWriter<'a, W> {
+    writer: &'a mut W,
+    subsampler: QuerySubsampler,
+}
+
+impl<'a, W> SubsamplingWriter<'a, W> {
+    /// Create a new SubsamplingWriter wrapping the supplied writer.
+    pub fn new(writer: &'a mut W, subsampler: QuerySubsampler) -> Self {
+        SubsamplingWriter { writer, subsampler }
+    }
+}
+
+/// Implement ChunkableRecordWriter for SubsamplingWriter, so it can be used anywhere records are
+/// written.
+impl<R, W> ChunkableRecordWriter<R> for SubsamplingWriter<'_, W>
+where
+    R: ChunkableRecord,
+    W: ChunkableRecordWriter<R>,
+{
+    fn write(&mut self, record: &R) -> Result<()> {
+        if self.subsampler.keep(record.group_qname()) {
+            self.writer.write(record)
+        } else {
+            Ok(())
+        }
+    }
+
+    fn is_full(&mut self) -> bool {
+        self.writer.is_full()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{QuerySubsampler, SubsamplingWriter};
+    use crate::{
+        chunkable::{ChunkableRecord, ChunkableRecordWriter},
+        fastq::FastqRecord,
+    };
+    use anyhow::Result;
+
+    /// Writer that keeps the query names of records
+    struct QnameWriter(Vec<Vec<u8>>);
+
+    impl ChunkableRecordWriter<FastqRecord> for QnameWriter {
+        fn write(&mut self, record: &FastqRecord) -> Result<()> {
+            self.0.push(record.qname().to_vec());
+            Ok(())
+        }
+    }
+
+    /// Test that subsampling keeps roughly the requested fraction, deterministically per seed.
+    #[test]
+    fn test_subsample_fraction() -> Result<()> {
+        let qnames: Vec<Vec<u8>> = (0..10000)
+            .map(|index| format!("read{index}").into_bytes())
+            .collect();
+        let count = |subsampler: &QuerySubsampler| {
+            qnames.iter().filter(|qname| subsampler.keep(qname)).count()
+        };
+        assert_eq!(count(&QuerySubsampler::new(0.0, 0)?), 0);
+        assert_eq!(count(&QuerySubsampler::new(1.0, 0)?), qnames.len());
+        let kept = count(&QuerySubsampler::new(0.1, 0)?);
+        assert!((800..1200).contains(&kept), "kept {kept} of 10000");
+
+        // subsamplers with the same seed agree, even when created separately, as for each chunk
+        let seed_1 = QuerySubsampler::new(0.5, 1)?;
+        let seed_1_again = QuerySubsampler::new(0.5, 1)?;
+        let seed_2 = QuerySubsampler::new(0.5, 2)?;
+        assert!(
+            qnames
+                .iter()
+                .all(|qname| seed_1.keep(qname) == seed_1_again.keep(qname))
+        );
+        assert!(
+            qnames
+                .iter()
+                .any(|qname| seed_1.keep(qname) != seed_2.keep(qname))
+        );
+
+        assert!(QuerySubsampler::new(1.5, 0).is_err());
+        assert!(QuerySubsampler::new(-0.1, 0).is_err());
+        Ok(())
+    }
+
+    /// Test that reads whose names collide get one decision: every read of a query group, mates
+    /// named with "/1" and "/2" suffixes, and a query name that recurs later in the file.
+    #[test]
+    fn test_subsample_name_collisions() -> Result<()> {
+        let mut records: Vec<FastqRecord> = Vec::new();
+        for query in (0..200).chain(0..50) {
+            for name in [
+                format!("q{query}/1"),
+                format!("q{query}/2"),
+                format!("q{query}"),
+            ] {
+                let mut record = FastqRecord::new();
+                record.set_fields(name.as_bytes(), b"ACGT", b"IIII")?;
+                records.push(record);
+            }
+        }
+        let mut kept = QnameWriter(Vec::new());
+        let mut writer = SubsamplingWriter::new(&mut kept, QuerySubsampler::new(0.5, 3)?);
+        for record in &records {
+            writer.write(record)?;
+        }
+        let kept_names = kept.0;
+        assert!(!kept_names.is_empty() && kept_names.len() < records.len());
+        assert_eq!(kept_names.len() % 3, 0);
+        for names in kept_names.chunks(3) {
+            let query = names[2].as_slice();
+            assert_eq!(names[0], [query, b"/1"].concat());
+            assert_eq!(names[1], [query, b"/2"].concat());
+        }
+        // a recurring query name is kept both times or neither
+        for query in 0..50 {
+            let name = format!("q{query}").into_bytes();
+            let count = kept_names.iter().filter(|kept| **kept == name).count();
+            assert!(count == 0 || count == 2, "q{query} kept {count} times");
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,189 @@
+use anyhow::{Result, anyhow};
+use bam_builder::{BamBuilder, bam_order::BamSortOrder};
+use noodles_bgzf::io::Writer as NoodlesBgzfWriter;
+use rand::Rng;
+use rust_htslib::bam::{Read, Reader, Record};
+use std::{
+    fs::File,
+    io::Write,
+    ops::Range,
+    path::{Path, PathBuf},
+};
+
+/// Read length of generated reads, unless another is requested
+pub const DEFAULT_READ_LENGTH: usize = 150;
+
+/// Base quality of generated reads
+const BASE_QUALITY: u8 = 30;
+
+/// Enum for generating random BAMs with different query types
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum QueryType {
+    Single,  // single-end, e.g. PacBio, Nanopore
+    Paired,  // pair-end, e.g. Illumina
+    Grouped, // query-grouped, could be anything
+}
+
+impl QueryType {
+    /// Generate a random BAM file with the specified number of query groups.
+    ///
+    /// Writes a BAM file to path with the appropriate query type (single-end, paired-end, or
+    /// grouped). The same seed always generates the same reads.
+    ///
+    /// # Arguments
+    /// * `path` - Path where the BAM file will be written
+    /// * `num_queries` - Number of query groups to generate
+    /// * `read_length` - Length of every read
+    /// * `seed` - Seed used for generating random bases and group sizes
+    ///
+    /// # Returns
+    /// The total number of reads in the BAM file
+    pub fn write_bam<P>(
+        &self,
+        path: P,
+        num_queries: usize,
+        read_length: usize,
+        seed: u64,
+    ) -> Result<usize>
+    where
+        P: AsRef<Path>,
+    {
+        let mut builder = BamBuilder::new(
+            read_length,                               // default read length
+            BASE_QUALITY,                              // default base quality
+            format!("{}-{num_queries}", self.label()), // name of sample
+            None,                                      // optional read group id
+            BamSortOrder::Unsorted,                    // how to sort reads when `.sort` is called
+            None,                                      // optional sequence dictionary
+            Some(seed as usize),                       // seed used for generating random bases
+        );
+        let num_reads = match self {
+            QueryType::Single => Self::add_single_reads(&mut builder, num_queries)?,
+            QueryType::Paired => Self::add_paired_reads(&mut builder, num_queries)?,
+            QueryType::Grouped => Self::add_grouped_reads(&mut builder, num_queries)?,
+        };
+        builder.to_path(path.as_ref())?;
+        Ok(num_reads)
+    }
+
+    /// Get the file name of a generated BAM with num_queries query groups.
+    pub fn file_name(&self, num_queries: usize) -> PathBuf {
+        PathBuf::from(format!("random-{}-{num_queries}.bam", self.label()))
+    }
+
+    /// Get a descriptive label for this query type.
+    ///
+    /// # Returns
+    /// A string slice containing "single", "paired", or "grouped" depending on the query type.
+    pub fn label(&self) -> &'static str {
+        match self {
+            QueryType::Single => "single",
+            QueryType::Paired => "paired",
+            QueryType::Grouped => "grouped",
+        }
+    }
+
+    /// Add single-end reads, one read per query group.
+    ///
+    /// # Returns
+    /// The number of reads added
+    fn add_single_reads(builder: &mut BamBuilder, num_queries: usize) -> Result<usize> {
+        for idx in 0..num_queries {
+            let read = builder
+                .frag_builder()
+                .name(format!("Single{idx:06}"))
+                .build()?;
+            builder.add_frag(read);
+        }
+        Ok(num_queries)
+    }
+
+    /// Add paired-end reads, two reads (forward and reverse) per query group.
+    ///
+    /// # Returns
+    /// The number of reads added (2 * num_queries)
+    fn add_paired_reads(builder: &mut BamBuilder, num_queries: usize) -> Result<usize> {
+        for idx in 0..num_queries {
+            let pair = builder
+                .pair_builder()
+                .name(format!("Pair{idx:06}"))
+                .build()?;
+            builder.add_pair(pair);
+        }
+        Ok(2 * num_queries)
+    }
+
+    /// Add query-grouped reads, a variable number of reads per query group.
+    ///
+    /// # Returns
+    /// The number of reads added
+    fn add_grouped_reads(builder: &mut BamBuilder, num_queries: usize) -> Result<usize> {
+        let group_size_range: Range<usize> = Range::<usize> {
+            start: 1usize,
+            end: 5usize,
+        };
+        let mut num_reads: usize = 0;
+        for group in 0..num_queries {
+            let group_size = builder.rng.random_range(group_size_range.clone());
+            num_reads += group_size;
+            for _ in 0..group_size {
+                let name = format!("Group{group:06}");
+                let read = builder.frag_builder().name(name.clone()).build()?;
+                builder.add_frag(read);
+            }
+        }
+        Ok(num_reads)
+    }
+}
+
+/// Write the reads of a BAM as BGZF-compressed FASTQ, in the same order, e.g. to generate FASTQ
+/// input with the same reads as a generated BAM. Returns the number of reads written.
+pub fn bam_to_fastq<P1, P2>(bam: P1, fastq: P2) -> Result<usize>
+where
+    P1: AsRef<Path>,
+    P2: AsRef<Path>,
+{
+    let mut reader = Reader::from_path(bam.as_ref())
+        .map_err(|err| anyhow!("Opening {:?}: {err}", bam.as_ref()))?;
+    let mut writer = NoodlesBgzfWriter::new(File::create(fastq.as_ref())?);
+    let mut record = Record::new();
+    let mut num_reads = 0usize;
+    while let Some(result) = reader.read(&mut record) {
+        result?;
+        let quality: Vec<u8> = record.qual().iter().map(|quality| quality + 33).collect();
+        writer.write_all(b"@")?;
+        writer.write_all(record.qname())?;
+        writer.write_all(b"\n")?;
+        writer.write_all(&record.seq().as_bytes())?;
+        writer.write_all(b"\n+\n")?;
+        writer.write_all(&quality)?;
+        writer.write_all(b"\n")?;
+        num_reads += 1;
+    }
+    writer.finish()?;
+    Ok(num_reads)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DEFAULT_READ_LENGTH, QueryType, bam_to_fastq};
+    use anyhow::Result;
+    use std::fs;
+    use tempfile::TempDir;
+
+    /// Test that the same seed generates the same reads, and that the FASTQ has every read.
+    #[test]
+    fn test_write_bam() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let mut bams = Vec::new();
+        for name in ["a.bam", "b.bam"] {
+            let path = temp_dir.path().join(name);
+            let num_reads = QueryType::Grouped.write_bam(&path, 20, DEFAULT_READ_LENGTH, 7)?;
+            let fastq = path.with_extension("fastq.gz");
+            assert_eq!(bam_to_fastq(&path, &fastq)?, num_reads);
+            bams.push(fs::read(&fastq)?);
+        }
+        assert_eq!(bams[0], bams[1]);
+        Ok(())
+    }
+}
@@ -0,0 +1,162 @@
+use anyhow::Result;
+use std::{
+    fs,
+    io::{BufRead, BufReader, Read, Write},
+    net::{TcpListener, TcpStream},
+    path::{Path, PathBuf},
+    thread,
+};
+
+/// Minimal HTTP/1.1 server for testing remote paths. Serves the files in a directory with GET and
+/// HEAD requests, honoring single "Range: bytes=start-end" headers the way htslib's curl backend
+/// uses them, and saves PUT uploads (with a Content-Length or chunked) into it. Runs in background
+/// threads for the rest of the test process.
+pub struct TestHttpServer {
+    port: u16,
+}
+
+impl TestHttpServer {
+    /// Start serving the files in `root` on an unused localhost port.
+    pub fn serve<P>(root: P) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let port = listener.local_addr()?.port();
+        let root = root.as_ref().to_path_buf();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let root = root.clone();
+                thread::spawn(move || {
+                    // a failed response just looks like a network error to the client
+                    let _ = Self::respond(stream, &root);
+                });
+            }
+        });
+        Ok(TestHttpServer { port })
+    }
+
+    /// Get the URL of a file in the served directory.
+    pub fn url(&self, file_name: &str) -> String {
+        format!("http://127.0.0.1:{}/{file_name}", self.port)
+    }
+
+    /// Parse a "bytes=start-end" range header value into an inclusive range, clamped to the file.
+    fn parse_range(value: &str, len: u64) -> Option<(u64, u64)> {
+        let (start, end) = value.trim().strip_prefix("bytes=")?.split_once('-')?;
+        let start: u64 = start.parse().ok()?;
+        let end: u64 = match end {
+            "" => len.checked_sub(1)?,
+            end => end.parse::<u64>().ok()?.min(len.checked_sub(1)?),
+        };
+        (start <= end).then_some((start, end))
+    }
+
+    /// Read a chunked request body, up to and including its trailer.
+    fn read_chunked<R>(reader: &mut R) -> Result<Vec<u8>>
+    where
+        R: BufRead,
+    {
+        let mut body = Vec::new();
+        loop {
+            let mut size_line = String::new();
+            reader.read_line(&mut size_line)?;
+            let size_field = size_line.split(';').next().unwrap_or_default().trim();
+            let size = usize::from_str_radix(size_field, 16)?;
+            if size == 0 {
+                // skip trailer fields up to the empty line ending the body
+                loop {
+                    let mut trailer = String::new();
+                    if reader.read_line(&mut trailer)? == 0 || trailer.trim().is_empty() {
+                        return Ok(body);
+                    }
+                }
+            }
+            let start = body.len();
+            body.resize(start + size, 0);
+            reader.read_exact(&mut body[start..])?;
+            let mut line_end = String::new();
+            reader.read_line(&mut line_end)?;
+        }
+    }
+
+    /// Answer the requests on one connection until the client closes it.
+    fn respond(stream: TcpStream, root: &Path) -> Result<()> {
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut writer = stream;
+        loop {
+            let mut request_line = String::new();
+            if reader.read_line(&mut request_line)? == 0 {
+                return Ok(());
+            }
+            let mut fields = request_line.split_whitespace();
+            let method = fields.next().unwrap_or_default().to_string();
+            let path = fields.next().unwrap_or_default().to_string();
+            let mut range_header: Option<String> = None;
+            let mut content_length: Option<usize> = None;
+            let (mut chunked, mut expect_continue) = (false, false);
+            loop {
+                let mut header = String::new();
+                if reader.read_line(&mut header)? == 0 || header.trim().is_empty() {
+                    break;
+                }
+                let Some((name, value)) = header.split_once(':') else {
+                    continue;
+                };
+                let value = value.trim();
+                match name.to_ascii_lowercase().as_str() {
+                    "range" => range_header = Some(value.to_string()),
+                    "content-length" => content_length = value.parse().ok(),
+                    "transfer-encoding" => chunked = value.eq_ignore_ascii_case("chunked"),
+                    "expect" => expect_continue = value.eq_ignore_ascii_case("100-continue"),
+                    _ => {}
+                }
+            }
+
+            let file_path: PathBuf = root.join(path.trim_start_matches('/'));
+            if method == "PUT" {
+                if expect_continue {
+                    write!(writer, "HTTP/1.1 100 Continue\r\n\r\n")?;
+                    writer.flush()?;
+                }
+                let body = if chunked {
+                    Self::read_chunked(&mut reader)?
+                } else {
+                    let mut body = vec![0u8; content_length.unwrap_or(0)];
+                    reader.read_exact(&mut body)?;
+                    body
+                };
+                fs::write(&file_path, body)?;
+                write!(writer, "HTTP/1.1 201 Created\r\nContent-Length: 0\r\n\r\n")?;
+                writer.flush()?;
+                continue;
+            }
+            let Ok(contents) = fs::read(&file_path) else {
+                write!(
+                    writer,
+                    "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n"
+                )?;
+                continue;
+            };
+            let len = contents.len() as u64;
+            let (status, start, end) =
+                match range_header.and_then(|value| Self::parse_range(&value, len)) {
+                    Some((start, end)) => ("206 Partial Content", start, end + 1),
+                    None => ("200 OK", 0, len),
+                };
+            write!(
+                writer,
+                "HTTP/1.1 {status}\r\nContent-Length: {}\r\nAccept-Ranges: bytes\r\n",
+                end - start
+            )?;
+            if status.starts_with("206") {
+                write!(writer, "Content-Range: bytes {start}-{}/{len}\r\n", end - 1)?;
+            }
+            write!(writer, "\r\n")?;
+            if method != "HEAD" {
+                writer.write_all(&contents[start as usize..end as usize])?;
+            }
+            writer.flush()?;
+        }
+    }
+}
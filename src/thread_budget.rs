@@ -0,0 +1,296 @@
+use crate::{memory_budget::MemoryBudget, util::RecordType};
+use anyhow::{Error, Result, anyhow};
+use log::warn;
+use std::{fmt, num::NonZero, str::FromStr, thread};
+
+/// Value of a --threads option: a thread count for each thread pool, or "auto" to divide the
+/// available CPUs between the pools.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Threads {
+    Auto,
+    Count(NonZero<usize>),
+}
+
+impl FromStr for Threads {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        if s.eq_ignore_ascii_case("auto") {
+            return Ok(Threads::Auto);
+        }
+        s.parse()
+            .map(Threads::Count)
+            .map_err(|_| anyhow!("Threads must be a positive integer or \"auto\", not {s:?}."))
+    }
+}
+
+impl fmt::Display for Threads {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Threads::Auto => write!(f, "auto"),
+            Threads::Count(count) => write!(f, "{count}"),
+        }
+    }
+}
+
+/// Number of thread pools working at once in a command that only reads its input
+pub const READING_POOLS: NonZero<usize> = NonZero::new(1).unwrap();
+
+/// Number of thread pools working at once in a command that reads its input and writes output
+pub const READ_WRITE_POOLS: NonZero<usize> = NonZero::new(2).unwrap();
+
+/// Get the number of CPUs this process may use, accounting for CPU affinity and cgroup quotas
+/// (e.g. a container or a batch-scheduler allocation on a shared node).
+pub fn available_cpus() -> NonZero<usize> {
+    thread::available_parallelism().unwrap_or(NonZero::new(1usize).unwrap())
+}
+
+impl Threads {
+    /// Get the number of threads for each of num_pools thread pools working at once (e.g. 2 when
+    /// decompressing input while compressing output). Without a --threads option, as with
+    /// "auto", the available CPUs are divided between the pools, so together they don't
+    /// oversubscribe the CPUs. Either way, the pools' job queues must fit the memory budget.
+    pub fn per_pool(threads: Option<Threads>, num_pools: NonZero<usize>) -> NonZero<usize> {
+        let threads = match threads {
+            Some(Threads::Count(count)) => count,
+            None | Some(Threads::Auto) => NonZero::new(available_cpus().get() / num_pools.get())
+                .unwrap_or(NonZero::<usize>::MIN),
+        };
+        MemoryBudget::current().worker_threads(threads, num_pools)
+    }
+
+    /// Warn if an explicit thread count oversubscribes the available CPUs, on its own or summed
+    /// over num_pools thread pools, because oversubscribed threads contend for CPUs and slow the
+    /// whole node down. Returns the warning, if any.
+    pub fn warn_if_oversubscribed(
+        threads: Option<Threads>,
+        num_pools: NonZero<usize>,
+    ) -> Option<String> {
+        let Some(Threads::Count(count)) = threads else {
+            return None;
+        };
+        let available = available_cpus();
+        let total = count.get() * num_pools.get();
+        let message = if count > available {
+            format!(
+                "--threads {count} exceeds the {available} CPU(s) available to this process \
+                (after CPU affinity and cgroup limits). Use --threads {available} or less, or \
+                --threads auto."
+            )
+        } else if total > available.get() {
+            format!(
+                "--threads {count} gives each of {num_pools} thread pools (reading and writing) \
+                {count} threads, {total} in total, oversubscribing the {available} CPU(s) \
+                available to this process. Use --threads {} or --threads auto.",
+                (available.get() / num_pools.get()).max(1)
+            )
+        } else {
+            return None;
+        };
+        warn!("{message}");
+        Some(message)
+    }
+}
+
+/// Relative CPU cost of decoding input or encoding output, in units of decompressing BGZF, used to
+/// divide threads between reading and writing.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct IoCost(u32);
+
+impl IoCost {
+    /// Plain text, which one thread reads or writes
+    pub const NONE: Self = IoCost(0);
+    /// Decompressing BGZF (BAM or bgzipped FASTQ)
+    pub const BGZF_DECOMPRESS: Self = IoCost(1);
+    /// Decoding or encoding CRAM
+    pub const CRAM: Self = IoCost(3);
+
+    /// Cost of compressing BGZF at level (the default, 6, if None). Deflate slows steeply with the
+    /// level, from about twice the cost of inflating at level 1 to about eight times at level 9.
+    pub fn bgzf_compress(level: Option<u32>) -> Self {
+        match level.unwrap_or(6) {
+            0 => IoCost::NONE,
+            1..=3 => IoCost(2),
+            4..=6 => IoCost(4),
+            _ => IoCost(8),
+        }
+    }
+
+    /// Cost of reading records of record_type, from a compressed file or not.
+    pub fn reading(record_type: RecordType, compressed: bool) -> Self {
+        match record_type {
+            RecordType::Cram => IoCost::CRAM,
+            RecordType::Bam => IoCost::BGZF_DECOMPRESS,
+            RecordType::Fastq | RecordType::Sam if compressed => IoCost::BGZF_DECOMPRESS,
+            RecordType::Fastq | RecordType::Sam => IoCost::NONE,
+        }
+    }
+
+    /// Cost of writing records of record_type, compressed at level or not.
+    pub fn writing(record_type: RecordType, compressed: bool, level: Option<u32>) -> Self {
+        match record_type {
+            RecordType::Cram => IoCost::CRAM,
+            RecordType::Bam => IoCost::bgzf_compress(level),
+            RecordType::Fastq | RecordType::Sam if compressed => IoCost::bgzf_compress(level),
+            RecordType::Fastq | RecordType::Sam => IoCost::NONE,
+        }
+    }
+}
+
+/// Threads for reading and for writing, dividing one budget between them by the cost of each, so
+/// that together they don't oversubscribe the CPUs.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ThreadSplit {
+    pub reader: NonZero<usize>,
+    pub writer: NonZero<usize>,
+}
+
+impl ThreadSplit {
+    /// Divide threads (a --threads option, all available CPUs without one or with "auto")
+    /// between reading and writing in proportion to their costs, with at least one thread each.
+    /// reader_threads or writer_threads override either side, which then leaves the rest of the
+    /// budget to the other. Either way, both sides' job queues must fit the memory budget.
+    pub fn new(
+        threads: Option<Threads>,
+        read_cost: IoCost,
+        write_cost: IoCost,
+        reader_threads: Option<NonZero<usize>>,
+        writer_threads: Option<NonZero<usize>>,
+    ) -> Self {
+        let total = match threads {
+            Some(Threads::Count(count)) => count.get(),
+            None | Some(Threads::Auto) => available_cpus().get(),
+        };
+        let rest = |used: NonZero<usize>| {
+            NonZero::new(total.saturating_sub(used.get())).unwrap_or(NonZero::<usize>::MIN)
+        };
+        let (reader, writer) = match (reader_threads, writer_threads) {
+            (Some(reader), Some(writer)) => (reader, writer),
+            (Some(reader), None) => (reader, rest(reader)),
+            (None, Some(writer)) => (rest(writer), writer),
+            (None, None) => {
+                let (read_cost, write_cost) = (read_cost.0 as usize, write_cost.0 as usize);
+                let reader = match read_cost + write_cost {
+                    0 => total / 2,
+                    cost => (total * read_cost + cost / 2) / cost,
+                };
+                let reader = NonZero::new(reader.clamp(1, total.saturating_sub(1).max(1)))
+                    .unwrap_or(NonZero::<usize>::MIN);
+                (reader, rest(reader))
+            }
+        };
+        let budget = MemoryBudget::current();
+        let two_pools = NonZero::new(2).unwrap();
+        ThreadSplit {
+            reader: budget.worker_threads(reader, two_pools),
+            writer: budget.worker_threads(writer, two_pools),
+        }
+    }
+
+    /// Warn if reading and writing together oversubscribe the available CPUs. Returns the
+    /// warning, if any.
+    pub fn warn_if_oversubscribed(&self) -> Option<String> {
+        let available = available_cpus();
+        let total = self.reader.get() + self.writer.get();
+        // one thread each is the minimum, however few CPUs there are
+        if total <= available.get().max(2) {
+            return None;
+        }
+        let message = format!(
+            "Reading ({} threads) and writing ({} threads) use {total} threads in total, \
+            oversubscribing the {available} CPU(s) available to this process (after CPU affinity \
+            and cgroup limits). Lower --threads, --reader-threads or --writer-threads.",
+            self.reader, self.writer
+        );
+        warn!("{message}");
+        Some(message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{IoCost, ThreadSplit, Threads, available_cpus};
+    use crate::util::RecordType;
+    use anyhow::Result;
+    use std::num::NonZero;
+
+    /// Test parsing, auto-scaling, and oversubscription warnings.
+    #[test]
+    fn test_threads() -> Result<()> {
+        assert_eq!("auto".parse::<Threads>()?, Threads::Auto);
+        assert_eq!(
+            "3".parse::<Threads>()?,
+            Threads::Count(NonZero::new(3).unwrap())
+        );
+        assert!("0".parse::<Threads>().is_err());
+        assert!("many".parse::<Threads>().is_err());
+
+        let available = available_cpus();
+        let two_pools = NonZero::new(2).unwrap();
+        for threads in [None, Some(Threads::Auto)] {
+            assert_eq!(
+                Threads::per_pool(threads, two_pools).get(),
+                (available.get() / 2).max(1)
+            );
+            assert_eq!(Threads::per_pool(threads, NonZero::<usize>::MIN), available);
+        }
+        let one = Some(Threads::Count(NonZero::<usize>::MIN));
+        assert_eq!(Threads::per_pool(one, two_pools), NonZero::<usize>::MIN);
+        assert!(Threads::warn_if_oversubscribed(one, NonZero::<usize>::MIN).is_none());
+        assert!(Threads::warn_if_oversubscribed(Some(Threads::Auto), two_pools).is_none());
+        let too_many = Some(Threads::Count(available.saturating_add(1)));
+        let warning = Threads::warn_if_oversubscribed(too_many, NonZero::<usize>::MIN).unwrap();
+        assert!(warning.contains("exceeds"), "{warning}");
+        let all = Some(Threads::Count(available));
+        if available.get() > 1 {
+            let warning = Threads::warn_if_oversubscribed(all, two_pools).unwrap();
+            assert!(warning.contains("in total"), "{warning}");
+        }
+        Ok(())
+    }
+
+    /// Test dividing threads between reading and writing by their costs, and overriding either.
+    #[test]
+    fn test_thread_split() {
+        let count = |count: usize| NonZero::new(count).unwrap();
+        let split = |threads: usize,
+                     read_cost,
+                     write_cost,
+                     reader: Option<usize>,
+                     writer: Option<usize>| {
+            let split = ThreadSplit::new(
+                Some(Threads::Count(count(threads))),
+                read_cost,
+                write_cost,
+                reader.map(count),
+                writer.map(count),
+            );
+            (split.reader.get(), split.writer.get())
+        };
+        let bam_in = IoCost::reading(RecordType::Bam, true);
+        let bam_out = IoCost::writing(RecordType::Bam, true, None);
+        assert_eq!(split(10, bam_in, bam_out, None, None), (2, 8));
+        let fast_out = IoCost::writing(RecordType::Fastq, true, Some(1));
+        assert_eq!(split(9, bam_in, fast_out, None, None), (3, 6));
+        let plain_out = IoCost::writing(RecordType::Fastq, false, Some(6));
+        assert_eq!(split(8, bam_in, plain_out, None, None), (7, 1));
+        let plain_in = IoCost::reading(RecordType::Sam, false);
+        assert_eq!(split(8, plain_in, bam_out, None, None), (1, 7));
+        assert_eq!(split(8, plain_in, plain_out, None, None), (4, 4));
+        assert_eq!(split(1, bam_in, bam_out, None, None), (1, 1));
+        assert_eq!(split(10, bam_in, bam_out, Some(6), None), (6, 4));
+        assert_eq!(split(10, bam_in, bam_out, None, Some(12)), (1, 12));
+        assert_eq!(split(10, bam_in, bam_out, Some(3), Some(3)), (3, 3));
+
+        let available = available_cpus();
+        let within = ThreadSplit::new(None, IoCost::CRAM, bam_out, None, None);
+        assert_eq!(
+            within.reader.get() + within.writer.get(),
+            available.get().max(2)
+        );
+        assert!(within.warn_if_oversubscribed().is_none());
+        let more = available.saturating_add(1);
+        let over = ThreadSplit::new(None, bam_in, bam_out, Some(more), Some(more));
+        assert!(over.warn_if_oversubscribed().is_some());
+    }
+}
@@ -0,0 +1,638 @@
+use crate::{
+    chunkable::{ChunkableRecord, MISSING_QUALITY_SENTINEL, RecordTransform, TransformResult},
+    read_filter::{ReadFilter, parse_flags},
+    translate_options::NameRewrite,
+    util::RecordType,
+};
+use anyhow::{Error, Result, anyhow};
+use rust_htslib::bam::HeaderView;
+use std::{fmt, str::FromStr};
+
+/// SAM flag: segment unmapped
+const FLAG_UNMAPPED: u16 = 0x4;
+
+/// Fewest bases at the 3' end of a read that must match the start of the adapter for it to be
+/// trimmed, so that a base or two matching by chance isn't trimmed from every read
+const MIN_ADAPTER_OVERLAP: usize = 3;
+
+/// Fewest bases of overlap with the adapter before a mismatch is allowed, so that short overlaps
+/// must match exactly
+const MIN_MISMATCH_OVERLAP: usize = 10;
+
+/// Most mismatches allowed when matching the adapter: with more, overlaps of MIN_MISMATCH_OVERLAP
+/// bases would match by chance often enough to trim reads with no adapter
+pub const MAX_ADAPTER_MISMATCHES: usize = 1;
+
+/// Trimming of reads: a fixed number of bases from the start and end, then a 3' adapter and
+/// everything after it. The adapter is found at the first position where the rest of the read
+/// matches the start of the adapter, with at most max_mismatches mismatches if the overlap is at
+/// least MIN_MISMATCH_OVERLAP bases, and exactly otherwise. Qualities are trimmed with the
+/// sequence, and other fields are kept.
+///
+/// This builder-style struct allows setting each kind of trimming separately.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Trim {
+    /// Number of bases to remove from the start of every read
+    pub start: usize,
+    /// Number of bases to remove from the end of every read
+    pub end: usize,
+    /// Adapter sequence to remove from the 3' end, as uppercase ASCII bases
+    pub adapter: Option<Vec<u8>>,
+    /// Most mismatches allowed in overlaps with the adapter of at least MIN_MISMATCH_OVERLAP, up
+    /// to MAX_ADAPTER_MISMATCHES
+    pub max_mismatches: usize,
+    /// Number of reads that were shortened
+    num_trimmed: usize,
+    /// Number of reads an adapter was found in
+    num_adapters: usize,
+    /// Number of reads seen
+    num_reads: usize,
+}
+
+impl Trim {
+    /// Create a new Trim that leaves reads unchanged.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the number of bases to remove from the start of every read.
+    pub fn start(&mut self, start: usize) -> &mut Self {
+        self.start = start;
+        self
+    }
+
+    /// Set the number of bases to remove from the end of every read.
+    pub fn end(&mut self, end: usize) -> &mut Self {
+        self.end = end;
+        self
+    }
+
+    /// Set the adapter sequence to remove from the 3' end of reads.
+    pub fn adapter(&mut self, adapter: Option<&[u8]>) -> &mut Self {
+        self.adapter = adapter.map(|adapter| adapter.to_ascii_uppercase());
+        self
+    }
+
+    /// Set the most mismatches allowed when matching the adapter, up to MAX_ADAPTER_MISMATCHES.
+    pub fn max_mismatches(&mut self, max_mismatches: usize) -> Result<&mut Self> {
+        self.max_mismatches = max_mismatches;
+        self.check_mismatches()?;
+        Ok(self)
+    }
+
+    /// Check that the most mismatches allowed is at most MAX_ADAPTER_MISMATCHES.
+    fn check_mismatches(&self) -> Result<()> {
+        if self.max_mismatches > MAX_ADAPTER_MISMATCHES {
+            return Err(anyhow!(
+                "At most {MAX_ADAPTER_MISMATCHES} adapter mismatch(es) can be allowed, not {}.",
+                self.max_mismatches
+            ));
+        }
+        Ok(())
+    }
+
+    /// Return true if the trim would change reads
+    pub fn is_active(&self) -> bool {
+        self.start > 0 || self.end > 0 || self.adapter.is_some()
+    }
+
+    /// Get the position of the adapter in seq, if it is there.
+    fn find_adapter(&self, seq: &[u8]) -> Option<usize> {
+        let adapter = self.adapter.as_ref()?;
+        (0..seq.len()).find(|&position| {
+            let overlap = adapter.len().min(seq.len() - position);
+            let max_mismatches = if overlap >= MIN_MISMATCH_OVERLAP {
+                self.max_mismatches
+            } else {
+                0
+            };
+            overlap >= MIN_ADAPTER_OVERLAP
+                && seq[position..position + overlap]
+                    .iter()
+                    .zip(adapter)
+                    .filter(|(base, adapter_base)| !base.eq_ignore_ascii_case(adapter_base))
+                    .take(max_mismatches + 1)
+                    .count()
+                    <= max_mismatches
+        })
+    }
+
+    /// Trim record in place. Aligned records can't be trimmed without invalidating their
+    /// alignment, so they are an error.
+    pub fn apply<R>(&mut self, record: &mut R) -> Result<()>
+    where
+        R: ChunkableRecord,
+    {
+        self.num_reads += 1;
+        let seq = record.seq();
+        let mut stop = seq.len().saturating_sub(self.end);
+        let start = self.start.min(stop);
+        if let Some(position) = self.find_adapter(&seq[start..stop]) {
+            stop = start + position;
+            self.num_adapters += 1;
+        }
+        if start == 0 && stop == seq.len() {
+            return Ok(());
+        }
+        if record
+            .flags()
+            .is_some_and(|flags| flags & FLAG_UNMAPPED == 0)
+        {
+            return Err(anyhow!(
+                "Cannot trim {}: it is aligned, and trimming would invalidate its alignment.",
+                String::from_utf8_lossy(record.qname())
+            ));
+        }
+        let seq = seq[start..stop].to_vec();
+        let qual = match record.qual() {
+            Some(qual) => qual[start..stop].to_vec(),
+            None => vec![MISSING_QUALITY_SENTINEL; seq.len()],
+        };
+        record.set_seq_qual(&seq, &qual)?;
+        self.num_trimmed += 1;
+        Ok(())
+    }
+}
+
+/// Summarize the trimming, e.g. "Trimmed 120 of 400 reads (80 at an adapter)."
+impl fmt::Display for Trim {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Trimmed {} of {} reads ({} at an adapter).",
+            self.num_trimmed, self.num_reads, self.num_adapters
+        )
+    }
+}
+
+/// Trim records, reporting whether they were shortened
+impl<R> RecordTransform<R> for Trim
+where
+    R: ChunkableRecord,
+{
+    fn transform(&mut self, record: &mut R) -> Result<TransformResult> {
+        let num_trimmed = self.num_trimmed;
+        self.apply(record)?;
+        Ok(if self.num_trimmed > num_trimmed {
+            TransformResult::Modified
+        } else {
+            TransformResult::Keep
+        })
+    }
+}
+
+/// Renaming of reads with a NameRewrite, e.g. adding a prefix to keep reads from several samples
+/// apart. Every read of a query group gets the same new name (up to its pair suffix), so groups
+/// stay intact.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Rename {
+    /// Rewriting of query names and comments
+    pub name_rewrite: NameRewrite,
+    /// Number of reads renamed
+    num_renamed: usize,
+}
+
+impl Rename {
+    /// Create a new Rename that rewrites names with name_rewrite.
+    pub fn new(name_rewrite: NameRewrite) -> Self {
+        Rename {
+            name_rewrite,
+            num_renamed: 0,
+        }
+    }
+}
+
+/// Rename records, reporting whether their names changed
+impl<R> RecordTransform<R> for Rename
+where
+    R: ChunkableRecord,
+{
+    fn transform(&mut self, record: &mut R) -> Result<TransformResult> {
+        let qname = self
+            .name_rewrite
+            .rewrite(record.qname(), record.comment(), record.flags());
+        let is_renamed = qname.as_ref() != record.qname();
+        let removes_comment = self.name_rewrite.removes_comment() && record.comment().is_some();
+        if !is_renamed && !removes_comment {
+            return Ok(TransformResult::Keep);
+        }
+        let qname = qname.into_owned();
+        if removes_comment {
+            record.set_comment(b"");
+        }
+        record.set_qname(&qname)?;
+        self.num_renamed += 1;
+        Ok(TransformResult::Modified)
+    }
+}
+
+/// Summarize the renaming, e.g. "Renamed 400 reads."
+impl fmt::Display for Rename {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Renamed {} reads.", self.num_renamed)
+    }
+}
+
+/// Conversion of lowercase (e.g. soft-masked) bases to uppercase, for tools that treat them
+/// differently. Qualities and alignments are unchanged.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Uppercase {
+    /// Number of reads that had lowercase bases
+    num_changed: usize,
+}
+
+/// Uppercase the bases of records, reporting whether any changed
+impl<R> RecordTransform<R> for Uppercase
+where
+    R: ChunkableRecord,
+{
+    fn transform(&mut self, record: &mut R) -> Result<TransformResult> {
+        let seq = record.seq();
+        if !seq.iter().any(u8::is_ascii_lowercase) {
+            return Ok(TransformResult::Keep);
+        }
+        let seq = seq.to_ascii_uppercase();
+        let qual = match record.qual() {
+            Some(qual) => qual.into_owned(),
+            None => vec![MISSING_QUALITY_SENTINEL; seq.len()],
+        };
+        record.set_seq_qual(&seq, &qual)?;
+        self.num_changed += 1;
+        Ok(TransformResult::Modified)
+    }
+}
+
+/// Summarize the conversion, e.g. "Uppercased 12 reads."
+impl fmt::Display for Uppercase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Uppercased {} reads.", self.num_changed)
+    }
+}
+
+/// Built-in transform, as named on the command line: "NAME" or "NAME:KEY=VALUE,KEY=VALUE", e.g.
+/// "filter:min-length=100,exclude-flags=0x900", "trim:start=10,adapter=AGATCGGAAGAGC",
+/// "rename:prefix=sampleA_", or "uppercase".
+#[derive(Clone, Debug, PartialEq)]
+pub enum Transform {
+    /// Keys: min-length, min-mean-qual, include-flags, exclude-flags
+    Filter(ReadFilter),
+    /// Keys: start, end, adapter, mismatches
+    Trim(Trim),
+    /// Keys: prefix, strip-comment, whitespace, pair-suffix
+    Rename(Rename),
+    /// No keys
+    Uppercase(Uppercase),
+}
+
+impl Transform {
+    /// Return true if the transform tests SAM flags, which only SAM/BAM/CRAM records have
+    pub fn uses_flags(&self) -> bool {
+        matches!(self, Transform::Filter(read_filter) if read_filter.uses_flags())
+    }
+}
+
+/// Apply the built-in transform
+impl<R> RecordTransform<R> for Transform
+where
+    R: ChunkableRecord,
+{
+    fn transform(&mut self, record: &mut R) -> Result<TransformResult> {
+        match self {
+            Transform::Filter(read_filter) => read_filter.transform(record),
+            Transform::Trim(trim) => trim.transform(record),
+            Transform::Rename(rename) => rename.transform(record),
+            Transform::Uppercase(uppercase) => uppercase.transform(record),
+        }
+    }
+}
+
+/// Summarize what the transform did
+impl fmt::Display for Transform {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Transform::Filter(read_filter) => write!(f, "{read_filter}"),
+            Transform::Trim(trim) => write!(f, "{trim}"),
+            Transform::Rename(rename) => write!(f, "{rename}"),
+            Transform::Uppercase(uppercase) => write!(f, "{uppercase}"),
+        }
+    }
+}
+
+/// Parse a built-in transform from its command line form.
+impl FromStr for Transform {
+    type Err = Error;
+
+    fn from_str(spec: &str) -> Result<Self> {
+        let (name, params) = spec.split_once(':').unwrap_or((spec, ""));
+        let params: Vec<(&str, &str)> = params
+            .split(',')
+            .filter(|param| !param.is_empty())
+            .map(|param| {
+                param
+                    .split_once('=')
+                    .ok_or_else(|| anyhow!("Transform parameter {param:?} must be KEY=VALUE."))
+            })
+            .collect::<Result<_>>()?;
+        let unknown = |key: &str| anyhow!("Unknown parameter {key:?} of transform {name:?}.");
+        let transform = match name {
+            "filter" => {
+                let mut read_filter = ReadFilter::new();
+                for (key, value) in params {
+                    match key {
+                        "min-length" => read_filter.min_length(value.parse()?),
+                        "min-mean-qual" => read_filter.min_mean_qual(Some(value.parse()?)),
+                        "include-flags" => read_filter.include_flags(parse_flags(value)?),
+                        "exclude-flags" => read_filter.exclude_flags(parse_flags(value)?),
+                        _ => return Err(unknown(key)),
+                    };
+                }
+                Transform::Filter(read_filter)
+            }
+            "trim" => {
+                let mut trim = Trim::new();
+                for (key, value) in params {
+                    match key {
+                        "start" => trim.start(value.parse()?),
+                        "end" => trim.end(value.parse()?),
+                        "adapter" => trim.adapter(Some(value.as_bytes())),
+                        "mismatches" => trim.max_mismatches(value.parse()?)?,
+                        _ => return Err(unknown(key)),
+                    };
+                }
+                Transform::Trim(trim)
+            }
+            "rename" => {
+                let mut name_rewrite = NameRewrite::new();
+                for (key, value) in params {
+                    match key {
+                        "prefix" => name_rewrite.prefix(value.as_bytes()),
+                        "strip-comment" => name_rewrite.strip_comment(value.parse()?),
+                        "whitespace" => name_rewrite.whitespace(Some(parse_whitespace(value)?)),
+                        "pair-suffix" => name_rewrite.pair_suffix(value.parse()?),
+                        _ => return Err(unknown(key)),
+                    };
+                }
+                Transform::Rename(Rename::new(name_rewrite))
+            }
+            "uppercase" => match params.first() {
+                Some((key, _)) => return Err(unknown(key)),
+                None => Transform::Uppercase(Uppercase::default()),
+            },
+            _ => {
+                return Err(anyhow!(
+                    "Unknown transform {name:?}: expected filter, trim, rename, or uppercase."
+                ));
+            }
+        };
+        Ok(transform)
+    }
+}
+
+/// Parse the single printable, non-whitespace ASCII character that replaces whitespace in names.
+pub fn parse_whitespace(whitespace: &str) -> Result<u8> {
+    match whitespace.as_bytes() {
+        [byte] if byte.is_ascii_graphic() => Ok(*byte),
+        _ => Err(anyhow!(
+            "Invalid whitespace replacement {whitespace:?}: it must be one printable character."
+        )),
+    }
+}
+
+/// Ordered chain of transforms applied to every record of a chunk between reading and writing
+/// it, so that pipelines that split before e.g. trimming don't pay for another pass over all the
+/// data. A record dropped by one transform is not passed to the rest.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TransformPipeline {
+    transforms: Vec<Transform>,
+}
+
+impl TransformPipeline {
+    /// Create a new TransformPipeline that leaves records unchanged.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a transform to the end of the chain. Filters, trims, and renames that would do nothing
+    /// are left out.
+    pub fn push(&mut self, transform: Transform) -> &mut Self {
+        let is_active = match &transform {
+            Transform::Filter(read_filter) => read_filter.is_active(),
+            Transform::Trim(trim) => trim.is_active(),
+            Transform::Rename(rename) => !rename.name_rewrite.is_empty(),
+            Transform::Uppercase(_) => true,
+        };
+        if is_active {
+            self.transforms.push(transform);
+        }
+        self
+    }
+
+    /// Return true if no transform is set
+    pub fn is_empty(&self) -> bool {
+        self.transforms.is_empty()
+    }
+
+    /// Return true if any transform tests SAM flags, which only SAM/BAM/CRAM records have
+    pub fn uses_flags(&self) -> bool {
+        self.transforms.iter().any(Transform::uses_flags)
+    }
+
+    /// Check, before any record is written, that the transforms can be applied to the records of
+    /// an input with this SAM header (None for FASTQ): that trims allow at most
+    /// MAX_ADAPTER_MISMATCHES mismatches, and that the input has no reference sequences if reads
+    /// are trimmed, because trimming aligned reads would invalidate their alignments.
+    pub fn check_input(&self, header: Option<&HeaderView>) -> Result<()> {
+        for transform in &self.transforms {
+            if let Transform::Trim(trim) = transform {
+                trim.check_mismatches()?;
+                if header.is_some_and(|header| header.target_count() > 0) {
+                    return Err(anyhow!(
+                        "Cannot trim reads of input with reference sequences (@SQ lines): they \
+                        may be aligned, and trimming would invalidate their alignments."
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Check, before any record is written, that the transforms suit output of output_type: that
+    /// renames only add pair suffixes to FASTQ.
+    pub fn check_output(&self, output_type: RecordType) -> Result<()> {
+        for transform in &self.transforms {
+            if let Transform::Rename(rename) = transform {
+                rename.name_rewrite.check_output(output_type)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Apply each transform in turn, stopping at the first that drops the record.
+impl<R> RecordTransform<R> for TransformPipeline
+where
+    R: ChunkableRecord,
+{
+    fn transform(&mut self, record: &mut R) -> Result<TransformResult> {
+        let mut result = TransformResult::Keep;
+        for transform in &mut self.transforms {
+            match transform.transform(record)? {
+                TransformResult::Drop => return Ok(TransformResult::Drop),
+                TransformResult::Modified => result = TransformResult::Modified,
+                TransformResult::Keep => {}
+            }
+        }
+        Ok(result)
+    }
+}
+
+/// Summarize what each transform did, one line per transform.
+impl fmt::Display for TransformPipeline {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let lines: Vec<String> = self
+            .transforms
+            .iter()
+            .map(|transform| transform.to_string())
+            .collect();
+        write!(f, "{}", lines.join("\n"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Transform, TransformPipeline, Trim};
+    use crate::{
+        chunkable::{ChunkableRecord, RecordTransform, TransformResult},
+        fastq::FastqRecord,
+        util::RecordType,
+    };
+    use anyhow::Result;
+    use rust_htslib::bam::{Header, HeaderView, Record, header::HeaderRecord};
+
+    /// Test hard trimming and adapter trimming, with and without mismatches, and that aligned
+    /// records are not trimmed.
+    #[test]
+    fn test_trim() -> Result<()> {
+        let adapter = b"AGATCGGAAGAGC";
+        let mut trim = Trim::new();
+        trim.adapter(Some(b"agatcggaagagc")).max_mismatches(1)?;
+        assert_eq!(trim.find_adapter(b"ACGTACGTAGATCGGAAGAGCACAC"), Some(8));
+        // one mismatch in a long overlap, none in a short one
+        assert_eq!(trim.find_adapter(b"ACGTACGTAGATCGGTAGAGC"), Some(8));
+        assert_eq!(trim.find_adapter(b"ACGTACGTAGATC"), Some(8));
+        assert_eq!(trim.find_adapter(b"ACGTACGTAGTTC"), None);
+        assert_eq!(trim.find_adapter(b"ACGTACGTCCAG"), None);
+        assert_eq!(trim.find_adapter(&adapter[..2]), None);
+        trim.max_mismatches(0)?;
+        assert_eq!(trim.find_adapter(b"ACGTACGTAGATCGGTAGAGC"), None);
+        assert!(trim.max_mismatches(2).is_err());
+        assert!(
+            "trim:adapter=ACGT,mismatches=2"
+                .parse::<Transform>()
+                .is_err()
+        );
+
+        let mut fastq = FastqRecord::new();
+        fastq.set_fields(b"read1", b"TTACGTACGTAGATCGGAAG", &[30; 20])?;
+        fastq.set_comment(b"BC:Z:ACGT");
+        let mut pipeline = TransformPipeline::new();
+        pipeline.push(Transform::Trim(Trim::new()));
+        assert!(pipeline.is_empty());
+        let mut trim = Trim::new();
+        trim.start(2).end(1).adapter(Some(adapter));
+        pipeline.push(Transform::Trim(trim));
+        assert_eq!(pipeline.transform(&mut fastq)?, TransformResult::Modified);
+        assert_eq!(fastq.seq().as_ref(), b"ACGTACGT");
+        assert_eq!(fastq.qual().unwrap().as_ref(), &[30; 8]);
+        assert_eq!(fastq.comment(), Some(b"BC:Z:ACGT".as_slice()));
+        assert_eq!(
+            pipeline.to_string(),
+            "Trimmed 1 of 1 reads (1 at an adapter)."
+        );
+
+        let mut record = Record::new();
+        record.set(b"read2", None, b"ACGTACGT", &[30; 8]);
+        record.set_flags(0x4);
+        let mut trim = Trim::new();
+        trim.start(3);
+        trim.apply(&mut record)?;
+        assert_eq!(record.seq().as_bytes(), b"TACGT");
+        assert_eq!(record.flags(), 0x4);
+        record.set_flags(0);
+        assert!(trim.apply(&mut record).is_err());
+
+        // input with reference sequences is rejected before any record is read
+        assert!(pipeline.check_input(None).is_ok());
+        let unaligned = HeaderView::from_header(&Header::new());
+        assert!(pipeline.check_input(Some(&unaligned)).is_ok());
+        let mut header = Header::new();
+        header.push_record(
+            HeaderRecord::new(b"SQ")
+                .push_tag(b"SN", "chr1")
+                .push_tag(b"LN", 1000),
+        );
+        let err = pipeline
+            .check_input(Some(&HeaderView::from_header(&header)))
+            .unwrap_err();
+        assert!(err.to_string().contains("Cannot trim"), "{err}");
+        let mut loose = Trim::new();
+        loose.adapter(Some(adapter)).max_mismatches = 3;
+        let mut pipeline = TransformPipeline::new();
+        pipeline.push(Transform::Trim(loose));
+        assert!(pipeline.check_input(None).is_err());
+        Ok(())
+    }
+
+    /// Test parsing transforms from the command line, and that a chain applies them in order,
+    /// stopping at the first that drops a read.
+    #[test]
+    fn test_transform_chain() -> Result<()> {
+        let mut pipeline = TransformPipeline::new();
+        for spec in [
+            "trim:start=2",
+            "uppercase",
+            "filter:min-length=6",
+            "rename:prefix=s1_",
+        ] {
+            pipeline.push(spec.parse()?);
+        }
+        assert!(!pipeline.uses_flags());
+        assert!(
+            "filter:exclude-flags=0x900"
+                .parse::<Transform>()?
+                .uses_flags()
+        );
+        assert!("reverse".parse::<Transform>().is_err());
+        assert!("trim:start".parse::<Transform>().is_err());
+        assert!("uppercase:all=1".parse::<Transform>().is_err());
+        assert!("filter:min-length=x".parse::<Transform>().is_err());
+        assert!(pipeline.check_output(RecordType::Bam).is_ok());
+        let mut suffixing = TransformPipeline::new();
+        suffixing.push("rename:pair-suffix=true".parse()?);
+        assert!(suffixing.check_output(RecordType::Fastq).is_ok());
+        assert!(suffixing.check_output(RecordType::Bam).is_err());
+
+        let mut fastq = FastqRecord::new();
+        fastq.set_fields(b"read1", b"ACgtacGT", &[30; 8])?;
+        assert_eq!(pipeline.transform(&mut fastq)?, TransformResult::Modified);
+        assert_eq!(fastq.qname(), b"s1_read1");
+        assert_eq!(fastq.seq().as_ref(), b"GTACGT");
+        assert_eq!(fastq.qual().unwrap().as_ref(), &[30; 6]);
+        fastq.set_fields(b"read2", b"ACGTACG", &[30; 7])?;
+        assert_eq!(pipeline.transform(&mut fastq)?, TransformResult::Drop);
+        assert_eq!(fastq.qname(), b"read2");
+
+        let mut record = Record::new();
+        record.set(b"read3", None, b"ACGTACGTAC", &[30; 10]);
+        record.set_flags(0x4);
+        assert_eq!(pipeline.transform(&mut record)?, TransformResult::Modified);
+        assert_eq!(record.qname(), b"s1_read3");
+        assert_eq!(record.seq().as_bytes(), b"GTACGTAC");
+        assert_eq!(
+            pipeline.to_string(),
+            "Trimmed 3 of 3 reads (0 at an adapter).\nUppercased 1 reads.\n\
+            Filtered out 1 of 3 reads: 1 too short, 0 low quality, 0 by flags.\nRenamed 2 reads."
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,400 @@
+use crate::{chunkable::MissingQualityPolicy, util::RecordType};
+use anyhow::{Result, anyhow};
+use rust_htslib::bam::{Header, HeaderView};
+use std::{borrow::Cow, collections::HashMap, path::Path};
+
+/// SAM flag: template has multiple segments
+const FLAG_PAIRED: u16 = 0x1;
+/// SAM flag: segment unmapped
+const FLAG_UNMAPPED: u16 = 0x4;
+/// SAM flag: next segment unmapped
+const FLAG_MATE_UNMAPPED: u16 = 0x8;
+/// SAM flag: first segment in the template
+const FLAG_FIRST_OF_PAIR: u16 = 0x40;
+/// SAM flag: last segment in the template
+const FLAG_SECOND_OF_PAIR: u16 = 0x80;
+
+/// Which read of a pair a FASTQ record (or every record of a FASTQ file) is.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PairEnd {
+    First,
+    Second,
+}
+
+impl PairEnd {
+    /// Infer the pair end of every read in a FASTQ from R1/R2 file naming conventions, e.g.
+    /// "sample_R1_001.fastq.gz", "sample.R2.fq", or "sample_1.fastq". None if the name doesn't
+    /// say, or says both.
+    pub fn from_path<P>(path: P) -> Option<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let file_name = path.as_ref().file_name()?.to_str()?.to_ascii_lowercase();
+        let tokens: Vec<&str> = file_name.split(['_', '.', '-']).collect();
+        let is_first = tokens.contains(&"r1");
+        let is_second = tokens.contains(&"r2");
+        match (is_first, is_second) {
+            (true, false) => Some(PairEnd::First),
+            (false, true) => Some(PairEnd::Second),
+            (true, true) => None,
+            (false, false) => {
+                // "_1" or "_2" right before the FASTQ extension
+                let extension = tokens
+                    .iter()
+                    .position(|token| *token == "fq" || *token == "fastq")?;
+                match *tokens.get(extension.checked_sub(1)?)? {
+                    "1" => Some(PairEnd::First),
+                    "2" => Some(PairEnd::Second),
+                    _ => None,
+                }
+            }
+        }
+    }
+
+    /// Split a "/1" or "/2" pair suffix off a FASTQ query name. None if there is no suffix.
+    pub fn from_qname_suffix(qname: &[u8]) -> Option<(Self, &[u8])> {
+        match qname {
+            [name @ .., b'/', b'1'] if !name.is_empty() => Some((PairEnd::First, name)),
+            [name @ .., b'/', b'2'] if !name.is_empty() => Some((PairEnd::Second, name)),
+            _ => None,
+        }
+    }
+
+    /// SAM flags for an unaligned read at this end of a pair whose mate is also unaligned
+    pub fn unaligned_flags(&self) -> u16 {
+        let end_flag = match self {
+            PairEnd::First => FLAG_FIRST_OF_PAIR,
+            PairEnd::Second => FLAG_SECOND_OF_PAIR,
+        };
+        FLAG_PAIRED | FLAG_UNMAPPED | FLAG_MATE_UNMAPPED | end_flag
+    }
+}
+
+/// Read-group metadata to stamp into the comment of every translated FASTQ record, so provenance
+/// survives the FASTQ intermediate even for tools that do not read tags.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ReadGroupStamp {
+    /// Stamp "RG:Z:<id>" from the read's RG tag
+    read_group: bool,
+    /// Map from read group ID to sample name. If present, stamp "SM:Z:<sample>"
+    samples: Option<HashMap<Vec<u8>, Vec<u8>>>,
+}
+
+impl ReadGroupStamp {
+    /// Create a new ReadGroupStamp that stamps nothing.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Stamp "RG:Z:<id>" from the read's RG tag.
+    pub fn read_group(&mut self, read_group: bool) -> &mut Self {
+        self.read_group = read_group;
+        self
+    }
+
+    /// Stamp "SM:Z:<sample>", looking up the read's read group in the @RG lines of the header.
+    pub fn samples_from_header(&mut self, header: &HeaderView) -> &mut Self {
+        let samples = Header::from_template(header)
+            .to_hashmap()
+            .get("RG")
+            .map(|read_groups| {
+                read_groups
+                    .iter()
+                    .filter_map(|read_group| {
+                        Some((
+                            read_group.get("ID")?.as_bytes().to_vec(),
+                            read_group.get("SM")?.as_bytes().to_vec(),
+                        ))
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        self.samples = Some(samples);
+        self
+    }
+
+    /// Return true if this stamp will never add anything to a comment.
+    pub fn is_empty(&self) -> bool {
+        !self.read_group && self.samples.is_none()
+    }
+
+    /// Get the tab-separated comment to stamp for a read in the requested read group.
+    pub fn comment(&self, read_group: Option<&[u8]>) -> Vec<u8> {
+        let mut fields: Vec<Vec<u8>> = Vec::with_capacity(2);
+        if let Some(read_group) = read_group {
+            if self.read_group {
+                fields.push([b"RG:Z:".as_slice(), read_group].concat());
+            }
+            if let Some(sample) = self
+                .samples
+                .as_ref()
+                .and_then(|samples| samples.get(read_group))
+            {
+                fields.push([b"SM:Z:".as_slice(), sample].concat());
+            }
+        }
+        fields.join(b"\t".as_slice())
+    }
+}
+
+/// Rewriting of query names as records are written, to normalize names from mixed sources: drop
+/// the FASTQ comment, or join it to the name with a character replacing whitespace, then add a
+/// "/1" or "/2" suffix to paired reads, then a prefix.
+///
+/// This builder-style struct allows setting each rewrite separately; with none set, names are
+/// unchanged.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct NameRewrite {
+    /// Remove the FASTQ comment
+    pub strip_comment: bool,
+    /// Character to replace whitespace with, joining the comment to the name
+    pub whitespace: Option<u8>,
+    /// Add "/1" or "/2" to reads flagged as first or second of a pair, unless already there
+    pub pair_suffix: bool,
+    /// Prefix to add to every name
+    pub prefix: Vec<u8>,
+}
+
+impl NameRewrite {
+    /// Create a new NameRewrite that leaves names unchanged.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Remove the FASTQ comment from every record.
+    pub fn strip_comment(&mut self, strip_comment: bool) -> &mut Self {
+        self.strip_comment = strip_comment;
+        self
+    }
+
+    /// Replace whitespace with this character, joining any FASTQ comment to the name (unless it
+    /// is stripped).
+    pub fn whitespace(&mut self, whitespace: Option<u8>) -> &mut Self {
+        self.whitespace = whitespace;
+        self
+    }
+
+    /// Add "/1" or "/2" to the names of reads flagged as first or second of a pair.
+    pub fn pair_suffix(&mut self, pair_suffix: bool) -> &mut Self {
+        self.pair_suffix = pair_suffix;
+        self
+    }
+
+    /// Set the prefix to add to every name.
+    pub fn prefix(&mut self, prefix: &[u8]) -> &mut Self {
+        self.prefix = prefix.to_vec();
+        self
+    }
+
+    /// Return true if no rewrite is set
+    pub fn is_empty(&self) -> bool {
+        !self.strip_comment
+            && self.whitespace.is_none()
+            && !self.pair_suffix
+            && self.prefix.is_empty()
+    }
+
+    /// Return true if the comment is removed from records, either stripped or joined to the name
+    pub fn removes_comment(&self) -> bool {
+        self.strip_comment || self.whitespace.is_some()
+    }
+
+    /// Check that the rewrite suits output of output_type. Pair suffixes are only for FASTQ:
+    /// SAM/BAM/CRAM mark mates by their flags, and suffixes would give them different query names.
+    pub fn check_output(&self, output_type: RecordType) -> Result<()> {
+        if self.pair_suffix && output_type != RecordType::Fastq {
+            return Err(anyhow!(
+                "Pair suffixes can only be added to FASTQ output, not {output_type}: they would \
+                give the mates of a pair different query names."
+            ));
+        }
+        Ok(())
+    }
+
+    /// Get the rewritten name of a record with qname, FASTQ comment, and SAM flags, or qname
+    /// itself if it is unchanged.
+    pub fn rewrite<'a>(
+        &self,
+        qname: &'a [u8],
+        comment: Option<&[u8]>,
+        flags: Option<u16>,
+    ) -> Cow<'a, [u8]> {
+        if self.is_empty() {
+            return Cow::Borrowed(qname);
+        }
+        let mut name = self.prefix.clone();
+        name.extend_from_slice(qname);
+        if let Some(whitespace) = self.whitespace {
+            if let Some(comment) = comment.filter(|comment| !comment.is_empty())
+                && !self.strip_comment
+            {
+                name.push(whitespace);
+                name.extend_from_slice(comment);
+            }
+            for byte in &mut name {
+                if byte.is_ascii_whitespace() {
+                    *byte = whitespace;
+                }
+            }
+        }
+        if self.pair_suffix
+            && let Some(flags) = flags.filter(|flags| flags & FLAG_PAIRED != 0)
+            && PairEnd::from_qname_suffix(&name).is_none()
+        {
+            if flags & FLAG_FIRST_OF_PAIR != 0 {
+                name.extend_from_slice(b"/1");
+            } else if flags & FLAG_SECOND_OF_PAIR != 0 {
+                name.extend_from_slice(b"/2");
+            }
+        }
+        Cow::Owned(name)
+    }
+}
+
+/// Options controlling how records are translated between record types.
+///
+/// This builder-style struct allows setting optional parameters for translation.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TranslateOptions {
+    /// How to handle records with missing qualities
+    pub missing_quality: MissingQualityPolicy,
+    /// Read-group metadata to stamp into comments of translated records
+    pub read_group_stamp: ReadGroupStamp,
+    /// Pair end of every record without its own flags or "/1" "/2" suffix, e.g. from R1/R2 files
+    pub pair_end: Option<PairEnd>,
+    /// SAM optional fields ("TG:T:value") to add to every translated record
+    pub tags: Vec<Vec<u8>>,
+    /// Rewriting of the query names of translated records
+    pub name_rewrite: NameRewrite,
+    /// Optional fields to carry over from BAM tags to FASTQ comments, or back
+    pub copy_tags: Vec<[u8; 2]>,
+}
+
+impl TranslateOptions {
+    /// Create new TranslateOptions with default settings.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set how to handle records with missing qualities.
+    pub fn missing_quality(&mut self, missing_quality: MissingQualityPolicy) -> &mut Self {
+        self.missing_quality = missing_quality;
+        self
+    }
+
+    /// Set read-group metadata to stamp into comments of translated records.
+    pub fn read_group_stamp(&mut self, read_group_stamp: ReadGroupStamp) -> &mut Self {
+        self.read_group_stamp = read_group_stamp;
+        self
+    }
+
+    /// Set the pair end of records that have no flags or pair suffix of their own.
+    pub fn pair_end(&mut self, pair_end: Option<PairEnd>) -> &mut Self {
+        self.pair_end = pair_end;
+        self
+    }
+
+    /// Set SAM optional fields ("TG:T:value") to add to every translated record.
+    pub fn tags(&mut self, tags: Vec<Vec<u8>>) -> &mut Self {
+        self.tags = tags;
+        self
+    }
+
+    /// Set the rewriting of the query names of translated records.
+    pub fn name_rewrite(&mut self, name_rewrite: NameRewrite) -> &mut Self {
+        self.name_rewrite = name_rewrite;
+        self
+    }
+
+    /// Set the optional fields (e.g. RG, BC, RX) to carry over when translating: BAM tags are
+    /// written into FASTQ comments as "TG:T:value", and such comments are parsed back into tags.
+    pub fn copy_tags(&mut self, copy_tags: Vec<[u8; 2]>) -> &mut Self {
+        self.copy_tags = copy_tags;
+        self
+    }
+
+    /// Return true if the optional field ("TG:T:value") is one to carry over
+    pub fn is_copied(&self, tag: &[u8]) -> bool {
+        self.copy_tags
+            .iter()
+            .any(|copy_tag| tag.starts_with(copy_tag) && tag.get(2) == Some(&b':'))
+    }
+
+    /// Get the query name and SAM flags for an unaligned record that has no flags of its own. A
+    /// "/1" or "/2" suffix marks the first or second read of a pair, and is removed because mates
+    /// must share a query name. Otherwise the pair end set for all records applies, if any.
+    pub fn unaligned_flags<'a>(&self, qname: &'a [u8]) -> (&'a [u8], u16) {
+        if let Some((pair_end, name)) = PairEnd::from_qname_suffix(qname) {
+            (name, pair_end.unaligned_flags())
+        } else if let Some(pair_end) = self.pair_end {
+            (qname, pair_end.unaligned_flags())
+        } else {
+            (qname, FLAG_UNMAPPED)
+        }
+    }
+}
+
+/// Parse the name of a SAM optional field, two characters such as "RX".
+pub fn parse_tag_name(tag: &str) -> Result<[u8; 2]> {
+    match tag.as_bytes() {
+        &[first, second] if first.is_ascii_alphabetic() && second.is_ascii_alphanumeric() => {
+            Ok([first, second])
+        }
+        _ => Err(anyhow!(
+            "Invalid tag {tag:?}: it must be a letter then a letter or digit, e.g. \"RX\"."
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{NameRewrite, TranslateOptions};
+    use crate::{chunkable::ChunkableRecord, fastq::FastqRecord};
+    use anyhow::Result;
+    use rust_htslib::bam::Record;
+
+    /// Test each name rewrite, and that translated records are renamed.
+    #[test]
+    fn test_name_rewrite() -> Result<()> {
+        let mut name_rewrite = NameRewrite::new();
+        assert!(name_rewrite.is_empty());
+        name_rewrite.whitespace(Some(b'_'));
+        assert_eq!(
+            name_rewrite
+                .rewrite(b"read1", Some(b"1:N:0 ACGT"), None)
+                .as_ref(),
+            b"read1_1:N:0_ACGT"
+        );
+        name_rewrite
+            .strip_comment(true)
+            .pair_suffix(true)
+            .prefix(b"s1_");
+        assert_eq!(
+            name_rewrite
+                .rewrite(b"read1", Some(b"1:N:0"), Some(0x1 | 0x80))
+                .as_ref(),
+            b"s1_read1/2"
+        );
+        assert_eq!(
+            name_rewrite
+                .rewrite(b"read1/2", None, Some(0x1 | 0x80))
+                .as_ref(),
+            b"s1_read1/2"
+        );
+        assert_eq!(
+            name_rewrite.rewrite(b"read1", None, Some(0x4)).as_ref(),
+            b"s1_read1"
+        );
+
+        let mut bam_record = Record::new();
+        bam_record.set(b"read2", None, b"ACGT", &[30; 4]);
+        bam_record.set_flags(0x1 | 0x4 | 0x8 | 0x40);
+        let mut fastq = FastqRecord::new();
+        fastq.translate(
+            &bam_record,
+            TranslateOptions::new().name_rewrite(name_rewrite),
+        )?;
+        assert_eq!(ChunkableRecord::qname(&fastq), b"s1_read2/1");
+        Ok(())
+    }
+}
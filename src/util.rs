@@ -1,12 +1,18 @@
 use crate::{
+    atomic_output::AtomicWriter,
+    error_category::{Categorize, ErrorCategory},
     fastq::{FastqReader, FastqWriter},
+    format_detect::detect_record_type,
     maybe_compressed_io::{MaybeCompressedReader, MaybeCompressedWriter},
     path_type::PathType,
+    reference_cache::set_reference,
+    reference_resolver::ReferenceResolver,
+    sam_text::{SamReader, SamTextReader},
 };
-use anyhow::Result;
+use anyhow::{Result, anyhow};
 use env;
-use log::warn;
-use rust_htslib::bam::{Read, Reader};
+use log::{debug, warn};
+use rust_htslib::bam::{Format, HeaderView, IndexedReader, Read, Reader};
 use seq_io::fastq::Reader as SeqIoFastqReader;
 use std::{
     fmt::Display,
@@ -14,7 +20,9 @@ use std::{
     path::{Path, PathBuf},
     process::Command,
     str::FromStr,
+    sync::Once,
 };
+use url::Url;
 
 /// Find the path to the system's SSL certificate file.
 ///
@@ -54,6 +62,51 @@ fn find_cert() -> Result<Option<String>> {
     }
 }
 
+/// Guards the process-wide setup for remote URLs, so it runs once however many readers and
+/// writers open URLs at once.
+static REMOTE_ACCESS_SETUP: Once = Once::new();
+
+/// Configure htslib's remote file access (HTTPS, GCS, S3) for this process, once. htslib reads
+/// the CA certificate bundle from the CURL_CA_BUNDLE environment variable, which cannot be set per
+/// reader, so if it is unset it is set to the system bundle. Opening a URL calls this, but the
+/// environment can only be changed safely while the process is single-threaded: programs that
+/// open URLs from several threads should call it at startup, before starting threads (or set
+/// CURL_CA_BUNDLE themselves). Later calls do nothing.
+pub fn configure_remote_access() {
+    REMOTE_ACCESS_SETUP.call_once(|| {
+        if env::var_os("CURL_CA_BUNDLE").is_some() {
+            return;
+        }
+        // Needed to ensure that certificates are up to date
+        match find_cert() {
+            Ok(Some(cert_path)) => {
+                if env::set_var("CURL_CA_BUNDLE", &cert_path).is_none() {
+                    warn!(
+                        "Unable to set CURL_CA_BUNDLE to {cert_path} while other threads are \
+                        running, so remote access may fail. Set it, or call \
+                        configure_remote_access before starting threads."
+                    );
+                }
+            }
+            Ok(None) => warn!("Unable to find current cert path"),
+            Err(err) => warn!("Unable to find current cert path: {err}"),
+        }
+    });
+}
+
+/// Configure remote access, as configure_remote_access does, to write to url. Errors unless
+/// split-reads was built with the cloud-output feature.
+pub fn configure_remote_output(url: &Url) -> Result<()> {
+    if cfg!(not(feature = "cloud-output")) {
+        return Err(anyhow!(
+            "Cannot write to {url}: split-reads was built without the cloud-output feature. Write \
+            locally and upload the file instead."
+        ));
+    }
+    configure_remote_access();
+    Ok(())
+}
+
 /// Get a BAM reader (also reads SAM and CRAM). Set threads for reading.
 pub fn get_bam_reader<P1, P2>(
     input: P1,
@@ -64,30 +117,107 @@ where
     P1: AsRef<Path>,
     P2: AsRef<Path>,
 {
-    let mut reader = match PathType::from_path(input)? {
-        PathType::Pipe => Reader::from_stdin(),
+    let mut reader = match PathType::from_path(input.as_ref())? {
+        PathType::Pipe => Reader::from_stdin()?,
         PathType::UrlPath(url) => {
-            if env::var("CURL_CA_BUNDLE").is_err() {
-                // Needed to ensure that certificats are up to date
-                if let Some(cert_path) = find_cert()? {
-                    if env::set_var("CURL_CA_BUNDLE", cert_path).is_none() {
-                        warn!("Unable to update certificates, remote read may fail.");
-                    }
-                } else {
-                    warn!("Unable to find current cert path");
-                }
-            }
-            Reader::from_url(&url)
+            configure_remote_access();
+            Reader::from_url(&url).categorize(ErrorCategory::RemoteIo)?
         }
-        PathType::FilePath(file_path) => Reader::from_path(file_path),
-    }?;
+        PathType::FilePath(file_path) => Reader::from_path(file_path)?,
+    };
+    reader.set_threads(threads.into())?;
+    if is_unaligned(reader.header()) {
+        // unaligned reads are decoded without a reference, so don't load one
+        if reference_fasta.is_some() {
+            debug!("Input has no @SQ lines, ignoring reference FASTA.");
+        }
+    } else if let Some(fasta) = reference_fasta {
+        set_reference(&mut reader, fasta)?;
+    } else if RecordType::detect(input.as_ref())?.is_some_and(|t| t.requires_reference()) {
+        match ReferenceResolver::from_env().map(|resolver| resolver.resolve(reader.header())) {
+            Some(Ok(fasta)) => set_reference(&mut reader, fasta)?,
+            Some(Err(err)) => warn!(
+                "Reading CRAM without a reference FASTA, and could not resolve one: {err}. Mapped \
+                reads can only be decoded if htslib finds the reference via REF_PATH or REF_CACHE."
+            ),
+            None => warn!(
+                "Reading CRAM without a reference FASTA. Mapped reads can only be decoded if \
+                htslib finds the reference via REF_PATH or REF_CACHE."
+            ),
+        }
+    }
+    Ok(reader)
+}
+
+/// Get a reader of a BAM/CRAM with a BAI/CSI/CRAI index, to fetch reads of genomic regions. Set
+/// threads for reading, and the reference FASTA for CRAM if given.
+pub fn get_indexed_bam_reader<P1, P2>(
+    input: P1,
+    reference_fasta: Option<P2>,
+    threads: NonZero<usize>,
+) -> Result<IndexedReader>
+where
+    P1: AsRef<Path>,
+    P2: AsRef<Path>,
+{
+    let mut reader = match PathType::from_path(input.as_ref())? {
+        PathType::Pipe => Err(anyhow!("Cannot fetch regions from stdin."))?,
+        PathType::UrlPath(url) => {
+            configure_remote_access();
+            IndexedReader::from_url(&url).categorize(ErrorCategory::RemoteIo)?
+        }
+        PathType::FilePath(file_path) => IndexedReader::from_path(&file_path)
+            .map_err(|err| anyhow!("Could not open {file_path:?} with its index: {err}"))
+            .categorize(ErrorCategory::BadIndex)?,
+    };
     reader.set_threads(threads.into())?;
     if let Some(fasta) = reference_fasta {
-        reader.set_reference(fasta)?;
+        set_reference(&mut reader, fasta)?;
     }
     Ok(reader)
 }
 
+/// Get a reader of SAM/BAM/CRAM records. Local plain-text SAM files are read natively by
+/// SamTextReader, whose offsets are cheap to tell and seek to; everything else (including SAM
+/// from stdin or a URL) is read by htslib, as get_bam_reader does.
+pub fn get_sam_reader<P1, P2>(
+    input: P1,
+    reference_fasta: Option<P2>,
+    threads: NonZero<usize>,
+) -> Result<SamReader>
+where
+    P1: AsRef<Path>,
+    P2: AsRef<Path>,
+{
+    let is_local_sam = matches!(PathType::from_path(input.as_ref())?, PathType::FilePath(_))
+        && RecordType::detect(input.as_ref())? == Some(RecordType::Sam);
+    if is_local_sam {
+        Ok(SamReader::Text(Box::new(SamTextReader::new(
+            MaybeCompressedReader::new(input, threads)?,
+        )?)))
+    } else {
+        Ok(SamReader::Htslib(get_bam_reader(
+            input,
+            reference_fasta,
+            threads,
+        )?))
+    }
+}
+
+/// Return true if the header has no @SQ lines, i.e. the reads are unaligned (e.g. a uBAM).
+/// Unaligned reads never need a reference FASTA.
+pub fn is_unaligned(header: &HeaderView) -> bool {
+    header.target_count() == 0
+}
+
+/// Return true if the header's @HD line declares the reads sorted by coordinate (SO:coordinate).
+pub fn is_coordinate_sorted(header: &HeaderView) -> bool {
+    String::from_utf8_lossy(header.as_bytes())
+        .lines()
+        .find(|line| line.starts_with("@HD\t"))
+        .is_some_and(|line| line.split('\t').any(|field| field == "SO:coordinate"))
+}
+
 /// Get a FASTQ reader, set threads for decompression.
 pub fn get_seq_io_fastq_reader<P>(
     input: P,
@@ -112,7 +242,9 @@ where
     Ok(FastqReader::new(reader))
 }
 
-/// Get a FASTQ writer, set threads for compression.
+/// Get a FASTQ writer, set threads and the level (the codec's default if None) for compression.
+/// Outputs whose path doesn't say whether they are compressed (e.g. stdout) are only compressed
+/// if the level is above 0, as for BAM.
 pub fn get_seq_io_fastq_writer<P>(
     output: P,
     compression: Option<u32>,
@@ -121,69 +253,137 @@ pub fn get_seq_io_fastq_writer<P>(
 where
     P: AsRef<Path>,
 {
-    let compressed = if let Some(ref compression_level) = compression {
-        *compression_level > 0
-    } else {
-        false
-    };
-    MaybeCompressedWriter::new(output, compressed, threads)
+    let compressed = compression.is_some_and(|level| level > 0);
+    let codec = MaybeCompressedWriter::codec(output.as_ref(), compressed)?;
+    MaybeCompressedWriter::with_codec(output, codec, compression, threads)
 }
 
-/// Get a FASTQ writer, set threads for compression.
+/// Get a FASTQ writer, set threads and the level for compression, as get_seq_io_fastq_writer
+/// does. Local files are written under a temporary name (see AtomicWriter), so call finish once
+/// the output is complete.
 pub fn get_fastq_writer<P>(
     output: P,
     compression: Option<u32>,
     threads: NonZero<usize>,
-) -> Result<FastqWriter<MaybeCompressedWriter>>
+) -> Result<AtomicWriter<FastqWriter<MaybeCompressedWriter>>>
 where
     P: AsRef<Path>,
 {
-    let compressed = if let Some(ref compression_level) = compression {
-        *compression_level > 0
-    } else {
-        false
-    };
-    let inner = MaybeCompressedWriter::new(output, compressed, threads)?;
-    Ok(FastqWriter::new(inner))
+    get_fastq_writer_for(output.as_ref(), output.as_ref(), compression, threads)
 }
 
-/// Enum for distinguishing between FASTQ and SAM/BAM/CRAM record formats.
-#[derive(PartialEq, Debug, Clone)]
+/// Get a FASTQ writer to output, compressed as it would be for final_path, e.g. when output is a
+/// pipe whose bytes are copied to final_path (see ChecksummedOutput), as the pipe's path has no
+/// extension.
+pub fn get_fastq_writer_for<P1, P2>(
+    output: P1,
+    final_path: P2,
+    compression: Option<u32>,
+    threads: NonZero<usize>,
+) -> Result<AtomicWriter<FastqWriter<MaybeCompressedWriter>>>
+where
+    P1: AsRef<Path>,
+    P2: AsRef<Path>,
+{
+    let compressed = compression.is_some_and(|level| level > 0);
+    // decide compression by the output path, as a temporary path has a different extension
+    let codec = MaybeCompressedWriter::codec(final_path.as_ref(), compressed)?;
+    AtomicWriter::create(output, |path| {
+        let inner = MaybeCompressedWriter::with_codec(path, codec, compression, threads)?;
+        Ok(FastqWriter::new(inner))
+    })
+}
+
+/// Extensions marking compressed files: BGZF or gzip, and zstd
+const COMPRESSION_EXTENSIONS: [&str; 3] = ["gz", "bgz", "zst"];
+
+/// Extensions of common compressed formats that are not reads, so that e.g. ".vcf.gz" isn't taken
+/// for FASTQ
+const NON_READ_EXTENSIONS: [&str; 12] = [
+    "vcf", "bcf", "bed", "gff", "gff3", "gtf", "fa", "fasta", "fna", "txt", "tsv", "csv",
+];
+
+/// Enum for distinguishing between FASTQ, SAM, BAM, and CRAM record formats.
+#[derive(PartialEq, Debug, Clone, Copy)]
 pub enum RecordType {
-    /// FASTQ format (with extensions .fq, .fastq, .gz, .bgz)
+    /// FASTQ format (with extensions .fq, .fastq, .gz, .bgz, .zst)
     Fastq,
-    /// SAM/BAM/CRAM format (with extensions .bam, .sam, .cram)
+    /// SAM format (with extension .sam)
+    Sam,
+    /// BAM format (with extension .bam)
     Bam,
+    /// CRAM format (with extension .cram)
+    Cram,
 }
 
 impl Display for RecordType {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             RecordType::Fastq => write!(f, "FASTQ"),
-            RecordType::Bam => write!(f, "SAM/BAM/CRAM"),
+            RecordType::Sam => write!(f, "SAM"),
+            RecordType::Bam => write!(f, "BAM"),
+            RecordType::Cram => write!(f, "CRAM"),
         }
     }
 }
 
 impl RecordType {
-    /// Detect the record type from a file path extension.
+    /// Detect the record type from a file path's extensions, including compressed ones such as
+    /// ".fastq.gz" and ".sam.gz" (see from_path_extensions). Compressed BAM and CRAM are not
+    /// recognized, as they can't be read.
     ///
     /// # Arguments
     /// * `path` - The file path to analyze
     ///
     /// # Returns
-    /// `Some(RecordType)` if the extension is recognized, `None` otherwise.
+    /// `Some(RecordType)` if the extensions are recognized, `None` otherwise.
     pub fn from_path<P>(path: P) -> Option<RecordType>
     where
         P: AsRef<Path>,
     {
-        if let Some(extension) = path.as_ref().extension() {
-            Self::from_extension(extension.to_str())
-        } else {
-            None
+        match Self::from_path_extensions(path) {
+            Some((RecordType::Bam | RecordType::Cram, true)) => None,
+            extensions => extensions.map(|(record_type, _)| record_type),
+        }
+    }
+
+    /// Detect the record type from a file path's extensions, and whether they end in a compression
+    /// suffix (".gz", ".bgz" or ".zst"): e.g. (Fastq, true) for "reads.fastq.gz", and (Sam, true) for
+    /// "reads.sam.gz". A compression suffix after an unrecognized extension, or none (e.g.
+    /// "reads.gz"), means FASTQ. None if the extensions aren't recognized, or the compression suffix
+    /// follows a format that isn't reads (e.g. ".vcf.gz" or ".fa.gz").
+    pub fn from_path_extensions<P>(path: P) -> Option<(RecordType, bool)>
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+        let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+        if !COMPRESSION_EXTENSIONS.contains(&extension.as_str()) {
+            return Self::from_extension(Some(&extension)).map(|record_type| (record_type, false));
+        }
+        let inner_extension = Path::new(path.file_stem()?)
+            .extension()
+            .and_then(|inner_extension| inner_extension.to_str())
+            .map(str::to_ascii_lowercase);
+        match inner_extension.as_deref() {
+            Some(inner_extension) if NON_READ_EXTENSIONS.contains(&inner_extension) => None,
+            inner_extension => Some((
+                Self::from_extension(inner_extension).unwrap_or(RecordType::Fastq),
+                true,
+            )),
         }
     }
 
+    /// Detect the record type of an input from its content if it is a local file (see
+    /// detect_record_type), so that files with missing or misleading extensions are read correctly,
+    /// falling back to the path extension (e.g. for stdin and URLs).
+    pub fn detect<P>(path: P) -> Result<Option<RecordType>>
+    where
+        P: AsRef<Path>,
+    {
+        Ok(detect_record_type(path.as_ref())?.or_else(|| Self::from_path(path)))
+    }
+
     /// Detect the record type from a file extension string.
     ///
     /// Recognizes FASTQ extensions (.fq, .fastq, .gz, .bgz) and SAM/BAM/CRAM extensions
@@ -198,11 +398,135 @@ impl RecordType {
         if let Some(extension) = extension {
             match extension.to_ascii_lowercase().as_str() {
                 "fq" | "fastq" | "gz" | "bgz" => Some(RecordType::Fastq),
-                "bam" | "sam" | "cram" => Some(RecordType::Bam),
+                "sam" => Some(RecordType::Sam),
+                "bam" => Some(RecordType::Bam),
+                "cram" => Some(RecordType::Cram),
                 _ => None,
             }
         } else {
             None
         }
     }
+
+    /// Return true for SAM, BAM, and CRAM, which are all read and written by htslib as BamRecords.
+    pub fn is_sam_family(&self) -> bool {
+        self.sam_format().is_some()
+    }
+
+    /// Return true if decoding records of this type may need a reference FASTA.
+    pub fn requires_reference(&self) -> bool {
+        *self == RecordType::Cram
+    }
+
+    /// Get the conventional file extension for this record type.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            RecordType::Fastq => "fastq",
+            RecordType::Sam => "sam",
+            RecordType::Bam => "bam",
+            RecordType::Cram => "cram",
+        }
+    }
+
+    /// Get the htslib format for SAM, BAM, or CRAM. None for FASTQ.
+    pub fn sam_format(&self) -> Option<Format> {
+        match self {
+            RecordType::Fastq => None,
+            RecordType::Sam => Some(Format::Sam),
+            RecordType::Bam => Some(Format::Bam),
+            RecordType::Cram => Some(Format::Cram),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        REMOTE_ACCESS_SETUP, RecordType, configure_remote_access, get_bam_reader, is_unaligned,
+    };
+    use crate::sam_writer_spec::{OutputFormat, SamWriterSpec};
+    use anyhow::Result;
+    use rust_htslib::bam::{Format, Header, Read, Record, header::HeaderRecord};
+    use std::{num::NonZero, thread};
+    use tempfile::TempDir;
+
+    /// Test that remote access setup runs once, however many threads ask for it at once.
+    #[test]
+    fn test_configure_remote_access() {
+        thread::scope(|scope| {
+            for _ in 0..8 {
+                scope.spawn(configure_remote_access);
+            }
+        });
+        assert!(REMOTE_ACCESS_SETUP.is_completed());
+        configure_remote_access();
+    }
+
+    /// Test that each extension maps to its own record type and htslib format.
+    #[test]
+    fn test_record_type_from_path() {
+        assert_eq!(RecordType::from_path("reads.SAM"), Some(RecordType::Sam));
+        assert_eq!(RecordType::from_path("reads.bam"), Some(RecordType::Bam));
+        assert_eq!(RecordType::from_path("reads.cram"), Some(RecordType::Cram));
+        assert_eq!(
+            RecordType::from_path("reads.fq.gz"),
+            Some(RecordType::Fastq)
+        );
+        assert_eq!(RecordType::from_path("reads.txt"), None);
+        assert_eq!(RecordType::from_path("-"), None);
+        assert_eq!(
+            RecordType::from_path_extensions("reads.sam.gz"),
+            Some((RecordType::Sam, true))
+        );
+        assert_eq!(
+            RecordType::from_path_extensions("reads.FQ.BGZ"),
+            Some((RecordType::Fastq, true))
+        );
+        assert_eq!(
+            RecordType::from_path_extensions("sample.R1.gz"),
+            Some((RecordType::Fastq, true))
+        );
+        assert_eq!(
+            RecordType::from_path_extensions("reads.fastq"),
+            Some((RecordType::Fastq, false))
+        );
+        assert_eq!(RecordType::from_path("reads.sam.gz"), Some(RecordType::Sam));
+        assert_eq!(RecordType::from_path("reads.bam.gz"), None);
+        assert_eq!(RecordType::from_path("calls.vcf.gz"), None);
+        assert_eq!(RecordType::from_path("ref.fa.bgz"), None);
+        assert_eq!(RecordType::Cram.sam_format(), Some(Format::Cram));
+        assert!(RecordType::Sam.is_sam_family());
+        assert!(!RecordType::Fastq.is_sam_family());
+        assert!(RecordType::Cram.requires_reference());
+        assert!(!RecordType::Bam.requires_reference());
+    }
+
+    /// Test that unaligned reads are written and read as CRAM without loading a reference, even
+    /// when one is (wrongly) supplied.
+    #[test]
+    fn test_unaligned_ignores_reference() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let cram = temp_dir.path().join("unaligned.cram");
+        let missing_fasta = temp_dir.path().join("missing.fa");
+        let threads = NonZero::new(1).unwrap();
+        let mut header = Header::new();
+        header.push_record(HeaderRecord::new(b"HD").push_tag(b"VN", "1.6"));
+        {
+            let mut writer = SamWriterSpec::new(&cram)
+                .header(header)
+                .format(OutputFormat::Cram)
+                .reference_fasta(Some(&missing_fasta))
+                .threads(threads)
+                .get_bam_writer()?;
+            let mut record = Record::new();
+            record.set(b"read1", None, b"ACGT", &[30, 30, 30, 30]);
+            record.set_unmapped();
+            writer.write(&record)?;
+            writer.finish()?;
+        }
+        let mut reader = get_bam_reader(&cram, Some(&missing_fasta), threads)?;
+        assert!(is_unaligned(reader.header()));
+        assert_eq!(reader.records().count(), 1);
+        Ok(())
+    }
 }
@@ -0,0 +1,340 @@
+use crate::{
+    chunkable::{ChunkableRecord, ChunkableRecordReader, OffsetKind, SplitRange},
+    query_grouping::QueryGroupingChecker,
+    resync::{ResyncFormat, SkippedRange, find_resync_offset},
+};
+use anyhow::{Error, Result, anyhow};
+use log::{info, warn};
+use rust_htslib::errors::Error as HtslibError;
+use std::{
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+/// How strictly to validate records while reading.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum ValidationLevel {
+    /// Error on the first malformed record.
+    #[default]
+    Strict,
+    /// Warn and skip records that htslib rejects as invalid (e.g. inconsistent CIGAR or corrupt
+    /// aux data) but that are complete, so reading can resume at the next record. Truncated
+    /// records are still errors.
+    Lenient,
+}
+
+impl FromStr for ValidationLevel {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "strict" => Ok(ValidationLevel::Strict),
+            "lenient" => Ok(ValidationLevel::Lenient),
+            _ => Err(anyhow!(
+                "Unknown validation level {s:?}, expected \"strict\" or \"lenient\"."
+            )),
+        }
+    }
+}
+
+/// Return true if the read error was for a complete but invalid record, so the next record can
+/// still be read.
+fn is_recoverable(err: &Error) -> bool {
+    matches!(
+        err.downcast_ref::<HtslibError>(),
+        Some(HtslibError::BamInvalidRecord)
+    )
+}
+
+/// Function reopening the underlying reader, because htslib readers stay in an error state after
+/// a corrupt block.
+type Reopen<Reader> = Box<dyn Fn() -> Result<Reader> + Send>;
+
+/// Settings for skipping corrupt records by resynchronizing at the next BGZF block.
+struct SkipErrors<Reader> {
+    /// Maximum number of corrupt records to skip before erroring
+    max_errors: usize,
+    /// Path of the BGZF file being read
+    path: PathBuf,
+    format: ResyncFormat,
+    reopen: Reopen<Reader>,
+}
+
+/// Reader that applies a ValidationLevel to an underlying ChunkableRecordReader. The same records
+/// are skipped every time the file is read, so indices built with lenient validation can be used
+/// to extract chunks with lenient validation.
+pub struct ValidatingReader<Reader> {
+    inner: Reader,
+    level: ValidationLevel,
+    num_skipped: usize,
+    skip_errors: Option<SkipErrors<Reader>>,
+    /// Ranges skipped after corrupt records while reading
+    skipped_ranges: Vec<SkippedRange>,
+    /// Ranges to jump over, from an index built with skip_errors, sorted by start
+    known_skips: Vec<SkippedRange>,
+    /// Check that records are grouped by query name, if set
+    grouping_checker: Option<QueryGroupingChecker>,
+}
+
+impl<Reader> ValidatingReader<Reader> {
+    /// Create a new ValidatingReader wrapping the supplied reader.
+    pub fn new(inner: Reader, level: ValidationLevel) -> Self {
+        ValidatingReader {
+            inner,
+            level,
+            num_skipped: 0,
+            skip_errors: None,
+            skipped_ranges: Vec::new(),
+            known_skips: Vec::new(),
+            grouping_checker: None,
+        }
+    }
+
+    /// On any read error, skip to the first plausible record in a following BGZF block of the file
+    /// at path, up to max_errors times. The skipped ranges are reported by take_skipped_ranges.
+    pub fn skip_errors<P, F>(
+        &mut self,
+        max_errors: usize,
+        path: P,
+        format: ResyncFormat,
+        reopen: F,
+    ) -> &mut Self
+    where
+        P: AsRef<Path>,
+        F: Fn() -> Result<Reader> + Send + 'static,
+    {
+        self.skip_errors = Some(SkipErrors {
+            max_errors,
+            path: path.as_ref().to_path_buf(),
+            format,
+            reopen: Box::new(reopen),
+        });
+        self
+    }
+
+    /// Jump over ranges skipped when the file was indexed, so the same records are read.
+    pub fn skip_ranges(&mut self, ranges: &[SkippedRange]) -> &mut Self {
+        self.known_skips = ranges.to_vec();
+        self.known_skips.sort_by_key(|range| range.start);
+        self
+    }
+
+    /// Check that records are grouped by query name as they are read. Depending on the checker,
+    /// reading errors at the first query name that reappears, or warns with a count at the end.
+    pub fn check_query_grouping(&mut self, grouping_checker: QueryGroupingChecker) -> &mut Self {
+        self.grouping_checker = Some(grouping_checker);
+        self
+    }
+
+    /// Get a reference to the underlying reader, e.g. to get its header
+    pub fn get_ref(&self) -> &Reader {
+        &self.inner
+    }
+
+    /// Get the number of invalid records skipped so far
+    pub fn num_skipped(&self) -> usize {
+        self.num_skipped
+    }
+
+    /// Resynchronize after the read error err at offset. Return Ok(true) if reading can resume,
+    /// Ok(false) if there are no more records, or the error if it cannot be skipped.
+    fn resync<R>(&mut self, offset: Option<u64>, err: Error) -> Result<bool>
+    where
+        R: ChunkableRecord,
+        Reader: ChunkableRecordReader<R>,
+    {
+        let (Some(skip_errors), Some(start)) = (self.skip_errors.as_ref(), offset) else {
+            return Err(err);
+        };
+        if self.skipped_ranges.len() >= skip_errors.max_errors {
+            return Err(anyhow!(
+                "Exceeded {} skipped errors. At offset {start}: {err}",
+                skip_errors.max_errors
+            ));
+        }
+        let resume = find_resync_offset(&skip_errors.path, start, skip_errors.format)?;
+        self.skipped_ranges.push(SkippedRange { start, resume });
+        match resume {
+            Some(resume) => {
+                warn!("Skipping corrupt data at offset {start}, resuming at {resume}: {err}");
+                self.inner = (skip_errors.reopen)()?;
+                self.inner.seek(resume)?;
+                Ok(true)
+            }
+            None => {
+                warn!("Skipping corrupt data from offset {start} to the end of file: {err}");
+                Ok(false)
+            }
+        }
+    }
+}
+
+/// Implement ChunkableRecordReader for ValidatingReader, skipping invalid records if lenient.
+impl<R, Reader> ChunkableRecordReader<R> for ValidatingReader<Reader>
+where
+    R: ChunkableRecord,
+    Reader: ChunkableRecordReader<R>,
+{
+    fn tell(&mut self) -> Result<u64> {
+        self.inner.tell()
+    }
+
+    fn seek(&mut self, offset: u64) -> Result<()> {
+        self.inner.seek(offset)
+    }
+
+    fn offset_kind(&self) -> OffsetKind {
+        self.inner.offset_kind()
+    }
+
+    fn seek_to_range(&mut self, split_range: &SplitRange) -> Result<()> {
+        self.inner.seek_to_range(split_range)
+    }
+
+    fn tell_uncompressed(&mut self) -> Result<Option<u64>> {
+        self.inner.tell_uncompressed()
+    }
+
+    fn read_into(&mut self, record: &mut R) -> Option<Result<()>> {
+        loop {
+            let offset = self.inner.tell().ok();
+            if let Some(offset) = offset
+                && let Ok(index) = self
+                    .known_skips
+                    .binary_search_by_key(&offset, |range| range.start)
+            {
+                let Some(resume) = self.known_skips[index].resume else {
+                    info!("Skipping corrupt data from offset {offset} to the end of file.");
+                    return None;
+                };
+                info!("Skipping corrupt data at offset {offset}, resuming at {resume}.");
+                if let Err(err) = self.inner.seek(resume) {
+                    return Some(Err(err));
+                }
+                continue;
+            }
+            match self.inner.read_into(record) {
+                Some(Err(err))
+                    if self.level == ValidationLevel::Lenient && is_recoverable(&err) =>
+                {
+                    self.num_skipped += 1;
+                    warn!(
+                        "Skipping invalid record at offset {}: {err}",
+                        offset.map_or("unknown".to_string(), |offset| offset.to_string())
+                    );
+                }
+                Some(Err(err)) if self.skip_errors.is_some() => match self.resync(offset, err) {
+                    Ok(true) => continue,
+                    Ok(false) => return None,
+                    Err(err) => return Some(Err(err)),
+                },
+                Some(Ok(())) if self.grouping_checker.is_some() => {
+                    let grouping_checker = self.grouping_checker.as_mut().unwrap();
+                    return Some(grouping_checker.check_record(record, offset));
+                }
+                None => {
+                    if let Some(grouping_checker) = self.grouping_checker.as_ref() {
+                        grouping_checker.report();
+                    }
+                    return None;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    fn take_skipped_ranges(&mut self) -> Vec<SkippedRange> {
+        std::mem::take(&mut self.skipped_ranges)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ValidatingReader, ValidationLevel};
+    use crate::{
+        chunkable::{ChunkableRecord, ChunkableRecordReader, OffsetKind},
+        fastq::FastqRecord,
+    };
+    use anyhow::Result;
+    use rust_htslib::errors::Error as HtslibError;
+
+    /// Reader yielding a fixed sequence of records and errors.
+    struct ScriptedReader {
+        /// Query name of each record, or the error to return instead
+        script: Vec<Result<&'static [u8], HtslibError>>,
+        position: usize,
+    }
+
+    impl ChunkableRecordReader<FastqRecord> for ScriptedReader {
+        fn tell(&mut self) -> Result<u64> {
+            Ok(self.position as u64)
+        }
+
+        fn seek(&mut self, offset: u64) -> Result<()> {
+            self.position = offset as usize;
+            Ok(())
+        }
+
+        fn offset_kind(&self) -> OffsetKind {
+            OffsetKind::Byte
+        }
+
+        fn read_into(&mut self, record: &mut FastqRecord) -> Option<Result<()>> {
+            let step = self.script.get(self.position)?;
+            self.position += 1;
+            Some(match step {
+                Ok(qname) => record.set_fields(qname, b"A", b"I"),
+                Err(HtslibError::BamInvalidRecord) => Err(HtslibError::BamInvalidRecord.into()),
+                Err(_) => Err(HtslibError::BamTruncatedRecord.into()),
+            })
+        }
+    }
+
+    /// Read every record, returning the query names read or the first error.
+    fn read_all(
+        level: ValidationLevel,
+        script: Vec<Result<&'static [u8], HtslibError>>,
+    ) -> Result<Vec<Vec<u8>>> {
+        let mut reader = ValidatingReader::new(
+            ScriptedReader {
+                script,
+                position: 0,
+            },
+            level,
+        );
+        let mut record = FastqRecord::new();
+        let mut qnames = Vec::new();
+        while let Some(result) = reader.read_into(&mut record) {
+            result?;
+            qnames.push(record.qname().to_vec());
+        }
+        Ok(qnames)
+    }
+
+    /// Test that lenient validation skips invalid records but not truncated ones, and strict
+    /// validation skips nothing.
+    #[test]
+    fn test_validation_levels() -> Result<()> {
+        let invalid = || {
+            vec![
+                Ok(b"r1".as_slice()),
+                Err(HtslibError::BamInvalidRecord),
+                Ok(b"r2"),
+            ]
+        };
+        assert!(read_all(ValidationLevel::Strict, invalid()).is_err());
+        assert_eq!(
+            read_all(ValidationLevel::Lenient, invalid())?,
+            [b"r1".to_vec(), b"r2".to_vec()]
+        );
+        let truncated = vec![Ok(b"r1".as_slice()), Err(HtslibError::BamTruncatedRecord)];
+        assert!(read_all(ValidationLevel::Lenient, truncated).is_err());
+
+        assert_eq!(
+            "Lenient".parse::<ValidationLevel>()?,
+            ValidationLevel::Lenient
+        );
+        assert!("sloppy".parse::<ValidationLevel>().is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,200 @@
+use anyhow::{Result, anyhow};
+use std::{
+    env,
+    ffi::OsString,
+    fs::File,
+    io::{self, Read, Write},
+    num::NonZero,
+    process::{Child, ChildStdin, ChildStdout, Command, Stdio},
+    thread::{self, JoinHandle},
+};
+
+/// Command that compresses and decompresses zstd, found on the PATH
+const ZSTD_COMMAND: &str = "zstd";
+
+/// Check that the zstd command is on the PATH, so that zstd input or output fails with an error
+/// naming the missing command before any output is created.
+pub fn check_zstd_command() -> Result<()> {
+    check_zstd_on_path(env::var_os("PATH"))
+}
+
+/// Check that the zstd command is in one of the directories of path.
+fn check_zstd_on_path(path: Option<OsString>) -> Result<()> {
+    let executable = format!("{ZSTD_COMMAND}{}", env::consts::EXE_SUFFIX);
+    let found = path.is_some_and(|path| {
+        env::split_paths(&path).any(|directory| directory.join(&executable).is_file())
+    });
+    if found {
+        Ok(())
+    } else {
+        Err(anyhow!(
+            "zstd-compressed FASTQ needs the {ZSTD_COMMAND} command, which was not found on the \
+            PATH. Install zstd, or use BGZF (bgzip) instead."
+        ))
+    }
+}
+
+/// Start the zstd command with args, reading from stdin and writing to stdout.
+fn spawn_zstd(args: &[String], stdin: Stdio, stdout: Stdio) -> Result<Child> {
+    check_zstd_command()?;
+    Command::new(ZSTD_COMMAND)
+        .args(args)
+        .stdin(stdin)
+        .stdout(stdout)
+        .stderr(Stdio::inherit())
+        .spawn()
+        .map_err(|err| {
+            anyhow!("Running {ZSTD_COMMAND}, which zstd-compressed FASTQ needs on the PATH: {err}")
+        })
+}
+
+/// Wait for the zstd command to exit, and fail if it did.
+fn wait_zstd(child: &mut Child) -> io::Result<()> {
+    let status = child.wait()?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(io::Error::other(format!("{ZSTD_COMMAND} failed: {status}")))
+    }
+}
+
+/// Writer that zstd-compresses to a file through the zstd command.
+pub struct ZstdWriter {
+    child: Child,
+    /// Input of the command, until it is closed by finish
+    stdin: Option<ChildStdin>,
+}
+
+impl ZstdWriter {
+    /// Compress to file at the given zstd level (or zstd's default level if None), with threads
+    /// worker threads.
+    pub fn new(file: File, level: Option<u32>, threads: NonZero<usize>) -> Result<Self> {
+        let mut args = vec!["-q".to_string(), "-c".to_string(), format!("-T{threads}")];
+        if let Some(level) = level {
+            args.push(format!("-{level}"));
+        }
+        let mut child = spawn_zstd(&args, Stdio::piped(), Stdio::from(file))?;
+        let stdin = child.stdin.take();
+        Ok(ZstdWriter { child, stdin })
+    }
+
+    /// Close the command's input and wait for it to finish writing, returning any error instead of
+    /// leaving it to Drop, which ignores errors.
+    pub fn finish(&mut self) -> io::Result<()> {
+        match self.stdin.take() {
+            Some(stdin) => {
+                drop(stdin);
+                wait_zstd(&mut self.child)
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Get the command's input, if it is still open.
+    fn stdin(&mut self) -> io::Result<&mut ChildStdin> {
+        self.stdin
+            .as_mut()
+            .ok_or_else(|| io::Error::other("zstd output was already finished."))
+    }
+}
+
+impl Write for ZstdWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.stdin()?.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stdin()?.flush()
+    }
+}
+
+/// Finish the output if finish wasn't called, so the command never outlives the writer.
+impl Drop for ZstdWriter {
+    fn drop(&mut self) {
+        let _ = self.finish();
+    }
+}
+
+/// Reader of zstd-compressed input, decompressed by the zstd command. The compressed input is fed
+/// to the command from another thread, so it can be any reader, e.g. a file whose first bytes were
+/// already read to detect its compression.
+pub struct ZstdReader {
+    child: Child,
+    stdout: ChildStdout,
+    /// Thread feeding the compressed input to the command, until it is joined at the end
+    feeder: Option<JoinHandle<io::Result<u64>>>,
+}
+
+impl ZstdReader {
+    /// Start decompressing input.
+    pub fn new<R>(mut input: R) -> Result<Self>
+    where
+        R: Read + Send + 'static,
+    {
+        let args = ["-q", "-d", "-c"].map(String::from);
+        let mut child = spawn_zstd(&args, Stdio::piped(), Stdio::piped())?;
+        let (Some(mut stdin), Some(stdout)) = (child.stdin.take(), child.stdout.take()) else {
+            return Err(anyhow!("{ZSTD_COMMAND} was started without pipes."));
+        };
+        let feeder = thread::Builder::new()
+            .name("zstd-input".to_string())
+            .spawn(move || io::copy(&mut input, &mut stdin))?;
+        Ok(ZstdReader {
+            child,
+            stdout,
+            feeder: Some(feeder),
+        })
+    }
+}
+
+impl Read for ZstdReader {
+    /// Read decompressed bytes. At the end, check that the command decompressed all the input, so
+    /// that truncated or corrupt input is an error rather than a short read.
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let num_read = self.stdout.read(buf)?;
+        if num_read == 0
+            && !buf.is_empty()
+            && let Some(feeder) = self.feeder.take()
+        {
+            wait_zstd(&mut self.child)?;
+            feeder
+                .join()
+                .map_err(|_| io::Error::other("zstd input thread panicked."))??;
+        }
+        Ok(num_read)
+    }
+}
+
+/// Stop the command if the input wasn't read to the end. The feeder then fails writing to it and
+/// ends too.
+impl Drop for ZstdReader {
+    fn drop(&mut self) {
+        if self.feeder.is_some() {
+            let _ = self.child.kill();
+            let _ = self.child.wait();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ZSTD_COMMAND, check_zstd_on_path};
+    use anyhow::Result;
+    use std::{env, fs};
+    use tempfile::TempDir;
+
+    /// Test that a missing zstd command is an error naming it, and a present one is found.
+    #[test]
+    fn test_check_zstd_on_path() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let path = env::join_paths([temp_dir.path()])?;
+        let err = check_zstd_on_path(Some(path.clone())).unwrap_err();
+        assert!(err.to_string().contains("zstd command"));
+        assert!(check_zstd_on_path(None).is_err());
+
+        let executable = format!("{ZSTD_COMMAND}{}", env::consts::EXE_SUFFIX);
+        fs::write(temp_dir.path().join(executable), "")?;
+        check_zstd_on_path(Some(path))?;
+        Ok(())
+    }
+}